@@ -1,41 +0,0 @@
-use clap::Parser;
-use client::{Client, Job};
-
-#[derive(Parser, Debug)]
-#[command(about = "Submit a wasm job to the distributed compute platform")]
-struct Args {
-    wasm_path: String,
-    #[arg(long, default_value = "http://127.0.0.1:50051")]
-    orchestrator: String,
-    #[arg(long)]
-    password: Option<String>,
-    #[arg(long, help = "Enable debug logging")]
-    verbose: bool,
-    #[arg(trailing_var_arg = true)]
-    wasm_args: Vec<String>
-}
-
-/// The main cli entrypoint to the Client, allowing submission of a wasm job.
-#[tokio::main]
-pub async fn main() {
-    let args = Args::parse();
-    let client = Client::connect(&args.orchestrator, args.password, args.verbose).await
-        .unwrap_or_else(|e| panic!("failed to connect to the client: {}", e));
-
-    let job = Job::from_path(&args.wasm_path)
-        .unwrap_or_else(|e| panic!("wasm file path not found: {}", e))
-        .args(args.wasm_args);
-
-    let result = client.submit_job(job).wait().await;
-    match result {
-        Ok(output) => {
-            print!("{}", String::from_utf8_lossy(&output.stdout));
-            if !output.stderr.is_empty() {
-                eprint!("{}", String::from_utf8_lossy(&output.stderr));
-            }
-        },
-        Err(e) => {
-            eprintln!("Job failed: {}", e);
-        }
-    }
-}
\ No newline at end of file
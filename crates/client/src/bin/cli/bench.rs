@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use client::{Client, JobId};
+use serde_json::json;
+use tokio::task::JoinSet;
+
+use crate::{Module, SubmitArgs, build_job, percentile};
+
+/// The outcome of one benchmark iteration.
+pub struct Sample {
+    pub iteration: usize,
+    pub job_id: JobId,
+    pub warmup: bool,
+    pub latency: Duration,
+    pub error: Option<String>,
+}
+
+/// Aggregate statistics over the measured (non-warmup) iterations. Latencies only cover
+/// successful jobs.
+pub struct Stats {
+    pub jobs: usize,
+    pub errors: BTreeMap<String, usize>,
+    pub latency: Option<LatencySummary>,
+    pub throughput: f64,
+}
+
+pub struct LatencySummary {
+    pub min: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Stats {
+    /// Aggregates the samples, ignoring warmup iterations. Throughput is successful jobs per
+    /// second of the given wall time.
+    pub fn from_samples(samples: &[Sample], wall_time: Duration) -> Stats {
+        let measured: Vec<&Sample> = samples.iter().filter(|s| !s.warmup).collect();
+        let mut errors = BTreeMap::new();
+        for error in measured.iter().filter_map(|s| s.error.as_ref()) {
+            *errors.entry(error.clone()).or_insert(0) += 1;
+        }
+
+        let mut latencies: Vec<Duration> = measured.iter()
+            .filter(|s| s.error.is_none())
+            .map(|s| s.latency)
+            .collect();
+        latencies.sort();
+
+        let latency = (!latencies.is_empty()).then(|| LatencySummary {
+            min: latencies[0],
+            mean: latencies.iter().sum::<Duration>() / latencies.len() as u32,
+            p50: percentile(&latencies, 0.50),
+            p90: percentile(&latencies, 0.90),
+            p99: percentile(&latencies, 0.99),
+            max: latencies[latencies.len() - 1],
+        });
+        let throughput = if wall_time.is_zero() {
+            0.0
+        } else {
+            latencies.len() as f64 / wall_time.as_secs_f64()
+        };
+
+        Stats { jobs: measured.len(), errors, latency, throughput }
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.errors.values().sum()
+    }
+}
+
+/// Submits the job --warmup + --repeat times with up to --concurrency in flight, then prints
+/// latency and throughput statistics over the non-warmup iterations. Exits non-zero if any
+/// measured iteration failed.
+pub async fn run(client: &Client, module: Module, repeat: usize, args: &SubmitArgs, as_json: bool) {
+    let mut samples = run_iterations(client, &module, args, 0..args.warmup, true).await;
+    let started_at = Instant::now();
+    samples.extend(run_iterations(client, &module, args, args.warmup..args.warmup + repeat, false).await);
+    let stats = Stats::from_samples(&samples, started_at.elapsed());
+
+    if let Some(path) = &args.bench_out {
+        std::fs::write(path, to_csv(&samples))
+            .unwrap_or_else(|e| panic!("failed to write {}: {}", path, e));
+    }
+    print_stats(&stats, as_json);
+    if stats.error_count() > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Runs the given range of iterations. The very first iteration runs on its own so the module
+/// is uploaded and compiled once, letting the rest take the hash-only path.
+async fn run_iterations(client: &Client, module: &Module, args: &SubmitArgs, mut iterations: Range<usize>, warmup: bool) -> Vec<Sample> {
+    let mut samples = Vec::with_capacity(iterations.len());
+    let mut in_flight = JoinSet::new();
+
+    if iterations.start == 0 && let Some(iteration) = iterations.next() {
+        in_flight.spawn(run_iteration(client, module, args, iteration, warmup));
+        if let Some(joined) = in_flight.join_next().await {
+            samples.push(joined.unwrap_or_else(|e| panic!("bench job task panicked: {}", e)));
+        }
+    }
+
+    loop {
+        while in_flight.len() < args.concurrency.max(1) && let Some(iteration) = iterations.next() {
+            in_flight.spawn(run_iteration(client, module, args, iteration, warmup));
+        }
+        let Some(joined) = in_flight.join_next().await else {
+            break;
+        };
+        samples.push(joined.unwrap_or_else(|e| panic!("bench job task panicked: {}", e)));
+    }
+
+    samples.sort_by_key(|s| s.iteration);
+    samples
+}
+
+/// Submits one iteration's job, returning a future that resolves to its sample once it finishes.
+fn run_iteration(client: &Client, module: &Module, args: &SubmitArgs, iteration: usize, warmup: bool) -> impl Future<Output = Sample> + use<> {
+    let running_job = client.submit_job(build_job(module.clone(), args, &args.wasm_args));
+    let job_id = running_job.job_id();
+    let started_at = Instant::now();
+    async move {
+        let result = running_job.wait().await;
+        Sample {
+            iteration,
+            job_id,
+            warmup,
+            latency: started_at.elapsed(),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// Renders one CSV row per iteration, including warmups.
+fn to_csv(samples: &[Sample]) -> String {
+    let mut csv = String::from("iteration,job_id,warmup,latency_ms,error\n");
+    for s in samples {
+        let error = s.error.as_deref()
+            .map(|e| format!("\"{}\"", e.replace('"', "\"\"")))
+            .unwrap_or_default();
+        writeln!(csv, "{},{},{},{:.3},{}", s.iteration, s.job_id, s.warmup, s.latency.as_secs_f64() * 1000.0, error)
+            .unwrap_or_else(|e| panic!("writing to a String can't fail: {e}"));
+    }
+    csv
+}
+
+fn print_stats(stats: &Stats, as_json: bool) {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    if as_json {
+        let latency = stats.latency.as_ref().map(|l| json!({
+            "min_ms": ms(l.min),
+            "mean_ms": ms(l.mean),
+            "p50_ms": ms(l.p50),
+            "p90_ms": ms(l.p90),
+            "p99_ms": ms(l.p99),
+            "max_ms": ms(l.max),
+        }));
+        println!("{}", json!({
+            "jobs": stats.jobs,
+            "succeeded": stats.jobs - stats.error_count(),
+            "failed": stats.error_count(),
+            "errors": stats.errors,
+            "latency": latency,
+            "throughput_per_sec": stats.throughput,
+        }));
+        return;
+    }
+
+    println!("jobs:       {}", stats.jobs);
+    println!("succeeded:  {}", stats.jobs - stats.error_count());
+    println!("failed:     {}", stats.error_count());
+    for (error, count) in &stats.errors {
+        println!("  {count} × {error}");
+    }
+    if let Some(l) = &stats.latency {
+        println!("latency:    min {:.1}ms  mean {:.1}ms  max {:.1}ms", ms(l.min), ms(l.mean), ms(l.max));
+        println!("            p50 {:.1}ms  p90 {:.1}ms  p99 {:.1}ms", ms(l.p50), ms(l.p90), ms(l.p99));
+    }
+    println!("throughput: {:.1} jobs/s", stats.throughput);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(latency_ms: u64, warmup: bool, error: Option<&str>) -> Sample {
+        Sample { iteration: 0, job_id: JobId::random(), warmup, latency: Duration::from_millis(latency_ms), error: error.map(str::to_string) }
+    }
+
+    #[test]
+    fn latencies_are_summarized() {
+        let mut samples: Vec<Sample> = (1..=100).rev().map(|ms| sample(ms, false, None)).collect();
+        let stats = Stats::from_samples(&samples, Duration::from_secs(2));
+        let latency = stats.latency.unwrap();
+        assert_eq!(stats.jobs, 100);
+        assert_eq!((latency.min, latency.max), (Duration::from_millis(1), Duration::from_millis(100)));
+        assert_eq!(latency.mean, Duration::from_micros(50_500));
+        assert_eq!(latency.p50, Duration::from_millis(51));
+        assert_eq!(latency.p90, Duration::from_millis(90));
+        assert_eq!(latency.p99, Duration::from_millis(99));
+        assert_eq!(stats.throughput, 50.0);
+
+        // Warmups are left out, and failed jobs only count as errors
+        samples.push(sample(5000, true, None));
+        samples.push(sample(5000, false, Some("timed out")));
+        samples.push(sample(5000, false, Some("timed out")));
+        let stats = Stats::from_samples(&samples, Duration::from_secs(2));
+        assert_eq!(stats.jobs, 102);
+        assert_eq!(stats.errors, BTreeMap::from([("timed out".to_string(), 2)]));
+        assert_eq!(stats.latency.unwrap().max, Duration::from_millis(100));
+        assert_eq!(stats.throughput, 50.0);
+    }
+
+    #[test]
+    fn a_single_sample_is_every_percentile() {
+        let stats = Stats::from_samples(&[sample(7, false, None)], Duration::from_millis(7));
+        let latency = stats.latency.unwrap();
+        let all = [latency.min, latency.mean, latency.p50, latency.p90, latency.p99, latency.max];
+        assert!(all.iter().all(|&l| l == Duration::from_millis(7)));
+    }
+
+    #[test]
+    fn no_successful_samples_have_no_latency() {
+        let stats = Stats::from_samples(&[], Duration::ZERO);
+        assert_eq!((stats.jobs, stats.error_count(), stats.throughput), (0, 0, 0.0));
+        assert!(stats.latency.is_none());
+
+        let stats = Stats::from_samples(&[sample(3, true, None), sample(3, false, Some("failed"))], Duration::from_secs(1));
+        assert_eq!((stats.jobs, stats.error_count(), stats.throughput), (1, 1, 0.0));
+        assert!(stats.latency.is_none());
+    }
+}
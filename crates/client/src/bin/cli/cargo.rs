@@ -0,0 +1,126 @@
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde_json::Value;
+
+/// The target `cli run` builds for. Workers run WASI components, which wasm32-wasip2 builds.
+pub const DEFAULT_TARGET: &str = "wasm32-wasip2";
+
+/// What `cli run` asks cargo to build.
+#[derive(Debug, Clone)]
+pub struct CargoBuild {
+    pub package: Option<String>,
+    pub bin: Option<String>,
+    pub release: bool,
+    pub target: String,
+    pub target_dir: Option<PathBuf>,
+    pub manifest_path: Option<PathBuf>,
+    pub quiet: bool,
+}
+
+/// Builds the binary with cargo, like `cargo run` would pick it, and returns the path of the
+/// wasm it produced. The workspace is found the way cargo finds it, so this works from any of
+/// its subdirectories. Cargo's progress and diagnostics stream to stderr as it builds, and a
+/// failed build returns an error without anything else to add.
+pub fn build(options: &CargoBuild) -> Result<PathBuf, String> {
+    let (package, manifest_path) = select_package(options)?;
+
+    let mut command = cargo(options);
+    command.args(["build", "--message-format=json-render-diagnostics", "--target", &options.target, "--package", &package]);
+    if let Some(bin) = &options.bin {
+        command.args(["--bin", bin]);
+    }
+    if options.release {
+        command.arg("--release");
+    }
+    if let Some(target_dir) = &options.target_dir {
+        command.arg("--target-dir").arg(target_dir);
+    }
+    if options.quiet {
+        command.arg("--quiet");
+    }
+    // Cargo's JSON messages come on stdout, its rendered diagnostics and progress on stderr
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::inherit()).spawn()
+        .map_err(|e| format!("failed to run cargo: {e}"))?;
+    let stdout = child.stdout.take()
+        .unwrap_or_else(|| unreachable!("stdout is piped"));
+
+    let mut artifacts = Vec::new();
+    for line in BufReader::new(stdout).lines() {
+        let line = line.map_err(|e| format!("failed to read cargo's output: {e}"))?;
+        if let Some(artifact) = executable(&line, &manifest_path) {
+            artifacts.push(artifact);
+        }
+    }
+    let status = child.wait().map_err(|e| format!("failed to wait for cargo: {e}"))?;
+    if !status.success() {
+        return Err("cargo build failed, not submitting".to_string());
+    }
+
+    match artifacts.as_slice() {
+        [artifact] => Ok(artifact.clone()),
+        [] => Err(format!("cargo built no binary for package {package}, which `cli run` needs")),
+        _ => Err(format!("package {package} has several binaries, pick one with --bin")),
+    }
+}
+
+fn cargo(options: &CargoBuild) -> Command {
+    let mut command = Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+    if let Some(manifest_path) = &options.manifest_path {
+        command.arg("--manifest-path").arg(manifest_path);
+    }
+    command
+}
+
+/// The name and manifest path of the package to build: the one named by --package, or else
+/// the one `cargo run` would build without it, whose directory the current directory is in
+/// or the workspace's only package.
+fn select_package(options: &CargoBuild) -> Result<(String, String), String> {
+    let output = cargo(options)
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("failed to run cargo: {e}"))?;
+    if !output.status.success() {
+        return Err("cargo metadata failed, is this in a cargo workspace?".to_string());
+    }
+    let metadata: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("cargo metadata printed malformed JSON: {e}"))?;
+    let packages: Vec<(&str, &str)> = metadata["packages"].as_array().into_iter().flatten()
+        .filter_map(|package| Some((package["name"].as_str()?, package["manifest_path"].as_str()?)))
+        .collect();
+    let names = || packages.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+
+    let selected = if let Some(package) = &options.package {
+        packages.iter()
+            .find(|(name, _)| name == package)
+            .ok_or_else(|| format!("the workspace has no package {package}, only {}", names()))?
+    } else if let [only] = packages.as_slice() {
+        only
+    } else {
+        let dir = match &options.manifest_path {
+            Some(manifest_path) => std::path::absolute(manifest_path)
+                .map_err(|e| format!("failed to resolve {}: {e}", manifest_path.display()))?
+                .parent().map(Path::to_path_buf).unwrap_or_default(),
+            None => std::env::current_dir().map_err(|e| format!("failed to read the current directory: {e}"))?,
+        };
+        packages.iter()
+            .filter(|(_, manifest_path)| Path::new(manifest_path).parent().is_some_and(|package_dir| dir.starts_with(package_dir)))
+            .max_by_key(|(_, manifest_path)| manifest_path.len())
+            .ok_or_else(|| format!("the workspace has several packages, pick one with --package: {}", names()))?
+    };
+    Ok((selected.0.to_string(), selected.1.to_string()))
+}
+
+/// The wasm a cargo JSON message says was built for a binary of the package with the
+/// manifest, if it is one.
+fn executable(line: &str, manifest_path: &str) -> Option<PathBuf> {
+    let message: Value = serde_json::from_str(line).ok()?;
+    let is_bin = message["target"]["kind"].as_array()
+        .is_some_and(|kinds| kinds.iter().any(|kind| kind == "bin"));
+    if message["reason"] != "compiler-artifact" || message["manifest_path"] != manifest_path || !is_bin {
+        return None;
+    }
+    message["executable"].as_str().map(PathBuf::from)
+}
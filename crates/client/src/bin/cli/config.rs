@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+pub const DEFAULT_ORCHESTRATOR: &str = "http://127.0.0.1:50051";
+pub const SERVER_ENV: &str = "MINI_LAMBDA_SERVER";
+pub const TOKEN_ENV: &str = "MINI_LAMBDA_TOKEN";
+pub const NAMESPACE_ENV: &str = "MINI_LAMBDA_NAMESPACE";
+const CONFIG_PATH_ENV: &str = "MINI_LAMBDA_CONFIG";
+
+/// The CLI config file, holding named profiles of connection settings.
+///
+/// ```toml
+/// default_profile = "local"
+///
+/// [profiles.local]
+/// orchestrator = "http://127.0.0.1:50051"
+///
+/// [profiles.staging]
+/// orchestrator = "http://staging.example.com:50051"
+/// password = "hunter2"
+/// namespace = "team-a"
+/// timeout = 60
+/// json = true
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// Settings for one orchestrator. Unset fields fall back to the built-in defaults.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(alias = "url")]
+    pub orchestrator: Option<String>,
+    #[serde(alias = "token")]
+    pub password: Option<String>,
+    /// Namespace to act in when the orchestrator doesn't derive it from the password.
+    pub namespace: Option<String>,
+    /// Default job timeout in seconds.
+    pub timeout: Option<u64>,
+    /// Print results as JSON by default.
+    pub json: Option<bool>,
+}
+
+/// Returns the config file location: $MINI_LAMBDA_CONFIG if set, otherwise
+/// mini-lambda/config.toml under $XDG_CONFIG_HOME or ~/.config.
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_PATH_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("mini-lambda").join("config.toml"))
+}
+
+/// Loads the config file, returning an empty config if it doesn't exist.
+/// Parse errors name the offending key and its location in the file.
+pub fn load(path: &Path) -> Result<ConfigFile, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ConfigFile::default()),
+        Err(e) => return Err(format!("failed to read {}: {e}", path.display())),
+    };
+    toml::from_str(&contents)
+        .map_err(|e| format!("invalid config file {}: {e}", path.display()))
+}
+
+impl ConfigFile {
+    /// Returns the named profile, or the default profile if no name is given. Naming a profile
+    /// that doesn't exist is an error; having no default profile is not.
+    pub fn profile(&self, name: Option<&str>) -> Result<(Option<String>, Profile), String> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => {
+                let profile = self.profiles.get(name)
+                    .ok_or_else(|| format!("profile '{name}' not found in the config file"))?;
+                Ok((Some(name.to_string()), profile.clone()))
+            },
+            None => Ok((None, Profile::default())),
+        }
+    }
+}
+
+/// Picks the first value set, in order of precedence: command-line flag, environment
+/// variable, then profile.
+pub fn resolve(flag: Option<String>, env_var: Option<String>, profile: Option<String>) -> Option<String> {
+    flag.or(env_var).or(profile)
+}
+
+/// The environment variable's value, treating an empty one as unset.
+pub fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+default_profile = "local"
+
+[profiles.local]
+orchestrator = "http://127.0.0.1:50051"
+
+[profiles.staging]
+url = "http://staging.example.com:50051"
+token = "hunter2"
+timeout = 60
+"#;
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    fn config_file(contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mini-lambda-config-{:016x}.toml", rand::random::<u64>()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn flags_win_over_env_vars_over_profiles() {
+        assert_eq!(resolve(some("flag"), some("env"), some("profile")), some("flag"));
+        assert_eq!(resolve(None, some("env"), some("profile")), some("env"));
+        assert_eq!(resolve(None, None, some("profile")), some("profile"));
+        assert_eq!(resolve(None, None, None).unwrap_or_else(|| DEFAULT_ORCHESTRATOR.to_string()), DEFAULT_ORCHESTRATOR);
+    }
+
+    #[test]
+    fn the_default_profile_is_used_unless_one_is_named() {
+        let config_file = load(&config_file(CONFIG)).unwrap();
+        let (name, profile) = config_file.profile(None).unwrap();
+        assert_eq!((name, profile.orchestrator), (some("local"), some("http://127.0.0.1:50051")));
+
+        let (name, profile) = config_file.profile(Some("staging")).unwrap();
+        assert_eq!(name, some("staging"));
+        assert_eq!(profile.orchestrator, some("http://staging.example.com:50051"));
+        assert_eq!(profile.password, some("hunter2"));
+        assert_eq!(profile.timeout, Some(60));
+
+        let error = config_file.profile(Some("prod")).unwrap_err();
+        assert!(error.contains("'prod' not found"), "{error}");
+    }
+
+    #[test]
+    fn a_missing_file_has_no_profiles() {
+        let config_file = load(Path::new("/nonexistent/mini-lambda/config.toml")).unwrap();
+        let (name, profile) = config_file.profile(None).unwrap();
+        assert_eq!((name, profile.orchestrator), (None, None));
+    }
+
+    #[test]
+    fn malformed_files_name_the_offending_key() {
+        let error = load(&config_file("[profiles.local]\norchestrater = \"http://127.0.0.1:50051\"\n")).unwrap_err();
+        assert!(error.contains("orchestrater"), "{error}");
+
+        let error = load(&config_file("[profiles.local]\ntimeout = \"soon\"\n")).unwrap_err();
+        assert!(error.contains("timeout"), "{error}");
+    }
+}
@@ -0,0 +1,1869 @@
+mod bench;
+mod cargo;
+mod config;
+mod verify;
+mod wasm;
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::{Args as _, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+
+use client::{CacheMode, Client, ClientError, DailyUsage, HashAlgorithm, Job, JobError, JobId, JobOutput, JobQuery, JobSigner, JobStatus, JobTiming, KnownModules, ModuleDefaults, ModuleDigest, ModuleSource, Pipeline, PlanSpec, PublishedModule, RunningJob, SubmittedModule, TlsConfig, WarmOutcome};
+use notify::{RecursiveMode, Watcher};
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+/// Shortest --poll-interval allowed.
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const SUBCOMMANDS: [&str; 18] = ["submit", "run", "pipeline", "status", "cancel", "replay", "jobs", "workers", "queue", "plan", "usage", "warm", "validate", "publish", "inspect", "config", "completions", "help"];
+
+#[derive(Parser, Debug)]
+#[command(name = "cli", about = "Submit and manage wasm jobs on the distributed compute platform", arg_required_else_help = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(long, exclusive = true, help = "Print a man page for the CLI to stdout")]
+    generate_man: bool,
+    #[arg(long, global = true, help = "Orchestrator URL [env: MINI_LAMBDA_SERVER] [default: http://127.0.0.1:50051]")]
+    orchestrator: Option<String>,
+    #[arg(long, global = true, help = "Password to authenticate with the orchestrator [env: MINI_LAMBDA_TOKEN]")]
+    password: Option<String>,
+    #[arg(long, global = true, help = "Namespace to submit jobs to and look them up in, when the orchestrator doesn't derive it from the password [env: MINI_LAMBDA_NAMESPACE] [default: default]")]
+    namespace: Option<String>,
+    #[arg(long, global = true, help = "Config file profile to use, instead of its default_profile")]
+    profile: Option<String>,
+    #[arg(long = "tls-ca", global = true, help = "PEM file of a CA to trust for https orchestrator and worker endpoints (repeatable)")]
+    tls_ca: Vec<String>,
+    #[arg(long, global = true, help = "Skip verifying server certificates over https. Only for development")]
+    insecure: bool,
+    #[arg(long, global = true, requires = "client_key", help = "PEM certificate to present for mutual TLS")]
+    client_cert: Option<String>,
+    #[arg(long, global = true, requires = "client_cert", help = "PEM private key for --client-cert")]
+    client_key: Option<String>,
+    #[arg(long, global = true, help = "Ed25519 private key (PKCS#8 PEM) to sign jobs with, for workers that only run signed jobs")]
+    sign_key: Option<PathBuf>,
+    #[arg(long, global = true, requires = "sign_key", help = "Key id workers know the signing key by [default: the key file's name without extension]")]
+    sign_key_id: Option<String>,
+    #[arg(long, global = true, help = "Print results as JSON")]
+    json: bool,
+    #[arg(short, long, global = true, conflicts_with = "verbose", help = "Only print the program's output and errors")]
+    quiet: bool,
+    #[arg(short, long, global = true, help = "Log each phase of the job, including retries")]
+    verbose: bool,
+}
+
+/// How much the CLI prints besides the job's own output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+impl Verbosity {
+    /// The tracing filter directive for the client library's logs.
+    fn log_filter(self) -> &'static str {
+        match self {
+            Verbosity::Quiet => "client=error",
+            Verbosity::Normal => "client=info",
+            Verbosity::Verbose => "client=debug",
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Submit a wasm job and wait for its output
+    Submit(Box<SubmitArgs>),
+    /// Build a cargo package for wasm and submit it, like cargo run
+    Run(Box<RunArgs>),
+    /// Run jobs one after another, each reading the stdout of the one before, and print the last one's output
+    Pipeline {
+        #[arg(long, help = "File the first stage reads on its stdin, or - to read it from stdin [default: nothing]")]
+        stdin: Option<String>,
+        #[arg(long, help = "Give up on the pipeline if it hasn't finished after this many seconds")]
+        timeout: Option<u64>,
+        #[arg(long, help = "Print where each stage's time went")]
+        timing: bool,
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true, value_name = "STAGES", help = "The stages in order, each as --stage <module> [-- <args>..], where module is a path or http(s) URL of the wasm module (binary or .wat text), or a name:tag alias made with publish. Options go before the first --stage")]
+        stages: Vec<String>,
+    },
+    /// Show the current state of a job
+    Status {
+        job_id: JobId,
+        #[arg(long, help = "Keep polling, printing each state change, until the job finishes")]
+        follow: bool,
+        #[arg(long, default_value = "1", value_parser = parse_poll_interval, requires = "follow", help = "Seconds between polls with --follow, at least 0.1")]
+        poll_interval: Duration,
+        #[arg(long, requires = "follow", help = "Give up following after this many seconds")]
+        timeout: Option<u64>,
+    },
+    /// Cancel a job that is still queued or scheduled
+    Cancel {
+        job_id: JobId,
+    },
+    /// Run a job that already ran again, with the same module and args
+    Replay {
+        job_id: JobId,
+        #[arg(long, help = "Run the replay on the worker at this address, failing if it has no free credit, instead of any worker")]
+        worker: Option<String>,
+        #[arg(long, conflicts_with = "worker", help = "Run the replay on the worker the job last ran on")]
+        same_worker: bool,
+        #[arg(long, help = "Path of the job's wasm module, to upload if the worker no longer has it cached. It must be the module the job ran")]
+        wasm: Option<String>,
+        #[arg(long, help = "File the job read on its stdin, or - to read it from stdin, to give it again if the orchestrator no longer keeps it. It must be what the job read")]
+        stdin: Option<String>,
+        #[arg(long, help = "Cancel the replay if it hasn't finished after this many seconds")]
+        timeout: Option<u64>,
+        #[arg(long, help = "Retry transient failures up to this many times [default: 3]")]
+        max_retries: Option<u32>,
+        #[arg(long, help = "Print a breakdown of where the replay's time went")]
+        timing: bool,
+    },
+    /// List the namespace's jobs, newest first
+    Jobs {
+        #[arg(long, help = "Only jobs in this state, e.g. queued, executing or failed")]
+        state: Option<String>,
+        #[arg(long, help = "Only jobs last dispatched to the worker at this address")]
+        worker: Option<String>,
+        #[arg(long, help = "Only jobs queued in the last this many seconds")]
+        since: Option<u64>,
+        #[arg(long, help = "Jobs to list per page, at most 500 [default: 100]")]
+        limit: Option<u32>,
+        #[arg(long, conflicts_with = "all", help = "List the page after the one that printed this token")]
+        page_token: Option<String>,
+        #[arg(long, help = "Keep fetching pages until every matching job is listed")]
+        all: bool,
+    },
+    /// List the workers known to the orchestrator
+    Workers,
+    /// Show how backed up the orchestrator's job queue is
+    Queue,
+    /// Show how jobs running a module would be scheduled if submitted now, without submitting them
+    Plan {
+        #[arg(help = "Path or http(s) URL of the wasm module (binary or .wat text), - to read it from stdin, or the hex blake3 hash of one")]
+        module: String,
+        #[arg(long, default_value_t = 1, help = "Jobs to plan")]
+        count: u32,
+    },
+    /// Show what the namespace's jobs used and cost on each recent day
+    Usage {
+        #[arg(long, default_value_t = 31, help = "Days to show, including today (UTC)")]
+        days: u32,
+    },
+    /// Have the orchestrator compile a module on its workers, so the first jobs that run it skip compiling
+    Warm {
+        #[arg(help = "Path or http(s) URL of the wasm module (binary or .wat text), - to read it from stdin, an s3://bucket/key URL for workers to fetch it from, or a name:tag alias made with publish")]
+        wasm_path: String,
+        #[arg(long, visible_alias = "sha256", help = "Digest of the module at an s3:// URL, as sha256:<hex> or blake3:<hex> (bare hex is SHA-256), which workers check it against")]
+        digest: Option<String>,
+        #[arg(long, help = "Warm every worker that may run the namespace's jobs, the default unless --worker is given")]
+        all_workers: bool,
+        #[arg(long = "worker", conflicts_with = "all_workers", help = "Only warm the worker at this address (repeatable)")]
+        workers: Vec<String>,
+        #[arg(long, help = "Give up on a worker that hasn't finished after this many seconds [default: 30]")]
+        timeout: Option<u64>,
+        #[arg(long, help = "Skip checking locally that the module is a runnable WASI component before sending it")]
+        no_validate: bool,
+    },
+    /// Have the orchestrator check a module is something workers can run, without running it
+    Validate {
+        #[arg(help = "Path or http(s) URL of the wasm module (binary or .wat text), or - to read it from stdin")]
+        wasm_path: String,
+    },
+    /// Publish an s3:// module under a name:tag alias, which submit accepts in place of the URL
+    Publish {
+        #[arg(help = "s3://bucket/key URL of the module")]
+        url: String,
+        #[arg(long, visible_alias = "sha256", help = "Digest of the module, as sha256:<hex> or blake3:<hex> (bare hex is SHA-256), which workers check it against")]
+        digest: String,
+        #[arg(long, help = "Name to publish the module under")]
+        name: String,
+        #[arg(long, help = "Tag to publish it as, replacing the module the tag pointed at before")]
+        tag: String,
+        #[arg(long, help = "Default timeout in seconds for jobs submitted by the alias")]
+        timeout: Option<u64>,
+        #[arg(long, help = "Default --max-retries for jobs submitted by the alias")]
+        max_retries: Option<u32>,
+        #[arg(long = "arg", help = "Default wasm arg for jobs submitted by the alias without args (repeatable)")]
+        args: Vec<String>,
+    },
+    /// Show the module a name:tag alias points at and the job defaults published with it, or with --worker the modules a worker has compiled
+    Inspect {
+        #[arg(required_unless_present = "worker", help = "name:tag alias to show, or with --worker the hex blake3 hash of the module to show instead of all of them")]
+        module: Option<String>,
+        #[arg(long, help = "Show the modules the worker at this address has compiled, with what it read from each")]
+        worker: Option<String>,
+    },
+    /// Inspect the CLI configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions {
+        shell: Shell,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the resolved configuration, with secrets redacted
+    Show,
+}
+
+/// The module a job runs: the wasm itself, or where workers should fetch it from.
+#[derive(Clone)]
+enum Module {
+    Wasm(Vec<u8>),
+    Source(ModuleSource),
+    /// A module submitted by its name:tag alias, with the job defaults published with it.
+    Published(PublishedModule),
+}
+
+#[derive(clap::Args, Debug)]
+struct SubmitArgs {
+    #[arg(help = "Path or http(s) URL of the wasm module (binary or .wat text), - to read it from stdin, an s3://bucket/key URL for workers to fetch it from, or a name:tag alias made with publish")]
+    wasm_path: String,
+    #[arg(long, visible_alias = "sha256", help = "Digest of the module at an s3:// URL, as sha256:<hex> or blake3:<hex> (bare hex is SHA-256), which workers check it against")]
+    digest: Option<String>,
+    #[arg(long, help = "Cancel the job if it hasn't finished this many seconds after it may start (after --delay, if given). Also bounds fetching the module from a URL")]
+    timeout: Option<u64>,
+    #[arg(long, help = "Wait this many seconds before queueing the job")]
+    delay: Option<u64>,
+    #[arg(long, help = "Refuse to submit if the orchestrator estimates the job would wait longer than this many seconds for a worker")]
+    max_wait: Option<u64>,
+    #[arg(long, help = "URL the orchestrator should POST a completion notice to")]
+    callback_url: Option<String>,
+    #[arg(long, help = "Share the run of an identical job (same module and args) already queued or running that was also submitted with --coalesce, instead of running it again")]
+    coalesce: bool,
+    #[arg(long, help = "Retry transient failures (orchestrator unavailable, quota full, worker failure) up to this many times [default: 3]")]
+    max_retries: Option<u32>,
+    #[arg(long, conflicts_with = "hash_only", help = "Always upload the wasm and have the worker recompile it")]
+    no_cache: bool,
+    #[arg(long, help = "Never upload the wasm, failing if the worker doesn't have it cached")]
+    hash_only: bool,
+    #[arg(long, help = "Keep no record of the modules the cluster's workers recently ran, which turns off delta uploads")]
+    no_local_cache: bool,
+    #[arg(long, default_value_t = HashAlgorithm::Blake3, help = "Digest to probe workers' caches with: blake3, or sha256 for workers started with --dual-hash. Falls back to blake3 on workers that can't find modules by it")]
+    hash_alg: HashAlgorithm,
+    #[arg(long, default_value_t = 600, conflicts_with = "no_local_cache", help = "Seconds a module stays known to be cached on the cluster after a job last ran it")]
+    local_cache_ttl: u64,
+    #[arg(long, help = "Always upload the module in full, rather than as a patch against the version last uploaded from the same path")]
+    no_delta: bool,
+    #[arg(long, help = "Skip checking locally that the module is a runnable WASI component before submitting")]
+    no_validate: bool,
+    #[arg(long, help = "Print the job id to stderr once it's submitted, as the CLI did by default before --verbose")]
+    print_job_id: bool,
+    #[arg(long, help = "Print a breakdown of where the job's time went")]
+    timing: bool,
+    #[arg(long, conflicts_with = "args_file", help = "Rerun the job whenever the wasm file changes, until Ctrl-C")]
+    watch: bool,
+    #[arg(long = "watch-path", requires = "watch", help = "Additional file or directory whose changes trigger a rerun")]
+    watch_paths: Vec<String>,
+    #[arg(long, help = "Submit one job per line of this file, each line holding whitespace-separated wasm args")]
+    args_file: Option<String>,
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["args_file", "watch", "repeat"], help = "Run the job this many times, on distinct workers where possible, and check every run exited with the same code and wrote the same stdout and stderr")]
+    verify: Option<u32>,
+    #[arg(long, conflicts_with_all = ["args_file", "watch"], help = "Benchmark the job by submitting it this many times, then print latency statistics")]
+    repeat: Option<usize>,
+    #[arg(long, default_value_t = 0, requires = "repeat", help = "Extra iterations to run before --repeat, excluded from the statistics")]
+    warmup: usize,
+    #[arg(long, requires = "repeat", help = "Write per-iteration results of a --repeat benchmark to this CSV file")]
+    bench_out: Option<String>,
+    #[arg(long, default_value_t = 4, help = "Maximum jobs in flight at once with --args-file or --repeat")]
+    concurrency: usize,
+    #[arg(trailing_var_arg = true)]
+    wasm_args: Vec<String>
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    #[arg(short, long, help = "Package to build [default: the one in the current directory, or the workspace's only package]")]
+    package: Option<String>,
+    #[arg(long, help = "Binary of the package to build, if it has several")]
+    bin: Option<String>,
+    #[arg(long, help = "Build with the release profile")]
+    release: bool,
+    #[arg(long, default_value = cargo::DEFAULT_TARGET, help = "Target to build for")]
+    target: String,
+    #[arg(long, help = "Directory for cargo's build artifacts [default: cargo's, respecting CARGO_TARGET_DIR and .cargo/config.toml]")]
+    target_dir: Option<PathBuf>,
+    #[arg(long, help = "Cargo.toml of the package or workspace, instead of finding it from the current directory")]
+    manifest_path: Option<PathBuf>,
+    #[arg(long, help = "Cancel the job if it hasn't finished after this many seconds")]
+    timeout: Option<u64>,
+    #[arg(long, help = "Retry transient failures up to this many times [default: 3]")]
+    max_retries: Option<u32>,
+    #[arg(long, help = "Skip checking locally that the module is a runnable WASI component before submitting")]
+    no_validate: bool,
+    #[arg(long, help = "Print the job id to stderr once it's submitted")]
+    print_job_id: bool,
+    #[arg(long, help = "Print a breakdown of where the job's time went")]
+    timing: bool,
+    #[arg(trailing_var_arg = true)]
+    wasm_args: Vec<String>,
+}
+
+impl RunArgs {
+    /// Builds the package, returning submit options for the wasm it produced. Exits if the
+    /// build fails, so nothing stale is submitted.
+    async fn build(self, verbosity: Verbosity) -> SubmitArgs {
+        let options = cargo::CargoBuild {
+            package: self.package,
+            bin: self.bin,
+            release: self.release,
+            target: self.target,
+            target_dir: self.target_dir,
+            manifest_path: self.manifest_path,
+            quiet: verbosity == Verbosity::Quiet,
+        };
+        let wasm_path = tokio::task::spawn_blocking(move || cargo::build(&options)).await
+            .unwrap_or_else(|e| panic!("cargo build task panicked: {}", e))
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+        // Starts from submit's defaults, so run keeps in step with them
+        let wasm_path = wasm_path.display().to_string();
+        let matches = SubmitArgs::augment_args(clap::Command::new("submit"))
+            .get_matches_from(["submit", wasm_path.as_str()]);
+        let mut submit_args = SubmitArgs::from_arg_matches(&matches)
+            .unwrap_or_else(|e| e.exit());
+        submit_args.timeout = self.timeout;
+        submit_args.max_retries = self.max_retries;
+        submit_args.no_validate = self.no_validate;
+        submit_args.print_job_id = self.print_job_id;
+        submit_args.timing = self.timing;
+        submit_args.wasm_args = self.wasm_args;
+        submit_args
+    }
+}
+
+/// The main cli entrypoint to the Client.
+#[tokio::main]
+pub async fn main() {
+    let matches = build_cli().get_matches_from(with_implicit_submit(std::env::args().collect()));
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // Generated output goes to stdout so packaging scripts can capture it
+    if args.generate_man {
+        clap_mangen::Man::new(build_cli()).render(&mut std::io::stdout())
+            .unwrap_or_else(|e| panic!("failed to write the man page: {}", e));
+        return;
+    }
+    let Some(mut command) = args.command else {
+        build_cli().error(clap::error::ErrorKind::MissingSubcommand, "a subcommand is required").exit();
+    };
+    if let Command::Completions { shell } = command {
+        clap_complete::generate(shell, &mut build_cli(), "cli", &mut std::io::stdout());
+        return;
+    }
+
+    let verbosity = match (args.quiet, args.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    };
+    // Initialized here rather than by Client::connect so --quiet can also silence warnings
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| verbosity.log_filter().into())
+        )
+        .init();
+
+    // Settings resolve in order of precedence: flag, environment variable, profile, default
+    let config_path = config::config_path();
+    let (profile_name, profile) = config_path.as_deref()
+        .map(config::load)
+        .transpose()
+        .and_then(|config_file| config_file.unwrap_or_default().profile(args.profile.as_deref()))
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+    let orchestrator = config::resolve(args.orchestrator, config::env(config::SERVER_ENV), profile.orchestrator)
+        .unwrap_or_else(|| config::DEFAULT_ORCHESTRATOR.to_string());
+    let password = config::resolve(args.password, config::env(config::TOKEN_ENV), profile.password);
+    let namespace = config::resolve(args.namespace, config::env(config::NAMESPACE_ENV), profile.namespace);
+    let as_json = args.json || profile.json.unwrap_or(false);
+
+    if let Command::Config(ConfigCommand::Show) = command {
+        let redacted = password.as_ref().map(|_| "<redacted>");
+        if as_json {
+            println!("{}", json!({
+                "config_file": config_path,
+                "profile": profile_name,
+                "orchestrator": orchestrator,
+                "password": redacted,
+                "namespace": namespace,
+                "timeout": profile.timeout,
+                "json": as_json,
+            }));
+        } else {
+            let config_file = config_path.map(|p| p.display().to_string());
+            println!("config file:  {}", config_file.as_deref().unwrap_or("-"));
+            println!("profile:      {}", profile_name.as_deref().unwrap_or("-"));
+            println!("orchestrator: {orchestrator}");
+            println!("password:     {}", redacted.unwrap_or("-"));
+            println!("namespace:    {}", namespace.as_deref().unwrap_or("-"));
+            println!("timeout:      {}", profile.timeout.map(|t| format!("{t}s")).as_deref().unwrap_or("-"));
+            println!("json:         {as_json}");
+        }
+        return;
+    }
+
+    // A built package is submitted like any other wasm file
+    command = match command {
+        Command::Run(run_args) => Command::Submit(Box::new(run_args.build(verbosity).await)),
+        command => command,
+    };
+
+    // Load the module before connecting, so a missing or invalid module fails without
+    // contacting the orchestrator. Watch mode loads it itself on every change
+    let mut module = None;
+    if let Command::Submit(submit_args) = &mut command {
+        submit_args.timeout = submit_args.timeout.or(profile.timeout);
+        let is_alias = module_alias(&submit_args.wasm_path).is_some();
+        if is_alias && (submit_args.watch || submit_args.digest.is_some()) {
+            eprintln!("--watch and --digest don't apply to the module alias {}", submit_args.wasm_path);
+            std::process::exit(1);
+        }
+        if !submit_args.watch && !is_alias {
+            let loaded = load_module(&submit_args.wasm_path, submit_args.digest.as_deref(), submit_args.timeout, !submit_args.no_validate).await
+                .unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                });
+            module = Some(loaded);
+        }
+    }
+    if let Command::Warm { wasm_path, digest, no_validate, .. } = &command
+        && module_alias(wasm_path).is_none()
+    {
+        let loaded = load_module(wasm_path, digest.as_deref(), None, !no_validate).await
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+        module = Some(loaded);
+    }
+    if let Command::Validate { wasm_path } = &command {
+        let loaded = load_wasm(wasm_path, None, false).await
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+        module = Some(Module::Wasm(loaded));
+    }
+    let mut pipeline_stages = Vec::new();
+    if let Command::Pipeline { stages, .. } = &command {
+        pipeline_stages = load_stages(stages).await
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+    }
+    if let Command::Plan { module: wasm_path, .. } = &command
+        && !is_plan_hash(wasm_path)
+    {
+        let loaded = load_wasm(wasm_path, None, false).await
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+        module = Some(Module::Wasm(loaded));
+    }
+
+    let mut tls_config = args.tls_ca.iter().fold(TlsConfig::new(), |tls, ca| tls.ca_cert(ca))
+        .insecure(args.insecure);
+    if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+        tls_config = tls_config.client_identity(cert, key);
+    }
+    let signer = args.sign_key.as_deref().map(|path| {
+        let key_id = args.sign_key_id.clone().unwrap_or_else(|| {
+            path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+        });
+        JobSigner::load(key_id, path).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        })
+    });
+    let mut client = Client::connect_with_tls(&orchestrator, password, args.verbose, tls_config).await
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+    if let Some(signer) = signer {
+        client = client.with_signer(signer);
+    }
+    if let Some(namespace) = namespace {
+        client = client.with_namespace(namespace);
+    }
+    if let Command::Submit(submit_args) = &command
+        && !submit_args.no_local_cache
+        && let Some(path) = KnownModules::default_path()
+    {
+        client = client.with_known_modules(KnownModules::new(path, Duration::from_secs(submit_args.local_cache_ttl)));
+    }
+    if let Command::Submit(submit_args) = &command {
+        client = client.with_hash_algorithm(submit_args.hash_alg);
+    }
+    let module_path = match &command {
+        Command::Submit(submit_args) => Some(submit_args.wasm_path.as_str()),
+        Command::Warm { wasm_path, .. } => Some(wasm_path.as_str()),
+        _ => None,
+    };
+    if let Some((name, tag)) = module_path.and_then(module_alias) {
+        let published = client.resolve_module(name, tag).await
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+        module = Some(Module::Published(published));
+    }
+    for stage in pipeline_stages.iter_mut().filter(|stage| stage.module.is_none()) {
+        let (name, tag) = module_alias(&stage.name).unwrap_or_else(|| unreachable!("only aliases aren't loaded"));
+        let published = client.resolve_module(name, tag).await
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+        stage.module = Some(Module::Published(published));
+    }
+
+    match command {
+        Command::Submit(submit_args) => {
+            if let Some(max_wait) = submit_args.max_wait
+                && !within_queue_wait(&client, Duration::from_secs(max_wait), as_json).await
+            {
+                std::process::exit(1);
+            }
+            match module {
+                Some(module) => submit(&client, module, *submit_args, as_json, verbosity).await,
+                None => watch(&client, *submit_args, as_json, verbosity).await,
+            }
+        },
+        Command::Pipeline { stdin, timeout, timing, .. } => {
+            pipeline(&client, pipeline_stages, stdin.as_deref(), timeout.or(profile.timeout), timing, as_json, verbosity).await
+        },
+        Command::Status { job_id, follow: false, .. } => status(&client, job_id, as_json).await,
+        Command::Status { job_id, follow: true, poll_interval, timeout } => {
+            follow(&client, job_id, poll_interval, timeout.map(Duration::from_secs), as_json).await
+        },
+        Command::Cancel { job_id } => cancel(&client, job_id, as_json).await,
+        Command::Replay { job_id, worker, same_worker, wasm, stdin, timeout, max_retries, timing } => {
+            let options = ReplayOptions { worker, same_worker, wasm, stdin, timeout: timeout.or(profile.timeout), max_retries, timing };
+            replay(&client, job_id, options, as_json, verbosity).await
+        },
+        Command::Jobs { state, worker, since, limit, page_token, all } => {
+            let since = since.map(|secs| SystemTime::now() - Duration::from_secs(secs));
+            jobs(&client, &JobQuery { state, worker_address: worker, since, limit }, page_token, all, as_json).await
+        },
+        Command::Workers => workers(&client, as_json).await,
+        Command::Queue => queue(&client, as_json).await,
+        Command::Plan { module: wasm_path, count } => {
+            let wasm_hash = match module {
+                Some(Module::Wasm(wasm_bytes)) => blake3::hash(&wasm_bytes),
+                _ => blake3::Hash::from_hex(&wasm_path).unwrap_or_else(|_| unreachable!("checked by is_plan_hash")),
+            };
+            plan(&client, *wasm_hash.as_bytes(), count, as_json).await
+        },
+        Command::Usage { days } => usage(&client, days, as_json).await,
+        Command::Warm { workers, timeout, .. } => {
+            let module = module.unwrap_or_else(|| unreachable!("loaded or resolved before connecting"));
+            warm(&client, module, &workers, timeout.map(Duration::from_secs), as_json).await
+        },
+        Command::Validate { .. } => {
+            let Some(Module::Wasm(wasm_bytes)) = module else {
+                unreachable!("loaded before connecting");
+            };
+            validate(&client, wasm_bytes, as_json).await
+        },
+        Command::Publish { url, digest, name, tag, timeout, max_retries, args } => {
+            let defaults = ModuleDefaults { timeout: timeout.map(Duration::from_secs), max_retries, args };
+            publish(&client, url, &digest, &name, &tag, &defaults, as_json).await
+        },
+        Command::Inspect { module, worker: Some(worker) } => inspect_worker(&client, &worker, module.as_deref(), as_json).await,
+        Command::Inspect { module: Some(alias), worker: None } => inspect(&client, &alias, as_json).await,
+        Command::Inspect { module: None, worker: None } => unreachable!("clap requires a module without --worker"),
+        Command::Config(_) | Command::Completions { .. } | Command::Run(_) => unreachable!("handled before connecting"),
+    }
+}
+
+/// Builds the clap command describing every subcommand and flag, shared by argument parsing
+/// and completion and man page generation.
+fn build_cli() -> clap::Command {
+    Args::command()
+}
+
+/// Keeps the original `cli <wasm> [args..]` form working by inserting the submit subcommand
+/// when the first positional argument, where the subcommand would be, is a .wasm path. Later
+/// arguments are the job's own, which may name .wasm files or subcommands.
+fn with_implicit_submit(mut argv: Vec<String>) -> Vec<String> {
+    let cli = build_cli();
+    let takes_value = |long: &str| cli.get_arguments()
+        .any(|arg| arg.get_long() == Some(long) && arg.get_action().takes_values());
+    let mut first = 1;
+    while let Some(arg) = argv.get(first) {
+        match arg.strip_prefix("--") {
+            Some("") => break,
+            // The option's value follows it, unless given as --option=value
+            Some(long) => first += if !long.contains('=') && takes_value(long) { 2 } else { 1 },
+            None if arg.starts_with('-') && arg != "-" => first += 1,
+            None => break,
+        }
+    }
+    if argv.get(first).is_some_and(|arg| !SUBCOMMANDS.contains(&arg.as_str()) && arg.ends_with(".wasm")) {
+        argv.insert(first, "submit".to_string());
+    }
+    argv
+}
+
+/// Builds a job from the submit options and the given wasm args. A published module's
+/// defaults fill in whatever the options leave unset.
+fn build_job(module: Module, args: &SubmitArgs, wasm_args: &[String]) -> Job {
+    let (job, defaults) = match module {
+        // A new version of a module is uploaded as a patch against the last one from its path
+        Module::Wasm(wasm_bytes) if !args.no_delta && args.wasm_path != "-" => {
+            (Job::from_bytes(wasm_bytes).delta_key(delta_key(&args.wasm_path)), None)
+        },
+        Module::Wasm(wasm_bytes) => (Job::from_bytes(wasm_bytes), None),
+        Module::Source(source) => (Job::from_source(source), None),
+        Module::Published(published) => (Job::from_source(published.source), Some(published.defaults)),
+    };
+    let mut job = job.args(wasm_args).coalesce(args.coalesce);
+    if let Some(max_retries) = args.max_retries {
+        job = job.max_retries(max_retries);
+    }
+    if args.no_cache {
+        job = job.cache_mode(CacheMode::NoCache);
+    } else if args.hash_only {
+        job = job.cache_mode(CacheMode::HashOnly);
+    }
+    if let Some(timeout) = args.timeout {
+        job = job.timeout(Duration::from_secs(timeout));
+    }
+    if let Some(delay) = args.delay {
+        job = job.delay(Duration::from_secs(delay));
+    }
+    if let Some(callback_url) = &args.callback_url {
+        job = job.callback_url(callback_url);
+    }
+    if let Some(defaults) = defaults {
+        job = job.defaults(&defaults);
+    }
+    job
+}
+
+/// What identifies the module at a path or URL across versions: a file's absolute path, so that
+/// it is the same from any directory, or the URL.
+fn delta_key(wasm_path: &str) -> String {
+    std::fs::canonicalize(wasm_path).map_or_else(|_| wasm_path.to_string(), |path| path.display().to_string())
+}
+
+async fn submit(client: &Client, module: Module, args: SubmitArgs, as_json: bool, verbosity: Verbosity) {
+    if let Some(args_file) = &args.args_file {
+        return submit_batch(client, module, args_file, &args, as_json, verbosity).await;
+    }
+    if let Some(repeat) = args.repeat {
+        return bench::run(client, module, repeat, &args, as_json).await;
+    }
+    if let Some(runs) = args.verify {
+        return verify::run(client, module, runs as usize, &args, as_json).await;
+    }
+
+    let job = build_job(module, &args, &args.wasm_args);
+    let running_job = client.submit_job(job);
+    let job_id = running_job.job_id();
+    if !as_json && (verbosity == Verbosity::Verbose || args.print_job_id) {
+        eprintln!("job id: {job_id}");
+    }
+
+    let progress = (!as_json && verbosity != Verbosity::Quiet)
+        .then(|| tokio::spawn(print_queue_progress(running_job.clone())));
+    let result = running_job.wait().await;
+    if let Some(progress) = progress {
+        progress.abort();
+    }
+    if !as_json && verbosity == Verbosity::Verbose
+        && let Ok(output) = &result
+    {
+        if output.timing.cold_start() {
+            eprintln!("cold start (compile {}ms)", output.timing.compile.as_millis());
+        } else {
+            eprintln!("warm start");
+        }
+        if !output.timing.instantiation_saved.is_zero() {
+            eprintln!("pooled instance saved {}ms instantiating", output.timing.instantiation_saved.as_millis());
+        }
+        eprintln!("estimated cost: {:.4}", output.estimated_cost);
+        if output.upload_bytes_saved > 0 {
+            eprintln!("delta upload saved {} bytes", output.upload_bytes_saved);
+        }
+        if let Some(leader) = output.coalesced_with {
+            eprintln!("coalesced with job {leader}");
+        }
+        if output.output_offloaded {
+            eprintln!("output fetched from the worker's store ({} bytes)", output.stdout.len());
+        }
+    }
+    print_result(job_id, result, args.timing, as_json);
+}
+
+/// Prints the job's place in the queue to stderr each time the orchestrator reports it.
+async fn print_queue_progress(mut job: RunningJob) {
+    while job.queue_position_changed().await {
+        if let Some(progress) = job.queue_position() {
+            let wait = progress.estimated_wait.map_or("unknown".to_string(), |wait| format!("{:.0}s", wait.as_secs_f64()));
+            eprintln!("queued, position {} of {}, est. {wait}", progress.position, progress.queued_jobs);
+        }
+    }
+}
+
+/// Loads the module to submit: a reference workers fetch themselves for an s3:// URL, which
+/// needs --digest, or else the wasm itself.
+async fn load_module(wasm_path: &str, digest: Option<&str>, timeout: Option<u64>, validate: bool) -> Result<Module, String> {
+    if !wasm_path.starts_with("s3://") {
+        if digest.is_some() {
+            return Err("--digest only applies to s3:// modules".to_string());
+        }
+        return load_wasm(wasm_path, timeout, validate).await.map(Module::Wasm);
+    }
+    let digest = digest
+        .ok_or_else(|| format!("--digest is required to submit {wasm_path}"))?;
+    let digest = ModuleDigest::parse(digest)
+        .map_err(|e| format!("invalid --digest {digest}: {e}"))?;
+    Ok(Module::Source(ModuleSource::ObjectStore { url: wasm_path.to_string(), digest }))
+}
+
+/// Splits a module argument of the form name:tag. Paths, URLs and anything naming an
+/// existing file are never aliases.
+fn module_alias(wasm_path: &str) -> Option<(&str, &str)> {
+    if wasm_path.contains('/') || Path::new(wasm_path).exists() {
+        return None;
+    }
+    let (name, tag) = wasm_path.split_once(':')?;
+    (!name.is_empty() && !tag.is_empty()).then_some((name, tag))
+}
+
+/// Reads the wasm module from a file path, an http(s) URL, or stdin if the source is "-",
+/// assembling it if it is wat and validating it unless told not to.
+async fn load_wasm(source: &str, timeout: Option<u64>, validate: bool) -> Result<Vec<u8>, String> {
+    let wasm_bytes = if source == "-" {
+        let mut wasm_bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut wasm_bytes)
+            .map_err(|e| format!("failed to read the module from stdin: {e}"))?;
+        if wasm_bytes.is_empty() {
+            return Err("no module was piped to stdin".to_string());
+        }
+        wasm_bytes
+    } else if source.starts_with("http://") || source.starts_with("https://") {
+        let mut http = reqwest::Client::builder();
+        if let Some(timeout) = timeout {
+            http = http.timeout(Duration::from_secs(timeout));
+        }
+        let http = http.build()
+            .map_err(|e| format!("failed to build http client: {e}"))?;
+        let response = http.get(source).send().await
+            .map_err(|e| format!("failed to fetch the module from {source}: {e}"))?;
+        if !response.status().is_success() {
+            return Err(format!("fetching the module from {source} returned {}", response.status()));
+        }
+        let wasm_bytes = response.bytes().await
+            .map_err(|e| format!("failed to read the module body from {source}: {e}"))?;
+        if wasm_bytes.is_empty() {
+            return Err(format!("{source} returned an empty body"));
+        }
+        wasm_bytes.to_vec()
+    } else {
+        std::fs::read(source)
+            .map_err(|e| format!("failed to read the module from {source}: {e}"))?
+    };
+
+    wasm::prepare(source, wasm_bytes, validate)
+}
+
+fn print_result(job_id: JobId, result: Result<JobOutput, JobError>, show_timing: bool, as_json: bool) {
+    match (result, as_json) {
+        (Ok(output), true) => {
+            let timing = &output.timing;
+            println!("{}", json!({
+                "job_id": job_id,
+                "stdout": String::from_utf8_lossy(&output.stdout),
+                "stderr": String::from_utf8_lossy(&output.stderr),
+                "timing": {
+                    "queued_ms": timing.queued.as_millis() as u64,
+                    "transfer_ms": timing.transfer.as_millis() as u64,
+                    "compile_ms": timing.compile.as_millis() as u64,
+                    "execute_ms": timing.execute.as_millis() as u64,
+                    "total_ms": timing.total.as_millis() as u64,
+                    "cache_hit": timing.cache_hit,
+                    "cold_start": timing.cold_start(),
+                    "instantiation_saved_ms": timing.instantiation_saved.as_millis() as u64,
+                },
+                "estimated_cost": output.estimated_cost,
+                "coalesced_with": output.coalesced_with.map(|id| id.to_string()),
+                "upload_bytes_saved": output.upload_bytes_saved,
+                "output_offloaded": output.output_offloaded,
+            }));
+        },
+        (Err(e), true) => {
+            println!("{}", json!({ "job_id": job_id, "error": e.to_string() }));
+        },
+        (Ok(output), false) => {
+            print!("{}", String::from_utf8_lossy(&output.stdout));
+            if !output.stderr.is_empty() {
+                eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            }
+            if show_timing {
+                print_timing(&output.timing);
+            }
+        },
+        (Err(e), false) => {
+            eprintln!("Job failed: {}", e);
+        }
+    }
+}
+
+/// Prints the job's timing breakdown to stderr.
+fn print_timing(timing: &JobTiming) {
+    let cache = if timing.cache_hit { " (cached)" } else { "" };
+    let pooled = if timing.instantiation_saved.is_zero() {
+        String::new()
+    } else {
+        format!(" (pooled instance, {:.3}s instantiating saved)", timing.instantiation_saved.as_secs_f64())
+    };
+    eprintln!();
+    eprintln!("queued:   {:>8.3}s", timing.queued.as_secs_f64());
+    eprintln!("transfer: {:>8.3}s", timing.transfer.as_secs_f64());
+    eprintln!("compile:  {:>8.3}s{cache}", timing.compile.as_secs_f64());
+    eprintln!("execute:  {:>8.3}s{pooled}", timing.execute.as_secs_f64());
+    eprintln!("total:    {:>8.3}s", timing.total.as_secs_f64());
+}
+
+/// Resubmits the job whenever the wasm file's contents change, until Ctrl-C.
+/// Rapid bursts of file events (e.g. during a rebuild) are debounced, and events that leave
+/// the contents unchanged are ignored.
+async fn watch(client: &Client, args: SubmitArgs, as_json: bool, verbosity: Verbosity) {
+    if !Path::new(&args.wasm_path).is_file() {
+        eprintln!("--watch requires the module to be a local file");
+        std::process::exit(1);
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res && !event.kind.is_access() {
+            tx.send(()).ok();
+        }
+    }).unwrap_or_else(|e| panic!("failed to start file watcher: {}", e));
+
+    // Watch the wasm file's directory rather than the file itself, since builds often
+    // replace the file instead of writing to it in place
+    let wasm_dir = Path::new(&args.wasm_path).parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    watcher.watch(wasm_dir, RecursiveMode::NonRecursive)
+        .unwrap_or_else(|e| panic!("failed to watch {}: {}", wasm_dir.display(), e));
+    for path in &args.watch_paths {
+        watcher.watch(Path::new(path), RecursiveMode::Recursive)
+            .unwrap_or_else(|e| panic!("failed to watch {}: {}", path, e));
+    }
+
+    // Run once on startup, then again each time the file settles with new contents
+    let mut last_hash = None;
+    loop {
+        match std::fs::read(&args.wasm_path) {
+            Err(e) => eprintln!("failed to read {}: {}", args.wasm_path, e),
+            Ok(file_bytes) => {
+                let hash = blake3::hash(&file_bytes);
+                if last_hash != Some(hash) {
+                    if !as_json && verbosity != Verbosity::Quiet {
+                        // Job output may not end with a newline
+                        let separator_prefix = if last_hash.is_some() { "\n" } else { "" };
+                        println!("{separator_prefix}── run at {} ──", fmt_time_of_day(SystemTime::now()));
+                    }
+                    last_hash = Some(hash);
+                    match wasm::prepare(&args.wasm_path, file_bytes, !args.no_validate) {
+                        // An invalid module is reported without submitting, then we wait for the next change
+                        Err(e) => eprintln!("{e}"),
+                        Ok(wasm_bytes) => {
+                            let running_job = client.submit_job(build_job(Module::Wasm(wasm_bytes), &args, &args.wasm_args));
+                            let job_id = running_job.job_id();
+                            tokio::select! {
+                                result = running_job.clone().wait() => print_result(job_id, result, args.timing, as_json),
+                                _ = tokio::signal::ctrl_c() => {
+                                    // Wait for the cancellation to reach the orchestrator or worker
+                                    running_job.clone().cancel().await;
+                                    running_job.wait().await.ok();
+                                    return;
+                                }
+                            }
+                        },
+                    }
+                }
+            },
+        }
+
+        tokio::select! {
+            event = rx.recv() => {
+                if event.is_none() {
+                    return;
+                }
+                // Debounce: wait until events stop arriving before rereading the file
+                while let Ok(Some(())) = tokio::time::timeout(WATCH_DEBOUNCE, rx.recv()).await {}
+            },
+            _ = tokio::signal::ctrl_c() => return,
+        }
+    }
+}
+
+/// Formats the UTC time of day as HH:MM:SS.
+fn fmt_time_of_day(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % 86400;
+    format!("{:02}:{:02}:{:02} UTC", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Submits one job per line of the args file, keeping up to --concurrency jobs in flight.
+/// Prints each job's result as it completes, then a summary. Exits non-zero if any job failed.
+async fn submit_batch(client: &Client, module: Module, args_file: &str, args: &SubmitArgs, as_json: bool, verbosity: Verbosity) {
+    let contents = std::fs::read_to_string(args_file)
+        .unwrap_or_else(|e| panic!("failed to read args file: {}", e));
+    let arg_sets: Vec<Vec<String>> = contents.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split_whitespace().map(String::from).collect())
+        .collect();
+
+    let mut pending = arg_sets.into_iter().enumerate();
+    let mut in_flight = JoinSet::new();
+    let mut latencies = Vec::new();
+    let mut failures = 0;
+
+    loop {
+        while in_flight.len() < args.concurrency.max(1) && let Some((index, wasm_args)) = pending.next() {
+            let running_job = client.submit_job(build_job(module.clone(), args, &wasm_args));
+            let job_id = running_job.job_id();
+            let started_at = Instant::now();
+            in_flight.spawn(async move {
+                let result = running_job.wait().await;
+                (index, job_id, wasm_args, started_at.elapsed(), result)
+            });
+        }
+        let Some(joined) = in_flight.join_next().await else {
+            break;
+        };
+        let (index, job_id, wasm_args, latency, result) = joined
+            .unwrap_or_else(|e| panic!("batch job task panicked: {}", e));
+
+        latencies.push(latency);
+        if result.is_err() {
+            failures += 1;
+        }
+        if as_json {
+            let mut line = json!({
+                "index": index,
+                "job_id": job_id,
+                "args": wasm_args,
+                "latency_ms": latency.as_millis() as u64,
+            });
+            match result {
+                Ok(output) => {
+                    line["stdout"] = json!(String::from_utf8_lossy(&output.stdout));
+                    line["stderr"] = json!(String::from_utf8_lossy(&output.stderr));
+                },
+                Err(e) => line["error"] = json!(e.to_string()),
+            }
+            println!("{line}");
+        } else {
+            match result {
+                Ok(output) => println!("[{index}] {}", String::from_utf8_lossy(&output.stdout).trim_end()),
+                Err(e) => println!("[{index}] failed: {e}"),
+            }
+        }
+    }
+
+    latencies.sort();
+    let total = latencies.len();
+    if verbosity != Verbosity::Quiet {
+        eprintln!();
+        eprintln!("jobs:      {total}");
+        eprintln!("succeeded: {}", total - failures);
+        eprintln!("failed:    {failures}");
+        if total > 0 {
+            eprintln!("p50:       {:.2}s", percentile(&latencies, 0.50).as_secs_f64());
+            eprintln!("p95:       {:.2}s", percentile(&latencies, 0.95).as_secs_f64());
+        }
+    }
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Returns the value at the given percentile of a sorted, non-empty slice.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
+
+async fn status(client: &Client, job_id: JobId, as_json: bool) {
+    match client.job_status(job_id).await {
+        Ok(status) => print_status(job_id, &status, as_json),
+        Err(e) => fail(e, as_json),
+    }
+}
+
+/// Parses --poll-interval, refusing intervals short enough to make --follow a busy loop.
+fn parse_poll_interval(secs: &str) -> Result<Duration, String> {
+    let secs: f64 = secs.parse().map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    match Duration::try_from_secs_f64(secs) {
+        Ok(interval) if interval >= MIN_POLL_INTERVAL => Ok(interval),
+        _ => Err(format!("must be at least {} seconds", MIN_POLL_INTERVAL.as_secs_f64())),
+    }
+}
+
+/// Polls the job's status until it reaches a terminal state, printing each state change.
+/// Exits non-zero if the job is unknown, doesn't complete successfully, or the timeout passes.
+async fn follow(client: &Client, job_id: JobId, poll_interval: Duration, timeout: Option<Duration>, as_json: bool) {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut last_state = None;
+    loop {
+        let status = match client.job_status(job_id).await {
+            Ok(status) => status,
+            Err(ClientError::JobNotFound) => {
+                if as_json {
+                    println!("{}", json!({ "job_id": job_id, "error": "unknown job" }));
+                } else {
+                    eprintln!("job {job_id} is unknown to the orchestrator");
+                }
+                std::process::exit(1);
+            },
+            Err(e) => return fail(e, as_json),
+        };
+        if last_state.as_ref() != Some(&status.state) {
+            if as_json {
+                println!("{}", json!({ "job_id": job_id, "state": status.state, "worker_address": status.worker_address }));
+            } else {
+                println!("{}  {}", fmt_time_of_day(SystemTime::now()), status.state);
+            }
+            last_state = Some(status.state.clone());
+        }
+
+        if status.is_terminal() {
+            if !as_json {
+                println!();
+                print_status(job_id, &status, false);
+            }
+            if status.state != "completed" {
+                std::process::exit(1);
+            }
+            return;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() + poll_interval > deadline) {
+            if as_json {
+                println!("{}", json!({ "job_id": job_id, "error": "timed out waiting for the job to finish" }));
+            } else {
+                eprintln!("timed out waiting for the job to finish, it is still {}", status.state);
+            }
+            std::process::exit(1);
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+fn print_status(job_id: JobId, status: &JobStatus, as_json: bool) {
+    if as_json {
+        println!("{}", json!({
+            "job_id": job_id,
+            "state": status.state,
+            "worker_address": status.worker_address,
+            "queued_at_ms": epoch_ms(status.queued_at),
+            "completed_at_ms": status.completed_at.map(epoch_ms),
+            "retries": status.retries,
+            "late_state": status.late_state,
+            "replay_of": status.replay_of,
+        }));
+    } else {
+        println!("state:   {}", status.state);
+        println!("worker:  {}", status.worker_address.as_deref().unwrap_or("-"));
+        if let Some(completed_at) = status.completed_at {
+            let took = completed_at.duration_since(status.queued_at).unwrap_or_default();
+            println!("took:    {:.2}s", took.as_secs_f64());
+        }
+        if status.retries > 0 {
+            println!("retries: {}", status.retries);
+        }
+        if let Some(late_state) = &status.late_state {
+            println!("late:    {late_state} (after it was presumed lost)");
+        }
+        if let Some(replay_of) = status.replay_of {
+            println!("replay:  of {replay_of}");
+        }
+    }
+}
+
+async fn cancel(client: &Client, job_id: JobId, as_json: bool) {
+    match client.cancel_job(job_id).await {
+        Ok(()) if as_json => println!("{}", json!({ "job_id": job_id, "cancelled": true })),
+        Ok(()) => println!("cancelled {job_id}"),
+        Err(e) => fail(e, as_json),
+    }
+}
+
+/// A stage of `cli pipeline`: the module as given, which is None until an alias is resolved,
+/// and its args.
+struct StageArgs {
+    name: String,
+    module: Option<Module>,
+    args: Vec<String>,
+}
+
+/// Splits `cli pipeline`'s stages, each `--stage <module> [-- <args>..]`, and loads their
+/// modules, leaving aliases to be resolved once connected. The `--` may be left out when no
+/// arg starts with a hyphen.
+async fn load_stages(argv: &[String]) -> Result<Vec<StageArgs>, String> {
+    let mut argv = argv.iter().peekable();
+    let mut stages = Vec::new();
+    while let Some(arg) = argv.next() {
+        if arg != "--stage" {
+            return Err(format!("expected --stage <module>, got {arg}"));
+        }
+        let name = argv.next().filter(|name| !name.starts_with("--"))
+            .ok_or("--stage needs a module")?;
+        argv.next_if(|arg| arg.as_str() == "--");
+        let mut args = Vec::new();
+        while let Some(arg) = argv.next_if(|arg| arg.as_str() != "--stage") {
+            args.push(arg.clone());
+        }
+
+        let module = if module_alias(name).is_some() {
+            None
+        } else if name == "-" || name.starts_with("s3://") {
+            return Err(format!("a pipeline stage can't run {name}, publish it under an alias or pass its path"));
+        } else {
+            Some(Module::Wasm(load_wasm(name, None, true).await?))
+        };
+        stages.push(StageArgs { name: name.clone(), module, args });
+    }
+    Ok(stages)
+}
+
+/// Runs the stages as a pipeline and prints the last one's output. Each stage's stderr goes to
+/// stderr as it would for a job on its own. A failed stage is named along with its job, and
+/// exits with an error.
+async fn pipeline(client: &Client, stages: Vec<StageArgs>, stdin: Option<&str>, timeout: Option<u64>, timing: bool, as_json: bool, verbosity: Verbosity) {
+    let names: Vec<String> = stages.iter().map(|stage| stage.name.clone()).collect();
+    let mut pipeline = Pipeline::new();
+    for stage in stages {
+        let job = match stage.module {
+            Some(Module::Wasm(wasm_bytes)) => Job::from_bytes(wasm_bytes).args(&stage.args),
+            Some(Module::Published(published)) => Job::from_source(published.source).args(&stage.args).defaults(&published.defaults),
+            Some(Module::Source(_)) | None => unreachable!("stages are loaded or resolved before running"),
+        };
+        pipeline = pipeline.stage(job);
+    }
+    if let Some(stdin) = stdin {
+        let input = read_input(stdin).unwrap_or_else(|e| {
+            eprintln!("failed to read the pipeline's stdin from {stdin}: {e}");
+            std::process::exit(1);
+        });
+        pipeline = pipeline.stdin(input);
+    }
+    if let Some(timeout) = timeout {
+        pipeline = pipeline.timeout(Duration::from_secs(timeout));
+    }
+
+    let output = match client.run_pipeline(&pipeline).await {
+        Ok(output) => output,
+        Err(e) => return fail(e, as_json),
+    };
+
+    if as_json {
+        let stages: Vec<_> = output.stages.iter().zip(&names).map(|(stage, name)| json!({
+            "module": name,
+            "job_id": stage.job_id,
+            "worker_address": stage.worker_address,
+            "stderr": String::from_utf8_lossy(&stage.stderr),
+            "stdout_bytes": stage.stdout_bytes,
+            "compile_ms": stage.compile.as_millis() as u64,
+            "execute_ms": stage.execute.as_millis() as u64,
+            "cache_hit": stage.cache_hit,
+            "estimated_cost": stage.estimated_cost,
+        })).collect();
+        let failure = output.failure.as_ref().map(|failure| json!({
+            "stage": failure.stage,
+            "module": names.get(failure.stage as usize),
+            "job_id": failure.job_id,
+            "error": failure.error.to_string(),
+            "kind": failure.error.kind(),
+        }));
+        println!("{}", json!({ "stdout": String::from_utf8_lossy(&output.stdout), "stages": stages, "failure": failure }));
+        if output.failure.is_some() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    for (index, (stage, name)) in output.stages.iter().zip(&names).enumerate() {
+        if !stage.stderr.is_empty() {
+            eprint!("{}", String::from_utf8_lossy(&stage.stderr));
+        }
+        if timing || verbosity == Verbosity::Verbose {
+            let cache = if stage.cache_hit { " (cached)" } else { "" };
+            eprintln!(
+                "stage {index} ({name}): job {} on {}, compile {:.3}s{cache}, execute {:.3}s, {} bytes out",
+                stage.job_id, stage.worker_address, stage.compile.as_secs_f64(), stage.execute.as_secs_f64(), stage.stdout_bytes,
+            );
+        }
+    }
+    if let Some(failure) = output.failure {
+        let name = names.get(failure.stage as usize).map_or("?", String::as_str);
+        eprintln!("Pipeline failed at stage {} ({name}), job {}: {}", failure.stage, failure.job_id, failure.error);
+        std::process::exit(1);
+    }
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+}
+
+/// Reads what a job reads on its stdin from a file, or from stdin if the source is "-".
+fn read_input(source: &str) -> std::io::Result<Vec<u8>> {
+    if source == "-" {
+        let mut input = Vec::new();
+        std::io::stdin().read_to_end(&mut input).map(|_| input)
+    } else {
+        std::fs::read(source)
+    }
+}
+
+/// How `cli replay` reruns a job.
+struct ReplayOptions {
+    worker: Option<String>,
+    same_worker: bool,
+    wasm: Option<String>,
+    stdin: Option<String>,
+    timeout: Option<u64>,
+    max_retries: Option<u32>,
+    timing: bool,
+}
+
+/// Reruns a job with the module, args and stdin the orchestrator recorded for it. The
+/// orchestrator only has the module's hash, so unless --wasm is given the replay runs only if
+/// its worker still has the module compiled. It keeps stdin it relayed to pipeline stages for
+/// a while, and a job's stdin must be given with --stdin once it no longer does.
+async fn replay(client: &Client, job_id: JobId, options: ReplayOptions, as_json: bool, verbosity: Verbosity) {
+    let submission = match client.job_submission(job_id).await {
+        Ok(submission) => submission,
+        Err(e) => return fail(e, as_json),
+    };
+    let stdin = options.stdin.as_deref().map(|source| read_input(source).unwrap_or_else(|e| {
+        eprintln!("failed to read job {job_id}'s stdin from {source}: {e}");
+        std::process::exit(1);
+    }));
+    let job = match (&options.wasm, &submission.module) {
+        (None, _) => submission.replay(stdin),
+        (Some(wasm_path), SubmittedModule::Hash(hash)) => {
+            let wasm_bytes = load_wasm(wasm_path, None, false).await
+                .unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                });
+            if blake3::hash(&wasm_bytes) != *hash {
+                eprintln!("{wasm_path} is not the module job {job_id} ran, which has hash {}", blake3::Hash::from_bytes(*hash));
+                std::process::exit(1);
+            }
+            submission.replay_stdin(stdin)
+                .map(|stdin| Job::from_bytes(wasm_bytes).args(&submission.args).stdin(stdin).replay_of(job_id))
+        },
+        (Some(_), SubmittedModule::Source(ModuleSource::ObjectStore { url, .. })) => {
+            eprintln!("--wasm doesn't apply to job {job_id}, whose worker fetches its module from {url}");
+            std::process::exit(1);
+        },
+    };
+    let mut job = match job {
+        Ok(job) => job,
+        Err(ClientError::StdinUnavailable(message)) => {
+            return fail(ClientError::StdinUnavailable(format!("{message}; pass --stdin <path> to give it again")), as_json);
+        },
+        Err(e) => return fail(e, as_json),
+    };
+    let worker = match (options.worker, options.same_worker) {
+        (Some(worker), _) => Some(worker),
+        (None, true) => match &submission.worker_address {
+            Some(worker) => Some(worker.clone()),
+            None => {
+                eprintln!("job {job_id} has no worker to replay it on");
+                std::process::exit(1);
+            },
+        },
+        (None, false) => None,
+    };
+    if let Some(worker) = worker {
+        job = job.worker(worker);
+    }
+    if let Some(timeout) = options.timeout {
+        job = job.timeout(Duration::from_secs(timeout));
+    }
+    if let Some(max_retries) = options.max_retries {
+        job = job.max_retries(max_retries);
+    }
+
+    let running_job = client.submit_job(job);
+    let replay_id = running_job.job_id();
+    if !as_json && verbosity == Verbosity::Verbose {
+        eprintln!("job id: {replay_id}");
+    }
+    let result = running_job.wait().await;
+    if let (Err(JobError::ModuleNotCached), SubmittedModule::Hash(hash)) = (&result, &submission.module) {
+        let message = format!(
+            "the module job {job_id} ran ({}) is no longer cached on the worker, and the orchestrator never keeps it; pass --wasm <path> to upload it again",
+            blake3::Hash::from_bytes(*hash),
+        );
+        if as_json {
+            println!("{}", json!({ "job_id": replay_id, "error": message }));
+        } else {
+            eprintln!("{message}");
+        }
+        std::process::exit(1);
+    }
+    print_result(replay_id, result, options.timing, as_json);
+}
+
+/// Prints a page of jobs, or with all every page, followed by the token for the next page if
+/// there is one.
+async fn jobs(client: &Client, query: &JobQuery, mut page_token: Option<String>, all: bool, as_json: bool) {
+    let mut jobs = Vec::new();
+    loop {
+        let page = match client.list_jobs(query, page_token).await {
+            Ok(page) => page,
+            Err(e) => return fail(e, as_json),
+        };
+        jobs.extend(page.jobs);
+        page_token = page.next_page_token;
+        if !all || page_token.is_none() {
+            break;
+        }
+    }
+
+    if as_json {
+        let jobs: Vec<_> = jobs.iter().map(|j| json!({
+            "job_id": j.job_id,
+            "state": j.status.state,
+            "worker_address": j.status.worker_address,
+            "scheduled_for_ms": j.scheduled_for.map(epoch_ms),
+            "queued_at_ms": epoch_ms(j.status.queued_at),
+            "started_at_ms": j.started_at.map(epoch_ms),
+            "completed_at_ms": j.status.completed_at.map(epoch_ms),
+            "retries": j.status.retries,
+            "late_state": j.status.late_state,
+            "replay_of": j.status.replay_of,
+            "owner": j.owner,
+        })).collect();
+        println!("{}", json!({ "jobs": jobs, "next_page_token": page_token }));
+        return;
+    }
+
+    if jobs.is_empty() {
+        println!("no jobs");
+    } else {
+        println!("{:<36} {:<15} {:<22} {:<22} {:>12} {:>9} {:>7}", "JOB ID", "STATE", "OWNER", "WORKER", "QUEUED", "TOOK", "RETRIES");
+        for j in &jobs {
+            let took = match j.status.completed_at {
+                Some(completed_at) => format!("{:.2}s", completed_at.duration_since(j.status.queued_at).unwrap_or_default().as_secs_f64()),
+                None => "-".to_string(),
+            };
+            let owner = j.owner.as_deref().unwrap_or("-");
+            let worker = j.status.worker_address.as_deref().unwrap_or("-");
+            println!("{:<36} {:<15} {:<22} {:<22} {:>12} {:>9} {:>7}", j.job_id, j.status.state, owner, worker, fmt_time_of_day(j.status.queued_at), took, j.status.retries);
+        }
+    }
+    if let Some(token) = page_token {
+        println!("\nmore jobs: --page-token {token}");
+    }
+}
+
+async fn workers(client: &Client, as_json: bool) {
+    let mut workers = match client.list_workers().await {
+        Ok(workers) => workers,
+        Err(e) => return fail(e, as_json),
+    };
+    workers.sort_by(|a, b| a.address.cmp(&b.address));
+
+    if as_json {
+        let workers: Vec<_> = workers.iter().map(|w| json!({
+            "address": w.address,
+            "online": w.online,
+            "quarantined": w.quarantined,
+            "drained": w.drained,
+            "validating": w.validating,
+            "jobs_received": w.jobs_received,
+            "total_job_time_ms": w.total_job_time.as_millis() as u64,
+            "cold_starts": w.cold_starts,
+            "total_compile_time_ms": w.total_compile_time.as_millis() as u64,
+            "flap_count": w.flap_count,
+            "limits": w.limits.map(|l| json!({
+                "max_wasm_bytes": l.max_wasm_bytes,
+                "max_args": l.max_args,
+                "max_args_bytes": l.max_args_bytes,
+            })),
+            "signed_jobs_only": w.signed_jobs_only,
+            "version": w.version,
+            "namespace": w.namespace,
+            "host_load": w.host_load,
+            "load_credits": w.load_credits,
+            "running_jobs": w.running_jobs,
+            "estimated_running_jobs": w.estimated_running_jobs,
+            "out_of_band_jobs": w.out_of_band_jobs,
+            "reserved_credits": w.reserved_credits,
+            "heartbeat_interval_ms": w.heartbeat_interval.map(|d| d.as_millis() as u64),
+            "heartbeat_jitter_ms": w.heartbeat_jitter.map(|d| d.as_millis() as u64),
+            "clock_skew_ms": w.clock_skew_ms,
+            "heartbeat_anomaly": w.heartbeat_anomaly,
+        })).collect();
+        println!("{}", json!(workers));
+        return;
+    }
+
+    println!("{:<22} {:<12} {:>9} {:>9} {:>6} {:>6} {:>6} {:>8} {:>9}  NAMESPACE", "ADDRESS", "STATUS", "JOBS", "AVG JOB", "COLD", "FLAPS", "LOAD", "RUN/EST", "VERSION");
+    for w in workers {
+        let status = if !w.online {
+            "offline"
+        } else if w.quarantined {
+            "quarantined"
+        } else if w.validating {
+            "validating"
+        } else if w.drained {
+            "drained"
+        } else {
+            "online"
+        };
+        let avg = if w.jobs_received > 0 {
+            format!("{:.2}s", (w.total_job_time / w.jobs_received).as_secs_f64())
+        } else {
+            "-".to_string()
+        };
+        let cold = if w.jobs_received > 0 {
+            format!("{:.0}%", w.cold_starts as f64 * 100.0 / w.jobs_received as f64)
+        } else {
+            "-".to_string()
+        };
+        let load = match w.host_load {
+            Some(load) => format!("{:.0}%", load * 100.0),
+            None => "-".to_string(),
+        };
+        // What the worker says it runs, against what the orchestrator's credit count says
+        let running = match (w.running_jobs, w.estimated_running_jobs) {
+            (Some(reported), Some(estimated)) => format!("{reported}/{estimated}"),
+            _ => "-".to_string(),
+        };
+        // Flags workers whose heartbeats arrive irregularly or whose clock is off
+        let status = if w.heartbeat_anomaly { format!("{status} ⚠") } else { status.to_string() };
+        let version = w.version.as_deref().unwrap_or("-");
+        let namespace = w.namespace.as_deref().unwrap_or("-");
+        println!("{:<22} {:<12} {:>9} {:>9} {:>6} {:>6} {:>6} {:>8} {:>9}  {}", w.address, status, w.jobs_received, avg, cold, w.flap_count, load, running, version, namespace);
+    }
+}
+
+async fn queue(client: &Client, as_json: bool) {
+    let stats = match client.queue_stats().await {
+        Ok(stats) => stats,
+        Err(e) => return fail(e, as_json),
+    };
+    if as_json {
+        println!("{}", json!({
+            "queued_jobs": stats.queued_jobs,
+            "advertised_credits": stats.advertised_credits,
+            "dispatches_last_minute": stats.dispatches_last_minute,
+            "estimated_wait_ms": stats.estimated_wait.map(|wait| wait.as_millis() as u64),
+        }));
+    } else {
+        println!("queued jobs:        {}", stats.queued_jobs);
+        println!("advertised credits: {}", stats.advertised_credits);
+        println!("dispatches/min:     {}", stats.dispatches_last_minute);
+        println!("estimated wait:     {}", fmt_estimated_wait(stats.estimated_wait));
+    }
+}
+
+/// Whether plan was given a module's hash rather than a path to it. Files named like a hash
+/// are still read.
+fn is_plan_hash(module: &str) -> bool {
+    blake3::Hash::from_hex(module).is_ok() && !Path::new(module).exists()
+}
+
+async fn plan(client: &Client, wasm_hash: [u8; 32], count: u32, as_json: bool) {
+    let spec = PlanSpec { wasm_hash: Some(wasm_hash), count };
+    let plan = match client.plan(std::slice::from_ref(&spec)).await {
+        Ok(plans) => plans.into_iter().next(),
+        Err(e) => return fail(e, as_json),
+    };
+    let Some(plan) = plan else {
+        return fail(ClientError::RequestFailed("the orchestrator returned no plan".to_string()), as_json);
+    };
+    if as_json {
+        let workers: Vec<_> = plan.workers.iter().map(|w| json!({
+            "worker_address": w.address,
+            "jobs": w.jobs,
+            "module_cached": w.module_cached,
+        })).collect();
+        println!("{}", json!({
+            "wasm_hash": hex(&wasm_hash),
+            "count": count,
+            "dispatched": plan.dispatched,
+            "workers": workers,
+            "queued": plan.queued,
+            "estimated_wait_ms": plan.estimated_wait.map(|wait| wait.as_millis() as u64),
+        }));
+        return;
+    }
+    println!("dispatched now: {}", plan.dispatched);
+    for worker in &plan.workers {
+        println!("  {:<24} {} jobs, {} with the module cached", worker.address, worker.jobs, worker.module_cached);
+    }
+    println!("queued:         {}", plan.queued);
+    if plan.queued > 0 {
+        println!("estimated wait: {}", fmt_estimated_wait(plan.estimated_wait));
+    }
+}
+
+async fn usage(client: &Client, days: u32, as_json: bool) {
+    let usage = match client.usage(Some(days)).await {
+        Ok(usage) => usage,
+        Err(e) => return fail(e, as_json),
+    };
+    let day_json = |day: &DailyUsage| json!({
+        "date": day.date,
+        "jobs": day.jobs,
+        "execute_ms": day.execute.as_millis() as u64,
+        "compiles": day.compiles,
+        "module_bytes": day.module_bytes,
+        "cost": day.cost,
+    });
+    if as_json {
+        let weights = &usage.cost_weights;
+        println!("{}", json!({
+            "days": usage.days.iter().map(day_json).collect::<Vec<_>>(),
+            "total": day_json(&usage.total),
+            "cost_weights": {
+                "per_execution_second": weights.per_execution_second,
+                "per_compile": weights.per_compile,
+                "per_module_mb": weights.per_module_mb,
+            },
+        }));
+        return;
+    }
+    if usage.days.is_empty() {
+        println!("no jobs in the last {days} days");
+        return;
+    }
+    println!("{:<10} {:>7} {:>10} {:>8} {:>10} {:>12}", "DATE", "JOBS", "EXECUTE", "COMPILES", "MODULES", "COST");
+    for day in usage.days.iter().chain([&usage.total]) {
+        let date = if day.date.is_empty() { "total" } else { &day.date };
+        println!(
+            "{:<10} {:>7} {:>9.1}s {:>8} {:>8.1}MB {:>12.4}",
+            date, day.jobs, day.execute.as_secs_f64(), day.compiles, day.module_bytes as f64 / (1024.0 * 1024.0), day.cost,
+        );
+    }
+}
+
+/// Prints how warming the module went on each worker, exiting non-zero if any failed.
+async fn warm(client: &Client, module: Module, workers: &[String], timeout: Option<Duration>, as_json: bool) {
+    let job = match module {
+        Module::Wasm(wasm_bytes) => Job::from_bytes(wasm_bytes),
+        Module::Source(source) => Job::from_source(source),
+        Module::Published(published) => Job::from_source(published.source),
+    };
+    let results = match client.warm_workers(&job, workers, timeout).await {
+        Ok(results) => results,
+        Err(e) => return fail(e, as_json),
+    };
+    let failed = results.iter().any(|r| matches!(r.outcome, WarmOutcome::Failed(_)));
+
+    if as_json {
+        let results: Vec<_> = results.iter().map(|r| json!({
+            "worker_address": r.worker_address,
+            "outcome": r.outcome.as_str(),
+            "reason": r.outcome.reason(),
+            "compile_ms": r.compile.as_millis() as u64,
+        })).collect();
+        println!("{}", json!(results));
+    } else if results.is_empty() {
+        println!("no workers to warm");
+    } else {
+        println!("{:<22} {:<9} {:>9}  REASON", "ADDRESS", "OUTCOME", "COMPILE");
+        for r in &results {
+            let compile = if r.outcome == WarmOutcome::Compiled {
+                format!("{:.2}s", r.compile.as_secs_f64())
+            } else {
+                "-".to_string()
+            };
+            println!("{:<22} {:<9} {:>9}  {}", r.worker_address, r.outcome.as_str(), compile, r.outcome.reason().unwrap_or("-"));
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+async fn validate(client: &Client, wasm_bytes: Vec<u8>, as_json: bool) {
+    let report = match client.validate_module(wasm_bytes).await {
+        Ok(report) => report,
+        Err(e) => return fail(e, as_json),
+    };
+
+    if as_json {
+        println!("{}", json!({
+            "kind": report.kind.as_str(),
+            "runnable": report.is_runnable(),
+            "imports": report.imports,
+            "exports": report.exports,
+            "errors": report.errors,
+            "warnings": report.warnings,
+        }));
+    } else {
+        println!("kind:     {}", report.kind.as_str());
+        println!("runnable: {}", if report.is_runnable() { "yes" } else { "no" });
+        for (label, names) in [("imports:", &report.imports), ("exports:", &report.exports)] {
+            println!("{label}{}", if names.is_empty() { "  -" } else { "" });
+            for name in names {
+                println!("  {name}");
+            }
+        }
+        for error in &report.errors {
+            println!("error:    {error}");
+        }
+        for warning in &report.warnings {
+            println!("warning:  {warning}");
+        }
+    }
+    if !report.is_runnable() {
+        std::process::exit(1);
+    }
+}
+
+/// Whether the orchestrator expects a job submitted now to reach a worker within max_wait,
+/// printing why not if it doesn't.
+async fn within_queue_wait(client: &Client, max_wait: Duration, as_json: bool) -> bool {
+    let stats = match client.queue_stats().await {
+        Ok(stats) => stats,
+        Err(e) => {
+            fail(e, as_json);
+            return false;
+        },
+    };
+    if stats.estimated_wait.is_some_and(|wait| wait <= max_wait) {
+        return true;
+    }
+    let message = format!("not submitting: the estimated queue wait of {} exceeds --max-wait {}s", fmt_estimated_wait(stats.estimated_wait), max_wait.as_secs());
+    if as_json {
+        println!("{}", json!({ "error": message, "queued_jobs": stats.queued_jobs }));
+    } else {
+        eprintln!("{message} ({} jobs queued)", stats.queued_jobs);
+    }
+    false
+}
+
+fn fmt_estimated_wait(wait: Option<Duration>) -> String {
+    match wait {
+        Some(wait) => format!("{:.1}s", wait.as_secs_f64()),
+        None => "unknown, jobs are queued but none were dispatched in the last minute".to_string(),
+    }
+}
+
+async fn publish(client: &Client, url: String, digest: &str, name: &str, tag: &str, defaults: &ModuleDefaults, as_json: bool) {
+    if !url.starts_with("s3://") {
+        eprintln!("only s3:// modules can be published, since workers fetch them by URL");
+        std::process::exit(1);
+    }
+    let digest = ModuleDigest::parse(digest).unwrap_or_else(|e| {
+        eprintln!("invalid --digest {digest}: {e}");
+        std::process::exit(1);
+    });
+    let source = ModuleSource::ObjectStore { url: url.clone(), digest };
+    let previous = match client.publish_module(name, tag, &source, defaults).await {
+        Ok(previous) => previous,
+        Err(e) => return fail(e, as_json),
+    };
+    let previous_url = previous.map(|ModuleSource::ObjectStore { url, .. }| url);
+    if as_json {
+        println!("{}", json!({ "alias": format!("{name}:{tag}"), "url": url, "previous_url": previous_url }));
+    } else {
+        match previous_url {
+            Some(previous_url) => println!("published {name}:{tag} -> {url}, replacing {previous_url}"),
+            None => println!("published {name}:{tag} -> {url}"),
+        }
+    }
+}
+
+async fn inspect(client: &Client, alias: &str, as_json: bool) {
+    let Some((name, tag)) = alias.split_once(':') else {
+        eprintln!("expected a name:tag alias, got {alias}");
+        std::process::exit(1);
+    };
+    let published = match client.resolve_module(name, tag).await {
+        Ok(published) => published,
+        Err(e) => return fail(e, as_json),
+    };
+    let ModuleSource::ObjectStore { url, digest } = &published.source;
+    let defaults = &published.defaults;
+    if as_json {
+        println!("{}", json!({
+            "alias": alias,
+            "url": url,
+            "digest": digest.to_string(),
+            "published_at_ms": epoch_ms(published.published_at),
+            "defaults": {
+                "timeout_secs": defaults.timeout.map(|t| t.as_secs()),
+                "max_retries": defaults.max_retries,
+                "args": defaults.args,
+            },
+        }));
+    } else {
+        println!("url:         {url}");
+        println!("digest:      {digest}");
+        println!("published:   {}", fmt_time_of_day(published.published_at));
+        println!("timeout:     {}", defaults.timeout.map(|t| format!("{}s", t.as_secs())).as_deref().unwrap_or("-"));
+        println!("max retries: {}", defaults.max_retries.map(|n| n.to_string()).as_deref().unwrap_or("-"));
+        println!("args:        {}", if defaults.args.is_empty() { "-".to_string() } else { defaults.args.join(" ") });
+    }
+}
+
+async fn inspect_worker(client: &Client, worker: &str, wasm_hash: Option<&str>, as_json: bool) {
+    let wasm_hash = wasm_hash.map(|hex| blake3::Hash::from_hex(hex).unwrap_or_else(|_| {
+        eprintln!("expected a 64 character hex blake3 hash, got {hex}");
+        std::process::exit(1);
+    }));
+    let modules = match client.worker_modules(worker, wasm_hash.as_ref().map(blake3::Hash::as_bytes)).await {
+        Ok(modules) => modules,
+        Err(e) => return fail(e, as_json),
+    };
+
+    if as_json {
+        let modules: Vec<_> = modules.iter().map(|m| {
+            let metadata = m.metadata.as_ref();
+            json!({
+                "wasm_hash": hex(&m.wasm_hash),
+                "compiled_at_ms": epoch_ms(m.compiled_at),
+                "size_bytes": metadata.map(|md| md.size_bytes),
+                "name": metadata.and_then(|md| md.name.as_deref()),
+                "has_name_section": metadata.map(|md| md.has_name_section),
+                "exports": metadata.map(|md| &md.exports),
+                "import_namespaces": metadata.map(|md| &md.import_namespaces),
+                "parse_error": metadata.and_then(|md| md.parse_error.as_deref()),
+            })
+        }).collect();
+        println!("{}", json!({ "worker_address": worker, "modules": modules }));
+        return;
+    }
+    if modules.is_empty() {
+        println!("no modules compiled on {worker}");
+        return;
+    }
+    for (i, module) in modules.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("hash:     {}", hex(&module.wasm_hash));
+        println!("compiled: {}", fmt_time_of_day(module.compiled_at));
+        let Some(metadata) = &module.metadata else {
+            println!("metadata: - (handed off by a peer without its bytes)");
+            continue;
+        };
+        println!("size:     {} bytes", metadata.size_bytes);
+        let name = match (&metadata.name, metadata.has_name_section) {
+            (Some(name), _) => name.as_str(),
+            (None, true) => "- (name section without a module name)",
+            (None, false) => "- (no name section)",
+        };
+        println!("name:     {name}");
+        for (label, names) in [("imports:", &metadata.import_namespaces), ("exports:", &metadata.exports)] {
+            println!("{label}{}", if names.is_empty() { "  -" } else { "" });
+            for name in names {
+                println!("  {name}");
+            }
+        }
+        if let Some(parse_error) = &metadata.parse_error {
+            println!("partial:  {parse_error}");
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn fail(e: ClientError, as_json: bool) {
+    if as_json {
+        println!("{}", json!({ "error": e.to_string() }));
+    } else {
+        eprintln!("{e}");
+    }
+    std::process::exit(1);
+}
+
+fn epoch_ms(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &str) -> Vec<String> {
+        std::iter::once("cli").chain(args.split_whitespace()).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn subcommands_match_the_cli() {
+        let mut subcommands: Vec<String> = build_cli().get_subcommands().map(|c| c.get_name().to_string()).collect();
+        subcommands.push("help".to_string());
+        subcommands.sort();
+        let mut listed = SUBCOMMANDS.map(str::to_string).to_vec();
+        listed.sort();
+        assert_eq!(listed, subcommands);
+    }
+
+    #[test]
+    fn a_leading_wasm_path_is_submitted() {
+        assert_eq!(with_implicit_submit(argv("job.wasm 1 2")), argv("submit job.wasm 1 2"));
+        assert_eq!(with_implicit_submit(argv("--orchestrator http://o:50051 -q job.wasm")), argv("--orchestrator http://o:50051 -q submit job.wasm"));
+        assert_eq!(with_implicit_submit(argv("--password=x --json job.wasm")), argv("--password=x --json submit job.wasm"));
+    }
+
+    #[test]
+    fn only_the_first_positional_argument_is_checked() {
+        // The job's arguments may name subcommands or .wasm files
+        assert_eq!(with_implicit_submit(argv("job.wasm status")), argv("submit job.wasm status"));
+        assert_eq!(with_implicit_submit(argv("submit job.wat other.wasm")), argv("submit job.wat other.wasm"));
+        assert_eq!(with_implicit_submit(argv("status abc other.wasm")), argv("status abc other.wasm"));
+        // An option's value isn't the first positional argument, even when it ends in .wasm
+        assert_eq!(with_implicit_submit(argv("--profile x.wasm status abc")), argv("--profile x.wasm status abc"));
+    }
+
+    #[test]
+    fn bash_completions_offer_every_subcommand() {
+        let mut script = Vec::new();
+        clap_complete::generate(Shell::Bash, &mut build_cli(), "cli", &mut script);
+        let script = String::from_utf8(script).unwrap();
+        // The words completed after `cli` itself
+        let top_level = script.lines()
+            .skip_while(|line| line.trim() != "cli)")
+            .find_map(|line| line.trim().strip_prefix("opts=\""))
+            .unwrap();
+        let words: Vec<&str> = top_level.trim_end_matches('"').split_whitespace().collect();
+        for subcommand in SUBCOMMANDS {
+            assert!(words.contains(&subcommand), "{subcommand} isn't completed");
+        }
+    }
+
+    #[test]
+    fn poll_intervals_that_would_busy_loop_are_refused() {
+        assert_eq!(parse_poll_interval("0.5"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_poll_interval("0.1"), Ok(MIN_POLL_INTERVAL));
+        for secs in ["0", "0.01", "-1", "inf", "NaN", "1e300", "soon"] {
+            assert!(parse_poll_interval(secs).is_err(), "{secs} was accepted");
+        }
+    }
+}
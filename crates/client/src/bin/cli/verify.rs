@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use client::{Client, JobError, JobId, JobOutput};
+use serde_json::json;
+use tokio::task::JoinSet;
+
+use crate::{Module, SubmitArgs, build_job};
+
+/// One run of the job and the worker that produced it.
+struct Run {
+    job_id: JobId,
+    worker_address: Option<String>,
+    outcome: Outcome,
+}
+
+/// What a run produced, compared across runs.
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+    /// The job's wasm exited with this code. Workers don't return the stdout of a job that
+    /// exited with a non-zero code, so theirs is None.
+    Exited { code: i32, stdout: Option<String>, stderr: String },
+    /// The job failed without its wasm exiting, e.g. it trapped or timed out.
+    Failed(String),
+}
+
+impl Outcome {
+    fn of(result: Result<JobOutput, JobError>) -> Self {
+        match result {
+            Ok(output) => Outcome::Exited {
+                code: 0,
+                stdout: Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            },
+            Err(JobError::Exited { code, stderr }) => Outcome::Exited {
+                code,
+                stdout: None,
+                stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            },
+            Err(e) => Outcome::Failed(e.to_string()),
+        }
+    }
+}
+
+/// Submits the job `runs` times at once and checks every run exited with the same code and
+/// wrote the same stdout and stderr.
+///
+/// The runs are submitted concurrently so each holds a credit on its worker while it runs,
+/// which steers the orchestrator towards assigning them to distinct workers when there are
+/// enough. Exits non-zero if any run diverges from the first.
+pub async fn run(client: &Client, module: Module, runs: usize, args: &SubmitArgs, as_json: bool) {
+    let mut in_flight = JoinSet::new();
+    for index in 0..runs {
+        let running_job = client.submit_job(build_job(module.clone(), args, &args.wasm_args));
+        in_flight.spawn(async move {
+            let job_id = running_job.job_id();
+            let result = running_job.clone().wait().await;
+            let run = Run { job_id, worker_address: running_job.worker_address(), outcome: Outcome::of(result) };
+            (index, run)
+        });
+    }
+    let mut results = in_flight.join_all().await;
+    results.sort_by_key(|(index, _)| *index);
+    let runs: Vec<Run> = results.into_iter().map(|(_, run)| run).collect();
+
+    let distinct_workers = runs.iter()
+        .filter_map(|run| run.worker_address.as_deref())
+        .collect::<HashSet<_>>()
+        .len();
+    let divergent = runs.iter().position(|run| run.outcome != runs[0].outcome);
+
+    if as_json {
+        let runs_json: Vec<_> = runs.iter().map(|run| {
+            let mut line = json!({ "job_id": run.job_id, "worker_address": run.worker_address });
+            match &run.outcome {
+                Outcome::Exited { code, stdout, stderr } => {
+                    line["exit_code"] = json!(code);
+                    line["stdout"] = json!(stdout);
+                    line["stderr"] = json!(stderr);
+                },
+                Outcome::Failed(e) => line["error"] = json!(e),
+            }
+            line
+        }).collect();
+        println!("{}", json!({
+            "agree": divergent.is_none(),
+            "distinct_workers": distinct_workers,
+            "runs": runs_json,
+        }));
+    } else {
+        for (index, run) in runs.iter().enumerate() {
+            let worker = run.worker_address.as_deref().unwrap_or("-");
+            let verdict = if run.outcome == runs[0].outcome { "same" } else { "DIFFERENT" };
+            let outcome = match &run.outcome {
+                Outcome::Exited { code, .. } => format!("exited with {code}"),
+                Outcome::Failed(e) => format!("failed: {e}"),
+            };
+            println!("[{index}] {worker:<21}  {verdict:<9}  {outcome}");
+        }
+        println!();
+        if distinct_workers < runs.len() {
+            println!("note: only {distinct_workers} distinct worker(s) ran the {} runs", runs.len());
+        }
+        match divergent {
+            None => println!("all {} runs agree", runs.len()),
+            Some(index) => {
+                println!("run {index} diverges from run 0:");
+                for line in first_divergence(&runs[0].outcome, &runs[index].outcome) {
+                    println!("  {line}");
+                }
+            },
+        }
+    }
+
+    if divergent.is_some() {
+        std::process::exit(1);
+    }
+}
+
+/// Describes how two outcomes differ: their exit codes, then the first line where their
+/// stdout and their stderr differ.
+fn first_divergence(expected: &Outcome, actual: &Outcome) -> Vec<String> {
+    match (expected, actual) {
+        (
+            Outcome::Exited { code: expected_code, stdout: expected_stdout, stderr: expected_stderr },
+            Outcome::Exited { code: actual_code, stdout: actual_stdout, stderr: actual_stderr },
+        ) => {
+            let mut lines = Vec::new();
+            if expected_code != actual_code {
+                lines.push(format!("exit code: run 0 exited with {expected_code}, this run with {actual_code}"));
+            }
+            if let (Some(expected), Some(actual)) = (expected_stdout, actual_stdout) {
+                lines.extend(first_different_line("stdout", expected, actual));
+            }
+            lines.extend(first_different_line("stderr", expected_stderr, actual_stderr));
+            lines
+        },
+        (Outcome::Exited { code, .. }, Outcome::Failed(e)) => vec![format!("run 0 exited with {code}, but this run failed: {e}")],
+        (Outcome::Failed(e), Outcome::Exited { code, .. }) => vec![format!("run 0 failed ({e}), but this run exited with {code}")],
+        (Outcome::Failed(expected), Outcome::Failed(actual)) => first_different_line("error", expected, actual).into_iter().collect(),
+    }
+}
+
+/// The first line where the two texts differ, if they do.
+fn first_different_line(label: &str, expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return Some(format!("{label} differs only in line endings")),
+            (e, a) if e != a => {
+                return Some(format!(
+                    "{label} line {line}:\n  - {}\n  + {}",
+                    e.unwrap_or("<end of output>"),
+                    a.unwrap_or("<end of output>"),
+                ));
+            },
+            _ => {},
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exited(code: i32, stdout: Option<&str>, stderr: &str) -> Outcome {
+        Outcome::Exited { code, stdout: stdout.map(str::to_string), stderr: stderr.to_string() }
+    }
+
+    #[test]
+    fn identical_runs_agree() {
+        let outcome = exited(0, Some("42\n"), "warming up\n");
+        assert_eq!(outcome, outcome.clone());
+        assert!(first_divergence(&outcome, &outcome.clone()).is_empty());
+    }
+
+    #[test]
+    fn runs_differing_only_in_exit_code_diverge() {
+        let expected = exited(2, None, "bad input\n");
+        let actual = exited(3, None, "bad input\n");
+        assert_ne!(expected, actual);
+        assert_eq!(first_divergence(&expected, &actual), ["exit code: run 0 exited with 2, this run with 3"]);
+    }
+
+    #[test]
+    fn runs_differing_only_in_stderr_diverge() {
+        let expected = exited(0, Some("42\n"), "seed 1\n");
+        let actual = exited(0, Some("42\n"), "seed 2\n");
+        assert_ne!(expected, actual);
+        assert_eq!(first_divergence(&expected, &actual), ["stderr line 1:\n  - seed 1\n  + seed 2"]);
+    }
+
+    #[test]
+    fn the_first_different_stdout_line_is_shown() {
+        let expected = exited(0, Some("a\nb\nc\n"), "");
+        let actual = exited(0, Some("a\nB\nc\n"), "");
+        assert_eq!(first_divergence(&expected, &actual), ["stdout line 2:\n  - b\n  + B"]);
+
+        let shorter = exited(0, Some("a\n"), "");
+        assert_eq!(first_divergence(&expected, &shorter), ["stdout line 2:\n  - b\n  + <end of output>"]);
+    }
+
+    #[test]
+    fn failed_runs_diverge_from_runs_that_exited() {
+        let expected = exited(0, Some(""), "");
+        let actual = Outcome::Failed("job timed out".to_string());
+        assert_eq!(first_divergence(&expected, &actual), ["run 0 exited with 0, but this run failed: job timed out"]);
+    }
+
+    #[test]
+    fn exits_are_told_apart_from_other_failures() {
+        let outcome = Outcome::of(Err(JobError::Exited { code: 4, stderr: b"oops\n".to_vec() }));
+        assert_eq!(outcome, exited(4, None, "oops\n"));
+        let trapped = Outcome::of(Err(JobError::WasmError("wasm trapped: unreachable".to_string())));
+        assert!(matches!(trapped, Outcome::Failed(message) if message.contains("unreachable")));
+    }
+}
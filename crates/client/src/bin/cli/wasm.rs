@@ -0,0 +1,76 @@
+use client::{ModuleKind, inspect_module};
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+/// Turns the loaded bytes into a wasm binary ready to submit, assembling them first if they
+/// are in the text format, then checking the module is something a worker can run (unless
+/// validation is disabled). Text must describe a component, since workers can't run the core
+/// module a `(module ...)` assembles to.
+pub fn prepare(source: &str, bytes: Vec<u8>, validate: bool) -> Result<Vec<u8>, String> {
+    let wasm_bytes = if bytes.starts_with(WASM_MAGIC) {
+        bytes
+    } else if source.ends_with(".wat") || std::str::from_utf8(&bytes).is_ok() {
+        let wasm_bytes = wat::parse_bytes(&bytes)
+            .map_err(|e| format!("{source} is neither a wasm binary nor valid wat: {e}"))?
+            .into_owned();
+        if inspect_module(&wasm_bytes).kind == ModuleKind::CoreModule {
+            return Err(format!("{source} is a core module in the text format, but workers only run components. Write it as a (component ...) that exports wasi:cli/run@0.2.0"));
+        }
+        wasm_bytes
+    } else {
+        return Err(format!("{source} is not a wasm module (missing the \\0asm header)"));
+    };
+
+    if validate {
+        check_runnable(&wasm_bytes).map_err(|e| format!("{source}: {e}"))?;
+    }
+    Ok(wasm_bytes)
+}
+
+/// Checks that the module is a valid WASI command component that only imports WASI
+/// interfaces, since that's all workers provide.
+fn check_runnable(wasm_bytes: &[u8]) -> Result<(), String> {
+    match inspect_module(wasm_bytes).errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPONENT_WAT: &str = r#"
+(component
+  (core module $m
+    (func (export "run") (result i32) i32.const 0))
+  (core instance $i (instantiate $m))
+  (func $run (result (result)) (canon lift (core func $i "run")))
+  (instance $run (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run)))
+"#;
+
+    #[test]
+    fn component_text_is_assembled() {
+        let wasm_bytes = prepare("noop.wat", COMPONENT_WAT.as_bytes().to_vec(), true).unwrap();
+        assert_eq!(inspect_module(&wasm_bytes).kind, ModuleKind::Component);
+        assert_eq!(prepare("noop.wasm", wasm_bytes.clone(), true).unwrap(), wasm_bytes);
+    }
+
+    #[test]
+    fn core_module_text_is_rejected_clearly() {
+        let wat = r#"(module (func (export "_start")))"#;
+        for validate in [true, false] {
+            let error = prepare("start.wat", wat.as_bytes().to_vec(), validate).unwrap_err();
+            assert!(error.contains("core module in the text format"), "{error}");
+        }
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let error = prepare("job.wat", b"(component".to_vec(), false).unwrap_err();
+        assert!(error.contains("neither a wasm binary nor valid wat"), "{error}");
+        let error = prepare("job.bin", vec![0xff, 0xfe, 0x00], false).unwrap_err();
+        assert!(error.contains("missing the \\0asm header"), "{error}");
+    }
+}
@@ -1,6 +1,5 @@
 use std::{ops::Range, time::Duration};
 
-use rand;
 use tokio::task::JoinSet;
 
 use client::{Client, Job};
@@ -74,17 +73,15 @@ fn create_job(
     timeout_range: &Range<u64>) -> Job {
     let bytes = if rand::random::<f64>() < cached_probability { wasm.to_vec() } else {
         let salt: [u8; 64] = rand::random();
-        add_custom_section(&wasm, &salt)
+        add_custom_section(wasm, &salt)
     };
 
     let arg = if rand::random::<f64>() < job_failure_probability { "invalid".to_string() } else {
         rand::random_range(sleep_range.clone()).to_string()
     };
-    let job = Job::from_bytes(bytes)
+    Job::from_bytes(bytes)
         .arg(arg)
-        .timeout(Duration::from_secs(rand::random_range(timeout_range.clone())));
-
-    return job;
+        .timeout(Duration::from_secs(rand::random_range(timeout_range.clone())))
 }
 
 // Helper for appending a section to the wasm file that doesn't change its
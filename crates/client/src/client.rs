@@ -1,6 +1,14 @@
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use shared::{CancelJobRequest, JobRequest};
+use shared::{CancelJobRequest, DeltaUpload, FetchOutputRequest, FetchOutputResponse, JobRequest, JobResponse, JobStatusRequest, ListJobsRequest, ListWorkerModulesRequest, ListWorkersRequest, OutputRef, PipelineRequest, PlanJobsRequest, PublishModuleRequest, QueueStatsRequest, QuotasRequest, RelayedCancelRequest, RelayedFetchOutputRequest, RelayedJobRequest, ResolveModuleRequest, UsageRequest, ValidateModuleRequest, WarmWorkersRequest, WatchQueuePositionRequest};
+use shared::{ErrorCode, HashAlgorithm};
+use shared::compat::PROTOCOL_VERSION;
+use shared::digest::ModuleDigest;
+use shared::inspect::ModuleReport;
+use shared::limits::JobLimits;
+use shared::output::OUTPUT_CHUNK_BYTES;
+use shared::signing::{JobSigner, signed_bytes};
 use shared::executor_client::ExecutorClient;
 use shared::{WorkerRequest, client_api_client::ClientApiClient};
 use tokio::sync::watch;
@@ -8,25 +16,58 @@ use tokio_util::sync::CancellationToken;
 use tonic::service::Interceptor;
 use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Channel;
-use tonic::{Request, Status};
-use uuid::Uuid;
+use tonic::{Code, Request, Status};
+use shared::JobId;
 
-use crate::job::{Job, JobError, JobOutput, JobState, RunningJob};
+use crate::known_modules::KnownModules;
+use crate::pipeline::{Pipeline, PipelineOutput};
+use crate::job::{CacheMode, DEFAULT_MAX_RETRIES, Job, JobError, JobOutput, JobState, JobTiming, ModuleDefaults, ModuleSource, PublishedModule, RunningJob};
+use crate::status::{CachedModule, JobListing, JobSubmission, JobPage, JobQuery, JobStatus, Plan, PlanSpec, QueueProgress, QueueStats, Quotas, Usage, WarmResult, WorkerStatus};
+use crate::tls::{self, TlsConfig};
+use crate::retry::{is_transient_orchestrator_error, is_worker_failure, wait_before_retry};
 
 // Note for error handling in this crate. Since this is meant to be a library, avoid panics
 // and exiting the process. Instead, return internal error status codes with descriptive messages,
 // indicating if a program invariant was violated.
 
+/// How long a job waits for a worker before the client starts following its queue position.
+const QUEUE_WATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Largest output a job's worker may have the client fetch, unless set with
+/// Client::with_max_output_bytes.
+const DEFAULT_MAX_OUTPUT_BYTES: u64 = 1024 * 1024 * 1024;
+
 /// The main entry point for submitting jobs to the distributed compute platform.
 /// Connects to an Orchestrator which assigns workers to run your wasm jobs.
 #[derive(Clone)]
 pub struct Client {
     orchestrator_client: ClientApiClient<InterceptedService<Channel, OrchestratorAuthInterceptor>>,
+    /// Used for an https orchestrator, and for workers that registered a TLS proxy.
+    tls: Arc<rustls::ClientConfig>,
+    /// Signs every job, for workers that only run signed jobs.
+    signer: Option<Arc<JobSigner>>,
+    /// Namespace to act in when the password isn't a namespace's token. None is "default".
+    namespace: Option<String>,
+    /// The endpoint connected to, which identifies the cluster in known_modules.
+    orchestrator_endpoint: String,
+    /// Modules recently cached on the cluster's workers, deciding whether to probe by hash.
+    known_modules: Option<Arc<KnownModules>>,
+    /// The digest workers are probed by, where they can find modules by it.
+    hash_algorithm: HashAlgorithm,
+    /// Largest output fetched from a job's worker, see fetch_output.
+    max_output_bytes: u64,
 }
 
 impl Client {
     /// Connect to an Orchestrator at the given endpoint, returning a Client on success.
     pub async fn connect(orchestrator_endpoint: &str, password: Option<String>, verbose: bool) -> Result<Client, ClientError> {
+        Self::connect_with_tls(orchestrator_endpoint, password, verbose, TlsConfig::default()).await
+    }
+
+    /// Like connect, using the given TLS settings if the endpoint is an https:// URL, and for
+    /// workers clients reach through a TLS proxy. Certificate and key files are loaded up
+    /// front, so a misconfiguration fails here.
+    pub async fn connect_with_tls(orchestrator_endpoint: &str, password: Option<String>, verbose: bool, tls_config: TlsConfig) -> Result<Client, ClientError> {
         let filter = if verbose { "client=debug" } else { "client=info" };
         let _ = tracing_subscriber::fmt()
             .with_env_filter(
@@ -35,18 +76,68 @@ impl Client {
             )
             .try_init();
             
-        let channel = Channel::from_shared(orchestrator_endpoint.to_string())
-            .map_err(|e| ClientError::InvalidEndpoint(e.to_string()))?
-            .connect().await?;
+        let endpoint = Channel::from_shared(orchestrator_endpoint.to_string())
+            .map_err(|e| ClientError::InvalidEndpoint(e.to_string()))?;
+        let tls = tls_config.build()?;
+        let https = endpoint.uri().scheme_str() == Some("https");
+        let channel = tls::connect(endpoint, https.then_some(&tls)).await?;
         let orchestrator_client = ClientApiClient::with_interceptor(channel, OrchestratorAuthInterceptor { password });
-        Ok(Client { orchestrator_client })
+        Ok(Client {
+            orchestrator_client,
+            tls,
+            signer: None,
+            namespace: None,
+            orchestrator_endpoint: orchestrator_endpoint.to_string(),
+            known_modules: None,
+            hash_algorithm: HashAlgorithm::Blake3,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        })
+    }
+
+    /// Sign every job submitted from now on with the given key, for workers started with
+    /// trusted keys.
+    pub fn with_signer(mut self, signer: JobSigner) -> Self {
+        self.signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Submit jobs to, and look them up in, the given namespace. Only needed when the
+    /// orchestrator doesn't derive the namespace from the password.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Remember which modules the cluster's workers have cached in the given record, which
+    /// delta uploads are made against, see Job::delta_key. Only affects jobs with the default
+    /// cache mode.
+    pub fn with_known_modules(mut self, known_modules: KnownModules) -> Self {
+        self.known_modules = Some(Arc::new(known_modules));
+        self
+    }
+
+    /// Probe workers' caches with a digest made with the given algorithm instead of blake3,
+    /// where the worker advertises it can find modules by it. Others are probed by blake3, as
+    /// are coalesced jobs. Uploads are always identified by blake3.
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Fail jobs whose worker stored an output larger than this for the client to fetch,
+    /// rather than fetch it, with JobError::OutputUnavailable. 1 GiB by default.
+    pub fn with_max_output_bytes(mut self, max_output_bytes: u64) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
     }
 
     /// Submit a job for execution and return a RunningJob handle immediately.
     /// The job is queued until a worker becomes available, then executed automatically.
     pub fn submit_job(&self, job: Job) -> RunningJob {
-        let job_id = Uuid::new_v4();
+        let job_id = JobId::random();
         let (state_tx, state_rx) = watch::channel(JobState::Queued);
+        let (worker_tx, worker_rx) = watch::channel(None);
+        let (queue_tx, queue_rx) = watch::channel(None);
         let cancel_token = CancellationToken::new();
 
         let state_tx_timeout = state_tx.clone();
@@ -55,112 +146,424 @@ impl Client {
         let mut client = self.clone();
 
         tokio::spawn(async move {
+            // The timeout counts from when the job may start, so a scheduled job's wait for its
+            // start time doesn't use it up
+            let delay = job.not_before
+                .and_then(|t| t.duration_since(SystemTime::now()).ok())
+                .unwrap_or_default();
+            let deadline = job.timeout.and_then(|t| Instant::now().checked_add(delay + t));
             let mut submit_task = tokio::spawn(async move {
-                let job_id_bytes = job_id.as_bytes().to_vec();
-                let worker_request = Request::new(WorkerRequest { job_id: job_id_bytes.clone() });
+                let job_id_bytes = job_id.to_bytes();
+                let wasm_hash = match (&job.module_source, job.known_hash) {
+                    (Some(ModuleSource::ObjectStore { digest, .. }), _) => digest.bytes.to_vec(),
+                    (None, Some(hash)) => hash.to_vec(),
+                    (None, None) => blake3::hash(&job.wasm_bytes).as_bytes().to_vec(),
+                };
+                let signature = client.signer.as_ref()
+                    .map(|signer| (signer.key_id().to_string(), signer.sign(&signed_bytes(&job_id_bytes, &wasm_hash, &job.stdin, &job.args))))
+                    .unwrap_or_default();
+                // Jobs that may upload their wasm tell the orchestrator what they run, so it can
+                // coordinate uploads of the same module
+                let uploaded_hash = (job.module_source.is_none() && job.cache_mode == CacheMode::Default)
+                    .then(|| wasm_hash.clone());
+                let coalesce_key = job.coalesces().then(|| shared::coalesce_key(&wasm_hash, &job.args));
+                // Those may send it as a patch against the version last uploaded under its delta key
+                let delta_key = job.delta_key.as_deref()
+                    .zip(client.known_modules.clone())
+                    .filter(|_| uploaded_hash.is_some());
+                let delta = match &delta_key {
+                    Some((key, known)) => make_delta(known, &client.orchestrator_endpoint, key, &job.wasm_bytes).await,
+                    None => None,
+                };
+                let max_retries = job.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+                let not_before_ms = job.not_before
+                    .map(|t| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64);
 
-                tracing::debug!(job_id = %job_id, "job submitted, waiting for worker");
+                // Each attempt requests a fresh worker assignment for the same job_id, so that
+                // a job which fails because its worker failed can be retried on another worker.
+                // All retries share the job's max_retries budget and back off between attempts.
+                let submitted_at = Instant::now();
+                let mut attempt = 0;
+                loop {
+                    // Phase 1: wait in the orchestrator's queue for a worker assignment
+                    let response = loop {
+                        let worker_request = Request::new(WorkerRequest {
+                            job_id: job_id_bytes.clone(),
+                            not_before_ms,
+                            callback_url: job.callback_url.clone(),
+                            protocol_version: PROTOCOL_VERSION,
+                            namespace: client.namespace.clone(),
+                            wasm_hash: uploaded_hash.clone(),
+                            // What's left of the timeout, so the orchestrator drops the job once it passes
+                            timeout_ms: deadline.map(|d| d.saturating_duration_since(Instant::now()).as_millis() as u64),
+                            coalesce_key: coalesce_key.clone(),
+                            worker_address: job.worker_address.clone(),
+                            replay_of: job.replay_of.map(JobId::to_bytes),
+                        });
 
-                let response = tokio::select! {
-                    result = client.orchestrator_client.request_worker(worker_request) => {
+                        tracing::debug!(job_id = %job_id, attempt, "job submitted, waiting for worker");
+
+                        let (watch_client, namespace) = (client.orchestrator_client.clone(), client.namespace.clone());
+                        let watch_queue = async {
+                            watch_queue_position(watch_client, job_id, namespace, &queue_tx).await;
+                            std::future::pending().await
+                        };
+                        let result = tokio::select! {
+                            result = client.orchestrator_client.request_worker(worker_request) => result,
+                            _ = watch_queue => unreachable!("watching the queue never finishes"),
+                            _ = cancel_token.cancelled() => {
+                                tracing::debug!(job_id = %job_id, "cancel fired, sending cancel_queued_job");
+                                client.cancel_queued_job(job_id).await;
+                                state_tx.send(stopped_state(deadline)).ok();
+                                return;
+                            }
+                        };
+                        queue_tx.send_replace(None);
                         match result {
-                            Ok(r) => r.into_inner(),
+                            Ok(r) => break r.into_inner(),
+                            Err(e) if is_transient_orchestrator_error(&e) && attempt < max_retries => {
+                                attempt += 1;
+                                tracing::debug!(job_id = %job_id, attempt, error = %e, "request_worker failed, retrying");
+                                if !wait_before_retry(attempt, &cancel_token).await {
+                                    state_tx.send(stopped_state(deadline)).ok();
+                                    return;
+                                }
+                            },
                             Err(e) => {
-                                state_tx.send(JobState::Completed(Err(JobError::Internal(e.to_string())))).ok();
+                                state_tx.send(JobState::Completed(Err(request_worker_error(e)))).ok();
                                 return;
                             }
                         }
-                    },
-                    _ = cancel_token.cancelled() => {
-                        tracing::debug!(job_id = %job_id, "cancel fired, sending cancel_queued_job");
-                        client.cancel_queued_job(job_id).await;
-                        state_tx.send(JobState::Cancelled).ok();
-                        return;
-                    }
-                };
+                    };
 
-                let worker_address = response.worker_address;
-                let worker_endpoint = "http://".to_string() + &worker_address;
-                let jwt_token = response.jwt_token;
-                tracing::debug!(job_id = %job_id, worker = %worker_address, "worker assigned, connecting");
+                    // Phase 2: run the job on the assigned worker
+                    let assigned_at = Instant::now();
 
-                if state_tx.send(JobState::Executing).is_err() {
-                    return; // no listening RunningJob's, so no point running the task
-                };
+                    let worker_address = response.worker_address;
+                    let jwt_token = response.jwt_token;
+                    tracing::debug!(job_id = %job_id, worker = %worker_address, relayed = response.relayed, "worker assigned, connecting");
+                    worker_tx.send_replace(Some(worker_address.clone()));
+
+                    if state_tx.send(JobState::Executing).is_err() {
+                        return; // no listening RunningJob's, so no point running the task
+                    };
 
-                let channel = match Channel::from_shared(worker_endpoint.to_string()) {
-                    Ok(endpoint) => {
-                        match endpoint.connect().await {
-                            Ok(c) => c,
+                    let mut transport = if response.relayed {
+                        // The worker accepts no connections, so its calls go through the orchestrator
+                        WorkerTransport::Relayed { orchestrator_client: client.orchestrator_client.clone(), worker_address: worker_address.clone(), jwt_token }
+                    } else {
+                        // Workers serve plaintext, unless they registered a TLS proxy in front of them
+                        let worker_endpoint = match &response.worker_tls_address {
+                            Some(tls_address) => format!("https://{tls_address}"),
+                            None => format!("http://{worker_address}"),
+                        };
+                        let tls = response.worker_tls_address.is_some().then_some(&client.tls);
+                        let channel = match Channel::from_shared(worker_endpoint) {
+                            Ok(endpoint) => match tls::connect(endpoint, tls).await {
+                                Ok(c) => c,
+                                Err(e) if attempt < max_retries => {
+                                    attempt += 1;
+                                    tracing::debug!(job_id = %job_id, attempt, worker = %worker_address, error = %e, "failed to connect to worker, retrying");
+                                    state_tx.send(JobState::Queued).ok();
+                                    if !wait_before_retry(attempt, &cancel_token).await {
+                                        state_tx.send(stopped_state(deadline)).ok();
+                                        return;
+                                    }
+                                    continue;
+                                },
+                                Err(e) => {
+                                    state_tx.send(JobState::Completed(Err(JobError::Internal(e.to_string())))).ok();
+                                    return;
+                                }
+                            },
                             Err(e) => {
-                                state_tx.send(JobState::Completed(Err(JobError::Internal(e.to_string())))).ok();
+                                state_tx.send(JobState::Completed(Err(JobError::Internal(
+                                    format!("received a malformed worker endpoint from the orchestrator, this should never occur: {}", e)
+                                )))).ok();
                                 return;
                             }
+                        };
+                        WorkerTransport::Direct(ExecutorClient::with_interceptor(channel, WorkerJwtInterceptor { jwt_token }))
+                    };
+
+                    // The worker is probed by the client's preferred digest if it can find modules by it
+                    let probe = (client.hash_algorithm != HashAlgorithm::Blake3
+                        && job.module_source.is_none()
+                        && job.known_hash.is_none()
+                        && response.coalesced_with.is_none()
+                        && response.hash_algorithms.contains(&(client.hash_algorithm as i32)))
+                        .then(|| client.probe(&job_id_bytes, &job, client.hash_algorithm));
+
+                    let execution_result = tokio::select! {
+                        r = run_on_worker(&mut transport, job_id, &job, &wasm_hash, &signature, probe.as_ref(), deadline, client.known_modules(), delta.as_ref()) => r,
+                        _ = cancel_token.cancelled() => {
+                            tracing::debug!(job_id = %job_id, "cancel fired, sending cancel_running_job");
+                            client.cancel_running_job(job_id, transport).await;
+                            state_tx.send(stopped_state(deadline)).ok();
+                            return;
+                        }
+                    };
+                    match execution_result {
+                        Ok(job_response) => {
+                            let job_response = job_response.into_inner();
+                            let compile = Duration::from_millis(job_response.compile_ms);
+                            let execute = Duration::from_millis(job_response.execute_ms);
+                            let timing = JobTiming {
+                                queued: assigned_at.duration_since(submitted_at),
+                                transfer: assigned_at.elapsed().saturating_sub(compile + execute),
+                                compile,
+                                execute,
+                                total: submitted_at.elapsed(),
+                                cache_hit: job_response.cache_hit,
+                                instantiation_saved: Duration::from_millis(job_response.instantiation_saved_ms),
+                            };
+                            // An output the worker stored rather than returned is fetched from it now
+                            let output_offloaded = job_response.output_ref.is_some();
+                            let stdout = match &job_response.output_ref {
+                                Some(output_ref) => match fetch_output(&mut transport, job_id, output_ref, client.max_output_bytes).await {
+                                    Ok(stdout) => stdout,
+                                    Err(e) => {
+                                        state_tx.send(JobState::Completed(Err(e))).ok();
+                                        return;
+                                    },
+                                },
+                                None => job_response.stdout,
+                            };
+                            let stderr = job_response.stderr;
+                            let coalesced_with = job_response.coalesced_with.and_then(|id| JobId::from_bytes(&id).ok());
+                            let job_output = JobOutput { stdout, stderr, timing, estimated_cost: job_response.estimated_cost, coalesced_with, upload_bytes_saved: job_response.upload_bytes_saved, output_offloaded };
+                            if let Some((key, known)) = &delta_key
+                                && known.last_upload(key).is_none_or(|kept| kept != job.wasm_bytes)
+                            {
+                                known.record_upload(key, &job.wasm_bytes);
+                            }
+                            state_tx.send(JobState::Completed(Ok(job_output))).ok();
+                        },
+                        Err(e) if is_worker_failure(&e) && attempt < max_retries => {
+                            attempt += 1;
+                            tracing::debug!(job_id = %job_id, attempt, worker = %worker_address, error = %e, "worker failed during execution, retrying");
+                            state_tx.send(JobState::Queued).ok();
+                            if !wait_before_retry(attempt, &cancel_token).await {
+                                state_tx.send(stopped_state(deadline)).ok();
+                                return;
+                            }
+                            continue;
+                        },
+                        Err(e) => {
+                            state_tx.send(JobState::Completed(Err(JobError::from(e)))).ok();
                         }
-                    },
-                    Err(e) => {
-                        state_tx.send(JobState::Completed(Err(JobError::Internal(
-                            format!("received a malformed worker endpoint from the orchestrator, this should never occur: {}", e)
-                        )))).ok();
-                        return;
-                    }
-                };
-                let mut executor_client = ExecutorClient::with_interceptor(channel, WorkerJwtInterceptor { jwt_token });
-
-                let job_request = Request::new(JobRequest {
-                    job_id: job_id_bytes,
-                    wasm_bytes: job.wasm_bytes,
-                    args: job.args
-                });
-
-                tracing::debug!(job_id = %job_id, "execute_job sent to worker");
-
-                let execution_result = tokio::select! {
-                    r = executor_client.execute_job(job_request) => r,
-                    _ = cancel_token.cancelled() => {
-                        tracing::debug!(job_id = %job_id, "cancel fired, sending cancel_running_job");
-                        client.cancel_running_job(job_id, executor_client).await;
-                        state_tx.send(JobState::Cancelled).ok();
-                        return;
-                    }
-                };
-                match execution_result {
-                    Ok(job_response) => {
-                        let job_response = job_response.into_inner();
-                        let stdout = job_response.stdout;
-                        let stderr = job_response.stderr;
-                        let job_output = JobOutput { stdout, stderr };
-                        state_tx.send(JobState::Completed(Ok(job_output))).ok();
-                    },
-                    Err(e) => {
-                        state_tx.send(JobState::Completed(Err(JobError::from(e)))).ok();
                     }
+                    return;
                 }
             });
 
-            let timeout = job.timeout.unwrap_or(Duration::MAX);
             tokio::select! {
                 _ = &mut submit_task => {},
-                _ = tokio::time::sleep(timeout) => {
+                _ = sleep_until_deadline(deadline) => {
                     tracing::debug!(job_id = %job_id, "timeout fired, cancelling job");
                     cancel_token_timeout.cancel();
                     submit_task.await.ok(); // wait for submit_task cleanup
-                    state_tx_timeout.send(JobState::Cancelled).ok();
+                    state_tx_timeout.send(stopped_state(deadline)).ok();
                 }
             };
         });
 
         RunningJob {
+            job_id,
             state_rx,
+            worker_rx,
+            queue_rx,
             cancel_token: cancel_token_handle,
         }
     }
     
+    /// Look up the current state of a job, including jobs submitted by other clients.
+    pub async fn job_status(&self, job_id: JobId) -> Result<JobStatus, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .get_job_status(JobStatusRequest { job_id: job_id.to_bytes(), namespace: self.namespace.clone() }).await?;
+        Ok(response.into_inner().into())
+    }
+
+    /// Look up what a job ran, to replay it, see JobSubmission::replay. Only known once the job
+    /// started executing, and not for jobs the orchestrator recovered after a restart.
+    pub async fn job_submission(&self, job_id: JobId) -> Result<JobSubmission, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .get_job_submission(JobStatusRequest { job_id: job_id.to_bytes(), namespace: self.namespace.clone() }).await?
+            .into_inner();
+        JobSubmission::from_proto(job_id, response)
+            .ok_or_else(|| ClientError::RequestFailed(format!("the orchestrator sent an unrecognized module for job {job_id}")))
+    }
+
+    /// List a page of the namespace's jobs matching the query, newest first. Pass None for the
+    /// first page, then each page's next_page_token for the one after it.
+    pub async fn list_jobs(&self, query: &JobQuery, page_token: Option<String>) -> Result<JobPage, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .list_jobs(ListJobsRequest {
+                namespace: self.namespace.clone(),
+                state: query.state.clone(),
+                worker_address: query.worker_address.clone(),
+                since_ms: query.since.map(|t| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64),
+                limit: query.limit.unwrap_or(0),
+                page_token,
+            }).await?
+            .into_inner();
+        Ok(JobPage {
+            jobs: response.items.into_iter().filter_map(JobListing::from_summary).collect(),
+            next_page_token: response.next_page_token,
+        })
+    }
+
+    /// Cancel a job that is still queued or scheduled in the orchestrator.
+    /// Returns JobNotFound if the job is unknown or has already been dispatched to a worker.
+    pub async fn cancel_job(&self, job_id: JobId) -> Result<(), ClientError> {
+        self.orchestrator_client.clone()
+            .cancel_job(CancelJobRequest { job_id: job_id.to_bytes(), namespace: self.namespace.clone() }).await?;
+        Ok(())
+    }
+
+    /// List every worker the orchestrator has seen, including disconnected ones. With a
+    /// namespace set by with_namespace, only workers that may run its jobs are listed.
+    pub async fn list_workers(&self) -> Result<Vec<WorkerStatus>, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .list_workers(ListWorkersRequest { namespace: self.namespace.clone() }).await?;
+        Ok(response.into_inner().workers.into_iter().map(WorkerStatus::from).collect())
+    }
+
+    /// Check how backed up the orchestrator's queue is, e.g. before submitting a large batch.
+    pub async fn queue_stats(&self) -> Result<QueueStats, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .get_queue_stats(QueueStatsRequest {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    /// Ask the orchestrator how batches of jobs would be scheduled if they were submitted now,
+    /// e.g. before submitting a large batch. The specs are planned in order, as if submitted one
+    /// after another. Nothing is submitted and no credits are used. Returns a plan per spec.
+    pub async fn plan(&self, specs: &[PlanSpec]) -> Result<Vec<Plan>, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .plan_jobs(PlanJobsRequest { specs: specs.iter().map(Into::into).collect(), namespace: self.namespace.clone() }).await?;
+        Ok(response.into_inner().plans.into_iter().map(Plan::from).collect())
+    }
+
+    /// What the client's namespace's jobs used and cost on each of the last days UTC days,
+    /// including today, or the last 31 if None.
+    pub async fn usage(&self, days: Option<u32>) -> Result<Usage, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .get_usage(UsageRequest { namespace: self.namespace.clone(), days }).await?;
+        Ok(response.into_inner().into())
+    }
+
+    /// The orchestrator's client quotas, and how much of them each client is using. A client
+    /// that authenticates with a token only sees its own usage.
+    pub async fn quotas(&self) -> Result<Quotas, ClientError> {
+        let response = self.orchestrator_client.clone().get_quotas(QuotasRequest {}).await?;
+        Ok(response.into_inner().into())
+    }
+
+    /// Have the orchestrator compile the job's module on every worker that may run the client's
+    /// jobs, or only the given workers if any are named, so the first jobs that run it don't
+    /// wait for it to compile. Drained and quarantined workers are skipped. Each worker is given
+    /// up on after timeout, 30 seconds if None. Returns how it went on each worker.
+    pub async fn warm_workers(&self, job: &Job, workers: &[String], timeout: Option<Duration>) -> Result<Vec<WarmResult>, ClientError> {
+        let (wasm_bytes, module_source) = match &job.module_source {
+            Some(source) => (Vec::new(), Some(source.into())),
+            None => (job.wasm_bytes.clone(), None),
+        };
+        let response = self.orchestrator_client.clone()
+            .warm_workers(WarmWorkersRequest {
+                wasm_bytes,
+                module_source,
+                namespace: self.namespace.clone(),
+                worker_addresses: workers.to_vec(),
+                timeout_ms: timeout.map(|t| t.as_millis() as u64),
+            }).await?;
+        Ok(response.into_inner().results.into_iter().map(WarmResult::from).collect())
+    }
+
+    /// Have the orchestrator check a module is something workers can run, without running it
+    /// or using any credits. The checks are those of inspect_module, as the orchestrator's
+    /// version has them.
+    pub async fn validate_module(&self, wasm_bytes: Vec<u8>) -> Result<ModuleReport, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .validate_module(ValidateModuleRequest { wasm_bytes }).await?;
+        Ok(response.into_inner().into())
+    }
+
+    /// List the modules a worker has compiled, with what the worker read from each as it cached
+    /// it, or only the module with the blake3 wasm_hash if given. Returns WorkerNotFound
+    /// if the worker isn't connected or can't run the client's jobs, and ModuleNotFound if it
+    /// doesn't have the module.
+    pub async fn worker_modules(&self, worker_address: &str, wasm_hash: Option<&[u8; 32]>) -> Result<Vec<CachedModule>, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .list_worker_modules(ListWorkerModulesRequest {
+                worker_address: worker_address.to_string(),
+                wasm_hash: wasm_hash.map(|hash| hash.to_vec()),
+                namespace: self.namespace.clone(),
+            }).await?;
+        Ok(response.into_inner().modules.into_iter().map(CachedModule::from).collect())
+    }
+
+    /// Run the pipeline's stages one after another, each reading the stdout of the one before,
+    /// and wait for the last one's output. The orchestrator queues each stage once the one
+    /// before it finished, and stops at the first stage that fails, which the output names
+    /// along with how the stages before it went. Stages aren't retried or signed.
+    pub async fn run_pipeline(&self, pipeline: &Pipeline) -> Result<PipelineOutput, ClientError> {
+        let response = self.orchestrator_client.clone()
+            // The last stage's stdout comes back in the response
+            .max_decoding_message_size(JobLimits::default().max_message_bytes())
+            .run_pipeline(PipelineRequest {
+                stages: pipeline.proto_stages(),
+                namespace: self.namespace.clone(),
+                timeout_ms: pipeline.timeout.map(|t| t.as_millis() as u64),
+                protocol_version: PROTOCOL_VERSION,
+                stdin: pipeline.stdin.clone(),
+            }).await?
+            .into_inner();
+        PipelineOutput::from_proto(response)
+            .ok_or_else(|| ClientError::RequestFailed("the orchestrator sent a malformed job id for a pipeline stage".to_string()))
+    }
+
+    /// Publish a module under a name:tag alias in the client's namespace, along with defaults
+    /// for jobs submitted by the alias, replacing whatever the tag pointed at before, which is
+    /// returned. The orchestrator keeps a history of replaced tags. Only object store modules
+    /// can be published, since workers fetch them.
+    pub async fn publish_module(&self, name: &str, tag: &str, source: &ModuleSource, defaults: &ModuleDefaults) -> Result<Option<ModuleSource>, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .publish_module(PublishModuleRequest {
+                name: name.to_string(),
+                tag: tag.to_string(),
+                module: Some(source.into()),
+                namespace: self.namespace.clone(),
+                defaults: Some(defaults.into()),
+            }).await?;
+        Ok(response.into_inner().previous.and_then(ModuleSource::from_proto))
+    }
+
+    /// Look up the module a name:tag alias points at, to submit with Job::from_source and
+    /// Job::defaults. Returns ModuleNotFound, listing the name's tags, if it isn't published.
+    pub async fn resolve_module(&self, name: &str, tag: &str) -> Result<PublishedModule, ClientError> {
+        let response = self.orchestrator_client.clone()
+            .resolve_module(ResolveModuleRequest {
+                name: name.to_string(),
+                tag: tag.to_string(),
+                namespace: self.namespace.clone(),
+            }).await?;
+        let response = response.into_inner();
+        let source = response.module.and_then(ModuleSource::from_proto)
+            .ok_or_else(|| ClientError::RequestFailed(format!("the orchestrator sent an unrecognized module for {name}:{tag}")))?;
+        Ok(PublishedModule {
+            source,
+            defaults: response.defaults.unwrap_or_default().into(),
+            published_at: UNIX_EPOCH + Duration::from_millis(response.published_at_ms),
+        })
+    }
+
     /// Send a cancellation request to the Orchestrator to remove a queued job.
-    pub(crate) async fn cancel_queued_job(&self, job_id: Uuid) {
+    pub(crate) async fn cancel_queued_job(&self, job_id: JobId) {
         if let Err(e) = self.orchestrator_client.clone()
             .cancel_job(CancelJobRequest {
-                job_id: job_id.as_bytes().to_vec()
+                job_id: job_id.to_bytes(),
+                namespace: self.namespace.clone(),
             }).await
         {
             // Not an error, a network race where the job was already dispatched is expected.
@@ -169,9 +572,24 @@ impl Client {
     }
 
     /// Send a cancellation request to the worker currently executing a job.
-    pub(crate) async fn cancel_running_job(&self, job_id: Uuid, mut executor_client: ExecutorClient<InterceptedService<Channel, WorkerJwtInterceptor>>) {
-        if let Err(e) = executor_client.cancel_job(CancelJobRequest {
-            job_id: job_id.as_bytes().to_vec()
+    /// The job's module digest made with the algorithm, and the job's signature over it.
+    fn probe(&self, job_id_bytes: &[u8], job: &Job, algorithm: HashAlgorithm) -> Probe {
+        let digest = ModuleDigest::of(algorithm, &job.wasm_bytes);
+        let signature = self.signer.as_ref()
+            .map(|signer| (signer.key_id().to_string(), signer.sign(&signed_bytes(job_id_bytes, &digest.bytes, &job.stdin, &job.args))))
+            .unwrap_or_default();
+        Probe { digest, signature }
+    }
+
+    /// The known modules record, with the orchestrator endpoint its entries are kept under.
+    fn known_modules(&self) -> Option<(&KnownModules, &str)> {
+        self.known_modules.as_deref().map(|known| (known, self.orchestrator_endpoint.as_str()))
+    }
+
+    pub(crate) async fn cancel_running_job(&self, job_id: JobId, mut transport: WorkerTransport) {
+        if let Err(e) = transport.cancel_job(CancelJobRequest {
+            job_id: job_id.to_bytes(),
+            namespace: None,
         }).await
         {
             // Not an error, the worker may have already finished the job.
@@ -181,11 +599,241 @@ impl Client {
 }
 
 
+/// Sends the job to its assigned worker according to the job's cache mode. By default only the
+/// module hash is sent at first, and the wasm is uploaded only if the worker doesn't have the
+/// module compiled already. Given known modules, the worker's answer is recorded in them. Given
+/// a delta, the wasm is uploaded as a patch, see upload_wasm. The worker is told how much of the job's timeout is left so that it stops the
+/// job itself once the deadline passes.
+#[allow(clippy::too_many_arguments)]
+async fn run_on_worker(
+    transport: &mut WorkerTransport,
+    job_id: JobId,
+    job: &Job,
+    wasm_hash: &[u8],
+    signature: &(String, Vec<u8>),
+    probe: Option<&Probe>,
+    deadline: Option<Instant>,
+    known_modules: Option<(&KnownModules, &str)>,
+    delta: Option<&DeltaUpload>,
+) -> Result<tonic::Response<JobResponse>, Status> {
+    let job_request = |upload: Upload| {
+        // A probe by another digest only replaces the hash sent without the wasm
+        let (hash, hash_algorithm, (key_id, signature)) = match probe.filter(|_| upload == Upload::Hash) {
+            Some(probe) => (&probe.digest.bytes[..], Some(probe.digest.algorithm as i32), &probe.signature),
+            None => (wasm_hash, None, signature),
+        };
+        Request::new(JobRequest {
+            job_id: job_id.to_bytes(),
+            wasm_bytes: if upload == Upload::Wasm { job.wasm_bytes.clone() } else { vec![] },
+            args: job.args.clone(),
+            wasm_hash: hash.to_vec(),
+            skip_cache: job.cache_mode == CacheMode::NoCache,
+            upload_on_miss: job.cache_mode == CacheMode::Default && job.module_source.is_none(),
+            protocol_version: PROTOCOL_VERSION,
+            key_id: key_id.clone(),
+            signature: signature.clone(),
+            module_source: job.module_source.as_ref().map(Into::into),
+            timeout_ms: deadline.map(|d| d.saturating_duration_since(Instant::now()).as_millis() as u64),
+            coalesce: job.coalesces(),
+            delta: delta.filter(|_| upload == Upload::Delta).cloned().map(Box::new),
+            wasm_hash_algorithm: hash_algorithm,
+            stdin: job.stdin.clone(),
+            offload_output: true,
+        })
+    };
+
+    if job.module_source.is_some() {
+        tracing::debug!(job_id = %job_id, "execute_job sent to worker with module source");
+        return transport.execute_job(job_request(Upload::Hash)).await;
+    }
+
+    if job.cache_mode == CacheMode::NoCache {
+        tracing::debug!(job_id = %job_id, "execute_job sent to worker with wasm, skipping cache");
+        return transport.execute_job(job_request(Upload::Wasm)).await;
+    }
+
+    let known_modules = known_modules.filter(|_| job.cache_mode == CacheMode::Default);
+    tracing::debug!(job_id = %job_id, "execute_job sent to worker with wasm hash");
+    let result = match transport.execute_job(job_request(Upload::Hash)).await {
+        Err(e) if is_module_not_cached(&e) && job.cache_mode == CacheMode::Default => {
+            tracing::debug!(job_id = %job_id, "module not cached on worker, uploading wasm");
+            if let Some((known, orchestrator)) = known_modules {
+                known.invalidate(orchestrator, wasm_hash);
+            }
+            upload_wasm(transport, job_id, job_request, delta.is_some()).await
+        },
+        result => result,
+    };
+    if let (Ok(_), Some((known, orchestrator))) = (&result, known_modules) {
+        known.confirm(orchestrator, wasm_hash);
+    }
+    result
+}
+
+/// The digest a job's worker is probed by in place of its blake3 hash, see
+/// Client::with_hash_algorithm, and the job's signature over it.
+struct Probe {
+    digest: ModuleDigest,
+    signature: (String, Vec<u8>),
+}
+
+/// What a job request sends of the job's module, besides its hash.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Upload {
+    Hash,
+    Delta,
+    Wasm,
+}
+
+/// Fetches a job's output that its worker stored rather than returned, in parts, and checks it
+/// is the output the reference was made for. The size in the reference is only the worker's
+/// word, so the output grows as its parts arrive, and no more than max_bytes are fetched.
+async fn fetch_output(transport: &mut WorkerTransport, job_id: JobId, output_ref: &OutputRef, max_bytes: u64) -> Result<Vec<u8>, JobError> {
+    tracing::debug!(job_id = %job_id, output_id = %output_ref.id, bytes = output_ref.size, "fetching stored job output");
+    if output_ref.size > max_bytes {
+        return Err(JobError::OutputUnavailable(format!("output {} is {} bytes, more than the {max_bytes} bytes the client fetches", output_ref.id, output_ref.size)));
+    }
+    let mut output = Vec::new();
+    loop {
+        let response = transport.fetch_output(FetchOutputRequest {
+            job_id: job_id.to_bytes(),
+            output_id: output_ref.id.clone(),
+            offset: output.len() as u64,
+            max_bytes: OUTPUT_CHUNK_BYTES,
+        }).await?;
+        output.extend_from_slice(&response.data);
+        if response.eof {
+            break;
+        }
+        if response.data.is_empty() || output.len() as u64 > output_ref.size {
+            return Err(JobError::OutputUnavailable(format!("the worker sent output {} at odds with its {} bytes", output_ref.id, output_ref.size)));
+        }
+    }
+    output_ref.verify(&output).map_err(JobError::OutputUnavailable)?;
+    Ok(output)
+}
+
+/// Uploads the job's wasm, first as a patch if there is a delta. If the worker can't rebuild
+/// the module from the patch, for example because it no longer has the base, the wasm is
+/// uploaded in full, which the worker waits for.
+async fn upload_wasm(
+    transport: &mut WorkerTransport,
+    job_id: JobId,
+    job_request: impl Fn(Upload) -> Request<JobRequest>,
+    delta: bool,
+) -> Result<tonic::Response<JobResponse>, Status> {
+    if delta {
+        tracing::debug!(job_id = %job_id, "execute_job sent to worker with a delta upload");
+        match transport.execute_job(job_request(Upload::Delta)).await {
+            Err(e) if is_delta_rejected(&e) => {
+                tracing::debug!(job_id = %job_id, error = %e, "worker rejected the delta upload, uploading wasm in full");
+            },
+            result => return result,
+        }
+    }
+    transport.execute_job(job_request(Upload::Wasm)).await
+}
+
+/// The patch for uploading wasm_bytes against the version last uploaded under the delta key,
+/// or None if none was, the cluster's workers may no longer have it, or the patch is no smaller.
+async fn make_delta(known: &KnownModules, orchestrator: &str, key: &str, wasm_bytes: &[u8]) -> Option<DeltaUpload> {
+    let base = known.last_upload(key)?;
+    let base_hash = blake3::hash(&base);
+    if base == wasm_bytes || !known.is_fresh(orchestrator, base_hash.as_bytes()) {
+        return None;
+    }
+    let wasm = wasm_bytes.to_vec();
+    let patch = tokio::task::spawn_blocking(move || shared::delta::make_patch(&base, &wasm)).await;
+    match patch {
+        Ok(Ok(patch)) if patch.len() < wasm_bytes.len() => Some(DeltaUpload { base_hash: base_hash.as_bytes().to_vec(), patch }),
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => {
+            tracing::debug!(error = %e, "failed to make a delta upload, uploading wasm in full");
+            None
+        },
+        Err(e) => {
+            tracing::debug!(error = %e, "delta upload task failed, uploading wasm in full");
+            None
+        },
+    }
+}
+
+/// Publishes the queued job's position each time the orchestrator reports it, until the job
+/// leaves the queue. Waits QUEUE_WATCH_DELAY first, so that jobs dispatched straight away
+/// never open a stream. Failing to watch only means no reports.
+async fn watch_queue_position(
+    mut orchestrator_client: ClientApiClient<InterceptedService<Channel, OrchestratorAuthInterceptor>>,
+    job_id: JobId,
+    namespace: Option<String>,
+    queue_tx: &watch::Sender<Option<QueueProgress>>,
+) {
+    tokio::time::sleep(QUEUE_WATCH_DELAY).await;
+    let request = WatchQueuePositionRequest { job_id: job_id.to_bytes(), namespace };
+    let mut stream = match orchestrator_client.watch_queue_position(request).await {
+        Ok(response) => response.into_inner(),
+        Err(e) => {
+            tracing::debug!(job_id = %job_id, error = %e, "failed to watch the job's queue position");
+            return;
+        },
+    };
+    while let Ok(Some(position)) = stream.message().await {
+        let progress = QueueProgress::from(position);
+        tracing::debug!(job_id = %job_id, position = progress.position, queued_jobs = progress.queued_jobs, "job waiting in queue");
+        queue_tx.send_replace(Some(progress));
+    }
+}
+
+/// Waits until the job's deadline, forever if it has none.
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The state of a job that was stopped before finishing: timed out if its deadline has passed,
+/// otherwise cancelled.
+fn stopped_state(deadline: Option<Instant>) -> JobState {
+    if deadline.is_some_and(|d| Instant::now() >= d) {
+        JobState::Completed(Err(JobError::TimedOut))
+    } else {
+        JobState::Cancelled
+    }
+}
+
+/// Whether a worker failed a job because it didn't have the module cached.
+fn is_module_not_cached(status: &Status) -> bool {
+    match ErrorCode::of(status) {
+        Some(error_code) => error_code == ErrorCode::ModuleNotCached,
+        None => status.code() == Code::FailedPrecondition,
+    }
+}
+
+/// Whether a worker couldn't rebuild a module from its delta upload. Workers that predate
+/// delta uploads ignore the patch, and so report the module as not cached.
+fn is_delta_rejected(status: &Status) -> bool {
+    matches!(ErrorCode::of(status), Some(ErrorCode::DeltaBaseMissing | ErrorCode::DeltaMismatch)) || is_module_not_cached(status)
+}
+
+/// Maps a failed request_worker call to a JobError. Unlike worker errors, an invalid argument
+/// here refers to the job's options rather than its wasm.
+fn request_worker_error(status: Status) -> JobError {
+    let message = status.message().to_string();
+    if let Some(ErrorCode::QuotaExceeded | ErrorCode::DailyQuotaExceeded | ErrorCode::InvalidCallbackUrl | ErrorCode::Unauthorized | ErrorCode::VersionMismatch | ErrorCode::WorkerNotFound) = ErrorCode::of(&status) {
+        return JobError::Rejected(message);
+    }
+    match status.code() {
+        Code::ResourceExhausted | Code::InvalidArgument | Code::Unauthenticated => JobError::Rejected(message),
+        Code::Unavailable => JobError::Internal(format!("the orchestrator is unavailable: {message}")),
+        _ => JobError::from(status),
+    }
+}
+
 /// Injects the client password into the authorization header of every outbound request
 /// to the Orchestrator.
 /// No-op if no password is configured.
 #[derive(Clone)]
-struct OrchestratorAuthInterceptor {
+pub(crate) struct OrchestratorAuthInterceptor {
     password: Option<String>
 }
 
@@ -215,11 +863,122 @@ impl Interceptor for WorkerJwtInterceptor {
     }
 }
 
+/// How the client calls the worker running a job.
+pub(crate) enum WorkerTransport {
+    /// Connected to the worker.
+    Direct(ExecutorClient<InterceptedService<Channel, WorkerJwtInterceptor>>),
+    /// Relayed by the orchestrator, for workers that accept no connections.
+    Relayed {
+        orchestrator_client: ClientApiClient<InterceptedService<Channel, OrchestratorAuthInterceptor>>,
+        worker_address: String,
+        jwt_token: String,
+    },
+}
+
+impl WorkerTransport {
+    async fn execute_job(&mut self, request: Request<JobRequest>) -> Result<tonic::Response<JobResponse>, Status> {
+        match self {
+            WorkerTransport::Direct(executor_client) => executor_client.execute_job(request).await,
+            WorkerTransport::Relayed { orchestrator_client, worker_address, jwt_token } => {
+                orchestrator_client.relay_execute_job(RelayedJobRequest {
+                    worker_address: worker_address.clone(),
+                    jwt_token: jwt_token.clone(),
+                    job: Some(request.into_inner()),
+                }).await
+            },
+        }
+    }
+
+    async fn fetch_output(&mut self, request: FetchOutputRequest) -> Result<FetchOutputResponse, Status> {
+        let response = match self {
+            WorkerTransport::Direct(executor_client) => executor_client.fetch_output(request).await?,
+            WorkerTransport::Relayed { orchestrator_client, worker_address, jwt_token } => {
+                orchestrator_client.relay_fetch_output(RelayedFetchOutputRequest {
+                    worker_address: worker_address.clone(),
+                    jwt_token: jwt_token.clone(),
+                    fetch: Some(request),
+                }).await?
+            },
+        };
+        Ok(response.into_inner())
+    }
+
+    async fn cancel_job(&mut self, request: CancelJobRequest) -> Result<(), Status> {
+        match self {
+            WorkerTransport::Direct(executor_client) => executor_client.cancel_job(request).await?,
+            WorkerTransport::Relayed { orchestrator_client, worker_address, jwt_token } => {
+                orchestrator_client.relay_cancel_job(RelayedCancelRequest {
+                    worker_address: worker_address.clone(),
+                    jwt_token: jwt_token.clone(),
+                    cancel: Some(request),
+                }).await?
+            },
+        };
+        Ok(())
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ClientError {
     #[error("the provided orchestrator endpoint is invalid: {0}")]
     InvalidEndpoint(String),
 
-    #[error("failed to connect to the orchestrator: {0}")]
+    #[error("failed to connect to the orchestrator: {}", error_chain(.0))]
     ConnectionFailed(#[from] tonic::transport::Error),
+
+    #[error("invalid TLS configuration: {0}")]
+    Tls(String),
+
+    #[error("job not found, or no longer queued")]
+    JobNotFound,
+
+    #[error("not permitted: {0}")]
+    PermissionDenied(String),
+
+    #[error("{0}")]
+    ModuleNotFound(String),
+
+    #[error("{0}")]
+    WorkerNotFound(String),
+
+    #[error("request to the orchestrator failed: {0}")]
+    RequestFailed(String),
+
+    #[error("{0}")]
+    StdinUnavailable(String),
+
+    #[error("{0}")]
+    StdinMismatch(String),
+}
+
+/// Formats an error along with its sources, since transport errors only describe themselves
+/// as "transport error" and keep the cause (e.g. a certificate problem) in their source.
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        // Wrapping errors often repeat their source's message
+        let source_message = e.to_string();
+        if !message.ends_with(&source_message) {
+            message += &format!(": {source_message}");
+        }
+        source = e.source();
+    }
+    message
+}
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        match status.code() {
+            Code::NotFound if matches!(ErrorCode::of(&status), Some(ErrorCode::ModuleNotFound | ErrorCode::ModuleNotCached)) => {
+                ClientError::ModuleNotFound(status.message().to_string())
+            },
+            Code::NotFound if ErrorCode::of(&status) == Some(ErrorCode::WorkerNotFound) => {
+                ClientError::WorkerNotFound(status.message().to_string())
+            },
+            Code::NotFound => ClientError::JobNotFound,
+            Code::PermissionDenied => ClientError::PermissionDenied(status.message().to_string()),
+            _ => ClientError::RequestFailed(format!("code: {}, message: {}", status.code(), status.message())),
+        }
+    }
 }
\ No newline at end of file
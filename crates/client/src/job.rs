@@ -1,9 +1,19 @@
-use std::{ops::Deref, path::Path, time::Duration};
+use std::{ops::Deref, path::Path, time::{Duration, SystemTime}};
 use std::fmt::Display;
 
 use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 use tonic::{Code, Status};
+use shared::{ErrorCode, JobId};
+use shared::digest::ModuleDigest;
+use shared::limits::{JobLimits, LimitError};
+
+use crate::status::QueueProgress;
+
+/// Retries a job gets unless it sets its own. Only failures that may pass on another attempt
+/// are retried, see is_transient_orchestrator_error and is_worker_failure, so a job whose wasm
+/// fails still runs once.
+pub(crate) const DEFAULT_MAX_RETRIES: u32 = 3;
 
 /// A wasm job to be submitted for remote execution.
 /// Construct with from_bytes or from_path, then configure using the builder methods.
@@ -13,6 +23,102 @@ pub struct Job {
     pub(crate) wasm_bytes: Vec<u8>,
     pub(crate) args: Vec<String>,
     pub(crate) timeout: Option<Duration>,
+    pub(crate) not_before: Option<SystemTime>,
+    pub(crate) callback_url: Option<String>,
+    /// None uses DEFAULT_MAX_RETRIES, or a module default.
+    pub(crate) max_retries: Option<u32>,
+    pub(crate) cache_mode: CacheMode,
+    pub(crate) module_source: Option<ModuleSource>,
+    pub(crate) coalesce: bool,
+    pub(crate) delta_key: Option<String>,
+    /// The blake3 hash of the wasm, for jobs created with from_hash, which have no wasm bytes.
+    pub(crate) known_hash: Option<[u8; 32]>,
+    pub(crate) worker_address: Option<String>,
+    pub(crate) replay_of: Option<JobId>,
+    pub(crate) stdin: Vec<u8>,
+}
+
+/// How a job's wasm is sent to its worker, which caches compiled modules by hash.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Send only the module hash at first, uploading the wasm if the worker doesn't have it cached.
+    #[default]
+    Default,
+    /// Always upload the wasm and have the worker recompile it, replacing any cached copy.
+    NoCache,
+    /// Never upload the wasm. The job fails with JobError::ModuleNotCached if the worker
+    /// doesn't have it cached.
+    HashOnly,
+}
+
+/// Where a worker fetches a job's module from, for jobs created with Job::from_source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModuleSource {
+    /// An object at an `s3://bucket/key` URL in the worker's S3-compatible object store, which
+    /// the worker rejects unless its digest matches. Workers need the object-store feature and
+    /// an --object-store-endpoint, though a worker that already has the module compiled runs
+    /// it without fetching it.
+    ObjectStore { url: String, digest: ModuleDigest },
+}
+
+/// Job settings published with a module alias, see Client::publish_module.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleDefaults {
+    pub timeout: Option<Duration>,
+    pub max_retries: Option<u32>,
+    /// Only used by jobs with no args of their own.
+    pub args: Vec<String>,
+}
+
+impl From<shared::ModuleDefaults> for ModuleDefaults {
+    fn from(defaults: shared::ModuleDefaults) -> Self {
+        Self {
+            timeout: defaults.timeout_ms.map(Duration::from_millis),
+            max_retries: defaults.max_retries,
+            args: defaults.args,
+        }
+    }
+}
+
+impl From<&ModuleDefaults> for shared::ModuleDefaults {
+    fn from(defaults: &ModuleDefaults) -> Self {
+        Self {
+            timeout_ms: defaults.timeout.map(|t| t.as_millis() as u64),
+            max_retries: defaults.max_retries,
+            args: defaults.args.clone(),
+        }
+    }
+}
+
+/// A module published under a name:tag alias, see Client::resolve_module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublishedModule {
+    pub source: ModuleSource,
+    pub defaults: ModuleDefaults,
+    pub published_at: SystemTime,
+}
+
+impl ModuleSource {
+    /// Converts a source received from the orchestrator, None if it isn't one this client knows.
+    pub(crate) fn from_proto(source: shared::ModuleSource) -> Option<Self> {
+        match source.source? {
+            shared::module_source::Source::ObjectStore(module) => Some(ModuleSource::ObjectStore {
+                digest: module.module_digest().ok()?,
+                url: module.url,
+            }),
+        }
+    }
+}
+
+impl From<&ModuleSource> for shared::ModuleSource {
+    fn from(source: &ModuleSource) -> Self {
+        let source = match source {
+            ModuleSource::ObjectStore { url, digest } => {
+                shared::module_source::Source::ObjectStore(shared::ObjectStoreModule::new(url.clone(), *digest))
+            },
+        };
+        shared::ModuleSource { source: Some(source) }
+    }
 }
 
 impl Job {
@@ -21,9 +127,31 @@ impl Job {
         Self { 
             wasm_bytes,
             args: vec![],
-            timeout: None
+            timeout: None,
+            not_before: None,
+            callback_url: None,
+            max_retries: None,
+            cache_mode: CacheMode::Default,
+            module_source: None,
+            coalesce: false,
+            delta_key: None,
+            known_hash: None,
+            worker_address: None,
+            replay_of: None,
+            stdin: Vec::new(),
         }
     }
+    /// Create a job whose worker fetches the module itself, so the client never uploads it.
+    /// Caching works as for other jobs, except that nothing is uploaded on a cache miss.
+    pub fn from_source(source: ModuleSource) -> Self {
+        Self { module_source: Some(source), ..Self::from_bytes(Vec::new()) }
+    }
+    /// Create a job that runs a module its worker already has compiled, by the blake3 hash of
+    /// its wasm. The wasm is never uploaded, so the job fails with JobError::ModuleNotCached
+    /// if the worker doesn't have it, and its cache mode is always HashOnly.
+    pub fn from_hash(wasm_hash: [u8; 32]) -> Self {
+        Self { known_hash: Some(wasm_hash), cache_mode: CacheMode::HashOnly, ..Self::from_bytes(Vec::new()) }
+    }
     /// Create a job by reading a wasm file from the given path.
     pub fn from_path(wasm_path: impl AsRef<Path>) -> Result<Self, std::io::Error> {
         match std::fs::read(wasm_path) {
@@ -42,10 +170,98 @@ impl Job {
         self
     }
     /// Set a maximum duration for the job. The job will fail with JobError::TimedOut if exceeded.
+    /// It counts from submission, or from the job's start time if it is scheduled.
     pub fn timeout(mut self, duration: Duration) -> Self {
         self.timeout = Some(duration);
         self
     }
+    /// Hold the job in the orchestrator until the given time before queueing it for a worker.
+    /// The timeout, if set, counts from then.
+    pub fn not_before(mut self, time: SystemTime) -> Self {
+        self.not_before = Some(time);
+        self
+    }
+    /// Hold the job in the orchestrator for the given duration after submission before queueing it.
+    pub fn delay(self, duration: Duration) -> Self {
+        self.not_before(SystemTime::now() + duration)
+    }
+    /// Retry transient failures up to this many times (default 3), backing off exponentially
+    /// between attempts. Retried failures are the orchestrator being unreachable, a client quota
+    /// being full, and the assigned worker failing (crashing, dropping the connection, or being
+    /// unreachable), in which case the job is rerun on another worker.
+    /// Jobs whose wasm fails to compile or run are never retried.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+    /// Fill in the settings this job hasn't set from a published module's defaults, so that
+    /// the job's own settings win. Call it after configuring the job.
+    pub fn defaults(mut self, defaults: &ModuleDefaults) -> Self {
+        self.timeout = self.timeout.or(defaults.timeout);
+        self.max_retries = self.max_retries.or(defaults.max_retries);
+        if self.args.is_empty() {
+            self.args = defaults.args.clone();
+        }
+        self
+    }
+    /// Checks the job against known worker limits, so an oversized job can be caught before
+    /// it is submitted. Workers check their own limits in any case.
+    pub fn validate(&self, limits: &JobLimits) -> Result<(), LimitError> {
+        limits.check(self.wasm_bytes.len(), &self.args)
+    }
+    /// Control whether the worker's compiled module cache is used, see CacheMode. Ignored by
+    /// jobs created with from_hash, which have no wasm to upload.
+    pub fn cache_mode(mut self, cache_mode: CacheMode) -> Self {
+        if self.known_hash.is_none() {
+            self.cache_mode = cache_mode;
+        }
+        self
+    }
+    /// Let the orchestrator coalesce this job with an identical one (same module and args, in
+    /// the same namespace) that is already queued or running and also opted in, so that the
+    /// module runs once and both jobs get its output. Only the job that runs is charged.
+    /// Scheduled jobs are never coalesced, nor are jobs that read stdin.
+    pub fn coalesce(mut self, coalesce: bool) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+    /// Whether the job may be coalesced, which workers refuse for jobs that read stdin.
+    pub(crate) fn coalesces(&self) -> bool {
+        self.coalesce && self.stdin.is_empty()
+    }
+    /// What the job reads on its stdin, nothing by default.
+    pub fn stdin(mut self, stdin: Vec<u8>) -> Self {
+        self.stdin = stdin;
+        self
+    }
+    /// Upload the wasm as a patch against the version of the module last uploaded under the
+    /// same key, typically the module's file path, which saves sending what didn't change.
+    /// Needs the client to keep known modules, see Client::with_known_modules, and is skipped
+    /// unless the cluster's workers recently had that version. A worker that no longer has it
+    /// gets the wasm in full instead. Only affects jobs with the default cache mode.
+    pub fn delta_key(mut self, key: impl Into<String>) -> Self {
+        self.delta_key = Some(key.into());
+        self
+    }
+    /// Have the orchestrator POST a JSON completion notice to the given http(s) URL once the
+    /// job finishes, fails, or is cancelled.
+    pub fn callback_url(mut self, url: impl Into<String>) -> Self {
+        self.callback_url = Some(url.into());
+        self
+    }
+    /// Run the job on the worker with the given address rather than letting the orchestrator
+    /// pick one. Such a job skips the queue: it is rejected if the worker has no credit free,
+    /// and it can't be scheduled or coalesced.
+    pub fn worker(mut self, worker_address: impl Into<String>) -> Self {
+        self.worker_address = Some(worker_address.into());
+        self
+    }
+    /// Record the job as a replay of an earlier job, which the orchestrator reports in its
+    /// status, see JobSubmission::replay.
+    pub fn replay_of(mut self, job_id: JobId) -> Self {
+        self.replay_of = Some(job_id);
+        self
+    }
 }
 
 pub enum JobState {
@@ -59,11 +275,38 @@ pub enum JobState {
 /// Use wait to block until the job finishes, or cancel to stop it early.
 #[derive(Clone)]
 pub struct RunningJob {
+    pub(crate) job_id: JobId,
     pub(crate) state_rx: watch::Receiver<JobState>,
+    pub(crate) worker_rx: watch::Receiver<Option<String>>,
+    pub(crate) queue_rx: watch::Receiver<Option<QueueProgress>>,
     pub(crate) cancel_token: CancellationToken,
 }
 
 impl RunningJob {
+    /// The job's id, which can be used to look up its status or cancel it from another process.
+    pub fn job_id(&self) -> JobId {
+        self.job_id
+    }
+
+    /// The address of the worker the job was most recently assigned to, or None if it hasn't
+    /// left the queue yet. Changes if the job is retried on another worker.
+    pub fn worker_address(&self) -> Option<String> {
+        self.worker_rx.borrow().clone()
+    }
+
+    /// The job's place in the orchestrator's queue as last reported, or None if it isn't
+    /// waiting in the queue. Reports arrive every few seconds once the job has waited for a
+    /// moment, so jobs dispatched straight away never have one.
+    pub fn queue_position(&self) -> Option<QueueProgress> {
+        self.queue_rx.borrow().clone()
+    }
+
+    /// Wait until queue_position changes, including to None when the job leaves the queue.
+    /// Returns false once the job has finished, after which it never changes.
+    pub async fn queue_position_changed(&mut self) -> bool {
+        self.queue_rx.changed().await.is_ok()
+    }
+
     /// Wait for the job to finish and return its output.
     /// Returns an error if the job failed, timed out, or was cancelled.
     pub async fn wait(mut self) -> Result<JobOutput, JobError> {
@@ -95,6 +338,42 @@ impl RunningJob {
 pub struct JobOutput {
     pub stdout: Vec<u8>,
     pub stderr: Vec<u8>,
+    pub timing: JobTiming,
+    /// What the job's namespace was charged for it, in the cluster's cost units.
+    pub estimated_cost: f64,
+    /// Set if the job was coalesced with an identical job, whose run it shared. See Job::coalesce.
+    pub coalesced_with: Option<JobId>,
+    /// How many fewer bytes were uploaded by sending the wasm as a patch. See Job::delta_key.
+    pub upload_bytes_saved: u64,
+    /// Whether the worker stored stdout rather than returned it, as it was larger than the
+    /// worker's offload threshold, and the client fetched it from the worker afterwards.
+    pub output_offloaded: bool,
+}
+
+/// Where the time went for a completed job. Compile and execute times are reported by the
+/// worker; transfer covers sending the wasm to the worker and receiving the output back.
+#[derive(Clone, Debug, Default)]
+pub struct JobTiming {
+    /// Time spent waiting for a worker assignment, including any scheduled delay.
+    pub queued: Duration,
+    pub transfer: Duration,
+    /// Zero if the worker already had the module compiled.
+    pub compile: Duration,
+    pub execute: Duration,
+    pub total: Duration,
+    /// Whether the worker had the compiled module cached.
+    pub cache_hit: bool,
+    /// How long instantiating the module took for the instance the job ran, which the worker
+    /// kept ready in a warm pool. Zero if the worker instantiated it for the job.
+    pub instantiation_saved: Duration,
+}
+
+impl JobTiming {
+    /// Whether the job had to wait for its module to be compiled, in which case compile is
+    /// how long that took.
+    pub fn cold_start(&self) -> bool {
+        !self.cache_hit
+    }
 }
 
 impl Display for JobOutput {
@@ -114,21 +393,81 @@ pub enum JobError {
     #[error("the submitted wasm contained an error when compiled or when run: {0}")]
     WasmError(String), // bad wasm input from user
 
+    /// The job's wasm exited with a non-zero code, writing stderr to its stderr. Its stdout
+    /// isn't returned.
+    #[error("the job exited with code {code}, stderr: {}", String::from_utf8_lossy(stderr))]
+    Exited { code: i32, stderr: Vec<u8> },
+
+    /// The orchestrator or worker refused the job, e.g. because a client quota was exceeded,
+    /// the password was wrong, an option such as the callback url was invalid, or the job was
+    /// larger than the worker's limits, the worker couldn't fetch the job's module source, or
+    /// the namespace was invalid or not the password's.
+    #[error("the job was rejected: {0}")]
+    Rejected(String),
+
     /// An unexpected system error occurred, not caused by user input.
     #[error("internal error: {0}")]
     Internal(String), // unexpected internal system error
 
+    /// The job was submitted with CacheMode::HashOnly and its worker didn't have the module cached.
+    #[error("the wasm module is not cached on the assigned worker")]
+    ModuleNotCached,
+
     /// The job was explicitly cancelled by the caller.
     #[error("job cancelled by user")]
     Cancelled, // job explicitly cancelled by user
+
+    /// The job didn't finish within its timeout, see Job::timeout.
+    #[error("job timed out")]
+    TimedOut,
+
+    /// The job ran, but the output its worker stored rather than returned couldn't be fetched,
+    /// e.g. because the worker's retention for it passed, or it didn't match its digest.
+    #[error("the job's output couldn't be fetched from its worker: {0}")]
+    OutputUnavailable(String),
+}
+
+impl JobError {
+    /// A short, stable name for the kind of failure, e.g. for grouping errors in reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobError::WasmError(_) => "wasm_error",
+            JobError::Exited { .. } => "exited",
+            JobError::Rejected(_) => "rejected",
+            JobError::Internal(_) => "internal",
+            JobError::ModuleNotCached => "module_not_cached",
+            JobError::Cancelled => "cancelled",
+            JobError::TimedOut => "timed_out",
+            JobError::OutputUnavailable(_) => "output_unavailable",
+        }
+    }
 }
 
 impl From<Status> for JobError {
     fn from(status: Status) -> Self {
         let message = status.message().to_string();
+        if let Some((code, stderr)) = shared::exit_of(&status) {
+            return JobError::Exited { code, stderr: stderr.to_vec() };
+        }
+        if let Some(error_code) = ErrorCode::of(&status) {
+            return match error_code {
+                ErrorCode::CompileError | ErrorCode::ExecutionError | ErrorCode::StackOverflow => JobError::WasmError(message),
+                ErrorCode::JobCancelled => JobError::Cancelled,
+                ErrorCode::JobTimedOut => JobError::TimedOut,
+                ErrorCode::ModuleNotCached => JobError::ModuleNotCached,
+                ErrorCode::OutputExpired => JobError::OutputUnavailable(message),
+                ErrorCode::LimitExceeded | ErrorCode::SignatureRejected | ErrorCode::ModuleFetchFailed => JobError::Rejected(message),
+                ErrorCode::InvalidNamespace | ErrorCode::NamespaceForbidden => JobError::Rejected(message),
+                ErrorCode::UnsupportedHashAlgorithm | ErrorCode::InvalidModuleDigest => JobError::Rejected(message),
+                _ => JobError::Internal(format!("{error_code}: {message}")),
+            };
+        }
+        // Servers that predate error codes
         match status.code() {
             Code::InvalidArgument => JobError::WasmError(message),
             Code::Cancelled => JobError::Cancelled,
+            Code::DeadlineExceeded => JobError::TimedOut,
+            Code::FailedPrecondition => JobError::ModuleNotCached,
             _ => JobError::Internal(format!("code: {}, message: {}", status.code(), message))
         }
     }
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// orchestrator URL -> hex module hash -> when a worker last had the module, in ms since the
+/// unix epoch
+type Entries = BTreeMap<String, BTreeMap<String, u64>>;
+
+/// A local record of modules recently confirmed cached on a cluster's workers, kept in a JSON
+/// file so that it is shared by every client on the machine.
+///
+/// A Client records every module a worker ran for it, and forgets modules a worker turned out
+/// not to have. Workers are still asked for a module by hash before it is uploaded, whatever
+/// the record says.
+///
+/// Alongside it, the wasm last uploaded under each delta key is kept in an uploads directory,
+/// for uploading the next version as a patch against it, see Job::delta_key.
+///
+/// The files are only a hint. Failing to read or write them is logged and otherwise ignored.
+#[derive(Debug, Clone)]
+pub struct KnownModules {
+    path: PathBuf,
+    ttl: Duration,
+}
+
+impl KnownModules {
+    /// Keeps the record at path, trusting entries for ttl after they were last confirmed.
+    pub fn new(path: impl Into<PathBuf>, ttl: Duration) -> Self {
+        Self { path: path.into(), ttl }
+    }
+
+    /// mini-lambda/known.json under $XDG_CACHE_HOME or ~/.cache, or None if neither is set.
+    pub fn default_path() -> Option<PathBuf> {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(cache_dir.join("mini-lambda").join("known.json"))
+    }
+
+    /// Whether the orchestrator's workers had the module within the last ttl.
+    pub fn is_fresh(&self, orchestrator: &str, module_hash: &[u8]) -> bool {
+        let now_ms = now_ms();
+        self.load().get(orchestrator)
+            .and_then(|modules| modules.get(&hex(module_hash)))
+            .is_some_and(|&confirmed_ms| now_ms.saturating_sub(confirmed_ms) < self.ttl.as_millis() as u64)
+    }
+
+    /// Records that a worker of the orchestrator has the module cached as of now.
+    pub fn confirm(&self, orchestrator: &str, module_hash: &[u8]) {
+        let now_ms = now_ms();
+        self.update(|entries| {
+            entries.entry(orchestrator.to_string()).or_default().insert(hex(module_hash), now_ms);
+        });
+    }
+
+    /// Forgets the module, after a worker of the orchestrator turned out not to have it.
+    pub fn invalidate(&self, orchestrator: &str, module_hash: &[u8]) {
+        self.update(|entries| {
+            if let Some(modules) = entries.get_mut(orchestrator) {
+                modules.remove(&hex(module_hash));
+            }
+        });
+    }
+
+    /// The wasm last uploaded under the delta key, if any is kept.
+    pub fn last_upload(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.upload_path(key);
+        fs::read(&path)
+            .inspect_err(|e| {
+                if e.kind() != io::ErrorKind::NotFound {
+                    tracing::debug!(path = %path.display(), error = %e, "failed to read the last upload");
+                }
+            })
+            .ok()
+    }
+
+    /// Keeps the wasm uploaded under the delta key, replacing the version kept before.
+    pub fn record_upload(&self, key: &str, wasm_bytes: &[u8]) {
+        let path = self.upload_path(key);
+        if let Err(e) = write_atomically(&path, wasm_bytes) {
+            tracing::debug!(path = %path.display(), error = %e, "failed to save the last upload");
+        }
+    }
+
+    /// Where the wasm last uploaded under the delta key is kept, named by the key's hash.
+    fn upload_path(&self, key: &str) -> PathBuf {
+        self.path.with_file_name("uploads").join(format!("{}.wasm", blake3::hash(key.as_bytes()).to_hex()))
+    }
+
+    fn load(&self) -> Entries {
+        match fs::read(&self.path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                tracing::debug!(path = %self.path.display(), error = %e, "ignoring malformed known modules file");
+                Entries::new()
+            }),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    tracing::debug!(path = %self.path.display(), error = %e, "failed to read known modules file");
+                }
+                Entries::new()
+            },
+        }
+    }
+
+    /// Applies the change to the file, dropping expired entries. Updates from other clients on
+    /// the machine wait on a lock file next to it, so none of their changes are lost.
+    fn update(&self, change: impl FnOnce(&mut Entries)) {
+        let _lock = match self.lock() {
+            Ok(lock) => lock,
+            Err(e) => {
+                tracing::debug!(path = %self.path.display(), error = %e, "failed to lock known modules file");
+                return;
+            },
+        };
+        let mut entries = self.load();
+        change(&mut entries);
+        let now_ms = now_ms();
+        let ttl_ms = self.ttl.as_millis() as u64;
+        for modules in entries.values_mut() {
+            modules.retain(|_, confirmed_ms| now_ms.saturating_sub(*confirmed_ms) < ttl_ms);
+        }
+        entries.retain(|_, modules| !modules.is_empty());
+
+        let contents = serde_json::to_vec(&entries).map_err(io::Error::from);
+        if let Err(e) = contents.and_then(|contents| write_atomically(&self.path, &contents)) {
+            tracing::debug!(path = %self.path.display(), error = %e, "failed to save known modules file");
+        }
+    }
+
+    /// Takes the exclusive lock on the file, held until the returned file is dropped.
+    fn lock(&self) -> io::Result<File> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let lock = File::options().create(true).truncate(false).write(true).open(self.path.with_extension("lock"))?;
+        lock.lock()?;
+        Ok(lock)
+    }
+}
+
+/// Replaces the file by renaming a temporary file over it, so readers never see a partial
+/// write. Each writer uses its own temporary file.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension(format!("{:016x}.tmp", rand::random::<u64>()));
+    let result = File::create(&tmp_path)
+        .and_then(|mut tmp| tmp.write_all(contents))
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORCHESTRATOR: &str = "http://127.0.0.1:50051";
+
+    fn known(ttl: Duration) -> KnownModules {
+        let dir = std::env::temp_dir().join(format!("mini-lambda-known-modules-{:016x}", rand::random::<u64>()));
+        KnownModules::new(dir.join("known.json"), ttl)
+    }
+
+    #[test]
+    fn confirmed_modules_are_fresh_until_invalidated() {
+        let known = known(Duration::from_secs(600));
+        assert!(!known.is_fresh(ORCHESTRATOR, b"module"));
+
+        known.confirm(ORCHESTRATOR, b"module");
+        assert!(known.is_fresh(ORCHESTRATOR, b"module"));
+        assert!(!known.is_fresh("http://127.0.0.1:50052", b"module"));
+
+        known.invalidate(ORCHESTRATOR, b"module");
+        assert!(!known.is_fresh(ORCHESTRATOR, b"module"));
+    }
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let known = known(Duration::ZERO);
+        known.confirm(ORCHESTRATOR, b"module");
+        assert!(!known.is_fresh(ORCHESTRATOR, b"module"));
+    }
+
+    #[test]
+    fn concurrent_updates_are_all_kept() {
+        let known = known(Duration::from_secs(600));
+        std::thread::scope(|scope| {
+            for writer in 0..8u8 {
+                let known = known.clone();
+                scope.spawn(move || {
+                    for module in 0..16u8 {
+                        known.confirm(ORCHESTRATOR, &[writer, module]);
+                    }
+                });
+            }
+        });
+        for writer in 0..8u8 {
+            for module in 0..16u8 {
+                assert!(known.is_fresh(ORCHESTRATOR, &[writer, module]), "lost module {writer}/{module}");
+            }
+        }
+    }
+}
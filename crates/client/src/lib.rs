@@ -1,5 +1,42 @@
+//! Client library for submitting wasm jobs to a mini-lambda orchestrator.
+//!
+//! Connect a Client to an orchestrator, build a Job, and submit it. The returned RunningJob
+//! can be awaited for the job's output or cancelled. The `cli` binary is a thin wrapper over
+//! this library.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use client::{Client, Job};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let client = Client::connect("http://127.0.0.1:50051", None, false).await?;
+//! let job = Job::from_path("fib.wasm")?
+//!     .arg("25")
+//!     .timeout(Duration::from_secs(10));
+//! let output = client.submit_job(job).wait().await?;
+//! println!("{output}");
+//! # Ok(())
+//! # }
+//! ```
+
 mod client;
 mod job;
+mod known_modules;
+mod pipeline;
+mod retry;
+mod status;
+mod tls;
 
-pub use client::Client;
-pub use job::{Job, JobOutput, RunningJob, JobError};
\ No newline at end of file
+pub use client::{Client, ClientError};
+pub use job::{CacheMode, Job, JobOutput, JobTiming, ModuleDefaults, ModuleSource, PublishedModule, RunningJob, JobError};
+pub use known_modules::KnownModules;
+pub use pipeline::{Pipeline, PipelineOutput, StageFailure, StageOutput};
+pub use status::{CachedModule, DailyUsage, JobListing, JobPage, JobQuery, JobStatus, JobSubmission, Plan, PlanSpec, PlannedWorker, QueueProgress, QueueStats, QuotaUsage, Quotas, SubmittedModule, Usage, WarmOutcome, WarmResult, WorkerStatus};
+pub use tls::TlsConfig;
+pub use shared::{CostWeights, ErrorCode, HashAlgorithm, JobId};
+pub use shared::digest::{DigestError, ModuleDigest};
+pub use shared::events::{JobEvent, JobRecord};
+pub use shared::inspect::{ModuleKind, ModuleMetadata, ModuleReport, inspect_module};
+pub use shared::limits::{JobLimits, LimitError};
+pub use shared::signing::JobSigner;
+pub use uuid::Uuid;
\ No newline at end of file
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use tonic::{Code, Status};
+
+use shared::{ErrorCode, JobId, PipelineStage};
+
+use crate::job::{Job, JobError, ModuleSource};
+
+/// Jobs run one after another, each reading the stdout of the one before on its stdin, see
+/// Client::run_pipeline. The orchestrator runs the stages itself, so the client only waits for
+/// the last one's output.
+#[derive(Default)]
+pub struct Pipeline {
+    pub(crate) stages: Vec<Job>,
+    pub(crate) stdin: Vec<u8>,
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a stage that runs the job's module with its args. The job's other settings, such
+    /// as its timeout, retries or cache mode, don't apply to a stage.
+    pub fn stage(mut self, job: Job) -> Self {
+        self.stages.push(job);
+        self
+    }
+
+    /// What the first stage reads on its stdin, nothing by default.
+    pub fn stdin(mut self, stdin: Vec<u8>) -> Self {
+        self.stdin = stdin;
+        self
+    }
+
+    /// Bounds the whole pipeline, from when it is submitted until its last stage finishes.
+    pub fn timeout(mut self, duration: Duration) -> Self {
+        self.timeout = Some(duration);
+        self
+    }
+
+    pub(crate) fn proto_stages(&self) -> Vec<PipelineStage> {
+        self.stages.iter().map(|job| {
+            let wasm_hash = match (&job.module_source, job.known_hash) {
+                (Some(ModuleSource::ObjectStore { digest, .. }), _) => digest.bytes.to_vec(),
+                (None, Some(hash)) => hash.to_vec(),
+                (None, None) => blake3::hash(&job.wasm_bytes).as_bytes().to_vec(),
+            };
+            PipelineStage {
+                wasm_bytes: job.wasm_bytes.clone(),
+                wasm_hash,
+                module_source: job.module_source.as_ref().map(Into::into),
+                args: job.args.clone(),
+            }
+        }).collect()
+    }
+}
+
+/// The output of a pipeline, see Client::run_pipeline.
+#[derive(Clone, Debug)]
+pub struct PipelineOutput {
+    /// The last stage's stdout, or empty if a stage failed.
+    pub stdout: Vec<u8>,
+    /// How each stage that finished went, in order.
+    pub stages: Vec<StageOutput>,
+    /// The stage that failed, which stopped the pipeline, if any did.
+    pub failure: Option<StageFailure>,
+}
+
+/// How a finished stage's job went.
+#[derive(Clone, Debug)]
+pub struct StageOutput {
+    pub job_id: JobId,
+    pub worker_address: String,
+    pub stderr: Vec<u8>,
+    /// The size of the stage's stdout, which the next stage read.
+    pub stdout_bytes: u64,
+    /// Zero if the worker already had the module compiled.
+    pub compile: Duration,
+    pub execute: Duration,
+    pub cache_hit: bool,
+    pub estimated_cost: f64,
+}
+
+/// The stage, counted from 0, that stopped a pipeline, and why.
+#[derive(Clone, Debug)]
+pub struct StageFailure {
+    pub stage: u32,
+    pub job_id: JobId,
+    pub error: JobError,
+}
+
+impl PipelineOutput {
+    /// None if the orchestrator sent a malformed job id.
+    pub(crate) fn from_proto(response: shared::PipelineResponse) -> Option<Self> {
+        Some(Self {
+            stdout: response.stdout,
+            stages: response.stages.into_iter().map(StageOutput::from_proto).collect::<Option<_>>()?,
+            failure: match response.failure {
+                Some(failure) => Some(StageFailure::from_proto(failure)?),
+                None => None,
+            },
+        })
+    }
+}
+
+impl StageOutput {
+    fn from_proto(result: shared::StageResult) -> Option<Self> {
+        Some(Self {
+            job_id: JobId::from_bytes(&result.job_id).ok()?,
+            worker_address: result.worker_address,
+            stderr: result.stderr,
+            stdout_bytes: result.stdout_bytes,
+            compile: Duration::from_millis(result.compile_ms),
+            execute: Duration::from_millis(result.execute_ms),
+            cache_hit: result.cache_hit,
+            estimated_cost: result.estimated_cost,
+        })
+    }
+}
+
+impl StageFailure {
+    fn from_proto(failure: shared::StageFailure) -> Option<Self> {
+        // Rebuilt as the status the stage's job failed with, so it maps to a JobError as a
+        // job submitted alone would
+        let code = Code::from(failure.code);
+        let status = match failure.error_code.as_deref() {
+            Some(error_code) => ErrorCode::parse(error_code).status(code, failure.message),
+            None => Status::new(code, failure.message),
+        };
+        Some(Self {
+            stage: failure.stage,
+            job_id: JobId::from_bytes(&failure.job_id).ok()?,
+            error: JobError::from(status),
+        })
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tonic::{Code, Status};
+
+use shared::ErrorCode;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Returns how long to wait before the given retry (starting at 1): exponential backoff
+/// capped at MAX_BACKOFF, with full jitter so that many clients retrying at once spread out.
+pub(crate) fn backoff(retry: u32) -> Duration {
+    let exp = INITIAL_BACKOFF.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+    let cap = exp.min(MAX_BACKOFF);
+    cap.mul_f64(rand::random::<f64>())
+}
+
+/// Sleeps for the backoff before the given retry. Returns false if the job was cancelled
+/// while waiting.
+pub(crate) async fn wait_before_retry(retry: u32, cancel_token: &CancellationToken) -> bool {
+    let delay = backoff(retry);
+    tracing::debug!(retry, delay = ?delay, "backing off before retry");
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => true,
+        _ = cancel_token.cancelled() => false,
+    }
+}
+
+/// Returns true if a failed request_worker call is worth retrying: the orchestrator was
+/// unreachable, or a client quota or the queue was full and may free up as other jobs finish. A daily
+/// quota only frees up once the day ends, so isn't retried.
+pub(crate) fn is_transient_orchestrator_error(status: &Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::ResourceExhausted)
+        && ErrorCode::of(status) != Some(ErrorCode::DailyQuotaExceeded)
+}
+
+/// Returns true if an execution error was caused by the worker itself (it crashed, the
+/// connection dropped, or its execution task failed) rather than by the submitted wasm,
+/// meaning the job may succeed if retried on another worker. A worker that answers says why
+/// the job failed with an ErrorCode, so a job whose wasm failed to compile, trapped or exited
+/// non-zero is never retried. Without a code, the worker was never heard from.
+/// Note that tonic reports a connection dropped mid-request as Unknown. Workers that predate
+/// guest traps having their own code also reported them as Unknown, but with a code.
+pub(crate) fn is_worker_failure(status: &Status) -> bool {
+    match ErrorCode::of(status) {
+        Some(ErrorCode::CompileError | ErrorCode::ExecutionError | ErrorCode::StackOverflow) => false,
+        Some(_) => matches!(status.code(), Code::Unavailable | Code::Internal),
+        None => matches!(status.code(), Code::Unavailable | Code::Unknown | Code::Internal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guest_failures_are_not_retried() {
+        // As the worker reports a trap, a non-zero exit, a stack overflow and a compile error
+        let failures = [
+            ErrorCode::ExecutionError.status(Code::InvalidArgument, "wasm trapped: wasm `unreachable` instruction executed"),
+            ErrorCode::ExecutionError.status(Code::InvalidArgument, "wasm execution failed: exited with code 1"),
+            ErrorCode::StackOverflow.status(Code::InvalidArgument, "guest stack overflow (limit 512 KiB)"),
+            ErrorCode::CompileError.status(Code::InvalidArgument, "wasm compilation failed"),
+            // As workers that predate Trapped report a trap
+            ErrorCode::Internal.status(Code::Unknown, "an unknown error occurred: wasm trap"),
+        ];
+        for status in failures {
+            assert!(!is_worker_failure(&status), "{status:?} was retried");
+        }
+    }
+
+    #[test]
+    fn worker_failures_are_retried() {
+        let failures = [
+            Status::unknown("connection reset by peer"),
+            Status::unavailable("worker 127.0.0.1:7000 disconnected"),
+            ErrorCode::JobAborted.status(Code::Unavailable, "job aborted as the worker shut down"),
+            ErrorCode::Internal.status(Code::Internal, "execution task failed: job process exited with signal 9"),
+        ];
+        for status in failures {
+            assert!(is_worker_failure(&status), "{status:?} wasn't retried");
+        }
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        assert!(!is_worker_failure(&Status::invalid_argument("too many args")));
+        assert!(!is_worker_failure(&ErrorCode::JobCancelled.status(Code::Cancelled, "job cancelled by client")));
+        assert!(!is_worker_failure(&ErrorCode::JobTimedOut.status(Code::DeadlineExceeded, "job ran past its timeout")));
+    }
+
+    #[test]
+    fn daily_quotas_are_not_retried() {
+        assert!(is_transient_orchestrator_error(&ErrorCode::QuotaExceeded.status(Code::ResourceExhausted, "client quota exceeded: max_concurrent_jobs (limit 4)")));
+        assert!(!is_transient_orchestrator_error(&ErrorCode::DailyQuotaExceeded.status(Code::ResourceExhausted, "client quota for the day exceeded: max_jobs_per_day (limit 100)")));
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        for retry in 1..64 {
+            let delay = backoff(retry);
+            assert!(delay <= MAX_BACKOFF, "retry {retry} backed off {delay:?}");
+            assert!(delay <= INITIAL_BACKOFF.saturating_mul(2u32.saturating_pow(retry - 1)));
+        }
+    }
+}
@@ -0,0 +1,523 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use shared::{CostWeights, JobId, JobPlanSpec, JobStatusResponse, JobSummary, QueuePosition, QueueStatsResponse, QuotasResponse, UsageDay, UsageResponse, WorkerPlan, WorkerSummary, WorkerWarmResult};
+use shared::inspect::ModuleMetadata;
+use shared::limits::JobLimits;
+use shared::signing::stdin_digest;
+
+use crate::client::ClientError;
+use crate::job::{Job, ModuleSource};
+
+/// A snapshot of a submitted job's progress, as tracked by the orchestrator.
+#[derive(Clone, Debug)]
+pub struct JobStatus {
+    /// One of scheduled, queued, dispatched, compiling, executing, completed, failed, or cancelled,
+    /// or unknown-outcome for a job that was dispatched before the orchestrator restarted, or
+    /// presumed-lost for a job its worker didn't finish well after its timeout.
+    pub state: String,
+    pub worker_address: Option<String>,
+    pub queued_at: SystemTime,
+    pub completed_at: Option<SystemTime>,
+    /// How many times the job was resubmitted after its worker failed.
+    pub retries: u32,
+    /// How the worker a job was presumed lost on said it finished, if it did after all. It
+    /// doesn't change state, which may be the job's retry's by then.
+    pub late_state: Option<String>,
+    /// The job this one replays, if it was submitted as a replay.
+    pub replay_of: Option<JobId>,
+}
+
+impl JobStatus {
+    /// Whether the job will not change state again: it has finished, successfully or not, or
+    /// the orchestrator lost track of it in a restart or gave up on its worker.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self.state.as_str(), "completed" | "failed" | "cancelled" | "unknown-outcome" | "presumed-lost")
+    }
+}
+
+impl From<JobStatusResponse> for JobStatus {
+    fn from(response: JobStatusResponse) -> Self {
+        Self {
+            state: response.state,
+            worker_address: response.worker_address,
+            queued_at: UNIX_EPOCH + Duration::from_millis(response.queued_at_ms),
+            completed_at: response.completed_at_ms.map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+            retries: response.retries,
+            late_state: response.late_state,
+            replay_of: response.replay_of.and_then(|id| JobId::from_bytes(&id).ok()),
+        }
+    }
+}
+
+/// Which of the namespace's jobs Client::list_jobs lists. Unset fields match every job.
+#[derive(Clone, Debug, Default)]
+pub struct JobQuery {
+    /// One of the states in JobStatus::state.
+    pub state: Option<String>,
+    /// The worker the job was last dispatched to.
+    pub worker_address: Option<String>,
+    /// Only jobs queued at or after this time.
+    pub since: Option<SystemTime>,
+    /// The most jobs in a page, 100 if None. The orchestrator caps it at 500.
+    pub limit: Option<u32>,
+}
+
+/// A page of jobs listed by Client::list_jobs, newest first.
+#[derive(Clone, Debug)]
+pub struct JobPage {
+    pub jobs: Vec<JobListing>,
+    /// Pass to list_jobs for the next page; None on the last page. Jobs submitted while
+    /// paging don't shift the pages after the first.
+    pub next_page_token: Option<String>,
+}
+
+/// A job as listed by Client::list_jobs.
+#[derive(Clone, Debug)]
+pub struct JobListing {
+    pub job_id: JobId,
+    pub status: JobStatus,
+    pub scheduled_for: Option<SystemTime>,
+    /// When the job began executing.
+    pub started_at: Option<SystemTime>,
+    /// The client that submitted the job, as the orchestrator tells clients apart for fair
+    /// queueing and quotas: "token:<namespace>", or the host it connected from.
+    pub owner: Option<String>,
+}
+
+impl JobListing {
+    /// None if the orchestrator sent a malformed job_id.
+    pub(crate) fn from_summary(summary: JobSummary) -> Option<Self> {
+        let at = |ms: u64| UNIX_EPOCH + Duration::from_millis(ms);
+        Some(Self {
+            job_id: JobId::from_bytes(&summary.job_id).ok()?,
+            status: JobStatus {
+                state: summary.state,
+                worker_address: summary.worker_address,
+                queued_at: at(summary.queued_at_ms),
+                completed_at: summary.completed_at_ms.map(at),
+                retries: summary.retries,
+                late_state: summary.late_state,
+                replay_of: summary.replay_of.and_then(|id| JobId::from_bytes(&id).ok()),
+            },
+            scheduled_for: summary.scheduled_for_ms.map(at),
+            started_at: summary.started_at_ms.map(at),
+            owner: summary.owner,
+        })
+    }
+}
+
+/// What a job ran, as its worker reported it to the orchestrator, see Client::job_submission.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobSubmission {
+    pub job_id: JobId,
+    pub module: SubmittedModule,
+    pub args: Vec<String>,
+    /// The worker the job last ran on.
+    pub worker_address: Option<String>,
+    /// The SHA-256 digest of what the job read on its stdin, None if it read nothing.
+    pub stdin_sha256: Option<[u8; 32]>,
+    /// What the job read on its stdin, if the orchestrator still keeps it. It only keeps the
+    /// stdin it relays to pipeline stages, dropping the least recently used first.
+    pub stdin: Option<Vec<u8>>,
+}
+
+/// The module a job ran.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubmittedModule {
+    /// The blake3 hash of the wasm the client sent.
+    Hash([u8; 32]),
+    /// Where the worker fetched the module from.
+    Source(ModuleSource),
+}
+
+impl JobSubmission {
+    /// None if the orchestrator sent neither a well-formed hash nor a source this client knows,
+    /// or a malformed stdin digest.
+    pub(crate) fn from_proto(job_id: JobId, submission: shared::JobSubmission) -> Option<Self> {
+        let module = match submission.module_source {
+            Some(source) => SubmittedModule::Source(ModuleSource::from_proto(source)?),
+            None => SubmittedModule::Hash(submission.wasm_hash.try_into().ok()?),
+        };
+        let stdin_sha256 = if submission.stdin_sha256.is_empty() { None } else { Some(submission.stdin_sha256.try_into().ok()?) };
+        Some(Self { job_id, module, args: submission.args, worker_address: submission.worker_address, stdin_sha256, stdin: submission.stdin })
+    }
+
+    /// A job that runs the same module with the same args and stdin, recorded as a replay of
+    /// this one, reading the given stdin or else the copy the orchestrator keeps, see
+    /// replay_stdin. The orchestrator never has a job's wasm, so a module sent by hash runs only
+    /// on workers that still have it compiled, failing with JobError::ModuleNotCached
+    /// otherwise. Pin the job to the worker it ran on with Job::worker to replay it there.
+    pub fn replay(&self, stdin: Option<Vec<u8>>) -> Result<Job, ClientError> {
+        let job = match &self.module {
+            SubmittedModule::Hash(hash) => Job::from_hash(*hash),
+            SubmittedModule::Source(source) => Job::from_source(source.clone()),
+        };
+        Ok(job.args(&self.args).stdin(self.replay_stdin(stdin)?).replay_of(self.job_id))
+    }
+
+    /// The stdin a replay reads: the given stdin, which must be what the job read, failing with
+    /// ClientError::StdinMismatch otherwise, or else the copy the orchestrator keeps. Fails with
+    /// ClientError::StdinUnavailable if the job read stdin the orchestrator no longer keeps.
+    pub fn replay_stdin(&self, stdin: Option<Vec<u8>>) -> Result<Vec<u8>, ClientError> {
+        match (stdin, &self.stdin, self.stdin_sha256) {
+            (Some(stdin), _, stdin_sha256) => {
+                if (!stdin.is_empty()).then(|| stdin_digest(&stdin)) != stdin_sha256 {
+                    return Err(ClientError::StdinMismatch(format!("that is not the stdin job {} read", self.job_id)));
+                }
+                Ok(stdin)
+            },
+            (None, Some(kept), _) => Ok(kept.clone()),
+            (None, None, None) => Ok(Vec::new()),
+            (None, None, Some(_)) => Err(ClientError::StdinUnavailable(format!("job {} read stdin that the orchestrator no longer keeps", self.job_id))),
+        }
+    }
+}
+
+/// A snapshot of a worker known to the orchestrator.
+#[derive(Clone, Debug)]
+pub struct WorkerStatus {
+    pub address: String,
+    pub online: bool,
+    /// Quarantined workers are connected but receive no jobs, see the orchestrator's flap detection.
+    pub quarantined: bool,
+    /// Drained workers are connected but were taken out of dispatch by an operator.
+    pub drained: bool,
+    /// Validating workers are connected but receive no jobs until they answer the
+    /// orchestrator's health probes.
+    pub validating: bool,
+    pub jobs_received: u32,
+    pub total_job_time: Duration,
+    /// Jobs received that had to compile their module first, see JobTiming::cold_start.
+    pub cold_starts: u32,
+    /// Time cold starts spent compiling.
+    pub total_compile_time: Duration,
+    pub flap_count: u32,
+    /// Job size limits the worker enforces; None for workers that don't report them.
+    pub limits: Option<JobLimits>,
+    /// Whether the worker only runs jobs signed by a key it trusts.
+    pub signed_jobs_only: bool,
+    pub version: Option<String>,
+    /// Set if the worker only runs jobs from this namespace.
+    pub namespace: Option<String>,
+    /// Set for load-aware workers: their host's last load sample, from 0 to 1.
+    pub host_load: Option<f32>,
+    /// Set for load-aware workers: the credits they advertise at their last host load sample.
+    pub load_credits: Option<u32>,
+    /// The jobs the worker said it was running in its last heartbeat, whoever dispatched them.
+    pub running_jobs: Option<u32>,
+    /// The jobs the orchestrator's credit count had the worker running at its last heartbeat.
+    /// Differing from running_jobs means the count has drifted.
+    pub estimated_running_jobs: Option<u32>,
+    /// Jobs the worker started that other orchestrators dispatched, since its first heartbeat.
+    pub out_of_band_jobs: u64,
+    /// Credits the orchestrator holds back from the worker while it is busy with those.
+    pub reserved_credits: u32,
+    /// Moving average of the intervals between the worker's heartbeats arriving.
+    pub heartbeat_interval: Option<Duration>,
+    /// Standard deviation of those intervals.
+    pub heartbeat_jitter: Option<Duration>,
+    /// How far the worker's clock is behind the orchestrator's, in ms, negative if ahead.
+    /// Includes the heartbeats' transit time.
+    pub clock_skew_ms: Option<i64>,
+    /// Whether the heartbeat intervals drift far from the worker's, or its clock is far off.
+    pub heartbeat_anomaly: bool,
+}
+
+impl From<WorkerSummary> for WorkerStatus {
+    fn from(summary: WorkerSummary) -> Self {
+        Self {
+            address: summary.address,
+            online: summary.online,
+            quarantined: summary.quarantined,
+            drained: summary.drained,
+            validating: summary.validating,
+            jobs_received: summary.jobs_received,
+            total_job_time: Duration::from_millis(summary.total_job_time_ms),
+            cold_starts: summary.cold_starts,
+            total_compile_time: Duration::from_millis(summary.total_compile_time_ms),
+            flap_count: summary.flap_count,
+            limits: summary.capabilities.as_ref().map(JobLimits::from),
+            signed_jobs_only: summary.capabilities.as_ref().is_some_and(|c| c.signed_jobs_only),
+            version: summary.capabilities.map(|c| c.version),
+            namespace: summary.namespace,
+            host_load: summary.host_load,
+            load_credits: summary.load_credits,
+            running_jobs: summary.running_jobs,
+            estimated_running_jobs: summary.estimated_running_jobs,
+            out_of_band_jobs: summary.out_of_band_jobs,
+            reserved_credits: summary.reserved_credits,
+            heartbeat_interval: summary.heartbeat_interval_ms.map(Duration::from_millis),
+            heartbeat_jitter: summary.heartbeat_jitter_ms.map(Duration::from_millis),
+            clock_skew_ms: summary.clock_skew_ms,
+            heartbeat_anomaly: summary.heartbeat_anomaly,
+        }
+    }
+}
+
+/// How backed up the orchestrator's job queue is.
+#[derive(Clone, Debug)]
+pub struct QueueStats {
+    pub queued_jobs: u64,
+    /// Total job slots of the connected workers.
+    pub advertised_credits: u64,
+    pub dispatches_last_minute: u64,
+    /// How long a job submitted now can expect to wait for a worker, from the last minute's
+    /// dispatch rate. None if jobs are queued but none were dispatched in the last minute.
+    pub estimated_wait: Option<Duration>,
+}
+
+impl From<QueueStatsResponse> for QueueStats {
+    fn from(response: QueueStatsResponse) -> Self {
+        Self {
+            queued_jobs: response.queued_jobs,
+            advertised_credits: response.advertised_credits,
+            dispatches_last_minute: response.dispatches_last_minute,
+            estimated_wait: response.estimated_wait_ms.map(Duration::from_millis),
+        }
+    }
+}
+
+/// A batch of hypothetical jobs to plan, see Client::plan.
+#[derive(Clone, Debug)]
+pub struct PlanSpec {
+    /// The blake3 hash of the jobs' wasm, or None for a module no worker is known to have.
+    pub wasm_hash: Option<[u8; 32]>,
+    pub count: u32,
+}
+
+impl From<&PlanSpec> for JobPlanSpec {
+    fn from(spec: &PlanSpec) -> Self {
+        Self { wasm_hash: spec.wasm_hash.map(|hash| hash.to_vec()), count: spec.count }
+    }
+}
+
+/// How a PlanSpec's jobs would be scheduled if they were submitted now.
+#[derive(Clone, Debug)]
+pub struct Plan {
+    /// Jobs that would go to a worker right away.
+    pub dispatched: u32,
+    /// The workers they would go to.
+    pub workers: Vec<PlannedWorker>,
+    /// Jobs that would queue for a worker.
+    pub queued: u32,
+    /// How long the last queued job can expect to wait for a worker, from the last minute's
+    /// dispatch rate. None if jobs would queue but none were dispatched in the last minute.
+    pub estimated_wait: Option<Duration>,
+}
+
+impl From<shared::JobPlan> for Plan {
+    fn from(plan: shared::JobPlan) -> Self {
+        Self {
+            dispatched: plan.dispatched,
+            workers: plan.workers.into_iter().map(PlannedWorker::from).collect(),
+            queued: plan.queued,
+            estimated_wait: plan.estimated_wait_ms.map(Duration::from_millis),
+        }
+    }
+}
+
+/// A worker that would be dispatched some of a PlanSpec's jobs.
+#[derive(Clone, Debug)]
+pub struct PlannedWorker {
+    pub address: String,
+    pub jobs: u32,
+    /// How many of the jobs would go to it because it has their module compiled.
+    pub module_cached: u32,
+}
+
+impl From<WorkerPlan> for PlannedWorker {
+    fn from(worker: WorkerPlan) -> Self {
+        Self { address: worker.worker_address, jobs: worker.jobs, module_cached: worker.module_cached }
+    }
+}
+
+/// What a namespace's jobs used and cost over recent days, see Client::usage.
+#[derive(Clone, Debug)]
+pub struct Usage {
+    /// Each day the namespace ran jobs, oldest first.
+    pub days: Vec<DailyUsage>,
+    /// The days summed, with an empty date.
+    pub total: DailyUsage,
+    /// The weights the costs were estimated with.
+    pub cost_weights: CostWeights,
+}
+
+/// What a namespace's jobs used over a UTC day. Jobs count on the day they finished.
+#[derive(Clone, Debug, Default)]
+pub struct DailyUsage {
+    /// YYYY-MM-DD.
+    pub date: String,
+    pub jobs: u64,
+    pub execute: Duration,
+    /// Jobs whose module was compiled for them.
+    pub compiles: u64,
+    /// Wasm sent to workers.
+    pub module_bytes: u64,
+    pub cost: f64,
+}
+
+impl From<UsageDay> for DailyUsage {
+    fn from(day: UsageDay) -> Self {
+        Self {
+            date: day.date,
+            jobs: day.jobs,
+            execute: Duration::from_millis(day.execute_ms),
+            compiles: day.compiles,
+            module_bytes: day.module_bytes,
+            cost: day.cost,
+        }
+    }
+}
+
+impl From<UsageResponse> for Usage {
+    fn from(response: UsageResponse) -> Self {
+        Self {
+            days: response.days.into_iter().map(DailyUsage::from).collect(),
+            total: response.total.map(DailyUsage::from).unwrap_or_default(),
+            cost_weights: response.cost_weights.unwrap_or_default(),
+        }
+    }
+}
+
+/// The orchestrator's client quotas, and how much of them each client is using, see
+/// Client::quotas. Limits that aren't enforced are None.
+#[derive(Clone, Debug, Default)]
+pub struct Quotas {
+    pub max_queued_jobs: Option<u32>,
+    pub max_concurrent_jobs: Option<u32>,
+    pub max_jobs_per_day: Option<u32>,
+    pub max_module_bytes_per_day: Option<u64>,
+    /// Each client with jobs in flight or that used a quota today.
+    pub clients: Vec<QuotaUsage>,
+}
+
+/// How much of the quotas a client is using.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuotaUsage {
+    /// "token:" and the namespace of the client's token, or else the IP address it connects from.
+    pub client: String,
+    pub queued_jobs: u32,
+    /// Jobs dispatched to workers and not yet finished.
+    pub active_jobs: u32,
+    /// Jobs submitted on the current UTC day.
+    pub jobs_today: u32,
+    /// Module bytes sent to workers by jobs that finished on the current UTC day.
+    pub module_bytes_today: u64,
+}
+
+impl From<QuotasResponse> for Quotas {
+    fn from(response: QuotasResponse) -> Self {
+        Self {
+            max_queued_jobs: response.max_queued_jobs,
+            max_concurrent_jobs: response.max_concurrent_jobs,
+            max_jobs_per_day: response.max_jobs_per_day,
+            max_module_bytes_per_day: response.max_module_bytes_per_day,
+            clients: response.clients.into_iter().map(|client| QuotaUsage {
+                client: client.client,
+                queued_jobs: client.queued_jobs,
+                active_jobs: client.active_jobs,
+                jobs_today: client.jobs_today,
+                module_bytes_today: client.module_bytes_today,
+            }).collect(),
+        }
+    }
+}
+
+/// A job's place in the orchestrator's queue while it waits for a worker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueProgress {
+    /// Counts from 1, the next job to be dispatched.
+    pub position: u64,
+    pub queued_jobs: u64,
+    /// How long the job can expect to wait from now, from the last minute's dispatch rate.
+    /// None if none were dispatched in the last minute.
+    pub estimated_wait: Option<Duration>,
+}
+
+impl From<QueuePosition> for QueueProgress {
+    fn from(position: QueuePosition) -> Self {
+        Self {
+            position: position.position,
+            queued_jobs: position.queued_jobs,
+            estimated_wait: position.estimated_wait_ms.map(Duration::from_millis),
+        }
+    }
+}
+
+/// How warming a module went on one worker.
+#[derive(Clone, Debug)]
+pub struct WarmResult {
+    pub worker_address: String,
+    pub outcome: WarmOutcome,
+    /// How long the worker took to compile the module, zero unless it was Compiled.
+    pub compile: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WarmOutcome {
+    /// The worker already had the module compiled.
+    Cached,
+    Compiled,
+    Failed(String),
+    /// The worker wasn't sent the module, because it is drained or quarantined.
+    Skipped(String),
+}
+
+impl From<WorkerWarmResult> for WarmResult {
+    fn from(result: WorkerWarmResult) -> Self {
+        let reason = result.reason.clone().unwrap_or_default();
+        let outcome = match result.outcome() {
+            shared::WarmOutcome::Cached => WarmOutcome::Cached,
+            shared::WarmOutcome::Compiled => WarmOutcome::Compiled,
+            shared::WarmOutcome::Skipped => WarmOutcome::Skipped(reason),
+            shared::WarmOutcome::Failed | shared::WarmOutcome::Unspecified => WarmOutcome::Failed(reason),
+        };
+        Self {
+            worker_address: result.worker_address,
+            outcome,
+            compile: Duration::from_millis(result.compile_ms),
+        }
+    }
+}
+
+impl WarmOutcome {
+    /// The outcome's name, as printed by the CLI.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WarmOutcome::Cached => "cached",
+            WarmOutcome::Compiled => "compiled",
+            WarmOutcome::Failed(_) => "failed",
+            WarmOutcome::Skipped(_) => "skipped",
+        }
+    }
+
+    /// Why the worker failed or was skipped.
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            WarmOutcome::Failed(reason) | WarmOutcome::Skipped(reason) => Some(reason),
+            WarmOutcome::Cached | WarmOutcome::Compiled => None,
+        }
+    }
+}
+
+/// A module in a worker's compiled module cache.
+#[derive(Clone, Debug)]
+pub struct CachedModule {
+    /// The module's blake3 hash.
+    pub wasm_hash: Vec<u8>,
+    /// What the worker read from the module's bytes as it cached it. None for modules handed
+    /// off to the worker by a peer, which arrive without their bytes.
+    pub metadata: Option<ModuleMetadata>,
+    /// When the module was compiled, or handed off to the worker.
+    pub compiled_at: SystemTime,
+}
+
+impl From<shared::CachedModule> for CachedModule {
+    fn from(module: shared::CachedModule) -> Self {
+        Self {
+            metadata: ModuleMetadata::from_cached_module(&module),
+            compiled_at: UNIX_EPOCH + Duration::from_millis(module.compiled_at_ms),
+            wasm_hash: module.wasm_hash,
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use hyper_util::rt::TokioIo;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tonic::transport::{Channel, Endpoint, Uri};
+
+use crate::client::ClientError;
+
+/// TLS settings used when the orchestrator endpoint is an https:// URL, and for workers that
+/// registered a TLS proxy in front of them, which are verified against the same settings.
+///
+/// By default servers are verified against the platform's trusted roots.
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    ca_certs: Vec<PathBuf>,
+    client_identity: Option<(PathBuf, PathBuf)>,
+    insecure: bool,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also trust the CA certificate(s) in this PEM file. Can be called repeatedly, e.g. when
+    /// the orchestrator and workers are signed by different private CAs.
+    pub fn ca_cert(mut self, pem_path: impl Into<PathBuf>) -> Self {
+        self.ca_certs.push(pem_path.into());
+        self
+    }
+
+    /// Present this certificate chain and private key, both PEM files, for mutual TLS.
+    pub fn client_identity(mut self, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        self.client_identity = Some((cert_path.into(), key_path.into()));
+        self
+    }
+
+    /// Skip verifying server certificates entirely. Only for development.
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Loads the certificates and keys and builds the rustls configuration, so that
+    /// misconfiguration is reported up front rather than on the first connection.
+    pub(crate) fn build(&self) -> Result<Arc<ClientConfig>, ClientError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| ClientError::Tls(e.to_string()))?;
+
+        let builder = if self.insecure {
+            builder.dangerous().with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+        } else {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+            for path in &self.ca_certs {
+                let certs = CertificateDer::pem_file_iter(path)
+                    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| ClientError::Tls(format!("failed to read CA certificate {}: {e}", path.display())))?;
+                if certs.is_empty() {
+                    return Err(ClientError::Tls(format!("no certificates found in {}", path.display())));
+                }
+                for cert in certs {
+                    roots.add(cert)
+                        .map_err(|e| ClientError::Tls(format!("invalid CA certificate in {}: {e}", path.display())))?;
+                }
+            }
+            builder.with_root_certificates(roots)
+        };
+
+        let mut config = match &self.client_identity {
+            Some((cert_path, key_path)) => {
+                let certs = CertificateDer::pem_file_iter(cert_path)
+                    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| ClientError::Tls(format!("failed to read client certificate {}: {e}", cert_path.display())))?;
+                let key = PrivateKeyDer::from_pem_file(key_path)
+                    .map_err(|e| ClientError::Tls(format!("failed to read client key {}: {e}", key_path.display())))?;
+                builder.with_client_auth_cert(certs, key)
+                    .map_err(|e| ClientError::Tls(format!("client certificate {} does not match key {}: {e}", cert_path.display(), key_path.display())))?
+            },
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+/// Connects to the endpoint, over TLS with the given configuration if there is one.
+pub(crate) async fn connect(endpoint: Endpoint, tls: Option<&Arc<ClientConfig>>) -> Result<Channel, tonic::transport::Error> {
+    let Some(tls) = tls else {
+        return endpoint.connect().await;
+    };
+    let connector = TlsConnector::from(tls.clone());
+    endpoint.connect_with_connector(tower::service_fn(move |uri: Uri| {
+        let connector = connector.clone();
+        async move {
+            let host = uri.host().unwrap_or_default().trim_matches(['[', ']']).to_string();
+            let port = uri.port_u16().unwrap_or(443);
+            let server_name = ServerName::try_from(host.clone())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            let tcp = TcpStream::connect((host.as_str(), port)).await?;
+            let stream = connector.connect(server_name, tcp).await?;
+            Ok::<_, std::io::Error>(TokioIo::new(stream))
+        }
+    })).await
+}
+
+/// Accepts any server certificate, for --insecure.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -3,4 +3,7 @@ cd "$(dirname "$0")"
 cargo build --release --target wasm32-wasip2 --target-dir ./target
 cp ./target/wasm32-wasip2/release/fib.wasm ./fib.wasm
 cp ./target/wasm32-wasip2/release/sleep.wasm ./sleep.wasm
-cp ./target/wasm32-wasip2/release/http.wasm ./http.wasm
\ No newline at end of file
+cp ./target/wasm32-wasip2/release/http.wasm ./http.wasm
+cp ./target/wasm32-wasip2/release/seq.wasm ./seq.wasm
+cp ./target/wasm32-wasip2/release/sum.wasm ./sum.wasm
+cp ./target/wasm32-wasip2/release/echo.wasm ./echo.wasm
\ No newline at end of file
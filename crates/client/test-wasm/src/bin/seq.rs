@@ -0,0 +1,21 @@
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let n_str = if args.len() == 1 {
+        &args[0]
+    } else {
+        eprint!("Expected 1 argument: <n>");
+        std::process::exit(1);
+    };
+
+    let n: u64 = match n_str.parse() {
+        Ok(v) => v,
+        Err(_) => {
+            eprint!("invalid number: {}", n_str);
+            std::process::exit(1);
+        }
+    };
+
+    for i in 1..=n {
+        println!("{i}");
+    }
+}
@@ -0,0 +1,21 @@
+use std::io::Read;
+
+fn main() {
+    let mut input = String::new();
+    if let Err(e) = std::io::stdin().read_to_string(&mut input) {
+        eprint!("failed to read stdin: {e}");
+        std::process::exit(1);
+    }
+
+    let mut sum: u64 = 0;
+    for word in input.split_whitespace() {
+        match word.parse::<u64>() {
+            Ok(v) => sum += v,
+            Err(_) => {
+                eprint!("invalid number: {}", word);
+                std::process::exit(1);
+            }
+        }
+    }
+    print!("{sum}");
+}
@@ -0,0 +1,173 @@
+mod report;
+
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use client::{Client, Job};
+use tokio::task::JoinSet;
+use tokio::time::sleep_until;
+
+use crate::report::{Report, Sample};
+
+#[derive(Parser, Debug)]
+#[command(about = "Drive load against a mini-lambda cluster and report throughput and latency")]
+struct Args {
+    #[arg(help = "Path of the wasm module to submit")]
+    wasm_path: String,
+    #[arg(long, default_value = "http://127.0.0.1:50051", help = "Orchestrator URL")]
+    orchestrator: String,
+    #[arg(long, help = "Password to authenticate with the orchestrator")]
+    password: Option<String>,
+    #[arg(long = "arg", allow_hyphen_values = true, help = "Argument passed to every job, with {i} replaced by the request's sequence number (repeatable)")]
+    arg_templates: Vec<String>,
+    #[arg(long, conflicts_with = "concurrency", help = "Submit jobs at this many per second, regardless of how many are in flight")]
+    rps: Option<f64>,
+    #[arg(long, requires = "rps", help = "Ramp the submission rate linearly from --rps to this many per second over the run, to find the saturation point")]
+    ramp_to: Option<f64>,
+    #[arg(long, default_value_t = 4, help = "Keep this many jobs in flight, when --rps isn't set")]
+    concurrency: usize,
+    #[arg(long, default_value_t = 10, help = "Seconds to keep submitting jobs for. Jobs still in flight afterwards are waited for")]
+    duration: u64,
+    #[arg(long, help = "Cancel a job if it hasn't finished after this many seconds")]
+    timeout: Option<u64>,
+    #[arg(long, default_value_t = 0, help = "Retry transient failures up to this many times. Off by default so failures show in the report")]
+    max_retries: u32,
+    #[arg(long, default_value_t = 1, help = "Seconds per row of the over-time breakdown printed for --ramp-to")]
+    interval: u64,
+    #[arg(long, help = "Write one CSV row per request to this file")]
+    csv: Option<String>,
+    #[arg(long, help = "Print the report as JSON")]
+    json: bool,
+}
+
+/// How new jobs are paced.
+enum Load {
+    /// Open loop: submit at a rate (jobs per second) that moves linearly from start to end.
+    Rate { start: f64, end: f64 },
+    /// Closed loop: submit a new job whenever one finishes.
+    Concurrency(usize),
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    let load = match args.rps {
+        Some(start) => {
+            let end = args.ramp_to.unwrap_or(start);
+            if start <= 0.0 || end <= 0.0 {
+                eprintln!("--rps and --ramp-to must be greater than zero");
+                std::process::exit(1);
+            }
+            Load::Rate { start, end }
+        },
+        None => Load::Concurrency(args.concurrency.max(1)),
+    };
+    let wasm_bytes = std::fs::read(&args.wasm_path).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {e}", args.wasm_path);
+        std::process::exit(1);
+    });
+    let client = Client::connect(&args.orchestrator, args.password.clone(), false).await
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+
+    let duration = Duration::from_secs(args.duration);
+    let submitter = Submitter { client, wasm_bytes, args: &args, started_at: Instant::now() };
+    let samples = match load {
+        Load::Rate { start, end } => submitter.run_rate(start, end, duration).await,
+        Load::Concurrency(concurrency) => submitter.run_concurrency(concurrency, duration).await,
+    };
+    let report = Report::new(samples, duration, submitter.started_at.elapsed());
+
+    if let Some(path) = &args.csv {
+        std::fs::write(path, report.to_csv())
+            .unwrap_or_else(|e| panic!("failed to write {}: {}", path, e));
+    }
+    let interval = args.ramp_to.map(|_| Duration::from_secs(args.interval.max(1)));
+    report.print(interval, args.json);
+}
+
+/// Submits jobs and collects a Sample for each once it finishes.
+struct Submitter<'a> {
+    client: Client,
+    wasm_bytes: Vec<u8>,
+    args: &'a Args,
+    started_at: Instant,
+}
+
+impl Submitter<'_> {
+    /// Submits jobs at a rate moving linearly from start to end per second until the duration
+    /// is up, then waits for the jobs still in flight.
+    async fn run_rate(&self, start: f64, end: f64, duration: Duration) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        let mut in_flight = JoinSet::new();
+        let deadline = self.started_at + duration;
+        let mut next_send = self.started_at;
+        let mut seq = 0;
+
+        while next_send < deadline {
+            sleep_until(next_send.into()).await;
+            while let Some(joined) = in_flight.try_join_next().map(joined_sample) {
+                samples.push(joined);
+            }
+            self.submit(&mut in_flight, seq);
+            seq += 1;
+
+            let progress = next_send.duration_since(self.started_at).as_secs_f64() / duration.as_secs_f64();
+            let rate = start + (end - start) * progress;
+            next_send += Duration::from_secs_f64(1.0 / rate);
+        }
+
+        while let Some(joined) = in_flight.join_next().await.map(joined_sample) {
+            samples.push(joined);
+        }
+        samples
+    }
+
+    /// Keeps the given number of jobs in flight until the duration is up, then waits for the
+    /// rest to finish.
+    async fn run_concurrency(&self, concurrency: usize, duration: Duration) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        let mut in_flight = JoinSet::new();
+        let deadline = self.started_at + duration;
+        let mut seq = 0;
+
+        loop {
+            while in_flight.len() < concurrency && Instant::now() < deadline {
+                self.submit(&mut in_flight, seq);
+                seq += 1;
+            }
+            let Some(joined) = in_flight.join_next().await.map(joined_sample) else {
+                break;
+            };
+            samples.push(joined);
+        }
+        samples
+    }
+
+    /// Submits the job with the given sequence number, tracking it in in_flight.
+    fn submit(&self, in_flight: &mut JoinSet<Sample>, seq: usize) {
+        let args = self.args.arg_templates.iter().map(|t| t.replace("{i}", &seq.to_string()));
+        let mut job = Job::from_bytes(self.wasm_bytes.clone())
+            .args(args)
+            .max_retries(self.args.max_retries);
+        if let Some(timeout) = self.args.timeout {
+            job = job.timeout(Duration::from_secs(timeout));
+        }
+
+        let sent_at = self.started_at.elapsed();
+        let submitted_at = Instant::now();
+        let running_job = self.client.submit_job(job);
+        let job_id = running_job.job_id();
+        in_flight.spawn(async move {
+            let result = running_job.wait().await;
+            Sample { seq, job_id, sent_at, latency: submitted_at.elapsed(), error: result.err() }
+        });
+    }
+}
+
+fn joined_sample(joined: Result<Sample, tokio::task::JoinError>) -> Sample {
+    joined.unwrap_or_else(|e| panic!("load job task panicked: {}", e))
+}
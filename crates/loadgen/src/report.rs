@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use client::{JobError, JobId};
+use serde_json::{Value, json};
+
+/// The outcome of one submitted job.
+pub struct Sample {
+    pub seq: usize,
+    pub job_id: JobId,
+    /// When the job was submitted, relative to the start of the run.
+    pub sent_at: Duration,
+    /// From submission until the job finished, successfully or not.
+    pub latency: Duration,
+    pub error: Option<JobError>,
+}
+
+/// Aggregate results of a run.
+pub struct Report {
+    samples: Vec<Sample>,
+    /// How long jobs were submitted for.
+    duration: Duration,
+    /// From the start of the run until the last job finished.
+    wall_time: Duration,
+}
+
+/// Failures of one JobError kind.
+struct ErrorGroup<'a> {
+    count: usize,
+    /// The first such error, as an example.
+    example: &'a JobError,
+}
+
+struct LatencySummary {
+    min: Duration,
+    mean: Duration,
+    p50: Duration,
+    p90: Duration,
+    p99: Duration,
+    max: Duration,
+}
+
+impl Report {
+    pub fn new(mut samples: Vec<Sample>, duration: Duration, wall_time: Duration) -> Self {
+        samples.sort_by_key(|s| s.seq);
+        Self { samples, duration, wall_time }
+    }
+
+    fn succeeded(&self) -> usize {
+        self.samples.iter().filter(|s| s.error.is_none()).count()
+    }
+
+    /// Successful jobs per second of wall time.
+    fn achieved_rps(&self) -> f64 {
+        per_second(self.succeeded(), self.wall_time)
+    }
+
+    fn errors(&self) -> BTreeMap<&'static str, ErrorGroup<'_>> {
+        let mut errors = BTreeMap::new();
+        for error in self.samples.iter().filter_map(|s| s.error.as_ref()) {
+            errors.entry(error.kind())
+                .or_insert(ErrorGroup { count: 0, example: error })
+                .count += 1;
+        }
+        errors
+    }
+
+    /// Rows of (interval start, samples submitted in that interval).
+    fn intervals(&self, interval: Duration) -> Vec<(Duration, Vec<&Sample>)> {
+        let mut rows: BTreeMap<u128, Vec<&Sample>> = BTreeMap::new();
+        for sample in &self.samples {
+            rows.entry(sample.sent_at.as_millis() / interval.as_millis()).or_default().push(sample);
+        }
+        rows.into_iter()
+            .map(|(i, samples)| (interval * i as u32, samples))
+            .collect()
+    }
+
+    /// Renders one CSV row per job.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("seq,job_id,sent_at_ms,latency_ms,error_kind,error\n");
+        for s in &self.samples {
+            let (kind, error) = match &s.error {
+                Some(e) => (e.kind(), format!("\"{}\"", e.to_string().replace('"', "\"\""))),
+                None => ("", String::new()),
+            };
+            writeln!(csv, "{},{},{},{:.3},{},{}", s.seq, s.job_id, s.sent_at.as_millis(), ms(s.latency), kind, error)
+                .unwrap_or_else(|e| panic!("writing to a String can't fail: {e}"));
+        }
+        csv
+    }
+
+    /// Prints the summary, followed by a breakdown per interval of submission time if given.
+    pub fn print(&self, interval: Option<Duration>, as_json: bool) {
+        let succeeded = self.succeeded();
+        let failed = self.samples.len() - succeeded;
+        let latency = latency_summary(self.samples.iter());
+        let errors = self.errors();
+        let intervals = interval.map(|i| self.intervals(i)).unwrap_or_default();
+
+        if as_json {
+            let errors: BTreeMap<_, _> = errors.iter()
+                .map(|(kind, group)| (*kind, json!({ "count": group.count, "example": group.example.to_string() })))
+                .collect();
+            let intervals: Vec<Value> = intervals.iter().map(|(start, samples)| {
+                let ok = samples.iter().filter(|s| s.error.is_none()).count();
+                json!({
+                    "start_s": start.as_secs_f64(),
+                    "submitted": samples.len(),
+                    "succeeded": ok,
+                    "failed": samples.len() - ok,
+                    "latency": latency_summary(samples.iter().copied()).as_ref().map(latency_json),
+                })
+            }).collect();
+            println!("{}", json!({
+                "duration_s": self.duration.as_secs_f64(),
+                "wall_time_s": self.wall_time.as_secs_f64(),
+                "submitted": self.samples.len(),
+                "succeeded": succeeded,
+                "failed": failed,
+                "offered_rps": per_second(self.samples.len(), self.duration),
+                "achieved_rps": self.achieved_rps(),
+                "latency": latency.as_ref().map(latency_json),
+                "errors": errors,
+                "intervals": intervals,
+            }));
+            return;
+        }
+
+        println!("submitted:    {} over {:.1}s ({:.1}/s offered)", self.samples.len(), self.duration.as_secs_f64(), per_second(self.samples.len(), self.duration));
+        println!("succeeded:    {succeeded}");
+        println!("failed:       {failed}");
+        for (kind, group) in &errors {
+            println!("  {} × {kind}, e.g. {}", group.count, group.example);
+        }
+        println!("achieved:     {:.1} jobs/s over {:.1}s", self.achieved_rps(), self.wall_time.as_secs_f64());
+        if let Some(l) = &latency {
+            println!("latency:      min {:.1}ms  mean {:.1}ms  max {:.1}ms", ms(l.min), ms(l.mean), ms(l.max));
+            println!("              p50 {:.1}ms  p90 {:.1}ms  p99 {:.1}ms", ms(l.p50), ms(l.p90), ms(l.p99));
+        }
+
+        if intervals.is_empty() {
+            return;
+        }
+        println!();
+        println!("{:>8} {:>9} {:>9} {:>7} {:>9} {:>9}", "AT", "SUBMITTED", "SUCCEEDED", "FAILED", "P50", "P99");
+        for (start, samples) in &intervals {
+            let ok = samples.iter().filter(|s| s.error.is_none()).count();
+            let (p50, p99) = match latency_summary(samples.iter().copied()) {
+                Some(l) => (format!("{:.1}ms", ms(l.p50)), format!("{:.1}ms", ms(l.p99))),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            println!("{:>7.0}s {:>9} {:>9} {:>7} {:>9} {:>9}", start.as_secs_f64(), samples.len(), ok, samples.len() - ok, p50, p99);
+        }
+    }
+}
+
+/// Latency statistics over the successful samples, or None if there are none.
+fn latency_summary<'a>(samples: impl Iterator<Item = &'a Sample>) -> Option<LatencySummary> {
+    let mut latencies: Vec<Duration> = samples
+        .filter(|s| s.error.is_none())
+        .map(|s| s.latency)
+        .collect();
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort();
+    Some(LatencySummary {
+        min: latencies[0],
+        mean: latencies.iter().sum::<Duration>() / latencies.len() as u32,
+        p50: percentile(&latencies, 0.50),
+        p90: percentile(&latencies, 0.90),
+        p99: percentile(&latencies, 0.99),
+        max: latencies[latencies.len() - 1],
+    })
+}
+
+fn latency_json(l: &LatencySummary) -> Value {
+    json!({
+        "min_ms": ms(l.min),
+        "mean_ms": ms(l.mean),
+        "p50_ms": ms(l.p50),
+        "p90_ms": ms(l.p90),
+        "p99_ms": ms(l.p99),
+        "max_ms": ms(l.max),
+    })
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
+
+fn per_second(count: usize, time: Duration) -> f64 {
+    if time.is_zero() {
+        0.0
+    } else {
+        count as f64 / time.as_secs_f64()
+    }
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
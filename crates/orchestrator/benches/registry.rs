@@ -0,0 +1,26 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use orchestrator::Namespace;
+use orchestrator::registry::WorkerRegistry;
+
+/// One dispatch and the matching credit return, as when a job runs to completion, with the
+/// registry holding the given number of workers.
+fn dispatch_cycle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("registry_dispatch_cycle");
+    let namespace = Namespace::default();
+    for workers in [10, 100, 1000] {
+        let mut registry = WorkerRegistry::new();
+        for i in 0..workers {
+            registry.register_worker(format!("10.0.{}.{}:7000", i / 256, i % 256), 4, None);
+        }
+        group.bench_with_input(BenchmarkId::from_parameter(workers), &workers, |b, _| {
+            b.iter(|| {
+                let address = registry.get_worker(&namespace).expect("every worker has credits");
+                registry.update_credits(&address, 1);
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dispatch_cycle);
+criterion_main!(benches);
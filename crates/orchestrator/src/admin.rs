@@ -0,0 +1,43 @@
+use tonic::Status;
+
+use crate::errors::OrchestratorError;
+use crate::orchestrator::Orchestrator;
+
+// Operator actions on connected workers, triggered from the TUI.
+impl Orchestrator {
+    /// Stops dispatching jobs to a Worker. Jobs it is already running are unaffected.
+    pub async fn drain_worker(&self, worker_address: &str) -> Result<(), OrchestratorError> {
+        if !self.registry.lock().await.drain_worker(worker_address) {
+            return Err(OrchestratorError::WorkerNotFound(worker_address.to_string()));
+        }
+        self.diagnostics.handle_worker_drained(worker_address, true);
+        tracing::info!(worker = %worker_address, "worker drained");
+        Ok(())
+    }
+
+    /// Makes a drained Worker eligible for dispatch again, and dispatches any pending jobs.
+    pub async fn resume_worker(&self, worker_address: &str) -> Result<(), OrchestratorError> {
+        let mut queue = self.job_queue.lock().await;
+        let mut registry = self.registry.lock().await;
+        if !registry.resume_worker(worker_address) {
+            return Err(OrchestratorError::WorkerNotDrained(worker_address.to_string()));
+        }
+        self.diagnostics.handle_worker_drained(worker_address, false);
+        tracing::info!(worker = %worker_address, "worker resumed");
+        Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
+        Ok(())
+    }
+
+    /// Disconnects a Worker by ending its stream with an error, which shuts the worker down.
+    /// It is drained first so no jobs are dispatched to it in the meantime; it is deregistered
+    /// as usual once its stream closes.
+    pub async fn remove_worker(&self, worker_address: &str) -> Result<(), OrchestratorError> {
+        let tx = self.worker_streams.lock().await.remove(worker_address)
+            .ok_or_else(|| OrchestratorError::WorkerNotFound(worker_address.to_string()))?;
+        self.registry.lock().await.drain_worker(worker_address);
+        self.diagnostics.handle_worker_drained(worker_address, true);
+        tx.send(Err(Status::aborted("removed by the orchestrator's operator"))).await.ok();
+        tracing::warn!(worker = %worker_address, "worker removed by operator");
+        Ok(())
+    }
+}
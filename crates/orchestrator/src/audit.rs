@@ -0,0 +1,471 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::{Duration, Instant, SystemTime};
+
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use serde::Serialize;
+
+use shared::events::{JobEvent, JobRecord, epoch_ms};
+use shared::{JobDetails, JobId};
+
+use crate::journal::JournalEntry;
+
+/// Most records waiting to be written before new ones are dropped.
+const BUFFER_RECORDS: usize = 4096;
+/// Rotated logs kept, as path.1 (newest) to path.N.
+const ROTATED_LOGS: usize = 5;
+/// How long an unfinished job's record is kept without any news of the job, in case it was
+/// retired some way that no terminal event or forget reports.
+const PENDING_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Settings for the Orchestrator's audit log.
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    pub path: PathBuf,
+    /// The log is rotated whenever it grows past this size.
+    pub max_bytes: u64,
+    /// Record jobs' args. Otherwise only their count is recorded.
+    pub include_args: bool,
+}
+
+/// JSON-lines log of every job the Orchestrator handled: who submitted it, what it ran,
+/// where, when each stage happened, and how it ended. Each job's record is written once it
+/// finishes.
+///
+/// Records are written by a background thread so that a slow disk never holds up dispatch.
+/// If it falls too far behind, records are dropped and counted, and the count is written to
+/// the log once it catches up.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    tx: SyncSender<AuditRecord>,
+    include_args: bool,
+    /// Jobs that haven't finished yet, with what is known about them so far. Entries are removed
+    /// when the job finishes, when its request or worker goes away (see forget and
+    /// worker_disconnected), or after PENDING_TTL without news.
+    pending: Arc<DashMap<JobId, AuditRecord>>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, Serialize)]
+struct AuditRecord {
+    #[serde(flatten)]
+    record: JobRecord,
+    namespace: String,
+    client_address: String,
+    /// Hex module hash, see JobDetails.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    module_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    module_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arg_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<Vec<String>>,
+    /// When the job's latest event was recorded.
+    #[serde(skip)]
+    updated_at: Instant,
+}
+
+/// Written in place of records that were dropped.
+#[derive(Debug, Serialize)]
+struct DroppedRecords {
+    dropped_records: u64,
+    at_ms: u64,
+}
+
+impl AuditLog {
+    /// Opens the audit log at the configured path, appending to it if it exists, and starts
+    /// the thread that writes to it.
+    pub fn open(config: AuditConfig) -> io::Result<Self> {
+        let (audit_log, writer, rx) = Self::new(config)?;
+        std::thread::Builder::new()
+            .name("audit".to_string())
+            .spawn(move || writer.run(rx))?;
+        Ok(audit_log)
+    }
+
+    /// The audit log and the writer for its file, which writes nothing until it is run.
+    fn new(config: AuditConfig) -> io::Result<(Self, Writer, Receiver<AuditRecord>)> {
+        let file = OpenOptions::new().create(true).append(true).open(&config.path)?;
+        let size = file.metadata()?.len();
+        let (tx, rx) = mpsc::sync_channel(BUFFER_RECORDS);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = Writer { path: config.path, max_bytes: config.max_bytes, file: BufWriter::new(file), size, dropped: dropped.clone() };
+        Ok((Self { tx, include_args: config.include_args, pending: Arc::new(DashMap::new()), dropped }, writer, rx))
+    }
+
+    /// Records a job event, writing the job's record if the event finishes it. Who the job
+    /// belongs to is taken from its queued entry.
+    pub(crate) fn record(&self, entry: &JournalEntry) {
+        let event = &entry.event;
+        let job_id = event.job_id();
+        match self.pending.entry(job_id) {
+            Entry::Occupied(mut pending) => {
+                let pending = pending.get_mut();
+                pending.record.apply(event);
+                pending.updated_at = Instant::now();
+            },
+            Entry::Vacant(pending) => {
+                pending.insert(AuditRecord {
+                    record: JobRecord::new(event),
+                    namespace: entry.namespace.clone().unwrap_or_default(),
+                    client_address: entry.client_address.clone().unwrap_or_default(),
+                    module_hash: None,
+                    module_url: None,
+                    arg_count: None,
+                    args: None,
+                    updated_at: Instant::now(),
+                });
+            },
+        }
+
+        if event.is_terminal()
+            && let Some((_, record)) = self.pending.remove(&job_id)
+        {
+            self.write(record);
+        }
+    }
+
+    /// Records that a job started running on its worker, along with what it runs.
+    pub(crate) fn job_started(&self, job_id: JobId, details: &JobDetails) {
+        let Some(mut pending) = self.pending.get_mut(&job_id) else {
+            return;
+        };
+        if pending.record.started_at_ms.is_none() {
+            pending.record.apply(&JobEvent::Started { job_id, at_ms: epoch_ms(SystemTime::now()) });
+        }
+        pending.module_hash = Some(details.module_hash.iter().map(|b| format!("{b:02x}")).collect());
+        pending.module_url = details.module_url.clone();
+        pending.arg_count = Some(details.args.len());
+        pending.args = self.include_args.then(|| details.args.clone());
+        pending.updated_at = Instant::now();
+    }
+
+    /// Drops the record of a job that stopped without a terminal event, e.g. because its
+    /// client disconnected while it was queued.
+    pub(crate) fn forget(&self, job_id: JobId) {
+        self.pending.remove(&job_id);
+    }
+
+    /// Writes the records of jobs dispatched to a Worker that disconnected as they stand, since
+    /// it will never report them finished. A client that retries one starts a new record.
+    pub(crate) fn worker_disconnected(&self, worker_address: &str) {
+        let lost: Vec<JobId> = self.pending.iter()
+            .filter(|pending| pending.record.worker_address.as_deref() == Some(worker_address))
+            .map(|pending| *pending.key())
+            .collect();
+        for job_id in lost {
+            if let Some((_, record)) = self.pending.remove(&job_id) {
+                self.write(record);
+            }
+        }
+    }
+
+    /// Drops the records of jobs with no news for PENDING_TTL, unless they are scheduled to
+    /// start later.
+    fn expire(&self, now: Instant) {
+        let now_ms = epoch_ms(SystemTime::now());
+        self.pending.retain(|_, pending| {
+            now.duration_since(pending.updated_at) < PENDING_TTL
+                || pending.record.scheduled_for_ms.is_some_and(|ms| ms > now_ms)
+        });
+    }
+
+    /// Drops expired records for as long as the orchestrator runs.
+    pub(crate) async fn run_expiry(self) {
+        loop {
+            tokio::time::sleep(EXPIRY_INTERVAL).await;
+            self.expire(Instant::now());
+        }
+    }
+
+    fn write(&self, record: AuditRecord) {
+        match self.tx.try_send(record) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            },
+            Err(TrySendError::Disconnected(_)) => {
+                tracing::error!("ERROR: audit log writer exited, this should never happen");
+                std::process::exit(1);
+            },
+        }
+    }
+}
+
+/// RAII guard that drops a job's audit record if its request is dropped (client disconnected)
+/// before the job is dispatched or stops.
+pub(crate) struct AuditGuard {
+    audit_log: Option<AuditLog>,
+    job_id: JobId,
+    dispatched: bool,
+}
+
+impl AuditGuard {
+    pub(crate) fn new(audit_log: Option<AuditLog>, job_id: JobId) -> Self {
+        Self { audit_log, job_id, dispatched: false }
+    }
+
+    pub(crate) fn set_dispatched(&mut self) {
+        self.dispatched = true;
+    }
+}
+
+impl Drop for AuditGuard {
+    /// A job that stopped while queued was already written with its terminal event, so this
+    /// only drops the records of jobs whose requests went away.
+    fn drop(&mut self) {
+        if let (false, Some(audit_log)) = (self.dispatched, &self.audit_log) {
+            audit_log.forget(self.job_id);
+        }
+    }
+}
+
+/// The audit log's file, owned by its writer thread.
+struct Writer {
+    path: PathBuf,
+    max_bytes: u64,
+    file: BufWriter<File>,
+    size: u64,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Writer {
+    /// Writes records as they arrive, flushing whenever none are waiting.
+    fn run(mut self, rx: Receiver<AuditRecord>) {
+        while let Ok(record) = rx.recv() {
+            let mut result = self.append(&record);
+            while let Ok(record) = rx.try_recv() {
+                result = result.and_then(|_| self.append(&record));
+            }
+            let dropped = self.dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                tracing::warn!(dropped, "audit log fell behind, records were dropped");
+                result = result.and_then(|_| self.append(&DroppedRecords { dropped_records: dropped, at_ms: epoch_ms(SystemTime::now()) }));
+            }
+            if let Err(e) = result.and_then(|_| self.file.flush()) {
+                tracing::warn!(path = %self.path.display(), error = %e, "failed to write to the audit log");
+            }
+        }
+    }
+
+    fn append(&mut self, line: &impl Serialize) -> io::Result<()> {
+        if self.size >= self.max_bytes {
+            self.rotate()?;
+        }
+        let mut buf = serde_json::to_vec(line)?;
+        buf.push(b'\n');
+        self.file.write_all(&buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Moves the log to path.1, shifting older logs up and deleting the oldest, and starts a
+    /// new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for n in (1..ROTATED_LOGS).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        self.file = BufWriter::new(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        self.size = 0;
+        tracing::debug!(path = %self.path.display(), "audit log rotated");
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = OsString::from(path);
+    rotated.push(format!(".{n}"));
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::namespaces::Namespace;
+
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("mini-lambda-audit-{}.jsonl", JobId::random()))
+    }
+
+    fn config(path: &Path, max_bytes: u64) -> AuditConfig {
+        AuditConfig { path: path.to_path_buf(), max_bytes, include_args: false }
+    }
+
+    fn lines(path: &Path) -> Vec<Value> {
+        fs::read_to_string(path).unwrap_or_default().lines()
+            .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("malformed line {line:?}: {e}")))
+            .collect()
+    }
+
+    fn remove_logs(path: &Path) {
+        let _ = fs::remove_file(path);
+        for n in 1..=ROTATED_LOGS + 1 {
+            let _ = fs::remove_file(rotated_path(path, n));
+        }
+    }
+
+    fn queued(audit_log: &AuditLog, job_id: JobId) {
+        let event = JobEvent::Queued { job_id, at_ms: 1, scheduled_for_ms: None };
+        audit_log.record(&JournalEntry::queued(event, &Namespace::default(), "127.0.0.1:1234"));
+    }
+
+    fn dispatched(audit_log: &AuditLog, job_id: JobId, worker_address: &str) {
+        audit_log.record(&JournalEntry::new(JobEvent::Dispatched { job_id, worker_address: worker_address.to_string(), at_ms: 2 }));
+    }
+
+    fn completed(audit_log: &AuditLog, job_id: JobId) {
+        audit_log.record(&JournalEntry::new(JobEvent::Completed { job_id, at_ms: 3 }));
+    }
+
+    /// Runs the writer until every record sent so far is written.
+    fn drain(audit_log: AuditLog, writer: Writer, rx: Receiver<AuditRecord>) {
+        drop(audit_log);
+        writer.run(rx);
+    }
+
+    #[test]
+    fn finished_jobs_are_written_in_order() {
+        let path = temp_path();
+        let (audit_log, writer, rx) = AuditLog::new(config(&path, u64::MAX)).unwrap();
+        let job_ids: Vec<JobId> = (0..3).map(|_| JobId::random()).collect();
+        for &job_id in &job_ids {
+            queued(&audit_log, job_id);
+            dispatched(&audit_log, job_id, "10.0.0.1:7000");
+        }
+        for &job_id in job_ids.iter().rev() {
+            completed(&audit_log, job_id);
+        }
+        assert!(audit_log.pending.is_empty());
+        drain(audit_log, writer, rx);
+
+        let lines = lines(&path);
+        let written: Vec<&str> = lines.iter().map(|line| line["job_id"].as_str().unwrap()).collect();
+        let expected: Vec<String> = job_ids.iter().rev().map(|job_id| job_id.to_string()).collect();
+        assert_eq!(written, expected);
+        for line in &lines {
+            assert_eq!(line["state"], "completed");
+            assert_eq!(line["namespace"], "default");
+            assert_eq!(line["client_address"], "127.0.0.1:1234");
+            assert_eq!(line["worker_address"], "10.0.0.1:7000");
+            assert_eq!((&line["queued_at_ms"], &line["dispatched_at_ms"], &line["finished_at_ms"]), (&1.into(), &2.into(), &3.into()));
+        }
+        remove_logs(&path);
+    }
+
+    #[test]
+    fn records_past_the_buffer_are_counted_as_dropped() {
+        let path = temp_path();
+        let (audit_log, writer, rx) = AuditLog::new(config(&path, u64::MAX)).unwrap();
+        for _ in 0..BUFFER_RECORDS + 3 {
+            let job_id = JobId::random();
+            queued(&audit_log, job_id);
+            completed(&audit_log, job_id);
+        }
+        drain(audit_log, writer, rx);
+
+        let lines = lines(&path);
+        assert_eq!(lines.len(), BUFFER_RECORDS + 1);
+        assert!(lines[..BUFFER_RECORDS].iter().all(|line| line["state"] == "completed"));
+        assert_eq!(lines[BUFFER_RECORDS]["dropped_records"], 3);
+        remove_logs(&path);
+    }
+
+    #[test]
+    fn full_logs_are_rotated_keeping_the_newest() {
+        let path = temp_path();
+        let (audit_log, writer, rx) = AuditLog::new(config(&path, 1)).unwrap();
+        let job_ids: Vec<JobId> = (0..ROTATED_LOGS + 3).map(|_| JobId::random()).collect();
+        for &job_id in &job_ids {
+            queued(&audit_log, job_id);
+            completed(&audit_log, job_id);
+        }
+        drain(audit_log, writer, rx);
+
+        // Each record fills a log, so the current log holds the newest and path.N the Nth newest
+        let mut logs = vec![path.clone()];
+        logs.extend((1..=ROTATED_LOGS).map(|n| rotated_path(&path, n)));
+        for (log, job_id) in logs.iter().zip(job_ids.iter().rev()) {
+            let lines = lines(log);
+            assert_eq!(lines.len(), 1, "{}", log.display());
+            assert_eq!(lines[0]["job_id"], job_id.to_string());
+        }
+        assert!(!rotated_path(&path, ROTATED_LOGS + 1).exists());
+        remove_logs(&path);
+    }
+
+    #[test]
+    fn abandoned_jobs_are_forgotten() {
+        let path = temp_path();
+        let (audit_log, writer, rx) = AuditLog::new(config(&path, u64::MAX)).unwrap();
+        let job_id = JobId::random();
+        queued(&audit_log, job_id);
+        drop(AuditGuard::new(Some(audit_log.clone()), job_id));
+        assert!(audit_log.pending.is_empty());
+
+        let dispatched_job = JobId::random();
+        queued(&audit_log, dispatched_job);
+        let mut guard = AuditGuard::new(Some(audit_log.clone()), dispatched_job);
+        guard.set_dispatched();
+        drop(guard);
+        assert!(audit_log.pending.contains_key(&dispatched_job));
+
+        drain(audit_log, writer, rx);
+        assert!(lines(&path).is_empty());
+        remove_logs(&path);
+    }
+
+    #[test]
+    fn jobs_on_a_lost_worker_are_written_as_they_stand() {
+        let path = temp_path();
+        let (audit_log, writer, rx) = AuditLog::new(config(&path, u64::MAX)).unwrap();
+        let lost = JobId::random();
+        let elsewhere = JobId::random();
+        queued(&audit_log, lost);
+        dispatched(&audit_log, lost, "10.0.0.1:7000");
+        queued(&audit_log, elsewhere);
+        dispatched(&audit_log, elsewhere, "10.0.0.2:7000");
+
+        audit_log.worker_disconnected("10.0.0.1:7000");
+        assert_eq!(audit_log.pending.iter().map(|p| *p.key()).collect::<Vec<_>>(), [elsewhere]);
+        drain(audit_log, writer, rx);
+
+        let lines = lines(&path);
+        assert_eq!(lines.len(), 1);
+        assert_eq!((&lines[0]["job_id"], &lines[0]["state"]), (&lost.to_string().into(), &"dispatched".into()));
+        remove_logs(&path);
+    }
+
+    #[test]
+    fn stale_records_expire_unless_scheduled_for_later() {
+        let path = temp_path();
+        let (audit_log, _writer, _rx) = AuditLog::new(config(&path, u64::MAX)).unwrap();
+        let stale = JobId::random();
+        let scheduled = JobId::random();
+        queued(&audit_log, stale);
+        let later = epoch_ms(SystemTime::now() + 2 * PENDING_TTL);
+        audit_log.record(&JournalEntry::new(JobEvent::Queued { job_id: scheduled, at_ms: 1, scheduled_for_ms: Some(later) }));
+
+        audit_log.expire(Instant::now() + PENDING_TTL / 2);
+        assert_eq!(audit_log.pending.len(), 2);
+        audit_log.expire(Instant::now() + PENDING_TTL);
+        assert_eq!(audit_log.pending.iter().map(|p| *p.key()).collect::<Vec<_>>(), [scheduled]);
+        remove_logs(&path);
+    }
+}
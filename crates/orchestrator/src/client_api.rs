@@ -1,16 +1,47 @@
-use tokio::sync::oneshot;
-use tonic::{Request, Status, Response};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Instant, timeout_at};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request, Status, Response};
 
 use shared::client_api_server::ClientApi;
-use shared::{CancelJobRequest, CancelJobResponse, WorkerRequest, WorkerResponse};
-use uuid::Uuid;
+use shared::{CancelJobRequest, CancelJobResponse, ClientQuotaUsage, FetchOutputResponse, JobStatusRequest, JobStatusResponse, JobSubmission, JobSummary, ListJobsRequest, ListJobsResponse, ListModulesResponse, ListWorkerModulesRequest, ListWorkersRequest, ListWorkersResponse, PipelineRequest, PipelineResponse, PlanJobsRequest, PlanJobsResponse, PublishModuleRequest, PublishModuleResponse, QueuePosition, QueueStatsRequest, QueueStatsResponse, QuotasRequest, QuotasResponse, RelayedCancelRequest, RelayedFetchOutputRequest, RelayedJobRequest, ResolveModuleRequest, ResolveModuleResponse, UsageRequest, UsageResponse, ValidateModuleRequest, ValidateModuleResponse, WarmWorkersRequest, WarmWorkersResponse, WatchQueuePositionRequest, WorkerRequest, WorkerResponse, WorkerSummary};
+use shared::{JobResponse, module_source, relayed_call, relayed_reply};
+use shared::{ErrorCode, JobId};
+use shared::events::{JobEvent, epoch_ms};
+use shared::inspect::inspect_module;
+use shared::limits::{JobLimits, PIPELINE_MAX_STDIN_BYTES};
 
 use crate::orchestrator::Orchestrator;
+use crate::audit::AuditGuard;
+use crate::coalescing::LeaderGuard;
+use crate::diagnostics::{JobFilter, JobState};
 use crate::errors::OrchestratorError;
+use crate::identity::ClientIdentity;
+use crate::job_queue::QueueGuard;
+use crate::journal::JournalEntry;
+use crate::module_uploads::ModuleHash;
+use crate::namespaces::Namespace;
+use crate::quotas::QueuedJobGuard;
+use crate::relay::mismatched_reply;
+use crate::usage;
+use crate::warm::{DEFAULT_WARM_TIMEOUT, WarmModule};
+use crate::webhooks::CallbackGuard;
+use crate::worker_api::{coalesced_job_token, job_token};
+
+/// How often a queued job's position is sent to clients watching it.
+const QUEUE_POSITION_INTERVAL: Duration = Duration::from_secs(3);
+/// Days of usage returned when the request doesn't say.
+const DEFAULT_USAGE_DAYS: u32 = 31;
+/// Jobs in a page of ListJobs when the request doesn't say, and the most it may ask for.
+const DEFAULT_JOBS_PAGE: u32 = 100;
+const MAX_JOBS_PAGE: u32 = 500;
 
 /// Implementation of the CliApi service for the Orchestrator.
 #[tonic::async_trait]
 impl ClientApi for Orchestrator {
+    type WatchQueuePositionStream = ReceiverStream<Result<QueuePosition, Status>>;
 
     /// A function exposed by the Orchestrator for the client to call to request
     /// a worker be assigned to them to execute their job.
@@ -20,85 +51,732 @@ impl ClientApi for Orchestrator {
     ) -> Result<Response<WorkerResponse>, Status> {
 
         // Get client address
-        let client_address = request.remote_addr()
+        let remote_addr = request.remote_addr()
             .unwrap_or_else(|| {
                 tracing::error!("ERROR: couldn't read client address, this should never occur");
                 std::process::exit(1);
-            })
-            .to_string();
+            });
+        let client_address = remote_addr.to_string();
         self.diagnostics.handle_client_connected(&client_address);
+        let identity = ClientIdentity::of_request(&request, remote_addr);
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let response = self.assign_worker(&client_address, &identity, namespace, request.into_inner()).await?;
+        Ok(Response::new(response))
+    }
 
-        // Create the pending job
-        let job_id = Uuid::from_slice(&request.into_inner().job_id)
+    /// A function exposed by the Orchestrator for the Client to call
+    /// to cancel a job waiting in the Orchestrator queue.
+    /// If this job is in the Orchestrator queue or still waiting for its scheduled
+    /// start time, it will remove it.
+    /// Returns an error on invalid job id, or if the job is in another namespace.
+    async fn cancel_job(
+        &self,
+        request: Request<CancelJobRequest>
+    ) -> Result<Response<CancelJobResponse>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let job_id = JobId::from_bytes(&request.into_inner().job_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed job_id: {e}")))?;
+        self.check_job_namespace(&job_id, &namespace)?;
+
+        let cancelled = self.job_queue.lock().await.cancel(&job_id)
+            || self.delay_queue.lock().await.cancel(&job_id);
+        if cancelled {
+            tracing::debug!(job_id = %job_id, "job cancelled from queue");
+            self.diagnostics.handle_cancel_queued_job(job_id);
+            Ok(Response::new(CancelJobResponse {}))
+        } else {
+            tracing::debug!(job_id = %job_id, "cancel requested but job not in queue (may have been dispatched)");
+            Err(OrchestratorError::JobNotFound.into())
+        }
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to look up the
+    /// current state of a job. Returns NotFound if the job is unknown, or PermissionDenied if
+    /// it is in another namespace.
+    async fn get_job_status(
+        &self,
+        request: Request<JobStatusRequest>
+    ) -> Result<Response<JobStatusResponse>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let job_id = JobId::from_bytes(&request.into_inner().job_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed job_id: {e}")))?;
+
+        let job_info = self.diagnostics.jobs.get(&job_id)
+            .ok_or(OrchestratorError::JobNotFound)?;
+        if job_info.namespace != namespace {
+            return Err(OrchestratorError::JobInOtherNamespace.into());
+        }
+        Ok(Response::new(JobStatusResponse {
+            state: job_info.state.as_str().to_string(),
+            worker_address: job_info.worker_address.clone(),
+            queued_at_ms: epoch_ms(job_info.queued_at),
+            completed_at_ms: job_info.completed_at.map(epoch_ms),
+            retries: job_info.retries,
+            late_state: job_info.late_state.as_ref().map(|state| state.as_str().to_string()),
+            replay_of: job_info.replay_of.map(|original| original.to_bytes()),
+        }))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to look up what a job ran,
+    /// to replay it. Returns FailedPrecondition if the job never started executing.
+    async fn get_job_submission(
+        &self,
+        request: Request<JobStatusRequest>
+    ) -> Result<Response<JobSubmission>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let job_id = JobId::from_bytes(&request.into_inner().job_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed job_id: {e}")))?;
+        Ok(Response::new(self.job_submission(job_id, &namespace).await?))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to list the jobs in its
+    /// namespace, newest first, a page at a time. A page token names the last job on the page
+    /// before, see DiagnosticsStore::list_jobs and DiagnosticsStore::page_token.
+    async fn list_jobs(
+        &self,
+        request: Request<ListJobsRequest>
+    ) -> Result<Response<ListJobsResponse>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let request = request.into_inner();
+        let state = request.state.as_deref()
+            .map(|name| JobState::from_name(name).ok_or_else(|| Status::invalid_argument(format!("unknown job state '{name}'"))))
+            .transpose()?;
+        let after = request.page_token.as_deref()
+            .map(|token| self.diagnostics.page_after(token).ok_or_else(|| Status::invalid_argument("malformed page_token")))
+            .transpose()?;
+        let filter = JobFilter {
+            state,
+            namespace: Some(namespace),
+            worker_address: request.worker_address,
+            since: request.since_ms.map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+        };
+        let limit = match request.limit {
+            0 => DEFAULT_JOBS_PAGE,
+            limit => limit.min(MAX_JOBS_PAGE),
+        };
+
+        let (jobs, next) = self.diagnostics.list_jobs(&filter, after, limit as usize);
+        let items = jobs.into_iter()
+            .map(|j| JobSummary {
+                job_id: j.job_id.to_bytes(),
+                state: j.state.as_str().to_string(),
+                worker_address: j.worker_address,
+                scheduled_for_ms: j.scheduled_for.map(epoch_ms),
+                queued_at_ms: epoch_ms(j.queued_at),
+                started_at_ms: j.executing_at.map(epoch_ms),
+                completed_at_ms: j.completed_at.map(epoch_ms),
+                retries: j.retries,
+                late_state: j.late_state.as_ref().map(|state| state.as_str().to_string()),
+                replay_of: j.replay_of.map(|original| original.to_bytes()),
+                owner: j.owner.map(|owner| owner.to_string()),
+            })
+            .collect();
+        Ok(Response::new(ListJobsResponse { items, next_page_token: next.map(|record_id| self.diagnostics.page_token(record_id)) }))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to list the workers
+    /// it knows about, as shown in the TUI workers table. If the request names a namespace,
+    /// only workers that may run its jobs are listed.
+    async fn list_workers(
+        &self,
+        request: Request<ListWorkersRequest>
+    ) -> Result<Response<ListWorkersResponse>, Status> {
+        let namespace = request.into_inner().namespace.as_deref()
+            .map(Namespace::new)
+            .transpose()?;
+        let workers = self.diagnostics.workers.iter()
+            .filter(|w| namespace.as_ref().is_none_or(|namespace| namespace.may_run_on(w.namespace.as_ref())))
+            .map(|w| WorkerSummary {
+                address: w.address.clone(),
+                online: w.disconnected_at.is_none(),
+                quarantined: w.quarantined_until.is_some(),
+                drained: w.drained,
+                capabilities: w.capabilities.clone(),
+                jobs_received: w.jobs_received,
+                total_job_time_ms: w.total_job_time.as_millis() as u64,
+                cold_starts: w.cold_starts,
+                total_compile_time_ms: w.total_compile_time.as_millis() as u64,
+                flap_count: w.flap_count,
+                namespace: w.namespace.as_ref().map(|n| n.to_string()),
+                host_load: w.host_load,
+                load_credits: w.load_credits,
+                validating: w.validating,
+                running_jobs: w.running_jobs,
+                estimated_running_jobs: w.estimated_running_jobs,
+                out_of_band_jobs: w.out_of_band_jobs,
+                reserved_credits: w.reserved_credits,
+                heartbeat_interval_ms: w.heartbeat_stats.interval_mean_ms.map(|ms| ms.round() as u64),
+                heartbeat_jitter_ms: w.heartbeat_stats.interval_jitter().map(|jitter| jitter.as_millis() as u64),
+                clock_skew_ms: w.heartbeat_stats.clock_skew_ms.map(|ms| ms.round() as i64),
+                heartbeat_anomaly: w.heartbeat_stats.anomalous(),
+            })
+            .collect();
+        Ok(Response::new(ListWorkersResponse { workers }))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to point a name:tag
+    /// alias at a module in the object store, so that jobs can be submitted by the alias.
+    async fn publish_module(
+        &self,
+        request: Request<PublishModuleRequest>
+    ) -> Result<Response<PublishModuleResponse>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let request = request.into_inner();
+        let Some(module_source::Source::ObjectStore(module)) = request.module.and_then(|module| module.source) else {
+            return Err(Status::invalid_argument("only object store modules can be published"));
+        };
+        if !module.url.starts_with("s3://") {
+            return Err(Status::invalid_argument(format!("invalid module url {}: expected s3://bucket/key", module.url)));
+        }
+        let digest = module.module_digest().map_err(OrchestratorError::from)?;
+
+        let previous = self.module_aliases.lock().await
+            .publish(&namespace, &request.name, &request.tag, &module.url, digest, request.defaults.unwrap_or_default().into())?;
+        Ok(Response::new(PublishModuleResponse { previous }))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to look up the module
+    /// a name:tag alias points at.
+    async fn resolve_module(
+        &self,
+        request: Request<ResolveModuleRequest>
+    ) -> Result<Response<ResolveModuleResponse>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let request = request.into_inner();
+        let module_aliases = self.module_aliases.lock().await;
+        let published = module_aliases.resolve(&namespace, &request.name, &request.tag)?;
+        Ok(Response::new(ResolveModuleResponse {
+            module: Some(published.source()),
+            published_at_ms: published.published_at_ms,
+            defaults: Some((&published.defaults).into()),
+        }))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to check how backed up
+    /// the queue is before submitting. Served from gauges, so it takes no locks.
+    async fn get_queue_stats(
+        &self,
+        _request: Request<QueueStatsRequest>
+    ) -> Result<Response<QueueStatsResponse>, Status> {
+        let stats = self.queue_stats.snapshot(SystemTime::now());
+        Ok(Response::new(QueueStatsResponse {
+            queued_jobs: stats.queued_jobs,
+            advertised_credits: stats.advertised_credits,
+            dispatches_last_minute: stats.dispatches_last_minute,
+            estimated_wait_ms: stats.estimated_wait.map(|wait| wait.as_millis() as u64),
+        }))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to see how a batch of jobs
+    /// would be scheduled before submitting it. Nothing is dispatched or queued.
+    async fn plan_jobs(
+        &self,
+        request: Request<PlanJobsRequest>
+    ) -> Result<Response<PlanJobsResponse>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let plans = self.plan_jobs(&request.get_ref().specs, &namespace).await?;
+        Ok(Response::new(PlanJobsResponse { plans }))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to have workers compile
+    /// a module before jobs that run it arrive, without knowing the workers' addresses.
+    async fn warm_workers(
+        &self,
+        request: Request<WarmWorkersRequest>
+    ) -> Result<Response<WarmWorkersResponse>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let request = request.into_inner();
+        if request.wasm_bytes.is_empty() == request.module_source.is_none() {
+            return Err(Status::invalid_argument("exactly one of wasm_bytes and module_source must be set"));
+        }
+        let module = WarmModule { wasm_bytes: request.wasm_bytes, module_source: request.module_source };
+        let timeout = request.timeout_ms.map_or(DEFAULT_WARM_TIMEOUT, Duration::from_millis);
+        let results = self.warm_workers(module, &namespace, &request.worker_addresses, timeout).await;
+        Ok(Response::new(WarmWorkersResponse { results }))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to follow a job's place
+    /// in the queue while its request_worker call waits. Sends the position right away and then
+    /// every QUEUE_POSITION_INTERVAL, ending once the job leaves the queue. Scheduled jobs get
+    /// no updates until they enter the queue.
+    async fn watch_queue_position(
+        &self,
+        request: Request<WatchQueuePositionRequest>
+    ) -> Result<Response<Self::WatchQueuePositionStream>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let job_id = JobId::from_bytes(&request.into_inner().job_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed job_id: {e}")))?;
+        if !self.diagnostics.jobs.contains_key(&job_id) {
+            return Err(OrchestratorError::JobNotFound.into());
+        }
+        self.check_job_namespace(&job_id, &namespace)?;
+
+        let (tx, rx) = mpsc::channel(1);
+        let orchestrator = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(QUEUE_POSITION_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = tx.closed() => return,
+                }
+                let Some(position) = orchestrator.job_queue.lock().await.position_of(&job_id) else {
+                    let scheduled = orchestrator.diagnostics.jobs.get(&job_id).is_some_and(|j| j.state == JobState::Scheduled);
+                    if scheduled {
+                        continue;
+                    }
+                    return;
+                };
+                let now = SystemTime::now();
+                let update = QueuePosition {
+                    position: position as u64,
+                    queued_jobs: orchestrator.queue_stats.snapshot(now).queued_jobs.max(position as u64),
+                    estimated_wait_ms: orchestrator.queue_stats.estimated_wait_at(position as u64, now).map(|wait| wait.as_millis() as u64),
+                };
+                if tx.send(Ok(update)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to see what its
+    /// namespace's jobs used and cost on each recent day.
+    async fn get_usage(
+        &self,
+        request: Request<UsageRequest>
+    ) -> Result<Response<UsageResponse>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let days = request.into_inner().days.unwrap_or(DEFAULT_USAGE_DAYS);
+        let buckets = self.usage_ledger.lock().await.days(&namespace, days, SystemTime::now());
+        let total = usage::total(buckets.iter().map(|(_, bucket)| bucket));
+        Ok(Response::new(UsageResponse {
+            days: buckets.into_iter().map(|(date, bucket)| bucket.to_proto(date)).collect(),
+            total: Some(total.to_proto(String::new())),
+            cost_weights: Some(self.cost_weights),
+        }))
+    }
+
+    /// A function exposed by the Orchestrator for operators to see how much of the client
+    /// quotas each client is using. A client that authenticated with a token only sees its own.
+    async fn get_quotas(
+        &self,
+        request: Request<QuotasRequest>
+    ) -> Result<Response<QuotasResponse>, Status> {
+        let caller = request.extensions().get::<Namespace>().cloned().map(ClientIdentity::Token);
+        let mut quota_tracker = self.quota_tracker.lock().await;
+        let quotas = quota_tracker.quotas().clone();
+        let clients = quota_tracker.usage(SystemTime::now()).into_iter()
+            .filter(|(client, _)| caller.as_ref().is_none_or(|caller| caller == client))
+            .map(|(client, (usage, daily))| ClientQuotaUsage {
+                client: client.to_string(),
+                queued_jobs: usage.queued,
+                active_jobs: usage.active,
+                jobs_today: daily.jobs,
+                module_bytes_today: daily.module_bytes,
+            })
+            .collect();
+        Ok(Response::new(QuotasResponse {
+            max_queued_jobs: quotas.max_queued_jobs,
+            max_concurrent_jobs: quotas.max_concurrent_jobs,
+            max_jobs_per_day: quotas.max_jobs_per_day,
+            max_module_bytes_per_day: quotas.max_module_bytes_per_day,
+            clients,
+        }))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to run its job on a
+    /// Worker that accepts no connections. The call goes to the Worker over its stream, which
+    /// checks the job's token as it would a direct call.
+    async fn relay_execute_job(
+        &self,
+        request: Request<RelayedJobRequest>
+    ) -> Result<Response<JobResponse>, Status> {
+        let authenticated = request.extensions().get::<Namespace>().cloned();
+        let request = request.into_inner();
+        let job = request.job.ok_or_else(|| Status::invalid_argument("missing job"))?;
+        if let Some(namespace) = authenticated
+            && let Ok(job_id) = JobId::from_bytes(&job.job_id)
+        {
+            self.check_job_namespace(&job_id, &namespace)?;
+        }
+        match self.relay_call(&request.worker_address, request.jwt_token, relayed_call::Call::ExecuteJob(job)).await? {
+            relayed_reply::Reply::ExecuteJob(response) => Ok(Response::new(response)),
+            _ => Err(mismatched_reply()),
+        }
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to cancel its job running
+    /// on a Worker that accepts no connections.
+    async fn relay_cancel_job(
+        &self,
+        request: Request<RelayedCancelRequest>
+    ) -> Result<Response<CancelJobResponse>, Status> {
+        let requested = request.get_ref().cancel.as_ref().and_then(|cancel| cancel.namespace.clone());
+        let namespace = Namespace::of_request(&request, requested.as_deref())?;
+        let request = request.into_inner();
+        let cancel = request.cancel.ok_or_else(|| Status::invalid_argument("missing cancel"))?;
+        if let Ok(job_id) = JobId::from_bytes(&cancel.job_id) {
+            self.check_job_namespace(&job_id, &namespace)?;
+        }
+        match self.relay_call(&request.worker_address, request.jwt_token, relayed_call::Call::CancelJob(cancel)).await? {
+            relayed_reply::Reply::CancelJob(response) => Ok(Response::new(response)),
+            _ => Err(mismatched_reply()),
+        }
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to read part of its job's
+    /// output, which a Worker that accepts no connections stored rather than returned.
+    async fn relay_fetch_output(
+        &self,
+        request: Request<RelayedFetchOutputRequest>
+    ) -> Result<Response<FetchOutputResponse>, Status> {
+        let authenticated = request.extensions().get::<Namespace>().cloned();
+        let request = request.into_inner();
+        let fetch = request.fetch.ok_or_else(|| Status::invalid_argument("missing fetch"))?;
+        if let Some(namespace) = authenticated
+            && let Ok(job_id) = JobId::from_bytes(&fetch.job_id)
+        {
+            self.check_job_namespace(&job_id, &namespace)?;
+        }
+        match self.relay_call(&request.worker_address, request.jwt_token, relayed_call::Call::FetchOutput(fetch)).await? {
+            relayed_reply::Reply::FetchOutput(response) => Ok(Response::new(response)),
+            _ => Err(mismatched_reply()),
+        }
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to check a module is
+    /// something Workers can run. The module is only parsed, never compiled or run, so no
+    /// Worker or credit is involved.
+    async fn validate_module(
+        &self,
+        request: Request<ValidateModuleRequest>
+    ) -> Result<Response<ValidateModuleResponse>, Status> {
+        let wasm_bytes = request.into_inner().wasm_bytes;
+        JobLimits::default().check(wasm_bytes.len(), &[])?;
+        let report = tokio::task::spawn_blocking(move || inspect_module(&wasm_bytes)).await
             .unwrap_or_else(|e| {
-                tracing::error!(error = %e, "ERROR: received malformed job_id bytes from the client, this should never occur");
+                tracing::error!(error = %e, "ERROR: module validation task panicked, this should never happen");
+                std::process::exit(1);
+            });
+        tracing::debug!(kind = report.kind.as_str(), errors = report.errors.len(), warnings = report.warnings.len(), "module validated");
+        Ok(Response::new(report.into()))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to see which modules a
+    /// worker has compiled, with the metadata the worker read from each, for debugging which
+    /// module a job actually ran.
+    async fn list_worker_modules(
+        &self,
+        request: Request<ListWorkerModulesRequest>
+    ) -> Result<Response<ListModulesResponse>, Status> {
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let request = request.into_inner();
+        let response = self.list_worker_modules(&request.worker_address, &namespace, request.wasm_hash).await?;
+        Ok(Response::new(response))
+    }
+
+    /// A function exposed by the Orchestrator for the Client to call to run jobs one after
+    /// another, each reading the stdout of the one before. A stage that fails is reported in
+    /// the response, the request only fails if it is malformed.
+    async fn run_pipeline(
+        &self,
+        request: Request<PipelineRequest>
+    ) -> Result<Response<PipelineResponse>, Status> {
+        let remote_addr = request.remote_addr()
+            .unwrap_or_else(|| {
+                tracing::error!("ERROR: couldn't read client address, this should never occur");
                 std::process::exit(1);
             });
+        let client_address = remote_addr.to_string();
+        self.diagnostics.handle_client_connected(&client_address);
+        let identity = ClientIdentity::of_request(&request, remote_addr);
+        let namespace = Namespace::of_request(&request, request.get_ref().namespace.as_deref())?;
+        let request = request.into_inner();
+        shared::compat::check(request.protocol_version)?;
+
+        if request.stages.is_empty() {
+            return Err(Status::invalid_argument("a pipeline needs at least one stage"));
+        }
+        if let Some(index) = request.stages.iter().position(|stage| stage.wasm_bytes.is_empty() && stage.wasm_hash.is_empty() && stage.module_source.is_none()) {
+            return Err(Status::invalid_argument(format!("stage {index} has no module")));
+        }
+        if request.stdin.len() > PIPELINE_MAX_STDIN_BYTES {
+            return Err(ErrorCode::LimitExceeded.status(
+                Code::InvalidArgument,
+                format!("stdin is {} bytes, more than the {PIPELINE_MAX_STDIN_BYTES} a pipeline may read", request.stdin.len()),
+            ));
+        }
+        if request.timeout_ms == Some(0) {
+            return Err(OrchestratorError::DeadlinePassed.into());
+        }
+
+        tracing::info!(stages = request.stages.len(), namespace = %namespace, "pipeline request received");
+        Ok(Response::new(self.run_pipeline(&client_address, &identity, namespace, request).await))
+    }
+}
+
+impl Orchestrator {
+    /// Queues a job from the client at client_address for a worker, and waits until one is
+    /// assigned to it, as for RequestWorker. Pipelines queue each of their stages this way.
+    pub(crate) async fn assign_worker(&self, client_address: &str, identity: &ClientIdentity, namespace: Namespace, request: WorkerRequest) -> Result<WorkerResponse, Status> {
+        // Create the pending job
+        let job_id = JobId::from_bytes(&request.job_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed job_id: {e}")))?;
+
+        tracing::info!(job_id = %job_id, namespace = %namespace, "job request received");
+        shared::compat::check(request.protocol_version)?;
+        #[cfg(feature = "fault-injection")]
+        shared::faults::inject("dispatch").await?;
 
-        tracing::info!(job_id = %job_id, "job request received");
+        // A start time that has already passed is treated as no start time
+        let not_before = request.not_before_ms
+            .map(|ms| UNIX_EPOCH + Duration::from_millis(ms))
+            .filter(|t| *t > SystemTime::now());
+        // The deadline is relative to the request's arrival, so the client's clock doesn't matter
+        let deadline = request.timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+        let starts_too_late = not_before.zip(request.timeout_ms)
+            .is_some_and(|(fire_at, ms)| fire_at > SystemTime::now() + Duration::from_millis(ms));
+        if request.timeout_ms == Some(0) || starts_too_late {
+            tracing::info!(job_id = %job_id, "job rejected, its deadline passes before it could start");
+            return Err(OrchestratorError::DeadlinePassed.into());
+        }
 
-        let (tx, rx) = oneshot::channel();
+        let replay_of = request.replay_of.as_deref()
+            .map(JobId::from_bytes)
+            .transpose()
+            .map_err(|e| Status::invalid_argument(format!("malformed replay_of: {e}")))?;
+        if let Some(original) = replay_of {
+            self.check_job_namespace(&original, &namespace)?;
+        }
+        if request.worker_address.is_some() && (not_before.is_some() || request.coalesce_key.is_some()) {
+            return Err(Status::invalid_argument("a job pinned to a worker can't be scheduled or coalesced"));
+        }
 
-        tracing::debug!(job_id = %job_id, "job enqueued, waiting for worker");
+        // A resubmitted job's previous dispatch is over, whatever its Worker says
+        self.runtime_watchdog.lock().await.resubmitted(job_id);
 
-        // Add this job to the queue and dispatch pending jobs atomically
+        // Refuse jobs that would be queued while the queue is full
+        if let Some(limit) = self.max_queued_jobs
+            && request.worker_address.is_none()
+            && not_before.is_none()
+            && self.job_queue.lock().await.len() >= limit as usize
         {
+            tracing::info!(job_id = %job_id, "job rejected, the queue is full");
+            return Err(OrchestratorError::QueueFull(limit).into());
+        }
+
+        // Enforce client quotas before the job is queued
+        if let Err(e) = self.quota_tracker.lock().await.try_admit(job_id, identity, SystemTime::now()) {
+            tracing::info!(job_id = %job_id, client = %identity, error = %e, "job rejected by client quota");
+            return Err(e.into());
+        }
+        let mut quota_guard = QueuedJobGuard::new(self.quota_tracker.clone(), job_id);
+
+        // A job identical to one in flight shares its run, unless it is scheduled
+        if request.coalesce_key.as_ref().is_some_and(|key| key.len() != 32) {
+            return Err(Status::invalid_argument("malformed coalesce_key, expected 32 bytes"));
+        }
+        let coalesce_key = request.coalesce_key.as_deref().filter(|_| not_before.is_none());
+        let coalesced_with = match coalesce_key {
+            Some(key) => {
+                let leader = self.await_coalesced_leader(job_id, &namespace, key);
+                match deadline {
+                    Some(deadline) => timeout_at(deadline, leader).await.map_err(|_| OrchestratorError::DeadlinePassed)?,
+                    None => leader.await,
+                }
+            },
+            None => None,
+        };
+        let mut leader_guard = (coalesce_key.is_some() && coalesced_with.is_none())
+            .then(|| LeaderGuard::new(self.coalescing.clone(), job_id));
+
+        // Coalesced jobs upload nothing, so take no part in upload coordination, nor do pinned
+        // jobs, which can't wait for an upload to another Worker
+        let wasm_hash = match (&request.wasm_hash, self.coordinate_uploads, &coalesced_with, &request.worker_address) {
+            (Some(hash), Some(_), None, None) => Some(
+                ModuleHash::try_from(hash.as_slice())
+                    .map_err(|_| Status::invalid_argument("malformed wasm_hash, expected 32 bytes"))?
+            ),
+            _ => None,
+        };
+        // Scheduled jobs don't wait, as they'd hold up the jobs waiting for their upload
+        if let (Some(hash), Some(wait), None) = (wasm_hash, self.coordinate_uploads, not_before) {
+            let upload = self.await_module_upload(job_id, hash, wait);
+            match deadline {
+                Some(deadline) => timeout_at(deadline, upload).await.map_err(|_| OrchestratorError::DeadlinePassed)??,
+                None => upload.await?,
+            }
+        }
+
+        // A pinned job skips the queue, so fails now if its Worker can't take it
+        if let Some(worker_address) = &request.worker_address {
+            self.take_pinned_worker(worker_address, &namespace).await?;
+        }
+
+        if let Some(callback_url) = &request.callback_url
+            && let Err(e) = self.webhooks.register(job_id, callback_url)
+        {
+            self.module_uploads.lock().await.stopped(job_id, false);
+            if let Some(worker_address) = &request.worker_address {
+                self.registry.lock().await.update_credits(worker_address, 1);
+            }
+            return Err(e.into());
+        }
+        let mut callback_guard = CallbackGuard::new(self.webhooks.clone(), job_id);
+        let mut queue_guard = QueueGuard::new(self.job_queue.clone(), job_id);
+        let mut audit_guard = AuditGuard::new(self.audit_log.clone(), job_id);
+
+        let queued = JobEvent::Queued { job_id, at_ms: epoch_ms(SystemTime::now()), scheduled_for_ms: not_before.map(epoch_ms) };
+        self.record(JournalEntry::queued(queued, &namespace, client_address)).await;
+
+        let (tx, mut rx) = oneshot::channel();
+
+        if let Some((leader, worker_address)) = coalesced_with {
+            // Skip the queue for the leader's worker, which runs the job once for both
+            tracing::debug!(job_id = %job_id, leader = %leader, "job coalesced with an in-flight job");
+            self.diagnostics.handle_job_enqueue(job_id, &namespace, client_address, identity, None);
+            let jwt_token = coalesced_job_token(job_id, leader, &self.jwt_secret);
+            let _ = tx.send(WorkerResponse { worker_address, jwt_token, module_cached: true, relayed: false, coalesced_with: Some(leader.to_bytes()), hash_algorithms: Vec::new(), worker_tls_address: None });
+        } else if let Some(worker_address) = &request.worker_address {
+            tracing::debug!(job_id = %job_id, worker = %worker_address, "job pinned to worker, skipping the queue");
+            self.diagnostics.handle_job_enqueue(job_id, &namespace, client_address, identity, None);
+            let jwt_token = job_token(job_id, &self.jwt_secret);
+            let _ = tx.send(WorkerResponse { worker_address: worker_address.clone(), jwt_token, module_cached: false, relayed: false, coalesced_with: None, hash_algorithms: Vec::new(), worker_tls_address: None });
+        } else if let Some(fire_at) = not_before {
+            // Hold the job in the delay queue, the timer task moves it to the queue when due
+            tracing::debug!(job_id = %job_id, "job scheduled, waiting for start time");
+            self.diagnostics.handle_job_enqueue(job_id, &namespace, client_address, identity, Some(fire_at));
+            self.delay_queue.lock().await.schedule(job_id, &namespace, identity, wasm_hash, fire_at, tx);
+            self.delay_queue_notify.notify_one();
+        } else {
+            tracing::debug!(job_id = %job_id, "job enqueued, waiting for worker");
+
+            // Add this job to the queue and dispatch pending jobs atomically
             let mut queue = self.job_queue.lock().await;
             let mut registry = self.registry.lock().await;
 
-            self.diagnostics.handle_job_enqueue(job_id, &client_address);
+            self.diagnostics.handle_job_enqueue(job_id, &namespace, client_address, identity, None);
 
-            queue.enqueue(job_id, tx);
+            queue.enqueue(job_id, &namespace, identity, wasm_hash, tx);
             Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
         }
+        if let Some(original) = replay_of {
+            self.diagnostics.handle_job_replay(job_id, original);
+        }
 
-        // Awake when this job is dispatched
-        match rx.await {
-            Ok(response) => {
+        // Awake when this job is dispatched, or its deadline passes
+        let dispatched = match deadline {
+            Some(deadline) => match timeout_at(deadline, &mut rx).await {
+                Ok(dispatched) => dispatched.map_err(|_| OrchestratorError::JobCancelled),
+                Err(_) => self.expire_queued_job(job_id, &mut rx).await,
+            },
+            None => rx.await.map_err(|_| OrchestratorError::JobCancelled),
+        };
+        match dispatched {
+            Ok(mut response) => {
+                response.relayed = self.relayed_workers.lock().await.contains(&response.worker_address);
+                response.worker_tls_address = self.tls_addresses.lock().await.get(&response.worker_address).cloned();
+                response.hash_algorithms = self.diagnostics.workers.get(&response.worker_address)
+                    .and_then(|worker| worker.capabilities.as_ref().map(|c| c.hash_algorithms.clone()))
+                    .unwrap_or_default();
                 tracing::info!(job_id = %job_id, worker = %response.worker_address, "worker assigned");
+                if wasm_hash.is_some() && not_before.is_none() {
+                    self.module_uploads.lock().await.dispatched(job_id, &response.worker_address);
+                }
+                // Workers that require reservations would refuse the jobs following this one
+                if let Some(leader_guard) = &mut leader_guard
+                    && !self.reserving_workers.lock().await.contains(&response.worker_address)
+                {
+                    self.coalescing.lock().await.dispatched(job_id, &response.worker_address);
+                    leader_guard.set_dispatched();
+                }
+                self.reserve_worker(job_id, &response.worker_address).await;
+                // Followers share the run of the job they follow, which is watched instead
+                if let Some(ms) = request.timeout_ms
+                    && response.coalesced_with.is_none()
+                {
+                    self.watch_runtime(job_id, &response.worker_address, Duration::from_millis(ms)).await;
+                }
+                let dispatched = JobEvent::Dispatched { job_id, worker_address: response.worker_address.clone(), at_ms: epoch_ms(SystemTime::now()) };
+                self.record(JournalEntry::new(dispatched)).await;
+                self.hold_quota(job_id, &response.worker_address).await;
+                quota_guard.set_dispatched();
+                self.webhooks.dispatched(job_id, &response.worker_address);
+                callback_guard.set_dispatched();
+                queue_guard.set_dispatched();
+                audit_guard.set_dispatched();
                 self.diagnostics.handle_dispatch_job(job_id, &response.worker_address);
-                Ok(Response::new(response))
+                Ok(response)
             },
-            Err(_) => Err(OrchestratorError::JobCancelled.into())
+            Err(e) => {
+                if wasm_hash.is_some() && not_before.is_none() {
+                    self.module_uploads.lock().await.stopped(job_id, false);
+                }
+                let at_ms = epoch_ms(SystemTime::now());
+                let stopped = match e {
+                    OrchestratorError::DeadlinePassed => JobEvent::Failed { job_id, at_ms },
+                    _ => JobEvent::Cancelled { job_id, at_ms },
+                };
+                self.record(JournalEntry::new(stopped.clone())).await;
+                self.webhooks.notify_completion(stopped, None);
+                Err(e.into())
+            }
         }
     }
 
-    /// A function exposed by the Orchestrator for the Client to call
-    /// to cancel a job waiting in the Orchestrator queue.
-    /// If this job is in the Orchestrator queue, it will remove it.
-    /// Returns an error on invalid job id.
-    async fn cancel_job(
-        &self,
-        request: Request<CancelJobRequest>
-    ) -> Result<Response<CancelJobResponse>, Status> {
-        let job_id = Uuid::from_slice(&request.into_inner().job_id)
-            .unwrap_or_else(|e| {
-                tracing::error!(error = %e, "ERROR: received malformed job_id bytes from the client, this should never occur");
-                std::process::exit(1);
-            });
+    /// Takes a job whose deadline passed out of the queue, or the delay queue, and fails it
+    /// with DeadlinePassed. Returns its worker instead if it was dispatched meanwhile.
+    async fn expire_queued_job(&self, job_id: JobId, rx: &mut oneshot::Receiver<WorkerResponse>) -> Result<WorkerResponse, OrchestratorError> {
+        // Jobs are dispatched with the queue locked, so once it is the receiver has its answer
+        let mut queue = self.job_queue.lock().await;
+        let removed = queue.cancel(&job_id) || self.delay_queue.lock().await.cancel(&job_id);
+        if let Ok(response) = rx.try_recv() {
+            return Ok(response);
+        }
+        drop(queue);
+        if removed {
+            tracing::info!(job_id = %job_id, "job's deadline passed before dispatch, dropped from queue");
+            self.diagnostics.handle_queued_job_expired(job_id);
+        }
+        Err(OrchestratorError::DeadlinePassed)
+    }
 
-        if self.job_queue.lock().await.cancel(&job_id) {
-            tracing::debug!(job_id = %job_id, "job cancelled from queue");
-            self.diagnostics.handle_cancel_queued_job(job_id);
-            Ok(Response::new(CancelJobResponse {}))
-        } else {
-            tracing::debug!(job_id = %job_id, "cancel requested but job not in queue (may have been dispatched)");
-            Err(OrchestratorError::JobNotFound.into())
+    /// Returns JobInOtherNamespace if the job is known and belongs to a namespace other than
+    /// the given one.
+    fn check_job_namespace(&self, job_id: &JobId, namespace: &Namespace) -> Result<(), OrchestratorError> {
+        match self.diagnostics.jobs.get(job_id) {
+            Some(job_info) if job_info.namespace != *namespace => Err(OrchestratorError::JobInOtherNamespace),
+            _ => Ok(()),
         }
     }
 }
 
-/// Interceptor that verifies the authorization header matches the configured client password.
-/// Returns Unauthenticated if the password is wrong or missing. No-op if no password is configured.
+/// Interceptor that verifies the authorization header matches the configured client password
+/// or one of the client tokens, in which case the token's namespace is attached to the request.
+/// Where there are tokens, the password is pinned to the default namespace the same way, so
+/// that it can't be used to act in a token's namespace.
+/// Returns Unauthenticated if neither matches. No-op if neither is configured.
 pub fn check_client_auth(orchestrator: Orchestrator) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Clone {
     let password = orchestrator.client_password.clone();
-    move |req: Request<()>| {
-        if let Some(expected) = &password {
-            let actual = req.metadata().get("authorization")
-                .and_then(|v| v.to_str().ok());
-            if actual != Some(expected) {
-                return Err(Status::unauthenticated("invalid client password"));
-            }
+    let tokens = orchestrator.client_tokens.clone();
+    move |mut req: Request<()>| {
+        let actual = req.metadata().get("authorization")
+            .and_then(|v| v.to_str().ok());
+        if let Some(namespace) = actual.and_then(|token| tokens.get(token)) {
+            req.extensions_mut().insert(namespace.clone());
+            return Ok(req);
+        }
+        let authorized = match &password {
+            Some(expected) => actual == Some(expected.as_str()),
+            None => tokens.is_empty(),
+        };
+        if !authorized {
+            return Err(ErrorCode::Unauthorized.status(Code::Unauthenticated, "invalid client password"));
+        }
+        if !tokens.is_empty() {
+            req.extensions_mut().insert(Namespace::default());
         }
         Ok(req)
     }
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::{Mutex, watch};
+
+use shared::JobId;
+
+use crate::namespaces::Namespace;
+use crate::orchestrator::Orchestrator;
+
+/// In-flight jobs that opted in to coalescing, by namespace and coalesce key. A job with the
+/// same key as one of them is coalesced with it: it skips the queue, takes no credit, and goes
+/// to the same Worker, which has it share the in-flight job's run.
+#[derive(Debug, Default)]
+pub struct Coalescing {
+    in_flight: HashMap<(Namespace, Vec<u8>), Leader>,
+}
+
+/// The job whose run jobs with its key share, and the Worker it was dispatched to once it is.
+#[derive(Debug)]
+struct Leader {
+    job_id: JobId,
+    worker_address: watch::Sender<Option<String>>,
+}
+
+/// What a job with a coalesce key does, see Coalescing::claim.
+enum Role {
+    /// Run as usual, leading the jobs with its key that come in meanwhile.
+    Lead,
+    /// Share the leader's run, once the leader is dispatched.
+    Follow { leader: JobId, worker_address: watch::Receiver<Option<String>> },
+}
+
+impl Coalescing {
+    /// Makes the job the leader for its key, unless another job already is.
+    fn claim(&mut self, job_id: JobId, namespace: &Namespace, key: &[u8]) -> Role {
+        let key = (namespace.clone(), key.to_vec());
+        if let Some(leader) = self.in_flight.get(&key)
+            && leader.job_id != job_id
+        {
+            return Role::Follow { leader: leader.job_id, worker_address: leader.worker_address.subscribe() };
+        }
+        let (worker_address, _) = watch::channel(None);
+        self.in_flight.insert(key, Leader { job_id, worker_address });
+        Role::Lead
+    }
+
+    /// Releases the jobs following a leader to the Worker it was dispatched to.
+    pub fn dispatched(&mut self, job_id: JobId, worker_address: &str) {
+        if let Some(leader) = self.in_flight.values().find(|leader| leader.job_id == job_id) {
+            leader.worker_address.send_replace(Some(worker_address.to_string()));
+        }
+    }
+
+    /// Forgets a leader that finished, or stopped before it was dispatched. Jobs still waiting
+    /// for it to be dispatched pick a new leader among themselves.
+    pub fn stopped(&mut self, job_id: JobId) {
+        self.in_flight.retain(|_, leader| leader.job_id != job_id);
+    }
+
+    /// Forgets the leaders dispatched to a Worker that disconnected.
+    pub fn worker_disconnected(&mut self, worker_address: &str) {
+        self.in_flight.retain(|_, leader| leader.worker_address.borrow().as_deref() != Some(worker_address));
+    }
+}
+
+impl Orchestrator {
+    /// Finds the in-flight job to coalesce a job with, waiting for it to be dispatched if it
+    /// is still queued. Returns the leader and its Worker, or None if the job leads instead.
+    pub(crate) async fn await_coalesced_leader(&self, job_id: JobId, namespace: &Namespace, key: &[u8]) -> Option<(JobId, String)> {
+        loop {
+            let role = self.coalescing.lock().await.claim(job_id, namespace, key);
+            let Role::Follow { leader, mut worker_address } = role else {
+                return None;
+            };
+            tracing::debug!(job_id = %job_id, leader = %leader, "waiting for the job to coalesce with to be dispatched");
+            if let Ok(address) = worker_address.wait_for(Option::is_some).await
+                && let Some(address) = address.clone()
+            {
+                return Some((leader, address));
+            }
+        }
+    }
+}
+
+/// RAII guard that forgets a leading job if its request is dropped or fails before the job is
+/// dispatched, like QueuedJobGuard.
+pub struct LeaderGuard {
+    coalescing: Arc<Mutex<Coalescing>>,
+    job_id: JobId,
+    dispatched: bool,
+}
+
+impl LeaderGuard {
+    pub fn new(coalescing: Arc<Mutex<Coalescing>>, job_id: JobId) -> Self {
+        Self { coalescing, job_id, dispatched: false }
+    }
+
+    pub fn set_dispatched(&mut self) {
+        self.dispatched = true;
+    }
+}
+
+impl Drop for LeaderGuard {
+    /// Forgets the leader unless it was dispatched, in which case it is forgotten once the
+    /// Worker reports the job as finished.
+    fn drop(&mut self) {
+        if self.dispatched {
+            return;
+        }
+        let coalescing = self.coalescing.clone();
+        let job_id = self.job_id;
+        tokio::spawn(async move {
+            coalescing.lock().await.stopped(job_id);
+        });
+    }
+}
@@ -0,0 +1,344 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::orchestrator::OrchestratorConfig;
+use crate::quotas::ClientQuotas;
+use crate::supervisor::SupervisorConfig;
+
+/// The command line argv with a TOML config file's settings appended as flags of command,
+/// leaving out those the command line, as parsed into matches, already sets. Parsing the
+/// result checks the file's values like the flags', requires included. Settings are keyed by
+/// flag name, and those in not_configurable can't be set.
+pub fn with_config_file(command: &Command, matches: &ArgMatches, mut argv: Vec<OsString>, path: &Path, not_configurable: &[&str]) -> Result<Vec<OsString>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read the config file {}: {e}", path.display()))?;
+    let table: toml::Table = toml::from_str(&contents)
+        .map_err(|e| format!("invalid config file {}: {e}", path.display()))?;
+    let flags = config_flags(command, matches, table, not_configurable)
+        .map_err(|e| format!("{e} in the config file {}", path.display()))?;
+    argv.extend(flags);
+    Ok(argv)
+}
+
+/// The flags that set a config file's settings, see with_config_file.
+fn config_flags(command: &Command, matches: &ArgMatches, table: toml::Table, not_configurable: &[&str]) -> Result<Vec<OsString>, String> {
+    let mut flags = Vec::new();
+    for (key, value) in table {
+        let arg = command.get_arguments()
+            .filter(|arg| !not_configurable.contains(&arg.get_id().as_str()))
+            .find(|arg| arg.get_long() == Some(key.as_str()) || arg.get_id() == key.replace('-', "_").as_str())
+            .ok_or_else(|| format!("unknown setting '{key}'"))?;
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        flags.extend(config_tokens(arg, &key, value)?);
+    }
+    Ok(flags)
+}
+
+/// The command-line tokens that set arg to a config file value. Lists set repeatable flags.
+fn config_tokens(arg: &Arg, key: &str, value: toml::Value) -> Result<Vec<OsString>, String> {
+    let flag = arg.get_long().map(|long| format!("--{long}"));
+    if matches!(arg.get_action(), ArgAction::SetTrue) {
+        return match (value, flag) {
+            (toml::Value::Boolean(true), Some(flag)) => Ok(vec![flag.into()]),
+            (toml::Value::Boolean(false), _) => Ok(vec![]),
+            _ => Err(format!("'{key}' must be true or false")),
+        };
+    }
+    let values = match value {
+        toml::Value::Array(values) => values,
+        value => vec![value],
+    };
+    values.into_iter()
+        .map(|value| {
+            let value = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Float(f) => f.to_string(),
+                _ => return Err(format!("'{key}' must be a string, a number, or a list of them")),
+            };
+            Ok(match &flag {
+                Some(flag) => format!("{flag}={value}").into(),
+                None => value.into(),
+            })
+        })
+        .collect()
+}
+
+/// A setting that is out of range, or at odds with another setting.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid configuration: {0}")]
+pub struct InvalidConfig(pub String);
+
+impl OrchestratorConfig {
+    /// Checks for settings that can't work together, such as a client quota no job could fit
+    /// in. Orchestrator::start doesn't check, so embedders should call this first.
+    pub fn validate(&self) -> Result<(), InvalidConfig> {
+        let ClientQuotas { max_queued_jobs, max_concurrent_jobs, max_jobs_per_day, max_module_bytes_per_day } = self.client_quotas;
+        if max_queued_jobs == Some(0) || max_concurrent_jobs == Some(0) || max_jobs_per_day == Some(0) {
+            return Err(InvalidConfig("a client quota of 0 jobs would reject every job".to_string()));
+        }
+        if self.max_queued_jobs == Some(0) {
+            return Err(InvalidConfig("a queue of 0 jobs would reject every job".to_string()));
+        }
+        if let (Some(per_client), Some(total)) = (max_queued_jobs, self.max_queued_jobs) && per_client >= total {
+            return Err(InvalidConfig(format!(
+                "the per-client queued jobs quota ({per_client}) must be below the queue's limit ({total}), or one client could fill the queue"
+            )));
+        }
+        if max_module_bytes_per_day == Some(0) {
+            return Err(InvalidConfig("a client quota of 0 module bytes a day would reject every job".to_string()));
+        }
+        if let (Some(queued), Some(concurrent)) = (max_queued_jobs, max_concurrent_jobs) && queued > concurrent {
+            return Err(InvalidConfig(format!(
+                "the per-client queued jobs quota ({queued}) exceeds the concurrent jobs quota ({concurrent}), which counts queued jobs too"
+            )));
+        }
+        if self.flap_policy.window.is_zero() {
+            return Err(InvalidConfig("the flap window must be longer than 0 seconds".to_string()));
+        }
+        if self.webhook_allowed_hosts.as_ref().is_some_and(Vec::is_empty) {
+            return Err(InvalidConfig("the webhook allowed hosts are empty, which would reject every callback URL".to_string()));
+        }
+        let weights = [
+            ("second of execution", self.cost_weights.per_execution_second),
+            ("compile", self.cost_weights.per_compile),
+            ("MiB of module", self.cost_weights.per_module_mb),
+        ];
+        for (per, weight) in weights {
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(InvalidConfig(format!("the cost per {per} must be a number of at least 0, got {weight}")));
+            }
+        }
+        if let Some(reserve) = self.out_of_band_reserve && !(0.0..=1.0).contains(&reserve) {
+            return Err(InvalidConfig(format!("the out-of-band reserve must be a fraction from 0 to 1, got {reserve}")));
+        }
+        Ok(())
+    }
+}
+
+impl SupervisorConfig {
+    /// Checks for settings that can't work together, see OrchestratorConfig::validate.
+    pub fn validate(&self) -> Result<(), InvalidConfig> {
+        if self.max_workers < self.min_workers {
+            return Err(InvalidConfig(format!(
+                "the most workers to scale up to ({}) is fewer than the workers kept running ({})",
+                self.max_workers, self.min_workers,
+            )));
+        }
+        if self.worker_credits == 0 {
+            return Err(InvalidConfig("spawned workers must advertise at least 1 credit".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use clap::value_parser;
+    use shared::CostWeights;
+
+    use crate::flapping::FlapPolicy;
+
+    use super::*;
+
+    /// A command shaped like the orchestrator binary's, with each kind of flag it has.
+    fn command() -> Command {
+        Command::new("orchestrator")
+            .arg(Arg::new("addr").default_value("127.0.0.1:50051"))
+            .arg(Arg::new("config").long("config"))
+            .arg(Arg::new("flap_threshold").long("flap-threshold").value_parser(value_parser!(u32)).default_value("3"))
+            .arg(Arg::new("cost_per_compile").long("cost-per-compile").value_parser(value_parser!(f64)))
+            .arg(Arg::new("verbose").long("verbose").action(ArgAction::SetTrue))
+            .arg(Arg::new("client_tokens").long("client-token").action(ArgAction::Append))
+    }
+
+    /// Parses the command line, then again with the config file's flags added.
+    fn parse(command_line: &[&str], file: &str) -> Result<ArgMatches, String> {
+        let argv: Vec<OsString> = std::iter::once("orchestrator").chain(command_line.iter().copied()).map(OsString::from).collect();
+        let matches = command().try_get_matches_from(&argv).unwrap();
+        let flags = config_flags(&command(), &matches, toml::from_str(file).unwrap(), &["config"])?;
+        command().try_get_matches_from(argv.into_iter().chain(flags)).map_err(|e| e.to_string())
+    }
+
+    fn tokens(matches: &ArgMatches) -> Vec<&str> {
+        matches.get_many::<String>("client_tokens").into_iter().flatten().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn file_settings_fill_in_unset_flags() {
+        let matches = parse(&[], "flap-threshold = 5\ncost-per-compile = 0.5\naddr = \"0.0.0.0:1\"").unwrap();
+        assert_eq!(matches.get_one::<u32>("flap_threshold"), Some(&5));
+        assert_eq!(matches.get_one::<f64>("cost_per_compile"), Some(&0.5));
+        assert_eq!(matches.get_one::<String>("addr").map(String::as_str), Some("0.0.0.0:1"));
+    }
+
+    #[test]
+    fn the_command_line_overrides_the_file() {
+        let matches = parse(&["--flap-threshold=7", "--client-token=a=1"], "flap-threshold = 5\nclient-token = [\"b=2\"]").unwrap();
+        assert_eq!(matches.get_one::<u32>("flap_threshold"), Some(&7));
+        assert_eq!(tokens(&matches), ["a=1"]);
+    }
+
+    #[test]
+    fn settings_may_be_keyed_by_id() {
+        let matches = parse(&[], "flap_threshold = 5").unwrap();
+        assert_eq!(matches.get_one::<u32>("flap_threshold"), Some(&5));
+    }
+
+    #[test]
+    fn unknown_and_unconfigurable_settings_are_rejected() {
+        assert_eq!(parse(&[], "flap-treshold = 5").unwrap_err(), "unknown setting 'flap-treshold'");
+        assert_eq!(parse(&[], "config = \"other.toml\"").unwrap_err(), "unknown setting 'config'");
+    }
+
+    #[test]
+    fn arrays_repeat_the_flag() {
+        let matches = parse(&[], "client-token = [\"a=1\", \"b=2\"]").unwrap();
+        assert_eq!(tokens(&matches), ["a=1", "b=2"]);
+        let matches = parse(&[], "client-token = \"a=1\"").unwrap();
+        assert_eq!(tokens(&matches), ["a=1"]);
+    }
+
+    #[test]
+    fn switches_take_booleans() {
+        assert!(parse(&[], "verbose = true").unwrap().get_flag("verbose"));
+        assert!(!parse(&[], "verbose = false").unwrap().get_flag("verbose"));
+        assert!(parse(&["--verbose"], "verbose = false").unwrap().get_flag("verbose"));
+        assert_eq!(parse(&[], "verbose = \"yes\"").unwrap_err(), "'verbose' must be true or false");
+        assert_eq!(parse(&[], "verbose = 1").unwrap_err(), "'verbose' must be true or false");
+    }
+
+    #[test]
+    fn values_are_checked_like_flags() {
+        assert_eq!(parse(&[], "flap-threshold = true").unwrap_err(), "'flap-threshold' must be a string, a number, or a list of them");
+        assert_eq!(parse(&[], "client-token = [[\"a=1\"]]").unwrap_err(), "'client-token' must be a string, a number, or a list of them");
+        assert!(parse(&[], "flap-threshold = -1").unwrap_err().contains("--flap-threshold"));
+    }
+
+    #[test]
+    fn config_files_are_read_and_named_in_errors() {
+        let path = std::env::temp_dir().join(format!("mini-lambda-config-{}.toml", shared::JobId::random()));
+        let argv = vec![OsString::from("orchestrator")];
+        let matches = command().get_matches_from(&argv);
+
+        std::fs::write(&path, "flap-threshold = 5").unwrap();
+        let argv = with_config_file(&command(), &matches, argv.clone(), &path, &["config"]).unwrap();
+        assert_eq!(argv, ["orchestrator", "--flap-threshold=5"]);
+
+        std::fs::write(&path, "flap-threshold = ").unwrap();
+        let error = with_config_file(&command(), &matches, argv.clone(), &path, &["config"]).unwrap_err();
+        assert!(error.starts_with(&format!("invalid config file {}", path.display())), "{error}");
+
+        std::fs::write(&path, "nope = 1").unwrap();
+        let error = with_config_file(&command(), &matches, argv.clone(), &path, &["config"]).unwrap_err();
+        assert_eq!(error, format!("unknown setting 'nope' in the config file {}", path.display()));
+
+        std::fs::remove_file(&path).unwrap();
+        let error = with_config_file(&command(), &matches, argv, &path, &["config"]).unwrap_err();
+        assert!(error.starts_with("failed to read the config file"), "{error}");
+    }
+
+    fn rejects(config: OrchestratorConfig, message: &str) {
+        let error = config.validate().unwrap_err();
+        assert!(error.0.contains(message), "expected '{message}', got '{error}'");
+    }
+
+    fn with_quotas(quotas: ClientQuotas) -> OrchestratorConfig {
+        OrchestratorConfig { client_quotas: quotas, ..Default::default() }
+    }
+
+    #[test]
+    fn the_default_orchestrator_config_is_valid() {
+        assert_eq!(OrchestratorConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn zero_job_quotas_are_rejected() {
+        for quotas in [
+            ClientQuotas { max_queued_jobs: Some(0), ..Default::default() },
+            ClientQuotas { max_concurrent_jobs: Some(0), ..Default::default() },
+            ClientQuotas { max_jobs_per_day: Some(0), ..Default::default() },
+        ] {
+            rejects(with_quotas(quotas), "a client quota of 0 jobs");
+        }
+        rejects(with_quotas(ClientQuotas { max_module_bytes_per_day: Some(0), ..Default::default() }), "0 module bytes a day");
+        rejects(OrchestratorConfig { max_queued_jobs: Some(0), ..Default::default() }, "a queue of 0 jobs");
+    }
+
+    #[test]
+    fn a_client_may_not_fill_the_queue() {
+        let quotas = ClientQuotas { max_queued_jobs: Some(10), ..Default::default() };
+        rejects(OrchestratorConfig { client_quotas: quotas.clone(), max_queued_jobs: Some(10), ..Default::default() }, "must be below the queue's limit");
+        assert_eq!(OrchestratorConfig { client_quotas: quotas, max_queued_jobs: Some(11), ..Default::default() }.validate(), Ok(()));
+    }
+
+    #[test]
+    fn queued_jobs_count_towards_concurrent_jobs() {
+        let quotas = ClientQuotas { max_queued_jobs: Some(5), max_concurrent_jobs: Some(4), ..Default::default() };
+        rejects(with_quotas(quotas), "exceeds the concurrent jobs quota");
+        let quotas = ClientQuotas { max_queued_jobs: Some(4), max_concurrent_jobs: Some(4), ..Default::default() };
+        assert_eq!(with_quotas(quotas).validate(), Ok(()));
+    }
+
+    #[test]
+    fn the_flap_window_must_not_be_empty() {
+        let flap_policy = FlapPolicy { window: Duration::ZERO, ..Default::default() };
+        rejects(OrchestratorConfig { flap_policy, ..Default::default() }, "the flap window");
+    }
+
+    #[test]
+    fn webhook_hosts_must_not_be_empty() {
+        rejects(OrchestratorConfig { webhook_allowed_hosts: Some(Vec::new()), ..Default::default() }, "webhook allowed hosts are empty");
+    }
+
+    #[test]
+    fn cost_weights_must_be_non_negative_numbers() {
+        for weight in [-1.0, f64::NAN, f64::INFINITY] {
+            let weights = [
+                CostWeights { per_execution_second: weight, ..Default::default() },
+                CostWeights { per_compile: weight, ..Default::default() },
+                CostWeights { per_module_mb: weight, ..Default::default() },
+            ];
+            for cost_weights in weights {
+                rejects(OrchestratorConfig { cost_weights, ..Default::default() }, "must be a number of at least 0");
+            }
+        }
+    }
+
+    #[test]
+    fn the_out_of_band_reserve_is_a_fraction() {
+        for reserve in [-0.1, 1.1, f64::NAN] {
+            rejects(OrchestratorConfig { out_of_band_reserve: Some(reserve), ..Default::default() }, "out-of-band reserve");
+        }
+        assert_eq!(OrchestratorConfig { out_of_band_reserve: Some(1.0), ..Default::default() }.validate(), Ok(()));
+    }
+
+    fn supervisor_config() -> SupervisorConfig {
+        SupervisorConfig {
+            worker_bin: PathBuf::from("worker"),
+            orchestrator_url: "http://127.0.0.1:50051".to_string(),
+            min_workers: 1,
+            max_workers: 2,
+            worker_credits: 1,
+            scale_up_wait: Duration::from_secs(1),
+            scale_down_idle: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn supervisors_need_room_and_credits() {
+        assert_eq!(supervisor_config().validate(), Ok(()));
+        let error = SupervisorConfig { min_workers: 3, ..supervisor_config() }.validate().unwrap_err();
+        assert!(error.0.contains("is fewer than the workers kept running"), "{error}");
+        let error = SupervisorConfig { worker_credits: 0, ..supervisor_config() }.validate().unwrap_err();
+        assert!(error.0.contains("at least 1 credit"), "{error}");
+    }
+}
@@ -0,0 +1,73 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::SystemTime;
+
+use shared::WorkerResponse;
+use tokio::sync::oneshot;
+use shared::JobId;
+
+use crate::identity::ClientIdentity;
+use crate::module_uploads::ModuleHash;
+use crate::namespaces::Namespace;
+
+/// Time-ordered queue of scheduled jobs waiting for their start time before entering the JobQueue.
+/// Cancelled jobs are removed from the pending map immediately and skipped lazily in the heap.
+#[derive(Debug)]
+pub struct DelayQueue {
+    heap: BinaryHeap<Reverse<(SystemTime, JobId)>>,
+    pending: HashMap<JobId, ScheduledJob>,
+}
+
+/// A job waiting for its start time, with the client that scheduled it.
+#[derive(Debug)]
+pub struct ScheduledJob {
+    pub namespace: Namespace,
+    pub client: ClientIdentity,
+    pub wasm_hash: Option<ModuleHash>,
+    pub tx: oneshot::Sender<WorkerResponse>,
+}
+
+impl DelayQueue {
+    /// Create a new, empty DelayQueue.
+    pub fn new() -> DelayQueue {
+        DelayQueue { heap: BinaryHeap::new(), pending: HashMap::new() }
+    }
+
+    /// Schedule a job to be released at the given time.
+    pub fn schedule(&mut self, job_id: JobId, namespace: &Namespace, client: &ClientIdentity, wasm_hash: Option<ModuleHash>, fire_at: SystemTime, tx: oneshot::Sender<WorkerResponse>) {
+        self.pending.insert(job_id, ScheduledJob { namespace: namespace.clone(), client: client.clone(), wasm_hash, tx });
+        self.heap.push(Reverse((fire_at, job_id)));
+    }
+
+    /// Returns the release time of the earliest job still scheduled, discarding cancelled entries.
+    pub fn next_fire_time(&mut self) -> Option<SystemTime> {
+        while let Some(Reverse((fire_at, job_id))) = self.heap.peek() {
+            if self.pending.contains_key(job_id) {
+                return Some(*fire_at);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+    /// Remove and return every job whose release time is at or before now, earliest first.
+    pub fn pop_due(&mut self, now: SystemTime) -> Vec<(JobId, ScheduledJob)> {
+        let mut due = Vec::new();
+        while let Some(Reverse((fire_at, job_id))) = self.heap.peek() {
+            if *fire_at > now {
+                break;
+            }
+            let job_id = *job_id;
+            self.heap.pop();
+            if let Some(job) = self.pending.remove(&job_id) {
+                due.push((job_id, job));
+            }
+        }
+        due
+    }
+
+    /// Remove a scheduled job. Returns false if it wasn't scheduled.
+    pub fn cancel(&mut self, job_id: &JobId) -> bool {
+        self.pending.remove(job_id).is_some()
+    }
+}
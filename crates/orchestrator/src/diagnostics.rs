@@ -1,8 +1,16 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, SystemTime};
 
 use dashmap::DashMap;
-use shared::JobUpdate;
-use uuid::Uuid;
+use shared::{HandoffSummary, Heartbeat, HostLoad, JobDetails, JobUpdate, WorkerCapabilities};
+use shared::JobId;
+use shared::events::epoch_ms;
+
+use crate::heartbeat_stats::HeartbeatStats;
+use crate::identity::ClientIdentity;
+use crate::journal::RecoveredJob;
+use crate::namespaces::Namespace;
 
 // TODO: add eviction policy so that only 1000 inactive jobs are held,
 // this can also apply to 1000 old workers and clients
@@ -14,9 +22,16 @@ use uuid::Uuid;
 pub struct DiagnosticsStore {
     /// When the orchestrator started, used for uptime display.
     pub started_at: SystemTime,
-    pub jobs: DashMap<Uuid, JobInfo>,
+    pub jobs: DashMap<JobId, JobInfo>,
+    /// The record_id of the next job recorded, shared with the TUI's snapshots of the store.
+    next_record_id: Arc<AtomicU64>,
+    /// Picked at random on startup and written into every page token, so that tokens from
+    /// another orchestrator, or from before a restart, are rejected instead of misread.
+    page_token_tag: u32,
     pub clients: DashMap<String, ClientInfo>,
     pub workers: DashMap<String, WorkerInfo>,
+    /// Worker processes spawned by the orchestrator's supervisor, keyed by index.
+    pub managed_workers: DashMap<usize, ManagedWorkerInfo>,
 }
 
 impl DiagnosticsStore {
@@ -24,21 +39,29 @@ impl DiagnosticsStore {
         Self {
             started_at: SystemTime::now(),
             jobs: DashMap::new(),
+            next_record_id: Arc::new(AtomicU64::new(1)),
+            page_token_tag: rand::random(),
             clients: DashMap::new(),
-            workers: DashMap::new()
+            workers: DashMap::new(),
+            managed_workers: DashMap::new(),
         }
     }
 }
 
+impl Default for DiagnosticsStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DiagnosticsStore {
     /// Updates job, client, and worker diagnostics in response to a state transition reported
     /// by a worker.
     pub fn handle_worker_job_update(&self, worker_address: &str, job_update: &JobUpdate) {
-        let job_id = Uuid::from_slice(&job_update.job_id)
-            .unwrap_or_else(|e| {
-                tracing::error!(error = %e, "ERROR: received malformed job_id bytes in job update from worker, this should never happen");
-                std::process::exit(1);
-            });
+        let Ok(job_id) = JobId::from_bytes(&job_update.job_id) else {
+            tracing::warn!(worker = %worker_address, "ignoring a job update with a malformed job_id");
+            return;
+        };
 
         let Some(mut job_info) = self.jobs.get_mut(&job_id) else {
             tracing::warn!(job_id = %job_id, "job not found in diagnostics store during job update");
@@ -53,19 +76,27 @@ impl DiagnosticsStore {
             return;
         };
 
+        let now = SystemTime::now();
+        worker_info.last_seen_at = now;
+
         let received_state: JobState = job_update.state().into();
         if received_state <= job_info.state {
             return;
         }
 
-        let now = SystemTime::now();
         match received_state {
             JobState::Compiling => {
                 job_info.compiling_at = Some(now);
             },
             JobState::Executing => {
                 job_info.executing_at = Some(now);
+                job_info.details = job_update.details.clone();
                 worker_info.jobs_received += 1;
+                // Workers only report compiling for jobs whose module wasn't already compiled
+                if let Some(compiling_at) = job_info.compiling_at {
+                    worker_info.cold_starts += 1;
+                    worker_info.total_compile_time += now.duration_since(compiling_at).unwrap_or_default();
+                }
             },
             JobState::Failed | JobState::Completed | JobState::Cancelled => {
                 let worker_time = if let Some(compiling_at) = job_info.compiling_at {
@@ -80,6 +111,7 @@ impl DiagnosticsStore {
                 worker_info.total_job_time += worker_time;
 
                 job_info.completed_at = Some(now);
+                job_info.exit_code = job_update.exit_code;
             },
             _ => {},
         }
@@ -100,19 +132,36 @@ impl DiagnosticsStore {
         });
     }
 
-    /// Records a new job entering the queue and increments the submitting client's job count.
-    pub fn handle_job_enqueue(&self, job_id: Uuid, client_address: &str) {
+    /// Records a new job entering the queue, or the delay queue if it has a scheduled start time,
+    /// and increments the submitting client's job count.
+    /// A job_id that is already known is a client retrying the job after its worker failed;
+    /// its retry count is carried over and it isn't counted as a new submission.
+    pub fn handle_job_enqueue(&self, job_id: JobId, namespace: &Namespace, client_address: &str, owner: &ClientIdentity, scheduled_for: Option<SystemTime>) {
+        let known = self.jobs.get(&job_id).map(|j| (j.record_id, j.retries + 1));
+        let retries = known.map(|(_, retries)| retries);
         let job_info = JobInfo {
             job_id,
-            state: JobState::Queued,
+            record_id: known.map_or_else(|| self.next_record_id(), |(record_id, _)| record_id),
+            state: if scheduled_for.is_some() { JobState::Scheduled } else { JobState::Queued },
+            namespace: namespace.clone(),
             client_address: client_address.to_string(),
+            owner: Some(owner.clone()),
             worker_address: None,
+            scheduled_for,
             queued_at: SystemTime::now(),
             compiling_at: None,
             executing_at: None,
-            completed_at: None
+            completed_at: None,
+            retries: retries.unwrap_or(0),
+            late_state: None,
+            details: None,
+            replay_of: None,
+            exit_code: None,
         };
         self.jobs.insert(job_id, job_info);
+        if retries.is_some() {
+            return;
+        }
 
         let Some(mut client_info) = self.clients.get_mut(client_address) else {
             tracing::warn!(job_id = %job_id, client = %client_address, "client not found in diagnostics store during job enqueue");
@@ -122,28 +171,125 @@ impl DiagnosticsStore {
         client_info.jobs_submitted += 1;
     }
 
+    /// Restores a job recovered from the journal on startup. Jobs that were dispatched but
+    /// never finished are marked as having an unknown outcome.
+    pub fn handle_job_recovered(&self, job: &RecoveredJob) {
+        let record = &job.record;
+        let state = match record.state.as_str() {
+            "completed" => JobState::Completed,
+            "failed" => JobState::Failed,
+            "cancelled" => JobState::Cancelled,
+            "presumed-lost" => JobState::PresumedLost,
+            _ => JobState::UnknownOutcome,
+        };
+        let at = |ms: u64| SystemTime::UNIX_EPOCH + Duration::from_millis(ms);
+        self.jobs.insert(record.job_id, JobInfo {
+            job_id: record.job_id,
+            record_id: self.next_record_id(),
+            state,
+            namespace: job.namespace.clone(),
+            client_address: job.client_address.clone(),
+            owner: None,
+            worker_address: record.worker_address.clone(),
+            scheduled_for: record.scheduled_for_ms.map(at),
+            queued_at: record.queued_at_ms.map(at).unwrap_or(self.started_at),
+            compiling_at: None,
+            executing_at: record.started_at_ms.map(at),
+            completed_at: record.finished_at_ms.map(at),
+            retries: record.retries,
+            late_state: None,
+            details: None,
+            replay_of: None,
+            exit_code: None,
+        });
+    }
+
+    fn next_record_id(&self) -> u64 {
+        self.next_record_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// A page of up to limit jobs matching the filter, newest first, continuing after the job
+    /// whose record_id is after if set. Also returns the record_id to continue after if more
+    /// jobs match. Jobs recorded meanwhile have higher record_ids, so later pages never shift.
+    pub fn list_jobs(&self, filter: &JobFilter, after: Option<u64>, limit: usize) -> (Vec<JobInfo>, Option<u64>) {
+        let mut jobs: Vec<JobInfo> = self.jobs.iter()
+            .filter(|j| after.is_none_or(|after| j.record_id < after) && filter.matches(j))
+            .map(|j| j.clone())
+            .collect();
+        jobs.sort_unstable_by_key(|j| std::cmp::Reverse(j.record_id));
+        if jobs.len() <= limit {
+            return (jobs, None);
+        }
+        jobs.truncate(limit);
+        let next = jobs.last().map(|j| j.record_id);
+        (jobs, next)
+    }
+
+    /// The page token to continue listing jobs after the job with this record_id.
+    pub fn page_token(&self, record_id: u64) -> String {
+        format!("{:08x}{record_id:016x}", self.page_token_tag)
+    }
+
+    /// The record_id a page token returned by page_token continues after, or None if the token
+    /// is malformed or wasn't issued by this store.
+    pub fn page_after(&self, token: &str) -> Option<u64> {
+        if token.len() != 24 || !token.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let (tag, record_id) = token.split_at(8);
+        let tag = u32::from_str_radix(tag, 16).ok()?;
+        let record_id = u64::from_str_radix(record_id, 16).ok()?;
+        (tag == self.page_token_tag && record_id < self.next_record_id.load(Ordering::Relaxed)).then_some(record_id)
+    }
+
+    /// Marks a scheduled job as queued once its start time arrives. Queue time is measured from here.
+    pub fn handle_scheduled_job_released(&self, job_id: JobId) {
+        let Some(mut job_info) = self.jobs.get_mut(&job_id) else {
+            tracing::warn!(job_id = %job_id, "job not found in diagnostics store during scheduled release");
+            return;
+        };
+        job_info.state = JobState::Queued;
+        job_info.queued_at = SystemTime::now();
+    }
+
     /// Marks a queued job as cancelled and accumulates its queue time on the client.
-    pub fn handle_cancel_queued_job(&self, job_id: Uuid) {
+    pub fn handle_cancel_queued_job(&self, job_id: JobId) {
+        self.handle_queued_job_stopped(job_id, JobState::Cancelled);
+    }
+
+    /// Marks a queued job whose deadline passed as failed, like handle_cancel_queued_job.
+    pub fn handle_queued_job_expired(&self, job_id: JobId) {
+        self.handle_queued_job_stopped(job_id, JobState::Failed);
+    }
+
+    fn handle_queued_job_stopped(&self, job_id: JobId, state: JobState) {
         let Some(mut job_info) = self.jobs.get_mut(&job_id) else {
-            tracing::warn!(job_id = %job_id, "job not found in diagnostics store during queued cancel");
+            tracing::warn!(job_id = %job_id, "job not found in diagnostics store while stopping a queued job");
             return;
         };
         let Some(mut client_info) = self.clients.get_mut(&job_info.client_address.clone()) else {
-            tracing::warn!(job_id = %job_id, client = %job_info.client_address, "client not found in diagnostics store during queued cancel");
+            tracing::warn!(job_id = %job_id, client = %job_info.client_address, "client not found in diagnostics store while stopping a queued job");
             return;
         };
 
         let now = SystemTime::now();
 
-        job_info.state = JobState::Cancelled;
+        job_info.state = state;
         job_info.completed_at = Some(now);
 
         client_info.total_queue_time += now.duration_since(job_info.queued_at).unwrap_or_default();
         client_info.last_seen_at = now;
     }
 
+    /// Links a job to the job it replays.
+    pub fn handle_job_replay(&self, job_id: JobId, replay_of: JobId) {
+        if let Some(mut job_info) = self.jobs.get_mut(&job_id) {
+            job_info.replay_of = Some(replay_of);
+        }
+    }
+
     /// Marks a job as dispatched to a worker and finalizes its queue time on the client.
-    pub fn handle_dispatch_job(&self, job_id: Uuid, worker_address: &str) {
+    pub fn handle_dispatch_job(&self, job_id: JobId, worker_address: &str) {
         let Some(mut job_info) = self.jobs.get_mut(&job_id) else {
             tracing::warn!(job_id = %job_id, "job not found in diagnostics store during dispatch");
             return;
@@ -159,30 +305,177 @@ impl DiagnosticsStore {
         client_info.total_queue_time += now.duration_since(job_info.queued_at).unwrap_or_default();
     }
 
+    /// Marks a job whose worker didn't finish it within its timeout and grace period as
+    /// presumed lost.
+    pub fn handle_job_presumed_lost(&self, job_id: JobId) {
+        let Some(mut job_info) = self.jobs.get_mut(&job_id) else {
+            tracing::warn!(job_id = %job_id, "job not found in diagnostics store while presuming it lost");
+            return;
+        };
+        job_info.state = JobState::PresumedLost;
+        job_info.completed_at = Some(SystemTime::now());
+    }
+
+    /// Counts a job presumed lost against the worker that started it.
+    pub fn handle_worker_lost_job(&self, worker_address: &str) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.lost_jobs += 1;
+        }
+    }
+
+    /// Records a load-aware worker's host load sample and the credits it advertises at it.
+    pub fn handle_worker_load(&self, worker_address: &str, host_load: &HostLoad) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.host_load = Some(host_load.load);
+            worker_info.load_credits = Some(host_load.advertised_credits);
+        }
+    }
+
+    /// Records a worker's heartbeat, alongside the orchestrator's estimate of its running jobs
+    /// and what reconciling the heartbeat found, see OutOfBandTracker.
+    pub fn handle_worker_heartbeat(&self, worker_address: &str, heartbeat: &Heartbeat, estimated_running: Option<u32>, out_of_band_jobs: u64, reserved_credits: u32) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.running_jobs = Some(heartbeat.running_jobs);
+            worker_info.estimated_running_jobs = estimated_running;
+            worker_info.out_of_band_jobs = out_of_band_jobs;
+            worker_info.reserved_credits = reserved_credits;
+            worker_info.heartbeat_stats = worker_info.heartbeat_stats.record(
+                epoch_ms(SystemTime::now()),
+                (heartbeat.sent_at_ms > 0).then_some(heartbeat.sent_at_ms),
+                (heartbeat.interval_ms > 0).then(|| Duration::from_millis(heartbeat.interval_ms)),
+            );
+        }
+    }
+
+    /// Records how a worker said a job presumed lost on it finished, without changing the
+    /// job's state, which may be a retry's by now.
+    pub fn handle_late_job_update(&self, job_id: JobId, job_update: &JobUpdate) {
+        if let Some(mut job_info) = self.jobs.get_mut(&job_id) {
+            job_info.late_state = Some(job_update.state().into());
+        }
+    }
+
+    /// The share of jobs started across all workers, including disconnected ones, that had to
+    /// compile their module first. None if no jobs have started.
+    pub fn cold_start_rate(&self) -> Option<f64> {
+        let (cold_starts, jobs) = self.workers.iter()
+            .fold((0u64, 0u64), |(cold, jobs), w| (cold + w.cold_starts as u64, jobs + w.jobs_received as u64));
+        (jobs > 0).then(|| cold_starts as f64 / jobs as f64)
+    }
+
     /// Records a new worker connection.
-    pub fn handle_worker_connected(&self, worker_address: &str) {
+    pub fn handle_worker_connected(&self, worker_address: &str, credits: u32, capabilities: Option<WorkerCapabilities>, namespace: Option<Namespace>, flap_count: u32, quarantined_until: Option<SystemTime>) {
+        let now = SystemTime::now();
         self.workers.insert(worker_address.to_string(), WorkerInfo {
             address: worker_address.to_string(),
+            credits,
+            capabilities,
+            namespace,
             jobs_received: 0,
             total_job_time: Duration::ZERO,
-            connected_at: SystemTime::now(),
-            disconnected_at: None
+            cold_starts: 0,
+            total_compile_time: Duration::ZERO,
+            modules_handed_off: 0,
+            modules_received: 0,
+            connected_at: now,
+            last_seen_at: now,
+            disconnected_at: None,
+            flap_count,
+            lost_jobs: 0,
+            quarantined_until,
+            drained: false,
+            validating: false,
+            failed_probes: 0,
+            host_load: None,
+            load_credits: None,
+            running_jobs: None,
+            estimated_running_jobs: None,
+            out_of_band_jobs: 0,
+            reserved_credits: 0,
+            heartbeat_stats: HeartbeatStats::default(),
         });
     }
 
-    /// Records the time a worker disconnected.
-    pub fn handle_worker_disconnected(&self, worker_address: &str) {
+    /// Records the time a worker disconnected, and its host's updated flap count.
+    pub fn handle_worker_disconnected(&self, worker_address: &str, flap_count: u32) {
         let Some(mut worker_info) = self.workers.get_mut(worker_address) else {
             tracing::warn!(worker = %worker_address, "worker not found in diagnostics store during disconnect");
             return;
         };
         worker_info.disconnected_at = Some(SystemTime::now());
+        worker_info.flap_count = flap_count;
+    }
+
+    /// Records the compiled modules a departing worker handed to its peers.
+    pub fn handle_modules_handed_off(&self, worker_address: &str, summary: &HandoffSummary) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.modules_handed_off += summary.modules.len() as u32;
+        }
+        for module in &summary.modules {
+            if let Some(mut peer_info) = self.workers.get_mut(&module.peer_address) {
+                peer_info.modules_received += 1;
+            }
+        }
+    }
+
+    /// Records a worker being drained or resumed by an operator.
+    pub fn handle_worker_drained(&self, worker_address: &str, drained: bool) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.drained = drained;
+        }
+    }
+
+    /// Records the latest status of a worker process managed by the supervisor.
+    pub fn handle_managed_worker_update(&self, info: ManagedWorkerInfo) {
+        self.managed_workers.insert(info.index, info);
+    }
+
+    /// Forgets a managed worker process once the supervisor has stopped it for good.
+    pub fn handle_managed_worker_stopped(&self, index: usize) {
+        self.managed_workers.remove(&index);
+    }
+
+    /// Records a connected worker being quarantined for losing jobs.
+    pub fn handle_worker_quarantined(&self, worker_address: &str, until: SystemTime) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.quarantined_until = Some(until);
+        }
+    }
+
+    /// Records a worker being held from dispatch until it answers a health probe.
+    pub fn handle_worker_validating(&self, worker_address: &str) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.validating = true;
+        }
+    }
+
+    /// Records a worker failing a health probe.
+    pub fn handle_worker_probe_failed(&self, worker_address: &str) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.failed_probes += 1;
+        }
+    }
+
+    /// Records a worker answering its health probe, which makes it eligible for dispatch.
+    pub fn handle_worker_validated(&self, worker_address: &str) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.validating = false;
+            worker_info.last_seen_at = SystemTime::now();
+        }
+    }
+
+    /// Clears a worker's quarantine once it becomes eligible for dispatch again.
+    pub fn handle_worker_quarantine_released(&self, worker_address: &str) {
+        if let Some(mut worker_info) = self.workers.get_mut(worker_address) {
+            worker_info.quarantined_until = None;
+        }
     }
 }
 
 /// The lifecycle state of a job, ordered from earliest to latest.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum JobState {
+    Scheduled,
     Queued,
     Dispatched,
     Compiling,
@@ -190,6 +483,46 @@ pub enum JobState {
     Failed,
     Completed,
     Cancelled,
+    /// Ran past its timeout and grace period on its worker without finishing, see
+    /// RuntimeWatchdog.
+    PresumedLost,
+    /// Dispatched before the orchestrator restarted, with no record of how it finished.
+    UnknownOutcome,
+}
+
+impl JobState {
+    /// The state with the given name, as in as_str.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "scheduled" => JobState::Scheduled,
+            "queued" => JobState::Queued,
+            "dispatched" => JobState::Dispatched,
+            "compiling" => JobState::Compiling,
+            "executing" => JobState::Executing,
+            "failed" => JobState::Failed,
+            "completed" => JobState::Completed,
+            "cancelled" => JobState::Cancelled,
+            "presumed-lost" => JobState::PresumedLost,
+            "unknown-outcome" => JobState::UnknownOutcome,
+            _ => return None,
+        })
+    }
+
+    /// Lowercase name of the state, as reported to clients.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Scheduled => "scheduled",
+            JobState::Queued => "queued",
+            JobState::Dispatched => "dispatched",
+            JobState::Compiling => "compiling",
+            JobState::Executing => "executing",
+            JobState::Failed => "failed",
+            JobState::Completed => "completed",
+            JobState::Cancelled => "cancelled",
+            JobState::PresumedLost => "presumed-lost",
+            JobState::UnknownOutcome => "unknown-outcome",
+        }
+    }
 }
 
 impl From<shared::JobState> for JobState {
@@ -211,14 +544,51 @@ impl From<shared::JobState> for JobState {
 /// Diagnostic snapshot of a single job's lifecycle.
 #[derive(Debug, Clone)]
 pub struct JobInfo {
-    pub job_id: Uuid,
+    pub job_id: JobId,
+    /// Orders jobs by when they were first recorded, so that listings page through them
+    /// stably, see list_jobs. A retried job keeps its own.
+    pub record_id: u64,
     pub state: JobState,
+    pub namespace: Namespace,
     pub client_address: String,
+    /// The client that submitted the job, as quotas and the queue tell clients apart. None for
+    /// jobs recovered from the journal.
+    pub owner: Option<ClientIdentity>,
     pub worker_address: Option<String>,
+    pub scheduled_for: Option<SystemTime>,
     pub queued_at: SystemTime,
     pub compiling_at: Option<SystemTime>,
     pub executing_at: Option<SystemTime>,
-    pub completed_at: Option<SystemTime>
+    pub completed_at: Option<SystemTime>,
+    pub retries: u32,
+    /// How the worker the job was presumed lost on said it finished, if it did after all.
+    pub late_state: Option<JobState>,
+    /// What the job ran, as its worker reported once it began executing, see replay.rs.
+    pub details: Option<JobDetails>,
+    /// The job this one replays, if it was submitted as a replay.
+    pub replay_of: Option<JobId>,
+    /// The code the job's guest exited with, as its worker reported once it finished. None
+    /// if it trapped, was stopped, or hasn't finished.
+    pub exit_code: Option<i32>,
+}
+
+/// Which jobs list_jobs lists. Unset fields match every job.
+#[derive(Debug, Clone, Default)]
+pub struct JobFilter {
+    pub state: Option<JobState>,
+    pub namespace: Option<Namespace>,
+    pub worker_address: Option<String>,
+    /// Only jobs queued at or after this time.
+    pub since: Option<SystemTime>,
+}
+
+impl JobFilter {
+    fn matches(&self, job: &JobInfo) -> bool {
+        self.state.as_ref().is_none_or(|state| job.state == *state)
+            && self.namespace.as_ref().is_none_or(|namespace| job.namespace == *namespace)
+            && self.worker_address.as_ref().is_none_or(|worker| job.worker_address.as_ref() == Some(worker))
+            && self.since.is_none_or(|since| job.queued_at >= since)
+    }
 }
 
 /// Diagnostic snapshot of a connected client.
@@ -236,8 +606,225 @@ pub struct ClientInfo {
 #[derive(Debug, Clone)]
 pub struct WorkerInfo {
     pub address: String,
+    /// Job slots the worker advertised when it registered.
+    pub credits: u32,
+    /// Reported at registration; None for workers that predate capabilities.
+    pub capabilities: Option<WorkerCapabilities>,
+    /// Set if the worker only runs jobs from this namespace.
+    pub namespace: Option<Namespace>,
     pub jobs_received: u32,
     pub total_job_time: Duration,
+    /// Jobs the worker had to compile the module for, rather than finding it in its cache.
+    pub cold_starts: u32,
+    /// Time cold starts spent compiling.
+    pub total_compile_time: Duration,
+    /// Compiled modules the worker handed to peers as it shut down.
+    pub modules_handed_off: u32,
+    /// Compiled modules departing workers handed to this one.
+    pub modules_received: u32,
     pub connected_at: SystemTime,
-    pub disconnected_at: Option<SystemTime>
-}
\ No newline at end of file
+    /// Last time the worker registered or reported a job update.
+    pub last_seen_at: SystemTime,
+    pub disconnected_at: Option<SystemTime>,
+    /// Recent disconnects from this worker's host, see FlapDetector.
+    pub flap_count: u32,
+    /// Jobs presumed lost on this worker, see RuntimeWatchdog.
+    pub lost_jobs: u32,
+    pub quarantined_until: Option<SystemTime>,
+    /// Taken out of dispatch by an operator.
+    pub drained: bool,
+    /// Not yet answering health probes, so held from dispatch, see ValidationPolicy.
+    pub validating: bool,
+    /// Health probes the worker failed since it connected.
+    pub failed_probes: u32,
+    /// Last host load a load-aware worker sampled, from 0 to 1.
+    pub host_load: Option<f32>,
+    /// Credits a load-aware worker advertises at its last host load sample.
+    pub load_credits: Option<u32>,
+    /// Jobs the worker said it was running in its last heartbeat, whoever dispatched them.
+    pub running_jobs: Option<u32>,
+    /// Jobs the orchestrator's credit count had the worker running at its last heartbeat.
+    pub estimated_running_jobs: Option<u32>,
+    /// Jobs the worker started since its first heartbeat that the orchestrator didn't
+    /// dispatch, see OutOfBandTracker.
+    pub out_of_band_jobs: u64,
+    /// Credits held back from the worker while it is busy with those.
+    pub reserved_credits: u32,
+    /// How regularly the worker's heartbeats arrive, and how far its clock is from ours.
+    pub heartbeat_stats: HeartbeatStats,
+}
+
+/// Whether a worker can currently receive jobs, ordered from least to most healthy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WorkerHealth {
+    Offline,
+    Quarantined,
+    Validating,
+    Drained,
+    Online,
+}
+
+impl WorkerInfo {
+    pub fn health(&self) -> WorkerHealth {
+        if self.disconnected_at.is_some() {
+            WorkerHealth::Offline
+        } else if self.quarantined_until.is_some() {
+            WorkerHealth::Quarantined
+        } else if self.validating {
+            WorkerHealth::Validating
+        } else if self.drained {
+            WorkerHealth::Drained
+        } else {
+            WorkerHealth::Online
+        }
+    }
+
+    /// Last time anything was heard from the worker, including its disconnect.
+    pub fn last_heard_at(&self) -> SystemTime {
+        self.disconnected_at.map_or(self.last_seen_at, |t| t.max(self.last_seen_at))
+    }
+}
+/// Lifecycle of a worker process managed by the supervisor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManagedWorkerState {
+    Running,
+    /// Exited unexpectedly and waiting out its restart backoff.
+    Restarting,
+    /// Being drained and shut down, by scale-down or orchestrator shutdown.
+    Stopping,
+}
+
+impl ManagedWorkerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ManagedWorkerState::Running => "running",
+            ManagedWorkerState::Restarting => "restarting",
+            ManagedWorkerState::Stopping => "stopping",
+        }
+    }
+}
+
+/// Diagnostic snapshot of a worker process managed by the supervisor.
+#[derive(Debug, Clone)]
+pub struct ManagedWorkerInfo {
+    /// Stable across restarts; prefixes the process's log lines.
+    pub index: usize,
+    /// The address the current process registers with, which changes on every restart.
+    pub address: String,
+    pub pid: Option<u32>,
+    pub state: ManagedWorkerState,
+    pub restarts: u32,
+    pub started_at: SystemTime,
+    /// How the previous process exited, if it has been restarted.
+    pub last_exit: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    const CLIENT: &str = "127.0.0.1:1234";
+
+    fn enqueue(store: &DiagnosticsStore, job_id: JobId) {
+        let owner = ClientIdentity::Host(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        store.handle_job_enqueue(job_id, &Namespace::default(), CLIENT, &owner, None);
+    }
+
+    fn store_with_jobs(count: usize) -> (DiagnosticsStore, Vec<JobId>) {
+        let store = DiagnosticsStore::new();
+        store.handle_client_connected(CLIENT);
+        let job_ids: Vec<JobId> = (0..count).map(|_| JobId::random()).collect();
+        for &job_id in &job_ids {
+            enqueue(&store, job_id);
+        }
+        (store, job_ids)
+    }
+
+    #[test]
+    fn paging_sees_every_job_once_while_jobs_arrive_and_retry() {
+        let (store, job_ids) = store_with_jobs(3000);
+        let before: HashSet<JobId> = job_ids.iter().copied().collect();
+        let mut seen = Vec::new();
+
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| {
+                for (i, &job_id) in job_ids.iter().enumerate() {
+                    enqueue(&store, JobId::random());
+                    if i % 3 == 0 {
+                        enqueue(&store, job_id);
+                    }
+                }
+            });
+
+            let mut token = None;
+            loop {
+                let after = token.as_deref().map(|token| store.page_after(token).expect("token was issued by this store"));
+                let (jobs, next) = store.list_jobs(&JobFilter::default(), after, 37);
+                seen.extend(jobs.iter().map(|j| (j.record_id, j.job_id)));
+                match next {
+                    Some(record_id) => token = Some(store.page_token(record_id)),
+                    None => break,
+                }
+            }
+            writer.join().unwrap();
+        });
+
+        assert!(seen.windows(2).all(|w| w[0].0 > w[1].0), "pages overlap or go out of order");
+        let seen_ids: HashSet<JobId> = seen.iter().map(|&(_, job_id)| job_id).collect();
+        assert_eq!(seen_ids.len(), seen.len(), "a job was listed twice");
+        assert!(before.is_subset(&seen_ids), "{} jobs recorded before paging were skipped", before.difference(&seen_ids).count());
+    }
+
+    #[test]
+    fn retried_jobs_keep_their_place() {
+        let (store, job_ids) = store_with_jobs(10);
+        let (first, next) = store.list_jobs(&JobFilter::default(), None, 4);
+        enqueue(&store, job_ids[0]);
+        enqueue(&store, job_ids[9]);
+        let (rest, _) = store.list_jobs(&JobFilter::default(), next, 10);
+
+        let listed: Vec<JobId> = first.iter().chain(&rest).map(|j| j.job_id).collect();
+        assert_eq!(listed, job_ids.iter().rev().copied().collect::<Vec<_>>());
+        assert_eq!(store.jobs.get(&job_ids[0]).unwrap().retries, 1);
+    }
+
+    #[test]
+    fn page_tokens_round_trip() {
+        let (store, _) = store_with_jobs(5);
+        let (_, next) = store.list_jobs(&JobFilter::default(), None, 2);
+        let record_id = next.unwrap();
+        assert_eq!(store.page_after(&store.page_token(record_id)), Some(record_id));
+        assert_eq!(store.clone().page_after(&store.page_token(record_id)), Some(record_id));
+    }
+
+    #[test]
+    fn malformed_page_tokens_are_rejected() {
+        let (store, _) = store_with_jobs(5);
+        let token = store.page_token(3);
+        for malformed in [
+            String::new(),
+            "3".to_string(),
+            format!("{:016x}", 3),
+            token[1..].to_string(),
+            format!("{token}0"),
+            format!("+{}", &token[1..]),
+            format!("{}g", &token[..23]),
+            format!("{} ", &token[..23]),
+        ] {
+            assert_eq!(store.page_after(&malformed), None, "accepted {malformed:?}");
+        }
+    }
+
+    #[test]
+    fn foreign_page_tokens_are_rejected() {
+        let (store, _) = store_with_jobs(5);
+        let (other, _) = store_with_jobs(5);
+        assert_eq!(store.page_after(&other.page_token(3)), None);
+        assert_eq!(store.page_after(&store.page_token(3)), Some(3));
+        // Not a job this store has recorded yet.
+        assert_eq!(store.page_after(&store.page_token(100)), None);
+    }
+}
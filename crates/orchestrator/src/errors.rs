@@ -1,3 +1,7 @@
+use shared::ErrorCode;
+use shared::digest::DigestError;
+use tonic::Code;
+
 /// Enum for all recoverable errors that can occur within the Orchestrator.
 #[derive(Debug, thiserror::Error)]
 pub enum OrchestratorError {
@@ -6,13 +10,91 @@ pub enum OrchestratorError {
 
     #[error("tried to cancel a job which couldn't be found or was already cancelled")]
     JobNotFound,
+
+    #[error("client quota exceeded: {quota} (limit {limit})")]
+    QuotaExceeded { quota: &'static str, limit: u64 },
+
+    #[error("client quota for the day exceeded: {quota} (limit {limit})")]
+    DailyQuotaExceeded { quota: &'static str, limit: u64 },
+
+    #[error("the job queue is full (limit {0})")]
+    QueueFull(u32),
+
+    #[error("invalid callback url: {0}")]
+    InvalidCallbackUrl(String),
+
+    #[error("worker {0} is not connected")]
+    WorkerNotFound(String),
+
+    #[error("worker {0} is not drained")]
+    WorkerNotDrained(String),
+
+    #[error("worker {0} has no free credit for a job pinned to it")]
+    WorkerBusy(String),
+
+    #[error("worker {0} doesn't run this namespace's jobs")]
+    WorkerInOtherNamespace(String),
+
+    #[error("the job never started on a worker, or was recovered from the journal, so what it ran isn't known")]
+    JobNotStarted,
+
+    #[error("invalid namespace {0:?}: expected 1 to 63 letters, digits, '-' or '_'")]
+    InvalidNamespace(String),
+
+    #[error("not permitted to act in namespace {0}")]
+    NamespaceForbidden(String),
+
+    #[error("the job belongs to another namespace")]
+    JobInOtherNamespace,
+
+    #[error("invalid module {0:?}: expected 1 to 128 letters, digits, '-', '_' or '.'")]
+    InvalidModuleAlias(String),
+
+    #[error(transparent)]
+    InvalidModuleDigest(#[from] DigestError),
+
+    #[error("no module is published as {alias}, {hint}")]
+    ModuleNotFound { alias: String, hint: String },
+
+    #[error("failed to save module aliases: {0}")]
+    ModuleAliasesNotSaved(std::io::Error),
+
+    #[error("job {0}, which was uploading this job's module, failed before the module compiled")]
+    ModuleUploadFailed(shared::JobId),
+
+    #[error("job {uploader} didn't finish uploading this job's module within {wait:?}")]
+    ModuleUploadTimedOut { uploader: shared::JobId, wait: std::time::Duration },
+
+    #[error("the job's deadline passed before a worker was assigned")]
+    DeadlinePassed,
 }
 
 impl From<OrchestratorError> for tonic::Status {
     fn from(e: OrchestratorError) -> Self {
-        match e {
-            OrchestratorError::JobCancelled => tonic::Status::cancelled(e.to_string()),
-            OrchestratorError::JobNotFound => tonic::Status::not_found(e.to_string()),
-        }
+        let (code, error_code) = match &e {
+            OrchestratorError::JobCancelled => (Code::Cancelled, ErrorCode::JobCancelled),
+            OrchestratorError::JobNotFound => (Code::NotFound, ErrorCode::JobNotFound),
+            OrchestratorError::QuotaExceeded { .. } => (Code::ResourceExhausted, ErrorCode::QuotaExceeded),
+            OrchestratorError::DailyQuotaExceeded { .. } => (Code::ResourceExhausted, ErrorCode::DailyQuotaExceeded),
+            OrchestratorError::QueueFull(_) => (Code::ResourceExhausted, ErrorCode::QueueFull),
+            OrchestratorError::InvalidCallbackUrl(_) => (Code::InvalidArgument, ErrorCode::InvalidCallbackUrl),
+            OrchestratorError::WorkerNotFound(_) => (Code::NotFound, ErrorCode::WorkerNotFound),
+            OrchestratorError::WorkerNotDrained(_) => (Code::FailedPrecondition, ErrorCode::WorkerNotDrained),
+            OrchestratorError::WorkerBusy(_) => (Code::ResourceExhausted, ErrorCode::WorkerBusy),
+            OrchestratorError::WorkerInOtherNamespace(_) => (Code::PermissionDenied, ErrorCode::NamespaceForbidden),
+            OrchestratorError::JobNotStarted => (Code::FailedPrecondition, ErrorCode::JobNotStarted),
+            OrchestratorError::InvalidNamespace(_) => (Code::InvalidArgument, ErrorCode::InvalidNamespace),
+            OrchestratorError::NamespaceForbidden(_) | OrchestratorError::JobInOtherNamespace => {
+                (Code::PermissionDenied, ErrorCode::NamespaceForbidden)
+            },
+            OrchestratorError::InvalidModuleAlias(_) => (Code::InvalidArgument, ErrorCode::InvalidModuleAlias),
+            OrchestratorError::InvalidModuleDigest(error) => (Code::InvalidArgument, error.error_code()),
+            OrchestratorError::ModuleNotFound { .. } => (Code::NotFound, ErrorCode::ModuleNotFound),
+            OrchestratorError::ModuleAliasesNotSaved(_) => (Code::Internal, ErrorCode::Internal),
+            OrchestratorError::ModuleUploadFailed(_) => (Code::Aborted, ErrorCode::ModuleUploadFailed),
+            OrchestratorError::ModuleUploadTimedOut { .. } => (Code::DeadlineExceeded, ErrorCode::ModuleUploadFailed),
+            OrchestratorError::DeadlinePassed => (Code::DeadlineExceeded, ErrorCode::JobTimedOut),
+        };
+        error_code.status(code, e.to_string())
     }
 }
\ No newline at end of file
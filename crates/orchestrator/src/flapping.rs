@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// Thresholds for detecting workers that repeatedly connect and drop off, or lose jobs.
+#[derive(Debug, Clone)]
+pub struct FlapPolicy {
+    /// A worker is quarantined once it disconnects more than this many times within the window.
+    pub max_flaps: u32,
+    /// A worker is quarantined once more than this many of its jobs are presumed lost within
+    /// the window.
+    pub max_lost_jobs: u32,
+    /// A worker is quarantined once more than this many of its health probes fail within the
+    /// window, see ValidationPolicy.
+    pub max_failed_probes: u32,
+    pub window: Duration,
+    /// How long a quarantined worker is kept out of dispatch.
+    pub quarantine: Duration,
+    /// Counts and quarantines whole hosts, by IP, rather than single workers. This catches
+    /// workers that bind a fresh port on every start, but one flapping worker then quarantines
+    /// every worker on its host.
+    pub per_host: bool,
+}
+
+impl Default for FlapPolicy {
+    fn default() -> Self {
+        Self { max_flaps: 3, max_lost_jobs: 3, max_failed_probes: 3, window: Duration::from_secs(60), quarantine: Duration::from_secs(300), per_host: false }
+    }
+}
+
+/// Tracks worker disconnects, lost jobs and failed health probes and quarantines workers that
+/// flap, keep losing jobs or keep failing probes. Workers are keyed by their address, or by
+/// their host with FlapPolicy::per_host.
+#[derive(Debug)]
+pub struct FlapDetector {
+    policy: FlapPolicy,
+    disconnects: HashMap<String, VecDeque<SystemTime>>,
+    lost_jobs: HashMap<String, VecDeque<SystemTime>>,
+    failed_probes: HashMap<String, VecDeque<SystemTime>>,
+    quarantined_until: HashMap<String, SystemTime>,
+    /// Workers being stopped on purpose, whose next disconnect isn't a flap.
+    expected: HashSet<String>,
+}
+
+impl FlapDetector {
+    /// Create a new FlapDetector enforcing the given policy.
+    pub fn new(policy: FlapPolicy) -> Self {
+        Self { policy, disconnects: HashMap::new(), lost_jobs: HashMap::new(), failed_probes: HashMap::new(), quarantined_until: HashMap::new(), expected: HashSet::new() }
+    }
+
+    /// Records a worker disconnect. Returns the end of the worker's quarantine if this
+    /// disconnect pushed it over the flap threshold.
+    pub fn record_disconnect(&mut self, worker_address: &str, now: SystemTime) -> Option<SystemTime> {
+        if self.expected.remove(worker_address) {
+            return None;
+        }
+        let key = self.key(worker_address);
+        let history = self.disconnects.entry(key.to_string()).or_default();
+        if !record_within(history, now, self.policy.window, self.policy.max_flaps) {
+            return None;
+        }
+        let until = now + self.policy.quarantine;
+        self.quarantined_until.insert(key.to_string(), until);
+        Some(until)
+    }
+
+    /// Records a job presumed lost on a worker. Returns the end of the worker's quarantine if
+    /// this pushed it over the lost job threshold. Lost jobs are counted per worker even with
+    /// FlapPolicy::per_host, as a worker losing jobs says nothing about the others on its host.
+    pub fn record_lost_job(&mut self, worker_address: &str, now: SystemTime) -> Option<SystemTime> {
+        let history = self.lost_jobs.entry(worker_address.to_string()).or_default();
+        if !record_within(history, now, self.policy.window, self.policy.max_lost_jobs) {
+            return None;
+        }
+        let until = now + self.policy.quarantine;
+        self.quarantined_until.insert(self.key(worker_address).to_string(), until);
+        Some(until)
+    }
+
+    /// Records a failed health probe of a worker. Returns the end of the worker's quarantine if
+    /// this pushed it over the failed probe threshold. Like lost jobs, failed probes are counted
+    /// per worker even with FlapPolicy::per_host.
+    pub fn record_failed_probe(&mut self, worker_address: &str, now: SystemTime) -> Option<SystemTime> {
+        let history = self.failed_probes.entry(worker_address.to_string()).or_default();
+        if !record_within(history, now, self.policy.window, self.policy.max_failed_probes) {
+            return None;
+        }
+        let until = now + self.policy.quarantine;
+        self.quarantined_until.insert(self.key(worker_address).to_string(), until);
+        Some(until)
+    }
+
+    /// Records a worker answering its health probe, ending its streak of failed probes.
+    pub fn record_healthy_probe(&mut self, worker_address: &str) {
+        self.failed_probes.remove(worker_address);
+    }
+
+    /// Marks a worker as being stopped on purpose, so its next disconnect isn't counted.
+    pub fn expect_disconnect(&mut self, worker_address: &str) {
+        self.expected.insert(worker_address.to_string());
+    }
+
+    /// Returns the end of the worker's quarantine, or None if it isn't quarantined.
+    /// Expired quarantines are discarded.
+    pub fn quarantined_until(&mut self, worker_address: &str, now: SystemTime) -> Option<SystemTime> {
+        let key = self.key(worker_address);
+        match self.quarantined_until.get(key) {
+            Some(until) if *until > now => Some(*until),
+            Some(_) => {
+                self.quarantined_until.remove(key);
+                None
+            },
+            None => None,
+        }
+    }
+
+    /// Returns the number of disconnects recorded for the worker within the current window.
+    pub fn flap_count(&self, worker_address: &str, now: SystemTime) -> u32 {
+        self.disconnects.get(self.key(worker_address))
+            .map(|history| history.iter()
+                .filter(|t| now.duration_since(**t).unwrap_or_default() <= self.policy.window)
+                .count() as u32)
+            .unwrap_or(0)
+    }
+
+    /// What the worker's history and quarantine are kept under: its address, or its host.
+    fn key<'a>(&self, worker_address: &'a str) -> &'a str {
+        if self.policy.per_host { host_of(worker_address) } else { worker_address }
+    }
+}
+
+/// Adds an occurrence to a worker's history, dropping those older than the window. Returns true,
+/// clearing the history, if there are now more than max in the window.
+fn record_within(history: &mut VecDeque<SystemTime>, now: SystemTime, window: Duration, max: u32) -> bool {
+    history.push_back(now);
+    history.retain(|t| now.duration_since(*t).unwrap_or_default() <= window);
+    if history.len() as u32 > max {
+        history.clear();
+        true
+    } else {
+        false
+    }
+}
+
+/// Strips the port from a worker address, leaving the host.
+fn host_of(worker_address: &str) -> &str {
+    worker_address.rsplit_once(':').map(|(host, _)| host).unwrap_or(worker_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(per_host: bool) -> FlapPolicy {
+        FlapPolicy { max_flaps: 1, max_lost_jobs: 1, max_failed_probes: 1, per_host, ..FlapPolicy::default() }
+    }
+
+    #[test]
+    fn flapping_worker_is_quarantined_alone() {
+        let mut detector = FlapDetector::new(policy(false));
+        let now = SystemTime::now();
+        assert_eq!(detector.record_disconnect("10.0.0.1:7000", now), None);
+        let until = detector.record_disconnect("10.0.0.1:7000", now).expect("second flap should quarantine");
+        assert_eq!(detector.quarantined_until("10.0.0.1:7000", now), Some(until));
+        assert_eq!(detector.quarantined_until("10.0.0.1:7001", now), None);
+        assert_eq!(detector.flap_count("10.0.0.1:7001", now), 0);
+    }
+
+    #[test]
+    fn per_host_quarantines_the_whole_host() {
+        let mut detector = FlapDetector::new(policy(true));
+        let now = SystemTime::now();
+        detector.record_disconnect("10.0.0.1:7000", now);
+        let until = detector.record_disconnect("10.0.0.1:7001", now).expect("flaps on a host should add up");
+        assert_eq!(detector.quarantined_until("10.0.0.1:7002", now), Some(until));
+        assert_eq!(detector.quarantined_until("10.0.0.2:7000", now), None);
+    }
+
+    #[test]
+    fn lost_jobs_are_counted_per_worker() {
+        let mut detector = FlapDetector::new(policy(true));
+        let now = SystemTime::now();
+        assert_eq!(detector.record_lost_job("10.0.0.1:7000", now), None);
+        assert_eq!(detector.record_lost_job("10.0.0.1:7001", now), None);
+        assert_eq!(detector.quarantined_until("10.0.0.1:7000", now), None);
+        assert!(detector.record_lost_job("10.0.0.1:7000", now).is_some());
+    }
+
+    #[test]
+    fn failed_probes_are_counted_per_worker() {
+        let mut detector = FlapDetector::new(policy(true));
+        let now = SystemTime::now();
+        assert_eq!(detector.record_failed_probe("10.0.0.1:7000", now), None);
+        assert_eq!(detector.record_failed_probe("10.0.0.1:7001", now), None);
+        // A healthy probe only clears the streak of the worker that answered
+        detector.record_healthy_probe("10.0.0.1:7001");
+        assert!(detector.record_failed_probe("10.0.0.1:7000", now).is_some());
+    }
+
+    #[test]
+    fn expected_disconnects_are_not_flaps() {
+        let mut detector = FlapDetector::new(policy(false));
+        let now = SystemTime::now();
+        detector.expect_disconnect("10.0.0.1:7000");
+        assert_eq!(detector.record_disconnect("10.0.0.1:7000", now), None);
+        assert_eq!(detector.record_disconnect("10.0.0.1:7000", now), None);
+        assert_eq!(detector.flap_count("10.0.0.1:7000", now), 1);
+    }
+
+    #[test]
+    fn flaps_outside_the_window_are_forgotten() {
+        let mut detector = FlapDetector::new(policy(false));
+        let now = SystemTime::now();
+        detector.record_disconnect("10.0.0.1:7000", now);
+        let later = now + FlapPolicy::default().window + Duration::from_secs(1);
+        assert_eq!(detector.record_disconnect("10.0.0.1:7000", later), None);
+    }
+
+    #[test]
+    fn quarantine_expires() {
+        let mut detector = FlapDetector::new(policy(false));
+        let now = SystemTime::now();
+        detector.record_disconnect("10.0.0.1:7000", now);
+        let until = detector.record_disconnect("10.0.0.1:7000", now).unwrap();
+        assert_eq!(detector.quarantined_until("10.0.0.1:7000", until), None);
+    }
+}
@@ -0,0 +1,61 @@
+use std::cmp::Reverse;
+
+use shared::{HandoffAck, HandoffSummary, OrchestratorMessage, Peer, PeerList, orchestrator_message};
+
+use crate::diagnostics::WorkerHealth;
+use crate::orchestrator::Orchestrator;
+
+impl Orchestrator {
+    /// Answers a departing worker's request for peers to hand its compiled modules to: the
+    /// other online workers dedicated to the same namespace, or like it to none, with the most
+    /// available credits first. Relayed workers can't be reached, so aren't peers.
+    pub(crate) async fn handle_peer_list_request(&self, worker_address: &str) {
+        let Some(namespace) = self.diagnostics.workers.get(worker_address).map(|w| w.namespace.clone()) else {
+            tracing::warn!(worker = %worker_address, "worker not found in diagnostics store during peer list request");
+            return;
+        };
+        let relayed_workers = self.relayed_workers.lock().await.clone();
+        let candidates: Vec<String> = self.diagnostics.workers.iter()
+            .filter(|w| w.address != worker_address && w.health() == WorkerHealth::Online && w.namespace == namespace)
+            .filter(|w| !relayed_workers.contains(&w.address))
+            .map(|w| w.address.clone())
+            .collect();
+        let mut peers: Vec<Peer> = {
+            let registry = self.registry.lock().await;
+            candidates.into_iter()
+                .filter_map(|address| registry.available_credits(&address).map(|available_credits| Peer { address, available_credits }))
+                .collect()
+        };
+        peers.sort_by(|a, b| (Reverse(a.available_credits), &a.address).cmp(&(Reverse(b.available_credits), &b.address)));
+        tracing::info!(worker = %worker_address, peers = peers.len(), "sending peers for module handoff");
+
+        let message = OrchestratorMessage { message: Some(orchestrator_message::Message::PeerList(PeerList { peers })) };
+        self.send_to_worker(worker_address, message).await;
+    }
+
+    /// Records the modules a departing worker handed to its peers, then acknowledges the
+    /// summary so the worker can disconnect.
+    pub(crate) async fn handle_handoff_summary(&self, worker_address: &str, summary: &HandoffSummary) {
+        tracing::info!(
+            worker = %worker_address,
+            modules = summary.modules.len(),
+            skipped = summary.skipped,
+            failed = summary.failed,
+            bytes = summary.bytes,
+            "worker handed off its compiled modules",
+        );
+        self.diagnostics.handle_modules_handed_off(worker_address, summary);
+
+        let message = OrchestratorMessage { message: Some(orchestrator_message::Message::HandoffAck(HandoffAck {})) };
+        self.send_to_worker(worker_address, message).await;
+    }
+
+    async fn send_to_worker(&self, worker_address: &str, message: OrchestratorMessage) {
+        let Some(tx) = self.worker_streams.lock().await.get(worker_address).cloned() else {
+            return;
+        };
+        if tx.send(Ok(message)).await.is_err() {
+            tracing::debug!(worker = %worker_address, "worker disconnected before its handoff reply was sent");
+        }
+    }
+}
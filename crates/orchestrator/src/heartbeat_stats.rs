@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+/// Weight of the newest sample in the moving averages.
+const ALPHA: f64 = 0.2;
+
+/// Share of the expected interval the average interval may drift by before the Worker is
+/// flagged.
+const MAX_INTERVAL_DRIFT: f64 = 0.5;
+
+/// How far a Worker's clock may be from the Orchestrator's before the Worker is flagged. The
+/// estimate includes the heartbeat's transit time, so it must allow for some of that.
+const MAX_CLOCK_SKEW_MS: f64 = 1000.0;
+
+/// Heartbeats needed before a Worker's averages are trusted enough to flag it.
+const MIN_SAMPLES: u32 = 5;
+
+/// Statistics of a Worker's heartbeats: how regularly they arrive, and how far its clock is
+/// from the Orchestrator's. Broken NTP on a Worker's host shows up as skew, and a stalled or
+/// overloaded Worker as intervals drifting from the one it said it sends them at.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeartbeatStats {
+    /// When the last heartbeat arrived, in ms since the unix epoch by the Orchestrator's clock.
+    last_arrival_ms: Option<u64>,
+    /// Intervals between heartbeats seen so far.
+    pub samples: u32,
+    /// The interval the Worker said it sends heartbeats at. None for Workers that don't say.
+    pub expected_interval: Option<Duration>,
+    /// Moving average of the intervals between heartbeats arriving, in ms.
+    pub interval_mean_ms: Option<f64>,
+    /// Moving variance of those intervals, in ms².
+    pub interval_variance: f64,
+    /// Moving average of when heartbeats arrived minus when the Worker said it sent them, in
+    /// ms. Positive if the Worker's clock is behind. None for Workers that don't say.
+    pub clock_skew_ms: Option<f64>,
+}
+
+/// Folds a sample into an exponentially weighted moving average.
+pub fn ewma(mean: Option<f64>, sample: f64) -> f64 {
+    match mean {
+        Some(mean) => mean + ALPHA * (sample - mean),
+        None => sample,
+    }
+}
+
+/// Folds a sample into an exponentially weighted moving variance around the average before
+/// the sample.
+pub fn ewm_variance(mean: Option<f64>, variance: f64, sample: f64) -> f64 {
+    match mean {
+        Some(mean) => {
+            let diff = sample - mean;
+            (1.0 - ALPHA) * (variance + ALPHA * diff * diff)
+        },
+        None => 0.0,
+    }
+}
+
+impl HeartbeatStats {
+    /// The statistics after a heartbeat that arrived at arrived_at_ms, sent at sent_at_ms by
+    /// the Worker's clock, from a Worker that sends them every expected_interval.
+    pub fn record(self, arrived_at_ms: u64, sent_at_ms: Option<u64>, expected_interval: Option<Duration>) -> Self {
+        let mut stats = Self { last_arrival_ms: Some(arrived_at_ms), expected_interval, ..self };
+        if let Some(last_arrival_ms) = self.last_arrival_ms {
+            let interval = arrived_at_ms.saturating_sub(last_arrival_ms) as f64;
+            stats.interval_variance = ewm_variance(self.interval_mean_ms, self.interval_variance, interval);
+            stats.interval_mean_ms = Some(ewma(self.interval_mean_ms, interval));
+            stats.samples = self.samples + 1;
+        }
+        if let Some(sent_at_ms) = sent_at_ms {
+            stats.clock_skew_ms = Some(ewma(self.clock_skew_ms, arrived_at_ms as f64 - sent_at_ms as f64));
+        }
+        stats
+    }
+
+    /// Standard deviation of the intervals between heartbeats.
+    pub fn interval_jitter(&self) -> Option<Duration> {
+        self.interval_mean_ms.map(|_| Duration::from_secs_f64(self.interval_variance.sqrt() / 1000.0))
+    }
+
+    /// Whether heartbeats arrive far more or less often than the Worker sends them.
+    pub fn interval_drifting(&self) -> bool {
+        match (self.interval_mean_ms, self.expected_interval) {
+            (Some(mean), Some(expected)) if self.samples >= MIN_SAMPLES => {
+                let expected = expected.as_millis() as f64;
+                (mean - expected).abs() > expected * MAX_INTERVAL_DRIFT
+            },
+            _ => false,
+        }
+    }
+
+    /// Whether the Worker's clock is far from the Orchestrator's.
+    pub fn clock_skewed(&self) -> bool {
+        self.clock_skew_ms.is_some_and(|skew| skew.abs() > MAX_CLOCK_SKEW_MS)
+    }
+
+    pub fn anomalous(&self) -> bool {
+        self.interval_drifting() || self.clock_skewed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    /// Stats after heartbeats arriving every interval_ms, each sent skew_ms before it arrived.
+    fn steady(count: u64, interval_ms: u64, skew_ms: u64) -> HeartbeatStats {
+        (0..count).fold(HeartbeatStats::default(), |stats, i| {
+            let arrived_at_ms = 1_000_000 + i * interval_ms;
+            stats.record(arrived_at_ms, Some(arrived_at_ms - skew_ms), Some(SECOND))
+        })
+    }
+
+    #[test]
+    fn the_average_starts_at_the_first_sample_and_moves_towards_later_ones() {
+        assert_eq!(ewma(None, 1000.0), 1000.0);
+        assert_eq!(ewma(Some(1000.0), 2000.0), 1200.0);
+        assert_eq!(ewm_variance(None, 0.0, 1000.0), 0.0);
+        assert_eq!(ewm_variance(Some(1000.0), 0.0, 1000.0), 0.0);
+        assert!(ewm_variance(Some(1000.0), 0.0, 2000.0) > 0.0);
+    }
+
+    #[test]
+    fn the_first_heartbeat_has_no_interval() {
+        let stats = HeartbeatStats::default().record(1_000_000, None, Some(SECOND));
+        assert_eq!((stats.samples, stats.interval_mean_ms, stats.interval_jitter()), (0, None, None));
+        assert_eq!(stats.clock_skew_ms, None);
+    }
+
+    #[test]
+    fn steady_heartbeats_have_no_jitter_and_are_not_flagged() {
+        let stats = steady(20, 1000, 5);
+        assert_eq!(stats.samples, 19);
+        assert_eq!(stats.interval_mean_ms, Some(1000.0));
+        assert_eq!(stats.interval_jitter(), Some(Duration::ZERO));
+        assert_eq!(stats.clock_skew_ms, Some(5.0));
+        assert!(!stats.anomalous());
+    }
+
+    #[test]
+    fn irregular_heartbeats_have_jitter() {
+        let stats = [0, 900, 2100, 2900, 4100, 4900].iter()
+            .fold(HeartbeatStats::default(), |stats, &at| stats.record(at, None, Some(SECOND)));
+        let jitter = stats.interval_jitter().unwrap();
+        assert!(jitter > Duration::from_millis(50), "{jitter:?}");
+        assert!(!stats.interval_drifting());
+    }
+
+    #[test]
+    fn intervals_drifting_from_the_expected_one_are_flagged() {
+        assert!(steady(20, 3000, 0).interval_drifting());
+        assert!(steady(20, 200, 0).interval_drifting());
+        assert!(!steady(20, 1400, 0).interval_drifting());
+    }
+
+    #[test]
+    fn drift_is_only_flagged_after_enough_heartbeats() {
+        assert!(!steady(MIN_SAMPLES as u64, 3000, 0).interval_drifting());
+        assert!(steady(MIN_SAMPLES as u64 + 1, 3000, 0).interval_drifting());
+    }
+
+    #[test]
+    fn workers_that_dont_say_their_interval_are_never_flagged_for_drift() {
+        let stats = (0..20).fold(HeartbeatStats::default(), |stats, i| stats.record(i * 3000, None, None));
+        assert!(!stats.interval_drifting());
+    }
+
+    #[test]
+    fn clocks_far_ahead_or_behind_are_flagged() {
+        let behind = steady(3, 1000, 5000);
+        assert!(behind.clock_skewed());
+        assert_eq!(behind.clock_skew_ms, Some(5000.0));
+
+        let ahead = (0..3).fold(HeartbeatStats::default(), |stats, i| {
+            stats.record(1_000_000 + i * 1000, Some(1_005_000 + i * 1000), Some(SECOND))
+        });
+        assert_eq!(ahead.clock_skew_ms, Some(-5000.0));
+        assert!(ahead.clock_skewed());
+        assert!(ahead.anomalous());
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use tonic::Request;
+
+use crate::namespaces::Namespace;
+
+/// Who a client is, for quotas: the namespace of the token it authenticated with (or that the
+/// client password is pinned to, see check_client_auth), or else the host it connects from.
+/// Unlike the client's address, it stays the same when the client opens another connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ClientIdentity {
+    Token(Namespace),
+    Host(IpAddr),
+}
+
+impl ClientIdentity {
+    /// The identity of the client that sent the request from client_address.
+    pub fn of_request<T>(request: &Request<T>, client_address: SocketAddr) -> Self {
+        match request.extensions().get::<Namespace>() {
+            Some(namespace) => Self::Token(namespace.clone()),
+            None => Self::Host(client_address.ip()),
+        }
+    }
+}
+
+impl fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Token(namespace) => write!(f, "token:{namespace}"),
+            Self::Host(ip) => write!(f, "{ip}"),
+        }
+    }
+}
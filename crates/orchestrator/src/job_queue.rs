@@ -1,36 +1,372 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::SystemTime;
+
 use hashlink::LinkedHashMap;
 use shared::WorkerResponse;
-use tokio::sync::oneshot;
-use uuid::Uuid;
+use tokio::sync::{Mutex, oneshot};
+use shared::JobId;
+
+use crate::identity::ClientIdentity;
+use crate::module_uploads::ModuleHash;
+use crate::namespaces::Namespace;
+use crate::queue_stats::QueueStats;
 
-/// FIFO queue of pending jobs, skipping any whose requester has disconnected.
+/// Queue of pending jobs, fair across clients. Each client has its own FIFO sub-queue per
+/// namespace and jobs are dequeued round-robin across them, so one client queueing many jobs
+/// can't starve the others. Clients are told apart by their ClientIdentity, so one opening
+/// more connections gets no more turns. Jobs whose requester has disconnected are skipped.
 #[derive(Debug)]
 pub struct JobQueue {
-    inner: LinkedHashMap<Uuid, oneshot::Sender<WorkerResponse>>
+    client_queues: HashMap<ClientKey, LinkedHashMap<JobId, QueuedJob>>,
+    // sub-queues with at least one queued job, in round-robin order
+    rotation: VecDeque<ClientKey>,
+    job_clients: HashMap<JobId, ClientKey>,
+    stats: Arc<QueueStats>,
+}
+
+/// Identifies a client's sub-queue. All of its jobs share the namespace.
+type ClientKey = (Namespace, ClientIdentity);
+
+#[derive(Debug)]
+struct QueuedJob {
+    /// The blake3 hash of the job's wasm, if it's one whose upload is coordinated.
+    wasm_hash: Option<ModuleHash>,
+    tx: oneshot::Sender<WorkerResponse>,
+    enqueued_at: SystemTime,
 }
 
 impl JobQueue {
-    /// Create a new, empty JobQueue.
-    pub fn new() -> JobQueue {
-        JobQueue { inner: LinkedHashMap::new() }
+    /// Create a new, empty JobQueue, which keeps the queue length in stats.
+    pub fn new(stats: Arc<QueueStats>) -> JobQueue {
+        JobQueue { client_queues: HashMap::new(), rotation: VecDeque::new(), job_clients: HashMap::new(), stats }
+    }
+
+    pub fn stats(&self) -> &QueueStats {
+        &self.stats
+    }
+
+    /// How many jobs are queued. A job whose requester disconnected is counted until its
+    /// QueueGuard takes it out.
+    pub fn len(&self) -> usize {
+        self.job_clients.len()
     }
 
-    /// Add a job to the back of the queue.
-    pub fn enqueue(&mut self, job_id: Uuid, tx: oneshot::Sender<WorkerResponse>) {
-        self.inner.insert(job_id, tx);
+    /// Add a job to the back of its client's sub-queue for the namespace.
+    pub fn enqueue(&mut self, job_id: JobId, namespace: &Namespace, client: &ClientIdentity, wasm_hash: Option<ModuleHash>, tx: oneshot::Sender<WorkerResponse>) {
+        let key = (namespace.clone(), client.clone());
+        let client_queue = self.client_queues.entry(key.clone()).or_insert_with(|| {
+            self.rotation.push_back(key.clone());
+            LinkedHashMap::new()
+        });
+        if client_queue.insert(job_id, QueuedJob { wasm_hash, tx, enqueued_at: SystemTime::now() }).is_none() {
+            self.stats.job_enqueued();
+        }
+        self.job_clients.insert(job_id, key);
     }
 
-    /// Remove and return the next job whose sender is still open, discarding any that have been cancelled.
-    pub fn dequeue(&mut self) -> Option<(Uuid, oneshot::Sender<WorkerResponse>)> {
-        while let Some((job_id, tx)) = self.inner.pop_front() {
-            if !tx.is_closed() {
-                return Some((job_id, tx))
+    /// Remove and return the next job whose sender is still open and whose namespace
+    /// can_dispatch accepts, taking from each sub-queue in turn and discarding any jobs that
+    /// have been cancelled. Sub-queues whose namespace isn't accepted keep their place.
+    pub fn dequeue(&mut self, mut can_dispatch: impl FnMut(&Namespace) -> bool) -> Option<(JobId, Namespace, Option<ModuleHash>, oneshot::Sender<WorkerResponse>)> {
+        for _ in 0..self.rotation.len() {
+            let Some(key) = self.rotation.pop_front() else {
+                break;
+            };
+            let Some(client_queue) = self.client_queues.get_mut(&key) else {
+                continue;
+            };
+            if !can_dispatch(&key.0) {
+                self.rotation.push_back(key);
+                continue;
+            }
+
+            let mut next = None;
+            while let Some((job_id, job)) = client_queue.pop_front() {
+                self.job_clients.remove(&job_id);
+                self.stats.job_dequeued();
+                if !job.tx.is_closed() {
+                    next = Some((job_id, key.0.clone(), job.wasm_hash, job.tx));
+                    break;
+                }
+            }
+
+            // Move the sub-queue to the back of the rotation, or drop it once it is empty
+            if client_queue.is_empty() {
+                self.client_queues.remove(&key);
+            } else {
+                self.rotation.push_back(key);
+            }
+
+            if next.is_some() {
+                return next;
             }
         }
         None
     }
 
-    pub fn cancel(&mut self, job_id: &Uuid) -> bool {
-        self.inner.remove(job_id).is_some()
+    /// When the longest-waiting job whose requester is still connected was enqueued, or None
+    /// if there is no such job.
+    pub fn oldest_enqueued_at(&self) -> Option<SystemTime> {
+        self.client_queues.values()
+            .filter_map(|client_queue| client_queue.values().find(|job| !job.tx.is_closed()))
+            .map(|job| job.enqueued_at)
+            .min()
+    }
+
+    /// How many jobs will be dequeued up to and including this one if no more are enqueued, or
+    /// None if it isn't queued. Counts every sub-queue ahead of it in the rotation up to its
+    /// own depth, and every other sub-queue up to one less, ignoring which namespaces workers
+    /// can take.
+    pub fn position_of(&self, job_id: &JobId) -> Option<usize> {
+        let key = self.job_clients.get(job_id)?;
+        let depth = self.client_queues.get(key)?.keys().position(|id| id == job_id)?;
+        let rotation_index = self.rotation.iter().position(|k| k == key)?;
+        let ahead: usize = self.rotation.iter().enumerate()
+            .filter(|(_, k)| *k != key)
+            .map(|(i, k)| {
+                let turns = if i < rotation_index { depth + 1 } else { depth };
+                self.client_queues.get(k).map_or(0, |q| q.len().min(turns))
+            })
+            .sum();
+        Some(ahead + depth + 1)
+    }
+
+    /// Remove a queued job if its requester has disconnected. Returns whether it was removed.
+    pub fn remove_disconnected(&mut self, job_id: &JobId) -> bool {
+        let disconnected = self.job_clients.get(job_id)
+            .and_then(|key| self.client_queues.get(key)?.get(job_id))
+            .is_some_and(|job| job.tx.is_closed());
+        disconnected && self.cancel(job_id)
+    }
+
+    /// Remove a queued job. Returns false if it wasn't queued.
+    pub fn cancel(&mut self, job_id: &JobId) -> bool {
+        let Some(key) = self.job_clients.remove(job_id) else {
+            return false;
+        };
+        let Some(client_queue) = self.client_queues.get_mut(&key) else {
+            return false;
+        };
+        let removed = client_queue.remove(job_id).is_some();
+        if removed {
+            self.stats.job_dequeued();
+        }
+        if client_queue.is_empty() {
+            self.client_queues.remove(&key);
+            self.rotation.retain(|k| *k != key);
+        }
+        removed
+    }
+}
+
+/// RAII guard that takes a job out of the queue if its request is dropped (client disconnected)
+/// before the job is dispatched, rather than when its turn comes, so that it stops counting
+/// towards the queue's length and stats straight away.
+pub struct QueueGuard {
+    queue: Arc<Mutex<JobQueue>>,
+    job_id: JobId,
+    dispatched: bool,
+}
+
+impl QueueGuard {
+    pub fn new(queue: Arc<Mutex<JobQueue>>, job_id: JobId) -> Self {
+        Self { queue, job_id, dispatched: false }
+    }
+
+    pub fn set_dispatched(&mut self) {
+        self.dispatched = true;
+    }
+}
+
+impl Drop for QueueGuard {
+    /// The request's receiver is gone by the time the task runs, so the job counts as
+    /// disconnected unless it was queued again meanwhile by a retry of the request.
+    fn drop(&mut self) {
+        if self.dispatched {
+            return;
+        }
+        let queue = self.queue.clone();
+        let job_id = self.job_id;
+        tokio::spawn(async move {
+            if queue.lock().await.remove_disconnected(&job_id) {
+                tracing::debug!(job_id = %job_id, "requester disconnected, job dropped from queue");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn host(last: u8) -> ClientIdentity {
+        ClientIdentity::Host(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last)))
+    }
+
+    /// Enqueues a job for each client in turn, returning the job ids and the receivers that
+    /// keep their requesters connected.
+    fn enqueue_all(queue: &mut JobQueue, clients: &[ClientIdentity]) -> (Vec<JobId>, Vec<oneshot::Receiver<WorkerResponse>>) {
+        clients.iter().map(|client| {
+            let (tx, rx) = oneshot::channel();
+            let job_id = JobId::random();
+            queue.enqueue(job_id, &Namespace::default(), client, None, tx);
+            (job_id, rx)
+        }).unzip()
+    }
+
+    fn drain(queue: &mut JobQueue) -> Vec<JobId> {
+        std::iter::from_fn(|| queue.dequeue(|_| true).map(|(job_id, ..)| job_id)).collect()
+    }
+
+    #[test]
+    fn interleaved_clients_are_dispatched_alternately() {
+        let mut queue = JobQueue::new(Arc::default());
+        let (a, b) = (host(1), host(2));
+        // a submits three jobs before b submits its two
+        let (jobs, _rx) = enqueue_all(&mut queue, &[a.clone(), a.clone(), a, b.clone(), b]);
+        assert_eq!(drain(&mut queue), [jobs[0], jobs[3], jobs[1], jobs[4], jobs[2]]);
+    }
+
+    #[test]
+    fn a_client_gets_one_turn_however_it_connects() {
+        // Jobs from the same host share a sub-queue, whichever connection sent them
+        let mut queue = JobQueue::new(Arc::default());
+        let (jobs, _rx) = enqueue_all(&mut queue, &[host(1), host(1), host(1), host(2)]);
+        assert_eq!(queue.len(), 4);
+        assert_eq!(drain(&mut queue), [jobs[0], jobs[3], jobs[1], jobs[2]]);
+    }
+
+    #[test]
+    fn disconnected_requesters_lose_their_turn() {
+        let mut queue = JobQueue::new(Arc::default());
+        let (jobs, mut rx) = enqueue_all(&mut queue, &[host(1), host(1), host(2)]);
+        drop(rx.remove(0));
+        assert_eq!(drain(&mut queue), [jobs[1], jobs[2]]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn disconnected_requesters_stop_counting_once_removed() {
+        let stats = Arc::new(QueueStats::default());
+        let mut queue = JobQueue::new(stats.clone());
+        let (jobs, mut rx) = enqueue_all(&mut queue, &[host(1), host(2)]);
+
+        // Still connected, so kept
+        assert!(!queue.remove_disconnected(&jobs[0]));
+        drop(rx.remove(0));
+        assert!(queue.remove_disconnected(&jobs[0]));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(stats.snapshot(SystemTime::now()).queued_jobs, 1);
+        assert_eq!(queue.position_of(&jobs[1]), Some(1));
+    }
+
+    /// Something done to the queue. Indices pick among the jobs still queued, whichever exist.
+    #[derive(Clone, Debug)]
+    enum Op {
+        Enqueue { client: u8, namespace: u8 },
+        /// Dequeue, from one namespace if given.
+        Dequeue(Option<u8>),
+        Cancel(usize),
+        /// Cancel a job that isn't queued.
+        CancelUnknown,
+        /// The requester disconnects, and its guard takes the job out unless told not to.
+        Disconnect { index: usize, remove: bool },
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (0..3u8, 0..2u8).prop_map(|(client, namespace)| Op::Enqueue { client, namespace }),
+            2 => proptest::option::of(0..2u8).prop_map(Op::Dequeue),
+            1 => any::<usize>().prop_map(Op::Cancel),
+            1 => Just(Op::CancelUnknown),
+            1 => (any::<usize>(), any::<bool>()).prop_map(|(index, remove)| Op::Disconnect { index, remove }),
+        ]
+    }
+
+    fn namespace(n: u8) -> Namespace {
+        Namespace::new(&format!("ns-{n}")).unwrap()
+    }
+
+    proptest! {
+        /// However jobs are enqueued, dequeued, cancelled and disconnected, every job whose
+        /// requester stays connected is dequeued exactly once, and the queue's length and stats
+        /// always count exactly the jobs still queued.
+        #[test]
+        fn no_job_is_lost_or_duplicated(ops in proptest::collection::vec(op(), 1..200)) {
+            let stats = Arc::new(QueueStats::default());
+            let mut queue = JobQueue::new(stats.clone());
+            // The jobs that should still be queued, with their namespace, in enqueue order
+            let mut queued: Vec<(JobId, Namespace)> = Vec::new();
+            let mut receivers: HashMap<JobId, oneshot::Receiver<WorkerResponse>> = HashMap::new();
+            let mut dequeued = HashSet::new();
+
+            for op in ops {
+                match op {
+                    Op::Enqueue { client, namespace: n } => {
+                        let (tx, rx) = oneshot::channel();
+                        let job_id = JobId::random();
+                        queue.enqueue(job_id, &namespace(n), &host(client), None, tx);
+                        queued.push((job_id, namespace(n)));
+                        receivers.insert(job_id, rx);
+                    },
+                    Op::Dequeue(only) => {
+                        let accepted = only.map(namespace);
+                        match queue.dequeue(|ns| accepted.as_ref().is_none_or(|a| a == ns)) {
+                            Some((job_id, ns, _, _)) => {
+                                prop_assert!(dequeued.insert(job_id), "{job_id} was dequeued twice");
+                                prop_assert!(receivers.contains_key(&job_id), "{job_id} was dequeued after its requester disconnected");
+                                prop_assert!(accepted.as_ref().is_none_or(|a| *a == ns));
+                                let index = queued.iter().position(|(id, _)| *id == job_id);
+                                prop_assert_eq!(index.map(|i| queued.remove(i).1), Some(ns));
+                            },
+                            None => prop_assert!(!queued.iter().any(|(id, ns)| {
+                                receivers.contains_key(id) && accepted.as_ref().is_none_or(|a| a == ns)
+                            }), "a connected job wasn't dequeued"),
+                        }
+                    },
+                    Op::Cancel(index) if !queued.is_empty() => {
+                        let (job_id, _) = queued.remove(index % queued.len());
+                        prop_assert!(queue.cancel(&job_id));
+                        prop_assert!(!queue.cancel(&job_id));
+                    },
+                    Op::Cancel(_) => {},
+                    Op::CancelUnknown => prop_assert!(!queue.cancel(&JobId::random())),
+                    Op::Disconnect { index, remove } if !queued.is_empty() => {
+                        let (job_id, _) = queued[index % queued.len()];
+                        if receivers.contains_key(&job_id) {
+                            // Still connected, so kept
+                            prop_assert!(!queue.remove_disconnected(&job_id));
+                            receivers.remove(&job_id);
+                        }
+                        if remove {
+                            prop_assert!(queue.remove_disconnected(&job_id));
+                            queued.retain(|(id, _)| *id != job_id);
+                        }
+                    },
+                    Op::Disconnect { .. } => {},
+                }
+
+                // Dequeueing discards disconnected jobs it passes over, unseen by the model
+                queued.retain(|(id, _)| receivers.contains_key(id) || queue.position_of(id).is_some());
+                prop_assert_eq!(queue.len(), queued.len());
+                prop_assert_eq!(stats.snapshot(SystemTime::now()).queued_jobs, queued.len() as u64);
+                for (job_id, _) in &queued {
+                    let position = queue.position_of(job_id);
+                    prop_assert!(position.is_some_and(|p| p >= 1 && p <= queued.len()), "{job_id} is at {position:?}");
+                }
+            }
+
+            let connected: HashSet<JobId> = queued.iter().map(|(id, _)| *id).filter(|id| receivers.contains_key(id)).collect();
+            let drained = drain(&mut queue);
+            prop_assert_eq!(drained.len(), connected.len());
+            prop_assert_eq!(drained.into_iter().collect::<HashSet<_>>(), connected);
+            prop_assert_eq!(queue.len(), 0);
+            prop_assert_eq!(stats.snapshot(SystemTime::now()).queued_jobs, 0);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use shared::JobId;
+use shared::events::{JobEvent, JobRecord, epoch_ms};
+
+use crate::namespaces::Namespace;
+
+/// Most events written and fsync'd together.
+const MAX_BATCH: usize = 64;
+
+/// Settings for the Orchestrator's journal.
+#[derive(Debug, Clone)]
+pub struct JournalConfig {
+    pub path: PathBuf,
+    /// The journal is compacted whenever it grows past this size.
+    pub max_bytes: u64,
+    /// How long finished jobs are kept in the journal when it is compacted.
+    pub retention: Duration,
+}
+
+/// Append-only log of job lifecycle events, so that the Orchestrator's record of which jobs
+/// were dispatched survives a crash. Each event is written and fsync'd before the Orchestrator
+/// acknowledges it; events recorded at the same time are written in one batch.
+///
+/// On open, the journal is replayed to recover the jobs it holds, and compacted. A job that
+/// was dispatched but never finished may or may not have run, and is recovered as such.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    tx: mpsc::Sender<Append>,
+    recovered: Arc<Vec<RecoveredJob>>,
+}
+
+/// A job rebuilt from the journal when it was opened.
+#[derive(Debug, Clone)]
+pub struct RecoveredJob {
+    pub record: JobRecord,
+    pub namespace: Namespace,
+    pub client_address: String,
+}
+
+/// One line of the journal: a job event, with the job's owner on queued events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    #[serde(flatten)]
+    pub(crate) event: JobEvent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) namespace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_address: Option<String>,
+}
+
+impl JournalEntry {
+    pub fn new(event: JobEvent) -> Self {
+        Self { event, namespace: None, client_address: None }
+    }
+
+    /// The entry for a job entering the queue, which records who it belongs to.
+    pub fn queued(event: JobEvent, namespace: &Namespace, client_address: &str) -> Self {
+        Self { event, namespace: Some(namespace.to_string()), client_address: Some(client_address.to_string()) }
+    }
+}
+
+#[derive(Debug)]
+struct Append {
+    entry: JournalEntry,
+    written: oneshot::Sender<()>,
+}
+
+impl Journal {
+    /// Opens the journal at the configured path, creating it if needed, and recovers the jobs
+    /// in it. Jobs that were queued but never dispatched are recorded as cancelled, since
+    /// their requests were lost with the previous Orchestrator.
+    pub fn open(config: JournalConfig) -> io::Result<Self> {
+        let mut jobs = read_jobs(&config.path)?;
+        let now_ms = epoch_ms(SystemTime::now());
+        for job in jobs.values_mut() {
+            if job.record.dispatched_at_ms.is_none() && !job.record.is_finished() {
+                job.record.apply(&JobEvent::Cancelled { job_id: job.record.job_id, at_ms: now_ms });
+            }
+        }
+
+        let mut writer = Writer { file: None, size: 0, jobs, config };
+        writer.compact()?;
+        let mut recovered: Vec<RecoveredJob> = writer.jobs.values().cloned().collect();
+        recovered.sort_by_key(|job| job.record.queued_at_ms);
+        let unknown = recovered.iter().filter(|job| !job.record.is_finished()).count();
+        tracing::info!(path = %writer.config.path.display(), jobs = recovered.len(), unknown_outcome = unknown, "journal recovered");
+
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("journal".to_string())
+            .spawn(move || writer.run(rx))?;
+        Ok(Self { tx, recovered: Arc::new(recovered) })
+    }
+
+    /// The jobs recovered when the journal was opened, oldest first.
+    pub fn recovered(&self) -> &[RecoveredJob] {
+        &self.recovered
+    }
+
+    /// Appends an entry, returning once it is durably written.
+    pub async fn record(&self, entry: JournalEntry) {
+        let (written, done) = oneshot::channel();
+        if self.tx.send(Append { entry, written }).is_err() || done.await.is_err() {
+            tracing::error!("ERROR: the journal writer stopped, this should never happen");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Owns the journal file on a dedicated thread, appending entries in batches.
+struct Writer {
+    /// None until compact first opens it.
+    file: Option<File>,
+    size: u64,
+    /// The jobs in the journal, kept to rewrite it when compacting.
+    jobs: HashMap<JobId, RecoveredJob>,
+    config: JournalConfig,
+}
+
+impl Writer {
+    /// Writes entries as they arrive until every Journal handle is dropped. The Orchestrator
+    /// must not acknowledge events the journal failed to record, so a write error exits.
+    fn run(mut self, rx: mpsc::Receiver<Append>) {
+        while let Ok(first) = rx.recv() {
+            let mut batch = vec![first];
+            while batch.len() < MAX_BATCH && let Ok(next) = rx.try_recv() {
+                batch.push(next);
+            }
+
+            let result = self.append(batch.iter().map(|append| &append.entry))
+                .and_then(|()| if self.size > self.config.max_bytes { self.compact() } else { Ok(()) });
+            if let Err(e) = result {
+                tracing::error!(path = %self.config.path.display(), error = %e, "failed to write the journal, exiting");
+                std::process::exit(1);
+            }
+            for append in batch {
+                append.written.send(()).ok();
+            }
+        }
+    }
+
+    fn append<'a>(&mut self, entries: impl Iterator<Item = &'a JournalEntry>) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut buf, entry)?;
+            buf.push(b'\n');
+            apply(&mut self.jobs, entry);
+        }
+        let file = self.file.as_mut().ok_or_else(|| io::Error::other("journal file not open"))?;
+        file.write_all(&buf)?;
+        file.sync_data()?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Drops jobs that finished longer than the retention period ago, then atomically replaces
+    /// the journal with one holding only the remaining jobs' events.
+    fn compact(&mut self) -> io::Result<()> {
+        let cutoff_ms = epoch_ms(SystemTime::now() - self.config.retention);
+        self.jobs.retain(|_, job| job.record.finished_at_ms.is_none_or(|at_ms| at_ms >= cutoff_ms));
+
+        let mut jobs: Vec<&RecoveredJob> = self.jobs.values().collect();
+        jobs.sort_by_key(|job| job.record.queued_at_ms);
+        let mut buf = Vec::new();
+        for job in jobs {
+            for entry in entries_for(job) {
+                serde_json::to_writer(&mut buf, &entry)?;
+                buf.push(b'\n');
+            }
+        }
+
+        let tmp_path = self.config.path.with_extension("compacting");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&buf)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.config.path)?;
+        sync_parent_dir(&self.config.path)?;
+
+        self.file = Some(OpenOptions::new().append(true).open(&self.config.path)?);
+        self.size = buf.len() as u64;
+        tracing::debug!(path = %self.config.path.display(), jobs = self.jobs.len(), bytes = self.size, "journal compacted");
+        Ok(())
+    }
+}
+
+/// Replays the journal at path, returning an empty set of jobs if it doesn't exist. Lines that
+/// can't be parsed, such as one torn by a crash mid-write, are skipped.
+fn read_jobs(path: &Path) -> io::Result<HashMap<JobId, RecoveredJob>> {
+    let mut jobs = HashMap::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(jobs),
+        Err(e) => return Err(e),
+    };
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        match serde_json::from_str::<JournalEntry>(&line) {
+            Ok(entry) => apply(&mut jobs, &entry),
+            Err(e) => tracing::warn!(path = %path.display(), line = number + 1, error = %e, "skipping unreadable journal entry"),
+        }
+    }
+    Ok(jobs)
+}
+
+/// Applies an entry to the job it is for. Entries for a job whose queued entry is missing
+/// are ignored.
+fn apply(jobs: &mut HashMap<JobId, RecoveredJob>, entry: &JournalEntry) {
+    let job_id = entry.event.job_id();
+    if let Some(job) = jobs.get_mut(&job_id) {
+        job.record.apply(&entry.event);
+        return;
+    }
+    if let JobEvent::Queued { .. } = entry.event {
+        let namespace = entry.namespace.as_deref()
+            .and_then(|namespace| Namespace::new(namespace).ok())
+            .unwrap_or_default();
+        jobs.insert(job_id, RecoveredJob {
+            record: JobRecord::new(&entry.event),
+            namespace,
+            client_address: entry.client_address.clone().unwrap_or_default(),
+        });
+    }
+}
+
+/// The entries that rebuild a job's record, as written when compacting.
+fn entries_for(job: &RecoveredJob) -> Vec<JournalEntry> {
+    let record = &job.record;
+    let job_id = record.job_id;
+    let queued = JobEvent::Queued {
+        job_id,
+        at_ms: record.queued_at_ms.unwrap_or_default(),
+        scheduled_for_ms: record.scheduled_for_ms,
+    };
+    let mut entries = vec![JournalEntry::queued(queued, &job.namespace, &job.client_address)];
+    if let (Some(worker_address), Some(at_ms)) = (&record.worker_address, record.dispatched_at_ms) {
+        entries.push(JournalEntry::new(JobEvent::Dispatched { job_id, worker_address: worker_address.clone(), at_ms }));
+    }
+    if let Some(at_ms) = record.finished_at_ms {
+        let finished = match record.state.as_str() {
+            "completed" => JobEvent::Completed { job_id, at_ms },
+            "failed" => JobEvent::Failed { job_id, at_ms },
+            "presumed-lost" => JobEvent::PresumedLost { job_id, worker_address: record.worker_address.clone().unwrap_or_default(), at_ms },
+            _ => JobEvent::Cancelled { job_id, at_ms },
+        };
+        entries.push(JournalEntry::new(finished));
+    }
+    entries
+}
+
+/// Makes a rename in the file's directory durable.
+pub(crate) fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => File::open(".")?.sync_all(),
+    }
+}
@@ -0,0 +1,69 @@
+//! The mini-lambda Orchestrator: assigns jobs from clients to registered workers.
+//!
+//! The `orchestrator` binary is a thin wrapper over this library. To run an Orchestrator
+//! inside another program, start one and serve its routes:
+//!
+//! ```no_run
+//! use orchestrator::{Orchestrator, OrchestratorConfig, build_router};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let orchestrator = Orchestrator::start(OrchestratorConfig {
+//!     client_password: Some("hunter2".into()),
+//!     ..Default::default()
+//! });
+//! tonic::transport::Server::builder()
+//!     .add_routes(build_router(&orchestrator))
+//!     .serve("127.0.0.1:50051".parse()?)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod orchestrator;
+mod config;
+mod client_api;
+mod worker_api;
+pub mod registry;
+mod job_queue;
+mod delay_queue;
+mod errors;
+pub mod diagnostics;
+mod quotas;
+mod identity;
+mod flapping;
+mod webhooks;
+mod admin;
+mod supervisor;
+mod namespaces;
+mod journal;
+mod module_aliases;
+mod module_uploads;
+mod coalescing;
+mod audit;
+mod warm;
+mod handoff;
+mod queue_stats;
+mod relay;
+mod watchdog;
+mod worker_modules;
+mod usage;
+mod validation;
+mod planning;
+mod replay;
+mod out_of_band;
+mod heartbeat_stats;
+mod pipeline;
+pub mod tui;
+
+pub use audit::{AuditConfig, AuditLog};
+pub use config::{InvalidConfig, with_config_file};
+pub use flapping::FlapPolicy;
+pub use heartbeat_stats::HeartbeatStats;
+pub use journal::{Journal, JournalConfig};
+pub use module_aliases::ModuleAliases;
+pub use namespaces::Namespace;
+pub use orchestrator::{Orchestrator, OrchestratorConfig, build_router};
+pub use quotas::ClientQuotas;
+pub use supervisor::{Supervisor, SupervisorConfig};
+pub use usage::UsageLedger;
+pub use validation::ValidationPolicy;
@@ -1,37 +1,199 @@
-mod orchestrator;
-mod client_api;
-mod worker_api;
-mod registry;
-mod job_queue;
-mod errors;
-mod diagnostics;
-mod tui;
-
-use clap::Parser;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
 use tonic::transport::Server;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
-use shared::{client_api_server::ClientApiServer, worker_api_server::WorkerApiServer};
-use crate::{client_api::check_client_auth, orchestrator::Orchestrator, worker_api::check_worker_auth};
+use orchestrator::{AuditConfig, AuditLog, ClientQuotas, FlapPolicy, Journal, JournalConfig, ModuleAliases, Namespace, Orchestrator, OrchestratorConfig, Supervisor, SupervisorConfig, UsageLedger, ValidationPolicy, build_router, tui, with_config_file};
+use orchestrator::tui::keymap::Keymap;
+use shared::CostWeights;
+
+/// Arguments that can't be set from a config file.
+const NOT_CONFIGURABLE: &[&str] = &["config", "print_config", "help"];
+/// Arguments whose values --print-config redacts.
+const SECRETS: &[&str] = &["worker_password", "client_password", "webhook_secret"];
 
 #[derive(Parser, Debug)]
 #[command(about = "Run the Orchestrator server")]
 struct Args {
     #[arg(default_value = "127.0.0.1:50051")]
     addr: std::net::SocketAddr,
+    #[arg(long, help = "Read settings from this TOML file, keyed by flag name, e.g. flap-threshold = 5. Flags on the command line take precedence")]
+    config: Option<PathBuf>,
+    #[arg(long, help = "Print the settings in effect as a config file, with secrets redacted, and exit")]
+    print_config: bool,
     #[arg(long, help = "Password required for workers to register. If not set, no password is required.")]
     worker_password: Option<String>,
     #[arg(long, help = "Password required for clients to submit jobs. If not set, no password is required.")]
     client_password: Option<String>,
+    #[arg(long = "client-token", value_name = "NAMESPACE=TOKEN", value_parser = parse_client_token, help = "Token a client may authenticate with instead of the client password, acting in the given namespace (repeatable)")]
+    client_tokens: Vec<(String, Namespace)>,
+    #[arg(long, help = "Maximum number of jobs each client may have waiting in the queue")]
+    max_queued_jobs_per_client: Option<u32>,
+    #[arg(long, help = "Maximum number of jobs waiting in the queue across all clients. Must be above --max-queued-jobs-per-client")]
+    max_queued_jobs: Option<u32>,
+    #[arg(long, help = "Maximum number of jobs each client may have queued or running at once")]
+    max_concurrent_jobs_per_client: Option<u32>,
+    #[arg(long, help = "Maximum number of jobs each client may submit per UTC day")]
+    max_jobs_per_day_per_client: Option<u32>,
+    #[arg(long, help = "Maximum MiB of modules each client's jobs may send to workers per UTC day")]
+    max_module_mb_per_day_per_client: Option<u64>,
+    #[arg(long, default_value_t = 3, help = "Quarantine a worker after it disconnects more than this many times within the flap window")]
+    flap_threshold: u32,
+    #[arg(long, default_value_t = 60, help = "Window in seconds over which worker disconnects are counted")]
+    flap_window_secs: u64,
+    #[arg(long, default_value_t = 300, help = "How long in seconds a flapping worker is kept out of dispatch")]
+    flap_quarantine_secs: u64,
+    #[arg(long, help = "Count flaps and quarantine workers per host rather than per worker, catching workers that restart on a fresh port. One flapping worker then quarantines every worker on its host")]
+    flap_per_host: bool,
+    #[arg(long, default_value_t = 3, help = "Quarantine a worker after more than this many of its jobs are presumed lost within the flap window")]
+    lost_job_threshold: u32,
+    #[arg(long, help = "Presume a job lost once its worker hasn't finished it this many seconds after its timeout, releasing its credit and counting it against the worker. If not set, jobs are never presumed lost")]
+    lost_job_grace_secs: Option<u64>,
+    #[arg(long, help = "Hold newly registered workers from dispatch until they answer a health probe at the address they registered with")]
+    validate_workers: bool,
+    #[arg(long, default_value_t = 2000, requires = "validate_workers", value_parser = clap::value_parser!(u64).range(1..), help = "Milliseconds a worker has to answer each health probe")]
+    probe_timeout_ms: u64,
+    #[arg(long, default_value_t = 30, requires = "validate_workers", help = "Seconds after a worker registers during which its failed health probes don't count against it")]
+    validation_grace_secs: u64,
+    #[arg(long, default_value_t = 3, help = "Quarantine a worker after more than this many of its health probes fail within the flap window")]
+    failed_probe_threshold: u32,
+    #[arg(long, help = "Hold back this fraction of the credits of workers whose heartbeats show them busy with other orchestrators' jobs, from 0 to 1. If not set, nothing is held back")]
+    out_of_band_reserve: Option<f64>,
+    #[arg(long, help = "Secret used to sign job completion webhooks. If not set, webhooks are unsigned.")]
+    webhook_secret: Option<String>,
+    #[arg(long, value_delimiter = ',', help = "Comma-separated hosts that webhook callback URLs may target. If not set, any host with a public address is allowed. Only these hosts may be private or loopback addresses.")]
+    webhook_allowed_hosts: Option<Vec<String>>,
     #[arg(long, help = "Permit jobs to make network connections")]
     network_access_allowed: bool,
+    #[arg(long, help = "Spawn and supervise this many local worker processes, restarting any that exit")]
+    spawn_workers: Option<usize>,
+    #[arg(long, requires = "spawn_workers", help = "Worker binary to spawn. Defaults to the worker binary next to this one")]
+    worker_bin: Option<PathBuf>,
+    #[arg(long, default_value_t = 4, requires = "spawn_workers", help = "Credits each spawned worker advertises")]
+    worker_credits: u32,
+    #[arg(long, requires = "spawn_workers", help = "Scale spawned workers up to this many while jobs wait in the queue")]
+    max_workers: Option<usize>,
+    #[arg(long, default_value_t = 5, requires = "max_workers", help = "Spawn another worker once a job has waited this many seconds in the queue")]
+    scale_up_wait_secs: u64,
+    #[arg(long, default_value_t = 60, requires = "max_workers", help = "Stop a worker above --spawn-workers once it has been idle this many seconds")]
+    scale_down_idle_secs: u64,
+    #[arg(long, help = "Journal job events to this file so dispatched jobs are accounted for across restarts")]
+    journal_path: Option<PathBuf>,
+    #[arg(long, default_value_t = 64, requires = "journal_path", value_parser = clap::value_parser!(u64).range(1..), help = "Compact the journal once it grows past this many MiB")]
+    journal_max_mb: u64,
+    #[arg(long, default_value_t = 86400, requires = "journal_path", help = "How long in seconds finished jobs are kept in the journal")]
+    journal_retention_secs: u64,
+    #[arg(long, help = "Save published module aliases to this file, loading any already in it")]
+    module_aliases_path: Option<PathBuf>,
+    #[arg(long, default_value_t = 1.0, help = "Cost charged per second a job executes")]
+    cost_per_execution_second: f64,
+    #[arg(long, default_value_t = 0.1, help = "Cost charged when a job's module is compiled for it")]
+    cost_per_compile: f64,
+    #[arg(long, default_value_t = 0.01, help = "Cost charged per MiB of wasm sent to a worker for a job")]
+    cost_per_module_mb: f64,
+    #[arg(long, help = "Save each namespace's daily usage to this file, loading any already in it")]
+    usage_path: Option<PathBuf>,
+    #[arg(long, help = "Hold jobs for a module no worker has while another job uploads it, and send jobs to workers that have their module")]
+    coordinate_uploads: bool,
+    #[arg(long, default_value_t = 60, requires = "coordinate_uploads", value_parser = clap::value_parser!(u64).range(1..), help = "How long jobs wait for another job's upload before failing")]
+    upload_wait_secs: u64,
+    #[arg(long, help = "Append a JSON record of every finished job to this file")]
+    audit_log: Option<PathBuf>,
+    #[arg(long, default_value_t = 100, requires = "audit_log", value_parser = clap::value_parser!(u64).range(1..), help = "Rotate the audit log once it grows past this many MiB, keeping 5 old logs")]
+    audit_log_max_mb: u64,
+    #[arg(long, requires = "audit_log", help = "Include jobs' args in the audit log, instead of only their count")]
+    audit_args: bool,
+    #[arg(long, help = "Serve gRPC reflection describing the client and worker APIs, for tools like grpcurl. It needs no password")]
+    reflection: bool,
     #[arg(long, help = "Launch the interactive TUI dashboard")]
     tui: bool,
+    #[arg(long, default_value_t = 250, requires = "tui", value_parser = clap::value_parser!(u64).range(50..), help = "Milliseconds between TUI data refreshes")]
+    tui_refresh_ms: u64,
+    #[arg(long, default_value = ".", requires = "tui", help = "Directory the TUI saves snapshots and recordings to")]
+    tui_export_dir: PathBuf,
+    #[cfg(feature = "fault-injection")]
+    #[arg(long, help = "Inject faults for resilience testing, e.g. 'dispatch:error_rate=0.2,latency_ms=500'")]
+    fault_spec: Option<String>,
     #[arg(long, help = "Enable debug logging")]
     verbose: bool,
 }
 
+/// Parses a --client-token value of the form NAMESPACE=TOKEN.
+fn parse_client_token(value: &str) -> Result<(String, Namespace), String> {
+    let (namespace, token) = value.split_once('=')
+        .ok_or_else(|| "expected NAMESPACE=TOKEN".to_string())?;
+    let namespace = Namespace::new(namespace).map_err(|e| e.to_string())?;
+    if token.is_empty() {
+        return Err("the token is empty".to_string());
+    }
+    Ok((token.to_string(), namespace))
+}
+
+/// Parses the command line, taking the settings it doesn't give from the --config file if
+/// there is one.
+fn parse_args() -> (Args, ArgMatches) {
+    let mut matches = Args::command().get_matches();
+    if let Some(path) = matches.get_one::<PathBuf>("config").cloned() {
+        let argv = with_config_file(&Args::command(), &matches, std::env::args_os().collect(), &path, NOT_CONFIGURABLE).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+        matches = Args::command().try_get_matches_from(argv).unwrap_or_else(|e| e.exit());
+    }
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    (args, matches)
+}
+
+/// Prints the settings in effect in the config file format, with secrets redacted and defaults
+/// commented out.
+fn print_config(matches: &ArgMatches) {
+    for arg in Args::command().get_arguments() {
+        let id = arg.get_id().as_str();
+        if NOT_CONFIGURABLE.contains(&id) {
+            continue;
+        }
+        let Some(raw) = matches.get_raw(id) else {
+            continue;
+        };
+        let values: Vec<String> = raw
+            .map(|value| toml_value(&redact(id, &value.to_string_lossy())))
+            .collect();
+        let key = arg.get_long().unwrap_or(id);
+        // Defaults are commented out, as setting them would trip flags that require others
+        let comment = if matches.value_source(id) == Some(ValueSource::DefaultValue) { "# " } else { "" };
+        if matches!(arg.get_action(), ArgAction::Append) {
+            println!("{comment}{key} = [{}]", values.join(", "));
+        } else {
+            println!("{comment}{key} = {}", values.join(", "));
+        }
+    }
+}
+
+fn redact(id: &str, value: &str) -> String {
+    if SECRETS.contains(&id) {
+        return "<redacted>".to_string();
+    }
+    match value.split_once('=') {
+        Some((namespace, _token)) if id == "client_tokens" => format!("{namespace}=<redacted>"),
+        _ => value.to_string(),
+    }
+}
+
+/// A flag's value as it would be written in the config file: numbers and booleans bare,
+/// anything else quoted.
+fn toml_value(value: &str) -> String {
+    let bare = value == "true" || value == "false"
+        || value.parse::<i64>().is_ok()
+        || value.parse::<f64>().is_ok_and(f64::is_finite);
+    if bare { value.to_string() } else { toml::Value::String(value.to_string()).to_string() }
+}
+
 fn init_tracing_plain(verbose: bool) {
     let filter = if verbose { "orchestrator=debug" } else { "orchestrator=info" };
     tracing_subscriber::fmt()
@@ -65,38 +227,215 @@ fn init_tracing_tui(verbose: bool) {
 /// Main entry point for the Orchestrator server binary.
 #[tokio::main]
 pub async fn main() {
-    let args = Args::parse();
+    let (args, matches) = parse_args();
+    if args.print_config {
+        print_config(&matches);
+        return;
+    }
+    let keymap = if args.tui {
+        // Reject a bad keybindings file before taking over the terminal
+        let keymap = tui::keymap::keymap_path()
+            .map_or_else(|| Ok(Keymap::default()), |path| Keymap::load(&path))
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+        init_tracing_tui(args.verbose);
+        Some(keymap)
+    } else {
+        init_tracing_plain(args.verbose);
+        None
+    };
 
-    let addr = args.addr;
-    let orchestrator = Orchestrator::new(args.worker_password, args.client_password, args.network_access_allowed);
+    let journal = args.journal_path.map(|path| {
+        let journal_config = JournalConfig {
+            path,
+            max_bytes: args.journal_max_mb * 1024 * 1024,
+            retention: Duration::from_secs(args.journal_retention_secs),
+        };
+        Journal::open(journal_config.clone()).unwrap_or_else(|e| {
+            eprintln!("failed to open the journal {}: {e}", journal_config.path.display());
+            std::process::exit(1);
+        })
+    });
 
-    let client_server = ClientApiServer::with_interceptor(orchestrator.clone(), check_client_auth(orchestrator.clone()));
-    let worker_server = WorkerApiServer::with_interceptor(orchestrator.clone(), check_worker_auth(orchestrator.clone()));
+    let audit_log = args.audit_log.map(|path| {
+        let audit_config = AuditConfig {
+            path,
+            max_bytes: args.audit_log_max_mb * 1024 * 1024,
+            include_args: args.audit_args,
+        };
+        AuditLog::open(audit_config.clone()).unwrap_or_else(|e| {
+            eprintln!("failed to open the audit log {}: {e}", audit_config.path.display());
+            std::process::exit(1);
+        })
+    });
 
-    if args.tui {
-        init_tracing_tui(args.verbose);
+    let module_aliases = match args.module_aliases_path {
+        Some(path) => ModuleAliases::load(path.clone()).unwrap_or_else(|e| {
+            eprintln!("failed to load module aliases from {}: {e}", path.display());
+            std::process::exit(1);
+        }),
+        None => ModuleAliases::default(),
+    };
+
+    let usage_ledger = match args.usage_path {
+        Some(path) => UsageLedger::load(path.clone()).unwrap_or_else(|e| {
+            eprintln!("failed to load usage from {}: {e}", path.display());
+            std::process::exit(1);
+        }),
+        None => UsageLedger::default(),
+    };
+
+    let addr = args.addr;
+    let config = OrchestratorConfig {
+        worker_password: args.worker_password,
+        client_password: args.client_password,
+        client_tokens: args.client_tokens.into_iter().collect(),
+        client_quotas: ClientQuotas {
+            max_queued_jobs: args.max_queued_jobs_per_client,
+            max_concurrent_jobs: args.max_concurrent_jobs_per_client,
+            max_jobs_per_day: args.max_jobs_per_day_per_client,
+            max_module_bytes_per_day: args.max_module_mb_per_day_per_client.map(|mb| mb.saturating_mul(1024 * 1024)),
+        },
+        max_queued_jobs: args.max_queued_jobs,
+        flap_policy: FlapPolicy {
+            max_flaps: args.flap_threshold,
+            max_lost_jobs: args.lost_job_threshold,
+            max_failed_probes: args.failed_probe_threshold,
+            window: Duration::from_secs(args.flap_window_secs),
+            quarantine: Duration::from_secs(args.flap_quarantine_secs),
+            per_host: args.flap_per_host,
+        },
+        webhook_secret: args.webhook_secret,
+        webhook_allowed_hosts: args.webhook_allowed_hosts,
+        network_access_allowed: args.network_access_allowed,
+        journal,
+        module_aliases,
+        audit_log,
+        cost_weights: CostWeights {
+            per_execution_second: args.cost_per_execution_second,
+            per_compile: args.cost_per_compile,
+            per_module_mb: args.cost_per_module_mb,
+        },
+        usage_ledger,
+        coordinate_uploads: args.coordinate_uploads.then(|| Duration::from_secs(args.upload_wait_secs)),
+        lost_job_grace: args.lost_job_grace_secs.map(Duration::from_secs),
+        worker_validation: args.validate_workers.then(|| ValidationPolicy {
+            probe_timeout: Duration::from_millis(args.probe_timeout_ms),
+            grace: Duration::from_secs(args.validation_grace_secs),
+        }),
+        out_of_band_reserve: args.out_of_band_reserve,
+    };
+    if let Err(e) = config.validate() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    #[cfg(feature = "fault-injection")]
+    if let Some(spec) = &args.fault_spec {
+        let spec = shared::faults::FaultSpec::parse(spec, &["dispatch"]).unwrap_or_else(|e| {
+            eprintln!("invalid --fault-spec: {e}");
+            std::process::exit(1);
+        });
+        eprintln!("fault injection enabled: {spec}");
+        shared::faults::install(spec);
+    }
+    let supervisor_config = args.spawn_workers.map(|min_workers| {
+        let max_workers = args.max_workers.unwrap_or(min_workers);
+        let worker_bin = args.worker_bin.clone().unwrap_or_else(default_worker_bin);
+        // Workers can't connect to an unspecified address, so point them at loopback instead
+        let orchestrator_addr = if addr.ip().is_unspecified() {
+            std::net::SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, addr.port()))
+        } else {
+            addr
+        };
+        SupervisorConfig {
+            worker_bin,
+            orchestrator_url: format!("http://{orchestrator_addr}"),
+            min_workers,
+            max_workers,
+            worker_credits: args.worker_credits,
+            scale_up_wait: Duration::from_secs(args.scale_up_wait_secs),
+            scale_down_idle: Duration::from_secs(args.scale_down_idle_secs),
+        }
+    });
+    if let Some(Err(e)) = supervisor_config.as_ref().map(SupervisorConfig::validate) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    let orchestrator = Orchestrator::start(config);
+    let mut routes = build_router(&orchestrator);
+    if args.reflection {
+        routes = routes.add_service(shared::reflection::service(shared::reflection::ORCHESTRATOR_SERVICES));
+    }
+    // Bind before spawning workers so they can connect as soon as they start
+    let listener = TcpListener::bind(addr).await
+        .unwrap_or_else(|e| panic!("Failed to bind the Orchestrator to {}: {}", addr, e));
 
-        let diagnostics = orchestrator.diagnostics.clone();
+    if let Some(keymap) = keymap {
+        let tui_orchestrator = orchestrator.clone();
         tokio::spawn(async move {
             tracing::info!("Orchestrator listening on {}", addr);
             Server::builder()
-                .add_service(client_server)
-                .add_service(worker_server)
-                .serve(addr)
+                .add_routes(routes)
+                .serve_with_incoming(TcpListenerStream::new(listener))
                 .await
                 .unwrap_or_else(|e| panic!("Failed to serve the Orchestrator: {}", e));
         });
+        let supervisor = supervisor_config.map(|config| start_supervisor(&orchestrator, config));
 
-        tui::run(diagnostics).await
+        tui::run(tui_orchestrator, addr, Duration::from_millis(args.tui_refresh_ms), args.tui_export_dir, keymap).await
             .unwrap_or_else(|e| eprintln!("TUI error: {e}"));
+        orchestrator.save_usage().await;
+        if let Some(supervisor) = supervisor {
+            supervisor.shutdown().await;
+        }
     } else {
-        init_tracing_plain(args.verbose);
         tracing::info!("Orchestrator listening on {}", addr);
-        Server::builder()
-            .add_service(client_server)
-            .add_service(worker_server)
-            .serve(addr)
-            .await
-            .unwrap_or_else(|e| panic!("Failed to serve the Orchestrator: {}", e));
+        let supervisor = supervisor_config.map(|config| start_supervisor(&orchestrator, config));
+        let server = Server::builder()
+            .add_routes(routes)
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        tokio::select! {
+            result = server => result.unwrap_or_else(|e| panic!("Failed to serve the Orchestrator: {}", e)),
+            _ = shutdown_signal() => tracing::info!("shutting down"),
+        }
+        orchestrator.save_usage().await;
+        if let Some(supervisor) = supervisor {
+            supervisor.shutdown().await;
+        }
+    }
+}
+
+/// The worker binary installed alongside this one.
+fn default_worker_bin() -> PathBuf {
+    let exe = std::env::current_exe().unwrap_or_else(|e| {
+        eprintln!("failed to locate the orchestrator binary, pass --worker-bin: {e}");
+        std::process::exit(1);
+    });
+    exe.with_file_name(format!("worker{}", std::env::consts::EXE_SUFFIX))
+}
+
+fn start_supervisor(orchestrator: &Orchestrator, config: SupervisorConfig) -> Supervisor {
+    let worker_bin = config.worker_bin.clone();
+    Supervisor::start(orchestrator.clone(), config).unwrap_or_else(|e| {
+        eprintln!("failed to spawn worker {}: {e}", worker_bin.display());
+        std::process::exit(1);
+    })
+}
+
+/// Completes on Ctrl-C or, on unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .unwrap_or_else(|e| panic!("Failed to listen for SIGTERM: {}", e));
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
 }
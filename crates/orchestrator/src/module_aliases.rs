@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use shared::events::epoch_ms;
+use shared::{ModuleDefaults, ModuleSource, ObjectStoreModule, module_source};
+use shared::digest::ModuleDigest;
+
+use crate::errors::OrchestratorError;
+use crate::journal::sync_parent_dir;
+use crate::namespaces::Namespace;
+
+/// Most overwrites remembered per tag.
+const MAX_HISTORY: usize = 32;
+const MAX_ALIAS_LEN: usize = 128;
+
+/// namespace -> name -> tag -> module
+type Namespaces = BTreeMap<String, BTreeMap<String, BTreeMap<String, PublishedModule>>>;
+
+/// Names for modules in the object store, so that clients can submit `name:tag` instead of a
+/// URL and digest. Each namespace has its own names. Publishing a tag that already exists
+/// replaces it, and the module it pointed at is kept in the tag's history.
+///
+/// If loaded from a file, every change is saved back to it.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleAliases {
+    path: Option<PathBuf>,
+    namespaces: Namespaces,
+}
+
+/// What a tag points at, as saved to the aliases file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublishedModule {
+    pub url: String,
+    /// Digest of the object at url, as `<algorithm>:<hex>`. Files saved before digests were
+    /// labelled hold bare SHA-256 hex under sha256, which is read the same.
+    #[serde(alias = "sha256")]
+    pub digest: String,
+    pub published_at_ms: u64,
+    #[serde(default, skip_serializing_if = "JobDefaults::is_empty")]
+    pub defaults: JobDefaults,
+    /// The modules this tag pointed at before, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<ReplacedModule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplacedModule {
+    pub url: String,
+    #[serde(alias = "sha256")]
+    pub digest: String,
+    pub published_at_ms: u64,
+    pub replaced_at_ms: u64,
+}
+
+/// Job settings published with a module, see ModuleDefaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+impl JobDefaults {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl From<ModuleDefaults> for JobDefaults {
+    fn from(defaults: ModuleDefaults) -> Self {
+        Self { timeout_ms: defaults.timeout_ms, max_retries: defaults.max_retries, args: defaults.args }
+    }
+}
+
+impl From<&JobDefaults> for ModuleDefaults {
+    fn from(defaults: &JobDefaults) -> Self {
+        Self { timeout_ms: defaults.timeout_ms, max_retries: defaults.max_retries, args: defaults.args.clone() }
+    }
+}
+
+impl PublishedModule {
+    pub fn source(&self) -> ModuleSource {
+        let module = match ModuleDigest::parse(&self.digest) {
+            Ok(digest) => ObjectStoreModule::new(self.url.clone(), digest),
+            // Only a hand-edited aliases file holds one, and workers refuse the module
+            Err(_) => ObjectStoreModule { url: self.url.clone(), ..Default::default() },
+        };
+        ModuleSource { source: Some(module_source::Source::ObjectStore(module)) }
+    }
+}
+
+impl ModuleAliases {
+    /// Loads the aliases saved at path, starting with none if it doesn't exist.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let namespaces = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path: Some(path), namespaces })
+    }
+
+    /// Points name:tag at the module at url and its job defaults, returning the module it
+    /// pointed at before.
+    pub fn publish(&mut self, namespace: &Namespace, name: &str, tag: &str, url: &str, digest: ModuleDigest, defaults: JobDefaults) -> Result<Option<ModuleSource>, OrchestratorError> {
+        validate(name)?;
+        validate(tag)?;
+        let now_ms = epoch_ms(SystemTime::now());
+        let tags = self.namespaces.entry(namespace.to_string()).or_default()
+            .entry(name.to_string()).or_default();
+        let mut previous = tags.remove(tag);
+        let mut history = previous.as_mut().map(|previous| std::mem::take(&mut previous.history)).unwrap_or_default();
+        if let Some(previous) = &previous {
+            history.push(ReplacedModule {
+                url: previous.url.clone(),
+                digest: previous.digest.clone(),
+                published_at_ms: previous.published_at_ms,
+                replaced_at_ms: now_ms,
+            });
+            history.drain(..history.len().saturating_sub(MAX_HISTORY));
+            tracing::info!(namespace = %namespace, name, tag, previous = %previous.url, url, "module alias replaced");
+        } else {
+            tracing::info!(namespace = %namespace, name, tag, url, "module alias published");
+        }
+        tags.insert(tag.to_string(), PublishedModule {
+            url: url.to_string(),
+            digest: digest.to_string(),
+            published_at_ms: now_ms,
+            defaults,
+            history,
+        });
+
+        if let Some(path) = &self.path {
+            save(path, &self.namespaces).map_err(OrchestratorError::ModuleAliasesNotSaved)?;
+        }
+        Ok(previous.map(|previous| previous.source()))
+    }
+
+    /// The module name:tag points at. If there isn't one, the error lists the name's tags,
+    /// or the namespace's names if the name is unknown too.
+    pub fn resolve(&self, namespace: &Namespace, name: &str, tag: &str) -> Result<&PublishedModule, OrchestratorError> {
+        let names = self.namespaces.get(namespace.as_str());
+        let tags = names.and_then(|names| names.get(name));
+        if let Some(module) = tags.and_then(|tags| tags.get(tag)) {
+            return Ok(module);
+        }
+        let hint = match (tags, names) {
+            (Some(tags), _) => format!("{name} has tags: {}", join(tags.keys())),
+            (None, Some(names)) => format!("published names: {}", join(names.keys())),
+            (None, None) => format!("no modules are published in namespace {namespace}"),
+        };
+        Err(OrchestratorError::ModuleNotFound { alias: format!("{name}:{tag}"), hint })
+    }
+}
+
+/// Checks an alias name or tag: 1 to 128 ASCII letters, digits, '-', '_' or '.'.
+fn validate(part: &str) -> Result<(), OrchestratorError> {
+    let valid = !part.is_empty()
+        && part.len() <= MAX_ALIAS_LEN
+        && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !valid {
+        return Err(OrchestratorError::InvalidModuleAlias(part.to_string()));
+    }
+    Ok(())
+}
+
+fn join<'a>(keys: impl Iterator<Item = &'a String>) -> String {
+    keys.map(String::as_str).collect::<Vec<_>>().join(", ")
+}
+
+/// Atomically replaces the aliases file.
+fn save(path: &Path, namespaces: &Namespaces) -> io::Result<()> {
+    let tmp_path = path.with_extension("saving");
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(&serde_json::to_vec_pretty(namespaces)?)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    sync_parent_dir(path)
+}
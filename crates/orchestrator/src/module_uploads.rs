@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+use shared::JobId;
+
+use crate::errors::OrchestratorError;
+use crate::orchestrator::Orchestrator;
+
+/// The blake3 hash of a module's wasm.
+pub type ModuleHash = [u8; 32];
+
+/// The modules a job is uploading to a Worker for the first time. Used when upload
+/// coordination is on, so that concurrent jobs for a module no Worker has wait for one of them
+/// to upload it rather than all uploading it. The WorkerRegistry tracks which Workers have
+/// which modules once they are uploaded.
+#[derive(Debug, Default)]
+pub struct ModuleUploads {
+    in_flight: HashMap<ModuleHash, Upload>,
+}
+
+/// A job uploading a module, and the jobs waiting for it to.
+#[derive(Debug)]
+struct Upload {
+    job_id: JobId,
+    /// When the waiting jobs give up on it.
+    deadline: Instant,
+    /// The Worker it was dispatched to, once it was.
+    worker_address: Option<String>,
+    outcome: watch::Sender<Option<UploadOutcome>>,
+}
+
+#[derive(Debug, Clone)]
+enum UploadOutcome {
+    /// The module compiled, so waiting jobs can go to the Worker that has it.
+    Compiled,
+    /// The upload stopped for reasons of its own, e.g. it was cancelled, so one of the waiting
+    /// jobs uploads the module instead.
+    Abandoned,
+    /// The job failed before its module compiled, which the waiting jobs would too.
+    Failed,
+}
+
+/// What a job for a module should do, see ModuleUploads::claim.
+enum Claim {
+    /// Go ahead, the module is on a Worker or the job is uploading it.
+    Proceed,
+    /// Wait for another job's upload.
+    Wait { uploader: JobId, deadline: Instant, outcome: watch::Receiver<Option<UploadOutcome>> },
+}
+
+impl ModuleUploads {
+    /// Decides whether a job for a module goes ahead, making it the module's uploader unless a
+    /// Worker has the module cached or another job is uploading it. An upload past its
+    /// deadline is taken over.
+    fn claim(&mut self, job_id: JobId, hash: ModuleHash, cached: bool, wait: Duration, now: Instant) -> Claim {
+        if cached {
+            return Claim::Proceed;
+        }
+        if let Some(upload) = self.in_flight.get(&hash)
+            && upload.job_id != job_id
+            && upload.deadline > now
+        {
+            return Claim::Wait { uploader: upload.job_id, deadline: upload.deadline, outcome: upload.outcome.subscribe() };
+        }
+        // A retry of the uploader keeps its place, so its waiters don't give up on it early
+        let deadline = match self.in_flight.remove(&hash) {
+            Some(upload) if upload.job_id == job_id => upload.deadline,
+            _ => now + wait,
+        };
+        let (outcome, _) = watch::channel(None);
+        self.in_flight.insert(hash, Upload { job_id, deadline, worker_address: None, outcome });
+        Claim::Proceed
+    }
+
+    /// Records the Worker an uploading job was dispatched to.
+    pub fn dispatched(&mut self, job_id: JobId, worker_address: &str) {
+        if let Some(upload) = self.in_flight.values_mut().find(|upload| upload.job_id == job_id) {
+            upload.worker_address = Some(worker_address.to_string());
+        }
+    }
+
+    /// Releases the jobs waiting for a module, now that a Worker has it compiled.
+    pub fn compiled(&mut self, hash: ModuleHash) {
+        if let Some(upload) = self.in_flight.remove(&hash) {
+            upload.outcome.send_replace(Some(UploadOutcome::Compiled));
+        }
+    }
+
+    /// Records that a job finished without executing, failing the jobs waiting for its upload
+    /// if it failed, or letting one of them upload the module if it was cancelled.
+    pub fn stopped(&mut self, job_id: JobId, failed: bool) {
+        let outcome = if failed { UploadOutcome::Failed } else { UploadOutcome::Abandoned };
+        self.in_flight.retain(|_, upload| {
+            if upload.job_id != job_id {
+                return true;
+            }
+            upload.outcome.send_replace(Some(outcome.clone()));
+            false
+        });
+    }
+
+    /// Abandons the uploads dispatched to a Worker that disconnected.
+    pub fn worker_disconnected(&mut self, worker_address: &str) {
+        self.in_flight.retain(|_, upload| {
+            if upload.worker_address.as_deref() != Some(worker_address) {
+                return true;
+            }
+            upload.outcome.send_replace(Some(UploadOutcome::Abandoned));
+            false
+        });
+    }
+}
+
+impl Orchestrator {
+    /// Waits while another job is uploading the job's module to a Worker, up to the upload
+    /// coordination wait. Returns once the module is compiled on a Worker, or the job is to
+    /// upload it itself. Fails if the upload it waited for failed or ran out of time.
+    pub(crate) async fn await_module_upload(&self, job_id: JobId, hash: ModuleHash, wait: Duration) -> Result<(), OrchestratorError> {
+        loop {
+            let cached = self.registry.lock().await.has_module(&hash);
+            let claim = self.module_uploads.lock().await.claim(job_id, hash, cached, wait, Instant::now());
+            let Claim::Wait { uploader, deadline, mut outcome } = claim else {
+                return Ok(());
+            };
+            tracing::debug!(job_id = %job_id, uploader = %uploader, "waiting for another job to upload the module");
+            let waited = tokio::time::timeout_at(deadline.into(), outcome.wait_for(Option::is_some)).await;
+            match waited {
+                Ok(Ok(outcome)) => match outcome.clone() {
+                    Some(UploadOutcome::Compiled) => return Ok(()),
+                    Some(UploadOutcome::Failed) => return Err(OrchestratorError::ModuleUploadFailed(uploader)),
+                    _ => continue,
+                },
+                // The upload was dropped without an outcome, e.g. taken over after its deadline
+                Ok(Err(_)) => continue,
+                Err(_) => return Err(OrchestratorError::ModuleUploadTimedOut { uploader, wait }),
+            }
+        }
+    }
+}
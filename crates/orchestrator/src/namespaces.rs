@@ -0,0 +1,61 @@
+use std::fmt;
+
+use tonic::Request;
+
+use crate::errors::OrchestratorError;
+
+/// A tenant's slice of the cluster. Every job belongs to one, and jobs can only be looked up
+/// or cancelled from their own namespace.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Namespace(String);
+
+impl Namespace {
+    pub const DEFAULT: &str = "default";
+    const MAX_LEN: usize = 63;
+
+    /// Validates a namespace name: 1 to 63 ASCII letters, digits, '-' or '_'.
+    pub fn new(name: &str) -> Result<Self, OrchestratorError> {
+        let valid = !name.is_empty()
+            && name.len() <= Self::MAX_LEN
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(OrchestratorError::InvalidNamespace(name.to_string()));
+        }
+        Ok(Self(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The namespace a client request acts in: the one its auth token belongs to if it has
+    /// one, otherwise the one the request names, otherwise the default. Naming a namespace
+    /// other than the token's is refused.
+    pub fn of_request<T>(request: &Request<T>, requested: Option<&str>) -> Result<Self, OrchestratorError> {
+        let requested = requested.map(Self::new).transpose()?;
+        match (request.extensions().get::<Namespace>(), requested) {
+            (Some(authenticated), Some(requested)) if *authenticated != requested => {
+                Err(OrchestratorError::NamespaceForbidden(requested.0))
+            },
+            (Some(authenticated), _) => Ok(authenticated.clone()),
+            (None, requested) => Ok(requested.unwrap_or_default()),
+        }
+    }
+
+    /// Whether a worker pinned to the given namespace, if any, may run this namespace's jobs.
+    pub fn may_run_on(&self, worker_namespace: Option<&Namespace>) -> bool {
+        worker_namespace.is_none_or(|pinned| pinned == self)
+    }
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Self(Self::DEFAULT.to_string())
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -1,8 +1,65 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
+use tonic::service::Routes;
+use tonic::service::interceptor::InterceptedService;
 
-use crate::{diagnostics::DiagnosticsStore, job_queue::JobQueue, registry::WorkerRegistry};
+use shared::{CostWeights, client_api_server::ClientApiServer, worker_api_server::WorkerApiServer};
+use shared::limits::JobLimits;
+use crate::{audit::AuditLog, client_api::check_client_auth, coalescing::Coalescing, delay_queue::DelayQueue, diagnostics::DiagnosticsStore, flapping::{FlapDetector, FlapPolicy}, job_queue::JobQueue, journal::{Journal, JournalEntry}, module_aliases::ModuleAliases, module_uploads::ModuleUploads, namespaces::Namespace, out_of_band::OutOfBandTracker, queue_stats::QueueStats, quotas::{ClientQuotas, QuotaTracker}, registry::WorkerRegistry, relay::RelayedCalls, replay::{REPLAY_STDIN_BUDGET, StdinCopies}, usage::UsageLedger, validation::ValidationPolicy, watchdog::RuntimeWatchdog, webhooks::WebhookNotifier, worker_api::{OutboundTx, check_worker_auth}};
+
+/// Settings for an Orchestrator. The defaults match the orchestrator binary's defaults.
+#[derive(Debug, Clone, Default)]
+pub struct OrchestratorConfig {
+    /// Password required for workers to register. If None, no password is required.
+    pub worker_password: Option<String>,
+    /// Password required for clients to submit jobs. If None, no password is required.
+    pub client_password: Option<String>,
+    /// Client auth tokens and the namespace each belongs to. Clients presenting one act in its
+    /// namespace, and when there are any, clients must present one or the client password.
+    pub client_tokens: HashMap<String, Namespace>,
+    pub client_quotas: ClientQuotas,
+    /// Most jobs the queue holds across every client. Jobs that would be queued while it is
+    /// full are refused. Must be above client_quotas.max_queued_jobs, so that no one client
+    /// can fill the queue.
+    pub max_queued_jobs: Option<u32>,
+    pub flap_policy: FlapPolicy,
+    /// Secret used to sign job completion webhooks. If None, webhooks are unsigned.
+    pub webhook_secret: Option<String>,
+    /// Hosts that webhook callback URLs may target. If None, any host with a public address is
+    /// allowed. Only these hosts may resolve to private or loopback addresses.
+    pub webhook_allowed_hosts: Option<Vec<String>>,
+    /// Permit jobs to make network connections.
+    pub network_access_allowed: bool,
+    /// If set, job events are journaled so that dispatched jobs survive a restart, and the
+    /// jobs recovered from it are restored on start.
+    pub journal: Option<Journal>,
+    /// The name:tag aliases clients can publish modules under and submit by.
+    pub module_aliases: ModuleAliases,
+    /// If set, a record of every finished job is appended to this log.
+    pub audit_log: Option<AuditLog>,
+    /// What jobs are charged to their namespace, which workers also estimate each job's cost with.
+    pub cost_weights: CostWeights,
+    /// Each namespace's usage so far, see UsageLedger.
+    pub usage_ledger: UsageLedger,
+    /// If set, a job for a module no Worker has waits up to this long while another job
+    /// uploads the same module, instead of uploading it too. Jobs also go to Workers that have
+    /// their module when those have credits to spare.
+    pub coordinate_uploads: Option<Duration>,
+    /// If set, a job with a timeout that its Worker hasn't finished this long after its timeout
+    /// is presumed lost: its Worker's credit and its client's quota are released, and it counts
+    /// against the Worker, which is quarantined like a flapping one if it loses too many.
+    pub lost_job_grace: Option<Duration>,
+    /// If set, a newly registered Worker receives no jobs until it answers a health probe at
+    /// the address it registered with. Relayed Workers, which accept no connections, aren't
+    /// probed.
+    pub worker_validation: Option<ValidationPolicy>,
+    /// If set, Workers whose heartbeats show them busy with other Orchestrators' jobs have
+    /// this share of their credits held back, leaving them headroom, see OutOfBandTracker.
+    pub out_of_band_reserve: Option<f64>,
+}
 
 /// Orchestrator struct representing the main Orchestrator server component.
 /// It implements CliApi and WorkerApi services, see cli_api.rs and worker_api.rs for details.
@@ -12,26 +69,157 @@ pub struct Orchestrator {
     // note: all shared state fields should use Arc<RwLock/Mutex<...>> for thread safety
     pub registry: Arc<Mutex<WorkerRegistry>>,
     pub job_queue: Arc<Mutex<JobQueue>>,
+    /// Gauges of the job queue's length and dispatch rate, readable without its lock.
+    pub queue_stats: Arc<QueueStats>,
+    pub delay_queue: Arc<Mutex<DelayQueue>>,
+    pub delay_queue_notify: Arc<Notify>,
+    pub quota_tracker: Arc<Mutex<QuotaTracker>>,
+    pub flap_detector: Arc<Mutex<FlapDetector>>,
+    /// Outbound stream of each connected Worker, used to disconnect a worker on request.
+    pub worker_streams: Arc<Mutex<HashMap<String, OutboundTx>>>,
+    /// Workers that only run jobs they were sent a reservation for.
+    pub reserving_workers: Arc<Mutex<HashSet<String>>>,
+    /// Workers that accept no connections, whose calls are relayed over their streams.
+    pub relayed_workers: Arc<Mutex<HashSet<String>>>,
+    pub relayed_calls: RelayedCalls,
+    /// Addresses of the TLS proxies clients reach Workers behind one at, by Worker.
+    pub tls_addresses: Arc<Mutex<HashMap<String, String>>>,
     pub worker_password: Option<String>,
     pub client_password: Option<String>,
+    pub client_tokens: Arc<HashMap<String, Namespace>>,
     pub jwt_secret: [u8; 32],
     pub network_access_allowed: bool,
+    pub webhooks: WebhookNotifier,
+    pub journal: Option<Journal>,
+    pub module_aliases: Arc<Mutex<ModuleAliases>>,
+    pub audit_log: Option<AuditLog>,
+    pub cost_weights: CostWeights,
+    pub usage_ledger: Arc<Mutex<UsageLedger>>,
+    pub coordinate_uploads: Option<Duration>,
+    pub module_uploads: Arc<Mutex<ModuleUploads>>,
+    pub coalescing: Arc<Mutex<Coalescing>>,
+    /// The stdin pipeline stages read, for replaying them.
+    pub stdin_copies: Arc<Mutex<StdinCopies>>,
+    pub lost_job_grace: Option<Duration>,
+    pub runtime_watchdog: Arc<Mutex<RuntimeWatchdog>>,
+    pub worker_validation: Option<ValidationPolicy>,
+    pub out_of_band: Arc<Mutex<OutOfBandTracker>>,
+    pub max_queued_jobs: Option<u32>,
 
     // diagnostics
     pub diagnostics: Arc<DiagnosticsStore>,
 }
 
 impl Orchestrator {
-    /// Creates a new Orchestrator instance
-    pub fn new(worker_password: Option<String>, client_password: Option<String>, network_access_allowed: bool) -> Self {
-        Self {
+    /// Creates a new Orchestrator and spawns its background tasks, so must be called from
+    /// within a Tokio runtime. Serve it with build_router.
+    pub fn start(config: OrchestratorConfig) -> Self {
+        let queue_stats = Arc::new(QueueStats::default());
+        let orchestrator = Self {
             registry: Arc::new(Mutex::new(WorkerRegistry::new())),
-            job_queue: Arc::new(Mutex::new(JobQueue::new())),
-            worker_password,
-            client_password,
+            job_queue: Arc::new(Mutex::new(JobQueue::new(queue_stats.clone()))),
+            queue_stats,
+            delay_queue: Arc::new(Mutex::new(DelayQueue::new())),
+            delay_queue_notify: Arc::new(Notify::new()),
+            quota_tracker: Arc::new(Mutex::new(QuotaTracker::new(config.client_quotas))),
+            flap_detector: Arc::new(Mutex::new(FlapDetector::new(config.flap_policy))),
+            worker_streams: Arc::new(Mutex::new(HashMap::new())),
+            reserving_workers: Arc::new(Mutex::new(HashSet::new())),
+            relayed_workers: Arc::new(Mutex::new(HashSet::new())),
+            relayed_calls: RelayedCalls::default(),
+            tls_addresses: Arc::new(Mutex::new(HashMap::new())),
+            worker_password: config.worker_password,
+            client_password: config.client_password,
+            client_tokens: Arc::new(config.client_tokens),
             jwt_secret: rand::random(),
             diagnostics: Arc::new(DiagnosticsStore::new()),
-            network_access_allowed
+            network_access_allowed: config.network_access_allowed,
+            webhooks: WebhookNotifier::new(config.webhook_secret, config.webhook_allowed_hosts),
+            journal: config.journal,
+            module_aliases: Arc::new(Mutex::new(config.module_aliases)),
+            audit_log: config.audit_log,
+            cost_weights: config.cost_weights,
+            usage_ledger: Arc::new(Mutex::new(config.usage_ledger)),
+            coordinate_uploads: config.coordinate_uploads,
+            module_uploads: Arc::new(Mutex::new(ModuleUploads::default())),
+            coalescing: Arc::new(Mutex::new(Coalescing::default())),
+            stdin_copies: Arc::new(Mutex::new(StdinCopies::new(REPLAY_STDIN_BUDGET))),
+            lost_job_grace: config.lost_job_grace,
+            runtime_watchdog: Arc::new(Mutex::new(RuntimeWatchdog::default())),
+            worker_validation: config.worker_validation,
+            out_of_band: Arc::new(Mutex::new(OutOfBandTracker::new(config.out_of_band_reserve))),
+            max_queued_jobs: config.max_queued_jobs,
+        };
+        if let Some(journal) = &orchestrator.journal {
+            for job in journal.recovered() {
+                orchestrator.diagnostics.handle_job_recovered(job);
+            }
         }
+        tokio::spawn(orchestrator.clone().run_delay_queue_timer());
+        tokio::spawn(orchestrator.clone().run_usage_saver());
+        tokio::spawn(orchestrator.webhooks.clone().run_expiry());
+        if let Some(audit_log) = &orchestrator.audit_log {
+            tokio::spawn(audit_log.clone().run_expiry());
+        }
+        orchestrator
     }
-}
\ No newline at end of file
+
+    /// Records a job event in the audit log and journal, if there are any, returning once it
+    /// is journaled.
+    pub(crate) async fn record(&self, entry: JournalEntry) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(&entry);
+        }
+        if let Some(journal) = &self.journal {
+            journal.record(entry).await;
+        }
+    }
+
+    /// Runs forever, moving scheduled jobs from the DelayQueue into the JobQueue once their
+    /// start time arrives and dispatching them. Woken early whenever a job is scheduled.
+    pub async fn run_delay_queue_timer(self) {
+        loop {
+            let next_fire_time = self.delay_queue.lock().await.next_fire_time();
+            let sleep_duration = next_fire_time
+                .map(|t| t.duration_since(SystemTime::now()).unwrap_or_default());
+
+            tokio::select! {
+                _ = self.delay_queue_notify.notified() => continue,
+                _ = async {
+                    match sleep_duration {
+                        Some(d) => tokio::time::sleep(d).await,
+                        None => std::future::pending().await,
+                    }
+                } => {}
+            }
+
+            let mut queue = self.job_queue.lock().await;
+            let mut registry = self.registry.lock().await;
+            let due = self.delay_queue.lock().await.pop_due(SystemTime::now());
+            for (job_id, job) in due {
+                tracing::debug!(job_id = %job_id, "scheduled job released into queue");
+                self.diagnostics.handle_scheduled_job_released(job_id);
+                queue.enqueue(job_id, &job.namespace, &job.client, job.wasm_hash, job.tx);
+            }
+            Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
+        }
+    }
+}
+/// Builds the gRPC routes serving the Orchestrator's client and worker APIs, with their
+/// password checks. Serve them with tonic's Server::builder().add_routes, or convert them
+/// with Routes::into_axum_router to mount them in an existing axum app.
+pub fn build_router(orchestrator: &Orchestrator) -> Routes {
+    // Large enough for a module to warm workers with, see warm_workers
+    let client_server = InterceptedService::new(
+        ClientApiServer::new(orchestrator.clone()).max_decoding_message_size(JobLimits::default().max_message_bytes()),
+        check_client_auth(orchestrator.clone()),
+    );
+    // Also large enough for the results of calls relayed to workers, see relay_call
+    let worker_server = InterceptedService::new(
+        WorkerApiServer::new(orchestrator.clone())
+            .max_decoding_message_size(JobLimits::default().max_message_bytes())
+            .max_encoding_message_size(usize::MAX),
+        check_worker_auth(orchestrator.clone()),
+    );
+    Routes::new(client_server).add_service(worker_server)
+}
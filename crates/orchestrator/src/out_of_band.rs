@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+/// Share of a Worker's credits that jobs this Orchestrator didn't dispatch must start between
+/// two of its heartbeats for it to count as busy out of band.
+const BUSY_OUT_OF_BAND_SHARE: f64 = 0.25;
+
+/// Jobs a Worker has started, as of one of its heartbeats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StartCounts {
+    /// The jobs_started_total the Worker reported.
+    pub reported: u64,
+    /// The jobs this Orchestrator dispatched to it that it said began executing.
+    pub dispatched: u64,
+}
+
+/// What a heartbeat says about the jobs a Worker ran for other Orchestrators.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reconciled {
+    /// Jobs the Worker started since its first heartbeat that this Orchestrator didn't dispatch.
+    pub out_of_band_total: u64,
+    /// Of those, the ones started since its previous heartbeat.
+    pub out_of_band: u64,
+}
+
+/// Reconciles a Worker's heartbeat with the jobs this Orchestrator dispatched to it. Counts are
+/// taken from the Worker's first heartbeat rather than its previous one, so a heartbeat that
+/// overtakes the update of a job this Orchestrator dispatched only borrows the job from the
+/// next interval, instead of counting it out of band for good.
+pub fn reconcile(baseline: StartCounts, previous_total: u64, current: StartCounts) -> Reconciled {
+    let started = current.reported.saturating_sub(baseline.reported);
+    let dispatched = current.dispatched.saturating_sub(baseline.dispatched);
+    let out_of_band_total = started.saturating_sub(dispatched);
+    Reconciled { out_of_band_total, out_of_band: out_of_band_total.saturating_sub(previous_total) }
+}
+
+/// Credits to hold back from a Worker with the given credits that started out_of_band jobs
+/// since its previous heartbeat: reserve_fraction of them, rounded up, if that is enough to
+/// count as busy out of band, otherwise none.
+pub fn reserve_credits(credits: u32, out_of_band: u64, reserve_fraction: f64) -> u32 {
+    let busy = out_of_band > 0 && out_of_band as f64 >= credits as f64 * BUSY_OUT_OF_BAND_SHARE;
+    if !busy {
+        return 0;
+    }
+    ((credits as f64 * reserve_fraction).ceil() as u32).min(credits)
+}
+
+/// Tracks how busy each Worker is with jobs this Orchestrator didn't dispatch, from their
+/// heartbeats. Workers registered with several Orchestrators are told of the credits the
+/// others' jobs take, but only as those jobs arrive, so this Orchestrator can dispatch into a
+/// Worker another just filled. Workers busy out of band have some of their credits held back,
+/// leaving them headroom.
+#[derive(Debug, Default)]
+pub struct OutOfBandTracker {
+    /// Share of a busy Worker's credits held back. None holds back nothing.
+    reserve_fraction: Option<f64>,
+    workers: HashMap<String, WorkerStarts>,
+}
+
+#[derive(Debug, Default)]
+struct WorkerStarts {
+    /// The counts at the Worker's first heartbeat, or None before it.
+    baseline: Option<StartCounts>,
+    dispatched: u64,
+    out_of_band_total: u64,
+}
+
+impl OutOfBandTracker {
+    pub fn new(reserve_fraction: Option<f64>) -> Self {
+        Self { reserve_fraction, workers: HashMap::new() }
+    }
+
+    /// Counts a job this Orchestrator dispatched beginning to execute on the Worker.
+    pub fn job_started(&mut self, worker_address: &str) {
+        self.workers.entry(worker_address.to_string()).or_default().dispatched += 1;
+    }
+
+    /// Reconciles a heartbeat from a Worker with the given credits, returning what it says and
+    /// the credits to hold back from the Worker until its next one.
+    pub fn heartbeat(&mut self, worker_address: &str, jobs_started_total: u64, credits: u32) -> (Reconciled, u32) {
+        let worker = self.workers.entry(worker_address.to_string()).or_default();
+        let current = StartCounts { reported: jobs_started_total, dispatched: worker.dispatched };
+        // A count that went backwards is from a Worker that restarted at the same address
+        let baseline = match worker.baseline {
+            Some(baseline) if baseline.reported <= current.reported => baseline,
+            _ => {
+                worker.out_of_band_total = 0;
+                *worker.baseline.insert(current)
+            },
+        };
+        let reconciled = reconcile(baseline, worker.out_of_band_total, current);
+        worker.out_of_band_total = reconciled.out_of_band_total;
+        let reserve = self.reserve_fraction
+            .map_or(0, |fraction| reserve_credits(credits, reconciled.out_of_band, fraction));
+        (reconciled, reserve)
+    }
+
+    pub fn worker_disconnected(&mut self, worker_address: &str) {
+        self.workers.remove(worker_address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(reported: u64, dispatched: u64) -> StartCounts {
+        StartCounts { reported, dispatched }
+    }
+
+    #[test]
+    fn jobs_this_orchestrator_dispatched_are_not_out_of_band() {
+        let reconciled = reconcile(counts(10, 4), 0, counts(15, 9));
+        assert_eq!(reconciled, Reconciled { out_of_band_total: 0, out_of_band: 0 });
+    }
+
+    #[test]
+    fn other_jobs_count_from_the_first_heartbeat() {
+        let reconciled = reconcile(counts(10, 4), 2, counts(20, 7));
+        assert_eq!(reconciled, Reconciled { out_of_band_total: 7, out_of_band: 5 });
+    }
+
+    #[test]
+    fn an_overtaken_job_update_is_only_borrowed() {
+        // The heartbeat counts a dispatched job whose executing update hasn't arrived yet
+        let early = reconcile(counts(0, 0), 0, counts(3, 2));
+        assert_eq!(early, Reconciled { out_of_band_total: 1, out_of_band: 1 });
+        // Once it arrives, the job is no longer out of band, and nothing new is
+        let late = reconcile(counts(0, 0), early.out_of_band_total, counts(3, 3));
+        assert_eq!(late, Reconciled { out_of_band_total: 0, out_of_band: 0 });
+    }
+
+    #[test]
+    fn reserve_needs_a_quarter_of_the_credits_out_of_band() {
+        assert_eq!(reserve_credits(8, 1, 0.5), 0);
+        assert_eq!(reserve_credits(8, 2, 0.5), 4);
+        assert_eq!(reserve_credits(8, 0, 0.5), 0);
+        assert_eq!(reserve_credits(0, 0, 0.5), 0);
+    }
+
+    #[test]
+    fn reserve_rounds_up_and_is_capped() {
+        assert_eq!(reserve_credits(3, 3, 0.1), 1);
+        assert_eq!(reserve_credits(4, 4, 1.5), 4);
+    }
+
+    #[test]
+    fn tracker_reserves_for_busy_workers() {
+        let mut tracker = OutOfBandTracker::new(Some(0.5));
+        assert_eq!(tracker.heartbeat("w", 5, 4), (Reconciled::default(), 0));
+        tracker.job_started("w");
+        // One of the two jobs started since was this orchestrator's
+        assert_eq!(tracker.heartbeat("w", 7, 4), (Reconciled { out_of_band_total: 1, out_of_band: 1 }, 2));
+        // Nothing else started, so nothing is held back
+        assert_eq!(tracker.heartbeat("w", 7, 4), (Reconciled { out_of_band_total: 1, out_of_band: 0 }, 0));
+    }
+
+    #[test]
+    fn tracker_without_a_reserve_holds_nothing_back() {
+        let mut tracker = OutOfBandTracker::new(None);
+        tracker.heartbeat("w", 0, 1);
+        assert_eq!(tracker.heartbeat("w", 10, 1).1, 0);
+    }
+
+    #[test]
+    fn restarted_worker_starts_a_new_baseline() {
+        let mut tracker = OutOfBandTracker::new(Some(1.0));
+        tracker.heartbeat("w", 100, 4);
+        assert_eq!(tracker.heartbeat("w", 104, 4).0.out_of_band_total, 4);
+        // The count went backwards, so the worker restarted
+        assert_eq!(tracker.heartbeat("w", 2, 4), (Reconciled::default(), 0));
+        assert_eq!(tracker.heartbeat("w", 3, 4).0, Reconciled { out_of_band_total: 1, out_of_band: 1 });
+    }
+}
@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tonic::{Request, Status};
+
+use shared::compat::PROTOCOL_VERSION;
+use shared::limits::{JobLimits, PIPELINE_MAX_STDIN_BYTES};
+use shared::{ErrorCode, JobId, JobRequest, JobResponse, PipelineRequest, PipelineResponse, PipelineStage, StageFailure, StageResult, WorkerRequest, WorkerResponse, relayed_call, relayed_reply};
+
+use crate::errors::OrchestratorError;
+use crate::identity::ClientIdentity;
+use crate::namespaces::Namespace;
+use crate::orchestrator::Orchestrator;
+use crate::relay::mismatched_reply;
+use crate::warm::connect_executor;
+
+impl Orchestrator {
+    /// Runs a pipeline's stages one after another for the client at client_address, each queued
+    /// as a job of its own once the stage before it finished and fed that stage's stdout. The
+    /// first stage to fail stops the pipeline, and is reported in the response rather than as
+    /// an error, so the client still learns how the stages before it went.
+    pub(crate) async fn run_pipeline(&self, client_address: &str, identity: &ClientIdentity, namespace: Namespace, request: PipelineRequest) -> PipelineResponse {
+        let deadline = request.timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+        let stage_count = request.stages.len();
+        let mut stdin = request.stdin;
+        let mut stages = Vec::with_capacity(stage_count);
+
+        for (index, stage) in request.stages.into_iter().enumerate() {
+            let job_id = JobId::random();
+            let stage_number = index as u32;
+            tracing::debug!(job_id = %job_id, stage = stage_number, "pipeline stage queued");
+            let result = self.run_stage(client_address, identity, &namespace, job_id, stage, std::mem::take(&mut stdin), deadline).await;
+            let (worker_address, response) = match result {
+                Ok(done) => done,
+                Err(status) => {
+                    tracing::info!(job_id = %job_id, stage = stage_number, error = %status.message(), "pipeline stage failed");
+                    return PipelineResponse { stages, stdout: Vec::new(), failure: Some(stage_failure(stage_number, job_id, &status)) };
+                },
+            };
+
+            stages.push(StageResult {
+                job_id: job_id.to_bytes(),
+                worker_address,
+                stderr: response.stderr,
+                stdout_bytes: response.stdout.len() as u64,
+                compile_ms: response.compile_ms,
+                execute_ms: response.execute_ms,
+                cache_hit: response.cache_hit,
+                estimated_cost: response.estimated_cost,
+            });
+            let is_last = index + 1 == stage_count;
+            if !is_last && response.stdout.len() > PIPELINE_MAX_STDIN_BYTES {
+                let status = ErrorCode::LimitExceeded.status(
+                    tonic::Code::InvalidArgument,
+                    format!("stage {index} wrote {} bytes of stdout, more than the {PIPELINE_MAX_STDIN_BYTES} the next stage may read", response.stdout.len()),
+                );
+                return PipelineResponse { stages, stdout: Vec::new(), failure: Some(stage_failure(stage_number, job_id, &status)) };
+            }
+            stdin = response.stdout;
+        }
+
+        PipelineResponse { stages, stdout: stdin, failure: None }
+    }
+
+    /// Queues a stage's job for a worker and runs it there with the stdin, returning the
+    /// worker's address and the job's response. A worker without the module compiled is sent
+    /// the stage's wasm after all, if it has any.
+    #[allow(clippy::too_many_arguments)]
+    async fn run_stage(&self, client_address: &str, identity: &ClientIdentity, namespace: &Namespace, job_id: JobId, stage: PipelineStage, stdin: Vec<u8>, deadline: Option<Instant>) -> Result<(String, JobResponse), Status> {
+        let remaining_ms = || deadline.map(|d| d.saturating_duration_since(Instant::now()).as_millis() as u64);
+        let has_wasm = !stage.wasm_bytes.is_empty();
+        let assigned = self.assign_worker(client_address, identity, namespace.clone(), WorkerRequest {
+            job_id: job_id.to_bytes(),
+            protocol_version: PROTOCOL_VERSION,
+            // Dispatch by module only for stages that would upload it, as clients do
+            wasm_hash: (has_wasm && !stage.wasm_hash.is_empty()).then(|| stage.wasm_hash.clone()),
+            timeout_ms: remaining_ms(),
+            ..Default::default()
+        }).await?;
+        if remaining_ms() == Some(0) {
+            return Err(OrchestratorError::DeadlinePassed.into());
+        }
+
+        // Kept so that the stage can be replayed reading the same stdin, see StdinCopies
+        self.stdin_copies.lock().await.keep(&stdin);
+
+        let send_wasm = has_wasm && (stage.wasm_hash.is_empty() || !assigned.module_cached);
+        let job_request = |send_wasm: bool, stdin: Vec<u8>| JobRequest {
+            job_id: job_id.to_bytes(),
+            wasm_bytes: if send_wasm { stage.wasm_bytes.clone() } else { Vec::new() },
+            args: stage.args.clone(),
+            wasm_hash: stage.wasm_hash.clone(),
+            upload_on_miss: has_wasm && stage.module_source.is_none(),
+            protocol_version: PROTOCOL_VERSION,
+            module_source: stage.module_source.clone(),
+            timeout_ms: remaining_ms(),
+            stdin,
+            ..Default::default()
+        };
+
+        let response = match self.execute_stage(&assigned, job_request(send_wasm, stdin.clone())).await {
+            Err(e) if !send_wasm && has_wasm && ErrorCode::of(&e) == Some(ErrorCode::ModuleNotCached) => {
+                tracing::debug!(job_id = %job_id, "module not cached on worker, sending pipeline stage's wasm");
+                self.execute_stage(&assigned, job_request(true, stdin)).await?
+            },
+            result => result?,
+        };
+        Ok((assigned.worker_address, response))
+    }
+
+    /// Sends a job to the worker assigned to it, over its stream if it is relayed.
+    async fn execute_stage(&self, assigned: &WorkerResponse, job: JobRequest) -> Result<JobResponse, Status> {
+        if assigned.relayed {
+            let reply = self.relay_call(&assigned.worker_address, assigned.jwt_token.clone(), relayed_call::Call::ExecuteJob(job)).await?;
+            let relayed_reply::Reply::ExecuteJob(response) = reply else {
+                return Err(mismatched_reply());
+            };
+            return Ok(response);
+        }
+
+        let executor_client = connect_executor(&assigned.worker_address).await
+            .map_err(Status::unavailable)?;
+        let mut executor_client = executor_client.max_decoding_message_size(JobLimits::default().max_message_bytes());
+        let mut request = Request::new(job);
+        let jwt_token = assigned.jwt_token.parse()
+            .map_err(|e| Status::internal(format!("malformed jwt: {e}")))?;
+        request.metadata_mut().insert("authorization", jwt_token);
+        Ok(executor_client.execute_job(request).await?.into_inner())
+    }
+}
+
+/// A stage's failure, from the status its job failed with.
+fn stage_failure(stage: u32, job_id: JobId, status: &Status) -> StageFailure {
+    StageFailure {
+        stage,
+        job_id: job_id.to_bytes(),
+        code: status.code() as i32,
+        message: status.message().to_string(),
+        error_code: ErrorCode::of(status).map(|code| code.as_str().to_string()),
+    }
+}
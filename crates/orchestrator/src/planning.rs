@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use tonic::Status;
+
+use shared::{JobPlan, JobPlanSpec, WorkerPlan};
+
+use crate::module_uploads::ModuleHash;
+use crate::namespaces::Namespace;
+use crate::orchestrator::Orchestrator;
+
+impl Orchestrator {
+    /// Works out how the specs' jobs would be scheduled if they were submitted now, picking
+    /// their workers as dispatch would but from a snapshot of the registry, so no credits are
+    /// taken. Jobs that find no worker would queue behind the jobs queued already.
+    pub(crate) async fn plan_jobs(&self, specs: &[JobPlanSpec], namespace: &Namespace) -> Result<Vec<JobPlan>, Status> {
+        let hashes = specs.iter()
+            .map(|spec| spec.wasm_hash.as_deref().map(ModuleHash::try_from).transpose())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Status::invalid_argument("malformed wasm_hash, expected 32 bytes"))?;
+
+        let mut registry = self.registry.lock().await.clone();
+        let now = SystemTime::now();
+        let mut queued_ahead = self.queue_stats.snapshot(now).queued_jobs;
+
+        let plans = specs.iter().zip(hashes).map(|(spec, wasm_hash)| {
+            // Dispatch only goes by module when uploads are coordinated
+            let wasm_hash = wasm_hash.filter(|_| self.coordinate_uploads.is_some());
+            let mut workers: BTreeMap<String, WorkerPlan> = BTreeMap::new();
+            let mut dispatched = 0;
+            while dispatched < spec.count {
+                let Some((worker_address, module_cached)) = registry.select_worker(namespace, wasm_hash.as_ref()) else {
+                    break;
+                };
+                let worker = workers.entry(worker_address.clone())
+                    .or_insert_with(|| WorkerPlan { worker_address, jobs: 0, module_cached: 0 });
+                worker.jobs += 1;
+                worker.module_cached += module_cached as u32;
+                dispatched += 1;
+            }
+
+            let queued = spec.count - dispatched;
+            let estimated_wait = if queued == 0 {
+                Some(Duration::ZERO)
+            } else {
+                queued_ahead += queued as u64;
+                self.queue_stats.estimated_wait_at(queued_ahead, now)
+            };
+            JobPlan {
+                dispatched,
+                workers: workers.into_values().collect(),
+                queued,
+                estimated_wait_ms: estimated_wait.map(|wait| wait.as_millis() as u64),
+            }
+        }).collect();
+        Ok(plans)
+    }
+}
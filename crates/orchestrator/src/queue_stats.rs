@@ -0,0 +1,188 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds of dispatches the dispatch rate is averaged over.
+const RATE_WINDOW_SECS: u64 = 60;
+/// Bits of a dispatch slot holding its count, below the epoch second it counts.
+const COUNT_BITS: u32 = 24;
+const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
+
+/// Gauges describing how backed up the job queue is, updated by the queue and dispatch paths.
+/// Reading them takes no locks, so clients can poll them cheaply before submitting.
+#[derive(Debug)]
+pub struct QueueStats {
+    started_at: SystemTime,
+    queued_jobs: AtomicU64,
+    advertised_credits: AtomicU64,
+    /// Dispatches per second over the last RATE_WINDOW_SECS, as a ring indexed by the epoch
+    /// second modulo its length. Each slot holds the second it counts, packed above its count.
+    dispatches: [AtomicU64; RATE_WINDOW_SECS as usize],
+}
+
+/// A reading of the QueueStats gauges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueStatsSnapshot {
+    pub queued_jobs: u64,
+    pub advertised_credits: u64,
+    pub dispatches_last_minute: u64,
+    /// How long a job enqueued now can expect to wait for a worker, or None if jobs are queued
+    /// but none have been dispatched for a minute.
+    pub estimated_wait: Option<Duration>,
+}
+
+impl Default for QueueStats {
+    fn default() -> Self {
+        Self {
+            started_at: SystemTime::now(),
+            queued_jobs: AtomicU64::new(0),
+            advertised_credits: AtomicU64::new(0),
+            dispatches: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl QueueStats {
+    pub fn job_enqueued(&self) {
+        self.queued_jobs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn job_dequeued(&self) {
+        // Saturating, so a miscount can't wrap the gauge
+        let _ = self.queued_jobs.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| queued.checked_sub(1));
+    }
+
+    pub fn worker_connected(&self, credits: u32) {
+        self.advertised_credits.fetch_add(credits as u64, Ordering::Relaxed);
+    }
+
+    pub fn worker_disconnected(&self, credits: u32) {
+        let _ = self.advertised_credits.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| Some(total.saturating_sub(credits as u64)));
+    }
+
+    /// Counts a job handed to a worker at the given time.
+    pub fn record_dispatch(&self, at: SystemTime) {
+        let second = epoch_secs(at);
+        let slot = &self.dispatches[(second % RATE_WINDOW_SECS) as usize];
+        let _ = slot.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |packed| {
+            let count = if packed >> COUNT_BITS == second { packed & COUNT_MASK } else { 0 };
+            Some((second << COUNT_BITS) | (count + 1).min(COUNT_MASK))
+        });
+    }
+
+    /// Reads the gauges, estimating the wait as the time the last minute's dispatch rate takes
+    /// to work through the jobs already queued plus the new one. An empty queue means workers
+    /// have credits free, since jobs only queue while none can take them. Within a minute of
+    /// starting, the rate is over the time since starting.
+    pub fn snapshot(&self, now: SystemTime) -> QueueStatsSnapshot {
+        let queued_jobs = self.queued_jobs.load(Ordering::Relaxed);
+        let now_secs = epoch_secs(now);
+        let dispatches_last_minute = self.dispatches.iter()
+            .map(|slot| slot.load(Ordering::Relaxed))
+            .filter(|packed| now_secs.saturating_sub(packed >> COUNT_BITS) < RATE_WINDOW_SECS)
+            .map(|packed| packed & COUNT_MASK)
+            .sum();
+        let estimated_wait = if queued_jobs == 0 {
+            Some(Duration::ZERO)
+        } else {
+            self.wait_for_position(dispatches_last_minute, queued_jobs + 1, now)
+        };
+        QueueStatsSnapshot {
+            queued_jobs,
+            advertised_credits: self.advertised_credits.load(Ordering::Relaxed),
+            dispatches_last_minute,
+            estimated_wait,
+        }
+    }
+
+    /// How long a job at the given queue position, counting from 1, can expect to wait for a
+    /// worker, at the last minute's dispatch rate. None if nothing was dispatched in that time.
+    pub fn estimated_wait_at(&self, position: u64, now: SystemTime) -> Option<Duration> {
+        self.wait_for_position(self.snapshot(now).dispatches_last_minute, position, now)
+    }
+
+    fn wait_for_position(&self, dispatches_last_minute: u64, position: u64, now: SystemTime) -> Option<Duration> {
+        if dispatches_last_minute == 0 {
+            return None;
+        }
+        let window_secs = now.duration_since(self.started_at).unwrap_or_default().as_secs_f64()
+            .clamp(1.0, RATE_WINDOW_SECS as f64);
+        let per_second = dispatches_last_minute as f64 / window_secs;
+        Some(Duration::from_secs_f64(position as f64 / per_second))
+    }
+}
+
+fn epoch_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stats started at a whole second well after the epoch, with jobs queued.
+    fn stats(queued_jobs: u64) -> (QueueStats, SystemTime) {
+        let started_at = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let stats = QueueStats { started_at, ..QueueStats::default() };
+        (0..queued_jobs).for_each(|_| stats.job_enqueued());
+        (stats, started_at)
+    }
+
+    fn dispatch(stats: &QueueStats, at: SystemTime, count: u64) {
+        (0..count).for_each(|_| stats.record_dispatch(at));
+    }
+
+    #[test]
+    fn an_empty_queue_has_no_wait() {
+        let (stats, started_at) = stats(0);
+        assert_eq!(stats.snapshot(started_at).estimated_wait, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn a_queue_that_isnt_moving_has_no_estimate() {
+        let (stats, started_at) = stats(2);
+        assert_eq!(stats.snapshot(started_at + Duration::from_secs(5)).estimated_wait, None);
+    }
+
+    #[test]
+    fn the_wait_is_the_queue_at_the_last_minutes_rate() {
+        let (stats, started_at) = stats(3);
+        let now = started_at + Duration::from_secs(120);
+        // 30 dispatches in the last minute is one every 2 seconds, and a new job is 4th
+        dispatch(&stats, now - Duration::from_secs(59), 10);
+        dispatch(&stats, now, 20);
+        let snapshot = stats.snapshot(now);
+        assert_eq!((snapshot.queued_jobs, snapshot.dispatches_last_minute), (3, 30));
+        assert_eq!(snapshot.estimated_wait, Some(Duration::from_secs(8)));
+        assert_eq!(stats.estimated_wait_at(1, now), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn dispatches_over_a_minute_ago_dont_count() {
+        let (stats, started_at) = stats(1);
+        let now = started_at + Duration::from_secs(300);
+        dispatch(&stats, now - Duration::from_secs(60), 5);
+        assert_eq!(stats.snapshot(now).dispatches_last_minute, 0);
+        // A dispatch a minute later reuses the slot, starting its count afresh
+        dispatch(&stats, now, 1);
+        assert_eq!(stats.snapshot(now).dispatches_last_minute, 1);
+    }
+
+    #[test]
+    fn the_rate_is_over_the_uptime_in_the_first_minute() {
+        let (stats, started_at) = stats(1);
+        let now = started_at + Duration::from_secs(10);
+        dispatch(&stats, now, 5);
+        assert_eq!(stats.snapshot(now).estimated_wait, Some(Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn gauges_dont_go_below_zero() {
+        let (stats, started_at) = stats(1);
+        stats.job_dequeued();
+        stats.job_dequeued();
+        stats.worker_connected(2);
+        stats.worker_disconnected(3);
+        let snapshot = stats.snapshot(started_at);
+        assert_eq!((snapshot.queued_jobs, snapshot.advertised_credits), (0, 0));
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::Mutex;
+use shared::JobId;
+
+use crate::errors::OrchestratorError;
+use crate::identity::ClientIdentity;
+use crate::orchestrator::Orchestrator;
+use crate::worker_api::RESERVATION_TTL;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Per-client limits enforced when a job request is admitted. A limit of None is not enforced.
+#[derive(Debug, Clone, Default)]
+pub struct ClientQuotas {
+    /// Maximum number of jobs a client may have waiting in the queue.
+    pub max_queued_jobs: Option<u32>,
+    /// Maximum number of jobs a client may have in flight (queued or running on a worker).
+    pub max_concurrent_jobs: Option<u32>,
+    /// Maximum number of jobs a client may submit per UTC day.
+    pub max_jobs_per_day: Option<u32>,
+    /// Maximum module bytes a client's jobs may send to workers per UTC day. Workers report
+    /// the bytes once a job finishes, so a client is refused once its day's total reaches it.
+    pub max_module_bytes_per_day: Option<u64>,
+}
+
+/// Tracks the jobs each client currently has in flight and what it used today, to enforce
+/// ClientQuotas. Clients are told apart by their ClientIdentity.
+#[derive(Debug)]
+pub struct QuotaTracker {
+    quotas: ClientQuotas,
+    jobs: HashMap<JobId, TrackedJob>,
+    usage: HashMap<ClientIdentity, ClientUsage>,
+    /// The UTC day, in days since the epoch, that daily usage is counted for.
+    day: u64,
+    daily: HashMap<ClientIdentity, DailyUsage>,
+    next_dispatch: u64,
+}
+
+#[derive(Debug)]
+struct TrackedJob {
+    client: ClientIdentity,
+    dispatch: Option<Dispatch>,
+}
+
+/// The Worker a job was dispatched to, see QuotaTracker::mark_dispatched.
+#[derive(Debug)]
+struct Dispatch {
+    /// Tells this dispatch of the job from later ones, as retries keep the job's id.
+    id: u64,
+    worker_address: String,
+    /// Whether the Worker reported the job started.
+    started: bool,
+}
+
+/// The jobs a client has in flight.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientUsage {
+    pub queued: u32,
+    pub active: u32,
+}
+
+/// What a client used over the current UTC day.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DailyUsage {
+    pub jobs: u32,
+    pub module_bytes: u64,
+}
+
+impl QuotaTracker {
+    /// Create a new QuotaTracker enforcing the given quotas.
+    pub fn new(quotas: ClientQuotas) -> Self {
+        Self { quotas, jobs: HashMap::new(), usage: HashMap::new(), day: 0, daily: HashMap::new(), next_dispatch: 0 }
+    }
+
+    pub fn quotas(&self) -> &ClientQuotas {
+        &self.quotas
+    }
+
+    /// Admits a new queued job for the given client, or returns an error naming the quota that
+    /// would be violated. If the job is already tracked (a client retrying it after its worker
+    /// failed), its previous usage is released first.
+    pub fn try_admit(&mut self, job_id: JobId, client: &ClientIdentity, now: SystemTime) -> Result<(), OrchestratorError> {
+        self.release(&job_id);
+        self.roll_over(now);
+        let usage = self.usage.get(client).copied().unwrap_or_default();
+        let daily = self.daily.get(client).copied().unwrap_or_default();
+
+        if let Some(limit) = self.quotas.max_queued_jobs && usage.queued >= limit {
+            return Err(OrchestratorError::QuotaExceeded { quota: "max_queued_jobs", limit: limit.into() });
+        }
+        if let Some(limit) = self.quotas.max_concurrent_jobs && usage.queued + usage.active >= limit {
+            return Err(OrchestratorError::QuotaExceeded { quota: "max_concurrent_jobs", limit: limit.into() });
+        }
+        if let Some(limit) = self.quotas.max_jobs_per_day && daily.jobs >= limit {
+            return Err(OrchestratorError::DailyQuotaExceeded { quota: "max_jobs_per_day", limit: limit.into() });
+        }
+        if let Some(limit) = self.quotas.max_module_bytes_per_day && daily.module_bytes >= limit {
+            return Err(OrchestratorError::DailyQuotaExceeded { quota: "max_module_bytes_per_day", limit });
+        }
+
+        self.usage.entry(client.clone()).or_default().queued += 1;
+        self.daily.entry(client.clone()).or_default().jobs += 1;
+        self.jobs.insert(job_id, TrackedJob { client: client.clone(), dispatch: None });
+        Ok(())
+    }
+
+    /// Moves a job from the client's queued count to its active count. Returns the dispatch
+    /// to release the job by if its Worker never starts it, see release_unstarted.
+    pub fn mark_dispatched(&mut self, job_id: &JobId, worker_address: &str) -> Option<u64> {
+        let Some(job) = self.jobs.get_mut(job_id) else {
+            tracing::warn!(job_id = %job_id, "job not found in quota tracker during dispatch");
+            return None;
+        };
+        if job.dispatch.is_some() {
+            return None;
+        }
+        self.next_dispatch += 1;
+        job.dispatch = Some(Dispatch { id: self.next_dispatch, worker_address: worker_address.to_string(), started: false });
+        if let Some(usage) = self.usage.get_mut(&job.client) {
+            usage.queued = usage.queued.saturating_sub(1);
+            usage.active += 1;
+        }
+        Some(self.next_dispatch)
+    }
+
+    /// Notes that the Worker a job was dispatched to started it.
+    pub fn mark_started(&mut self, job_id: &JobId, worker_address: &str) {
+        if let Some(dispatch) = self.jobs.get_mut(job_id).and_then(|job| job.dispatch.as_mut())
+            && dispatch.worker_address == worker_address
+        {
+            dispatch.started = true;
+        }
+    }
+
+    /// Adds the module bytes a finished job sent to its Worker to its client's usage today.
+    pub fn record_module_bytes(&mut self, job_id: &JobId, module_bytes: u64, now: SystemTime) {
+        self.roll_over(now);
+        if let Some(job) = self.jobs.get(job_id) {
+            self.daily.entry(job.client.clone()).or_default().module_bytes += module_bytes;
+        }
+    }
+
+    /// Releases a job's quota usage. No-op if the job has already been released.
+    pub fn release(&mut self, job_id: &JobId) {
+        let Some(job) = self.jobs.remove(job_id) else {
+            return;
+        };
+        if let Some(usage) = self.usage.get_mut(&job.client) {
+            if job.dispatch.is_some() {
+                usage.active = usage.active.saturating_sub(1);
+            } else {
+                usage.queued = usage.queued.saturating_sub(1);
+            }
+            if *usage == ClientUsage::default() {
+                self.usage.remove(&job.client);
+            }
+        }
+    }
+
+    /// Releases a job's quota usage if its Worker still hasn't started it under the given
+    /// dispatch. Returns whether it did.
+    pub fn release_unstarted(&mut self, job_id: &JobId, dispatch: u64) -> bool {
+        let unstarted = self.jobs.get(job_id)
+            .and_then(|job| job.dispatch.as_ref())
+            .is_some_and(|d| d.id == dispatch && !d.started);
+        if unstarted {
+            self.release(job_id);
+        }
+        unstarted
+    }
+
+    /// Releases the quota usage of every job dispatched to a Worker that disconnected, whose
+    /// clients see them fail. Returns how many there were.
+    pub fn worker_disconnected(&mut self, worker_address: &str) -> usize {
+        let lost: Vec<JobId> = self.jobs.iter()
+            .filter(|(_, job)| job.dispatch.as_ref().is_some_and(|d| d.worker_address == worker_address))
+            .map(|(job_id, _)| *job_id)
+            .collect();
+        for job_id in &lost {
+            self.release(job_id);
+        }
+        lost.len()
+    }
+
+    /// Each client's jobs in flight and usage today, for those with either.
+    pub fn usage(&mut self, now: SystemTime) -> BTreeMap<ClientIdentity, (ClientUsage, DailyUsage)> {
+        self.roll_over(now);
+        let mut clients: BTreeMap<_, (ClientUsage, DailyUsage)> = BTreeMap::new();
+        for (client, usage) in &self.usage {
+            clients.entry(client.clone()).or_default().0 = *usage;
+        }
+        for (client, daily) in &self.daily {
+            clients.entry(client.clone()).or_default().1 = *daily;
+        }
+        clients
+    }
+
+    /// Starts counting daily usage afresh once the UTC day changes.
+    fn roll_over(&mut self, now: SystemTime) {
+        let day = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / SECS_PER_DAY;
+        if day != self.day {
+            self.day = day;
+            self.daily.clear();
+        }
+    }
+}
+
+/// RAII guard that releases a job's quota usage if its request is dropped (client disconnected
+/// or job cancelled) before the job is dispatched to a worker.
+pub struct QueuedJobGuard {
+    tracker: Arc<Mutex<QuotaTracker>>,
+    job_id: JobId,
+    dispatched: bool,
+}
+
+impl QueuedJobGuard {
+    /// Creates a new guard for a job that has already been admitted by the tracker.
+    pub fn new(tracker: Arc<Mutex<QuotaTracker>>, job_id: JobId) -> Self {
+        Self { tracker, job_id, dispatched: false }
+    }
+
+    pub fn set_dispatched(&mut self) {
+        self.dispatched = true;
+    }
+}
+
+impl Drop for QueuedJobGuard {
+    /// Releases the job's quota usage unless it was dispatched, in which case it is
+    /// released once the worker reports the job as finished.
+    fn drop(&mut self) {
+        if self.dispatched {
+            return;
+        }
+        let tracker = self.tracker.clone();
+        let job_id = self.job_id;
+        tokio::spawn(async move {
+            tracker.lock().await.release(&job_id);
+        });
+    }
+}
+
+impl Orchestrator {
+    /// Counts a job just dispatched to a Worker against its client's active jobs until the
+    /// Worker reports it finished. If the Worker hasn't started it by the time a reservation
+    /// for it would expire, its client presumably never sent it there, so it is released then.
+    pub(crate) async fn hold_quota(&self, job_id: JobId, worker_address: &str) {
+        let Some(dispatch) = self.quota_tracker.lock().await.mark_dispatched(&job_id, worker_address) else {
+            return;
+        };
+        let tracker = self.quota_tracker.clone();
+        let worker_address = worker_address.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(RESERVATION_TTL).await;
+            if tracker.lock().await.release_unstarted(&job_id, dispatch) {
+                tracing::info!(job_id = %job_id, worker = %worker_address, "worker never started the job, releasing its client quota");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::namespaces::Namespace;
+
+    fn host(last: u8) -> ClientIdentity {
+        ClientIdentity::Host(Ipv4Addr::new(10, 0, 0, last).into())
+    }
+
+    fn quota_of(error: OrchestratorError) -> &'static str {
+        match error {
+            OrchestratorError::QuotaExceeded { quota, .. } | OrchestratorError::DailyQuotaExceeded { quota, .. } => quota,
+            other => panic!("expected a quota error, got {other:?}"),
+        }
+    }
+
+    const NOON: Duration = Duration::from_secs(20_000 * SECS_PER_DAY + 12 * 60 * 60);
+
+    #[test]
+    fn queued_jobs_are_limited() {
+        let mut tracker = QuotaTracker::new(ClientQuotas { max_queued_jobs: Some(2), ..Default::default() });
+        let now = UNIX_EPOCH + NOON;
+        let first = JobId::random();
+        tracker.try_admit(first, &host(1), now).unwrap();
+        tracker.try_admit(JobId::random(), &host(1), now).unwrap();
+        assert_eq!(quota_of(tracker.try_admit(JobId::random(), &host(1), now).unwrap_err()), "max_queued_jobs");
+
+        // Other clients have quotas of their own, and dispatching a job frees a queued slot
+        tracker.try_admit(JobId::random(), &host(2), now).unwrap();
+        tracker.mark_dispatched(&first, "w1");
+        tracker.try_admit(JobId::random(), &host(1), now).unwrap();
+    }
+
+    #[test]
+    fn concurrent_jobs_are_limited_until_released() {
+        let mut tracker = QuotaTracker::new(ClientQuotas { max_concurrent_jobs: Some(1), ..Default::default() });
+        let now = UNIX_EPOCH + NOON;
+        let job = JobId::random();
+        tracker.try_admit(job, &host(1), now).unwrap();
+        tracker.mark_dispatched(&job, "w1");
+        assert_eq!(quota_of(tracker.try_admit(JobId::random(), &host(1), now).unwrap_err()), "max_concurrent_jobs");
+
+        tracker.release(&job);
+        tracker.try_admit(JobId::random(), &host(1), now).unwrap();
+    }
+
+    #[test]
+    fn token_clients_share_a_quota_across_hosts() {
+        let mut tracker = QuotaTracker::new(ClientQuotas { max_concurrent_jobs: Some(1), ..Default::default() });
+        let now = UNIX_EPOCH + NOON;
+        let team = ClientIdentity::Token(Namespace::new("team").unwrap());
+        tracker.try_admit(JobId::random(), &team, now).unwrap();
+        assert!(tracker.try_admit(JobId::random(), &team, now).is_err());
+    }
+
+    #[test]
+    fn retried_job_is_counted_once() {
+        let mut tracker = QuotaTracker::new(ClientQuotas { max_concurrent_jobs: Some(1), ..Default::default() });
+        let now = UNIX_EPOCH + NOON;
+        let job = JobId::random();
+        tracker.try_admit(job, &host(1), now).unwrap();
+        tracker.mark_dispatched(&job, "w1");
+        tracker.try_admit(job, &host(1), now).unwrap();
+    }
+
+    #[test]
+    fn daily_jobs_are_limited_until_the_utc_day_ends() {
+        let mut tracker = QuotaTracker::new(ClientQuotas { max_jobs_per_day: Some(2), ..Default::default() });
+        let now = UNIX_EPOCH + NOON;
+        for _ in 0..2 {
+            let job = JobId::random();
+            tracker.try_admit(job, &host(1), now).unwrap();
+            tracker.release(&job);
+        }
+        assert_eq!(quota_of(tracker.try_admit(JobId::random(), &host(1), now).unwrap_err()), "max_jobs_per_day");
+        let just_before_midnight = now + Duration::from_secs(12 * 60 * 60 - 1);
+        assert!(tracker.try_admit(JobId::random(), &host(1), just_before_midnight).is_err());
+
+        let midnight = now + Duration::from_secs(12 * 60 * 60);
+        tracker.try_admit(JobId::random(), &host(1), midnight).unwrap();
+    }
+
+    #[test]
+    fn daily_module_bytes_are_limited() {
+        let mut tracker = QuotaTracker::new(ClientQuotas { max_module_bytes_per_day: Some(1000), ..Default::default() });
+        let now = UNIX_EPOCH + NOON;
+        let job = JobId::random();
+        tracker.try_admit(job, &host(1), now).unwrap();
+        tracker.record_module_bytes(&job, 999, now);
+        tracker.release(&job);
+        let job = JobId::random();
+        tracker.try_admit(job, &host(1), now).unwrap();
+        tracker.record_module_bytes(&job, 1, now);
+        tracker.release(&job);
+
+        let error = tracker.try_admit(JobId::random(), &host(1), now).unwrap_err();
+        assert_eq!(tonic::Status::from(error).code(), tonic::Code::ResourceExhausted);
+        tracker.try_admit(JobId::random(), &host(1), now + Duration::from_secs(SECS_PER_DAY)).unwrap();
+    }
+
+    #[test]
+    fn disconnected_workers_release_their_jobs() {
+        let mut tracker = QuotaTracker::new(ClientQuotas { max_concurrent_jobs: Some(2), ..Default::default() });
+        let now = UNIX_EPOCH + NOON;
+        let (on_w1, on_w2) = (JobId::random(), JobId::random());
+        tracker.try_admit(on_w1, &host(1), now).unwrap();
+        tracker.try_admit(on_w2, &host(1), now).unwrap();
+        tracker.mark_dispatched(&on_w1, "w1");
+        tracker.mark_dispatched(&on_w2, "w2");
+
+        assert_eq!(tracker.worker_disconnected("w1"), 1);
+        tracker.try_admit(JobId::random(), &host(1), now).unwrap();
+        assert!(tracker.try_admit(JobId::random(), &host(1), now).is_err());
+    }
+
+    #[test]
+    fn only_unstarted_dispatches_are_released() {
+        let mut tracker = QuotaTracker::new(ClientQuotas::default());
+        let now = UNIX_EPOCH + NOON;
+        let (started, unstarted) = (JobId::random(), JobId::random());
+        tracker.try_admit(started, &host(1), now).unwrap();
+        tracker.try_admit(unstarted, &host(1), now).unwrap();
+        let started_dispatch = tracker.mark_dispatched(&started, "w1").unwrap();
+        let unstarted_dispatch = tracker.mark_dispatched(&unstarted, "w1").unwrap();
+        // Updates from other workers don't count
+        tracker.mark_started(&unstarted, "w2");
+        tracker.mark_started(&started, "w1");
+
+        assert!(!tracker.release_unstarted(&started, started_dispatch));
+        assert!(tracker.release_unstarted(&unstarted, unstarted_dispatch));
+        assert_eq!(tracker.usage(now)[&host(1)].0, ClientUsage { queued: 0, active: 1 });
+    }
+
+    #[test]
+    fn earlier_dispatch_doesnt_release_a_retry() {
+        let mut tracker = QuotaTracker::new(ClientQuotas::default());
+        let now = UNIX_EPOCH + NOON;
+        let job = JobId::random();
+        tracker.try_admit(job, &host(1), now).unwrap();
+        let first = tracker.mark_dispatched(&job, "w1").unwrap();
+        tracker.try_admit(job, &host(1), now).unwrap();
+        tracker.mark_dispatched(&job, "w1").unwrap();
+        assert!(!tracker.release_unstarted(&job, first));
+    }
+
+    #[test]
+    fn usage_lists_clients_in_flight_or_active_today() {
+        let mut tracker = QuotaTracker::new(ClientQuotas::default());
+        let now = UNIX_EPOCH + NOON;
+        let (done, queued) = (JobId::random(), JobId::random());
+        tracker.try_admit(done, &host(1), now).unwrap();
+        tracker.record_module_bytes(&done, 10, now);
+        tracker.release(&done);
+        tracker.try_admit(queued, &host(2), now).unwrap();
+
+        let usage = tracker.usage(now);
+        assert_eq!(usage[&host(1)], (ClientUsage::default(), DailyUsage { jobs: 1, module_bytes: 10 }));
+        assert_eq!(usage[&host(2)], (ClientUsage { queued: 1, active: 0 }, DailyUsage { jobs: 1, module_bytes: 0 }));
+        assert!(!tracker.usage(now + Duration::from_secs(SECS_PER_DAY)).contains_key(&host(1)));
+    }
+}
@@ -1,58 +1,328 @@
+use std::collections::{HashMap, HashSet};
+
+use hashlink::LinkedHashMap;
 use priority_queue::PriorityQueue;
 
+use crate::module_uploads::ModuleHash;
+use crate::namespaces::Namespace;
+
+/// Most modules whose Workers are remembered.
+const MAX_TRACKED_MODULES: usize = 4096;
+
 /// Registry to manage the Workers registered to this Orchestrator.
-#[derive(Debug)]
+/// Quarantined, drained and validating workers are held separately and never dispatched to
+/// until their quarantine is released, they are resumed and they have been validated.
+/// Workers pinned to a namespace are only dispatched that namespace's jobs.
+/// Which Workers have which modules compiled is tracked when upload coordination is on, so that
+/// jobs can go to Workers that have their module. Workers busy with other Orchestrators' jobs
+/// may have some of their credits held back, and are only dispatched jobs beyond them.
+/// Cloning it gives a snapshot that jobs can be planned against without taking real credits.
+#[derive(Debug, Clone)]
 pub struct WorkerRegistry {
     inner: PriorityQueue<String, u32>,
+    /// Available credits of the workers withheld from dispatch.
+    held: HashMap<String, u32>,
+    quarantined: HashSet<String>,
+    drained: HashSet<String>,
+    /// Workers that haven't yet answered a health probe, see ValidationPolicy.
+    validating: HashSet<String>,
+    pinned: HashMap<String, Namespace>,
+    /// Workers known to have each module compiled, least recently run first. They may have
+    /// evicted it since.
+    modules: LinkedHashMap<ModuleHash, HashSet<String>>,
+    /// Credits taken from each Worker by other Orchestrators' jobs beyond those it had
+    /// available, paid off by the credits it returns.
+    owed: HashMap<String, u32>,
+    /// Credits held back from Workers busy with other Orchestrators' jobs, see OutOfBandTracker.
+    reserved: HashMap<String, u32>,
+}
+
+impl Default for WorkerRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WorkerRegistry {
     /// Create a new WorkerRegistry.
     pub fn new() -> Self {
         Self {
-            inner: PriorityQueue::new()
+            inner: PriorityQueue::new(),
+            held: HashMap::new(),
+            quarantined: HashSet::new(),
+            drained: HashSet::new(),
+            validating: HashSet::new(),
+            pinned: HashMap::new(),
+            modules: LinkedHashMap::new(),
+            owed: HashMap::new(),
+            reserved: HashMap::new(),
         }
     }
 
-    /// Registers a new Worker with the given address.
-    pub fn register_worker(&mut self, address: String, credits: u32) {
+    /// Registers a new Worker with the given address, pinned to the namespace if given.
+    pub fn register_worker(&mut self, address: String, credits: u32, namespace: Option<Namespace>) {
+        self.pin(&address, namespace);
         self.inner.push(address, credits);
     }
 
-    /// Retrieves the Worker address with the most available credits from the registry and 
-    /// decrements its credit count by one. Returns None if there are no Workers with 
-    /// any available credits.
-    pub fn get_worker(&mut self) -> Option<String> {
-        if let Some((address, credits)) = self.inner.peek() {
-            let (address, credits) = (address.clone(), *credits);
-            if credits > 0 {
-                self.inner.change_priority(&address, credits - 1);
-                Some(address)
-            } else {
-                None
-            }
+    /// Registers a new Worker that won't receive any jobs until release_quarantine is called.
+    pub fn register_quarantined_worker(&mut self, address: String, credits: u32, namespace: Option<Namespace>) {
+        self.pin(&address, namespace);
+        self.quarantined.insert(address.clone());
+        self.held.insert(address, credits);
+    }
+
+    fn pin(&mut self, address: &str, namespace: Option<Namespace>) {
+        if let Some(namespace) = namespace {
+            self.pinned.insert(address.to_string(), namespace);
+        }
+    }
+
+    /// Makes a quarantined Worker eligible for dispatch again, unless it is drained. No-op if
+    /// the worker isn't quarantined (e.g. it has since disconnected).
+    pub fn release_quarantine(&mut self, address: &str) {
+        if self.quarantined.remove(address) {
+            self.release_if_unheld(address);
+        }
+    }
+
+    /// Withholds a registered Worker from dispatch until release_quarantine is called.
+    /// Returns false if the worker isn't registered.
+    pub fn quarantine_worker(&mut self, address: &str) -> bool {
+        if let Some((address, credits)) = self.inner.remove(address) {
+            self.held.insert(address.clone(), credits);
+            self.quarantined.insert(address);
+        } else if self.held.contains_key(address) {
+            self.quarantined.insert(address.to_string());
         } else {
-            None
+            return false;
         }
+        true
+    }
+
+    /// Stops dispatching jobs to a Worker, letting the jobs it is running finish.
+    /// Returns false if the worker isn't registered.
+    pub fn drain_worker(&mut self, address: &str) -> bool {
+        if let Some((address, credits)) = self.inner.remove(address) {
+            self.held.insert(address.clone(), credits);
+            self.drained.insert(address);
+        } else if self.held.contains_key(address) {
+            self.drained.insert(address.to_string());
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Undoes drain_worker, making the Worker eligible for dispatch again unless it is
+    /// quarantined. Returns false if the worker isn't drained.
+    pub fn resume_worker(&mut self, address: &str) -> bool {
+        if !self.drained.remove(address) {
+            return false;
+        }
+        self.release_if_unheld(address);
+        true
+    }
+
+    /// Withholds a registered Worker from dispatch until release_validation is called.
+    /// Returns false if the worker isn't registered.
+    pub fn hold_for_validation(&mut self, address: &str) -> bool {
+        if let Some((address, credits)) = self.inner.remove(address) {
+            self.held.insert(address.clone(), credits);
+            self.validating.insert(address);
+        } else if self.held.contains_key(address) {
+            self.validating.insert(address.to_string());
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Makes a validated Worker eligible for dispatch, unless it is quarantined or drained.
+    /// No-op if the worker isn't held for validation.
+    pub fn release_validation(&mut self, address: &str) {
+        if self.validating.remove(address) {
+            self.release_if_unheld(address);
+        }
+    }
+
+    /// Moves a held Worker back into dispatch once nothing is holding it.
+    fn release_if_unheld(&mut self, address: &str) {
+        if self.quarantined.contains(address) || self.drained.contains(address) || self.validating.contains(address) {
+            return;
+        }
+        if let Some((address, credits)) = self.held.remove_entry(address) {
+            self.inner.push(address, credits);
+        }
+    }
+
+    /// Picks the Worker to run one of the namespace's jobs and takes one of its credits. Workers
+    /// known to have the job's module take it first, sparing it an upload, otherwise the one
+    /// with the most available credits does. Returns the Worker and whether it has the module,
+    /// or None if no Worker may take the job now.
+    pub fn select_worker(&mut self, namespace: &Namespace, wasm_hash: Option<&ModuleHash>) -> Option<(String, bool)> {
+        if let Some(address) = wasm_hash.and_then(|hash| self.get_worker_with_module(namespace, hash)) {
+            return Some((address, true));
+        }
+        self.get_worker(namespace).map(|address| (address, false))
+    }
+
+    /// Retrieves the Worker address with the most available credits that may run the
+    /// namespace's jobs and decrements its credit count by one. Returns None if there are no
+    /// such Workers with any available credits.
+    pub fn get_worker(&mut self, namespace: &Namespace) -> Option<String> {
+        let address = self.best_worker(namespace)?;
+        self.inner.change_priority_by(&address, |credits| *credits -= 1);
+        Some(address)
+    }
+
+    /// Like get_worker, but only retrieves a Worker known to have the module compiled.
+    pub fn get_worker_with_module(&mut self, namespace: &Namespace, wasm_hash: &ModuleHash) -> Option<String> {
+        let address = self.modules.get(wasm_hash)?.iter()
+            .find(|address| {
+                namespace.may_run_on(self.pinned.get(*address))
+                    && self.inner.get_priority(*address).is_some_and(|credits| self.spare_credits(address, *credits) > 0)
+            })?
+            .clone();
+        self.inner.change_priority_by(&address, |credits| *credits -= 1);
+        Some(address)
+    }
+
+    /// Records that a Worker has a module compiled, as it ran a job with it. Only the most
+    /// recently run MAX_TRACKED_MODULES modules are remembered.
+    pub fn record_module(&mut self, wasm_hash: ModuleHash, worker_address: &str) {
+        let mut holders = self.modules.remove(&wasm_hash).unwrap_or_default();
+        holders.insert(worker_address.to_string());
+        self.modules.insert(wasm_hash, holders);
+        while self.modules.len() > MAX_TRACKED_MODULES {
+            self.modules.pop_front();
+        }
+    }
+
+    /// Whether any Worker is known to have the module compiled.
+    pub fn has_module(&self, wasm_hash: &ModuleHash) -> bool {
+        self.modules.contains_key(wasm_hash)
+    }
+
+    /// The Worker with the most spare credits that may run the namespace's jobs, if it has
+    /// any. Only scans the registry when some Worker is pinned or has credits held back.
+    fn best_worker(&self, namespace: &Namespace) -> Option<String> {
+        let best = if self.pinned.is_empty() && self.reserved.is_empty() {
+            self.inner.peek().map(|(address, credits)| (address, *credits))
+        } else {
+            self.inner.iter()
+                .filter(|(address, _)| namespace.may_run_on(self.pinned.get(*address)))
+                .map(|(address, credits)| (address, self.spare_credits(address, *credits)))
+                .max_by_key(|(_, spare)| *spare)
+        };
+        best.filter(|(_, spare)| *spare > 0)
+            .map(|(address, _)| address.clone())
+    }
+
+    /// A Worker's available credits beyond those held back from it.
+    fn spare_credits(&self, address: &str, credits: u32) -> u32 {
+        credits.saturating_sub(self.reserved.get(address).copied().unwrap_or(0))
+    }
+
+    /// Holds back credits from a Worker, which is only dispatched jobs while it has more
+    /// available. Zero holds back none. Returns whether that changed what is held back.
+    pub fn set_reserve(&mut self, worker_address: &str, credits: u32) -> bool {
+        let previous = if credits == 0 {
+            self.reserved.remove(worker_address)
+        } else {
+            self.reserved.insert(worker_address.to_string(), credits)
+        };
+        previous.unwrap_or(0) != credits
     }
 
     /// Update the credit count for a given worker address in the registry.
     /// Logs an error if the worker isn't in the registry.
     pub fn update_credits(&mut self, worker_address: &str, delta: u32) {
-        if !self.inner.change_priority_by(worker_address, |p| *p += delta) {
+        let delta = match self.owed.get_mut(worker_address) {
+            Some(owed) => {
+                let paid = delta.min(*owed);
+                *owed -= paid;
+                if *owed == 0 {
+                    self.owed.remove(worker_address);
+                }
+                delta - paid
+            },
+            None => delta,
+        };
+        if let Some(credits) = self.held.get_mut(worker_address) {
+            *credits += delta;
+        } else if !self.inner.change_priority_by(worker_address, |p| *p += delta) {
             tracing::warn!(worker = %worker_address, "attempted to update credits for an unknown worker");
         }
     }
 
+    /// Takes credits from a Worker for jobs another Orchestrator it is registered with
+    /// dispatched to it. Credits beyond those it has available are owed until it returns them.
+    pub fn take_credits(&mut self, worker_address: &str, taken: u32) {
+        let available = match self.held.get(worker_address) {
+            Some(credits) => *credits,
+            None => match self.inner.get_priority(worker_address) {
+                Some(credits) => *credits,
+                None => {
+                    tracing::warn!(worker = %worker_address, "attempted to take credits from an unknown worker");
+                    return;
+                },
+            },
+        };
+        let take = taken.min(available);
+        if let Some(credits) = self.held.get_mut(worker_address) {
+            *credits -= take;
+        } else {
+            self.inner.change_priority_by(worker_address, |credits| *credits -= take);
+        }
+        if taken > take {
+            *self.owed.entry(worker_address.to_string()).or_default() += taken - take;
+        }
+    }
+
     /// Removes a given worker from the registry. Logs an error if the worker isn't present.
     pub fn deregister_worker(&mut self, worker_address: &str) {
-        if self.inner.remove(worker_address).is_none() {
+        self.owed.remove(worker_address);
+        self.reserved.remove(worker_address);
+        self.quarantined.remove(worker_address);
+        self.drained.remove(worker_address);
+        self.validating.remove(worker_address);
+        self.pinned.remove(worker_address);
+        self.modules.retain(|_, holders| {
+            holders.remove(worker_address);
+            !holders.is_empty()
+        });
+        if self.inner.remove(worker_address).is_none() && self.held.remove(worker_address).is_none() {
             tracing::warn!(worker = %worker_address, "attempted to remove an unknown worker");
         }
     }
 
-    /// Returns true if any registered Worker has at least one available credit.
-    pub fn has_available_credits(&self) -> bool {
-        self.inner.peek().map(|(_, &credits)| credits > 0).unwrap_or(false)
+    /// Returns a Worker's available credits, whether or not it is held from dispatch, or None
+    /// if it isn't registered.
+    pub fn available_credits(&self, worker_address: &str) -> Option<u32> {
+        self.held.get(worker_address).or_else(|| self.inner.get_priority(worker_address)).copied()
     }
-}
\ No newline at end of file
+
+    /// Whether a Worker may run the namespace's jobs, as it isn't pinned to another namespace.
+    pub fn may_run(&self, worker_address: &str, namespace: &Namespace) -> bool {
+        namespace.may_run_on(self.pinned.get(worker_address))
+    }
+
+    /// Takes one of a Worker's spare credits for a job pinned to it, however many the other
+    /// Workers have. Returns false if it has none to spare, or is held from dispatch.
+    pub fn take_pinned_credit(&mut self, worker_address: &str) -> bool {
+        let spare = self.inner.get_priority(worker_address)
+            .map_or(0, |credits| self.spare_credits(worker_address, *credits));
+        if spare == 0 {
+            return false;
+        }
+        self.inner.change_priority_by(worker_address, |credits| *credits -= 1);
+        true
+    }
+
+    /// Returns true if any registered Worker that may run the namespace's jobs has at least
+    /// one available credit.
+    pub fn has_available_credits(&self, namespace: &Namespace) -> bool {
+        self.best_worker(namespace).is_some()
+    }
+}
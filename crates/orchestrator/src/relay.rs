@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::{Mutex, oneshot};
+use tonic::Status;
+
+use shared::{OrchestratorMessage, RelayedCall, RelayedReply, orchestrator_message, relayed_call, relayed_reply};
+
+use crate::orchestrator::Orchestrator;
+
+/// Calls relayed to Workers that accept no connections, awaiting their replies.
+#[derive(Debug, Default, Clone)]
+pub struct RelayedCalls {
+    next_call_id: Arc<AtomicU64>,
+    pending: Arc<Mutex<HashMap<u64, PendingCall>>>,
+}
+
+/// The Worker a call went to, and where its reply goes.
+#[derive(Debug)]
+struct PendingCall {
+    worker_address: String,
+    reply_tx: oneshot::Sender<relayed_reply::Reply>,
+}
+
+impl Orchestrator {
+    /// Relays a call to a relayed Worker over its stream and waits for the reply. Fails with
+    /// UNAVAILABLE if the Worker isn't connected or disconnects first, as a direct call would.
+    pub(crate) async fn relay_call(&self, worker_address: &str, jwt_token: String, call: relayed_call::Call) -> Result<relayed_reply::Reply, Status> {
+        if !self.relayed_workers.lock().await.contains(worker_address) {
+            return Err(Status::unavailable(format!("worker {worker_address} is not connected for relaying")));
+        }
+        let Some(tx) = self.worker_streams.lock().await.get(worker_address).cloned() else {
+            return Err(Status::unavailable(format!("worker {worker_address} is not connected for relaying")));
+        };
+
+        let call_id = self.relayed_calls.next_call_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.relayed_calls.pending.lock().await.insert(call_id, PendingCall { worker_address: worker_address.to_string(), reply_tx });
+        let message = OrchestratorMessage {
+            message: Some(orchestrator_message::Message::RelayedCall(Box::new(RelayedCall { call_id, jwt_token, call: Some(call) }))),
+        };
+        if tx.send(Ok(message)).await.is_err() {
+            self.relayed_calls.pending.lock().await.remove(&call_id);
+            return Err(Status::unavailable(format!("worker {worker_address} disconnected")));
+        }
+        match reply_rx.await {
+            Ok(relayed_reply::Reply::Error(error)) => Err(error.into()),
+            Ok(reply) => Ok(reply),
+            Err(_) => Err(Status::unavailable(format!("worker {worker_address} disconnected before replying"))),
+        }
+    }
+
+    /// Delivers a relayed Worker's reply to the call waiting for it. Replies from a Worker the
+    /// call didn't go to are dropped. A reply with no content is an error, after which the
+    /// Worker's stream is dropped, failing the calls relayed to it.
+    pub(crate) async fn handle_relayed_reply(&self, worker_address: &str, reply: RelayedReply) -> Result<(), Status> {
+        let Some(content) = reply.reply else {
+            return Err(Status::invalid_argument(format!("relayed reply to call {} has no content", reply.call_id)));
+        };
+        let mut pending = self.relayed_calls.pending.lock().await;
+        match pending.remove(&reply.call_id) {
+            Some(call) if call.worker_address == worker_address => {
+                let _ = call.reply_tx.send(content);
+            },
+            Some(call) => {
+                tracing::warn!(worker = %worker_address, call_id = reply.call_id, "worker replied to a call relayed to another worker");
+                pending.insert(reply.call_id, call);
+            },
+            None => tracing::debug!(worker = %worker_address, call_id = reply.call_id, "reply to a relayed call no longer waited for"),
+        }
+        Ok(())
+    }
+
+    /// Fails the calls relayed to a Worker that disconnected.
+    pub(crate) async fn fail_relayed_calls(&self, worker_address: &str) {
+        self.relayed_calls.pending.lock().await.retain(|_, call| call.worker_address != worker_address);
+    }
+}
+
+/// The error for a relayed call answered with the response to another kind of call.
+pub(crate) fn mismatched_reply() -> Status {
+    Status::internal("the worker replied to a relayed call with another call's response")
+}
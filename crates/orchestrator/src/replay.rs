@@ -0,0 +1,152 @@
+use hashlink::LinkedHashMap;
+use shared::{JobId, JobSubmission, ModuleSource, ObjectStoreModule, module_source};
+use shared::signing::stdin_digest;
+
+use crate::errors::OrchestratorError;
+use crate::namespaces::Namespace;
+use crate::orchestrator::Orchestrator;
+
+/// Most bytes of stdin kept for replaying the pipeline stages that read it.
+pub const REPLAY_STDIN_BUDGET: usize = 64 * 1024 * 1024;
+
+/// Copies of the stdin the Orchestrator relayed to pipeline stages, by SHA-256 digest, so that
+/// replays of the stages read it again. Workers report the digest of what each job read, see
+/// JobDetails. The least recently used copies are dropped once they add up to more than the
+/// budget, after which the client has to send the stdin itself.
+#[derive(Debug)]
+pub struct StdinCopies {
+    budget: usize,
+    copies: LinkedHashMap<[u8; 32], Vec<u8>>,
+    total_bytes: usize,
+}
+
+impl StdinCopies {
+    pub fn new(budget: usize) -> Self {
+        Self { budget, copies: LinkedHashMap::new(), total_bytes: 0 }
+    }
+
+    /// Keeps a copy of stdin, unless it alone is over the budget.
+    pub fn keep(&mut self, stdin: &[u8]) {
+        if stdin.is_empty() || stdin.len() > self.budget {
+            return;
+        }
+        let digest = stdin_digest(stdin);
+        if self.copies.to_back(&digest).is_some() {
+            return;
+        }
+        self.copies.insert(digest, stdin.to_vec());
+        self.total_bytes += stdin.len();
+        while self.total_bytes > self.budget {
+            let Some((_, dropped)) = self.copies.pop_front() else {
+                break;
+            };
+            self.total_bytes -= dropped.len();
+        }
+    }
+
+    /// The stdin with the SHA-256 digest, if a copy is kept.
+    pub fn get(&mut self, digest: &[u8]) -> Option<Vec<u8>> {
+        let digest: [u8; 32] = digest.try_into().ok()?;
+        self.copies.to_back(&digest).cloned()
+    }
+}
+
+impl Orchestrator {
+    /// What a job ran, as its Worker reported once the job began executing, for replaying it.
+    /// The Orchestrator never has a job's wasm, so a replay runs the module by its hash, on a
+    /// Worker that still has it compiled, unless the client uploads it again. Any stdin the job
+    /// read is sent along if a copy is kept, see StdinCopies.
+    pub(crate) async fn job_submission(&self, job_id: JobId, namespace: &Namespace) -> Result<JobSubmission, OrchestratorError> {
+        let (details, worker_address) = {
+            let job_info = self.diagnostics.jobs.get(&job_id)
+                .ok_or(OrchestratorError::JobNotFound)?;
+            if job_info.namespace != *namespace {
+                return Err(OrchestratorError::JobInOtherNamespace);
+            }
+            let details = job_info.details.clone()
+                .ok_or(OrchestratorError::JobNotStarted)?;
+            (details, job_info.worker_address.clone())
+        };
+        let stdin = if details.stdin_sha256.is_empty() {
+            None
+        } else {
+            self.stdin_copies.lock().await.get(&details.stdin_sha256)
+        };
+        let module_source = details.module_url.as_ref().map(|url| ModuleSource {
+            source: Some(module_source::Source::ObjectStore(ObjectStoreModule {
+                url: url.clone(),
+                digest: details.module_hash.clone(),
+                hash_algorithm: details.module_hash_algorithm,
+            })),
+        });
+        Ok(JobSubmission {
+            wasm_hash: if module_source.is_some() { Vec::new() } else { details.module_hash.clone() },
+            module_source,
+            args: details.args,
+            worker_address,
+            stdin_sha256: details.stdin_sha256,
+            stdin,
+        })
+    }
+
+    /// Takes a credit on the Worker a job is pinned to, as dispatch would have, or fails if
+    /// that Worker can't take the job now. Pinned jobs don't queue, so that a Worker that has
+    /// gone away or stays busy fails the job rather than holding it indefinitely.
+    pub(crate) async fn take_pinned_worker(&self, worker_address: &str, namespace: &Namespace) -> Result<(), OrchestratorError> {
+        let mut registry = self.registry.lock().await;
+        if registry.available_credits(worker_address).is_none() {
+            return Err(OrchestratorError::WorkerNotFound(worker_address.to_string()));
+        }
+        if !registry.may_run(worker_address, namespace) {
+            return Err(OrchestratorError::WorkerInOtherNamespace(worker_address.to_string()));
+        }
+        if !registry.take_pinned_credit(worker_address) {
+            return Err(OrchestratorError::WorkerBusy(worker_address.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_are_found_by_digest() {
+        let mut copies = StdinCopies::new(100);
+        copies.keep(b"input");
+        assert_eq!(copies.get(&stdin_digest(b"input")).as_deref(), Some(&b"input"[..]));
+        assert_eq!(copies.get(&stdin_digest(b"other")), None);
+        assert_eq!(copies.get(b"not a digest"), None);
+    }
+
+    #[test]
+    fn least_recently_used_copies_are_dropped_past_the_budget() {
+        let mut copies = StdinCopies::new(100);
+        let [a, b, c] = [b'a', b'b', b'c'].map(|byte| vec![byte; 40]);
+        copies.keep(&a);
+        copies.keep(&b);
+        copies.get(&stdin_digest(&a));
+        copies.keep(&c);
+        assert!(copies.get(&stdin_digest(&a)).is_some());
+        assert!(copies.get(&stdin_digest(&b)).is_none());
+        assert!(copies.get(&stdin_digest(&c)).is_some());
+        assert_eq!(copies.total_bytes, 80);
+    }
+
+    #[test]
+    fn the_same_stdin_is_kept_once() {
+        let mut copies = StdinCopies::new(100);
+        copies.keep(b"input");
+        copies.keep(b"input");
+        assert_eq!(copies.total_bytes, 5);
+    }
+
+    #[test]
+    fn stdin_over_the_budget_is_not_kept() {
+        let mut copies = StdinCopies::new(4);
+        copies.keep(b"input");
+        assert!(copies.get(&stdin_digest(b"input")).is_none());
+        assert_eq!(copies.total_bytes, 0);
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::diagnostics::{ManagedWorkerInfo, ManagedWorkerState};
+use crate::orchestrator::Orchestrator;
+
+/// Host managed workers bind and register with.
+const WORKER_HOST: &str = "127.0.0.1";
+/// How often due restarts are carried out and scaling is reconsidered.
+const TICK: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+/// A worker that ran at least this long before exiting is restarted without backoff.
+const HEALTHY_RUN: Duration = Duration::from_secs(60);
+/// How long a worker has to exit after being interrupted before it is killed.
+const STOP_GRACE: Duration = Duration::from_secs(10);
+
+/// Settings for the worker processes an Orchestrator spawns and supervises.
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    /// Path of the worker binary.
+    pub worker_bin: PathBuf,
+    /// URL the workers reach the Orchestrator at.
+    pub orchestrator_url: String,
+    /// Workers kept running at all times.
+    pub min_workers: usize,
+    /// Most workers to scale up to. Autoscaling is off if this equals min_workers.
+    pub max_workers: usize,
+    /// Credits each worker advertises.
+    pub worker_credits: u32,
+    /// Add a worker once the oldest queued job has waited this long.
+    pub scale_up_wait: Duration,
+    /// Drain and stop a worker above min_workers once it has been idle this long.
+    pub scale_down_idle: Duration,
+}
+
+/// Runs worker processes on this machine pointed at the Orchestrator. Restarts ones that exit
+/// unexpectedly, with backoff, and scales between min_workers and max_workers based on how
+/// long jobs wait in the queue.
+pub struct Supervisor {
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Supervisor {
+    /// Spawns min_workers workers and supervises them in a background task. Fails if one
+    /// can't be spawned, e.g. because the worker binary doesn't exist.
+    pub fn start(orchestrator: Orchestrator, config: SupervisorConfig) -> io::Result<Self> {
+        let (exit_tx, exit_rx) = mpsc::unbounded_channel();
+        let mut state = SupervisorState { orchestrator, config, workers: BTreeMap::new(), exit_tx, last_scale_up: None };
+        for _ in 0..state.config.min_workers {
+            state.add_worker()?;
+        }
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let task = tokio::spawn(state.run(exit_rx, shutdown_rx));
+        Ok(Self { shutdown, task })
+    }
+
+    /// Interrupts every worker, killing any that haven't exited after a grace period, and
+    /// waits for them all to exit.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        self.task.await.unwrap_or_else(|e| panic!("Supervisor task failed: {}", e));
+    }
+}
+
+/// A managed worker slot. Its process is replaced on every restart.
+struct ManagedWorker {
+    info: ManagedWorkerInfo,
+    /// Interrupts the current process when sent or dropped. None while waiting to restart.
+    stop: Option<oneshot::Sender<()>>,
+    started_at: Instant,
+    /// Exits in a row that came soon after starting, which set the restart backoff.
+    failures: u32,
+    restart_at: Option<Instant>,
+    /// Since when the worker has had all its credits available.
+    idle_since: Option<Instant>,
+}
+
+type ExitTx = mpsc::UnboundedSender<(usize, io::Result<ExitStatus>)>;
+type ExitRx = mpsc::UnboundedReceiver<(usize, io::Result<ExitStatus>)>;
+
+struct SupervisorState {
+    orchestrator: Orchestrator,
+    config: SupervisorConfig,
+    workers: BTreeMap<usize, ManagedWorker>,
+    /// Each process's wait task reports its exit here.
+    exit_tx: ExitTx,
+    last_scale_up: Option<Instant>,
+}
+
+impl SupervisorState {
+    async fn run(mut self, mut exits: ExitRx, mut shutdown: oneshot::Receiver<()>) {
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            tokio::select! {
+                Some((index, status)) = exits.recv() => self.handle_exit(index, status),
+                _ = tick.tick() => self.tick().await,
+                _ = &mut shutdown => break,
+            }
+        }
+        self.stop_all(exits).await;
+    }
+
+    /// Spawns a worker in the lowest free slot.
+    fn add_worker(&mut self) -> io::Result<()> {
+        let index = self.workers.keys().enumerate()
+            .find(|(i, index)| i != *index)
+            .map_or(self.workers.len(), |(i, _)| i);
+        let (address, pid, stop) = self.spawn_process(index)?;
+        tracing::info!(worker = index, address = %address, pid = ?pid, "spawned managed worker");
+
+        let info = ManagedWorkerInfo {
+            index,
+            address,
+            pid,
+            state: ManagedWorkerState::Running,
+            restarts: 0,
+            started_at: SystemTime::now(),
+            last_exit: None,
+        };
+        self.orchestrator.diagnostics.handle_managed_worker_update(info.clone());
+        self.workers.insert(index, ManagedWorker {
+            info,
+            stop: Some(stop),
+            started_at: Instant::now(),
+            failures: 0,
+            restart_at: None,
+            idle_since: None,
+        });
+        Ok(())
+    }
+
+    /// Starts a worker process on a free port, forwarding its output to the log prefixed with
+    /// its index. Returns its address, pid, and the sender that stops it.
+    fn spawn_process(&self, index: usize) -> io::Result<(String, Option<u32>, oneshot::Sender<()>)> {
+        let port = TcpListener::bind((WORKER_HOST, 0))?.local_addr()?.port();
+        let address = format!("{WORKER_HOST}:{port}");
+
+        let mut command = Command::new(&self.config.worker_bin);
+        command
+            .arg(WORKER_HOST)
+            .arg(self.config.worker_credits.to_string())
+            .arg("--port").arg(port.to_string())
+            .arg("--orchestrator").arg(&self.config.orchestrator_url);
+        if let Some(password) = &self.orchestrator.worker_password {
+            command.arg("--password").arg(password);
+        }
+        command
+            .env("NO_COLOR", "1")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        // Keep a Ctrl-C in the terminal from reaching workers directly, so they're stopped
+        // by the supervisor rather than seen as crashing
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut child = command.spawn()?;
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward_output(index, stdout));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward_output(index, stderr));
+        }
+        let pid = child.id();
+        let (stop, stop_rx) = oneshot::channel();
+        let exit_tx = self.exit_tx.clone();
+        tokio::spawn(async move {
+            let status = wait_or_stop(index, child, stop_rx).await;
+            let _ = exit_tx.send((index, status));
+        });
+        Ok((address, pid, stop))
+    }
+
+    /// Forgets a worker that was stopped on purpose, or schedules the restart of one that exited
+    /// on its own.
+    fn handle_exit(&mut self, index: usize, status: io::Result<ExitStatus>) {
+        let Some(worker) = self.workers.get_mut(&index) else {
+            return;
+        };
+        if worker.info.state == ManagedWorkerState::Stopping {
+            tracing::info!(worker = index, "managed worker stopped");
+            self.workers.remove(&index);
+            self.orchestrator.diagnostics.handle_managed_worker_stopped(index);
+            return;
+        }
+
+        let exit = match status {
+            Ok(status) => status.to_string(),
+            Err(e) => format!("failed to wait for it: {e}"),
+        };
+        worker.failures = if worker.started_at.elapsed() >= HEALTHY_RUN { 0 } else { worker.failures + 1 };
+        let backoff = restart_backoff(worker.failures);
+        tracing::warn!(worker = index, address = %worker.info.address, exit = %exit, restart_in = ?backoff, "managed worker exited unexpectedly");
+
+        worker.stop = None;
+        worker.idle_since = None;
+        worker.restart_at = Some(Instant::now() + backoff);
+        worker.info.state = ManagedWorkerState::Restarting;
+        worker.info.pid = None;
+        worker.info.last_exit = Some(exit);
+        self.orchestrator.diagnostics.handle_managed_worker_update(worker.info.clone());
+    }
+
+    async fn tick(&mut self) {
+        let now = Instant::now();
+        let due: Vec<usize> = self.workers.iter()
+            .filter(|(_, w)| w.restart_at.is_some_and(|t| t <= now))
+            .map(|(index, _)| *index)
+            .collect();
+        for index in due {
+            self.restart_worker(index);
+        }
+
+        if self.config.max_workers > self.config.min_workers {
+            self.autoscale().await;
+        }
+    }
+
+    fn restart_worker(&mut self, index: usize) {
+        let spawned = self.spawn_process(index);
+        let Some(worker) = self.workers.get_mut(&index) else {
+            return;
+        };
+        match spawned {
+            Ok((address, pid, stop)) => {
+                tracing::info!(worker = index, address = %address, pid = ?pid, "restarted managed worker");
+                worker.stop = Some(stop);
+                worker.started_at = Instant::now();
+                worker.restart_at = None;
+                worker.info.address = address;
+                worker.info.pid = pid;
+                worker.info.state = ManagedWorkerState::Running;
+                worker.info.restarts += 1;
+                worker.info.started_at = SystemTime::now();
+            },
+            Err(e) => {
+                worker.failures += 1;
+                let backoff = restart_backoff(worker.failures);
+                tracing::error!(worker = index, error = %e, restart_in = ?backoff, "failed to restart managed worker");
+                worker.restart_at = Some(Instant::now() + backoff);
+                worker.info.last_exit = Some(format!("failed to spawn: {e}"));
+            },
+        }
+        self.orchestrator.diagnostics.handle_managed_worker_update(worker.info.clone());
+    }
+
+    /// Adds a worker if jobs have been waiting too long, or otherwise stops one that has been
+    /// idle too long. Changes by at most one worker per tick.
+    async fn autoscale(&mut self) {
+        let now = Instant::now();
+        let active = self.workers.values().filter(|w| w.info.state != ManagedWorkerState::Stopping).count();
+
+        let oldest_queued_at = self.orchestrator.job_queue.lock().await.oldest_enqueued_at();
+        if let Some(queued_at) = oldest_queued_at {
+            let wait = SystemTime::now().duration_since(queued_at).unwrap_or_default();
+            let cooled_down = self.last_scale_up.is_none_or(|t| now.duration_since(t) >= self.config.scale_up_wait);
+            if wait >= self.config.scale_up_wait && active < self.config.max_workers && cooled_down {
+                tracing::info!(queue_wait = ?wait, workers = active + 1, "scaling up managed workers");
+                if let Err(e) = self.add_worker() {
+                    tracing::error!(error = %e, "failed to spawn managed worker");
+                }
+                self.last_scale_up = Some(now);
+            }
+            for worker in self.workers.values_mut() {
+                worker.idle_since = None;
+            }
+            return;
+        }
+
+        {
+            let registry = self.orchestrator.registry.lock().await;
+            for worker in self.workers.values_mut().filter(|w| w.info.state == ManagedWorkerState::Running) {
+                let idle = registry.available_credits(&worker.info.address) == Some(self.config.worker_credits);
+                worker.idle_since = if idle { Some(worker.idle_since.unwrap_or(now)) } else { None };
+            }
+        }
+        if active <= self.config.min_workers {
+            return;
+        }
+        // Stop the highest slot, so the lowest ones stay put
+        let idle = self.workers.iter()
+            .filter(|(_, w)| w.idle_since.is_some_and(|t| now.duration_since(t) >= self.config.scale_down_idle))
+            .map(|(index, _)| *index)
+            .next_back();
+        if let Some(index) = idle {
+            tracing::info!(worker = index, workers = active - 1, "scaling down managed workers");
+            self.stop_worker(index).await;
+        }
+    }
+
+    /// Drains a worker so no more jobs are dispatched to it, then interrupts its process.
+    /// Its disconnect doesn't count towards flap detection.
+    async fn stop_worker(&mut self, index: usize) {
+        let Some(worker) = self.workers.get_mut(&index) else {
+            return;
+        };
+        worker.info.state = ManagedWorkerState::Stopping;
+        worker.idle_since = None;
+        self.orchestrator.diagnostics.handle_managed_worker_update(worker.info.clone());
+
+        let address = &worker.info.address;
+        self.orchestrator.flap_detector.lock().await.expect_disconnect(address);
+        if let Err(e) = self.orchestrator.drain_worker(address).await {
+            tracing::warn!(worker = index, error = %e, "failed to drain managed worker before stopping it");
+        }
+        worker.stop.take();
+    }
+
+    /// Stops every running worker and waits for them to exit.
+    async fn stop_all(mut self, mut exits: ExitRx) {
+        let mut running = 0;
+        let mut flap_detector = self.orchestrator.flap_detector.lock().await;
+        for worker in self.workers.values_mut() {
+            worker.info.state = ManagedWorkerState::Stopping;
+            if worker.stop.take().is_some() {
+                flap_detector.expect_disconnect(&worker.info.address);
+                running += 1;
+            }
+        }
+        drop(flap_detector);
+        tracing::info!(workers = running, "stopping managed workers");
+        while running > 0 {
+            let Some((index, status)) = exits.recv().await else {
+                break;
+            };
+            if let Err(e) = status {
+                tracing::warn!(worker = index, error = %e, "failed to wait for managed worker to exit");
+            }
+            running -= 1;
+        }
+        for index in self.workers.keys() {
+            self.orchestrator.diagnostics.handle_managed_worker_stopped(*index);
+        }
+    }
+}
+
+/// Waits for a worker process to exit. If stopped first, interrupts it so it shuts down
+/// gracefully, and kills it if it hasn't exited within the grace period.
+async fn wait_or_stop(index: usize, mut child: Child, stop: oneshot::Receiver<()>) -> io::Result<ExitStatus> {
+    tokio::select! {
+        status = child.wait() => return status,
+        _ = stop => {},
+    }
+    interrupt(&child);
+    match tokio::time::timeout(STOP_GRACE, child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            tracing::warn!(worker = index, grace = ?STOP_GRACE, "managed worker didn't exit after being interrupted, killing it");
+            child.kill().await?;
+            child.wait().await
+        },
+    }
+}
+
+/// Sends the process SIGINT, which the worker handles like Ctrl-C.
+#[cfg(unix)]
+fn interrupt(child: &Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: kill takes no pointers. The child hasn't been waited on yet, so the pid
+        // can't have been reused.
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGINT);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn interrupt(_child: &Child) {}
+
+/// Logs each line a worker writes to stdout or stderr.
+async fn forward_output(index: usize, output: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        tracing::info!("[worker {index}] {line}");
+    }
+}
+
+/// Delay before restarting a worker: 1s, doubling with each quick exit in a row, capped at
+/// MAX_RESTART_BACKOFF.
+fn restart_backoff(failures: u32) -> Duration {
+    Duration::from_secs(1)
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_RESTART_BACKOFF)
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use crate::diagnostics::{DiagnosticsStore, JobState};
+
+/// Number of events kept, older events are dropped.
+const EVENT_LOG_LEN: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub at: SystemTime,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Notable changes between consecutive diagnostics snapshots, newest last.
+#[derive(Debug, Default)]
+pub struct EventLog {
+    entries: VecDeque<Event>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The logged events, newest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        self.entries.iter().rev()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn push(&mut self, severity: Severity, message: String) {
+        if self.entries.len() == EVENT_LOG_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Event { at: SystemTime::now(), severity, message });
+    }
+
+    /// Logs worker connections, disconnects and quarantines, new clients, and failed, lost and
+    /// retried jobs that happened between two snapshots.
+    pub fn record_changes(&mut self, prev: &DiagnosticsStore, next: &DiagnosticsStore) {
+        for worker in next.workers.iter() {
+            let before = prev.workers.get(&worker.address);
+            let reconnected = before.as_ref().is_none_or(|b| b.connected_at != worker.connected_at);
+            if reconnected {
+                match worker.quarantined_until {
+                    Some(_) => self.push(Severity::Warn, format!("worker {} connected but is quarantined for flapping ({} recent disconnects)", worker.address, worker.flap_count)),
+                    None => self.push(Severity::Info, format!("worker {} connected with {} credits", worker.address, worker.credits)),
+                }
+                continue;
+            }
+            let Some(before) = before else { continue };
+            if before.disconnected_at.is_none() && worker.disconnected_at.is_some() {
+                self.push(Severity::Warn, format!("worker {} disconnected", worker.address));
+            }
+            if before.quarantined_until.is_none() && worker.quarantined_until.is_some() {
+                self.push(Severity::Warn, format!("worker {} quarantined for losing jobs ({} lost)", worker.address, worker.lost_jobs));
+            }
+            if before.quarantined_until.is_some() && worker.quarantined_until.is_none() {
+                self.push(Severity::Info, format!("worker {} released from quarantine", worker.address));
+            }
+        }
+
+        for client in next.clients.iter() {
+            if !prev.clients.contains_key(&client.address) {
+                self.push(Severity::Info, format!("client {} connected", client.address));
+            }
+        }
+
+        for job in next.jobs.iter() {
+            let before = prev.jobs.get(&job.job_id);
+            if job.state == JobState::Failed && before.as_ref().is_none_or(|b| b.state != JobState::Failed) {
+                let worker = job.worker_address.as_deref().unwrap_or("no worker");
+                self.push(Severity::Error, format!("job {} failed on {worker}", job.job_id));
+            }
+            if job.state == JobState::PresumedLost && before.as_ref().is_none_or(|b| b.state != JobState::PresumedLost) {
+                let worker = job.worker_address.as_deref().unwrap_or("no worker");
+                self.push(Severity::Error, format!("job {} presumed lost on {worker}, it ran past its timeout without finishing", job.job_id));
+            }
+            if let Some(before) = before && job.retries > before.retries {
+                self.push(Severity::Warn, format!("job {} retried by its client (retry {})", job.job_id, job.retries));
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::diagnostics::{DiagnosticsStore, JobState};
+use crate::tui::events::Severity;
+use crate::tui::history::History;
+
+/// Outcome of a background write, reported in the TUI's event log.
+pub type ExportResult = (Severity, String);
+
+/// Writes diagnostics snapshots to files in the export directory. Files are written by
+/// background tasks so a slow disk can't stall the TUI; their outcomes arrive on the results
+/// channel returned by new.
+#[derive(Debug)]
+pub struct Exporter {
+    dir: PathBuf,
+    results: mpsc::UnboundedSender<ExportResult>,
+    /// Feeds the task appending to the current recording, if one is running.
+    recording: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl Exporter {
+    pub fn new(dir: PathBuf) -> (Self, mpsc::UnboundedReceiver<ExportResult>) {
+        let (results, results_rx) = mpsc::unbounded_channel();
+        (Self { dir, results, recording: None }, results_rx)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Writes the snapshot as pretty-printed JSON to its own timestamped file.
+    pub fn save(&self, snapshot: &Value) {
+        let path = self.dir.join(format!("mini-lambda-snapshot-{}.json", epoch_ms(SystemTime::now())));
+        let contents = serde_json::to_vec_pretty(snapshot)
+            .unwrap_or_else(|e| panic!("a json Value should always serialize: {e}"));
+        let results = self.results.clone();
+        tokio::spawn(async move {
+            let result = match tokio::fs::write(&path, contents).await {
+                Ok(()) => (Severity::Info, format!("saved snapshot to {}", path.display())),
+                Err(e) => (Severity::Error, format!("failed to save snapshot to {}: {e}", path.display())),
+            };
+            results.send(result).ok();
+        });
+    }
+
+    /// Starts appending every recorded snapshot to a new JSON-lines file, or stops the
+    /// current recording.
+    pub fn toggle_recording(&mut self) {
+        if self.recording.take().is_some() {
+            // Dropping the sender ends the writer task once it has written what it was sent
+            return;
+        }
+        let path = self.dir.join(format!("mini-lambda-recording-{}.jsonl", epoch_ms(SystemTime::now())));
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.recording = Some(tx);
+        tokio::spawn(write_recording(path, rx, self.results.clone()));
+    }
+
+    /// Appends the snapshot to the current recording. No-op if not recording.
+    pub fn record(&mut self, snapshot: &Value) {
+        if let Some(tx) = &self.recording && tx.send(snapshot.to_string()).is_err() {
+            // The writer task failed and has reported why
+            self.recording = None;
+        }
+    }
+}
+
+async fn write_recording(path: PathBuf, mut lines: mpsc::UnboundedReceiver<String>, results: mpsc::UnboundedSender<ExportResult>) {
+    let mut file = match tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            results.send((Severity::Error, format!("failed to start recording to {}: {e}", path.display()))).ok();
+            return;
+        }
+    };
+    results.send((Severity::Info, format!("recording snapshots to {}", path.display()))).ok();
+
+    let mut written = 0;
+    while let Some(line) = lines.recv().await {
+        if let Err(e) = file.write_all(format!("{line}\n").as_bytes()).await {
+            results.send((Severity::Error, format!("recording to {} failed: {e}", path.display()))).ok();
+            return;
+        }
+        written += 1;
+    }
+    if let Err(e) = file.flush().await {
+        results.send((Severity::Error, format!("recording to {} failed: {e}", path.display()))).ok();
+        return;
+    }
+    results.send((Severity::Info, format!("stopped recording, {written} snapshots written to {}", path.display()))).ok();
+}
+
+/// The diagnostics store and the TUI's dashboard history as JSON, with times as milliseconds
+/// since the Unix epoch.
+pub fn snapshot_json(diagnostics: &DiagnosticsStore, taken_at: SystemTime, history: &History) -> Value {
+    let jobs: Vec<Value> = diagnostics.jobs.iter().map(|j| json!({
+        "job_id": j.job_id,
+        "state": j.state.as_str(),
+        "namespace": j.namespace.as_str(),
+        "client_address": j.client_address,
+        "worker_address": j.worker_address,
+        "retries": j.retries,
+        "late_state": j.late_state.as_ref().map(JobState::as_str),
+        "replay_of": j.replay_of,
+        "exit_code": j.exit_code,
+        "scheduled_for_ms": j.scheduled_for.map(epoch_ms),
+        "queued_at_ms": epoch_ms(j.queued_at),
+        "compiling_at_ms": j.compiling_at.map(epoch_ms),
+        "executing_at_ms": j.executing_at.map(epoch_ms),
+        "completed_at_ms": j.completed_at.map(epoch_ms),
+    })).collect();
+    let workers: Vec<Value> = diagnostics.workers.iter().map(|w| json!({
+        "address": w.address,
+        "health": format!("{:?}", w.health()).to_lowercase(),
+        "credits": w.credits,
+        "namespace": w.namespace.as_ref().map(|n| n.as_str()),
+        "capabilities": w.capabilities.as_ref().map(|c| json!({
+            "max_wasm_bytes": c.max_wasm_bytes,
+            "max_args": c.max_args,
+            "max_args_bytes": c.max_args_bytes,
+            "signed_jobs_only": c.signed_jobs_only,
+            "version": c.version,
+            "hash_algorithms": c.hash_algorithms().map(|a| a.label()).collect::<Vec<_>>(),
+        })),
+        "jobs_received": w.jobs_received,
+        "total_job_time_ms": w.total_job_time.as_millis() as u64,
+        "cold_starts": w.cold_starts,
+        "total_compile_time_ms": w.total_compile_time.as_millis() as u64,
+        "modules_handed_off": w.modules_handed_off,
+        "modules_received": w.modules_received,
+        "connected_at_ms": epoch_ms(w.connected_at),
+        "last_seen_at_ms": epoch_ms(w.last_seen_at),
+        "disconnected_at_ms": w.disconnected_at.map(epoch_ms),
+        "flap_count": w.flap_count,
+        "lost_jobs": w.lost_jobs,
+        "quarantined_until_ms": w.quarantined_until.map(epoch_ms),
+        "drained": w.drained,
+        "validating": w.validating,
+        "failed_probes": w.failed_probes,
+        "host_load": w.host_load,
+        "load_credits": w.load_credits,
+        "running_jobs": w.running_jobs,
+        "estimated_running_jobs": w.estimated_running_jobs,
+        "out_of_band_jobs": w.out_of_band_jobs,
+        "reserved_credits": w.reserved_credits,
+        "heartbeat_interval_ms": w.heartbeat_stats.interval_mean_ms,
+        "heartbeat_jitter_ms": w.heartbeat_stats.interval_jitter().map(|jitter| jitter.as_millis() as u64),
+        "clock_skew_ms": w.heartbeat_stats.clock_skew_ms,
+        "heartbeat_anomaly": w.heartbeat_stats.anomalous(),
+    })).collect();
+    let managed_workers: Vec<Value> = diagnostics.managed_workers.iter().map(|m| json!({
+        "index": m.index,
+        "address": m.address,
+        "pid": m.pid,
+        "state": m.state.as_str(),
+        "restarts": m.restarts,
+        "started_at_ms": epoch_ms(m.started_at),
+        "last_exit": m.last_exit,
+    })).collect();
+    let clients: Vec<Value> = diagnostics.clients.iter().map(|c| json!({
+        "address": c.address,
+        "jobs_submitted": c.jobs_submitted,
+        "total_queue_time_ms": c.total_queue_time.as_millis() as u64,
+        "total_worker_time_ms": c.total_worker_time.as_millis() as u64,
+        "connected_at_ms": epoch_ms(c.connected_at),
+        "last_seen_at_ms": epoch_ms(c.last_seen_at),
+    })).collect();
+
+    json!({
+        "taken_at_ms": epoch_ms(taken_at),
+        "started_at_ms": epoch_ms(diagnostics.started_at),
+        "jobs": jobs,
+        "workers": workers,
+        "managed_workers": managed_workers,
+        "clients": clients,
+        "history": {
+            "queue_depth": history.queue_depth.to_vec(),
+            "dispatches": history.dispatches.to_vec(),
+        },
+    })
+}
+
+fn epoch_ms(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+
+use crate::diagnostics::{DiagnosticsStore, JobState};
+
+/// Number of samples kept for each history, one per data refresh.
+pub const HISTORY_LEN: usize = 120;
+
+/// A bounded series of samples, oldest first. Pushing past capacity drops the oldest sample.
+#[derive(Debug)]
+pub struct Samples {
+    values: VecDeque<u64>,
+    capacity: usize,
+}
+
+impl Samples {
+    pub fn new(capacity: usize) -> Self {
+        Self { values: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn push(&mut self, value: u64) {
+        if self.values.len() == self.capacity {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    /// The samples, oldest first.
+    pub fn to_vec(&self) -> Vec<u64> {
+        self.values.iter().copied().collect()
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.values.iter().copied().min()
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.values.iter().copied().max()
+    }
+}
+
+/// Trends shown on the dashboard, sampled each time the TUI refreshes its data.
+#[derive(Debug)]
+pub struct History {
+    /// Jobs waiting in the queue for a worker.
+    pub queue_depth: Samples,
+    /// Jobs dispatched to a worker since the previous sample.
+    pub dispatches: Samples,
+    /// Jobs that had been dispatched as of the previous sample.
+    dispatched_total: Option<u64>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            queue_depth: Samples::new(HISTORY_LEN),
+            dispatches: Samples::new(HISTORY_LEN),
+            dispatched_total: None,
+        }
+    }
+
+    /// Records a sample of the given diagnostics. The first sample only sets the baseline for
+    /// counting dispatches, so jobs dispatched before the TUI started don't show as a spike.
+    pub fn record(&mut self, diagnostics: &DiagnosticsStore) {
+        let mut queued = 0;
+        let mut dispatched_total: u64 = 0;
+        for job in diagnostics.jobs.iter() {
+            match job.state {
+                JobState::Queued => queued += 1,
+                JobState::Scheduled => {},
+                _ if job.worker_address.is_some() => dispatched_total += 1,
+                _ => {},
+            }
+        }
+        self.queue_depth.push(queued);
+        // A retried job is re-queued and dispatched again, so the total can briefly drop
+        let dispatched = self.dispatched_total.map_or(0, |previous| dispatched_total.saturating_sub(previous));
+        self.dispatches.push(dispatched);
+        self.dispatched_total = Some(dispatched_total);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use shared::JobId;
+
+    use super::*;
+    use crate::identity::ClientIdentity;
+    use crate::namespaces::Namespace;
+
+    fn samples(capacity: usize, values: impl IntoIterator<Item = u64>) -> Samples {
+        let mut samples = Samples::new(capacity);
+        values.into_iter().for_each(|value| samples.push(value));
+        samples
+    }
+
+    #[test]
+    fn samples_are_kept_oldest_first() {
+        let samples = samples(4, [3, 1, 2]);
+        assert_eq!(samples.to_vec(), [3, 1, 2]);
+        assert_eq!((samples.min(), samples.max()), (Some(1), Some(3)));
+    }
+
+    #[test]
+    fn pushing_past_capacity_drops_the_oldest() {
+        let samples = samples(3, 1..=7);
+        assert_eq!(samples.to_vec(), [5, 6, 7]);
+        assert_eq!((samples.min(), samples.max()), (Some(5), Some(7)));
+    }
+
+    #[test]
+    fn a_single_sample_is_kept_at_capacity_one() {
+        let samples = samples(1, [4, 9, 2]);
+        assert_eq!(samples.to_vec(), [2]);
+        assert_eq!((samples.min(), samples.max()), (Some(2), Some(2)));
+    }
+
+    #[test]
+    fn no_samples_have_no_range() {
+        let samples = samples(HISTORY_LEN, []);
+        assert!(samples.to_vec().is_empty());
+        assert_eq!((samples.min(), samples.max()), (None, None));
+    }
+
+    #[test]
+    fn dispatches_are_counted_since_the_previous_sample() {
+        let diagnostics = DiagnosticsStore::new();
+        let client = ClientIdentity::Host(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        diagnostics.handle_client_connected("127.0.0.1:1");
+        let jobs: Vec<JobId> = (0..3).map(|_| JobId::random()).collect();
+        for &job_id in &jobs {
+            diagnostics.handle_job_enqueue(job_id, &Namespace::default(), "127.0.0.1:1", &client, None);
+        }
+        // Dispatched before the first sample, so it isn't counted
+        diagnostics.handle_dispatch_job(jobs[0], "worker:1");
+
+        let mut history = History::new();
+        history.record(&diagnostics);
+        diagnostics.handle_dispatch_job(jobs[1], "worker:1");
+        diagnostics.handle_dispatch_job(jobs[2], "worker:1");
+        history.record(&diagnostics);
+        history.record(&diagnostics);
+
+        assert_eq!(history.queue_depth.to_vec(), [2, 0, 0]);
+        assert_eq!(history.dispatches.to_vec(), [0, 2, 0]);
+    }
+}
@@ -0,0 +1,53 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// A single-line text field with a cursor.
+#[derive(Debug, Default)]
+pub struct TextInput {
+    value: String,
+    /// Cursor position, in chars.
+    cursor: usize,
+}
+
+impl TextInput {
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The text before and after the cursor, for rendering.
+    pub fn split_at_cursor(&self) -> (&str, &str) {
+        self.value.split_at(self.byte_index())
+    }
+
+    /// Applies an editing key. Returns false if the key isn't an editing key.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let len = self.value.chars().count();
+        match key.code {
+            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.value.clear();
+                self.cursor = 0;
+            }
+            KeyCode::Char(c) => {
+                self.value.insert(self.byte_index(), c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.value.remove(self.byte_index());
+            }
+            KeyCode::Delete if self.cursor < len => {
+                self.value.remove(self.byte_index());
+            }
+            KeyCode::Left  => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(len),
+            KeyCode::Home  => self.cursor = 0,
+            KeyCode::End   => self.cursor = len,
+            KeyCode::Backspace | KeyCode::Delete => {}
+            _ => return false,
+        }
+        true
+    }
+
+    fn byte_index(&self) -> usize {
+        self.value.char_indices().nth(self.cursor).map_or(self.value.len(), |(i, _)| i)
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+use crate::tui::state::Tab;
+
+/// Something a key can be bound to. Each command belongs to one scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Quit,
+    Help,
+    Refresh,
+    Pause,
+    Save,
+    ToggleRecording,
+    NewJob,
+    ToggleEvents,
+    EventsPaneDown,
+    EventsPaneUp,
+    Filter,
+    ClearFilter,
+    NextTab,
+    PrevTab,
+    GoTo(Tab),
+    Down,
+    Up,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    SortNext,
+    SortPrev,
+    SortReverse,
+    ToggleJobsOrder,
+    Replay,
+    Drain,
+    Resume,
+    Remove,
+    ScrollDown,
+    ScrollUp,
+    ScrollPageDown,
+    ScrollPageUp,
+    ScrollTop,
+    ScrollBottom,
+    FilterAccept,
+    FilterCancel,
+    Confirm,
+    SubmitSend,
+    SubmitNextField,
+    SubmitCancel,
+}
+
+/// Where a binding applies: everywhere in normal mode, on some tabs, or in one input mode.
+/// Keys may only be reused across scopes that are never active at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Global,
+    /// The Jobs, Workers and Clients tables.
+    Table,
+    Jobs,
+    Workers,
+    Events,
+    Filter,
+    Confirm,
+    SubmitForm,
+}
+
+impl Scope {
+    /// Every scope, in the order they are listed in the help overlay.
+    pub const ALL: &'static [Scope] = &[
+        Scope::Global,
+        Scope::Table,
+        Scope::Jobs,
+        Scope::Workers,
+        Scope::Events,
+        Scope::Filter,
+        Scope::Confirm,
+        Scope::SubmitForm,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Scope::Global     => "Global",
+            Scope::Table      => "Tables",
+            Scope::Jobs       => "Jobs tab",
+            Scope::Workers    => "Workers tab",
+            Scope::Events     => "Events tab",
+            Scope::Filter     => "Typing a filter",
+            Scope::Confirm    => "Confirmation prompt",
+            Scope::SubmitForm => "Submit form",
+        }
+    }
+
+    /// The scopes active in normal mode on the given tab.
+    pub fn active(tab: Tab) -> &'static [Scope] {
+        match tab {
+            Tab::Jobs    => &[Scope::Jobs, Scope::Table, Scope::Global],
+            Tab::Workers => &[Scope::Workers, Scope::Table, Scope::Global],
+            Tab::Clients => &[Scope::Table, Scope::Global],
+            Tab::Events  => &[Scope::Events, Scope::Global],
+            _ => &[Scope::Global],
+        }
+    }
+
+    fn overlaps(self, other: Scope) -> bool {
+        use Scope::*;
+        self == other || matches!(
+            (self, other),
+            (Global, Table | Jobs | Workers | Events) | (Table | Jobs | Workers | Events, Global)
+                | (Table, Jobs | Workers) | (Jobs | Workers, Table)
+        )
+    }
+}
+
+/// A command's name in tui.toml, the scope it applies in, its help text and default keys.
+struct CommandInfo {
+    command: Command,
+    name: &'static str,
+    scope: Scope,
+    description: &'static str,
+    default_keys: &'static [&'static str],
+}
+
+const fn info(command: Command, name: &'static str, scope: Scope, description: &'static str, default_keys: &'static [&'static str]) -> CommandInfo {
+    CommandInfo { command, name, scope, description, default_keys }
+}
+
+/// Every bindable command. The help overlay and the key dispatch are both generated from this.
+const COMMANDS: &[CommandInfo] = &[
+    info(Command::Quit,            "quit",              Scope::Global,     "Quit (Ctrl-C always quits)", &["q"]),
+    info(Command::Help,            "help",              Scope::Global,     "Show this help", &["?"]),
+    info(Command::Refresh,         "refresh",           Scope::Global,     "Refresh now", &["R"]),
+    info(Command::Pause,           "pause",             Scope::Global,     "Pause or resume refreshing", &["p"]),
+    info(Command::Save,            "save",              Scope::Global,     "Save a snapshot", &["e"]),
+    info(Command::ToggleRecording, "record",            Scope::Global,     "Start or stop recording", &["E"]),
+    info(Command::NewJob,          "new_job",           Scope::Global,     "Submit a job", &["n"]),
+    info(Command::ToggleEvents,    "events_pane",       Scope::Global,     "Show or hide the events pane", &["l"]),
+    info(Command::EventsPaneDown,  "events_pane_down",  Scope::Global,     "Scroll the events pane down", &["shift+down"]),
+    info(Command::EventsPaneUp,    "events_pane_up",    Scope::Global,     "Scroll the events pane up", &["shift+up"]),
+    info(Command::Filter,          "filter",            Scope::Global,     "Filter the table", &["/"]),
+    info(Command::ClearFilter,     "clear_filter",      Scope::Global,     "Clear the filter", &["esc"]),
+    info(Command::NextTab,         "next_tab",          Scope::Global,     "Next tab", &["tab"]),
+    info(Command::PrevTab,         "prev_tab",          Scope::Global,     "Previous tab", &["backtab"]),
+    info(Command::GoTo(Tab::Dashboard), "tab_dashboard", Scope::Global,    "Dashboard tab", &["1"]),
+    info(Command::GoTo(Tab::Jobs),      "tab_jobs",      Scope::Global,    "Jobs tab", &["2"]),
+    info(Command::GoTo(Tab::Workers),   "tab_workers",   Scope::Global,    "Workers tab", &["3"]),
+    info(Command::GoTo(Tab::Clients),   "tab_clients",   Scope::Global,    "Clients tab", &["4"]),
+    info(Command::GoTo(Tab::Logs),      "tab_logs",      Scope::Global,    "Logs tab", &["5"]),
+    info(Command::GoTo(Tab::Events),    "tab_events",    Scope::Global,    "Events tab", &["6"]),
+    info(Command::Down,            "down",              Scope::Table,      "Next row", &["down", "j"]),
+    info(Command::Up,              "up",                Scope::Table,      "Previous row", &["up", "k"]),
+    info(Command::PageDown,        "page_down",         Scope::Table,      "Next page", &["pagedown"]),
+    info(Command::PageUp,          "page_up",           Scope::Table,      "Previous page", &["pageup"]),
+    info(Command::Top,             "top",               Scope::Table,      "First row", &["home"]),
+    info(Command::Bottom,          "bottom",            Scope::Table,      "Last row", &["end"]),
+    info(Command::SortNext,        "sort_next",         Scope::Table,      "Sort by the next column", &["right", "s"]),
+    info(Command::SortPrev,        "sort_prev",         Scope::Table,      "Sort by the previous column", &["left"]),
+    info(Command::SortReverse,     "sort_reverse",      Scope::Table,      "Reverse the sort", &["r"]),
+    info(Command::ToggleJobsOrder, "jobs_order",        Scope::Jobs,       "Sort by age or duration", &["o"]),
+    info(Command::Replay,          "replay",            Scope::Jobs,       "Replay the selected job", &["a"]),
+    info(Command::Drain,           "drain",             Scope::Workers,    "Drain the selected worker", &["d"]),
+    info(Command::Resume,          "resume",            Scope::Workers,    "Resume the selected worker", &["u"]),
+    info(Command::Remove,          "remove",            Scope::Workers,    "Remove the selected worker", &["x"]),
+    info(Command::ScrollDown,      "scroll_down",       Scope::Events,     "Scroll down", &["down", "j"]),
+    info(Command::ScrollUp,        "scroll_up",         Scope::Events,     "Scroll up", &["up", "k"]),
+    info(Command::ScrollPageDown,  "scroll_page_down",  Scope::Events,     "Scroll a page down", &["pagedown"]),
+    info(Command::ScrollPageUp,    "scroll_page_up",    Scope::Events,     "Scroll a page up", &["pageup"]),
+    info(Command::ScrollTop,       "scroll_top",        Scope::Events,     "Oldest events", &["home"]),
+    info(Command::ScrollBottom,    "scroll_bottom",     Scope::Events,     "Newest events", &["end"]),
+    info(Command::FilterAccept,    "filter_accept",     Scope::Filter,     "Keep the filter", &["enter"]),
+    info(Command::FilterCancel,    "filter_cancel",     Scope::Filter,     "Clear the filter", &["esc"]),
+    info(Command::Confirm,         "confirm",           Scope::Confirm,    "Confirm (any other key cancels)", &["y"]),
+    info(Command::SubmitSend,      "submit_send",       Scope::SubmitForm, "Submit the job", &["enter"]),
+    info(Command::SubmitNextField, "submit_next_field", Scope::SubmitForm, "Switch field", &["tab", "backtab", "up", "down"]),
+    info(Command::SubmitCancel,    "submit_cancel",     Scope::SubmitForm, "Close the form", &["esc"]),
+];
+
+/// A key with its modifiers, written in tui.toml as e.g. "q", "R", "ctrl+d", "shift+up" or "pagedown".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    /// Characters carry their own case, so terminals differ in whether they also report shift.
+    fn from_event(event: &KeyEvent) -> Self {
+        let mut modifiers = event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        if matches!(event.code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Self { code: event.code, modifiers }
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut name = s;
+        // A lone "+" is a key, not a separator
+        while let Some((modifier, rest)) = name.split_once('+') && !rest.is_empty() {
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl"  => KeyModifiers::CONTROL,
+                "alt"   => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("unknown modifier '{modifier}' in key '{s}'")),
+            };
+            name = rest;
+        }
+
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match name.to_ascii_lowercase().as_str() {
+                "up"        => KeyCode::Up,
+                "down"      => KeyCode::Down,
+                "left"      => KeyCode::Left,
+                "right"     => KeyCode::Right,
+                "pageup"    => KeyCode::PageUp,
+                "pagedown"  => KeyCode::PageDown,
+                "home"      => KeyCode::Home,
+                "end"       => KeyCode::End,
+                "tab"       => KeyCode::Tab,
+                "backtab"   => KeyCode::BackTab,
+                "enter"     => KeyCode::Enter,
+                "esc"       => KeyCode::Esc,
+                "backspace" => KeyCode::Backspace,
+                "delete"    => KeyCode::Delete,
+                "space"     => KeyCode::Char(' '),
+                _ => return Err(format!("unknown key '{s}'")),
+            },
+        };
+        // Terminals report shift+tab as its own key
+        if code == KeyCode::Tab && modifiers.contains(KeyModifiers::SHIFT) {
+            return Ok(Self { code: KeyCode::BackTab, modifiers: modifiers - KeyModifiers::SHIFT });
+        }
+        if let KeyCode::Char(_) = code && modifiers.contains(KeyModifiers::SHIFT) {
+            return Err(format!("key '{s}': write the shifted character instead of shift+"));
+        }
+        Ok(Self { code, modifiers })
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c)   => write!(f, "{c}"),
+            KeyCode::Up        => write!(f, "↑"),
+            KeyCode::Down      => write!(f, "↓"),
+            KeyCode::Left      => write!(f, "←"),
+            KeyCode::Right     => write!(f, "→"),
+            KeyCode::PageUp    => write!(f, "pageup"),
+            KeyCode::PageDown  => write!(f, "pagedown"),
+            KeyCode::Home      => write!(f, "home"),
+            KeyCode::End       => write!(f, "end"),
+            KeyCode::Tab       => write!(f, "tab"),
+            KeyCode::BackTab   => write!(f, "shift+tab"),
+            KeyCode::Enter     => write!(f, "enter"),
+            KeyCode::Esc       => write!(f, "esc"),
+            KeyCode::Backspace => write!(f, "backspace"),
+            KeyCode::Delete    => write!(f, "delete"),
+            code => write!(f, "{code}"),
+        }
+    }
+}
+
+/// The optional keybinding overrides file.
+///
+/// ```toml
+/// [keys]
+/// quit = ["ctrl+q"]
+/// down = ["j"]
+/// up = ["k"]
+/// ```
+///
+/// Each entry replaces all of that command's default keys; an empty list unbinds it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFile {
+    #[serde(default)]
+    keys: HashMap<String, Vec<String>>,
+}
+
+/// The keys bound to each command, by scope.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Command, Scope, Vec<Key>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = COMMANDS.iter()
+            .map(|info| {
+                let keys = info.default_keys.iter()
+                    .map(|k| Key::parse(k).unwrap_or_else(|e| panic!("default binding for {} should parse: {e}", info.name)))
+                    .collect();
+                (info.command, info.scope, keys)
+            })
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    /// Loads the default keymap with the overrides in path applied, or the defaults if the file
+    /// doesn't exist. Unknown command names, unparseable keys and keys bound twice within
+    /// overlapping scopes are errors.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("failed to read {}: {e}", path.display())),
+        };
+        let file: KeymapFile = toml::from_str(&contents)
+            .map_err(|e| format!("invalid keybindings file {}: {e}", path.display()))?;
+        Self::with_overrides(&file.keys)
+            .map_err(|e| format!("invalid keybindings file {}: {e}", path.display()))
+    }
+
+    fn with_overrides(overrides: &HashMap<String, Vec<String>>) -> Result<Self, String> {
+        let mut keymap = Self::default();
+        for (name, keys) in overrides {
+            let Some(index) = COMMANDS.iter().position(|info| info.name == name) else {
+                return Err(format!("unknown action '{name}'"));
+            };
+            keymap.bindings[index].2 = keys.iter()
+                .map(|k| Key::parse(k).map_err(|e| format!("{name}: {e}")))
+                .collect::<Result<_, _>>()?;
+        }
+        keymap.check_conflicts()?;
+        Ok(keymap)
+    }
+
+    fn check_conflicts(&self) -> Result<(), String> {
+        for (i, (_, scope, keys)) in self.bindings.iter().enumerate() {
+            for (j, (_, other_scope, other_keys)) in self.bindings.iter().enumerate().skip(i) {
+                if !scope.overlaps(*other_scope) {
+                    continue;
+                }
+                // Repeats within one command's own list are harmless
+                let clash = if i == j { None } else { keys.iter().find(|k| other_keys.contains(k)) };
+                if let Some(key) = clash {
+                    return Err(format!("key '{key}' is bound to both '{}' and '{}'", COMMANDS[i].name, COMMANDS[j].name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The command bound to the key in the first of the given scopes that binds it.
+    pub fn lookup(&self, event: &KeyEvent, scopes: &[Scope]) -> Option<Command> {
+        let key = Key::from_event(event);
+        scopes.iter().find_map(|scope| {
+            self.bindings.iter()
+                .find(|(_, s, keys)| s == scope && keys.contains(&key))
+                .map(|(command, ..)| *command)
+        })
+    }
+
+    /// The first key bound to the command, for hints in titles.
+    pub fn hint(&self, command: Command) -> String {
+        self.bindings.iter()
+            .find(|(c, ..)| *c == command)
+            .and_then(|(_, _, keys)| keys.first())
+            .map_or_else(|| "unbound".to_string(), Key::to_string)
+    }
+
+    /// Every binding in the scope as (keys, description), for the help overlay.
+    pub fn describe(&self, scope: Scope) -> Vec<(String, &'static str)> {
+        self.bindings.iter()
+            .zip(COMMANDS)
+            .filter(|((_, s, _), _)| *s == scope)
+            .map(|((_, _, keys), info)| {
+                let keys = if keys.is_empty() {
+                    "unbound".to_string()
+                } else {
+                    keys.iter().map(Key::to_string).collect::<Vec<_>>().join(" ")
+                };
+                (keys, info.description)
+            })
+            .collect()
+    }
+}
+
+/// Returns the keybindings file location: mini-lambda/tui.toml under $XDG_CONFIG_HOME or ~/.config.
+pub fn keymap_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("mini-lambda").join("tui.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(entries: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        entries.iter()
+            .map(|(name, keys)| (name.to_string(), keys.iter().map(|k| k.to_string()).collect()))
+            .collect()
+    }
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn keys_parse() {
+        assert_eq!(Key::parse("q"), Ok(Key { code: KeyCode::Char('q'), modifiers: KeyModifiers::NONE }));
+        assert_eq!(Key::parse("ctrl+alt+d"), Ok(Key { code: KeyCode::Char('d'), modifiers: KeyModifiers::CONTROL | KeyModifiers::ALT }));
+        assert_eq!(Key::parse("shift+up"), Ok(Key { code: KeyCode::Up, modifiers: KeyModifiers::SHIFT }));
+        assert_eq!(Key::parse("PageDown"), Ok(Key { code: KeyCode::PageDown, modifiers: KeyModifiers::NONE }));
+        assert_eq!(Key::parse("+"), Ok(Key { code: KeyCode::Char('+'), modifiers: KeyModifiers::NONE }));
+        assert_eq!(Key::parse("shift+tab"), Key::parse("backtab"));
+    }
+
+    #[test]
+    fn bad_keys_are_refused() {
+        assert!(Key::parse("hyper+q").unwrap_err().contains("unknown modifier"));
+        assert!(Key::parse("f13").unwrap_err().contains("unknown key"));
+        assert!(Key::parse("shift+q").unwrap_err().contains("shifted character"));
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        Keymap::default().check_conflicts().unwrap();
+    }
+
+    #[test]
+    fn keys_are_looked_up_in_the_active_scopes() {
+        let keymap = Keymap::default();
+        let j = press(KeyCode::Char('j'), KeyModifiers::NONE);
+        assert_eq!(keymap.lookup(&j, Scope::active(Tab::Jobs)), Some(Command::Down));
+        assert_eq!(keymap.lookup(&j, Scope::active(Tab::Events)), Some(Command::ScrollDown));
+        assert_eq!(keymap.lookup(&j, Scope::active(Tab::Dashboard)), None);
+        // Terminals may or may not report shift with an uppercase letter
+        assert_eq!(keymap.lookup(&press(KeyCode::Char('R'), KeyModifiers::SHIFT), &[Scope::Global]), Some(Command::Refresh));
+    }
+
+    #[test]
+    fn overrides_replace_the_default_keys() {
+        let keymap = Keymap::with_overrides(&overrides(&[("quit", &["ctrl+q"]), ("help", &[])])).unwrap();
+        let q = press(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert_eq!(keymap.lookup(&q, &[Scope::Global]), None);
+        assert_eq!(keymap.lookup(&press(KeyCode::Char('q'), KeyModifiers::CONTROL), &[Scope::Global]), Some(Command::Quit));
+        assert_eq!(keymap.hint(Command::Help), "unbound");
+    }
+
+    #[test]
+    fn swapped_keys_dont_conflict() {
+        let keymap = Keymap::with_overrides(&overrides(&[("down", &["k"]), ("up", &["j"])])).unwrap();
+        let k = press(KeyCode::Char('k'), KeyModifiers::NONE);
+        assert_eq!(keymap.lookup(&k, Scope::active(Tab::Workers)), Some(Command::Down));
+    }
+
+    #[test]
+    fn unknown_actions_are_refused() {
+        let error = Keymap::with_overrides(&overrides(&[("explode", &["x"])])).unwrap_err();
+        assert_eq!(error, "unknown action 'explode'");
+    }
+
+    #[test]
+    fn keys_bound_twice_in_overlapping_scopes_conflict() {
+        // Global and the Jobs tab are active together
+        let error = Keymap::with_overrides(&overrides(&[("replay", &["q"])])).unwrap_err();
+        assert_eq!(error, "key 'q' is bound to both 'quit' and 'replay'");
+        // The Jobs and Workers tabs never are
+        Keymap::with_overrides(&overrides(&[("replay", &["d"])])).unwrap();
+    }
+}
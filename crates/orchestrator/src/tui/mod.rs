@@ -2,12 +2,19 @@
 // The rest of the code was almost all written by me, with AI assistance primarily being used to 
 // bounce ideas off of and help with some debugging.
 
+mod events;
+mod export;
+mod history;
+mod input;
+pub mod keymap;
+mod submit;
 mod render;
 pub mod state;
 
 use std::io;
-use std::sync::Arc;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
 use crossterm::event::{Event, EventStream, KeyCode, KeyModifiers};
 use crossterm::execute;
@@ -15,49 +22,122 @@ use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_ra
 use futures::StreamExt;
 use ratatui::Terminal;
 use ratatui::backend::CrosstermBackend;
+use shared::JobId;
+use tokio::time::MissedTickBehavior;
 use tui_logger::TuiWidgetState;
 
-use crate::diagnostics::DiagnosticsStore;
-use state::{CLIENT_COLS, JOB_COLS, Tab, TuiState, WORKER_COLS};
+use crate::orchestrator::Orchestrator;
+use events::Severity;
+use export::{Exporter, snapshot_json};
+use keymap::{Command, Keymap, Scope};
+use submit::SubmitUpdate;
+use state::{CLIENT_COLS, InputMode, JOB_AGE_COL, JOB_COLS, JOB_DURATION_COL, SortDir, Tab, TuiState, WORKER_COLS, WorkerOp};
+
+/// How often the screen is redrawn between data refreshes, so that ages keep counting up.
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(PartialEq)]
 enum Action {
     Continue,
+    Refresh,
+    Save,
+    ToggleRecording,
+    Submit,
+    Replay(JobId),
+    Worker(WorkerOp, String),
     Quit,
 }
 
 /// Runs the TUI event loop. Returns when the user quits.
 /// The gRPC server must already be running (spawned in main) before calling this.
-pub async fn run(diagnostics: Arc<DiagnosticsStore>) -> io::Result<()> {
+/// The display works from a snapshot of the diagnostics store, retaken every refresh_interval
+/// unless paused. Snapshots can be saved or recorded to files in export_dir. Jobs submitted
+/// from the TUI are sent to the orchestrator at addr like any other client's. Keys are
+/// dispatched through the keymap.
+pub async fn run(orchestrator: Orchestrator, addr: SocketAddr, refresh_interval: Duration, export_dir: PathBuf, keymap: Keymap) -> io::Result<()> {
+    let diagnostics = orchestrator.diagnostics.clone();
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen)?;
     let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
 
-    let mut state = TuiState::default();
+    let mut state = TuiState { keymap, ..TuiState::default() };
     let log_state = TuiWidgetState::new();
     let mut events = EventStream::new();
-    let mut tick = tokio::time::interval(Duration::from_millis(250));
+    let (mut exporter, mut export_results) = Exporter::new(export_dir);
+    let (submit_tx, mut submit_updates) = tokio::sync::mpsc::unbounded_channel();
+    let mut snapshot = (*diagnostics).clone();
+    state.history.record(&snapshot);
+    let mut refresh = tokio::time::interval(refresh_interval);
+    let mut redraw = tokio::time::interval(REDRAW_INTERVAL);
+    refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    redraw.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
     loop {
-        terminal.draw(|f| render::draw(f, &mut state, &diagnostics, &log_state))?;
+        terminal.draw(|f| render::draw(f, &mut state, &snapshot, &log_state))?;
 
-        tokio::select! {
+        let action = tokio::select! {
             maybe_event = events.next() => {
                 match maybe_event {
-                    Some(Ok(Event::Key(key)))
-                        if key.kind == crossterm::event::KeyEventKind::Press =>
-                    {
-                        if handle_key(key, &mut state) == Action::Quit {
-                            break;
-                        }
-                    }
-                    Some(Err(_)) | None => break,
-                    _ => {}
+                    Some(Ok(Event::Key(key))) if key.kind == crossterm::event::KeyEventKind::Press => handle_key(key, &mut state),
+                    Some(Err(_)) | None => Action::Quit,
+                    _ => Action::Continue,
                 }
             }
-            _ = tick.tick() => {}
+            _ = refresh.tick(), if !state.paused => Action::Refresh,
+            _ = redraw.tick() => Action::Continue,
+            Some((severity, message)) = export_results.recv() => {
+                state.notice = Some((severity, message.clone(), SystemTime::now()));
+                state.events.push(severity, message);
+                Action::Continue
+            }
+            Some(update) = submit_updates.recv() => {
+                handle_submit_update(update, &mut state);
+                Action::Continue
+            }
+        };
+        if let Action::Worker(op, address) = &action {
+            let result = match op {
+                WorkerOp::Drain  => orchestrator.drain_worker(address).await,
+                WorkerOp::Resume => orchestrator.resume_worker(address).await,
+                WorkerOp::Remove => orchestrator.remove_worker(address).await,
+            };
+            match result {
+                Ok(()) => state.events.push(Severity::Info, format!("{} worker {address}: done", op.verb())),
+                Err(e) => state.events.push(Severity::Error, format!("{} worker {address}: {e}", op.verb())),
+            }
+        }
+        match action {
+            Action::Quit => break,
+            Action::Save => exporter.save(&snapshot_json(&snapshot, state.snapshot_at, &state.history)),
+            Action::Submit => {
+                let form = &state.submit_form;
+                submit::spawn(addr, orchestrator.client_password.clone(), form.wasm_path.value().to_string(), form.args.value().to_string(), submit_tx.clone());
+            }
+            // In the job's own namespace, which the orchestrator's password may act in
+            Action::Replay(job_id) => {
+                let namespace = snapshot.jobs.get(&job_id).map(|job| job.namespace.to_string());
+                submit::spawn_replay(addr, orchestrator.client_password.clone(), job_id, namespace, submit_tx.clone());
+            }
+            Action::ToggleRecording => {
+                exporter.toggle_recording();
+                state.recording = exporter.is_recording();
+            }
+            // Show the worker's new state right away
+            Action::Refresh | Action::Worker(..) => {
+                let next = (*diagnostics).clone();
+                state.events.record_changes(&snapshot, &next);
+                snapshot = next;
+                state.snapshot_at = SystemTime::now();
+                state.history.record(&snapshot);
+                if state.recording {
+                    exporter.record(&snapshot_json(&snapshot, state.snapshot_at, &state.history));
+                    state.recording = exporter.is_recording();
+                }
+                refresh.reset();
+            }
+            Action::Continue => {}
         }
     }
 
@@ -67,81 +147,229 @@ pub async fn run(diagnostics: Arc<DiagnosticsStore>) -> io::Result<()> {
 }
 
 fn handle_key(key: crossterm::event::KeyEvent, state: &mut TuiState) -> Action {
-    // Global quit
-    if key.code == KeyCode::Char('q')
-        || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
-    {
+    // Global quit, whatever the keymap says
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
         return Action::Quit;
     }
 
-    // Tab switching via number keys
-    match key.code {
-        KeyCode::Char('1') => { state.tab = Tab::Dashboard; return Action::Continue; }
-        KeyCode::Char('2') => { state.tab = Tab::Jobs;      return Action::Continue; }
-        KeyCode::Char('3') => { state.tab = Tab::Workers;   return Action::Continue; }
-        KeyCode::Char('4') => { state.tab = Tab::Clients;   return Action::Continue; }
-        KeyCode::Char('5') => { state.tab = Tab::Logs;      return Action::Continue; }
-        _ => {}
+    if state.help_visible {
+        state.help_visible = false;
+        return Action::Continue;
+    }
+
+    // While typing a filter, every other key goes into it
+    if state.input_mode == InputMode::Filtering {
+        handle_filter_key(key, state);
+        return Action::Continue;
     }
 
-    // Tab cycling
-    match key.code {
-        KeyCode::Tab => {
-            state.tab = state.tab.next();
-            return Action::Continue;
+    if state.input_mode == InputMode::Submitting {
+        return handle_submit_key(key, state);
+    }
+
+    // Only the confirm key runs a pending action, any other key cancels it
+    if state.input_mode == InputMode::Confirming {
+        state.input_mode = InputMode::Normal;
+        let confirmed = state.keymap.lookup(&key, &[Scope::Confirm]) == Some(Command::Confirm);
+        return match state.pending_action.take() {
+            Some((op, address)) if confirmed => Action::Worker(op, address),
+            _ => Action::Continue,
+        };
+    }
+
+    let Some(command) = state.keymap.lookup(&key, Scope::active(state.tab)) else {
+        return Action::Continue;
+    };
+    match command {
+        Command::Quit            => return Action::Quit,
+        Command::Refresh         => return Action::Refresh,
+        Command::Save            => return Action::Save,
+        Command::ToggleRecording => return Action::ToggleRecording,
+        Command::Help            => state.help_visible = true,
+        Command::NewJob          => state.input_mode = InputMode::Submitting,
+        Command::ToggleEvents    => state.events_visible = !state.events_visible,
+        // Only while the pane is shown, leaving the Events tab to its own scroll keys
+        Command::EventsPaneDown if state.events_visible => state.events_scroll = state.events_scroll.saturating_add(1),
+        Command::EventsPaneUp   if state.events_visible => state.events_scroll = state.events_scroll.saturating_sub(1),
+        Command::Pause => {
+            state.paused = !state.paused;
+            // Resuming shows current data right away rather than after the next interval
+            if !state.paused {
+                return Action::Refresh;
+            }
+        }
+        Command::Filter if state.filter_mut().is_some() => state.input_mode = InputMode::Filtering,
+        Command::ClearFilter => {
+            if let Some(filter) = state.filter_mut() {
+                filter.clear();
+            }
         }
-        KeyCode::BackTab => {
-            state.tab = state.tab.prev();
-            return Action::Continue;
+        Command::GoTo(tab) => state.tab = tab,
+        Command::NextTab   => state.tab = state.tab.next(),
+        Command::PrevTab   => state.tab = state.tab.prev(),
+        // Newest or longest-running first
+        Command::ToggleJobsOrder => {
+            state.jobs_sort_col = if state.jobs_sort_col == JOB_AGE_COL { JOB_DURATION_COL } else { JOB_AGE_COL };
+            state.jobs_sort_dir = SortDir::Desc;
         }
-        _ => {}
+        Command::Replay => {
+            if let Some(job_id) = state.jobs_selected_id {
+                return Action::Replay(job_id);
+            }
+        }
+        Command::Drain | Command::Resume | Command::Remove => {
+            let op = match command {
+                Command::Drain  => WorkerOp::Drain,
+                Command::Resume => WorkerOp::Resume,
+                _               => WorkerOp::Remove,
+            };
+            if let Some(address) = state.workers_selected_address.clone() {
+                state.pending_action = Some((op, address));
+                state.input_mode = InputMode::Confirming;
+            }
+        }
+        Command::ScrollDown | Command::ScrollUp | Command::ScrollPageDown
+        | Command::ScrollPageUp | Command::ScrollTop | Command::ScrollBottom => {
+            let scroll = &mut state.events_scroll;
+            let page = state.events_page_size.max(1);
+            *scroll = match command {
+                Command::ScrollDown     => scroll.saturating_add(1),
+                Command::ScrollUp       => scroll.saturating_sub(1),
+                Command::ScrollPageDown => scroll.saturating_add(page),
+                Command::ScrollPageUp   => scroll.saturating_sub(page),
+                Command::ScrollTop      => 0,
+                _                       => usize::MAX,
+            };
+        }
+        _ => handle_table_command(command, state),
     }
 
-    // Per-tab keys
+    Action::Continue
+}
+
+/// Applies a navigation or sorting command to the active tab's table.
+fn handle_table_command(command: Command, state: &mut TuiState) {
     match state.tab {
-        Tab::Jobs => handle_table_key(
-            key,
-            &mut state.jobs_selected,
-            &mut state.jobs_sort_col,
-            &mut state.jobs_sort_dir,
-            JOB_COLS,
-        ),
-        Tab::Workers => handle_table_key(
-            key,
-            &mut state.workers_selected,
-            &mut state.workers_sort_col,
-            &mut state.workers_sort_dir,
-            WORKER_COLS,
-        ),
-        Tab::Clients => handle_table_key(
-            key,
-            &mut state.clients_selected,
-            &mut state.clients_sort_col,
-            &mut state.clients_sort_dir,
-            CLIENT_COLS,
-        ),
+        Tab::Jobs => {
+            let moved = apply_table_command(
+                command,
+                &mut state.jobs_selected,
+                &mut state.jobs_sort_col,
+                &mut state.jobs_sort_dir,
+                JOB_COLS,
+                state.jobs_page_size,
+            );
+            if moved {
+                state.jobs_selected_id = None;
+            }
+        }
+        Tab::Workers => {
+            apply_table_command(
+                command,
+                &mut state.workers_selected,
+                &mut state.workers_sort_col,
+                &mut state.workers_sort_dir,
+                WORKER_COLS,
+                1,
+            );
+        }
+        Tab::Clients => {
+            apply_table_command(
+                command,
+                &mut state.clients_selected,
+                &mut state.clients_sort_col,
+                &mut state.clients_sort_dir,
+                CLIENT_COLS,
+                1,
+            );
+        }
         _ => {}
     }
+}
 
+/// Edits the submit form, or switches field, submits or closes it.
+fn handle_submit_key(key: crossterm::event::KeyEvent, state: &mut TuiState) -> Action {
+    let form = &mut state.submit_form;
+    match state.keymap.lookup(&key, &[Scope::SubmitForm]) {
+        Some(Command::SubmitCancel) => state.input_mode = InputMode::Normal,
+        Some(Command::SubmitSend) => {
+            if !form.wasm_path.value().trim().is_empty() {
+                state.input_mode = InputMode::Normal;
+                return Action::Submit;
+            }
+        }
+        Some(Command::SubmitNextField) => form.args_focused = !form.args_focused,
+        _ => { form.focused().handle_key(key); }
+    }
     Action::Continue
 }
 
-fn handle_table_key(
-    key: crossterm::event::KeyEvent,
+/// Reports a submitted job's progress in the event log, and selects it on the Jobs tab once
+/// it has been submitted.
+fn handle_submit_update(update: SubmitUpdate, state: &mut TuiState) {
+    match update {
+        SubmitUpdate::Submitted { job_id, label } => {
+            state.events.push(Severity::Info, format!("submitted {label} as job {job_id}"));
+            state.tab = Tab::Jobs;
+            state.jobs_selected_id = Some(job_id);
+        }
+        SubmitUpdate::Completed { job_id, output } => {
+            let output = output.lines().next().unwrap_or("(no output)").to_string();
+            state.events.push(Severity::Info, format!("job {job_id} completed: {output}"));
+        }
+        SubmitUpdate::Failed { job_id: Some(job_id), error } => {
+            state.events.push(Severity::Error, format!("job {job_id} failed: {error}"));
+        }
+        SubmitUpdate::Failed { job_id: None, error } => {
+            state.events.push(Severity::Error, format!("submit failed: {error}"));
+        }
+    }
+}
+
+/// Edits the active tab's filter, or keeps or clears it.
+fn handle_filter_key(key: crossterm::event::KeyEvent, state: &mut TuiState) {
+    let command = state.keymap.lookup(&key, &[Scope::Filter]);
+    let Some(filter) = state.filter_mut() else {
+        state.input_mode = InputMode::Normal;
+        return;
+    };
+    match command {
+        Some(Command::FilterCancel) => {
+            filter.clear();
+            state.input_mode = InputMode::Normal;
+        }
+        Some(Command::FilterAccept) => state.input_mode = InputMode::Normal,
+        _ => match key.code {
+            KeyCode::Backspace => { filter.pop(); }
+            KeyCode::Char(c)   => filter.push(c),
+            _ => {}
+        },
+    }
+}
+
+/// Applies a navigation or sorting command to a table. Returns true if the selected row moved.
+/// Selections past the end are clamped when the table is drawn.
+fn apply_table_command(
+    command: Command,
     selected: &mut usize,
     sort_col: &mut usize,
     sort_dir: &mut state::SortDir,
     num_cols: usize,
-) {
-    match key.code {
+    page_size: usize,
+) -> bool {
+    match command {
         // Row navigation
-        KeyCode::Down | KeyCode::Char('j') => *selected = selected.saturating_add(1),
-        KeyCode::Up   | KeyCode::Char('k') => *selected = selected.saturating_sub(1),
-        // Sort column: left/right arrows or s (right alias)
-        KeyCode::Right | KeyCode::Char('s') => *sort_col = (*sort_col + 1) % num_cols,
-        KeyCode::Left                        => *sort_col = sort_col.checked_sub(1).unwrap_or(num_cols - 1),
-        // Toggle sort direction
-        KeyCode::Char('r') => *sort_dir = sort_dir.toggle(),
-        _ => {}
+        Command::Down     => *selected = selected.saturating_add(1),
+        Command::Up       => *selected = selected.saturating_sub(1),
+        Command::PageDown => *selected = selected.saturating_add(page_size.max(1)),
+        Command::PageUp   => *selected = selected.saturating_sub(page_size.max(1)),
+        Command::Top      => *selected = 0,
+        Command::Bottom   => *selected = usize::MAX,
+        // Sort column, wrapping around
+        Command::SortNext => { *sort_col = (*sort_col + 1) % num_cols; return false; }
+        Command::SortPrev => { *sort_col = sort_col.checked_sub(1).unwrap_or(num_cols - 1); return false; }
+        Command::SortReverse => { *sort_dir = sort_dir.toggle(); return false; }
+        _ => return false,
     }
+    true
 }
@@ -1,15 +1,20 @@
 use std::time::{Duration, SystemTime};
 
 use ratatui::Frame;
-use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span, Text};
-use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Tabs};
+use ratatui::widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Sparkline, Table, TableState, Tabs};
 use tui_logger::{TuiLoggerWidget, TuiWidgetState};
-use uuid::Uuid;
 
-use crate::diagnostics::{ClientInfo, DiagnosticsStore, JobInfo, JobState, WorkerInfo};
-use crate::tui::state::{SortDir, Tab, TuiState};
+use crate::diagnostics::{ClientInfo, DiagnosticsStore, JobFilter, JobInfo, JobState, WorkerHealth, WorkerInfo};
+use crate::heartbeat_stats::HeartbeatStats;
+use crate::namespaces::Namespace;
+use crate::tui::events::Severity;
+use crate::tui::history::Samples;
+use crate::tui::input::TextInput;
+use crate::tui::keymap::{Command, Keymap, Scope};
+use crate::tui::state::{InputMode, SortDir, Tab, TuiState};
 
 // ── Colour palette ────────────────────────────────────────────────────────────
 
@@ -20,6 +25,9 @@ const WARN: Color = Color::Yellow;
 const ERR: Color = Color::Red;
 const CANCEL: Color = Color::Magenta;
 
+/// How long a notice stays in the header.
+const NOTICE_DURATION: Duration = Duration::from_secs(5);
+
 // ── Entry point ───────────────────────────────────────────────────────────────
 
 pub fn draw(frame: &mut Frame, state: &mut TuiState, diagnostics: &DiagnosticsStore, log_state: &TuiWidgetState) {
@@ -30,20 +38,205 @@ pub fn draw(frame: &mut Frame, state: &mut TuiState, diagnostics: &DiagnosticsSt
         .constraints([Constraint::Length(3), Constraint::Min(0)])
         .split(area);
 
-    draw_tabs(frame, chunks[0], state.tab);
+    draw_tabs(frame, chunks[0], state);
+
+    // The Events tab already shows the events full height
+    let body = if state.events_visible && state.tab != Tab::Events {
+        let vsplit = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(10)])
+            .split(chunks[1]);
+        draw_events(frame, vsplit[1], state);
+        vsplit[0]
+    } else {
+        chunks[1]
+    };
 
     match state.tab {
-        Tab::Dashboard => draw_dashboard(frame, chunks[1], diagnostics, log_state),
-        Tab::Jobs      => draw_jobs(frame, chunks[1], state, diagnostics),
-        Tab::Workers   => draw_workers(frame, chunks[1], state, diagnostics),
-        Tab::Clients   => draw_clients(frame, chunks[1], state, diagnostics),
-        Tab::Logs      => draw_logs(frame, chunks[1], log_state),
+        Tab::Dashboard => draw_dashboard(frame, body, state, diagnostics, log_state),
+        Tab::Jobs      => draw_jobs(frame, body, state, diagnostics),
+        Tab::Workers   => draw_workers(frame, body, state, diagnostics),
+        Tab::Clients   => draw_clients(frame, body, state, diagnostics),
+        Tab::Logs      => draw_logs(frame, body, log_state),
+        Tab::Events    => draw_events(frame, body, state),
+    }
+
+    if let Some((op, address)) = &state.pending_action {
+        draw_confirm(frame, area, &format!("{} worker {address}?", capitalize(op.verb())), &state.keymap);
     }
+    if state.input_mode == InputMode::Submitting {
+        draw_submit_form(frame, area, state);
+    }
+    if state.help_visible {
+        draw_help(frame, area, &state.keymap);
+    }
+}
+
+/// Every keybinding, grouped by where it applies.
+fn draw_help(frame: &mut Frame, area: Rect, keymap: &Keymap) {
+    let mut lines = Vec::new();
+    for &scope in Scope::ALL {
+        if !lines.is_empty() {
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::from(Span::styled(scope.title(), Style::default().fg(ACCENT).add_modifier(Modifier::BOLD))));
+        for (keys, description) in keymap.describe(scope) {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {keys:<22}"), Style::default().fg(Color::White)),
+                Span::styled(description, Style::default().fg(DIM)),
+            ]));
+        }
+    }
+
+    // Two columns if the bindings don't fit in one
+    let height = area.height.saturating_sub(2);
+    let columns = if lines.len() + 2 > height as usize { 2 } else { 1 };
+    let per_column = lines.len().div_ceil(columns);
+    let width = (64 * columns as u16).min(area.width);
+    let popup = Rect {
+        x: area.x + area.width.saturating_sub(width) / 2,
+        y: area.y + 1,
+        width,
+        height: (per_column as u16 + 2).min(height),
+    };
+    frame.render_widget(Clear, popup);
+    let block = styled_block("Keys  any key closes");
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+    let split = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![Constraint::Ratio(1, columns as u32); columns])
+        .split(inner);
+    for (column, chunk) in lines.chunks(per_column).enumerate() {
+        frame.render_widget(Paragraph::new(chunk.to_vec()), split[column]);
+    }
+}
+
+fn draw_submit_form(frame: &mut Frame, area: Rect, state: &TuiState) {
+    let form = &state.submit_form;
+    let keymap = &state.keymap;
+    let width = 70.min(area.width);
+    let popup = Rect {
+        x: area.x + area.width.saturating_sub(width) / 2,
+        y: area.y + area.height.saturating_sub(6) / 2,
+        width,
+        height: 6.min(area.height),
+    };
+    let field = |label: &str, input: &TextInput, focused: bool| {
+        let label = Span::styled(format!("{label:<10}"), Style::default().fg(DIM));
+        if !focused {
+            return Line::from(vec![label, Span::raw(input.value().to_string())]);
+        }
+        let (before, after) = input.split_at_cursor();
+        let mut after = after.chars();
+        let at_cursor = after.next().map_or(" ".to_string(), String::from);
+        Line::from(vec![
+            label,
+            Span::raw(before.to_string()),
+            Span::styled(at_cursor, Style::default().add_modifier(Modifier::REVERSED)),
+            Span::raw(after.collect::<String>()),
+        ])
+    };
+    let text = Text::from(vec![
+        field("Wasm path", &form.wasm_path, !form.args_focused),
+        field("Args", &form.args, form.args_focused),
+        Line::from(""),
+        Line::from(Span::styled(format!(
+            "[{}] submit  [{}] next field  [{}] close",
+            keymap.hint(Command::SubmitSend), keymap.hint(Command::SubmitNextField), keymap.hint(Command::SubmitCancel),
+        ), Style::default().fg(DIM))),
+    ]);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(text).block(styled_block("Submit job")), popup);
+}
+
+/// A prompt centred over the screen, answered with the confirm key or any other key to cancel.
+fn draw_confirm(frame: &mut Frame, area: Rect, question: &str, keymap: &Keymap) {
+    let width = (question.len() as u16 + 6).clamp(30, area.width);
+    let popup = Rect {
+        x: area.x + area.width.saturating_sub(width) / 2,
+        y: area.y + area.height.saturating_sub(5) / 2,
+        width,
+        height: 5.min(area.height),
+    };
+    let text = Text::from(vec![
+        Line::from(question.to_string()),
+        Line::from(Span::styled(format!("[{}] confirm  any other key cancels", keymap.hint(Command::Confirm)), Style::default().fg(DIM))),
+    ]);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(text).alignment(Alignment::Center).block(styled_block("Confirm")), popup);
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+// ── Events pane ───────────────────────────────────────────────────────────────
+
+fn draw_events(frame: &mut Frame, area: Rect, state: &mut TuiState) {
+    // Borders take 2 lines; keep the last page of events on screen
+    let visible = area.height.saturating_sub(2) as usize;
+    state.events_page_size = visible;
+    state.events_scroll = state.events_scroll.min(state.events.len().saturating_sub(visible));
+
+    let lines: Vec<Line> = state.events.iter()
+        .skip(state.events_scroll)
+        .take(visible)
+        .map(|e| {
+            Line::from(vec![
+                Span::styled(format!("{:>9}  ", fmt_system_time(e.at)), Style::default().fg(DIM)),
+                Span::styled(e.message.clone(), severity_style(e.severity)),
+            ])
+        })
+        .collect();
+    let content = if lines.is_empty() {
+        Text::from(Line::from(Span::styled("no events yet", Style::default().fg(DIM))))
+    } else {
+        Text::from(lines)
+    };
+    let title = if state.tab == Tab::Events {
+        format!("Events  {}/{} scroll  ({} total)",
+            state.keymap.hint(Command::ScrollUp), state.keymap.hint(Command::ScrollDown), state.events.len())
+    } else {
+        format!("Events  {}/{} scroll  [{}] hide  ({} total)",
+            state.keymap.hint(Command::EventsPaneUp), state.keymap.hint(Command::EventsPaneDown),
+            state.keymap.hint(Command::ToggleEvents), state.events.len())
+    };
+    frame.render_widget(Paragraph::new(content).block(styled_block(&title)), area);
 }
 
 // ── Tab bar ───────────────────────────────────────────────────────────────────
 
-fn draw_tabs(frame: &mut Frame, area: Rect, active: Tab) {
+fn draw_tabs(frame: &mut Frame, area: Rect, state: &TuiState) {
+    let notice = state.notice.as_ref()
+        .filter(|(_, _, at)| at.elapsed().unwrap_or_default() < NOTICE_DURATION);
+    let mut status = if let Some((severity, message, _)) = notice {
+        vec![Span::styled(message.clone(), severity_style(*severity))]
+    } else if state.paused {
+        vec![
+            Span::styled(" PAUSED ", Style::default().fg(Color::Black).bg(WARN).add_modifier(Modifier::BOLD)),
+            Span::styled(format!(" data from {}", fmt_system_time(state.snapshot_at)), Style::default().fg(WARN)),
+        ]
+    } else {
+        let hint = |command| state.keymap.hint(command);
+        vec![Span::styled(format!(
+            "[{}] help  [{}] new job  [{}] events  [{}] pause  [{}] refresh  [{}] export",
+            hint(Command::Help), hint(Command::NewJob), hint(Command::ToggleEvents),
+            hint(Command::Pause), hint(Command::Refresh), hint(Command::Save),
+        ), Style::default().fg(DIM))]
+    };
+    if state.recording {
+        status.insert(0, Span::styled("● REC  ", Style::default().fg(ERR).add_modifier(Modifier::BOLD)));
+    }
+    let status = Line::from(status);
+    let status_width = (status.width() as u16).clamp(60, area.width * 2 / 3);
+
+    let hsplit = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(0), Constraint::Length(status_width)])
+        .split(area);
+
     let titles: Vec<Line> = Tab::ALL
         .iter()
         .enumerate()
@@ -51,20 +244,25 @@ fn draw_tabs(frame: &mut Frame, area: Rect, active: Tab) {
         .collect();
 
     let tabs = Tabs::new(titles)
-        .select(active.index())
+        .select(state.tab.index())
         .block(Block::default().borders(Borders::BOTTOM).border_style(Style::default().fg(DIM)))
         .highlight_style(Style::default().fg(ACCENT).add_modifier(Modifier::BOLD))
         .divider(Span::styled("│", Style::default().fg(DIM)));
 
-    frame.render_widget(tabs, area);
+    frame.render_widget(tabs, hsplit[0]);
+
+    let status = Paragraph::new(status)
+        .alignment(Alignment::Right)
+        .block(Block::default().borders(Borders::BOTTOM).border_style(Style::default().fg(DIM)));
+    frame.render_widget(status, hsplit[1]);
 }
 
 // ── Dashboard ─────────────────────────────────────────────────────────────────
 
-fn draw_dashboard(frame: &mut Frame, area: Rect, diagnostics: &DiagnosticsStore, log_state: &TuiWidgetState) {
+fn draw_dashboard(frame: &mut Frame, area: Rect, state: &TuiState, diagnostics: &DiagnosticsStore, log_state: &TuiWidgetState) {
     let vsplit = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(10), Constraint::Length(10)])
+        .constraints([Constraint::Min(10), Constraint::Length(5), Constraint::Length(10)])
         .split(area);
 
     let hsplit = Layout::default()
@@ -74,13 +272,37 @@ fn draw_dashboard(frame: &mut Frame, area: Rect, diagnostics: &DiagnosticsStore,
 
     draw_orchestrator_stats(frame, hsplit[0], diagnostics);
     draw_jobs_panel(frame, hsplit[1], diagnostics);
-    draw_mini_logs(frame, vsplit[1], log_state);
+
+    let trends = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(vsplit[1]);
+    draw_trend(frame, trends[0], "Queue depth", &state.history.queue_depth, WARN);
+    draw_trend(frame, trends[1], "Dispatches / refresh", &state.history.dispatches, ACCENT);
+
+    draw_mini_logs(frame, vsplit[2], log_state);
+}
+
+fn draw_trend(frame: &mut Frame, area: Rect, name: &str, samples: &Samples, color: Color) {
+    let title = match (samples.min(), samples.max()) {
+        (Some(min), Some(max)) => format!("{name}  min {min}  max {max}"),
+        _ => name.to_string(),
+    };
+    // Show the most recent samples that fit inside the borders
+    let data = samples.to_vec();
+    let width = area.width.saturating_sub(2) as usize;
+    let sparkline = Sparkline::default()
+        .data(&data[data.len().saturating_sub(width)..])
+        .style(Style::default().fg(color))
+        .block(styled_block(&title));
+    frame.render_widget(sparkline, area);
 }
 
 fn draw_orchestrator_stats(frame: &mut Frame, area: Rect, diagnostics: &DiagnosticsStore) {
     let now = SystemTime::now();
     let uptime = now.duration_since(diagnostics.started_at).unwrap_or_default();
 
+    let mut scheduled = 0u32;
     let mut queued = 0u32;
     let mut dispatched = 0u32;
     let mut compiling = 0u32;
@@ -88,10 +310,13 @@ fn draw_orchestrator_stats(frame: &mut Frame, area: Rect, diagnostics: &Diagnost
     let mut completed = 0u32;
     let mut failed = 0u32;
     let mut cancelled = 0u32;
+    let mut unknown = 0u32;
+    let mut lost = 0u32;
     let total = diagnostics.jobs.len() as u32;
 
     for entry in diagnostics.jobs.iter() {
         match entry.state {
+            JobState::Scheduled   => scheduled += 1,
             JobState::Queued      => queued += 1,
             JobState::Dispatched  => dispatched += 1,
             JobState::Compiling   => compiling += 1,
@@ -99,6 +324,8 @@ fn draw_orchestrator_stats(frame: &mut Frame, area: Rect, diagnostics: &Diagnost
             JobState::Completed   => completed += 1,
             JobState::Failed      => failed += 1,
             JobState::Cancelled   => cancelled += 1,
+            JobState::PresumedLost => lost += 1,
+            JobState::UnknownOutcome => unknown += 1,
         }
     }
 
@@ -135,11 +362,12 @@ fn draw_orchestrator_stats(frame: &mut Frame, area: Rect, diagnostics: &Diagnost
     let active_sum_s   = Style::default().fg(ACCENT).add_modifier(Modifier::BOLD);
     let terminal_sum_s = Style::default().add_modifier(Modifier::BOLD);
 
-    let lines: Vec<Line> = vec![
+    let mut lines: Vec<Line> = vec![
         stat("Uptime",     fmt_duration(uptime),           Style::default().fg(ACCENT).add_modifier(Modifier::BOLD)),
         stat("Workers",    connected_workers.to_string(),  Style::default().add_modifier(Modifier::BOLD)),
         stat("Total jobs", total.to_string(),              Style::default().add_modifier(Modifier::BOLD)),
         Line::from(Span::styled("─".repeat(20), dim)),
+        stat("Scheduled",  scheduled.to_string(),          Style::default().fg(DIM)),
         stat("Queued",     queued.to_string(),             Style::default().fg(WARN)),
         brow("Dispatched", dispatched.to_string(), Style::default().fg(Color::LightYellow), "┐", None),
         brow("Compiling",  compiling.to_string(),  Style::default().fg(Color::Blue), "│", Some((active.to_string(),   active_sum_s))),
@@ -148,6 +376,17 @@ fn draw_orchestrator_stats(frame: &mut Frame, area: Rect, diagnostics: &Diagnost
         brow("Failed",     failed.to_string(),     Style::default().fg(ERR),         "│", Some((terminal.to_string(), terminal_sum_s))),
         brow("Cancelled",  cancelled.to_string(),  Style::default().fg(CANCEL),      "┘", None),
     ];
+    // Only jobs recovered from the journal after a restart can have an unknown outcome
+    if unknown > 0 {
+        lines.push(stat("Unknown", unknown.to_string(), Style::default().fg(WARN).add_modifier(Modifier::BOLD)));
+    }
+    if lost > 0 {
+        lines.push(stat("Lost", lost.to_string(), Style::default().fg(ERR).add_modifier(Modifier::BOLD)));
+    }
+    if let Some(rate) = diagnostics.cold_start_rate() {
+        lines.push(Line::from(Span::styled("─".repeat(20), dim)));
+        lines.push(stat("Cold starts", format!("{:.0}%", rate * 100.0), Style::default().fg(Color::Blue)));
+    }
     let para = Paragraph::new(Text::from(lines))
         .block(styled_block("Orchestrator"));
     frame.render_widget(para, area);
@@ -156,10 +395,8 @@ fn draw_orchestrator_stats(frame: &mut Frame, area: Rect, diagnostics: &Diagnost
 fn draw_jobs_panel(frame: &mut Frame, area: Rect, diagnostics: &DiagnosticsStore) {
     let now = SystemTime::now();
 
-    // All jobs, newest first
-    let mut jobs: Vec<_> = diagnostics.jobs.iter().map(|j| j.clone()).collect();
-    jobs.sort_by(|a, b| b.queued_at.cmp(&a.queued_at));
-    jobs.truncate(50);
+    // The first page of jobs, newest first
+    let (jobs, _) = diagnostics.list_jobs(&JobFilter::default(), None, 50);
 
     if jobs.is_empty() {
         let para = Paragraph::new(Span::styled("no jobs yet", Style::default().fg(DIM)))
@@ -178,7 +415,7 @@ fn draw_jobs_panel(frame: &mut Frame, area: Rect, diagnostics: &DiagnosticsStore
     let rows: Vec<Row> = jobs.iter().map(|j| {
         let age = now.duration_since(j.queued_at).unwrap_or_default();
         Row::new(vec![
-            Cell::from(short_id(j.job_id)),
+            Cell::from(j.job_id.short()),
             Cell::from(short_addr(&j.client_address)),
             Cell::from(state_str(&j.state)).style(state_style(&j.state)),
             Cell::from(fmt_duration_short(age)),
@@ -212,20 +449,35 @@ fn draw_mini_logs(frame: &mut Frame, area: Rect, log_state: &TuiWidgetState) {
 fn draw_jobs(frame: &mut Frame, area: Rect, state: &mut TuiState, diagnostics: &DiagnosticsStore) {
     let vsplit = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(0), Constraint::Length(9)])
+        .constraints([Constraint::Min(0), Constraint::Length(11)])
         .split(area);
 
     let now = SystemTime::now();
-    let mut jobs: Vec<_> = diagnostics.jobs.iter().map(|j| j.clone()).collect();
+    let id_prefix = state.jobs_filter.to_lowercase();
+    let mut jobs: Vec<_> = diagnostics.jobs.iter()
+        .filter(|j| j.job_id.to_string().starts_with(&id_prefix)
+            || j.worker_address.as_deref().is_some_and(|w| w.contains(state.jobs_filter.as_str()))
+            || j.namespace.as_str() == state.jobs_filter)
+        .map(|j| j.clone())
+        .collect();
     sort_jobs(&mut jobs, state.jobs_sort_col, state.jobs_sort_dir, now);
 
+    // Follow the selected job to its new row, unless a navigation key moved the selection.
     // Clamp and write back so the key handler can't drift past the end
+    if let Some(id) = state.jobs_selected_id
+        && let Some(index) = jobs.iter().position(|j| j.job_id == id)
+    {
+        state.jobs_selected = index;
+    }
     let max = jobs.len().saturating_sub(1);
     state.jobs_selected = state.jobs_selected.min(max);
     let selected = state.jobs_selected;
     let detail = jobs.get(selected).cloned();
+    state.jobs_selected_id = detail.as_ref().map(|j| j.job_id);
+    // Borders and the header row take 3 lines
+    state.jobs_page_size = vsplit[0].height.saturating_sub(3) as usize;
 
-    let col_headers = ["Age", "Client", "Worker", "State", "Queue time", "Wkr time", "ID"];
+    let col_headers = ["Age", "Client", "Worker", "State", "Queue time", "Wkr time", "Duration", "Exit", "ID"];
     let sort_indicator = sort_indicator(state.jobs_sort_dir);
 
     let header = Row::new(col_headers.iter().enumerate().map(|(i, name)| {
@@ -248,16 +500,21 @@ fn draw_jobs(frame: &mut Frame, area: Rect, state: &mut TuiState, diagnostics: &
             Cell::from(state_str(&j.state)).style(state_style(&j.state)),
             Cell::from(queue_time.map(fmt_duration_short).unwrap_or_else(|| "—".into())),
             Cell::from(worker_time.map(fmt_duration_short).unwrap_or_else(|| "—".into())),
-            Cell::from(short_id(j.job_id)),
+            Cell::from(duration(j, now).map(fmt_duration_short).unwrap_or_else(|| "—".into())),
+            Cell::from(j.exit_code.map(|code| code.to_string()).unwrap_or_else(|| "—".into())),
+            Cell::from(j.job_id.short()),
         ])
     }).collect();
 
-    let mut table_state = TableState::default();
-    if !jobs.is_empty() {
-        table_state.select(Some(selected));
-    }
+    state.jobs_table.select((!jobs.is_empty()).then_some(selected));
 
-    let title = format!("Jobs  ←/→ sort col  [r] reverse  ({} total)", jobs.len());
+    let mut title = format!("Jobs  {}  [{}] age/duration  [{}] filter  {}",
+        table_hints(&state.keymap), state.keymap.hint(Command::ToggleJobsOrder), state.keymap.hint(Command::Filter),
+        fmt_count(jobs.len(), diagnostics.jobs.len(), &state.jobs_filter));
+    title += &filter_label(&state.jobs_filter, state.input_mode, &state.keymap);
+    if let Some(job) = &detail {
+        title += &format!("  ▸ {}", job.job_id);
+    }
     let table = Table::new(
         rows,
         [
@@ -268,13 +525,19 @@ fn draw_jobs(frame: &mut Frame, area: Rect, state: &mut TuiState, diagnostics: &
             Constraint::Length(12),
             Constraint::Length(10),
             Constraint::Length(10),
+            Constraint::Length(5),
+            Constraint::Length(10),
         ],
     )
     .header(header)
     .block(styled_block(&title))
     .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
 
-    frame.render_stateful_widget(table, vsplit[0], &mut table_state);
+    if jobs.is_empty() && !state.jobs_filter.is_empty() {
+        draw_no_matches(frame, vsplit[0], &title);
+    } else {
+        frame.render_stateful_widget(table, vsplit[0], &mut state.jobs_table);
+    }
     draw_job_detail(frame, vsplit[1], detail.as_ref());
 }
 
@@ -287,11 +550,20 @@ fn draw_job_detail(frame: &mut Frame, area: Rect, job: Option<&JobInfo>) {
                 detail_line("State",   state_str(&j.state)),
                 detail_line("Client",  j.client_address.clone()),
                 detail_line("Worker",  j.worker_address.clone().unwrap_or_else(|| "—".into())),
-                detail_line("Queued",  fmt_system_time(j.queued_at)),
             ];
+            if j.namespace.as_str() != Namespace::DEFAULT { lines.push(detail_line("Namespace", j.namespace.to_string())); }
+            if let Some(t) = j.scheduled_for { lines.push(detail_line("Scheduled", fmt_scheduled_time(t))); }
+            lines.push(detail_line("Queued", fmt_system_time(j.queued_at)));
             if let Some(t) = j.compiling_at  { lines.push(detail_line("Compiling", fmt_system_time(t))); }
             if let Some(t) = j.executing_at  { lines.push(detail_line("Executing", fmt_system_time(t))); }
             if let Some(t) = j.completed_at  { lines.push(detail_line("Completed", fmt_system_time(t))); }
+            if let Some(code) = j.exit_code  { lines.push(detail_line("Exit code", code.to_string())); }
+            if j.retries > 0 { lines.push(detail_line("Retries", j.retries.to_string())); }
+            if let Some(original) = j.replay_of { lines.push(detail_line("Replay of", original.to_string())); }
+            if let Some(d) = &j.details {
+                let args = if d.args.is_empty() { "(none)".to_string() } else { d.args.join(" ") };
+                lines.push(detail_line("Args", args));
+            }
             Text::from(lines)
         }
     };
@@ -303,18 +575,23 @@ fn draw_job_detail(frame: &mut Frame, area: Rect, job: Option<&JobInfo>) {
 fn draw_workers(frame: &mut Frame, area: Rect, state: &mut TuiState, diagnostics: &DiagnosticsStore) {
     let vsplit = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Min(0), Constraint::Length(7)])
+        .constraints([Constraint::Min(0), Constraint::Length(10)])
         .split(area);
 
-    let mut workers: Vec<_> = diagnostics.workers.iter().map(|w| w.clone()).collect();
+    let mut workers: Vec<_> = diagnostics.workers.iter()
+        .filter(|w| w.address.contains(state.workers_filter.as_str())
+            || w.namespace.as_ref().is_some_and(|n| n.as_str() == state.workers_filter))
+        .map(|w| w.clone())
+        .collect();
     sort_workers(&mut workers, state.workers_sort_col, state.workers_sort_dir);
 
     let max = workers.len().saturating_sub(1);
     state.workers_selected = state.workers_selected.min(max);
     let selected = state.workers_selected;
     let detail = workers.get(selected).cloned();
+    state.workers_selected_address = detail.as_ref().map(|w| w.address.clone());
 
-    let col_headers = ["Address", "Status", "Jobs rcvd", "Avg job", "Total time", "Connected"];
+    let col_headers = ["Address", "Status", "Jobs rcvd", "Avg job", "Total time", "Connected", "Last seen"];
     let sort_indicator = sort_indicator(state.workers_sort_dir);
 
     let header = Row::new(col_headers.iter().enumerate().map(|(i, name)| {
@@ -327,26 +604,31 @@ fn draw_workers(frame: &mut Frame, area: Rect, state: &mut TuiState, diagnostics
     }));
 
     let rows: Vec<Row> = workers.iter().map(|w| {
-        let status = if w.disconnected_at.is_some() { "offline" } else { "online" };
-        let status_style = if w.disconnected_at.is_some() {
-            Style::default().fg(DIM)
-        } else {
-            Style::default().fg(SUCCESS)
-        };
+        let health = w.health();
         let avg = if w.jobs_received > 0 {
             fmt_duration_short(w.total_job_time / w.jobs_received)
         } else {
             "—".into()
         };
         let connected_ago = SystemTime::now().duration_since(w.connected_at).unwrap_or_default();
+        let status = if w.heartbeat_stats.anomalous() {
+            format!("{} ⚠", health_str(health))
+        } else {
+            health_str(health).to_string()
+        };
         Row::new(vec![
             Cell::from(w.address.as_str()),
-            Cell::from(status).style(status_style),
+            Cell::from(status).style(health_style(health)),
             Cell::from(w.jobs_received.to_string()),
             Cell::from(avg),
             Cell::from(fmt_duration_short(w.total_job_time)),
             Cell::from(fmt_duration_short(connected_ago)),
+            Cell::from(fmt_system_time(w.last_heard_at())),
         ])
+        .style(match health {
+            WorkerHealth::Offline => Style::default().fg(DIM),
+            _ => Style::default(),
+        })
     }).collect();
 
     let mut table_state = TableState::default();
@@ -354,43 +636,143 @@ fn draw_workers(frame: &mut Frame, area: Rect, state: &mut TuiState, diagnostics
         table_state.select(Some(selected));
     }
 
-    let title = format!("Workers  ←/→ sort col  [r] reverse  ({} total)", workers.len());
+    let hint = |command| state.keymap.hint(command);
+    let title = format!("Workers  {}  [{}] filter  [{}] drain [{}] un-drain [{}] remove  {}",
+        table_hints(&state.keymap), hint(Command::Filter), hint(Command::Drain), hint(Command::Resume), hint(Command::Remove),
+        fmt_count(workers.len(), diagnostics.workers.len(), &state.workers_filter))
+        + &filter_label(&state.workers_filter, state.input_mode, &state.keymap);
     let table = Table::new(
         rows,
         [
             Constraint::Min(20),
-            Constraint::Length(8),
+            Constraint::Length(12),
             Constraint::Length(11),
             Constraint::Length(10),
             Constraint::Length(12),
             Constraint::Length(12),
+            Constraint::Length(12),
         ],
     )
     .header(header)
     .block(styled_block(&title))
     .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
 
-    frame.render_stateful_widget(table, vsplit[0], &mut table_state);
-    draw_worker_detail(frame, vsplit[1], detail.as_ref());
+    if workers.is_empty() && !state.workers_filter.is_empty() {
+        draw_no_matches(frame, vsplit[0], &title);
+    } else {
+        frame.render_stateful_widget(table, vsplit[0], &mut table_state);
+    }
+    draw_worker_detail(frame, vsplit[1], detail.as_ref(), diagnostics);
 }
 
-fn draw_worker_detail(frame: &mut Frame, area: Rect, worker: Option<&WorkerInfo>) {
+fn draw_worker_detail(frame: &mut Frame, area: Rect, worker: Option<&WorkerInfo>, diagnostics: &DiagnosticsStore) {
+    let hsplit = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(area);
+
+    let now = SystemTime::now();
+    let mut recent_jobs: Vec<JobInfo> = match worker {
+        Some(w) => diagnostics.jobs.iter()
+            .filter(|j| j.worker_address.as_deref() == Some(w.address.as_str()))
+            .map(|j| j.clone())
+            .collect(),
+        None => Vec::new(),
+    };
+    recent_jobs.sort_by_key(|j| std::cmp::Reverse(j.queued_at));
+
     let content = match worker {
         None => Text::from(Line::from(Span::styled("no selection", Style::default().fg(DIM)))),
         Some(w) => {
+            let running = recent_jobs.iter()
+                .filter(|j| matches!(j.state, JobState::Dispatched | JobState::Compiling | JobState::Executing))
+                .count();
             let mut lines = vec![
                 detail_line("Address",    w.address.clone()),
+                detail_line("Credits",    format!("{running} running / {} advertised", w.load_credits.unwrap_or(w.credits))),
                 detail_line("Jobs rcvd",  w.jobs_received.to_string()),
                 detail_line("Total time", fmt_duration_short(w.total_job_time)),
+                detail_line("Cold starts", match w.total_compile_time.checked_div(w.cold_starts) {
+                    Some(avg) => format!("{} of {}, {} avg compile", w.cold_starts, w.jobs_received, fmt_duration_short(avg)),
+                    None => "0".to_string(),
+                }),
                 detail_line("Connected",  fmt_system_time(w.connected_at)),
+                detail_line("Last seen",  fmt_system_time(w.last_heard_at())),
             ];
             if let Some(t) = w.disconnected_at {
                 lines.push(detail_line("Disconnected", fmt_system_time(t)));
             }
+            if let (Some(load), Some(credits)) = (w.host_load, w.load_credits) {
+                lines.push(detail_line("Host load", format!("{:.0}%, {credits} of {} credits", load * 100.0, w.credits)));
+            }
+            if let (Some(reported), Some(estimated)) = (w.running_jobs, w.estimated_running_jobs) {
+                lines.push(detail_line("Heartbeat", format!("{reported} running, {estimated} by credit count")));
+            }
+            if let Some(heartbeats) = heartbeat_summary(&w.heartbeat_stats) {
+                lines.push(detail_line("Heartbeats", heartbeats));
+            }
+            if w.out_of_band_jobs > 0 {
+                lines.push(detail_line("Out of band", format!("{} jobs, {} credits held back", w.out_of_band_jobs, w.reserved_credits)));
+            }
+            if w.modules_handed_off > 0 || w.modules_received > 0 {
+                lines.push(detail_line("Handoff", format!("{} modules received, {} handed off", w.modules_received, w.modules_handed_off)));
+            }
+            if let Some(namespace) = &w.namespace {
+                lines.push(detail_line("Namespace", format!("{namespace} only")));
+            }
+            if let Some(c) = &w.capabilities {
+                lines.push(detail_line("Version", c.version.clone()));
+                lines.push(detail_line("Limits", format!(
+                    "{} wasm, {} args, {} of args",
+                    fmt_bytes(c.max_wasm_bytes), c.max_args, fmt_bytes(c.max_args_bytes),
+                )));
+                if c.signed_jobs_only {
+                    lines.push(detail_line("Signing", "signed jobs only".to_string()));
+                }
+                if !c.hash_algorithms.is_empty() {
+                    let algorithms: Vec<_> = c.hash_algorithms().map(|a| a.label()).collect();
+                    lines.push(detail_line("Digests", algorithms.join(", ")));
+                }
+            }
+            if let Some(m) = diagnostics.managed_workers.iter().find(|m| m.address == w.address) {
+                let restarts = if m.restarts == 1 { "1 restart".to_string() } else { format!("{} restarts", m.restarts) };
+                lines.push(detail_line("Managed", format!("#{} {}, {restarts}", m.index, m.state.as_str())));
+            }
+            if w.flap_count > 0 {
+                lines.push(detail_line("Flaps", w.flap_count.to_string()));
+            }
+            if w.lost_jobs > 0 {
+                lines.push(detail_line("Lost jobs", w.lost_jobs.to_string()));
+            }
+            if w.failed_probes > 0 {
+                lines.push(detail_line("Failed probes", w.failed_probes.to_string()));
+            }
+            if let Some(t) = w.quarantined_until {
+                lines.push(detail_line("Quarantined", format!("until {}", fmt_system_time(t))));
+            }
             Text::from(lines)
         }
     };
-    frame.render_widget(Paragraph::new(content).block(styled_block("Detail")), area);
+    frame.render_widget(Paragraph::new(content).block(styled_block("Detail")), hsplit[0]);
+
+    // Most recently queued jobs first, as many as fit
+    let rows: Vec<Row> = recent_jobs.iter()
+        .take(hsplit[1].height.saturating_sub(3) as usize)
+        .map(|j| {
+            let last_update = j.completed_at.or(j.executing_at).or(j.compiling_at).unwrap_or(j.queued_at);
+            Row::new(vec![
+                Cell::from(j.job_id.short()),
+                Cell::from(state_str(&j.state)).style(state_style(&j.state)),
+                Cell::from(format!("{} ago", fmt_duration_short(now.duration_since(last_update).unwrap_or_default()))),
+            ])
+        })
+        .collect();
+    let header = Row::new(["ID", "State", "Last update"].map(|name| Cell::from(name).style(header_style())));
+    let title = format!("Recent jobs ({})", recent_jobs.len());
+    let table = Table::new(rows, [Constraint::Length(10), Constraint::Length(12), Constraint::Min(12)])
+        .header(header)
+        .block(styled_block(&title));
+    frame.render_widget(table, hsplit[1]);
 }
 
 // ── Clients tab ───────────────────────────────────────────────────────────────
@@ -449,7 +831,7 @@ fn draw_clients(frame: &mut Frame, area: Rect, state: &mut TuiState, diagnostics
         table_state.select(Some(selected));
     }
 
-    let title = format!("Clients  ←/→ sort col  [r] reverse  ({} total)", clients.len());
+    let title = format!("Clients  {}  ({} total)", table_hints(&state.keymap), clients.len());
     let table = Table::new(
         rows,
         [
@@ -501,7 +883,7 @@ fn draw_logs(frame: &mut Frame, area: Rect, log_state: &TuiWidgetState) {
 
 // ── Sorting ───────────────────────────────────────────────────────────────────
 
-fn sort_jobs(jobs: &mut Vec<JobInfo>, col: usize, dir: SortDir, now: SystemTime) {
+fn sort_jobs(jobs: &mut [JobInfo], col: usize, dir: SortDir, now: SystemTime) {
     jobs.sort_by(|a, b| {
         let ord = match col {
             0 => a.queued_at.cmp(&b.queued_at),                              // Age
@@ -510,17 +892,19 @@ fn sort_jobs(jobs: &mut Vec<JobInfo>, col: usize, dir: SortDir, now: SystemTime)
             3 => a.state.cmp(&b.state),                                      // State
             4 => queue_time(a, now).cmp(&queue_time(b, now)),                // Queue time
             5 => worker_time(a, now).cmp(&worker_time(b, now)),              // Wkr time
-            _ => a.job_id.cmp(&b.job_id),                                    // ID (col 6)
+            6 => duration(a, now).cmp(&duration(b, now)),                    // Duration
+            7 => a.exit_code.cmp(&b.exit_code),                              // Exit
+            _ => a.job_id.cmp(&b.job_id),                                    // ID (col 8)
         };
         if dir == SortDir::Desc { ord.reverse() } else { ord }
     });
 }
 
-fn sort_workers(workers: &mut Vec<WorkerInfo>, col: usize, dir: SortDir) {
+fn sort_workers(workers: &mut [WorkerInfo], col: usize, dir: SortDir) {
     workers.sort_by(|a, b| {
         let ord = match col {
             0 => a.address.cmp(&b.address),
-            1 => a.disconnected_at.is_none().cmp(&b.disconnected_at.is_none()),
+            1 => a.health().cmp(&b.health()),
             2 => a.jobs_received.cmp(&b.jobs_received),
             3 => {
                 let avg_a = if a.jobs_received > 0 { a.total_job_time / a.jobs_received } else { Duration::ZERO };
@@ -528,13 +912,14 @@ fn sort_workers(workers: &mut Vec<WorkerInfo>, col: usize, dir: SortDir) {
                 avg_a.cmp(&avg_b)
             }
             4 => a.total_job_time.cmp(&b.total_job_time),
-            _ => a.connected_at.cmp(&b.connected_at),                        // Connected (col 5)
+            5 => a.connected_at.cmp(&b.connected_at),
+            _ => a.last_heard_at().cmp(&b.last_heard_at()),                  // Last seen (col 6)
         };
         if dir == SortDir::Desc { ord.reverse() } else { ord }
     });
 }
 
-fn sort_clients(clients: &mut Vec<ClientInfo>, col: usize, dir: SortDir) {
+fn sort_clients(clients: &mut [ClientInfo], col: usize, dir: SortDir) {
     clients.sort_by(|a, b| {
         let ord = match col {
             0 => a.address.cmp(&b.address),
@@ -561,6 +946,7 @@ fn sort_clients(clients: &mut Vec<ClientInfo>, col: usize, dir: SortDir) {
 
 fn queue_time(job: &JobInfo, now: SystemTime) -> Option<Duration> {
     match job.state {
+        JobState::Scheduled => None,
         JobState::Queued | JobState::Dispatched => {
             Some(now.duration_since(job.queued_at).unwrap_or_default())
         }
@@ -577,6 +963,16 @@ fn worker_time(job: &JobInfo, now: SystemTime) -> Option<Duration> {
     Some(end.duration_since(start).unwrap_or_default())
 }
 
+/// How long a pending job has waited in the queue so far, or how long a job that reached a
+/// worker ran, or has been running. None for jobs that are scheduled or whose outcome is unknown.
+fn duration(job: &JobInfo, now: SystemTime) -> Option<Duration> {
+    match job.state {
+        JobState::Scheduled | JobState::UnknownOutcome => None,
+        JobState::Queued | JobState::Dispatched => queue_time(job, now),
+        _ => worker_time(job, now),
+    }
+}
+
 fn fmt_duration(d: Duration) -> String {
     let total = d.as_secs();
     let h = total / 3600;
@@ -602,13 +998,26 @@ fn fmt_duration_short(d: Duration) -> String {
     }
 }
 
+fn fmt_bytes(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{} MiB", bytes / (1024 * 1024))
+    } else if bytes >= 1024 {
+        format!("{} KiB", bytes / 1024)
+    } else {
+        format!("{bytes} B")
+    }
+}
+
 fn fmt_system_time(t: SystemTime) -> String {
     let elapsed = SystemTime::now().duration_since(t).unwrap_or_default();
     format!("{} ago", fmt_duration_short(elapsed))
 }
 
-fn short_id(id: Uuid) -> String {
-    id.to_string()[..8].to_string()
+fn fmt_scheduled_time(t: SystemTime) -> String {
+    match t.duration_since(SystemTime::now()) {
+        Ok(remaining) => format!("in {}", fmt_duration_short(remaining)),
+        Err(_) => fmt_system_time(t),
+    }
 }
 
 fn short_addr(addr: &str) -> String {
@@ -621,6 +1030,7 @@ fn short_addr(addr: &str) -> String {
 
 fn state_str(state: &JobState) -> &'static str {
     match state {
+        JobState::Scheduled  => "Scheduled",
         JobState::Queued     => "Queued",
         JobState::Dispatched => "Dispatched",
         JobState::Compiling  => "Compiling",
@@ -628,20 +1038,100 @@ fn state_str(state: &JobState) -> &'static str {
         JobState::Completed  => "Completed",
         JobState::Failed     => "Failed",
         JobState::Cancelled  => "Cancelled",
+        JobState::PresumedLost => "Lost",
+        JobState::UnknownOutcome => "Unknown",
+    }
+}
+
+fn severity_style(severity: Severity) -> Style {
+    match severity {
+        Severity::Info  => Style::default(),
+        Severity::Warn  => Style::default().fg(WARN),
+        Severity::Error => Style::default().fg(ERR).add_modifier(Modifier::BOLD),
+    }
+}
+
+/// How regularly a worker's heartbeats arrive and how far off its clock is, marked ⚠ where
+/// either looks wrong. None before the second heartbeat.
+fn heartbeat_summary(stats: &HeartbeatStats) -> Option<String> {
+    let mean = Duration::from_secs_f64(stats.interval_mean_ms? / 1000.0);
+    let mut summary = format!("every {} ± {}", fmt_duration_short(mean), fmt_duration_short(stats.interval_jitter().unwrap_or_default()));
+    if let Some(expected) = stats.expected_interval {
+        summary += &format!(" (sent every {})", fmt_duration_short(expected));
+    }
+    if stats.interval_drifting() {
+        summary += " ⚠";
+    }
+    if let Some(skew_ms) = stats.clock_skew_ms {
+        summary += &format!(", clock {skew_ms:+.0}ms behind");
+        if stats.clock_skewed() {
+            summary += " ⚠";
+        }
+    }
+    Some(summary)
+}
+
+fn health_str(health: WorkerHealth) -> &'static str {
+    match health {
+        WorkerHealth::Offline     => "OFFLINE",
+        WorkerHealth::Quarantined => "QUARANTINED",
+        WorkerHealth::Validating  => "VALIDATING",
+        WorkerHealth::Drained     => "DRAINED",
+        WorkerHealth::Online      => "online",
+    }
+}
+
+fn health_style(health: WorkerHealth) -> Style {
+    match health {
+        WorkerHealth::Offline     => Style::default().fg(ERR).add_modifier(Modifier::BOLD),
+        WorkerHealth::Quarantined | WorkerHealth::Validating | WorkerHealth::Drained => Style::default().fg(WARN).add_modifier(Modifier::BOLD),
+        WorkerHealth::Online      => Style::default().fg(SUCCESS),
     }
 }
 
 fn state_style(state: &JobState) -> Style {
     match state {
+        JobState::Scheduled  => Style::default().fg(DIM),
         JobState::Queued | JobState::Dispatched => Style::default().fg(WARN),
         JobState::Compiling  => Style::default().fg(Color::Blue),
         JobState::Executing  => Style::default().fg(ACCENT),
         JobState::Completed => Style::default().fg(SUCCESS),
         JobState::Failed => Style::default().fg(ERR),
         JobState::Cancelled => Style::default().fg(CANCEL),
+        JobState::PresumedLost => Style::default().fg(ERR).add_modifier(Modifier::BOLD),
+        JobState::UnknownOutcome => Style::default().fg(WARN).add_modifier(Modifier::BOLD),
+    }
+}
+
+/// Row count for a table title, out of the unfiltered total when a filter is set.
+fn fmt_count(shown: usize, total: usize, filter: &str) -> String {
+    if filter.is_empty() {
+        format!("({total} total)")
+    } else {
+        format!("({shown} of {total})")
+    }
+}
+
+/// Sorting hints shared by the table titles.
+fn table_hints(keymap: &Keymap) -> String {
+    format!("{}/{} sort col  [{}] reverse", keymap.hint(Command::SortPrev), keymap.hint(Command::SortNext), keymap.hint(Command::SortReverse))
+}
+
+/// Title suffix showing a table's filter, with a cursor while it is being typed.
+fn filter_label(filter: &str, mode: InputMode, keymap: &Keymap) -> String {
+    match mode {
+        InputMode::Filtering => format!("  /{filter}▏"),
+        _ if !filter.is_empty() => format!("  /{filter}  [{}] clear", keymap.hint(Command::ClearFilter)),
+        _ => String::new(),
     }
 }
 
+/// Placeholder for a table whose filter matches nothing.
+fn draw_no_matches(frame: &mut Frame, area: Rect, title: &str) {
+    let text = Line::from(Span::styled("no matches", Style::default().fg(DIM)));
+    frame.render_widget(Paragraph::new(text).block(styled_block(title)), area);
+}
+
 fn sort_indicator(dir: SortDir) -> &'static str {
     match dir {
         SortDir::Asc  => " ▲",
@@ -666,3 +1156,167 @@ fn detail_line(label: &str, value: impl Into<String>) -> Line<'static> {
         Span::raw(value.into()),
     ])
 }
+
+#[cfg(test)]
+mod tests {
+    use shared::JobId;
+
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    /// A job queued at 100s, moved through states by the caller.
+    fn job(state: JobState) -> JobInfo {
+        JobInfo {
+            job_id: JobId::random(),
+            record_id: 0,
+            state,
+            namespace: Namespace::new(Namespace::DEFAULT).unwrap(),
+            client_address: "127.0.0.1:1".into(),
+            owner: None,
+            worker_address: None,
+            scheduled_for: None,
+            queued_at: at(100),
+            compiling_at: None,
+            executing_at: None,
+            completed_at: None,
+            retries: 0,
+            late_state: None,
+            details: None,
+            replay_of: None,
+            exit_code: None,
+        }
+    }
+
+    fn ids(jobs: &[JobInfo]) -> Vec<JobId> {
+        jobs.iter().map(|j| j.job_id).collect()
+    }
+
+    #[test]
+    fn pending_jobs_last_as_long_as_they_have_queued() {
+        let now = at(130);
+        assert_eq!(duration(&job(JobState::Queued), now), Some(Duration::from_secs(30)));
+        assert_eq!(duration(&job(JobState::Dispatched), now), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn running_jobs_last_as_long_as_they_have_run() {
+        let mut compiling = job(JobState::Compiling);
+        compiling.compiling_at = Some(at(110));
+        let mut executing = job(JobState::Executing);
+        executing.executing_at = Some(at(120));
+
+        let now = at(130);
+        assert_eq!(duration(&compiling, now), Some(Duration::from_secs(20)));
+        assert_eq!(duration(&executing, now), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn finished_jobs_last_as_long_as_they_ran() {
+        let mut completed = job(JobState::Completed);
+        completed.executing_at = Some(at(120));
+        completed.completed_at = Some(at(125));
+        let mut failed = job(JobState::Failed);
+        failed.compiling_at = Some(at(110));
+        failed.executing_at = Some(at(115));
+        failed.completed_at = Some(at(118));
+
+        let now = at(1000);
+        assert_eq!(duration(&completed, now), Some(Duration::from_secs(5)));
+        assert_eq!(duration(&failed, now), Some(Duration::from_secs(8)));
+    }
+
+    #[test]
+    fn jobs_that_never_ran_have_no_duration() {
+        let mut cancelled = job(JobState::Cancelled);
+        cancelled.completed_at = Some(at(105));
+        let mut scheduled = job(JobState::Scheduled);
+        scheduled.scheduled_for = Some(at(200));
+        let unknown = job(JobState::UnknownOutcome);
+
+        let now = at(130);
+        assert_eq!(duration(&cancelled, now), None);
+        assert_eq!(duration(&scheduled, now), None);
+        assert_eq!(duration(&unknown, now), None);
+    }
+
+    #[test]
+    fn jobs_sort_by_duration_in_either_direction() {
+        let mut queued = job(JobState::Queued);
+        queued.queued_at = at(120);
+        let mut completed = job(JobState::Completed);
+        completed.executing_at = Some(at(100));
+        completed.completed_at = Some(at(102));
+        let mut executing = job(JobState::Executing);
+        executing.executing_at = Some(at(125));
+        let scheduled = job(JobState::Scheduled);
+        let now = at(130);
+
+        let mut jobs = vec![queued.clone(), completed.clone(), executing.clone(), scheduled.clone()];
+        sort_jobs(&mut jobs, 6, SortDir::Asc, now);
+        assert_eq!(ids(&jobs), ids(&[scheduled.clone(), completed.clone(), executing.clone(), queued.clone()]));
+
+        sort_jobs(&mut jobs, 6, SortDir::Desc, now);
+        assert_eq!(ids(&jobs), ids(&[queued, executing, completed, scheduled]));
+    }
+
+    #[test]
+    fn jobs_sort_by_exit_code_with_unfinished_jobs_first() {
+        let mut failed = job(JobState::Failed);
+        failed.exit_code = Some(3);
+        let mut completed = job(JobState::Completed);
+        completed.exit_code = Some(0);
+        let executing = job(JobState::Executing);
+
+        let mut jobs = vec![failed.clone(), completed.clone(), executing.clone()];
+        sort_jobs(&mut jobs, 7, SortDir::Asc, at(130));
+        assert_eq!(ids(&jobs), ids(&[executing.clone(), completed.clone(), failed.clone()]));
+
+        sort_jobs(&mut jobs, 7, SortDir::Desc, at(130));
+        assert_eq!(ids(&jobs), ids(&[failed, completed, executing]));
+    }
+
+    #[test]
+    fn jobs_sort_by_age_oldest_first() {
+        let mut older = job(JobState::Queued);
+        older.queued_at = at(10);
+        let mut newer = job(JobState::Queued);
+        newer.queued_at = at(20);
+
+        let mut jobs = vec![newer.clone(), older.clone()];
+        sort_jobs(&mut jobs, 0, SortDir::Asc, at(30));
+        assert_eq!(ids(&jobs), ids(&[older.clone(), newer.clone()]));
+
+        sort_jobs(&mut jobs, 0, SortDir::Desc, at(30));
+        assert_eq!(ids(&jobs), ids(&[newer, older]));
+    }
+
+    #[test]
+    fn jobs_sort_by_state_and_queue_time() {
+        let mut queued = job(JobState::Queued);
+        queued.queued_at = at(125);
+        let mut executing = job(JobState::Executing);
+        executing.executing_at = Some(at(110));
+        let now = at(130);
+
+        let mut jobs = vec![executing.clone(), queued.clone()];
+        sort_jobs(&mut jobs, 3, SortDir::Asc, now);
+        assert_eq!(ids(&jobs), ids(&[queued.clone(), executing.clone()]));
+
+        // The executing job queued for 10s, the queued one for 5s so far
+        sort_jobs(&mut jobs, 4, SortDir::Asc, now);
+        assert_eq!(ids(&jobs), ids(&[queued, executing]));
+    }
+
+    #[test]
+    fn the_id_column_sorts_by_job_id() {
+        let mut jobs: Vec<JobInfo> = (0..5).map(|_| job(JobState::Queued)).collect();
+        let mut expected = ids(&jobs);
+        expected.sort();
+
+        sort_jobs(&mut jobs, 8, SortDir::Asc, at(130));
+        assert_eq!(ids(&jobs), expected);
+    }
+}
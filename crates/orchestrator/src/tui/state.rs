@@ -1,6 +1,16 @@
+use std::time::SystemTime;
+
+use ratatui::widgets::TableState;
+use shared::JobId;
+
+use crate::tui::events::{EventLog, Severity};
+use crate::tui::history::History;
+use crate::tui::keymap::Keymap;
+use crate::tui::submit::SubmitForm;
+
 /// Number of sortable columns in each table — used by the key handler for wrapping.
-pub const JOB_COLS: usize = 7;
-pub const WORKER_COLS: usize = 6;
+pub const JOB_COLS: usize = 9;
+pub const WORKER_COLS: usize = 7;
 pub const CLIENT_COLS: usize = 7;
 
 /// Which tab is currently active.
@@ -12,6 +22,7 @@ pub enum Tab {
     Workers,
     Clients,
     Logs,
+    Events,
 }
 
 impl Tab {
@@ -21,6 +32,7 @@ impl Tab {
         Tab::Workers,
         Tab::Clients,
         Tab::Logs,
+        Tab::Events,
     ];
 
     pub fn title(self) -> &'static str {
@@ -30,6 +42,7 @@ impl Tab {
             Tab::Workers   => "Workers",
             Tab::Clients   => "Clients",
             Tab::Logs      => "Logs",
+            Tab::Events    => "Events",
         }
     }
 
@@ -67,20 +80,87 @@ impl SortDir {
     }
 }
 
+/// Whether keys drive the UI, are typed into the active tab's filter or the submit form, or
+/// answer a confirmation prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputMode {
+    #[default]
+    Normal,
+    Filtering,
+    Confirming,
+    Submitting,
+}
+
+/// An operator action on a worker, confirmed before it is run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerOp {
+    Drain,
+    Resume,
+    Remove,
+}
+
+impl WorkerOp {
+    pub fn verb(self) -> &'static str {
+        match self {
+            WorkerOp::Drain  => "drain",
+            WorkerOp::Resume => "resume",
+            WorkerOp::Remove => "remove",
+        }
+    }
+}
+
 /// Full UI state — current tab, per-table sort column (by index), direction, and row selection.
 #[derive(Debug)]
 pub struct TuiState {
     pub tab: Tab,
+    pub input_mode: InputMode,
+    pub keymap: Keymap,
+    /// The keybindings overlay, closed by any key.
+    pub help_visible: bool,
+    /// While paused the displayed data is not refreshed.
+    pub paused: bool,
+    /// When the displayed data was copied from the diagnostics store.
+    pub snapshot_at: SystemTime,
+    /// Whether every refresh is being appended to a recording file.
+    pub recording: bool,
+    /// A message shown briefly in the header, e.g. where a snapshot was saved.
+    pub notice: Option<(Severity, String, SystemTime)>,
+    /// Dashboard trends, sampled on every refresh.
+    pub history: History,
+    /// Changes seen between refreshes, shown on the Events tab or in a pane below the active tab.
+    pub events: EventLog,
+    pub events_visible: bool,
+    /// Number of newest events scrolled past in the events pane.
+    pub events_scroll: usize,
+    /// Events visible as of the last draw, used for PageUp/PageDown.
+    pub events_page_size: usize,
 
-    /// Jobs table: col 0=Age, 1=Client, 2=Worker, 3=State, 4=Queue time, 5=Wkr time, 6=ID
+    /// Jobs table: col 0=Age, 1=Client, 2=Worker, 3=State, 4=Queue time, 5=Wkr time, 6=Duration, 7=Exit, 8=ID
     pub jobs_sort_col: usize,
     pub jobs_sort_dir: SortDir,
     pub jobs_selected: usize,
+    /// The selected job, so the selection follows it as rows shift between refreshes.
+    /// Cleared by navigation keys, then re-resolved from jobs_selected on the next draw.
+    pub jobs_selected_id: Option<JobId>,
+    /// Kept across frames so the table's scroll offset is stable.
+    pub jobs_table: TableState,
+    /// Rows visible in the jobs table as of the last draw, used for PageUp/PageDown.
+    pub jobs_page_size: usize,
+    /// Shows only jobs whose ID starts with, or whose worker address contains, this text.
+    pub jobs_filter: String,
 
-    /// Workers table: col 0=Address, 1=Status, 2=Jobs rcvd, 3=Avg job, 4=Total time, 5=Connected
+    /// Workers table: col 0=Address, 1=Status, 2=Jobs rcvd, 3=Avg job, 4=Total time, 5=Connected, 6=Last seen
     pub workers_sort_col: usize,
     pub workers_sort_dir: SortDir,
     pub workers_selected: usize,
+    /// Address of the selected worker as of the last draw, the target of worker actions.
+    pub workers_selected_address: Option<String>,
+    /// Action awaiting confirmation, while in InputMode::Confirming.
+    pub pending_action: Option<(WorkerOp, String)>,
+    /// Open while in InputMode::Submitting, and kept afterwards to resubmit quickly.
+    pub submit_form: SubmitForm,
+    /// Shows only workers whose address contains this text.
+    pub workers_filter: String,
 
     /// Clients table: col 0=Address, 1=Jobs, 2=Avg queue, 3=Tot queue, 4=Avg worker, 5=Tot worker, 6=Connected
     pub clients_sort_col: usize,
@@ -92,16 +172,52 @@ impl Default for TuiState {
     fn default() -> Self {
         Self {
             tab: Tab::default(),
+            input_mode: InputMode::default(),
+            keymap: Keymap::default(),
+            help_visible: false,
+            paused: false,
+            snapshot_at: SystemTime::now(),
+            recording: false,
+            notice: None,
+            history: History::new(),
+            events: EventLog::new(),
+            events_visible: false,
+            events_scroll: 0,
+            events_page_size: 1,
             // Sort jobs by Age (col 0) descending = youngest first
             jobs_sort_col: 0,
             jobs_sort_dir: SortDir::Desc,
             jobs_selected: 0,
-            workers_sort_col: 0,
+            jobs_selected_id: None,
+            jobs_table: TableState::default(),
+            jobs_page_size: 1,
+            jobs_filter: String::new(),
+            // Sort workers by Status (col 1) ascending = unhealthy first
+            workers_sort_col: 1,
             workers_sort_dir: SortDir::default(),
             workers_selected: 0,
+            workers_selected_address: None,
+            pending_action: None,
+            submit_form: SubmitForm::default(),
+            workers_filter: String::new(),
             clients_sort_col: 0,
             clients_sort_dir: SortDir::default(),
             clients_selected: 0,
         }
     }
 }
+
+/// Jobs table columns toggled between by Command::ToggleJobsOrder.
+pub const JOB_AGE_COL: usize = 0;
+pub const JOB_DURATION_COL: usize = 6;
+
+impl TuiState {
+    /// The active tab's filter, if the tab can be filtered.
+    pub fn filter_mut(&mut self) -> Option<&mut String> {
+        match self.tab {
+            Tab::Jobs    => Some(&mut self.jobs_filter),
+            Tab::Workers => Some(&mut self.workers_filter),
+            _ => None,
+        }
+    }
+}
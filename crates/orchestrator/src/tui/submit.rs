@@ -0,0 +1,118 @@
+use std::net::SocketAddr;
+
+use client::{Client, Job, JobError, JobId};
+use tokio::sync::mpsc;
+
+use crate::tui::input::TextInput;
+
+/// The form opened with n to submit a job to this orchestrator.
+#[derive(Debug, Default)]
+pub struct SubmitForm {
+    pub wasm_path: TextInput,
+    /// Space-separated arguments passed to the job.
+    pub args: TextInput,
+    /// Whether the args field has focus rather than the path.
+    pub args_focused: bool,
+}
+
+impl SubmitForm {
+    pub fn focused(&mut self) -> &mut TextInput {
+        if self.args_focused { &mut self.args } else { &mut self.wasm_path }
+    }
+}
+
+/// Progress of a job submitted from the TUI.
+#[derive(Debug)]
+pub enum SubmitUpdate {
+    /// label says what was submitted: the wasm path, or the job replayed.
+    Submitted { job_id: JobId, label: String },
+    Completed { job_id: JobId, output: String },
+    Failed { job_id: Option<JobId>, error: String },
+}
+
+/// Submits a job to the orchestrator listening on addr through the client library, the same
+/// way the cli does, reporting progress on updates. Runs in the background.
+pub fn spawn(addr: SocketAddr, password: Option<String>, wasm_path: String, args: String, updates: mpsc::UnboundedSender<SubmitUpdate>) {
+    let addr = loopback(addr);
+    tokio::spawn(async move {
+        let job = match Job::from_path(&wasm_path) {
+            Ok(job) => job.args(args.split_whitespace()),
+            Err(e) => {
+                updates.send(SubmitUpdate::Failed { job_id: None, error: format!("failed to read {wasm_path}: {e}") }).ok();
+                return;
+            }
+        };
+        let client = match Client::connect(&format!("http://{addr}"), password, false).await {
+            Ok(client) => client,
+            Err(e) => {
+                updates.send(SubmitUpdate::Failed { job_id: None, error: e.to_string() }).ok();
+                return;
+            }
+        };
+
+        let running_job = client.submit_job(job);
+        let job_id = running_job.job_id();
+        updates.send(SubmitUpdate::Submitted { job_id, label: wasm_path }).ok();
+        let update = match running_job.wait().await {
+            Ok(output) => SubmitUpdate::Completed { job_id, output: output.to_string() },
+            Err(e) => SubmitUpdate::Failed { job_id: Some(job_id), error: e.to_string() },
+        };
+        updates.send(update).ok();
+    });
+}
+
+/// Replays a job with the module, args and stdin it ran, in the given namespace, on any worker, reporting progress on updates
+/// like spawn. The replay only runs on a worker that still has the module compiled, and only
+/// if the orchestrator still keeps any stdin the job read, since the TUI has neither to
+/// upload. Runs in the background.
+pub fn spawn_replay(addr: SocketAddr, password: Option<String>, replay_of: JobId, namespace: Option<String>, updates: mpsc::UnboundedSender<SubmitUpdate>) {
+    let addr = loopback(addr);
+    tokio::spawn(async move {
+        let mut client = match Client::connect(&format!("http://{addr}"), password, false).await {
+            Ok(client) => client,
+            Err(e) => {
+                updates.send(SubmitUpdate::Failed { job_id: None, error: e.to_string() }).ok();
+                return;
+            }
+        };
+        if let Some(namespace) = namespace {
+            client = client.with_namespace(namespace);
+        }
+        let submission = match client.job_submission(replay_of).await {
+            Ok(submission) => submission,
+            Err(e) => {
+                updates.send(SubmitUpdate::Failed { job_id: None, error: format!("can't replay job {replay_of}: {e}") }).ok();
+                return;
+            }
+        };
+
+        let job = match submission.replay(None) {
+            Ok(job) => job,
+            Err(e) => {
+                updates.send(SubmitUpdate::Failed { job_id: None, error: format!("can't replay job {replay_of}: {e}, replay it with cli replay --stdin") }).ok();
+                return;
+            }
+        };
+
+        let running_job = client.submit_job(job);
+        let job_id = running_job.job_id();
+        updates.send(SubmitUpdate::Submitted { job_id, label: format!("a replay of job {replay_of}") }).ok();
+        let update = match running_job.wait().await {
+            Ok(output) => SubmitUpdate::Completed { job_id, output: output.to_string() },
+            Err(JobError::ModuleNotCached) => SubmitUpdate::Failed {
+                job_id: Some(job_id),
+                error: "no worker has the module cached any more, replay it with cli replay --wasm".to_string(),
+            },
+            Err(e) => SubmitUpdate::Failed { job_id: Some(job_id), error: e.to_string() },
+        };
+        updates.send(update).ok();
+    });
+}
+
+/// The orchestrator may listen on all interfaces, but can always be reached on loopback.
+fn loopback(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(if addr.is_ipv4() { [127, 0, 0, 1].into() } else { std::net::Ipv6Addr::LOCALHOST.into() });
+    }
+    addr
+}
@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use shared::{JobId, JobUsage, UsageDay};
+
+use crate::journal::sync_parent_dir;
+use crate::namespaces::Namespace;
+use crate::orchestrator::Orchestrator;
+
+/// Days of usage kept per namespace, enough to bill this month and the last.
+const RETAINED_DAYS: usize = 62;
+/// How often usage is saved to the usage file, if there is one.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// namespace -> UTC date (YYYY-MM-DD) -> usage
+type Namespaces = BTreeMap<String, BTreeMap<String, UsageBucket>>;
+
+/// Running totals of what each namespace's jobs used and cost, per UTC day, for chargeback.
+/// Jobs are counted on the day they finished.
+///
+/// If loaded from a file, changes are saved back to it by save.
+#[derive(Debug, Clone, Default)]
+pub struct UsageLedger {
+    path: Option<PathBuf>,
+    namespaces: Namespaces,
+    unsaved: bool,
+}
+
+/// What a namespace's jobs used over a day, as saved to the usage file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageBucket {
+    pub jobs: u64,
+    pub execute_ms: u64,
+    pub compiles: u64,
+    pub module_bytes: u64,
+    pub cost: f64,
+}
+
+impl UsageBucket {
+    fn add(&mut self, other: &UsageBucket) {
+        self.jobs += other.jobs;
+        self.execute_ms += other.execute_ms;
+        self.compiles += other.compiles;
+        self.module_bytes += other.module_bytes;
+        self.cost += other.cost;
+    }
+
+    pub fn to_proto(self, date: String) -> UsageDay {
+        UsageDay {
+            date,
+            jobs: self.jobs,
+            execute_ms: self.execute_ms,
+            compiles: self.compiles,
+            module_bytes: self.module_bytes,
+            cost: self.cost,
+        }
+    }
+}
+
+impl UsageLedger {
+    /// Loads the usage saved in the file, or starts empty if it doesn't exist yet. Changes are
+    /// saved back to it.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let namespaces = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path: Some(path), namespaces, unsaved: false })
+    }
+
+    /// Adds a finished job's usage and cost to its namespace's bucket for the day.
+    pub fn record(&mut self, namespace: &Namespace, usage: &JobUsage, cost: f64, at: SystemTime) {
+        let days = self.namespaces.entry(namespace.to_string()).or_default();
+        days.entry(utc_date(at)).or_default().add(&UsageBucket {
+            jobs: 1,
+            execute_ms: usage.execute_ms,
+            compiles: usage.compiled as u64,
+            module_bytes: usage.module_bytes,
+            cost,
+        });
+        while days.len() > RETAINED_DAYS {
+            days.pop_first();
+        }
+        self.unsaved = true;
+    }
+
+    /// The namespace's usage on each of the last `days` UTC days up to now, oldest first.
+    /// Days without jobs are left out.
+    pub fn days(&self, namespace: &Namespace, days: u32, now: SystemTime) -> Vec<(String, UsageBucket)> {
+        let first_day = now.checked_sub(Duration::from_secs(SECS_PER_DAY * days.saturating_sub(1) as u64))
+            .unwrap_or(UNIX_EPOCH);
+        let Some(buckets) = self.namespaces.get(namespace.as_str()) else {
+            return Vec::new();
+        };
+        buckets.range(utc_date(first_day)..=utc_date(now))
+            .map(|(date, bucket)| (date.clone(), *bucket))
+            .collect()
+    }
+
+    /// Saves the usage to its file, if it was loaded from one and changed since last saved.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.unsaved {
+            save(path, &self.namespaces)?;
+            self.unsaved = false;
+        }
+        Ok(())
+    }
+
+    fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+}
+
+/// Sums usage buckets, e.g. over a billing period.
+pub fn total<'a>(buckets: impl IntoIterator<Item = &'a UsageBucket>) -> UsageBucket {
+    let mut total = UsageBucket::default();
+    for bucket in buckets {
+        total.add(bucket);
+    }
+    total
+}
+
+/// The UTC calendar date of t, as YYYY-MM-DD.
+fn utc_date(t: SystemTime) -> String {
+    // Days since 1970-01-01 to a civil date, from Howard Hinnant's date algorithms
+    let days = (t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / SECS_PER_DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+fn save(path: &Path, namespaces: &Namespaces) -> io::Result<()> {
+    let tmp_path = path.with_extension("saving");
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(&serde_json::to_vec_pretty(namespaces)?)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    sync_parent_dir(path)
+}
+
+impl Orchestrator {
+    /// Charges a finished job's usage to its namespace, priced with the cluster's cost weights.
+    pub(crate) async fn record_usage(&self, job_id: JobId, usage: &JobUsage) {
+        let Some(namespace) = self.diagnostics.jobs.get(&job_id).map(|j| j.namespace.clone()) else {
+            tracing::warn!(job_id = %job_id, "job not found in diagnostics store while recording its usage");
+            return;
+        };
+        let cost = self.cost_weights.estimate(usage);
+        self.usage_ledger.lock().await.record(&namespace, usage, cost, SystemTime::now());
+    }
+
+    /// Saves usage to the usage file, if there is one, logging any failure.
+    pub async fn save_usage(&self) {
+        if let Err(e) = self.usage_ledger.lock().await.save() {
+            tracing::warn!(error = %e, "failed to save usage");
+        }
+    }
+
+    /// Runs forever, saving usage every few seconds so a restart loses at most the last few.
+    /// Returns straight away if usage isn't saved to a file.
+    pub(crate) async fn run_usage_saver(self) {
+        if !self.usage_ledger.lock().await.is_persistent() {
+            return;
+        }
+        loop {
+            tokio::time::sleep(SAVE_INTERVAL).await;
+            self.save_usage().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn usage(execute_ms: u64) -> JobUsage {
+        JobUsage { execute_ms, compiled: true, module_bytes: 1024 }
+    }
+
+    /// 2024-02-29T00:00:00Z
+    const LEAP_DAY: u64 = 1_709_164_800;
+
+    #[test]
+    fn utc_dates() {
+        assert_eq!(utc_date(UNIX_EPOCH), "1970-01-01");
+        assert_eq!(utc_date(at(LEAP_DAY - 1)), "2024-02-28");
+        assert_eq!(utc_date(at(LEAP_DAY)), "2024-02-29");
+        assert_eq!(utc_date(at(LEAP_DAY + SECS_PER_DAY)), "2024-03-01");
+        assert_eq!(utc_date(at(1_735_689_599)), "2024-12-31");
+        assert_eq!(utc_date(at(1_735_689_600)), "2025-01-01");
+    }
+
+    #[test]
+    fn jobs_are_summed_into_their_day() {
+        let mut ledger = UsageLedger::default();
+        let namespace = Namespace::default();
+        ledger.record(&namespace, &usage(100), 1.5, at(LEAP_DAY + 60));
+        ledger.record(&namespace, &usage(200), 2.25, at(LEAP_DAY + SECS_PER_DAY - 1));
+
+        let days = ledger.days(&namespace, 1, at(LEAP_DAY + 3600));
+        let expected = UsageBucket { jobs: 2, execute_ms: 300, compiles: 2, module_bytes: 2048, cost: 3.75 };
+        assert_eq!(days, [("2024-02-29".to_string(), expected)]);
+        assert!(ledger.days(&Namespace::new("other").unwrap(), 1, at(LEAP_DAY)).is_empty());
+    }
+
+    #[test]
+    fn buckets_roll_over_at_utc_midnight() {
+        let mut ledger = UsageLedger::default();
+        let namespace = Namespace::default();
+        ledger.record(&namespace, &usage(100), 1.0, at(LEAP_DAY - 1));
+        ledger.record(&namespace, &usage(200), 2.0, at(LEAP_DAY));
+
+        let days = ledger.days(&namespace, 2, at(LEAP_DAY));
+        let dates: Vec<&str> = days.iter().map(|(date, _)| date.as_str()).collect();
+        assert_eq!(dates, ["2024-02-28", "2024-02-29"]);
+        assert_eq!((days[0].1.execute_ms, days[1].1.execute_ms), (100, 200));
+        // A window of one day only covers today
+        assert_eq!(ledger.days(&namespace, 1, at(LEAP_DAY)).len(), 1);
+        assert_eq!(total(days.iter().map(|(_, bucket)| bucket)).cost, 3.0);
+    }
+
+    #[test]
+    fn old_days_are_dropped() {
+        let mut ledger = UsageLedger::default();
+        let namespace = Namespace::default();
+        for day in 0..RETAINED_DAYS as u64 + 5 {
+            ledger.record(&namespace, &usage(1), 1.0, at(LEAP_DAY + day * SECS_PER_DAY));
+        }
+        let now = at(LEAP_DAY + (RETAINED_DAYS as u64 + 4) * SECS_PER_DAY);
+        let days = ledger.days(&namespace, 1000, now);
+        assert_eq!(days.len(), RETAINED_DAYS);
+        assert_eq!(days[0].0, utc_date(at(LEAP_DAY + 5 * SECS_PER_DAY)));
+    }
+
+    #[test]
+    fn usage_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("mini-lambda-usage-{}.json", JobId::random()));
+        let namespace = Namespace::default();
+        let mut ledger = UsageLedger::load(path.clone()).unwrap();
+        ledger.record(&namespace, &usage(100), 1.0, at(LEAP_DAY));
+        ledger.save().unwrap();
+
+        let reloaded = UsageLedger::load(path.clone()).unwrap();
+        assert_eq!(reloaded.days(&namespace, 1, at(LEAP_DAY)), ledger.days(&namespace, 1, at(LEAP_DAY)));
+        fs::remove_file(path).unwrap();
+    }
+}
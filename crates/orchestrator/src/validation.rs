@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use tonic::Code;
+
+use shared::HealthRequest;
+
+use crate::orchestrator::Orchestrator;
+use crate::warm::connect_executor;
+
+/// Wait before probing a Worker again after its first failed probe, doubled after each
+/// failure up to MAX_PROBE_BACKOFF.
+const INITIAL_PROBE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_PROBE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How newly registered Workers are checked before they receive jobs.
+#[derive(Debug, Clone)]
+pub struct ValidationPolicy {
+    /// How long a Worker has to answer each health probe.
+    pub probe_timeout: Duration,
+    /// How long after registering a Worker's failed probes don't count against it, so
+    /// that Workers still starting up aren't quarantined.
+    pub grace: Duration,
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        Self { probe_timeout: Duration::from_secs(2), grace: Duration::from_secs(30) }
+    }
+}
+
+impl Orchestrator {
+    /// Probes a newly registered Worker, which is held from dispatch, until it answers, then
+    /// makes it eligible for dispatch. Failed probes are retried with exponential backoff.
+    /// Once the grace period has passed they count against the Worker's host like disconnects
+    /// do, quarantining it if it keeps failing them. Gives up once the Worker disconnects.
+    pub(crate) async fn validate_worker(self, worker_address: String, policy: ValidationPolicy) {
+        let registered_at = Instant::now();
+        let mut backoff = INITIAL_PROBE_BACKOFF;
+        loop {
+            if !self.worker_streams.lock().await.contains_key(&worker_address) {
+                tracing::debug!(worker = %worker_address, "worker disconnected before it was validated");
+                return;
+            }
+            let result = tokio::time::timeout(policy.probe_timeout, probe_worker(&worker_address)).await
+                .unwrap_or_else(|_| Err(format!("timed out after {}s", policy.probe_timeout.as_secs_f64())));
+            let Err(reason) = result else {
+                break;
+            };
+            self.diagnostics.handle_worker_probe_failed(&worker_address);
+            if registered_at.elapsed() < policy.grace {
+                tracing::debug!(worker = %worker_address, reason = %reason, retry_in = ?backoff, "worker health probe failed within its grace period");
+            } else {
+                tracing::warn!(worker = %worker_address, reason = %reason, retry_in = ?backoff, "worker health probe failed");
+                let quarantine = self.flap_detector.lock().await.record_failed_probe(&worker_address, SystemTime::now());
+                if let Some(until) = quarantine
+                    && self.registry.lock().await.quarantine_worker(&worker_address)
+                {
+                    tracing::warn!(worker = %worker_address, quarantine = ?until.duration_since(SystemTime::now()).unwrap_or_default(), "worker keeps failing health probes, quarantining it");
+                    self.diagnostics.handle_worker_quarantined(&worker_address, until);
+                    tokio::spawn(self.clone().release_quarantine_at(worker_address.clone(), until));
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_PROBE_BACKOFF);
+        }
+
+        self.flap_detector.lock().await.record_healthy_probe(&worker_address);
+        let mut queue = self.job_queue.lock().await;
+        let mut registry = self.registry.lock().await;
+        registry.release_validation(&worker_address);
+        self.diagnostics.handle_worker_validated(&worker_address);
+        tracing::info!(worker = %worker_address, "worker validated");
+        Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
+    }
+}
+
+/// Calls the Worker's Health endpoint at the address it registered with. Workers that predate
+/// it still count as healthy, since they answered.
+async fn probe_worker(address: &str) -> Result<(), String> {
+    let mut executor_client = connect_executor(address).await?;
+    match executor_client.health(HealthRequest {}).await {
+        Ok(_) => Ok(()),
+        Err(status) if status.code() == Code::Unimplemented => Ok(()),
+        Err(status) => Err(status.message().to_string()),
+    }
+}
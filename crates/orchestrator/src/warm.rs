@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use tonic::Request;
+use tonic::transport::Channel;
+
+use shared::compat::PROTOCOL_VERSION;
+use shared::executor_client::ExecutorClient;
+use shared::{JobId, ModuleSource, WarmModuleRequest, WarmOutcome, WorkerWarmResult, relayed_call, relayed_reply};
+
+use crate::diagnostics::WorkerHealth;
+use crate::namespaces::Namespace;
+use crate::orchestrator::Orchestrator;
+use crate::relay::mismatched_reply;
+use crate::worker_api::job_token;
+
+/// Most workers sent a module to warm at once.
+const WARM_CONCURRENCY: usize = 8;
+pub(crate) const DEFAULT_WARM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A module to compile on workers ahead of its jobs.
+#[derive(Debug, Clone)]
+pub(crate) struct WarmModule {
+    pub wasm_bytes: Vec<u8>,
+    pub module_source: Option<ModuleSource>,
+}
+
+impl Orchestrator {
+    /// Has every connected worker that may run the namespace's jobs compile the module, or
+    /// only the given workers if any are named, and reports how each went. Drained, quarantined
+    /// and validating workers are skipped. Each worker gets at most timeout, so a hung worker
+    /// only fails its own result.
+    pub(crate) async fn warm_workers(&self, module: WarmModule, namespace: &Namespace, worker_addresses: &[String], timeout: Duration) -> Vec<WorkerWarmResult> {
+        let mut targets: Vec<(String, WorkerHealth)> = self.diagnostics.workers.iter()
+            .filter(|w| w.health() != WorkerHealth::Offline && namespace.may_run_on(w.namespace.as_ref()))
+            .filter(|w| worker_addresses.is_empty() || worker_addresses.contains(&w.address))
+            .map(|w| (w.address.clone(), w.health()))
+            .collect();
+        targets.sort();
+        tracing::info!(namespace = %namespace, workers = targets.len(), "warming module on workers");
+
+        let mut results: Vec<WorkerWarmResult> = futures::stream::iter(targets)
+            .map(|(address, health)| {
+                let module = module.clone();
+                async move {
+                    let skipped = match health {
+                        WorkerHealth::Quarantined => Some("quarantined"),
+                        WorkerHealth::Drained => Some("drained"),
+                        WorkerHealth::Validating => Some("validating"),
+                        WorkerHealth::Online | WorkerHealth::Offline => None,
+                    };
+                    if let Some(reason) = skipped {
+                        return warm_result(address, WarmOutcome::Skipped, Some(reason.to_string()), 0);
+                    }
+                    let result = tokio::time::timeout(timeout, self.warm_worker(&address, module)).await
+                        .unwrap_or_else(|_| Err(format!("timed out after {}s", timeout.as_secs_f64())));
+                    match result {
+                        Ok((true, _)) => warm_result(address, WarmOutcome::Cached, None, 0),
+                        Ok((false, compile_ms)) => warm_result(address, WarmOutcome::Compiled, None, compile_ms),
+                        Err(reason) => {
+                            tracing::warn!(worker = %address, reason = %reason, "failed to warm module");
+                            warm_result(address, WarmOutcome::Failed, Some(reason), 0)
+                        },
+                    }
+                }
+            })
+            .buffer_unordered(WARM_CONCURRENCY)
+            .collect()
+            .await;
+        results.sort_by(|a, b| a.worker_address.cmp(&b.worker_address));
+        results
+    }
+
+    /// Sends the module to one worker, returning whether it was already cached and how long
+    /// compiling it took. Relayed workers get it over their stream.
+    async fn warm_worker(&self, address: &str, module: WarmModule) -> Result<(bool, u64), String> {
+        let warm_id = JobId::random();
+        let warm_request = WarmModuleRequest {
+            warm_id: warm_id.to_bytes(),
+            wasm_bytes: module.wasm_bytes,
+            module_source: module.module_source,
+            protocol_version: PROTOCOL_VERSION,
+        };
+        let jwt_token = job_token(warm_id, &self.jwt_secret);
+
+        if self.relayed_workers.lock().await.contains(address) {
+            let reply = self.relay_call(address, jwt_token, relayed_call::Call::WarmModule(warm_request)).await
+                .map_err(|e| e.message().to_string())?;
+            let relayed_reply::Reply::WarmModule(response) = reply else {
+                return Err(mismatched_reply().message().to_string());
+            };
+            return Ok((response.cached, response.compile_ms));
+        }
+
+        let mut executor_client = connect_executor(address).await?;
+
+        let mut request = Request::new(warm_request);
+        let jwt_token = jwt_token.parse()
+            .map_err(|e| format!("malformed jwt: {e}"))?;
+        request.metadata_mut().insert("authorization", jwt_token);
+
+        let response = executor_client.warm_module(request).await
+            .map_err(|e| e.message().to_string())?
+            .into_inner();
+        Ok((response.cached, response.compile_ms))
+    }
+}
+
+/// Connects to a worker's executor server, for calls made on a client's behalf.
+pub(crate) async fn connect_executor(address: &str) -> Result<ExecutorClient<Channel>, String> {
+    let channel = Channel::from_shared(format!("http://{address}"))
+        .map_err(|e| format!("malformed worker address: {e}"))?
+        .connect()
+        .await
+        .map_err(|e| format!("failed to connect: {e}"))?;
+    Ok(ExecutorClient::new(channel))
+}
+
+fn warm_result(worker_address: String, outcome: WarmOutcome, reason: Option<String>, compile_ms: u64) -> WorkerWarmResult {
+    WorkerWarmResult { worker_address, outcome: outcome.into(), reason, compile_ms }
+}
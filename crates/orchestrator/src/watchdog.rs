@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use hashlink::LinkedHashMap;
+
+use shared::{JobId, JobState, JobUpdate};
+use shared::events::{JobEvent, epoch_ms};
+
+use crate::journal::JournalEntry;
+use crate::orchestrator::Orchestrator;
+
+/// Most jobs presumed lost that are remembered, to tell their late updates apart.
+const MAX_LOST_JOBS: usize = 4096;
+
+/// Dispatched jobs with a timeout that the Orchestrator expects their Workers to finish, and
+/// those it gave up on. A job not finished within its timeout and a grace period is presumed
+/// lost, whatever its Worker says, so that a Worker that never reports a job finished can't
+/// hold its credit and its client's quota forever.
+#[derive(Debug, Default)]
+pub struct RuntimeWatchdog {
+    watched: HashMap<JobId, Watched>,
+    next_watch: u64,
+    /// The Worker each job was presumed lost on, so that its late updates aren't taken for
+    /// those of the job's retry, least recently lost first.
+    lost: LinkedHashMap<JobId, String>,
+    /// Credits taken back from each Worker for jobs lost on it, which it still returns if it
+    /// ever finishes them.
+    reclaimed: HashMap<String, u32>,
+}
+
+/// A dispatched job being watched, see Orchestrator::watch_runtime.
+#[derive(Debug)]
+struct Watched {
+    /// Tells this dispatch of the job from later ones, as retries keep the job's id.
+    watch: u64,
+    worker_address: String,
+    /// Whether the Worker reported the job started, and so holds a credit for it.
+    started: bool,
+}
+
+impl RuntimeWatchdog {
+    /// Watches a job just dispatched to a Worker, returning the watch to presume it lost by.
+    fn watch(&mut self, job_id: JobId, worker_address: &str) -> u64 {
+        // A retry on the Worker the job was lost on can't be told apart from the lost run
+        if self.is_lost_on(job_id, worker_address) {
+            self.lost.remove(&job_id);
+        }
+        self.next_watch += 1;
+        self.watched.insert(job_id, Watched { watch: self.next_watch, worker_address: worker_address.to_string(), started: false });
+        self.next_watch
+    }
+
+    /// Stops watching a job its client submitted again, as its previous dispatch is over.
+    pub fn resubmitted(&mut self, job_id: JobId) {
+        self.watched.remove(&job_id);
+    }
+
+    /// Notes a job update from a Worker, which stops the job being watched once it finishes.
+    pub fn job_updated(&mut self, job_id: JobId, worker_address: &str, state: JobState) {
+        let Some(watched) = self.watched.get_mut(&job_id) else {
+            return;
+        };
+        if watched.worker_address != worker_address {
+            return;
+        }
+        match state {
+            JobState::Compiling | JobState::Executing => watched.started = true,
+            JobState::Completed | JobState::Failed | JobState::Cancelled => {
+                self.watched.remove(&job_id);
+            },
+            JobState::Unspecified => {},
+        }
+    }
+
+    /// Whether the job was presumed lost on the Worker, so that its updates from it are late.
+    pub fn is_lost_on(&self, job_id: JobId, worker_address: &str) -> bool {
+        self.lost.get(&job_id).is_some_and(|lost_on| lost_on == worker_address)
+    }
+
+    /// Forgets a lost job once its Worker reports it finished after all.
+    pub fn finished_late(&mut self, job_id: JobId) {
+        self.lost.remove(&job_id);
+    }
+
+    /// Gives up on a job still unfinished under the watch, remembering it as lost on its
+    /// Worker and taking back its credit if the Worker holds one. Returns the Worker and
+    /// whether it started the job, or None if the job finished or was dispatched again.
+    fn presume_lost(&mut self, job_id: JobId, watch: u64) -> Option<(String, bool)> {
+        if self.watched.get(&job_id).is_none_or(|watched| watched.watch != watch) {
+            return None;
+        }
+        let Watched { worker_address, started, .. } = self.watched.remove(&job_id)?;
+        if started {
+            *self.reclaimed.entry(worker_address.clone()).or_default() += 1;
+        }
+        self.lost.insert(job_id, worker_address.clone());
+        while self.lost.len() > MAX_LOST_JOBS {
+            self.lost.pop_front();
+        }
+        Some((worker_address, started))
+    }
+
+    /// Takes the credits a Worker returns for jobs lost on it out of a credit update, as they
+    /// were already taken back. Returns what is left of the update.
+    pub fn absorb_reclaimed(&mut self, worker_address: &str, delta: u32) -> u32 {
+        let Some(reclaimed) = self.reclaimed.get_mut(worker_address) else {
+            return delta;
+        };
+        let absorbed = delta.min(*reclaimed);
+        *reclaimed -= absorbed;
+        if *reclaimed == 0 {
+            self.reclaimed.remove(worker_address);
+        }
+        delta - absorbed
+    }
+
+    /// Forgets the jobs dispatched to a Worker that disconnected, whose clients see them fail.
+    pub fn worker_disconnected(&mut self, worker_address: &str) {
+        self.watched.retain(|_, watched| watched.worker_address != worker_address);
+        self.reclaimed.remove(worker_address);
+    }
+}
+
+impl Orchestrator {
+    /// Watches a job just dispatched with a timeout, presuming it lost if its Worker hasn't
+    /// reported it finished by the end of its timeout and the lost job grace period. No-op
+    /// unless the grace period is set.
+    pub(crate) async fn watch_runtime(&self, job_id: JobId, worker_address: &str, timeout: Duration) {
+        let Some(grace) = self.lost_job_grace else {
+            return;
+        };
+        let watch = self.runtime_watchdog.lock().await.watch(job_id, worker_address);
+        let orchestrator = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout + grace).await;
+            orchestrator.presume_lost(job_id, watch).await;
+        });
+    }
+
+    /// Marks a job that outran its timeout and grace period as presumed lost, if it is still
+    /// unfinished, and releases its client's quota. If its Worker started it, the Worker's
+    /// credit is taken back and the job is counted against the Worker, quarantining it if it
+    /// has lost too many lately.
+    async fn presume_lost(&self, job_id: JobId, watch: u64) {
+        let Some((worker_address, started)) = self.runtime_watchdog.lock().await.presume_lost(job_id, watch) else {
+            return;
+        };
+        tracing::warn!(job_id = %job_id, worker = %worker_address, started, "job ran past its timeout without finishing, presuming it lost");
+
+        if started {
+            let quarantine = self.flap_detector.lock().await.record_lost_job(&worker_address, SystemTime::now());
+            self.diagnostics.handle_worker_lost_job(&worker_address);
+            let mut queue = self.job_queue.lock().await;
+            let mut registry = self.registry.lock().await;
+            registry.update_credits(&worker_address, 1);
+            if let Some(until) = quarantine
+                && registry.quarantine_worker(&worker_address)
+            {
+                tracing::warn!(worker = %worker_address, quarantine = ?until.duration_since(SystemTime::now()).unwrap_or_default(), "worker keeps losing jobs, quarantining it");
+                self.diagnostics.handle_worker_quarantined(&worker_address, until);
+                tokio::spawn(self.clone().release_quarantine_at(worker_address.clone(), until));
+            }
+            Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
+        } else if self.coordinate_uploads.is_some() {
+            // The job may never have reached the Worker, so let another job upload its module
+            self.module_uploads.lock().await.stopped(job_id, false);
+        }
+
+        self.diagnostics.handle_job_presumed_lost(job_id);
+        self.coalescing.lock().await.stopped(job_id);
+        let event = JobEvent::PresumedLost { job_id, worker_address: worker_address.clone(), at_ms: epoch_ms(SystemTime::now()) };
+        self.record(JournalEntry::new(event.clone())).await;
+        self.quota_tracker.lock().await.release(&job_id);
+        self.webhooks.notify_completion(event, Some(worker_address));
+    }
+
+    /// Records how a Worker finished a job presumed lost on it after all. The job's state is
+    /// left alone, as it may belong to the job's retry by now; updates before the job finished
+    /// are dropped.
+    pub(crate) async fn handle_late_job_update(&self, worker_address: &str, job_id: JobId, job_update: &JobUpdate) {
+        if !matches!(job_update.state(), JobState::Completed | JobState::Failed | JobState::Cancelled) {
+            return;
+        }
+        tracing::warn!(job_id = %job_id, worker = %worker_address, state = ?job_update.state(), "worker finished a job presumed lost on it, recording it as late");
+        self.runtime_watchdog.lock().await.finished_late(job_id);
+        self.diagnostics.handle_late_job_update(job_id, job_update);
+    }
+}
@@ -0,0 +1,332 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::Url;
+use reqwest::redirect::Policy;
+use serde::Serialize;
+use sha2::Sha256;
+use shared::JobId;
+use shared::events::JobEvent;
+
+use crate::errors::OrchestratorError;
+
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a callback is kept for a job that never reports finishing, e.g. because its client
+/// never sent it to its Worker.
+const CALLBACK_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Header carrying the hex HMAC-SHA256 of the request body, keyed by the webhook secret.
+pub const SIGNATURE_HEADER: &str = "x-mini-lambda-signature";
+
+/// JSON payload POSTed to a job's callback URL once it reaches a terminal state.
+/// The top-level fields predate `event`, the terminal JobEvent, and are kept for existing
+/// receivers.
+#[derive(Debug, Serialize)]
+pub struct JobCompletion {
+    pub job_id: JobId,
+    pub state: &'static str,
+    pub worker_address: Option<String>,
+    pub completed_at_ms: u64,
+    pub event: JobEvent,
+}
+
+/// Delivers job completion callbacks for jobs submitted with a callback URL.
+/// Delivery is best-effort: failures are retried, then logged and counted, and never
+/// affect the job itself.
+///
+/// Callbacks may only reach public addresses, unless their host is one of the allowed hosts,
+/// so that clients can't have the orchestrator call into internal services. The host is
+/// resolved and checked before each delivery, which then goes to the checked address, and
+/// redirects aren't followed.
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    secret: Option<String>,
+    allowed_hosts: Option<Vec<String>>,
+    callbacks: Arc<DashMap<JobId, Callback>>,
+    pub failed_deliveries: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+struct Callback {
+    url: Url,
+    /// The Worker the job was dispatched to, once it was.
+    worker_address: Option<String>,
+    registered_at: Instant,
+}
+
+impl WebhookNotifier {
+    /// Create a new WebhookNotifier. If allowed_hosts is set, callback URLs must point at one
+    /// of those hosts.
+    pub fn new(secret: Option<String>, allowed_hosts: Option<Vec<String>>) -> Self {
+        Self {
+            secret,
+            allowed_hosts,
+            callbacks: Arc::new(DashMap::new()),
+            failed_deliveries: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Validates a callback URL and registers it for the given job.
+    /// Only http and https URLs are accepted, restricted to the allowed hosts if configured.
+    /// An address in the URL must be public unless it is an allowed host.
+    pub fn register(&self, job_id: JobId, callback_url: &str) -> Result<(), OrchestratorError> {
+        let url = Url::parse(callback_url)
+            .map_err(|e| OrchestratorError::InvalidCallbackUrl(e.to_string()))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(OrchestratorError::InvalidCallbackUrl(format!("unsupported scheme '{}'", url.scheme())));
+        }
+        let host = url.host_str()
+            .ok_or_else(|| OrchestratorError::InvalidCallbackUrl("missing host".into()))?;
+        if let Some(allowed) = &self.allowed_hosts && !allowed.iter().any(|h| h == host) {
+            return Err(OrchestratorError::InvalidCallbackUrl(format!("host '{host}' is not allowed")));
+        }
+        let ip = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>();
+        if let Ok(ip) = ip && !self.reachable(host, ip) {
+            return Err(OrchestratorError::InvalidCallbackUrl(format!("address {ip} is not public")));
+        }
+        self.callbacks.insert(job_id, Callback { url, worker_address: None, registered_at: Instant::now() });
+        Ok(())
+    }
+
+    /// Whether a callback to host may be sent to ip.
+    fn reachable(&self, host: &str, ip: IpAddr) -> bool {
+        is_public(ip) || self.allowed_hosts.as_ref().is_some_and(|allowed| allowed.iter().any(|h| h == host))
+    }
+
+    /// Notes the Worker a job with a callback was dispatched to.
+    pub fn dispatched(&self, job_id: JobId, worker_address: &str) {
+        if let Some(mut callback) = self.callbacks.get_mut(&job_id) {
+            callback.worker_address = Some(worker_address.to_string());
+        }
+    }
+
+    /// Drops the callback of a job that stopped without a terminal event, e.g. because its
+    /// client disconnected while it was queued.
+    pub fn forget(&self, job_id: JobId) {
+        self.callbacks.remove(&job_id);
+    }
+
+    /// Drops the callbacks of jobs dispatched to a Worker that disconnected, which will never
+    /// report them finished. Their clients register them again if they retry them.
+    pub fn worker_disconnected(&self, worker_address: &str) {
+        self.callbacks.retain(|_, callback| callback.worker_address.as_deref() != Some(worker_address));
+    }
+
+    /// Drops callbacks registered longer than CALLBACK_TTL ago.
+    fn expire(&self, now: Instant) {
+        self.callbacks.retain(|_, callback| now.duration_since(callback.registered_at) < CALLBACK_TTL);
+    }
+
+    /// Drops expired callbacks for as long as the orchestrator runs.
+    pub async fn run_expiry(self) {
+        loop {
+            tokio::time::sleep(EXPIRY_INTERVAL).await;
+            self.expire(Instant::now());
+        }
+    }
+
+    /// Sends the completion callback for the job of a terminal event, if one was registered.
+    /// Fire-and-forget; spawns a delivery task and returns immediately. Each job is notified at
+    /// most once.
+    pub fn notify_completion(&self, event: JobEvent, worker_address: Option<String>) {
+        let Some((_, Callback { url, .. })) = self.callbacks.remove(&event.job_id()) else {
+            return;
+        };
+        let completion = JobCompletion {
+            job_id: event.job_id(),
+            state: event.name(),
+            worker_address,
+            completed_at_ms: event.at_ms(),
+            event,
+        };
+        let notifier = self.clone();
+        tokio::spawn(async move {
+            notifier.deliver(url, completion).await;
+        });
+    }
+
+    /// POSTs the payload, retrying with exponential backoff on errors and non-2xx responses.
+    async fn deliver(&self, url: Url, completion: JobCompletion) {
+        let body = serde_json::to_vec(&completion)
+            .unwrap_or_else(|e| panic!("JobCompletion should always serialize: {e}"));
+        let signature = self.secret.as_ref().map(|secret| sign(secret.as_bytes(), &body));
+
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let http = match self.client_for(&url).await {
+                Ok(http) => http,
+                Err(e) => {
+                    tracing::warn!(job_id = %completion.job_id, url = %url, error = %e, "webhook not sent");
+                    self.failed_deliveries.fetch_add(1, Ordering::Relaxed);
+                    return;
+                },
+            };
+            let mut request = http.post(url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    tracing::debug!(job_id = %completion.job_id, url = %url, "webhook delivered");
+                    return;
+                },
+                Ok(response) => {
+                    tracing::debug!(job_id = %completion.job_id, url = %url, attempt, status = %response.status(), "webhook rejected");
+                },
+                Err(e) => {
+                    tracing::debug!(job_id = %completion.job_id, url = %url, attempt, error = %e, "webhook delivery failed");
+                }
+            }
+
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+
+        tracing::warn!(job_id = %completion.job_id, url = %url, "webhook delivery failed after {MAX_ATTEMPTS} attempts");
+        self.failed_deliveries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An http client that sends requests for url's host to an address it resolves to that the
+    /// callback may reach, so that the host can't resolve elsewhere once checked.
+    async fn client_for(&self, url: &Url) -> Result<reqwest::Client, String> {
+        let host = url.host_str().ok_or("missing host")?;
+        let port = url.port_or_known_default().ok_or("missing port")?;
+        let lookup = host.trim_start_matches('[').trim_end_matches(']');
+        let addresses: Vec<SocketAddr> = tokio::net::lookup_host((lookup, port)).await
+            .map_err(|e| format!("failed to resolve {host}: {e}"))?
+            .collect();
+        let address = addresses.iter()
+            .find(|address| self.reachable(host, address.ip()))
+            .ok_or_else(|| format!("{host} resolves to no public address"))?;
+        reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(Policy::none())
+            .resolve(lookup, *address)
+            .build()
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Whether ip is an address on the public internet, rather than this host, a private network,
+/// or one reserved for another use.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !(ip.is_unspecified() || ip.is_loopback() || ip.is_private() || ip.is_link_local()
+            || ip.is_broadcast() || ip.is_documentation() || ip.is_multicast()
+            // Shared address space, as carrier-grade NAT uses
+            || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => !(ip.is_unspecified() || ip.is_loopback() || ip.is_unique_local() || ip.is_unicast_link_local() || ip.is_multicast()),
+        },
+    }
+}
+
+/// RAII guard that drops a job's callback if its request is dropped (client disconnected)
+/// before the job is dispatched or stops.
+pub struct CallbackGuard {
+    webhooks: WebhookNotifier,
+    job_id: JobId,
+    dispatched: bool,
+}
+
+impl CallbackGuard {
+    /// Creates a guard for a job whose callback was just registered.
+    pub fn new(webhooks: WebhookNotifier, job_id: JobId) -> Self {
+        Self { webhooks, job_id, dispatched: false }
+    }
+
+    pub fn set_dispatched(&mut self) {
+        self.dispatched = true;
+    }
+}
+
+impl Drop for CallbackGuard {
+    /// Forgets the callback unless the job was dispatched, in which case it is sent once the
+    /// worker reports the job finished. A job that stopped while queued was already notified.
+    fn drop(&mut self) {
+        if !self.dispatched {
+            self.webhooks.forget(self.job_id);
+        }
+    }
+}
+
+/// Returns the hex-encoded HMAC-SHA256 of body under the given key.
+fn sign(key: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .unwrap_or_else(|e| panic!("HMAC accepts keys of any length: {e}"));
+    mac.update(body);
+    mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notifier(allowed_hosts: Option<&[&str]>) -> WebhookNotifier {
+        WebhookNotifier::new(None, allowed_hosts.map(|hosts| hosts.iter().map(|h| h.to_string()).collect()))
+    }
+
+    #[test]
+    fn private_addresses_are_refused() {
+        let webhooks = notifier(None);
+        for url in ["http://127.0.0.1/done", "http://10.0.0.1/done", "http://169.254.169.254/latest", "http://[::1]/done", "http://[::ffff:192.168.0.1]/done", "http://100.64.0.1/done"] {
+            let refused = webhooks.register(JobId::random(), url);
+            assert!(matches!(refused, Err(OrchestratorError::InvalidCallbackUrl(_))), "{url} was accepted");
+        }
+        webhooks.register(JobId::random(), "http://93.184.216.34/done").unwrap();
+        webhooks.register(JobId::random(), "https://example.com/done").unwrap();
+    }
+
+    #[test]
+    fn allowed_hosts_may_be_private() {
+        let webhooks = notifier(Some(&["127.0.0.1"]));
+        webhooks.register(JobId::random(), "http://127.0.0.1:8080/done").unwrap();
+        assert!(webhooks.register(JobId::random(), "http://10.0.0.1/done").is_err());
+    }
+
+    #[test]
+    fn callbacks_of_stopped_jobs_are_dropped() {
+        let webhooks = notifier(None);
+        let (queued, lost, running) = (JobId::random(), JobId::random(), JobId::random());
+        for job_id in [queued, lost, running] {
+            webhooks.register(job_id, "https://example.com/done").unwrap();
+        }
+        webhooks.dispatched(lost, "10.0.0.1:7000");
+        webhooks.dispatched(running, "10.0.0.1:7001");
+
+        drop(CallbackGuard::new(webhooks.clone(), queued));
+        webhooks.worker_disconnected("10.0.0.1:7000");
+        assert!(!webhooks.callbacks.contains_key(&queued));
+        assert!(!webhooks.callbacks.contains_key(&lost));
+        assert!(webhooks.callbacks.contains_key(&running));
+
+        let mut guard = CallbackGuard::new(webhooks.clone(), running);
+        guard.set_dispatched();
+        drop(guard);
+        assert!(webhooks.callbacks.contains_key(&running));
+    }
+
+    #[test]
+    fn callbacks_expire() {
+        let webhooks = notifier(None);
+        let job_id = JobId::random();
+        webhooks.register(job_id, "https://example.com/done").unwrap();
+        webhooks.expire(Instant::now() + CALLBACK_TTL / 2);
+        assert!(webhooks.callbacks.contains_key(&job_id));
+        webhooks.expire(Instant::now() + CALLBACK_TTL);
+        assert!(webhooks.callbacks.is_empty());
+    }
+}
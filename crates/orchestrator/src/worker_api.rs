@@ -1,15 +1,45 @@
+use std::time::{Duration, SystemTime};
+
 use jsonwebtoken::{EncodingKey, Header};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::{Request, Status, Response, Streaming};
+use tonic::{Code, Request, Status, Response, Streaming};
 
-use shared::{CreditUpdate, JobClaims, JobUpdate, OrchestratorMessage, RegistrationAck, WorkerMessage, WorkerResponse, orchestrator_message, worker_message};
+use shared::{CreditUpdate, ErrorCode, Heartbeat, JobId, JobClaims, JobState, JobUpdate, OrchestratorMessage, RegistrationAck, Reservation, WorkerMessage, WorkerResponse, orchestrator_message, worker_message};
 use shared::worker_api_server::WorkerApi;
+use shared::events::{JobEvent, epoch_ms};
 
 use crate::job_queue::JobQueue;
+use crate::journal::JournalEntry;
+use crate::module_uploads::ModuleHash;
+use crate::namespaces::Namespace;
 use crate::orchestrator::Orchestrator;
 use crate::registry::WorkerRegistry;
 
+/// How long a Worker that requires reservations holds a credit for a job dispatched to it
+/// before giving up on the job arriving.
+pub(crate) const RESERVATION_TTL: Duration = Duration::from_secs(30);
+
+/// Issues the jwt that authorizes a request to a Worker about the given job.
+pub(crate) fn job_token(job_id: JobId, jwt_secret: &[u8]) -> String {
+    encode_job_claims(&JobClaims::new(job_id), jwt_secret)
+}
+
+/// Like job_token, for a job coalesced with the in-flight job leader, which shares its run.
+pub(crate) fn coalesced_job_token(job_id: JobId, leader: JobId, jwt_secret: &[u8]) -> String {
+    encode_job_claims(&JobClaims::coalesced(job_id, leader), jwt_secret)
+}
+
+fn encode_job_claims(job_claims: &JobClaims, jwt_secret: &[u8]) -> String {
+    let header = Header::default();
+    let key = EncodingKey::from_secret(jwt_secret);
+    jsonwebtoken::encode(&header, job_claims, &key)
+        .unwrap_or_else(|e| {
+            tracing::error!(error = %e, "ERROR: jwt encoding failed, this should not happen");
+            std::process::exit(1);
+        })
+}
+
 /// Implementation of the WorkerApi service for Orchestrator.
 #[tonic::async_trait]
 impl WorkerApi for Orchestrator {
@@ -31,17 +61,31 @@ impl WorkerApi for Orchestrator {
         tokio::spawn(async move {
             tracing::debug!("waiting for worker registration");
             // Expect a registration as the first message
-            let worker_address = match inbound.message().await {
+            let (worker_address, credits) = match inbound.message().await {
                 Ok(Some(WorkerMessage { message: Some(worker_message::Message::Registration(registration)) })) => {
-                    if !orchestrator.handle_worker_registration(tx.clone(), &registration).await {
+                    if let Err(e) = shared::compat::check(registration.protocol_version) {
+                        tracing::warn!(worker = %registration.address, error = %e, "rejecting worker registration");
+                        let _ = tx.send(Err(e.into())).await;
+                        return;
+                    }
+                    let namespace = match registration.namespace.as_deref().map(Namespace::new).transpose() {
+                        Ok(namespace) => namespace,
+                        Err(e) => {
+                            tracing::warn!(worker = %registration.address, error = %e, "rejecting worker registration");
+                            let _ = tx.send(Err(e.into())).await;
+                            return;
+                        }
+                    };
+                    if !orchestrator.handle_worker_registration(tx.clone(), &registration, namespace).await {
                         tracing::warn!(worker = %registration.address, "failed to handle worker registration");
                         return;
                     };
-                    registration.address
+                    (registration.address, registration.credits)
                 },
                 Ok(Some(m)) => {
-                    tracing::error!("ERROR: should always receive registration as first message, got {:?}", m);
-                    std::process::exit(1);
+                    tracing::warn!(message = ?m, "rejecting worker stream that didn't start with a registration");
+                    let _ = tx.send(Err(Status::invalid_argument("the first message must be a registration"))).await;
+                    return;
                 },
                 Ok(None) => {
                     tracing::warn!("worker disconnected before sending registration");
@@ -53,7 +97,6 @@ impl WorkerApi for Orchestrator {
                 }
             };
 
-            orchestrator.diagnostics.handle_worker_connected(&worker_address);
             tracing::info!(worker = %worker_address, "worker registered");
 
             loop {
@@ -64,15 +107,32 @@ impl WorkerApi for Orchestrator {
                                 orchestrator.handle_credit_update(&worker_address, credit_update).await;
                             },
                             Some(worker_message::Message::Registration(_)) => {
-                                tracing::error!(worker = %worker_address, "ERROR: worker sent a second registration after already registering, this should never happen");
-                                std::process::exit(1);
+                                tracing::warn!(worker = %worker_address, "worker sent a second registration, deregistering");
+                                let _ = tx.send(Err(Status::invalid_argument("the worker is already registered"))).await;
+                                break;
                             },
                             Some(worker_message::Message::JobUpdate(job_update)) => {
-                                orchestrator.handle_job_update(&worker_address, &job_update);
-                            }
+                                orchestrator.handle_job_update(&worker_address, &job_update).await;
+                            },
+                            Some(worker_message::Message::PeerListRequest(_)) => {
+                                orchestrator.handle_peer_list_request(&worker_address).await;
+                            },
+                            Some(worker_message::Message::HandoffSummary(summary)) => {
+                                orchestrator.handle_handoff_summary(&worker_address, &summary).await;
+                            },
+                            Some(worker_message::Message::RelayedReply(reply)) => {
+                                if let Err(e) = orchestrator.handle_relayed_reply(&worker_address, reply).await {
+                                    tracing::warn!(worker = %worker_address, error = %e.message(), "worker sent a malformed relayed reply, deregistering");
+                                    let _ = tx.send(Err(e)).await;
+                                    break;
+                                }
+                            },
+                            Some(worker_message::Message::Heartbeat(heartbeat)) => {
+                                orchestrator.handle_heartbeat(&worker_address, heartbeat).await;
+                            },
                             None => {
-                                tracing::error!(worker = %worker_address, "ERROR: worker sent a message with no content, this should never happen");
-                                std::process::exit(1);
+                                // A newer worker's message that this orchestrator doesn't know
+                                tracing::warn!(worker = %worker_address, "ignoring an unrecognized message from the worker");
                             }
                         }
                     },
@@ -86,8 +146,23 @@ impl WorkerApi for Orchestrator {
                     }
                 }
             }
-            orchestrator.diagnostics.handle_worker_disconnected(&worker_address);
             orchestrator.registry.lock().await.deregister_worker(&worker_address);
+            orchestrator.worker_streams.lock().await.remove(&worker_address);
+            orchestrator.reserving_workers.lock().await.remove(&worker_address);
+            orchestrator.module_uploads.lock().await.worker_disconnected(&worker_address);
+            orchestrator.coalescing.lock().await.worker_disconnected(&worker_address);
+            orchestrator.relayed_workers.lock().await.remove(&worker_address);
+            orchestrator.tls_addresses.lock().await.remove(&worker_address);
+            orchestrator.runtime_watchdog.lock().await.worker_disconnected(&worker_address);
+            orchestrator.quota_tracker.lock().await.worker_disconnected(&worker_address);
+            orchestrator.out_of_band.lock().await.worker_disconnected(&worker_address);
+            orchestrator.webhooks.worker_disconnected(&worker_address);
+            if let Some(audit_log) = &orchestrator.audit_log {
+                audit_log.worker_disconnected(&worker_address);
+            }
+            orchestrator.fail_relayed_calls(&worker_address).await;
+            orchestrator.queue_stats.worker_disconnected(credits);
+            orchestrator.handle_worker_disconnect(&worker_address).await;
         });
 
         Ok(Response::new(ReceiverStream::new(rx)))
@@ -97,78 +172,249 @@ impl WorkerApi for Orchestrator {
 
 // Helper methods for the WorkerApi implementation.
 // They may use the outbound tx channel to send messages back to the worker.
-type OutboundTx = mpsc::Sender<Result<OrchestratorMessage, Status>>;
+pub type OutboundTx = mpsc::Sender<Result<OrchestratorMessage, Status>>;
 impl Orchestrator {
     /// Handles an incoming Worker registration message.
-    async fn handle_worker_registration(&self, tx: OutboundTx, registration: &shared::WorkerRegistration) -> bool {
-        tracing::debug!(worker = %registration.address, credits = registration.credits, "handling worker registration");
-        {
-            let mut queue = self.job_queue.lock().await;
-            let mut registry = self.registry.lock().await;
-
-            registry.register_worker(registration.address.to_owned(), registration.credits);
-            Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
-        }
+    /// Flapping workers (or, with --flap-per-host, workers on a flapping host) are registered but quarantined until their cool-down ends.
+    /// With worker validation on, Workers are also held until they answer a health probe.
+    /// A Worker with a namespace only receives that namespace's jobs.
+    /// The registration is acknowledged before any jobs are dispatched to the Worker, so that
+    /// their reservations follow the ack on its stream.
+    async fn handle_worker_registration(&self, tx: OutboundTx, registration: &shared::WorkerRegistration, namespace: Option<Namespace>) -> bool {
+        tracing::debug!(worker = %registration.address, credits = registration.credits, namespace = ?namespace.as_ref().map(Namespace::as_str), require_reservation = registration.require_reservation, relayed = registration.relayed, tls_address = ?registration.tls_address, "handling worker registration");
 
         // Send registration ack back to worker
         let ack = OrchestratorMessage {
             message: Some(orchestrator_message::Message::RegistrationAck(
-                RegistrationAck { jwt_secret: self.jwt_secret.to_vec(), network_access_allowed: self.network_access_allowed }
+                RegistrationAck { jwt_secret: self.jwt_secret.to_vec(), network_access_allowed: self.network_access_allowed, cost_weights: Some(self.cost_weights) }
             ))
         };
         if tx.send(Ok(ack)).await.is_err() {
-            tracing::error!(worker = %registration.address, "failed to send registration ack, deregistering");
-            self.registry.lock().await.deregister_worker(&registration.address);
-            false
-        } else {
-            tracing::info!(worker = %registration.address, "registration ack sent to worker");
-            true
+            tracing::error!(worker = %registration.address, "failed to send registration ack");
+            return false;
+        }
+        tracing::info!(worker = %registration.address, "registration ack sent to worker");
+        self.worker_streams.lock().await.insert(registration.address.to_owned(), tx);
+        if registration.require_reservation {
+            self.reserving_workers.lock().await.insert(registration.address.to_owned());
         }
+        if registration.relayed {
+            self.relayed_workers.lock().await.insert(registration.address.to_owned());
+        }
+        if let Some(tls_address) = &registration.tls_address {
+            self.tls_addresses.lock().await.insert(registration.address.to_owned(), tls_address.clone());
+        }
+        self.queue_stats.worker_connected(registration.credits);
+
+        let validation = self.worker_validation.clone().filter(|_| !registration.relayed);
+        let (flap_count, quarantined_until) = {
+            let mut flap_detector = self.flap_detector.lock().await;
+            let now = SystemTime::now();
+            (flap_detector.flap_count(&registration.address, now), flap_detector.quarantined_until(&registration.address, now))
+        };
+        {
+            let mut queue = self.job_queue.lock().await;
+            let mut registry = self.registry.lock().await;
+
+            if let Some(until) = quarantined_until {
+                tracing::warn!(worker = %registration.address, remaining = ?until.duration_since(SystemTime::now()).unwrap_or_default(), "worker host is quarantined for flapping, withholding jobs");
+                registry.register_quarantined_worker(registration.address.to_owned(), registration.credits, namespace.clone());
+                tokio::spawn(self.clone().release_quarantine_at(registration.address.to_owned(), until));
+            } else {
+                registry.register_worker(registration.address.to_owned(), registration.credits, namespace.clone());
+            }
+            if validation.is_some() {
+                registry.hold_for_validation(&registration.address);
+            } else {
+                Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
+            }
+        }
+        self.diagnostics.handle_worker_connected(&registration.address, registration.credits, registration.capabilities.clone(), namespace, flap_count, quarantined_until);
+        if let Some(policy) = validation {
+            self.diagnostics.handle_worker_validating(&registration.address);
+            tokio::spawn(self.clone().validate_worker(registration.address.to_owned(), policy));
+        }
+        true
+    }
+
+    /// Sends a reservation for a job just dispatched to a Worker, if the Worker requires them,
+    /// so that it holds a credit for the job. Called before the client is told to use the Worker.
+    pub(crate) async fn reserve_worker(&self, job_id: JobId, worker_address: &str) {
+        if !self.reserving_workers.lock().await.contains(worker_address) {
+            return;
+        }
+        let Some(tx) = self.worker_streams.lock().await.get(worker_address).cloned() else {
+            return;
+        };
+        let reservation = Reservation {
+            job_id: job_id.to_bytes(),
+            ttl_ms: RESERVATION_TTL.as_millis() as u64,
+        };
+        let message = OrchestratorMessage { message: Some(orchestrator_message::Message::Reservation(reservation)) };
+        if tx.send(Ok(message)).await.is_err() {
+            tracing::debug!(job_id = %job_id, worker = %worker_address, "worker disconnected before its reservation was sent");
+        }
+    }
+
+    /// Records a Worker disconnect with the flap detector, quarantining its host if it has
+    /// disconnected too often recently.
+    async fn handle_worker_disconnect(&self, worker_address: &str) {
+        let mut flap_detector = self.flap_detector.lock().await;
+        let now = SystemTime::now();
+        if let Some(until) = flap_detector.record_disconnect(worker_address, now) {
+            tracing::warn!(worker = %worker_address, quarantine = ?until.duration_since(now).unwrap_or_default(), "worker host is flapping, quarantining it");
+        }
+        self.diagnostics.handle_worker_disconnected(worker_address, flap_detector.flap_count(worker_address, now));
+    }
+
+    /// Waits until a quarantined Worker's cool-down ends, then makes it eligible for dispatch.
+    pub(crate) async fn release_quarantine_at(self, worker_address: String, until: SystemTime) {
+        tokio::time::sleep(until.duration_since(SystemTime::now()).unwrap_or_default()).await;
+
+        let mut queue = self.job_queue.lock().await;
+        let mut registry = self.registry.lock().await;
+        registry.release_quarantine(&worker_address);
+        self.diagnostics.handle_worker_quarantine_released(&worker_address);
+        tracing::info!(worker = %worker_address, "worker quarantine ended");
+        Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
     }
 
     /// Handles a credit update from a Worker, updating its available credits in the registry
-    /// and dispatching any pending jobs that can now be served.
+    /// and dispatching any pending jobs that can now be served. Credits for jobs presumed lost
+    /// were already taken back, so aren't counted again. Credits taken by jobs another
+    /// Orchestrator dispatched to the Worker are taken from its count.
     async fn handle_credit_update(&self, worker_address: &str, credit_update: CreditUpdate) {
-        tracing::debug!(worker = %worker_address, delta = credit_update.delta, "credit update received");
+        tracing::debug!(worker = %worker_address, delta = credit_update.delta, taken = credit_update.taken, "credit update received");
+        if let Some(host_load) = &credit_update.host_load {
+            self.diagnostics.handle_worker_load(worker_address, host_load);
+        }
+        let delta = self.runtime_watchdog.lock().await.absorb_reclaimed(worker_address, credit_update.delta);
         let mut queue = self.job_queue.lock().await;
         let mut registry = self.registry.lock().await;
 
-        registry.update_credits(worker_address, credit_update.delta);
+        if credit_update.taken > 0 {
+            registry.take_credits(worker_address, credit_update.taken);
+        }
+        registry.update_credits(worker_address, delta);
         Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
     }
 
-    fn handle_job_update(&self, worker_address: &str, job_update: &JobUpdate) {
+    /// Handles a Worker's heartbeat, reconciling the jobs it says it started with those this
+    /// Orchestrator dispatched to it. While the difference shows it busy with other
+    /// Orchestrators' jobs, some of its credits are held back, see OutOfBandTracker. Its
+    /// running jobs are recorded alongside this Orchestrator's estimate of them, so that drift
+    /// between the two shows.
+    async fn handle_heartbeat(&self, worker_address: &str, heartbeat: Heartbeat) {
+        let Some((credits, advertised)) = self.diagnostics.workers.get(worker_address)
+            .map(|w| (w.credits, w.load_credits.unwrap_or(w.credits)))
+        else {
+            return;
+        };
+        let (reconciled, reserve) = self.out_of_band.lock().await.heartbeat(worker_address, heartbeat.jobs_started_total, credits);
+        if reconciled.out_of_band > 0 {
+            tracing::debug!(worker = %worker_address, jobs = reconciled.out_of_band, reserve, "worker started jobs other orchestrators dispatched");
+        }
+        let mut queue = self.job_queue.lock().await;
+        let mut registry = self.registry.lock().await;
+        let estimated_running = registry.available_credits(worker_address)
+            .map(|available| advertised.saturating_sub(available));
+        self.diagnostics.handle_worker_heartbeat(worker_address, &heartbeat, estimated_running, reconciled.out_of_band_total, reserve);
+        if registry.set_reserve(worker_address, reserve) {
+            Self::dispatch_pending_jobs(&mut queue, &mut registry, &self.jwt_secret);
+        }
+    }
+
+    /// Handles a job state update from a Worker. Once the job reaches a terminal state, records
+    /// it in the journal, releases its client quota usage and sends its completion webhook.
+    /// Updates for a job presumed lost on the Worker are only recorded as late.
+    async fn handle_job_update(&self, worker_address: &str, job_update: &JobUpdate) {
+        let Ok(job_id) = JobId::from_bytes(&job_update.job_id) else {
+            tracing::warn!(worker = %worker_address, "ignoring a job update with a malformed job_id");
+            return;
+        };
+        // Includes states sent by a newer worker that this orchestrator doesn't know
+        if job_update.state() == JobState::Unspecified {
+            tracing::warn!(worker = %worker_address, job_id = %job_id, state = job_update.state, "ignoring a job update with an unrecognized state");
+            return;
+        }
+        if self.runtime_watchdog.lock().await.is_lost_on(job_id, worker_address) {
+            self.handle_late_job_update(worker_address, job_id, job_update).await;
+            return;
+        }
         self.diagnostics.handle_worker_job_update(worker_address, job_update);
+
+        self.runtime_watchdog.lock().await.job_updated(job_id, worker_address, job_update.state());
+        if matches!(job_update.state(), JobState::Compiling | JobState::Executing) {
+            self.quota_tracker.lock().await.mark_started(&job_id, worker_address);
+        }
+        if job_update.state() == JobState::Executing {
+            self.out_of_band.lock().await.job_started(worker_address);
+        }
+        if job_update.state() == JobState::Executing
+            && let (Some(audit_log), Some(details)) = (&self.audit_log, &job_update.details)
+        {
+            audit_log.job_started(job_id, details);
+        }
+        if self.coordinate_uploads.is_some() {
+            self.track_module_upload(worker_address, job_id, job_update).await;
+        }
+        let at_ms = epoch_ms(SystemTime::now());
+        let event = match job_update.state() {
+            JobState::Completed => JobEvent::Completed { job_id, at_ms },
+            JobState::Failed => JobEvent::Failed { job_id, at_ms },
+            JobState::Cancelled => JobEvent::Cancelled { job_id, at_ms },
+            _ => return,
+        };
+        self.coalescing.lock().await.stopped(job_id);
+        if let Some(usage) = &job_update.usage {
+            self.record_usage(job_id, usage).await;
+        }
+        self.record(JournalEntry::new(event.clone())).await;
+        let mut quota_tracker = self.quota_tracker.lock().await;
+        if let Some(usage) = &job_update.usage {
+            quota_tracker.record_module_bytes(&job_id, usage.module_bytes, SystemTime::now());
+        }
+        quota_tracker.release(&job_id);
+        drop(quota_tracker);
+        self.webhooks.notify_completion(event, Some(worker_address.to_string()));
+    }
+
+    /// Learns from a job update which Workers have which modules compiled, and how uploads
+    /// other jobs are waiting for end.
+    async fn track_module_upload(&self, worker_address: &str, job_id: JobId, job_update: &JobUpdate) {
+        match job_update.state() {
+            JobState::Executing => {
+                let hash = job_update.details.as_ref()
+                    .and_then(|details| ModuleHash::try_from(details.module_hash.as_slice()).ok());
+                if let Some(hash) = hash {
+                    self.registry.lock().await.record_module(hash, worker_address);
+                    self.module_uploads.lock().await.compiled(hash);
+                }
+            },
+            JobState::Failed => self.module_uploads.lock().await.stopped(job_id, true),
+            JobState::Cancelled => self.module_uploads.lock().await.stopped(job_id, false),
+            _ => {},
+        }
     }
 
     /// Dispatches as many pending jobs as possible to available workers, consuming one registry
-    /// credit per job. Stops when the queue is empty or no credits remain.
+    /// credit per job. Stops when no queued job has a worker with credits that may run it.
     /// The caller must hold write guards on both the queue and registry for the duration.
     pub fn dispatch_pending_jobs(queue: &mut JobQueue, registry: &mut WorkerRegistry, jwt_secret: &[u8]) {
-        while registry.has_available_credits() {
-            match queue.dequeue() {
-                Some((job_id, tx)) => {
-                    let worker_address = registry.get_worker()
-                        .unwrap_or_else(|| {
-                            tracing::error!("ERROR: worker availability in registry should be guaranteed by has_available_credits() above");
-                            std::process::exit(1);
-                        });
-                    let header = Header::default();
-                    let job_claims = JobClaims::new(job_id);
-                    let key = EncodingKey::from_secret(jwt_secret);
-                    let jwt_token = jsonwebtoken::encode(&header, &job_claims, &key)
-                        .unwrap_or_else(|e| {
-                            tracing::error!(error = %e, "ERROR: jwt encoding failed, this should not happen");
-                            std::process::exit(1);
-                        });
-
-                    tracing::debug!(job_id = %job_id, worker = %worker_address, "job dispatched to worker");
-                    if tx.send(WorkerResponse { worker_address: worker_address.clone(), jwt_token }).is_err() {
-                        tracing::debug!(job_id = %job_id, worker = %worker_address, "client disconnected before dispatch, restoring credit");
-                        registry.update_credits(&worker_address, 1);
-                    }
-                },
-                None => break
+        while let Some((job_id, namespace, wasm_hash, tx)) = queue.dequeue(|namespace| registry.has_available_credits(namespace)) {
+            let (worker_address, module_cached) = registry.select_worker(&namespace, wasm_hash.as_ref())
+                .unwrap_or_else(|| {
+                    tracing::error!("ERROR: worker availability in registry should be guaranteed by has_available_credits() in dequeue, this should never happen");
+                    std::process::exit(1);
+                });
+            let jwt_token = job_token(job_id, jwt_secret);
+
+            tracing::debug!(job_id = %job_id, worker = %worker_address, "job dispatched to worker");
+            if tx.send(WorkerResponse { worker_address: worker_address.clone(), jwt_token, module_cached, relayed: false, coalesced_with: None, hash_algorithms: Vec::new(), worker_tls_address: None }).is_err() {
+                tracing::debug!(job_id = %job_id, worker = %worker_address, "client disconnected before dispatch, restoring credit");
+                registry.update_credits(&worker_address, 1);
+            } else {
+                queue.stats().record_dispatch(SystemTime::now());
             }
         }
     }
@@ -183,7 +429,7 @@ pub fn check_worker_auth(orchestrator: Orchestrator) -> impl Fn(Request<()>) ->
             let actual = req.metadata().get("authorization")
                 .and_then(|v| v.to_str().ok());
             if actual != Some(expected) {
-                return Err(Status::unauthenticated("invalid worker password"));
+                return Err(ErrorCode::Unauthorized.status(Code::Unauthenticated, "invalid worker password"));
             }
         }
         Ok(req)
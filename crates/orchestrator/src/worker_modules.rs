@@ -0,0 +1,44 @@
+use tonic::{Request, Status};
+
+use shared::compat::PROTOCOL_VERSION;
+use shared::{JobId, ListModulesRequest, ListModulesResponse, relayed_call, relayed_reply};
+
+use crate::diagnostics::WorkerHealth;
+use crate::errors::OrchestratorError;
+use crate::namespaces::Namespace;
+use crate::orchestrator::Orchestrator;
+use crate::relay::mismatched_reply;
+use crate::warm::connect_executor;
+use crate::worker_api::job_token;
+
+impl Orchestrator {
+    /// Asks a connected worker that may run the namespace's jobs which modules it has compiled,
+    /// or whether it has the one with wasm_hash. Relayed workers are asked over their stream.
+    pub(crate) async fn list_worker_modules(&self, address: &str, namespace: &Namespace, wasm_hash: Option<Vec<u8>>) -> Result<ListModulesResponse, Status> {
+        let known = self.diagnostics.workers.get(address)
+            .is_some_and(|w| w.health() != WorkerHealth::Offline && namespace.may_run_on(w.namespace.as_ref()));
+        if !known {
+            return Err(OrchestratorError::WorkerNotFound(address.to_string()).into());
+        }
+
+        let list_id = JobId::random();
+        let list_request = ListModulesRequest { list_id: list_id.to_bytes(), wasm_hash, protocol_version: PROTOCOL_VERSION };
+        let jwt_token = job_token(list_id, &self.jwt_secret);
+
+        if self.relayed_workers.lock().await.contains(address) {
+            let reply = self.relay_call(address, jwt_token, relayed_call::Call::ListModules(list_request)).await?;
+            let relayed_reply::Reply::ListModules(response) = reply else {
+                return Err(mismatched_reply());
+            };
+            return Ok(response);
+        }
+
+        let mut executor_client = connect_executor(address).await
+            .map_err(Status::unavailable)?;
+        let mut request = Request::new(list_request);
+        let jwt_token = jwt_token.parse()
+            .map_err(|e| Status::internal(format!("malformed jwt: {e}")))?;
+        request.metadata_mut().insert("authorization", jwt_token);
+        Ok(executor_client.list_modules(request).await?.into_inner())
+    }
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use client::{Job, JobId};
+use common::{Cluster, NOOP_WAT, TRAP_WAT, component};
+use orchestrator::{AuditConfig, AuditLog, OrchestratorConfig};
+use serde_json::Value;
+
+fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!("mini-lambda-test-audit-{}.jsonl", JobId::random()))
+}
+
+/// Waits until the audit log holds the given number of lines, and parses them.
+async fn wait_for_lines(path: &Path, count: usize) -> Vec<Value> {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let text = std::fs::read_to_string(path).unwrap_or_default();
+            // Only whole lines count, the writer may be partway through one
+            if text.ends_with('\n') && text.lines().count() >= count {
+                return text.lines()
+                    .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("malformed line {line:?}: {e}")))
+                    .collect();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.unwrap_or_else(|_| panic!("the audit log never held {count} lines"))
+}
+
+#[tokio::test]
+async fn finished_jobs_are_audited_in_order() {
+    let path = temp_path();
+    let audit_log = AuditLog::open(AuditConfig { path: path.clone(), max_bytes: u64::MAX, include_args: true }).unwrap();
+    let cluster = Cluster::start(OrchestratorConfig { audit_log: Some(audit_log), ..Default::default() }, 1).await;
+    let client = cluster.client().await;
+
+    let mut expected = Vec::new();
+    for (wat, state) in [(NOOP_WAT, "completed"), (TRAP_WAT, "failed"), (NOOP_WAT, "completed")] {
+        let job = client.submit_job(Job::from_bytes(component(wat)).args(["a", "b"]).max_retries(0));
+        expected.push((job.job_id().to_string(), state));
+        let _ = job.wait().await;
+    }
+
+    let lines = wait_for_lines(&path, expected.len()).await;
+    assert_eq!(lines.len(), expected.len(), "{lines:?}");
+    let worker_address = cluster.orchestrator.diagnostics.workers.iter().next().unwrap().key().clone();
+    for (line, (job_id, state)) in lines.iter().zip(&expected) {
+        assert_eq!((line["job_id"].as_str(), line["state"].as_str()), (Some(job_id.as_str()), Some(*state)), "{line}");
+        assert_eq!(line["namespace"], "default");
+        assert_eq!(line["worker_address"].as_str(), Some(worker_address.as_str()));
+        assert_eq!(line["arg_count"], 2);
+        assert_eq!(line["args"], serde_json::json!(["a", "b"]));
+        assert_eq!(line["module_hash"].as_str().map(str::len), Some(64), "{line}");
+        assert!(line["client_address"].as_str().is_some_and(|address| !address.is_empty()), "{line}");
+        let times: Vec<u64> = ["queued_at_ms", "dispatched_at_ms", "started_at_ms", "finished_at_ms"].iter()
+            .map(|field| line[field].as_u64().unwrap_or_else(|| panic!("{field} missing from {line}")))
+            .collect();
+        assert!(times.is_sorted(), "{line}");
+    }
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::time::Duration;
+
+use client::{CacheMode, Client, Job, JobError, JobOutput};
+use common::{BANG_WAT, Cluster, component};
+use orchestrator::OrchestratorConfig;
+
+/// The module bytes sent to workers and the modules compiled for the namespace's jobs, once
+/// the given number of jobs are counted.
+async fn usage_after(client: &Client, jobs: u64) -> (u64, u64) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let total = client.usage(None).await.unwrap().total;
+            if total.jobs >= jobs {
+                assert_eq!(total.jobs, jobs);
+                return (total.module_bytes, total.compiles);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.unwrap_or_else(|_| panic!("usage never counted {jobs} jobs"))
+}
+
+async fn run(client: &Client, cache_mode: CacheMode) -> Result<JobOutput, JobError> {
+    client.submit_job(Job::from_bytes(component(BANG_WAT)).cache_mode(cache_mode).max_retries(0)).wait().await
+}
+
+#[tokio::test]
+async fn by_default_the_module_is_only_sent_on_a_miss() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+    let wasm_len = component(BANG_WAT).len() as u64;
+
+    let first = run(&client, CacheMode::Default).await.unwrap();
+    assert!(!first.timing.cache_hit);
+    assert_eq!(usage_after(&client, 1).await, (wasm_len, 1));
+
+    let second = run(&client, CacheMode::Default).await.unwrap();
+    assert!(second.timing.cache_hit);
+    assert_eq!(usage_after(&client, 2).await, (wasm_len, 1));
+}
+
+#[tokio::test]
+async fn no_cache_always_sends_and_recompiles_the_module() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+    let wasm_len = component(BANG_WAT).len() as u64;
+
+    run(&client, CacheMode::Default).await.unwrap();
+    let output = run(&client, CacheMode::NoCache).await.unwrap();
+    assert!(!output.timing.cache_hit);
+    assert_eq!(usage_after(&client, 2).await, (2 * wasm_len, 2));
+}
+
+#[tokio::test]
+async fn hash_only_never_sends_the_module() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+    let wasm_len = component(BANG_WAT).len() as u64;
+
+    match run(&client, CacheMode::HashOnly).await {
+        Err(JobError::ModuleNotCached) => {},
+        result => panic!("expected the job to fail as not cached, got {result:?}"),
+    }
+    let cold_starts = || cluster.orchestrator.diagnostics.workers.iter().map(|w| w.cold_starts).sum::<u32>();
+    assert_eq!(cold_starts(), 0);
+
+    run(&client, CacheMode::Default).await.unwrap();
+    let output = run(&client, CacheMode::HashOnly).await.unwrap();
+    assert!(output.timing.cache_hit);
+    assert_eq!(output.stdout, b"!");
+    let (module_bytes, compiles) = usage_after(&client, 2).await;
+    assert_eq!((module_bytes, compiles), (wasm_len, 1));
+    assert_eq!(cold_starts(), 1);
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use client::{Job, JobError};
+use common::{Cluster, NOOP_WAT, SPIN_WAT, component, sleep_wat};
+use orchestrator::OrchestratorConfig;
+use orchestrator::diagnostics::JobState;
+use shared::client_api_client::ClientApiClient;
+use shared::compat::PROTOCOL_VERSION;
+use shared::executor_client::ExecutorClient;
+use shared::{ErrorCode, JobId, JobRequest, WorkerRequest};
+use tonic::{Code, Request};
+
+/// Slack allowed for a stopped job's outcome to reach the client and orchestrator.
+const MARGIN: Duration = Duration::from_millis(1500);
+
+/// Waits until the worker running the job reports it in the state.
+async fn wait_for_state(cluster: &Cluster, job_id: JobId, state: JobState) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while cluster.orchestrator.diagnostics.jobs.get(&job_id).is_none_or(|job| job.state != state) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.unwrap_or_else(|_| panic!("job {job_id} never reached {state:?}"));
+}
+
+/// Runs a job straight away on the cluster's only worker, which must have a free credit, to
+/// show its one credit is free.
+async fn assert_credit_free(cluster: &Cluster) {
+    let job = Job::from_bytes(component(NOOP_WAT)).timeout(Duration::from_secs(2)).max_retries(0);
+    cluster.client().await.submit_job(job).wait().await.unwrap();
+}
+
+/// Has the orchestrator assign a worker to a job and calls it to run the job directly, as the
+/// client would, returning the job's id and the pending call.
+async fn execute_directly(cluster: &Cluster, wat: &str, timeout_ms: Option<u64>) -> (JobId, tokio::task::JoinHandle<Result<(), tonic::Status>>) {
+    let job_id = JobId::random();
+    let mut orchestrator = ClientApiClient::connect(cluster.url.clone()).await.unwrap();
+    let request = WorkerRequest { job_id: job_id.to_bytes(), protocol_version: PROTOCOL_VERSION, timeout_ms, ..Default::default() };
+    let assigned = orchestrator.request_worker(request).await.unwrap().into_inner();
+    let mut worker = ExecutorClient::connect(format!("http://{}", assigned.worker_address)).await.unwrap();
+    let mut request = Request::new(JobRequest { job_id: job_id.to_bytes(), wasm_bytes: component(wat), protocol_version: PROTOCOL_VERSION, timeout_ms, ..Default::default() });
+    request.metadata_mut().insert("authorization", assigned.jwt_token.parse().unwrap());
+    let call = tokio::spawn(async move { worker.execute_job(request).await.map(|_| ()) });
+    (job_id, call)
+}
+
+#[tokio::test]
+async fn cancelled_jobs_stop_running() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let job = cluster.client().await.submit_job(Job::from_bytes(component(SPIN_WAT)).max_retries(0));
+    let job_id = job.job_id();
+    wait_for_state(&cluster, job_id, JobState::Executing).await;
+
+    let started = Instant::now();
+    job.clone().cancel().await;
+    match job.wait().await {
+        Err(JobError::Cancelled) => {},
+        result => panic!("expected the job to be cancelled, got {result:?}"),
+    }
+    wait_for_state(&cluster, job_id, JobState::Cancelled).await;
+    assert!(started.elapsed() < MARGIN, "took {:?}", started.elapsed());
+    assert_credit_free(&cluster).await;
+}
+
+#[tokio::test]
+async fn jobs_past_their_timeout_time_out() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let timeout = Duration::from_millis(500);
+    let job = cluster.client().await.submit_job(Job::from_bytes(component(SPIN_WAT)).timeout(timeout).max_retries(0));
+    let job_id = job.job_id();
+
+    let started = Instant::now();
+    match job.wait().await {
+        Err(JobError::TimedOut) => {},
+        result => panic!("expected the job to time out, got {result:?}"),
+    }
+    assert!(started.elapsed() < timeout + MARGIN, "took {:?}", started.elapsed());
+    // The client stops it as soon as it times out, so the worker may call it either
+    tokio::time::timeout(MARGIN, async {
+        while cluster.orchestrator.diagnostics.jobs.get(&job_id).is_none_or(|job| !matches!(job.state, JobState::Cancelled | JobState::Failed)) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.expect("the worker never reported the job stopped");
+    assert_credit_free(&cluster).await;
+}
+
+#[tokio::test]
+async fn jobs_stop_when_their_client_goes_away() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let (job_id, call) = execute_directly(&cluster, SPIN_WAT, None).await;
+    wait_for_state(&cluster, job_id, JobState::Executing).await;
+
+    call.abort();
+    wait_for_state(&cluster, job_id, JobState::Cancelled).await;
+    assert_credit_free(&cluster).await;
+}
+
+#[tokio::test]
+async fn jobs_aborted_by_a_stopping_worker_are_retried_elsewhere() {
+    let mut cluster = Cluster::start_with(OrchestratorConfig::default(), 2, |config| config.abort_jobs_after = Some(Duration::from_millis(100))).await;
+    let job = cluster.client().await.submit_job(Job::from_bytes(component(&sleep_wat(1500))).max_retries(1));
+    let job_id = job.job_id();
+    wait_for_state(&cluster, job_id, JobState::Executing).await;
+
+    let first_worker = job.worker_address().unwrap();
+    let index = cluster.workers.iter().position(|worker| worker.address() == first_worker).unwrap();
+    let started = Instant::now();
+    cluster.workers.remove(index).shutdown().await.unwrap();
+    assert!(started.elapsed() < MARGIN, "shutdown took {:?}", started.elapsed());
+
+    let output = job.clone().wait().await.unwrap();
+    assert_eq!(output.stdout, b"done\n");
+    assert_ne!(job.worker_address().unwrap(), first_worker);
+    assert_eq!(cluster.retries(job_id), 1);
+}
+
+// Tests run on a single-threaded runtime, which a spinning guest would starve of any tokio
+// task that was meant to interrupt it, so these also check the epoch ticker runs elsewhere.
+#[tokio::test]
+async fn workers_stop_jobs_at_their_deadline_on_their_own() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let timeout = Duration::from_millis(300);
+    let started = Instant::now();
+    let (job_id, call) = execute_directly(&cluster, SPIN_WAT, Some(timeout.as_millis() as u64)).await;
+
+    let status = call.await.unwrap().unwrap_err();
+    assert!(started.elapsed() < timeout + MARGIN, "took {:?}", started.elapsed());
+    assert_eq!((status.code(), ErrorCode::of(&status)), (Code::DeadlineExceeded, Some(ErrorCode::JobTimedOut)), "{status:?}");
+    wait_for_state(&cluster, job_id, JobState::Failed).await;
+    assert_credit_free(&cluster).await;
+}
+
+#[tokio::test]
+async fn jobs_within_their_deadline_on_the_worker_finish() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let (job_id, call) = execute_directly(&cluster, &sleep_wat(100), Some(5000)).await;
+    call.await.unwrap().unwrap();
+    wait_for_state(&cluster, job_id, JobState::Completed).await;
+}
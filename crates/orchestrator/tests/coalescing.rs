@@ -0,0 +1,72 @@
+mod common;
+
+use std::time::Duration;
+
+use client::{Job, JobError};
+use common::{Cluster, component, sleep_wat};
+use orchestrator::OrchestratorConfig;
+
+/// A job that runs long enough for identical jobs submitted right after it to join its run.
+fn slow_job() -> Job {
+    Job::from_bytes(component(&sleep_wat(500))).coalesce(true).max_retries(0)
+}
+
+/// Jobs the cluster's workers said they started executing.
+fn executions(cluster: &Cluster) -> u32 {
+    cluster.orchestrator.diagnostics.workers.iter().map(|w| w.jobs_received).sum()
+}
+
+#[tokio::test]
+async fn identical_jobs_share_one_run() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+
+    let jobs: Vec<_> = (0..3).map(|_| client.submit_job(slow_job())).collect();
+    let job_ids: Vec<_> = jobs.iter().map(|job| job.job_id()).collect();
+    let outputs = futures::future::join_all(jobs.into_iter().map(|job| job.wait())).await;
+    let outputs: Vec<_> = outputs.into_iter().map(Result::unwrap).collect();
+
+    for output in &outputs {
+        assert_eq!((output.stdout.as_slice(), output.stderr.as_slice()), (&b"done\n"[..], &b""[..]));
+    }
+    let leaders: Vec<_> = job_ids.iter().zip(&outputs).filter(|(_, output)| output.coalesced_with.is_none()).collect();
+    assert_eq!(leaders.len(), 1, "{outputs:?}");
+    let leader = *leaders[0].0;
+    for (job_id, output) in job_ids.iter().zip(&outputs).filter(|(job_id, _)| **job_id != leader) {
+        assert_eq!(output.coalesced_with, Some(leader), "{job_id}");
+    }
+    assert_eq!(executions(&cluster), 1);
+}
+
+#[tokio::test]
+async fn cancelling_a_follower_leaves_the_run_going() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+
+    let leader = client.submit_job(slow_job());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let follower = client.submit_job(slow_job());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    follower.clone().cancel().await;
+
+    assert!(matches!(follower.wait().await, Err(JobError::Cancelled)));
+    let output = leader.wait().await.unwrap();
+    assert_eq!(output.stdout, b"done\n");
+}
+
+#[tokio::test]
+async fn cancelling_the_leader_leaves_the_run_going_for_its_followers() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+
+    let leader = client.submit_job(slow_job());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let follower = client.submit_job(slow_job());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let leader_id = leader.job_id();
+    leader.clone().cancel().await;
+
+    assert!(matches!(leader.wait().await, Err(JobError::Cancelled)));
+    let output = follower.wait().await.unwrap();
+    assert_eq!((output.stdout.as_slice(), output.coalesced_with), (&b"done\n"[..], Some(leader_id)));
+}
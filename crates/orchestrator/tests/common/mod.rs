@@ -0,0 +1,281 @@
+//! An orchestrator, its workers and a client, all running in the test's process.
+
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use client::{Client, JobId};
+use orchestrator::{Orchestrator, OrchestratorConfig, build_router};
+use shared::compat::PROTOCOL_VERSION;
+use shared::worker_api_client::WorkerApiClient;
+use shared::{OrchestratorMessage, WorkerMessage, WorkerRegistration, orchestrator_message, worker_message};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::Streaming;
+use tonic::transport::Server;
+use worker::{Worker, WorkerConfig, WorkerHandle};
+
+/// A component that traps as soon as it runs. It imports nothing, so it runs on any worker.
+pub const TRAP_WAT: &str = r#"
+(component
+  (core module $m
+    (func (export "run") (result i32) unreachable))
+  (core instance $i (instantiate $m))
+  (func $run (result (result)) (canon lift (core func $i "run")))
+  (instance $run (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run)))
+"#;
+
+/// A component that returns successfully without doing anything.
+pub const NOOP_WAT: &str = r#"
+(component
+  (core module $m
+    (func (export "run") (result i32) i32.const 0))
+  (core instance $i (instantiate $m))
+  (func $run (result (result)) (canon lift (core func $i "run")))
+  (instance $run (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run)))
+"#;
+
+/// A component that copies its stdin to its stdout and then prints "!", as a pipeline stage.
+pub const BANG_WAT: &str = r#"
+(component
+  (import "wasi:io/error@0.2.0" (instance $error
+    (export "error" (type (sub resource)))))
+  (alias export $error "error" (type $error-type))
+  (import "wasi:io/streams@0.2.0" (instance $streams
+    (export "input-stream" (type (sub resource)))
+    (export "output-stream" (type (sub resource)))
+    (alias outer 1 $error-type (type))
+    (export "error" (type (eq 2)))
+    (type (own 3))
+    (type (variant (case "last-operation-failed" 4) (case "closed")))
+    (export "stream-error" (type (eq 5)))
+    (type (borrow 0))
+    (type (list u8))
+    (type (result 8 (error 6)))
+    (type (func (param "self" 7) (param "len" u64) (result 9)))
+    (export "[method]input-stream.blocking-read" (func (type 10)))
+    (type (borrow 1))
+    (type (result (error 6)))
+    (type (func (param "self" 11) (param "contents" 8) (result 12)))
+    (export "[method]output-stream.blocking-write-and-flush" (func (type 13)))))
+  (alias export $streams "input-stream" (type $input-stream))
+  (alias export $streams "output-stream" (type $output-stream))
+  (import "wasi:cli/stdin@0.2.0" (instance $stdin
+    (alias outer 1 $input-stream (type))
+    (export "input-stream" (type (eq 0)))
+    (type (own 1))
+    (type (func (result 2)))
+    (export "get-stdin" (func (type 3)))))
+  (import "wasi:cli/stdout@0.2.0" (instance $stdout
+    (alias outer 1 $output-stream (type))
+    (export "output-stream" (type (eq 0)))
+    (type (own 1))
+    (type (func (result 2)))
+    (export "get-stdout" (func (type 3)))))
+
+  ;; Each read is written out before the next, so reads can all land in the same buffer
+  (core module $memory
+    (memory (export "memory") 1)
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32) i32.const 4096))
+  (core instance $memory (instantiate $memory))
+  (alias core export $memory "memory" (core memory $mem))
+  (alias core export $memory "realloc" (core func $realloc))
+  (core func $get-stdin (canon lower (func $stdin "get-stdin")))
+  (core func $get-stdout (canon lower (func $stdout "get-stdout")))
+  (core func $read (canon lower (func $streams "[method]input-stream.blocking-read") (memory $mem) (realloc $realloc)))
+  (core func $write (canon lower (func $streams "[method]output-stream.blocking-write-and-flush") (memory $mem)))
+
+  (core module $m
+    (import "host" "memory" (memory 1))
+    (import "host" "get-stdin" (func $get-stdin (result i32)))
+    (import "host" "get-stdout" (func $get-stdout (result i32)))
+    (import "host" "read" (func $read (param i32 i64 i32)))
+    (import "host" "write" (func $write (param i32 i32 i32 i32)))
+    (data (i32.const 0) "!")
+    (func (export "run") (result i32)
+      (local $stdin i32) (local $stdout i32)
+      (local.set $stdin (call $get-stdin))
+      (local.set $stdout (call $get-stdout))
+      ;; Read results land at 1024: a tag, then the buffer's pointer and length
+      (block $closed
+        (loop $copy
+          (call $read (local.get $stdin) (i64.const 4096) (i32.const 1024))
+          (br_if $closed (i32.load8_u (i32.const 1024)))
+          (call $write (local.get $stdout) (i32.load (i32.const 1028)) (i32.load (i32.const 1032)) (i32.const 1040))
+          (if (i32.load8_u (i32.const 1040)) (then (return (i32.const 1))))
+          (br $copy)))
+      (call $write (local.get $stdout) (i32.const 0) (i32.const 1) (i32.const 1040))
+      (i32.load8_u (i32.const 1040))))
+  (core instance $i (instantiate $m
+    (with "host" (instance
+      (export "memory" (memory $mem))
+      (export "get-stdin" (func $get-stdin))
+      (export "get-stdout" (func $get-stdout))
+      (export "read" (func $read))
+      (export "write" (func $write))))))
+  (func $run (result (result)) (canon lift (core func $i "run")))
+  (instance $run (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run)))
+"#;
+
+/// A component that sleeps for the given number of milliseconds, then prints "done".
+pub fn sleep_wat(ms: u64) -> String {
+    format!(r#"
+(component
+  (import "wasi:io/poll@0.2.0" (instance $poll
+    (export "pollable" (type (sub resource)))
+    (type (borrow 0))
+    (type (func (param "self" 1)))
+    (export "[method]pollable.block" (func (type 2)))))
+  (alias export $poll "pollable" (type $pollable))
+  (import "wasi:clocks/monotonic-clock@0.2.0" (instance $clock
+    (alias outer 1 $pollable (type))
+    (export "pollable" (type (eq 0)))
+    (type (own 1))
+    (type (func (param "when" u64) (result 2)))
+    (export "subscribe-duration" (func (type 3)))))
+  (import "wasi:io/error@0.2.0" (instance $error
+    (export "error" (type (sub resource)))))
+  (alias export $error "error" (type $error-type))
+  (import "wasi:io/streams@0.2.0" (instance $streams
+    (export "output-stream" (type (sub resource)))
+    (alias outer 1 $error-type (type))
+    (export "error" (type (eq 1)))
+    (type (own 2))
+    (type (variant (case "last-operation-failed" 3) (case "closed")))
+    (export "stream-error" (type (eq 4)))
+    (type (borrow 0))
+    (type (list u8))
+    (type (result (error 5)))
+    (type (func (param "self" 6) (param "contents" 7) (result 8)))
+    (export "[method]output-stream.blocking-write-and-flush" (func (type 9)))))
+  (alias export $streams "output-stream" (type $output-stream))
+  (import "wasi:cli/stdout@0.2.0" (instance $stdout
+    (alias outer 1 $output-stream (type))
+    (export "output-stream" (type (eq 0)))
+    (type (own 1))
+    (type (func (result 2)))
+    (export "get-stdout" (func (type 3)))))
+
+  (core module $memory
+    (memory (export "memory") 1)
+    (data (i32.const 0) "done\n"))
+  (core instance $memory (instantiate $memory))
+  (alias core export $memory "memory" (core memory $mem))
+  (core func $subscribe (canon lower (func $clock "subscribe-duration")))
+  (core func $block (canon lower (func $poll "[method]pollable.block")))
+  (core func $get-stdout (canon lower (func $stdout "get-stdout")))
+  (core func $write (canon lower (func $streams "[method]output-stream.blocking-write-and-flush") (memory $mem)))
+
+  (core module $m
+    (import "host" "memory" (memory 1))
+    (import "host" "subscribe" (func $subscribe (param i64) (result i32)))
+    (import "host" "block" (func $block (param i32)))
+    (import "host" "get-stdout" (func $get-stdout (result i32)))
+    (import "host" "write" (func $write (param i32 i32 i32 i32)))
+    (func (export "run") (result i32)
+      (call $block (call $subscribe (i64.const {ns})))
+      (call $write (call $get-stdout) (i32.const 0) (i32.const 5) (i32.const 1024))
+      (i32.load8_u (i32.const 1024))))
+  (core instance $i (instantiate $m
+    (with "host" (instance
+      (export "memory" (memory $mem))
+      (export "subscribe" (func $subscribe))
+      (export "block" (func $block))
+      (export "get-stdout" (func $get-stdout))
+      (export "write" (func $write))))))
+  (func $run (result (result)) (canon lift (core func $i "run")))
+  (instance $run (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run)))
+"#, ns = ms * 1_000_000)
+}
+
+/// A component that loops forever without calling the host, until the worker interrupts it.
+pub const SPIN_WAT: &str = r#"
+(component
+  (core module $m
+    (func (export "run") (result i32)
+      (loop $spin (br $spin))
+      (i32.const 0)))
+  (core instance $i (instantiate $m))
+  (func $run (result (result)) (canon lift (core func $i "run")))
+  (instance $run (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run)))
+"#;
+
+pub fn component(wat: &str) -> Vec<u8> {
+    wat::parse_str(wat).unwrap_or_else(|e| panic!("invalid test component: {e}"))
+}
+
+pub struct Cluster {
+    pub orchestrator: Orchestrator,
+    pub url: String,
+    pub workers: Vec<WorkerHandle>,
+}
+
+impl Cluster {
+    /// Serves an orchestrator on a free port and registers the given number of workers with it,
+    /// each with a single credit.
+    pub async fn start(config: OrchestratorConfig, workers: usize) -> Self {
+        Self::start_with(config, workers, |_| {}).await
+    }
+
+    /// Like start, letting the caller adjust each worker's config.
+    pub async fn start_with(config: OrchestratorConfig, workers: usize, configure: impl Fn(&mut WorkerConfig)) -> Self {
+        let orchestrator = Orchestrator::start(config);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(Server::builder().add_routes(build_router(&orchestrator)).serve_with_incoming(TcpListenerStream::new(listener)));
+
+        let mut handles = Vec::new();
+        for _ in 0..workers {
+            let mut worker_config = WorkerConfig::new("127.0.0.1", 1);
+            worker_config.orchestrators = vec![url.clone()];
+            configure(&mut worker_config);
+            handles.push(Worker::start(worker_config).await.unwrap());
+        }
+        let cluster = Self { orchestrator, url, workers: handles };
+        cluster.wait_for_workers(workers).await;
+        cluster
+    }
+
+    /// Waits until the given number of workers registered with the orchestrator.
+    pub async fn wait_for_workers(&self, count: usize) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while self.orchestrator.diagnostics.workers.iter().filter(|w| w.disconnected_at.is_none()).count() < count {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }).await.unwrap_or_else(|_| panic!("{count} workers didn't register"));
+    }
+
+    pub async fn client(&self) -> Client {
+        Client::connect(&self.url, None, false).await.unwrap()
+    }
+
+    /// How many times the job was queued again after its first attempt.
+    pub fn retries(&self, job_id: JobId) -> u32 {
+        self.orchestrator.diagnostics.jobs.get(&job_id).map_or(0, |job| job.retries)
+    }
+}
+
+/// Opens a worker stream to the orchestrator, as a worker would before registering.
+pub async fn open_worker_stream(url: &str) -> (mpsc::Sender<WorkerMessage>, Streaming<OrchestratorMessage>) {
+    let mut client = WorkerApiClient::connect(url.to_string()).await.unwrap();
+    let (tx, rx) = mpsc::channel(8);
+    let inbound = client.connect_worker(ReceiverStream::new(rx)).await.unwrap().into_inner();
+    (tx, inbound)
+}
+
+/// Registers a stand-in for a worker with a single credit and waits for the ack. It is relayed,
+/// so the orchestrator never tries to reach it. Dropping the sender disconnects it.
+pub async fn register_fake_worker(url: &str, address: &str) -> (mpsc::Sender<WorkerMessage>, Streaming<OrchestratorMessage>) {
+    let (tx, mut inbound) = open_worker_stream(url).await;
+    let registration = WorkerRegistration { address: address.to_string(), credits: 1, protocol_version: PROTOCOL_VERSION, relayed: true, ..Default::default() };
+    tx.send(WorkerMessage { message: Some(worker_message::Message::Registration(registration)) }).await.unwrap();
+    let ack = inbound.message().await.unwrap().and_then(|message| message.message);
+    assert!(matches!(ack, Some(orchestrator_message::Message::RegistrationAck(_))), "expected an ack, got {ack:?}");
+    (tx, inbound)
+}
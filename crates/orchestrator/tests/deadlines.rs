@@ -0,0 +1,75 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use client::{Job, JobError};
+use common::{Cluster, NOOP_WAT, SPIN_WAT, component, sleep_wat};
+use orchestrator::OrchestratorConfig;
+use orchestrator::diagnostics::JobState;
+use shared::client_api_client::ClientApiClient;
+use shared::compat::PROTOCOL_VERSION;
+use shared::{JobId, WorkerRequest};
+use tonic::Code;
+
+/// Slack allowed past a deadline for the job's outcome to reach the client.
+const MARGIN: Duration = Duration::from_millis(1500);
+
+/// Jobs the cluster's workers said they started executing.
+fn executions(cluster: &Cluster) -> u32 {
+    cluster.orchestrator.diagnostics.workers.iter().map(|w| w.jobs_received).sum()
+}
+
+#[tokio::test]
+async fn jobs_expire_in_the_queue_without_reaching_a_worker() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+    let blocker = client.submit_job(Job::from_bytes(component(&sleep_wat(2000))).max_retries(0));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let job = client.submit_job(Job::from_bytes(component(NOOP_WAT)).timeout(Duration::from_millis(200)).max_retries(0));
+    let job_id = job.job_id();
+    let started = Instant::now();
+    match job.wait().await {
+        Err(JobError::TimedOut) => {},
+        result => panic!("expected the job to time out, got {result:?}"),
+    }
+    assert!(started.elapsed() < Duration::from_millis(200) + MARGIN, "took {:?}", started.elapsed());
+
+    // The status the orchestrator answers with, without the client's mapping
+    let mut api = ClientApiClient::connect(cluster.url.clone()).await.unwrap();
+    let request = WorkerRequest { job_id: JobId::random().to_bytes(), protocol_version: PROTOCOL_VERSION, timeout_ms: Some(200), ..Default::default() };
+    let status = api.request_worker(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::DeadlineExceeded, "{status:?}");
+
+    let job = cluster.orchestrator.diagnostics.jobs.get(&job_id).unwrap().clone();
+    assert_eq!((job.state, job.worker_address), (JobState::Failed, None));
+    blocker.wait().await.unwrap();
+    assert_eq!(executions(&cluster), 1);
+}
+
+#[tokio::test]
+async fn running_jobs_are_stopped_at_their_deadline() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+
+    let deadline = Duration::from_millis(500);
+    let started = Instant::now();
+    match client.submit_job(Job::from_bytes(component(SPIN_WAT)).timeout(deadline).max_retries(0)).wait().await {
+        Err(JobError::TimedOut) => {},
+        result => panic!("expected the job to time out, got {result:?}"),
+    }
+    assert!(started.elapsed() < deadline + MARGIN, "took {:?}", started.elapsed());
+
+    // The worker's only credit came back, so the next job runs straight away
+    let next = client.submit_job(Job::from_bytes(component(NOOP_WAT)).timeout(Duration::from_secs(2)).max_retries(0));
+    next.wait().await.unwrap();
+    assert_eq!(executions(&cluster), 2);
+}
+
+#[tokio::test]
+async fn jobs_within_their_deadline_finish() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let job = Job::from_bytes(component(&sleep_wat(100))).timeout(Duration::from_secs(5)).max_retries(0);
+    let output = cluster.client().await.submit_job(job).wait().await.unwrap();
+    assert_eq!(output.stdout, b"done\n");
+}
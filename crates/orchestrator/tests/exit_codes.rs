@@ -0,0 +1,68 @@
+mod common;
+
+use std::time::Duration;
+
+use client::{Job, JobError};
+use common::{Cluster, NOOP_WAT, TRAP_WAT, component};
+use orchestrator::OrchestratorConfig;
+use orchestrator::diagnostics::JobState;
+use shared::JobId;
+use worker::ConnectMode;
+
+/// A component whose run returns an error, which counts as exiting with 1.
+const ERR_WAT: &str = r#"
+(component
+  (core module $m
+    (func (export "run") (result i32) i32.const 1))
+  (core instance $i (instantiate $m))
+  (func $run (result (result)) (canon lift (core func $i "run")))
+  (instance $run (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run)))
+"#;
+
+/// Runs a job to its end and returns the state and exit code its worker reported.
+async fn finish(cluster: &Cluster, wat: &str) -> (JobState, Option<i32>) {
+    let job = cluster.client().await.submit_job(Job::from_bytes(component(wat)).max_retries(0));
+    let job_id = job.job_id();
+    _ = job.wait().await;
+    wait_for_finished(cluster, job_id).await
+}
+
+async fn wait_for_finished(cluster: &Cluster, job_id: JobId) -> (JobState, Option<i32>) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(job) = cluster.orchestrator.diagnostics.jobs.get(&job_id)
+                && matches!(job.state, JobState::Completed | JobState::Failed)
+            {
+                return (job.state.clone(), job.exit_code);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.unwrap_or_else(|_| panic!("job {job_id} never finished"))
+}
+
+#[tokio::test]
+async fn workers_report_the_code_jobs_exit_with() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    assert_eq!(finish(&cluster, NOOP_WAT).await, (JobState::Completed, Some(0)));
+    assert_eq!(finish(&cluster, ERR_WAT).await, (JobState::Failed, Some(1)));
+}
+
+#[tokio::test]
+async fn trapped_jobs_have_no_exit_code() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    assert_eq!(finish(&cluster, TRAP_WAT).await, (JobState::Failed, None));
+}
+
+/// Clients learn the code a job exited with, whether they reach its worker directly or their
+/// calls are relayed to it.
+#[tokio::test]
+async fn clients_see_the_code_jobs_exit_with() {
+    for connect_mode in [ConnectMode::Listen, ConnectMode::Relay] {
+        let cluster = Cluster::start_with(OrchestratorConfig::default(), 1, |worker_config| worker_config.connect_mode = connect_mode).await;
+        match cluster.client().await.submit_job(Job::from_bytes(component(ERR_WAT)).max_retries(0)).wait().await {
+            Err(JobError::Exited { code: 1, .. }) => {},
+            result => panic!("expected the job to exit with 1 ({connect_mode:?}), got {result:?}"),
+        }
+    }
+}
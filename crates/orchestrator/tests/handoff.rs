@@ -0,0 +1,35 @@
+mod common;
+
+use std::time::Duration;
+
+use client::{CacheMode, Job};
+use common::{BANG_WAT, Cluster, component};
+use orchestrator::OrchestratorConfig;
+use worker::HandoffConfig;
+
+#[tokio::test]
+async fn a_stopping_worker_hands_its_modules_to_a_peer() {
+    let handoff = HandoffConfig { max_bytes: 64 * 1024 * 1024, deadline: Duration::from_secs(5) };
+    let mut cluster = Cluster::start_with(OrchestratorConfig::default(), 2, |config| config.handoff = Some(handoff)).await;
+    let client = cluster.client().await;
+
+    let first = client.submit_job(Job::from_bytes(component(BANG_WAT)).max_retries(0));
+    first.clone().wait().await.unwrap();
+    let compiled_on = first.worker_address().unwrap();
+    let index = cluster.workers.iter().position(|worker| worker.address() == compiled_on).unwrap();
+    cluster.workers.remove(index).shutdown().await.unwrap();
+
+    let peer = cluster.workers[0].address().to_string();
+    let handed_off = |address: &str| cluster.orchestrator.diagnostics.workers.get(address).map(|w| (w.modules_handed_off, w.modules_received));
+    assert_eq!(handed_off(&compiled_on), Some((1, 0)));
+    assert_eq!(handed_off(&peer), Some((0, 1)));
+
+    // Sent by hash only, so the job fails unless the peer has the module
+    let job = client.submit_job(Job::from_bytes(component(BANG_WAT)).cache_mode(CacheMode::HashOnly).max_retries(0));
+    let output = job.clone().wait().await.unwrap();
+    assert_eq!(job.worker_address(), Some(peer.clone()));
+    assert!(output.timing.cache_hit);
+    assert_eq!(output.stdout, b"!");
+    let cold_starts = cluster.orchestrator.diagnostics.workers.get(&peer).unwrap().cold_starts;
+    assert_eq!(cold_starts, 0);
+}
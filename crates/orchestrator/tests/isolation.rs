@@ -0,0 +1,99 @@
+//! Jobs run in helper processes, with `--isolate process`. The helpers are copies of this test
+//! binary, so it has no libtest harness and serves as one itself when asked, as the worker's
+//! main does.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use client::{Job, JobError};
+use common::{BANG_WAT, Cluster, NOOP_WAT, SPIN_WAT, component};
+use orchestrator::OrchestratorConfig;
+use worker::{Isolation, ProcessLimits, RUN_JOB_COMMAND};
+
+/// A component that grows its memory to 256 MiB, and traps if it can't.
+const HOG_WAT: &str = r#"
+(component
+  (core module $m
+    (memory 1)
+    (func (export "run") (result i32)
+      (if (i32.eq (memory.grow (i32.const 4096)) (i32.const -1)) (then unreachable))
+      (i32.const 0)))
+  (core instance $i (instantiate $m))
+  (func $run (result (result)) (canon lift (core func $i "run")))
+  (instance $run (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run)))
+"#;
+
+/// Slack allowed past a limit for the job's process to be stopped and its failure to arrive.
+const MARGIN: Duration = Duration::from_millis(1500);
+
+async fn isolated_cluster(process_limits: ProcessLimits) -> Cluster {
+    Cluster::start_with(OrchestratorConfig::default(), 1, |worker_config| {
+        worker_config.isolation = Isolation::Process;
+        worker_config.process_limits = process_limits;
+    }).await
+}
+
+/// Runs a job on the cluster, expecting it to fail as its process was stopped.
+async fn run_stopped(cluster: &Cluster, wat: &str) -> String {
+    match cluster.client().await.submit_job(Job::from_bytes(component(wat)).max_retries(0)).wait().await {
+        Err(JobError::WasmError(message)) => message,
+        result => panic!("expected the job's process to be stopped, got {result:?}"),
+    }
+}
+
+async fn isolated_jobs_run() {
+    let cluster = isolated_cluster(ProcessLimits::default()).await;
+    let client = cluster.client().await;
+    let output = client.submit_job(Job::from_bytes(component(BANG_WAT)).stdin(b"hi".to_vec())).wait().await.unwrap();
+    assert_eq!(output.stdout, b"hi!");
+    client.submit_job(Job::from_bytes(component(HOG_WAT))).wait().await.unwrap();
+}
+
+async fn processes_are_killed_past_the_wall_clock_limit() {
+    let limit = Duration::from_millis(500);
+    let cluster = isolated_cluster(ProcessLimits { wall_clock: Some(limit), ..Default::default() }).await;
+    let started = Instant::now();
+    let message = run_stopped(&cluster, SPIN_WAT).await;
+    assert!(message.contains("limit on job processes"), "{message}");
+    assert!(started.elapsed() < limit + MARGIN, "took {:?}", started.elapsed());
+
+    // The worker carried on, and its credit came back
+    cluster.client().await.submit_job(Job::from_bytes(component(NOOP_WAT))).wait().await.unwrap();
+}
+
+#[cfg(unix)]
+async fn processes_are_stopped_past_the_cpu_limit() {
+    let cluster = isolated_cluster(ProcessLimits { cpu_secs: Some(1), ..Default::default() }).await;
+    let started = Instant::now();
+    let message = run_stopped(&cluster, SPIN_WAT).await;
+    assert!(message.contains("limit on CPU time"), "{message}");
+    assert!(started.elapsed() < Duration::from_secs(1) + MARGIN, "took {:?}", started.elapsed());
+    cluster.client().await.submit_job(Job::from_bytes(component(NOOP_WAT))).wait().await.unwrap();
+}
+
+#[cfg(unix)]
+async fn processes_cant_grow_past_the_memory_limit() {
+    let cluster = isolated_cluster(ProcessLimits { memory_bytes: Some(128 * 1024 * 1024), ..Default::default() }).await;
+    let message = run_stopped(&cluster, HOG_WAT).await;
+    assert!(message.contains("wasm trapped"), "{message}");
+    cluster.client().await.submit_job(Job::from_bytes(component(NOOP_WAT))).wait().await.unwrap();
+}
+
+#[tokio::main]
+async fn main() {
+    if std::env::args().nth(1).as_deref() == Some(RUN_JOB_COMMAND) {
+        worker::run_job_helper().await;
+    }
+
+    macro_rules! run {
+        ($($test:ident),* $(,)?) => {$(
+            $test().await;
+            println!("test {} ... ok", stringify!($test));
+        )*};
+    }
+    run!(isolated_jobs_run, processes_are_killed_past_the_wall_clock_limit);
+    #[cfg(unix)]
+    run!(processes_are_stopped_past_the_cpu_limit, processes_cant_grow_past_the_memory_limit);
+}
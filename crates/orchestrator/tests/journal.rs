@@ -0,0 +1,85 @@
+mod common;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use common::{Cluster, register_fake_worker};
+use orchestrator::diagnostics::JobState;
+use orchestrator::{Journal, JournalConfig, Orchestrator, OrchestratorConfig};
+use shared::client_api_client::ClientApiClient;
+use shared::compat::PROTOCOL_VERSION;
+use shared::{JobId, JobUpdate, WorkerMessage, WorkerRequest, WorkerResponse, worker_message};
+
+fn journal(path: &Path) -> Journal {
+    let config = JournalConfig { path: path.to_path_buf(), max_bytes: 1024 * 1024, retention: Duration::from_secs(3600) };
+    Journal::open(config).unwrap()
+}
+
+fn journal_path() -> PathBuf {
+    std::env::temp_dir().join(format!("mini-lambda-test-journal-{}.jsonl", JobId::random()))
+}
+
+async fn request_worker(url: &str, job_id: JobId) -> WorkerResponse {
+    let mut client = ClientApiClient::connect(url.to_string()).await.unwrap();
+    let request = WorkerRequest { job_id: job_id.to_bytes(), protocol_version: PROTOCOL_VERSION, ..Default::default() };
+    client.request_worker(request).await.unwrap().into_inner()
+}
+
+/// Waits until the journal holds an event of the given type for the job, as it would be
+/// found by an orchestrator started after a crash.
+async fn wait_for_event(path: &Path, event: &str, job_id: JobId) {
+    let line = format!(r#"{{"type":"{event}","job_id":"{job_id}""#);
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !std::fs::read_to_string(path).unwrap_or_default().lines().any(|l| l.starts_with(&line)) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.unwrap_or_else(|_| panic!("no {event} event for {job_id} was journaled"));
+}
+
+fn recovered(path: &Path) -> Orchestrator {
+    Orchestrator::start(OrchestratorConfig { journal: Some(journal(path)), ..Default::default() })
+}
+
+fn state(orchestrator: &Orchestrator, job_id: JobId) -> (JobState, Option<String>) {
+    let job = orchestrator.diagnostics.jobs.get(&job_id).unwrap_or_else(|| panic!("{job_id} wasn't recovered"));
+    (job.state.clone(), job.worker_address.clone())
+}
+
+#[tokio::test]
+async fn a_restart_recovers_each_jobs_outcome() {
+    let path = journal_path();
+    let cluster = Cluster::start(OrchestratorConfig { journal: Some(journal(&path)), ..Default::default() }, 0).await;
+    let first = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+    let second = register_fake_worker(&cluster.url, "10.0.0.2:7000").await;
+
+    // One job finishes, one is left running, and one waits in the queue for a credit
+    let (completed, running, queued) = (JobId::random(), JobId::random(), JobId::random());
+    let completed_on = request_worker(&cluster.url, completed).await.worker_address;
+    let running_on = request_worker(&cluster.url, running).await.worker_address;
+    let worker = if completed_on == "10.0.0.1:7000" { &first.0 } else { &second.0 };
+    let update = JobUpdate { job_id: completed.to_bytes(), state: shared::JobState::Completed as i32, ..Default::default() };
+    worker.send(WorkerMessage { message: Some(worker_message::Message::JobUpdate(update)) }).await.unwrap();
+    let url = cluster.url.clone();
+    let _waiting = tokio::spawn(async move { request_worker(&url, queued).await });
+
+    wait_for_event(&path, "completed", completed).await;
+    wait_for_event(&path, "dispatched", running).await;
+    wait_for_event(&path, "queued", queued).await;
+    // The crash tore the event being written
+    std::fs::OpenOptions::new().append(true).open(&path).unwrap()
+        .write_all(format!(r#"{{"type":"completed","job_id":"{running}"#).as_bytes()).unwrap();
+
+    let restarted = recovered(&path);
+    assert_eq!(state(&restarted, completed), (JobState::Completed, Some(completed_on)));
+    assert_eq!(state(&restarted, running), (JobState::UnknownOutcome, Some(running_on.clone())));
+    // The queued job's request was lost with the orchestrator
+    assert_eq!(state(&restarted, queued), (JobState::Cancelled, None));
+
+    // Recovery compacted the journal, which a second restart recovers the same
+    let restarted_again = recovered(&path);
+    for job_id in [completed, running, queued] {
+        assert_eq!(state(&restarted_again, job_id), state(&restarted, job_id));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
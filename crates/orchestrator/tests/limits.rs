@@ -0,0 +1,30 @@
+mod common;
+
+use client::{Job, JobError};
+use common::{Cluster, NOOP_WAT, component};
+use orchestrator::OrchestratorConfig;
+use shared::limits::JobLimits;
+
+async fn start() -> Cluster {
+    let limits = JobLimits { max_args: 2, max_args_bytes: 16, ..JobLimits::default() };
+    Cluster::start_with(OrchestratorConfig::default(), 1, |config| config.limits = limits).await
+}
+
+#[tokio::test]
+async fn jobs_within_the_limits_run() {
+    let cluster = start().await;
+    let job = Job::from_bytes(component(NOOP_WAT)).args(["a", "b"]).max_retries(0);
+    cluster.client().await.submit_job(job).wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn workers_reject_jobs_over_their_limits() {
+    let cluster = start().await;
+    for args in [vec!["a", "b", "c"], vec!["0123456789", "0123456789"]] {
+        let job = Job::from_bytes(component(NOOP_WAT)).args(args).max_retries(0);
+        match cluster.client().await.submit_job(job).wait().await {
+            Err(JobError::Rejected(message)) => assert!(message.contains("over the limit"), "{message}"),
+            result => panic!("expected the job to be rejected, got {result:?}"),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::{Cluster, NOOP_WAT, component};
+use client::{Job, JobQuery};
+use orchestrator::OrchestratorConfig;
+use shared::ListJobsRequest;
+use shared::client_api_client::ClientApiClient;
+use tonic::Code;
+
+/// Lists a page of jobs straight over the API, so the status code of a rejection can be checked.
+async fn list_jobs(cluster: &Cluster, page_token: &str) -> Result<Vec<Vec<u8>>, tonic::Status> {
+    let mut client = ClientApiClient::connect(cluster.url.clone()).await.unwrap();
+    let request = ListJobsRequest { limit: 2, page_token: Some(page_token.to_string()), ..Default::default() };
+    Ok(client.list_jobs(request).await?.into_inner().items.into_iter().map(|j| j.job_id).collect())
+}
+
+#[tokio::test]
+async fn page_tokens_only_work_where_they_were_issued() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let other = Cluster::start(OrchestratorConfig::default(), 0).await;
+    let client = cluster.client().await;
+    let mut job_ids = Vec::new();
+    for _ in 0..5 {
+        let job = client.submit_job(Job::from_bytes(component(NOOP_WAT)));
+        job_ids.push(job.job_id());
+        job.wait().await.unwrap();
+    }
+
+    let query = JobQuery { limit: Some(2), ..Default::default() };
+    let first = client.list_jobs(&query, None).await.unwrap();
+    let token = first.next_page_token.unwrap();
+    let listed = list_jobs(&cluster, &token).await.unwrap();
+    assert_eq!(listed, [job_ids[2].to_bytes(), job_ids[1].to_bytes()]);
+
+    for malformed in ["", "zz", "00000000000000000000000g", &format!("+{}", &token[1..]), &token[..16]] {
+        let error = list_jobs(&cluster, malformed).await.unwrap_err();
+        assert_eq!(error.code(), Code::InvalidArgument, "{malformed:?}: {error:?}");
+    }
+    let error = list_jobs(&other, &token).await.unwrap_err();
+    assert_eq!(error.code(), Code::InvalidArgument, "{error:?}");
+}
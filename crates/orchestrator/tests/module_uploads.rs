@@ -0,0 +1,67 @@
+mod common;
+
+use std::time::Duration;
+
+use client::Job;
+use common::{Cluster, NOOP_WAT, component, sleep_wat};
+use orchestrator::OrchestratorConfig;
+
+fn coordinating(wait: Duration) -> OrchestratorConfig {
+    OrchestratorConfig { coordinate_uploads: Some(wait), ..Default::default() }
+}
+
+/// Fails the test if the jobs' results don't all arrive within a few seconds, as jobs waiting
+/// on an upload mustn't hang.
+async fn wait_all(jobs: Vec<client::RunningJob>) -> Vec<Result<client::JobOutput, client::JobError>> {
+    tokio::time::timeout(Duration::from_secs(10), futures::future::join_all(jobs.into_iter().map(|job| job.wait())))
+        .await
+        .expect("jobs waiting on an upload hung")
+}
+
+#[tokio::test]
+async fn concurrent_jobs_for_a_new_module_upload_it_once() {
+    let cluster = Cluster::start_with(coordinating(Duration::from_secs(10)), 1, |config| config.credits = 10).await;
+    let client = cluster.client().await;
+
+    let jobs = (0..10).map(|_| client.submit_job(Job::from_bytes(component(NOOP_WAT)).max_retries(0))).collect();
+    let outputs: Vec<_> = wait_all(jobs).await.into_iter().map(Result::unwrap).collect();
+
+    // Only the uploading job finds the module missing, the others send its hash once it compiled
+    let uploads = outputs.iter().filter(|output| !output.timing.cache_hit).count();
+    assert_eq!(uploads, 1, "{outputs:?}");
+    let worker = cluster.orchestrator.diagnostics.workers.iter().next().map(|w| (w.jobs_received, w.cold_starts));
+    assert_eq!(worker, Some((10, 1)));
+}
+
+#[tokio::test]
+async fn a_failed_upload_fails_the_jobs_waiting_for_it() {
+    let cluster = Cluster::start_with(coordinating(Duration::from_secs(10)), 1, |config| config.credits = 5).await;
+    let client = cluster.client().await;
+
+    // A core module, which the worker refuses to compile
+    let wasm_bytes = wat::parse_str("(module)").unwrap();
+    let jobs = (0..5).map(|_| client.submit_job(Job::from_bytes(wasm_bytes.clone()).max_retries(0))).collect();
+    for result in wait_all(jobs).await {
+        assert!(result.is_err(), "{result:?}");
+    }
+}
+
+#[tokio::test]
+async fn jobs_stop_waiting_for_an_upload_that_takes_too_long() {
+    let cluster = Cluster::start_with(coordinating(Duration::from_millis(300)), 1, |_| {}).await;
+    let client = cluster.client().await;
+
+    // Holds the worker's only credit, so the upload waits in the queue past the wait
+    let blocker = client.submit_job(Job::from_bytes(component(&sleep_wat(2000))).max_retries(0));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let uploader = client.submit_job(Job::from_bytes(component(NOOP_WAT)).max_retries(0));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let waiters = (0..3).map(|_| client.submit_job(Job::from_bytes(component(NOOP_WAT)).max_retries(0))).collect();
+
+    for result in wait_all(waiters).await {
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("uploading this job's module"), "{error}");
+    }
+    assert!(uploader.wait().await.is_ok());
+    assert!(blocker.wait().await.is_ok());
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::{Cluster, register_fake_worker};
+use orchestrator::{Namespace, OrchestratorConfig};
+use shared::client_api_client::ClientApiClient;
+use shared::compat::PROTOCOL_VERSION;
+use shared::{CancelJobRequest, JobId, JobStatusRequest, WorkerRequest};
+use tonic::transport::Channel;
+use tonic::{Code, Request};
+
+const PASSWORD: &str = "hunter2";
+const TEAM_A: &str = "s3cret-a";
+const TEAM_B: &str = "s3cret-b";
+
+async fn start(tokens: bool) -> Cluster {
+    let client_tokens = match tokens {
+        true => HashMap::from([
+            (TEAM_A.to_string(), Namespace::new("team-a").unwrap()),
+            (TEAM_B.to_string(), Namespace::new("team-b").unwrap()),
+        ]),
+        false => HashMap::new(),
+    };
+    let config = OrchestratorConfig { client_password: Some(PASSWORD.to_string()), client_tokens, ..Default::default() };
+    Cluster::start(config, 0).await
+}
+
+async fn connect(cluster: &Cluster) -> ClientApiClient<Channel> {
+    ClientApiClient::connect(cluster.url.clone()).await.unwrap()
+}
+
+fn authorized<T>(message: T, secret: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert("authorization", secret.parse().unwrap());
+    request
+}
+
+/// Submits a job with the secret, naming the namespace if any, and returns its id once a
+/// worker was assigned.
+async fn submit(cluster: &Cluster, secret: &str, namespace: Option<&str>) -> Result<JobId, tonic::Status> {
+    let job_id = JobId::random();
+    let request = WorkerRequest { job_id: job_id.to_bytes(), protocol_version: PROTOCOL_VERSION, namespace: namespace.map(str::to_string), ..Default::default() };
+    connect(cluster).await.request_worker(authorized(request, secret)).await?;
+    Ok(job_id)
+}
+
+async fn status(cluster: &Cluster, secret: &str, job_id: JobId, namespace: Option<&str>) -> Result<String, tonic::Status> {
+    let request = JobStatusRequest { job_id: job_id.to_bytes(), namespace: namespace.map(str::to_string) };
+    let response = connect(cluster).await.get_job_status(authorized(request, secret)).await?;
+    Ok(response.into_inner().state)
+}
+
+#[tokio::test]
+async fn tokens_only_see_their_own_namespace() {
+    let cluster = start(true).await;
+    let _worker = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+    let job_id = submit(&cluster, TEAM_A, None).await.unwrap();
+
+    assert_eq!(status(&cluster, TEAM_A, job_id, None).await.unwrap(), "dispatched");
+    assert_eq!(status(&cluster, TEAM_B, job_id, None).await.unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(status(&cluster, TEAM_B, job_id, Some("team-a")).await.unwrap_err().code(), Code::PermissionDenied);
+
+    let cancel = CancelJobRequest { job_id: job_id.to_bytes(), namespace: None };
+    let denied = connect(&cluster).await.cancel_job(authorized(cancel, TEAM_B)).await.unwrap_err();
+    assert_eq!(denied.code(), Code::PermissionDenied);
+}
+
+#[tokio::test]
+async fn password_is_pinned_to_the_default_namespace_alongside_tokens() {
+    let cluster = start(true).await;
+    let _worker = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+    let team_job = submit(&cluster, TEAM_A, None).await.unwrap();
+
+    // Naming a token's namespace with the password is refused, for submitting and looking up
+    assert_eq!(submit(&cluster, PASSWORD, Some("team-a")).await.unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(status(&cluster, PASSWORD, team_job, Some("team-a")).await.unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(status(&cluster, PASSWORD, team_job, None).await.unwrap_err().code(), Code::PermissionDenied);
+
+    let _worker = register_fake_worker(&cluster.url, "10.0.0.1:7001").await;
+    let default_job = submit(&cluster, PASSWORD, Some(Namespace::DEFAULT)).await.unwrap();
+    assert_eq!(status(&cluster, PASSWORD, default_job, None).await.unwrap(), "dispatched");
+    assert_eq!(status(&cluster, TEAM_A, default_job, None).await.unwrap_err().code(), Code::PermissionDenied);
+}
+
+#[tokio::test]
+async fn password_chooses_a_namespace_without_tokens() {
+    let cluster = start(false).await;
+    let _worker = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+    let job_id = submit(&cluster, PASSWORD, Some("team-a")).await.unwrap();
+
+    assert_eq!(status(&cluster, PASSWORD, job_id, Some("team-a")).await.unwrap(), "dispatched");
+    assert_eq!(status(&cluster, PASSWORD, job_id, None).await.unwrap_err().code(), Code::PermissionDenied);
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use client::{Job, JobError, JobId};
+use common::{Cluster, component};
+use orchestrator::OrchestratorConfig;
+use shared::client_api_client::ClientApiClient;
+use shared::compat::PROTOCOL_VERSION;
+use shared::executor_client::ExecutorClient;
+use shared::{FetchOutputRequest, JobRequest, WorkerRequest};
+use tonic::{Code, Request};
+use worker::WorkerConfig;
+
+/// Bytes of stdout OUTPUT_WAT writes.
+const OUTPUT_BYTES: usize = 16 * 4096;
+
+/// A component that writes OUTPUT_BYTES bytes of "x" to its stdout, 4 KiB at a time.
+const OUTPUT_WAT: &str = r#"
+(component
+  (import "wasi:io/error@0.2.0" (instance $error
+    (export "error" (type (sub resource)))))
+  (alias export $error "error" (type $error-type))
+  (import "wasi:io/streams@0.2.0" (instance $streams
+    (export "output-stream" (type (sub resource)))
+    (alias outer 1 $error-type (type))
+    (export "error" (type (eq 1)))
+    (type (own 2))
+    (type (variant (case "last-operation-failed" 3) (case "closed")))
+    (export "stream-error" (type (eq 4)))
+    (type (borrow 0))
+    (type (list u8))
+    (type (result (error 5)))
+    (type (func (param "self" 6) (param "contents" 7) (result 8)))
+    (export "[method]output-stream.blocking-write-and-flush" (func (type 9)))))
+  (alias export $streams "output-stream" (type $output-stream))
+  (import "wasi:cli/stdout@0.2.0" (instance $stdout
+    (alias outer 1 $output-stream (type))
+    (export "output-stream" (type (eq 0)))
+    (type (own 1))
+    (type (func (result 2)))
+    (export "get-stdout" (func (type 3)))))
+
+  (core module $memory
+    (memory (export "memory") 1))
+  (core instance $memory (instantiate $memory))
+  (alias core export $memory "memory" (core memory $mem))
+  (core func $get-stdout (canon lower (func $stdout "get-stdout")))
+  (core func $write (canon lower (func $streams "[method]output-stream.blocking-write-and-flush") (memory $mem)))
+
+  (core module $m
+    (import "host" "memory" (memory 1))
+    (import "host" "get-stdout" (func $get-stdout (result i32)))
+    (import "host" "write" (func $write (param i32 i32 i32 i32)))
+    (func (export "run") (result i32)
+      (local $stdout i32) (local $written i32)
+      (local.set $stdout (call $get-stdout))
+      (memory.fill (i32.const 8192) (i32.const 120) (i32.const 4096))
+      ;; Write results land at 1024, a tag set if the write failed
+      (loop $write
+        (call $write (local.get $stdout) (i32.const 8192) (i32.const 4096) (i32.const 1024))
+        (if (i32.load8_u (i32.const 1024)) (then (return (i32.const 1))))
+        (local.set $written (i32.add (local.get $written) (i32.const 1)))
+        (br_if $write (i32.lt_u (local.get $written) (i32.const 16))))
+      (i32.const 0)))
+  (core instance $i (instantiate $m
+    (with "host" (instance
+      (export "memory" (memory $mem))
+      (export "get-stdout" (func $get-stdout))
+      (export "write" (func $write))))))
+  (func $run (result (result)) (canon lift (core func $i "run")))
+  (instance $run (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run)))
+"#;
+
+/// A directory of the test's own for workers to store outputs in.
+fn output_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("mini-lambda-test-outputs-{}", JobId::random()))
+}
+
+fn authorized<T>(message: T, jwt: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert("authorization", jwt.parse().unwrap());
+    request
+}
+
+/// Starts a worker that stores outputs larger than threshold, and adjusts its config further.
+async fn start(threshold: usize, configure: impl Fn(&mut WorkerConfig)) -> Cluster {
+    let dir = output_dir();
+    Cluster::start_with(OrchestratorConfig::default(), 1, |config| {
+        config.offload_output_bytes = threshold;
+        config.output_dir = dir.clone();
+        configure(config);
+    }).await
+}
+
+#[tokio::test]
+async fn output_at_the_threshold_is_returned_inline() {
+    let cluster = start(OUTPUT_BYTES, |_| {}).await;
+    let output = cluster.client().await.submit_job(Job::from_bytes(component(OUTPUT_WAT))).wait().await.unwrap();
+    assert!(!output.output_offloaded);
+    assert_eq!(output.stdout, vec![b'x'; OUTPUT_BYTES]);
+}
+
+#[tokio::test]
+async fn output_past_the_threshold_is_fetched_and_verified() {
+    let cluster = start(OUTPUT_BYTES - 1, |_| {}).await;
+    let output = cluster.client().await.submit_job(Job::from_bytes(component(OUTPUT_WAT))).wait().await.unwrap();
+    assert!(output.output_offloaded);
+    assert_eq!(output.stdout, vec![b'x'; OUTPUT_BYTES]);
+}
+
+#[tokio::test]
+async fn expired_output_fails_the_job_clearly() {
+    let cluster = start(1024, |config| config.output_retention = Duration::ZERO).await;
+    match cluster.client().await.submit_job(Job::from_bytes(component(OUTPUT_WAT))).wait().await {
+        Err(JobError::OutputUnavailable(message)) => assert!(message.contains("retention passed"), "{message}"),
+        result => panic!("expected the output to have expired, got {result:?}"),
+    }
+}
+
+#[tokio::test]
+async fn stored_output_is_capped() {
+    let cluster = start(1024, |config| config.max_offloaded_output_bytes = OUTPUT_BYTES as u64 / 2).await;
+    match cluster.client().await.submit_job(Job::from_bytes(component(OUTPUT_WAT))).wait().await {
+        Err(JobError::Exited { code: 1, .. }) => {},
+        result => panic!("expected the job to fail, got {result:?}"),
+    }
+}
+
+#[tokio::test]
+async fn output_is_only_fetched_by_its_job() {
+    let cluster = start(1024, |config| config.credits = 2).await;
+    let mut orchestrator = ClientApiClient::connect(cluster.url.clone()).await.unwrap();
+    let mut assign = async || {
+        let job_id = JobId::random();
+        let request = WorkerRequest { job_id: job_id.to_bytes(), protocol_version: PROTOCOL_VERSION, ..Default::default() };
+        let response = orchestrator.request_worker(request).await.unwrap().into_inner();
+        (job_id, response.worker_address, response.jwt_token)
+    };
+
+    let (job_id, worker_address, jwt) = assign().await;
+    let mut worker = ExecutorClient::connect(format!("http://{worker_address}")).await.unwrap();
+    let job = JobRequest { job_id: job_id.to_bytes(), wasm_bytes: component(OUTPUT_WAT), protocol_version: PROTOCOL_VERSION, offload_output: true, ..Default::default() };
+    let output_ref = worker.execute_job(authorized(job, &jwt)).await.unwrap().into_inner().output_ref.unwrap();
+
+    let fetch = |job_id: JobId| FetchOutputRequest { job_id: job_id.to_bytes(), output_id: output_ref.id.clone(), offset: 0, max_bytes: 4096 };
+    let (other_job_id, _, other_jwt) = assign().await;
+    let denied = worker.fetch_output(authorized(fetch(other_job_id), &other_jwt)).await.unwrap_err();
+    assert_eq!(denied.code(), Code::PermissionDenied);
+
+    let part = worker.fetch_output(authorized(fetch(job_id), &jwt)).await.unwrap().into_inner();
+    assert_eq!((part.data, part.eof), (vec![b'x'; 4096], false));
+}
+
+#[tokio::test]
+async fn stale_outputs_are_deleted_on_startup() {
+    let dir = output_dir();
+    let stopped_worker = dir.join("10.0.0.1_7000");
+    std::fs::create_dir_all(&stopped_worker).unwrap();
+    let stale = stopped_worker.join("stale");
+    let fresh = stopped_worker.join("fresh");
+    std::fs::write(&fresh, b"output").unwrap();
+    std::fs::File::create(&stale).unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(7200)).unwrap();
+
+    let _cluster = Cluster::start_with(OrchestratorConfig::default(), 1, |config| config.output_dir = dir.clone()).await;
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while stale.exists() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.expect("the stale output wasn't deleted");
+    assert!(fresh.exists());
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use client::{Job, JobError, Pipeline};
+use common::{BANG_WAT, Cluster, TRAP_WAT, component};
+use orchestrator::OrchestratorConfig;
+
+/// Each stage reads the stdout of the stage before it, and the client gets the last one's.
+#[tokio::test]
+async fn stages_pass_their_output_along() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 2).await;
+    let client = cluster.client().await;
+
+    let pipeline = Pipeline::new()
+        .stdin(b"hello".to_vec())
+        .stage(Job::from_bytes(component(BANG_WAT)))
+        .stage(Job::from_bytes(component(BANG_WAT)))
+        .stage(Job::from_bytes(component(BANG_WAT)));
+    let output = client.run_pipeline(&pipeline).await.unwrap();
+
+    assert!(output.failure.is_none(), "{:?}", output.failure);
+    assert_eq!(output.stdout, b"hello!!!");
+    let stdout_bytes: Vec<u64> = output.stages.iter().map(|stage| stage.stdout_bytes).collect();
+    assert_eq!(stdout_bytes, [6, 7, 8]);
+}
+
+/// The first stage to fail stops the pipeline, and is named in its output.
+#[tokio::test]
+async fn failing_stage_stops_the_pipeline() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+
+    let pipeline = Pipeline::new()
+        .stage(Job::from_bytes(component(BANG_WAT)))
+        .stage(Job::from_bytes(component(TRAP_WAT)))
+        .stage(Job::from_bytes(component(BANG_WAT)));
+    let output = client.run_pipeline(&pipeline).await.unwrap();
+
+    assert_eq!(output.stages.len(), 1);
+    assert!(output.stdout.is_empty());
+    let failure = output.failure.expect("the second stage should have failed");
+    assert_eq!(failure.stage, 1);
+    assert!(matches!(failure.error, JobError::WasmError(_)), "{:?}", failure.error);
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use std::time::Duration;
+
+use common::Cluster;
+use orchestrator::OrchestratorConfig;
+use shared::client_api_client::ClientApiClient;
+use shared::compat::PROTOCOL_VERSION;
+use shared::{JobId, QueueStatsRequest, WorkerRequest};
+
+/// Waits until the orchestrator reports the given number of queued jobs.
+async fn wait_for_queued_jobs(url: &str, expected: u64) {
+    let mut client = ClientApiClient::connect(url.to_string()).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while client.get_queue_stats(QueueStatsRequest {}).await.unwrap().into_inner().queued_jobs != expected {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.unwrap_or_else(|_| panic!("the queue never held {expected} jobs"));
+}
+
+#[tokio::test]
+async fn disconnected_requesters_leave_the_queue() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 0).await;
+    let url = cluster.url.clone();
+    let waiting = tokio::spawn(async move {
+        let mut client = ClientApiClient::connect(url).await.unwrap();
+        let request = WorkerRequest { job_id: JobId::random().to_bytes(), protocol_version: PROTOCOL_VERSION, ..Default::default() };
+        client.request_worker(request).await
+    });
+
+    wait_for_queued_jobs(&cluster.url, 1).await;
+    waiting.abort();
+    wait_for_queued_jobs(&cluster.url, 0).await;
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use std::time::Duration;
+
+use client::ErrorCode;
+use common::{Cluster, register_fake_worker};
+use orchestrator::{ClientQuotas, Namespace, OrchestratorConfig};
+use shared::client_api_client::ClientApiClient;
+use shared::compat::PROTOCOL_VERSION;
+use shared::{JobId, WorkerRequest, WorkerResponse};
+use tonic::{Code, Request, Status};
+
+/// Asks for a worker for a new job over a connection of its own, as a client run would.
+async fn request_worker(url: &str) -> Result<WorkerResponse, Status> {
+    let mut client = ClientApiClient::connect(url.to_string()).await.unwrap();
+    let request = WorkerRequest { job_id: JobId::random().to_bytes(), protocol_version: PROTOCOL_VERSION, ..Default::default() };
+    client.request_worker(request).await.map(|response| response.into_inner())
+}
+
+fn concurrency_limit(limit: u32) -> OrchestratorConfig {
+    OrchestratorConfig {
+        client_quotas: ClientQuotas { max_concurrent_jobs: Some(limit), ..Default::default() },
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn new_connections_share_the_quota() {
+    let cluster = Cluster::start(concurrency_limit(1), 0).await;
+    let _worker = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+
+    request_worker(&cluster.url).await.unwrap();
+    let status = request_worker(&cluster.url).await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert_eq!(ErrorCode::of(&status), Some(ErrorCode::QuotaExceeded));
+    assert!(status.message().contains("max_concurrent_jobs"), "{}", status.message());
+}
+
+#[tokio::test]
+async fn worker_disconnect_releases_the_quota() {
+    let cluster = Cluster::start(concurrency_limit(1), 0).await;
+    let worker = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+    request_worker(&cluster.url).await.unwrap();
+
+    // The client never hears back about the job once its worker is gone
+    drop(worker);
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while cluster.orchestrator.diagnostics.workers.get("10.0.0.1:7000").is_some_and(|w| w.disconnected_at.is_none()) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.unwrap();
+
+    let _worker = register_fake_worker(&cluster.url, "10.0.0.1:7001").await;
+    let response = request_worker(&cluster.url).await.unwrap();
+    assert_eq!(response.worker_address, "10.0.0.1:7001");
+}
+
+#[tokio::test]
+async fn daily_quota_is_not_retried() {
+    let config = OrchestratorConfig {
+        client_quotas: ClientQuotas { max_jobs_per_day: Some(1), ..Default::default() },
+        ..Default::default()
+    };
+    let cluster = Cluster::start(config, 0).await;
+    let _worker = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+
+    request_worker(&cluster.url).await.unwrap();
+    let status = request_worker(&cluster.url).await.unwrap_err();
+    assert_eq!(ErrorCode::of(&status), Some(ErrorCode::DailyQuotaExceeded));
+}
+
+#[tokio::test]
+async fn quotas_show_each_client_usage() {
+    let cluster = Cluster::start(concurrency_limit(4), 0).await;
+    let _worker = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+    request_worker(&cluster.url).await.unwrap();
+
+    let quotas = cluster.client().await.quotas().await.unwrap();
+    assert_eq!(quotas.max_concurrent_jobs, Some(4));
+    assert_eq!(quotas.max_jobs_per_day, None);
+    assert_eq!(quotas.clients.len(), 1);
+    let client = &quotas.clients[0];
+    assert_eq!(client.client, "127.0.0.1");
+    assert_eq!((client.queued_jobs, client.active_jobs, client.jobs_today), (0, 1, 1));
+}
+
+/// Asks for a worker for a new job as the client holding token.
+async fn request_worker_as(url: &str, token: &str) -> Result<WorkerResponse, Status> {
+    let mut client = ClientApiClient::connect(url.to_string()).await.unwrap();
+    let mut request = Request::new(WorkerRequest { job_id: JobId::random().to_bytes(), protocol_version: PROTOCOL_VERSION, ..Default::default() });
+    request.metadata_mut().insert("authorization", token.parse().unwrap());
+    client.request_worker(request).await.map(|response| response.into_inner())
+}
+
+#[tokio::test]
+async fn full_queue_refuses_jobs() {
+    let config = OrchestratorConfig {
+        client_tokens: ["a", "b", "c"].map(|team| (format!("token-{team}"), Namespace::new(team).unwrap())).into(),
+        client_quotas: ClientQuotas { max_queued_jobs: Some(1), ..Default::default() },
+        max_queued_jobs: Some(2),
+        ..Default::default()
+    };
+    let cluster = Cluster::start(config, 0).await;
+
+    // With no workers, jobs wait in the queue
+    for token in ["token-a", "token-b"] {
+        let url = cluster.url.clone();
+        tokio::spawn(async move { request_worker_as(&url, token).await });
+    }
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while cluster.orchestrator.job_queue.lock().await.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }).await.unwrap();
+
+    let status = request_worker_as(&cluster.url, "token-c").await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert_eq!(ErrorCode::of(&status), Some(ErrorCode::QueueFull));
+}
+
+#[test]
+fn per_client_queue_quota_must_be_below_the_queue_limit() {
+    let config = |per_client, total| OrchestratorConfig {
+        client_quotas: ClientQuotas { max_queued_jobs: Some(per_client), ..Default::default() },
+        max_queued_jobs: Some(total),
+        ..Default::default()
+    };
+    assert!(config(2, 2).validate().is_err());
+    config(1, 2).validate().unwrap();
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::time::Duration;
+
+use client::{Client, Job};
+use common::{BANG_WAT, component};
+use orchestrator::{Orchestrator, OrchestratorConfig, build_router};
+use tokio::runtime::Runtime;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use worker::{Worker, WorkerConfig, WorkerHandle};
+
+/// An orchestrator served from a runtime of its own, so that shutting the runtime down kills
+/// it the way a crash would: every connection to it drops at once.
+struct Killable {
+    orchestrator: Orchestrator,
+    url: String,
+    runtime: Runtime,
+}
+
+impl Killable {
+    fn start() -> Self {
+        let runtime = Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let orchestrator = Orchestrator::start(OrchestratorConfig::default());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+        runtime.spawn(Server::builder().add_routes(build_router(&orchestrator)).serve_with_incoming(TcpListenerStream::new(listener)));
+        Self { orchestrator, url, runtime }
+    }
+
+    fn kill(self) {
+        self.runtime.shutdown_background();
+    }
+
+    /// Workers registered and still connected.
+    fn workers(&self) -> usize {
+        self.orchestrator.diagnostics.workers.iter().filter(|w| w.disconnected_at.is_none()).count()
+    }
+
+    async fn wait_for_workers(&self, count: usize) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while self.workers() != count {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }).await.unwrap_or_else(|_| panic!("{count} workers didn't register with {}", self.url));
+    }
+
+    async fn client(&self) -> Client {
+        Client::connect(&self.url, None, false).await.unwrap()
+    }
+}
+
+async fn start_workers(count: usize, orchestrators: &[&Killable]) -> Vec<WorkerHandle> {
+    let mut workers = Vec::new();
+    for _ in 0..count {
+        let mut config = WorkerConfig::new("127.0.0.1", 1);
+        config.orchestrators = orchestrators.iter().map(|o| o.url.clone()).collect();
+        workers.push(Worker::start(config).await.unwrap());
+    }
+    workers
+}
+
+async fn run_jobs(client: &Client, count: usize) {
+    let jobs: Vec<_> = (0..count).map(|_| client.submit_job(Job::from_bytes(component(BANG_WAT)).max_retries(0))).collect();
+    for job in jobs {
+        assert_eq!(job.wait().await.unwrap().stdout, b"!");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn workers_keep_serving_when_one_of_their_orchestrators_dies() {
+    let first = Killable::start();
+    let second = Killable::start();
+    let _workers = start_workers(2, &[&first, &second]).await;
+    first.wait_for_workers(2).await;
+    second.wait_for_workers(2).await;
+
+    let first_client = first.client().await;
+    let second_client = second.client().await;
+    tokio::join!(run_jobs(&first_client, 4), run_jobs(&second_client, 4));
+
+    first.kill();
+    // Give the workers a moment to notice, so the jobs below run after they have
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(tokio::time::timeout(Duration::from_secs(1), first_client.submit_job(Job::from_bytes(component(BANG_WAT)).max_retries(0)).wait()).await
+        .is_ok_and(|result| result.is_err()), "the killed orchestrator still ran a job");
+
+    tokio::time::timeout(Duration::from_secs(10), run_jobs(&second_client, 6)).await
+        .expect("the surviving orchestrator stopped serving jobs");
+    assert_eq!(second.workers(), 2);
+    let executed: u32 = second.orchestrator.diagnostics.workers.iter().map(|w| w.jobs_received).sum();
+    assert_eq!(executed, 10);
+    second.kill();
+}
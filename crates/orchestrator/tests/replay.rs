@@ -0,0 +1,45 @@
+mod common;
+
+use client::{ClientError, Job, Pipeline};
+use common::{BANG_WAT, Cluster, component};
+use orchestrator::OrchestratorConfig;
+
+/// A replayed pipeline stage reads the stdin the stage read, which the orchestrator relayed.
+#[tokio::test]
+async fn replayed_stages_read_their_stdin_again() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+
+    let pipeline = Pipeline::new()
+        .stdin(b"hello".to_vec())
+        .stage(Job::from_bytes(component(BANG_WAT)))
+        .stage(Job::from_bytes(component(BANG_WAT)));
+    let output = client.run_pipeline(&pipeline).await.unwrap();
+    assert_eq!(output.stdout, b"hello!!");
+
+    let submission = client.job_submission(output.stages[1].job_id).await.unwrap();
+    assert_eq!(submission.stdin.as_deref(), Some(&b"hello!"[..]));
+    let replayed = client.submit_job(submission.replay(None).unwrap()).wait().await.unwrap();
+    assert_eq!(replayed.stdout, b"hello!!");
+}
+
+/// The stdin a replay is given must be what the job read.
+#[tokio::test]
+async fn replays_are_given_the_stdin_the_job_read() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let client = cluster.client().await;
+
+    // The orchestrator never sees the stdin of a job sent straight to its worker, so keeps none
+    let job = client.submit_job(Job::from_bytes(component(BANG_WAT)).stdin(b"direct".to_vec()));
+    let job_id = job.job_id();
+    assert_eq!(job.wait().await.unwrap().stdout, b"direct!");
+
+    let submission = client.job_submission(job_id).await.unwrap();
+    assert!(submission.stdin.is_none());
+    assert!(matches!(submission.replay(None), Err(ClientError::StdinUnavailable(_))));
+    assert!(matches!(submission.replay(Some(b"other".to_vec())), Err(ClientError::StdinMismatch(_))));
+    assert!(matches!(submission.replay(Some(Vec::new())), Err(ClientError::StdinMismatch(_))));
+
+    let replay = submission.replay(Some(b"direct".to_vec())).unwrap();
+    assert_eq!(client.submit_job(replay).wait().await.unwrap().stdout, b"direct!");
+}
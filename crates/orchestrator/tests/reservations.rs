@@ -0,0 +1,17 @@
+mod common;
+
+use client::Job;
+use common::{Cluster, NOOP_WAT, component};
+use orchestrator::OrchestratorConfig;
+
+#[tokio::test]
+async fn workers_requiring_reservations_run_dispatched_jobs() {
+    let cluster = Cluster::start_with(OrchestratorConfig::default(), 1, |config| config.require_reservation = true).await;
+    let client = cluster.client().await;
+
+    // One credit, so each job only runs once the last one's reservation was redeemed and its
+    // credit returned
+    for _ in 0..3 {
+        client.submit_job(Job::from_bytes(component(NOOP_WAT)).max_retries(0)).wait().await.unwrap();
+    }
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use client::{Job, JobError};
+use common::{Cluster, TRAP_WAT, component};
+use orchestrator::OrchestratorConfig;
+
+/// A module that traps fails the same way on every worker, so it runs once however many
+/// retries its job allows.
+#[tokio::test]
+async fn trapping_module_runs_once() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 2).await;
+    let client = cluster.client().await;
+
+    let job = client.submit_job(Job::from_bytes(component(TRAP_WAT)).max_retries(3));
+    let job_id = job.job_id();
+    match job.wait().await {
+        Err(JobError::WasmError(message)) => assert!(message.contains("trapped"), "{message}"),
+        result => panic!("expected the job to trap, got {result:?}"),
+    }
+    assert_eq!(cluster.retries(job_id), 0);
+}
+
+/// Jobs are retried by default, which mustn't run a failing module more than once either.
+#[tokio::test]
+async fn trapping_module_runs_once_by_default() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 2).await;
+    let client = cluster.client().await;
+
+    let job = client.submit_job(Job::from_bytes(component(TRAP_WAT)));
+    let job_id = job.job_id();
+    assert!(matches!(job.wait().await, Err(JobError::WasmError(_))));
+    assert_eq!(cluster.retries(job_id), 0);
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use std::time::Duration;
+
+use client::{Job, JobError};
+use common::{Cluster, NOOP_WAT, component};
+use orchestrator::OrchestratorConfig;
+
+#[tokio::test]
+async fn timeout_counts_from_the_start_time() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 1).await;
+    let job = Job::from_bytes(component(NOOP_WAT))
+        .delay(Duration::from_secs(1))
+        .timeout(Duration::from_millis(800));
+    cluster.client().await.submit_job(job).wait().await.unwrap();
+}
+
+#[tokio::test]
+async fn scheduled_jobs_still_time_out() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 0).await;
+    let job = Job::from_bytes(component(NOOP_WAT))
+        .delay(Duration::from_millis(200))
+        .timeout(Duration::from_millis(200));
+    match cluster.client().await.submit_job(job).wait().await {
+        Err(JobError::TimedOut) => {},
+        result => panic!("expected the job to time out, got {result:?}"),
+    }
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use std::time::Duration;
+
+use client::Job;
+use common::{Cluster, NOOP_WAT, component};
+use orchestrator::OrchestratorConfig;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+
+/// First byte of a TLS handshake record, which a ClientHello starts with.
+const TLS_HANDSHAKE: u8 = 0x16;
+
+#[tokio::test]
+async fn workers_are_reached_at_their_tls_address_over_https() {
+    let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tls_address = proxy.local_addr().unwrap().to_string();
+    let cluster = Cluster::start_with(OrchestratorConfig::default(), 1, |config| config.tls_address = Some(tls_address.clone())).await;
+
+    let job = cluster.client().await.submit_job(Job::from_bytes(component(NOOP_WAT)).max_retries(0));
+    let (mut stream, _) = tokio::time::timeout(Duration::from_secs(10), proxy.accept()).await
+        .expect("the client didn't connect to the worker's TLS address")
+        .unwrap();
+    assert_eq!(stream.read_u8().await.unwrap(), TLS_HANDSHAKE);
+
+    // Nothing answers the handshake, so the job can't reach its worker
+    drop(stream);
+    assert!(job.wait().await.is_err());
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use std::time::Duration;
+
+use client::Job;
+use common::{Cluster, NOOP_WAT, component};
+use hmac::{Hmac, KeyInit, Mac};
+use orchestrator::OrchestratorConfig;
+use sha2::Sha256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+const SECRET: &str = "webhook-secret";
+
+/// A request the mock receiver got: its headers, lowercased, and its body.
+struct Received {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Received {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serves HTTP on a free port, answering every request with the given status line and
+/// headers, and passing each request on. Returns the receiver's URL.
+async fn receiver(response: &'static str) -> (String, mpsc::UnboundedReceiver<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/done", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            let header_end = loop {
+                let read = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..read]);
+                if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break end;
+                }
+            };
+            let headers: Vec<(String, String)> = String::from_utf8_lossy(&request[..header_end]).lines().skip(1)
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+                .collect();
+            let length: usize = headers.iter().find(|(n, _)| n == "content-length").map_or(0, |(_, v)| v.parse().unwrap());
+            while request.len() < header_end + 4 + length {
+                let read = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..read]);
+            }
+            let body = request[header_end + 4..].to_vec();
+            stream.write_all(format!("{response}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n").as_bytes()).await.unwrap();
+            let _ = tx.send(Received { headers, body });
+        }
+    });
+    (url, rx)
+}
+
+async fn start() -> Cluster {
+    let config = OrchestratorConfig {
+        webhook_secret: Some(SECRET.to_string()),
+        webhook_allowed_hosts: Some(vec!["127.0.0.1".to_string()]),
+        ..Default::default()
+    };
+    Cluster::start(config, 1).await
+}
+
+async fn next(rx: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+    tokio::time::timeout(Duration::from_secs(10), rx.recv()).await
+        .expect("no webhook arrived")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn completion_is_posted_and_signed() {
+    let cluster = start().await;
+    let (url, mut rx) = receiver("HTTP/1.1 204 No Content").await;
+
+    let job = cluster.client().await.submit_job(Job::from_bytes(component(NOOP_WAT)).callback_url(url));
+    let job_id = job.job_id();
+    job.wait().await.unwrap();
+
+    let received = next(&mut rx).await;
+    let completion: serde_json::Value = serde_json::from_slice(&received.body).unwrap();
+    assert_eq!(completion["job_id"], job_id.to_string());
+    assert_eq!(completion["state"], "completed");
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+    mac.update(&received.body);
+    let expected: String = mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect();
+    assert_eq!(received.header("x-mini-lambda-signature"), Some(expected.as_str()));
+}
+
+#[tokio::test]
+async fn redirects_are_not_followed() {
+    let cluster = start().await;
+    let (target, mut redirected) = receiver("HTTP/1.1 204 No Content").await;
+    let redirect: &'static str = format!("HTTP/1.1 307 Temporary Redirect\r\nlocation: {target}").leak();
+    let (url, mut rx) = receiver(redirect).await;
+
+    cluster.client().await.submit_job(Job::from_bytes(component(NOOP_WAT)).callback_url(url)).wait().await.unwrap();
+
+    // Each attempt is refused with the redirect, and none of them reaches its target
+    for _ in 0..3 {
+        next(&mut rx).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(redirected.try_recv().is_err());
+}
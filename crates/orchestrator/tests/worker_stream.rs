@@ -0,0 +1,62 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Cluster, open_worker_stream, register_fake_worker};
+use orchestrator::OrchestratorConfig;
+use shared::compat::PROTOCOL_VERSION;
+use shared::{CreditUpdate, RelayedReply, WorkerMessage, WorkerRegistration, worker_message};
+use tonic::Code;
+
+fn connected(cluster: &Cluster, address: &str) -> bool {
+    cluster.orchestrator.diagnostics.workers.get(address).is_some_and(|worker| worker.disconnected_at.is_none())
+}
+
+#[tokio::test]
+async fn stream_must_start_with_a_registration() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 0).await;
+
+    let (tx, mut inbound) = open_worker_stream(&cluster.url).await;
+    tx.send(WorkerMessage { message: Some(worker_message::Message::CreditUpdate(CreditUpdate { delta: 1, ..Default::default() })) }).await.unwrap();
+    let status = inbound.message().await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    // The orchestrator carries on
+    register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+}
+
+#[tokio::test]
+async fn empty_relayed_reply_drops_only_that_worker() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 0).await;
+    let (_healthy_tx, _healthy) = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+    let (tx, mut inbound) = register_fake_worker(&cluster.url, "10.0.0.1:7001").await;
+
+    tx.send(WorkerMessage { message: Some(worker_message::Message::RelayedReply(RelayedReply { call_id: 1, reply: None })) }).await.unwrap();
+    let status = inbound.message().await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    cluster.wait_for_workers(1).await;
+    assert!(connected(&cluster, "10.0.0.1:7000"));
+}
+
+#[tokio::test]
+async fn second_registration_drops_the_worker() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 0).await;
+    let (tx, mut inbound) = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+
+    let registration = WorkerRegistration { address: "10.0.0.1:7000".to_string(), credits: 1, protocol_version: PROTOCOL_VERSION, relayed: true, ..Default::default() };
+    tx.send(WorkerMessage { message: Some(worker_message::Message::Registration(registration)) }).await.unwrap();
+    assert_eq!(inbound.message().await.unwrap_err().code(), Code::InvalidArgument);
+}
+
+/// A message a newer worker sends that this orchestrator doesn't know is ignored.
+#[tokio::test]
+async fn unrecognized_messages_are_ignored() {
+    let cluster = Cluster::start(OrchestratorConfig::default(), 0).await;
+    let (tx, _inbound) = register_fake_worker(&cluster.url, "10.0.0.1:7000").await;
+
+    tx.send(WorkerMessage { message: None }).await.unwrap();
+    tx.send(WorkerMessage { message: Some(worker_message::Message::CreditUpdate(CreditUpdate::default())) }).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(connected(&cluster, "10.0.0.1:7000"));
+}
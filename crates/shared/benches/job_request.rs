@@ -0,0 +1,26 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use prost::Message;
+use shared::{JobId, JobRequest};
+
+const WASM_BYTES: usize = 5 * 1024 * 1024;
+
+/// Encoding and decoding a job request carrying a 5 MiB module, as sent on a cache miss.
+fn job_request(c: &mut Criterion) {
+    let request = JobRequest {
+        job_id: JobId::random().to_bytes(),
+        wasm_bytes: vec![0xab; WASM_BYTES],
+        wasm_hash: vec![0; 32],
+        args: (0..16).map(|i| format!("arg-{i}")).collect(),
+        ..Default::default()
+    };
+    let encoded = request.encode_to_vec();
+
+    let mut group = c.benchmark_group("job_request_5mib");
+    group.throughput(Throughput::Bytes(encoded.len() as u64));
+    group.bench_function("encode", |b| b.iter(|| request.encode_to_vec()));
+    group.bench_function("decode", |b| b.iter(|| JobRequest::decode(encoded.as_slice()).expect("valid encoding")));
+    group.finish();
+}
+
+criterion_group!(benches, job_request);
+criterion_main!(benches);
@@ -1,5 +1,12 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
     tonic_prost_build::configure()
+        // Served by the reflection service, see reflection.rs
+        .file_descriptor_set_path(out_dir.join("mini_lambda_descriptor.bin"))
+        // Keeps JobRequest small, as it is inlined in the relayed call oneof
+        .boxed(".executor.JobRequest.delta")
+        // Keeps OrchestratorMessage small, as a relayed call carries a whole JobRequest
+        .boxed(".worker_api.OrchestratorMessage.message.relayed_call")
         .compile_protos(
             &["proto/shared.proto", "proto/client_api.proto", "proto/worker_api.proto", "proto/executor.proto"], 
             &["proto"]
@@ -0,0 +1,114 @@
+use std::fmt;
+
+/// Version of the messages exchanged between the Orchestrator, Workers and clients. Bumped on
+/// any change that a peer built against the previous version couldn't safely ignore, such as
+/// a new message variant.
+///
+/// 2: Workers send Heartbeats, and the streams carry handoff, reservation and relay messages.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest protocol version this binary still talks to. Peers that speak it or any later
+/// version up to PROTOCOL_VERSION are accepted. Messages they don't send, or send but this side
+/// doesn't know, are ignored rather than treated as errors.
+pub const OLDEST_SUPPORTED_VERSION: u32 = 1;
+
+/// Peers built before protocol versions were sent leave the field unset, which reads as 0.
+/// They speak version 1.
+const UNVERSIONED: u32 = 0;
+
+/// The protocol version a peer speaks, given the version field of its message.
+pub fn peer_version(sent: u32) -> u32 {
+    if sent == UNVERSIONED { 1 } else { sent }
+}
+
+/// Checks that a peer, identified by the version field of its message, speaks a protocol
+/// version this side supports. A peer newer than this side is rejected, as it may rely on
+/// messages this side would ignore.
+pub fn check(sent: u32) -> Result<(), VersionMismatch> {
+    let theirs = peer_version(sent);
+    if (OLDEST_SUPPORTED_VERSION..=PROTOCOL_VERSION).contains(&theirs) {
+        Ok(())
+    } else {
+        Err(VersionMismatch { ours: PROTOCOL_VERSION, theirs })
+    }
+}
+
+/// A peer speaks a different protocol version than this binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionMismatch {
+    pub ours: u32,
+    pub theirs: u32,
+}
+
+impl fmt::Display for VersionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "protocol version mismatch: peer speaks v{}, this side speaks v{}", self.theirs, self.ours)
+    }
+}
+
+impl std::error::Error for VersionMismatch {}
+
+impl From<VersionMismatch> for tonic::Status {
+    fn from(e: VersionMismatch) -> Self {
+        crate::ErrorCode::VersionMismatch.status(tonic::Code::FailedPrecondition, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::worker_api::{CreditUpdate, WorkerMessage, WorkerRegistration, worker_message};
+
+    #[test]
+    fn unversioned_peers_speak_version_1() {
+        assert_eq!(peer_version(0), 1);
+        assert_eq!(peer_version(1), 1);
+        assert_eq!(peer_version(7), 7);
+        assert!(check(0).is_ok());
+    }
+
+    #[test]
+    fn supported_versions_are_accepted() {
+        for version in OLDEST_SUPPORTED_VERSION..=PROTOCOL_VERSION {
+            assert!(check(version).is_ok(), "v{version} was rejected");
+        }
+    }
+
+    #[test]
+    fn newer_peers_are_rejected() {
+        let e = check(PROTOCOL_VERSION + 1).unwrap_err();
+        assert_eq!(e, VersionMismatch { ours: PROTOCOL_VERSION, theirs: PROTOCOL_VERSION + 1 });
+        let message = e.to_string();
+        assert!(message.contains(&format!("v{}", PROTOCOL_VERSION + 1)) && message.contains(&format!("v{PROTOCOL_VERSION}")), "{message}");
+        let status = tonic::Status::from(e);
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(crate::ErrorCode::of(&status), Some(crate::ErrorCode::VersionMismatch));
+    }
+
+    #[test]
+    fn registrations_without_a_version_decode_as_version_1() {
+        // A registration as a worker that predates protocol versions encodes it
+        let registration = WorkerRegistration { address: "127.0.0.1:50052".to_string(), credits: 4, ..Default::default() };
+        let bytes = registration.encode_to_vec();
+        let decoded = WorkerRegistration::decode(bytes.as_slice()).unwrap();
+        assert_eq!(decoded.protocol_version, 0);
+        assert!(check(decoded.protocol_version).is_ok());
+    }
+
+    #[test]
+    fn unknown_message_variants_decode_as_empty() {
+        // A WorkerMessage whose oneof holds a variant this side doesn't know, field 99
+        let mut bytes = Vec::new();
+        prost::encoding::message::encode(99, &CreditUpdate { delta: 1, ..Default::default() }, &mut bytes);
+        let decoded = WorkerMessage::decode(bytes.as_slice()).unwrap();
+        assert_eq!(decoded.message, None);
+
+        // Known variants still decode next to unknown fields
+        let known = WorkerMessage { message: Some(worker_message::Message::CreditUpdate(CreditUpdate { delta: 2, ..Default::default() })) };
+        let mut bytes = known.encode_to_vec();
+        prost::encoding::message::encode(99, &CreditUpdate::default(), &mut bytes);
+        assert_eq!(WorkerMessage::decode(bytes.as_slice()).unwrap(), known);
+    }
+}
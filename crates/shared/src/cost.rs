@@ -0,0 +1,38 @@
+//! Estimating what a job cost, for chargeback. The Orchestrator holds the weights and sends them
+//! to Workers when they register, so a job's estimate in its result matches what its namespace
+//! was charged.
+
+use crate::{CostWeights, JobUsage};
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+impl CostWeights {
+    /// The job's cost: its execution time, a compile if its module wasn't cached, and the
+    /// module bytes sent to its worker, each at its weight.
+    pub fn estimate(&self, usage: &JobUsage) -> f64 {
+        let compile = if usage.compiled { self.per_compile } else { 0.0 };
+        self.per_execution_second * usage.execute_ms as f64 / 1000.0
+            + compile
+            + self.per_module_mb * usage.module_bytes as f64 / MIB
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEIGHTS: CostWeights = CostWeights { per_execution_second: 2.0, per_compile: 0.5, per_module_mb: 0.25 };
+
+    #[test]
+    fn each_resource_is_charged_at_its_weight() {
+        let usage = JobUsage { execute_ms: 1500, compiled: true, module_bytes: 4 * 1024 * 1024 };
+        assert_eq!(WEIGHTS.estimate(&usage), 3.0 + 0.5 + 1.0);
+    }
+
+    #[test]
+    fn cached_hash_only_jobs_pay_for_execution_alone() {
+        let usage = JobUsage { execute_ms: 250, compiled: false, module_bytes: 0 };
+        assert_eq!(WEIGHTS.estimate(&usage), 0.5);
+        assert_eq!(CostWeights::default().estimate(&usage), 0.0);
+    }
+}
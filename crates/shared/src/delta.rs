@@ -0,0 +1,35 @@
+//! Delta module uploads, which send a new version of a module as a patch against a version the
+//! Worker already has. A patch is the new version compressed with zstd using the base version
+//! as a raw content dictionary, as `zstd --patch-from` does, so only what changed takes space.
+
+use std::io;
+
+use zstd::bulk::{Compressor, Decompressor};
+use zstd::zstd_safe::{CParameter, DParameter};
+
+/// Compression level patches are made with.
+const LEVEL: i32 = 9;
+
+/// Largest zstd window, which bounds how far back in the base a patch can refer to.
+const MAX_WINDOW_LOG: u32 = 31;
+
+/// The patch that turns base into wasm_bytes.
+pub fn make_patch(base: &[u8], wasm_bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut compressor = Compressor::with_dictionary(LEVEL, base)?;
+    compressor.set_parameter(CParameter::WindowLog(window_log(base.len() + wasm_bytes.len())))?;
+    compressor.set_parameter(CParameter::EnableLongDistanceMatching(true))?;
+    compressor.compress(wasm_bytes)
+}
+
+/// Rebuilds a module from the base the patch was made against. Fails if the patch is malformed,
+/// was made against another base, or would rebuild more than max_bytes.
+pub fn apply_patch(base: &[u8], patch: &[u8], max_bytes: usize) -> io::Result<Vec<u8>> {
+    let mut decompressor = Decompressor::with_dictionary(base)?;
+    decompressor.set_parameter(DParameter::WindowLogMax(window_log(base.len() + max_bytes)))?;
+    decompressor.decompress(patch, max_bytes)
+}
+
+/// The smallest window log covering len bytes, within what zstd supports.
+fn window_log(len: usize) -> u32 {
+    (usize::BITS - len.leading_zeros()).clamp(10, MAX_WINDOW_LOG)
+}
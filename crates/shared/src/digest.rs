@@ -0,0 +1,230 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{ErrorCode, HashAlgorithm, ObjectStoreModule};
+
+/// A module digest labelled with the algorithm that made it, written `sha256:<hex>` or
+/// `blake3:<hex>`. Bare hex is read as SHA-256, which is all digests were before they were
+/// labelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleDigest {
+    pub algorithm: HashAlgorithm,
+    pub bytes: [u8; 32],
+}
+
+/// Why a digest was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DigestError {
+    /// Labelled with, or sent as, an algorithm this binary doesn't know.
+    UnsupportedAlgorithm(String),
+    /// Not 32 bytes, or not hex.
+    Malformed(String),
+}
+
+impl fmt::Display for DigestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DigestError::UnsupportedAlgorithm(algorithm) => write!(f, "unsupported hash algorithm {algorithm}, expected sha256 or blake3"),
+            DigestError::Malformed(reason) => write!(f, "malformed module digest: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for DigestError {}
+
+impl DigestError {
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            DigestError::UnsupportedAlgorithm(_) => ErrorCode::UnsupportedHashAlgorithm,
+            DigestError::Malformed(_) => ErrorCode::InvalidModuleDigest,
+        }
+    }
+}
+
+impl HashAlgorithm {
+    /// The label written before a digest made with the algorithm.
+    pub fn label(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Reads an algorithm sent in a protobuf field, where unset means the field's default.
+    pub fn from_proto(value: Option<i32>, default: Self) -> Result<Self, DigestError> {
+        match value {
+            None => Ok(default),
+            Some(value) => Self::try_from(value).map_err(|_| DigestError::UnsupportedAlgorithm(value.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = DigestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_label(s).ok_or_else(|| DigestError::UnsupportedAlgorithm(s.to_string()))
+    }
+}
+
+impl ModuleDigest {
+    pub fn new(algorithm: HashAlgorithm, bytes: [u8; 32]) -> Self {
+        Self { algorithm, bytes }
+    }
+
+    /// Parses `<algorithm>:<hex>`, or bare hex as SHA-256.
+    pub fn parse(s: &str) -> Result<Self, DigestError> {
+        let (algorithm, hex) = match s.split_once(':') {
+            Some((label, hex)) => (label.parse()?, hex),
+            None => (HashAlgorithm::Sha256, s),
+        };
+        let bytes = parse_hex(hex)
+            .ok_or_else(|| DigestError::Malformed(format!("expected 64 hex digits after {algorithm}:, got {hex}")))?;
+        Ok(Self { algorithm, bytes })
+    }
+
+    /// Reads the digest from raw bytes sent in a protobuf field with its algorithm.
+    pub fn from_proto(bytes: &[u8], algorithm: Option<i32>, default: HashAlgorithm) -> Result<Self, DigestError> {
+        let algorithm = HashAlgorithm::from_proto(algorithm, default)?;
+        let bytes = bytes.try_into()
+            .map_err(|_| DigestError::Malformed(format!("expected 32 bytes of {algorithm}, got {}", bytes.len())))?;
+        Ok(Self { algorithm, bytes })
+    }
+
+    /// The digest of the bytes made with the algorithm.
+    #[cfg(feature = "blake3")]
+    pub fn of(algorithm: HashAlgorithm, bytes: &[u8]) -> Self {
+        let digest = match algorithm {
+            HashAlgorithm::Sha256 => {
+                let mut digest = [0; 32];
+                digest.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, bytes).as_ref());
+                digest
+            },
+            HashAlgorithm::Blake3 => *blake3::hash(bytes).as_bytes(),
+        };
+        Self { algorithm, bytes: digest }
+    }
+
+    /// The digest as hex, without its label.
+    pub fn hex(&self) -> String {
+        self.bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+impl fmt::Display for ModuleDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.hex())
+    }
+}
+
+impl FromStr for ModuleDigest {
+    type Err = DigestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl ObjectStoreModule {
+    /// The object's digest. Modules that don't say which algorithm made it are SHA-256.
+    pub fn module_digest(&self) -> Result<ModuleDigest, DigestError> {
+        ModuleDigest::from_proto(&self.digest, self.hash_algorithm, HashAlgorithm::Sha256)
+    }
+
+    pub fn new(url: String, digest: ModuleDigest) -> Self {
+        Self { url, digest: digest.bytes.to_vec(), hash_algorithm: Some(digest.algorithm as i32) }
+    }
+}
+
+/// Reads exactly 64 hex digits. Checked digit by digit, as from_str_radix also takes a sign.
+fn parse_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    fn bytes() -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (i as u8 % 16) * 0x11;
+        }
+        bytes
+    }
+
+    #[test]
+    fn labelled_digests_round_trip() {
+        for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+            let digest: ModuleDigest = format!("{algorithm}:{HEX}").parse().unwrap();
+            assert_eq!(digest, ModuleDigest::new(algorithm, bytes()));
+            assert_eq!(digest.to_string(), format!("{}:{HEX}", algorithm.label()));
+            assert_eq!(digest.to_string().parse::<ModuleDigest>().unwrap(), digest);
+        }
+    }
+
+    #[test]
+    fn bare_hex_is_read_as_sha256() {
+        assert_eq!(ModuleDigest::parse(HEX).unwrap(), ModuleDigest::new(HashAlgorithm::Sha256, bytes()));
+        assert_eq!(ModuleDigest::parse(&HEX.to_uppercase()).unwrap(), ModuleDigest::new(HashAlgorithm::Sha256, bytes()));
+    }
+
+    #[test]
+    fn unknown_labels_are_unsupported() {
+        assert_eq!(ModuleDigest::parse(&format!("md5:{HEX}")), Err(DigestError::UnsupportedAlgorithm("md5".to_string())));
+        assert_eq!(ModuleDigest::parse(&format!(":{HEX}")), Err(DigestError::UnsupportedAlgorithm(String::new())));
+    }
+
+    #[test]
+    fn wrong_lengths_are_malformed() {
+        for hex in ["", &HEX[..62], &format!("{HEX}00")] {
+            assert!(matches!(ModuleDigest::parse(&format!("sha256:{hex}")), Err(DigestError::Malformed(_))), "{hex}");
+            assert!(matches!(ModuleDigest::parse(hex), Err(DigestError::Malformed(_))), "{hex}");
+        }
+    }
+
+    #[test]
+    fn non_hex_is_malformed() {
+        let signed = "+0".repeat(32);
+        let spaced = format!(" {}", &HEX[1..]);
+        let not_hex = format!("g{}", &HEX[1..]);
+        let multibyte = format!("é{}", &HEX[2..]);
+        for hex in [signed.as_str(), &spaced, &not_hex, &multibyte] {
+            assert!(matches!(ModuleDigest::parse(&format!("sha256:{hex}")), Err(DigestError::Malformed(_))), "{hex}");
+        }
+    }
+
+    #[test]
+    fn proto_digests_need_32_bytes_and_a_known_algorithm() {
+        let digest = ModuleDigest::from_proto(&bytes(), Some(HashAlgorithm::Blake3 as i32), HashAlgorithm::Sha256).unwrap();
+        assert_eq!(digest, ModuleDigest::new(HashAlgorithm::Blake3, bytes()));
+        let digest = ModuleDigest::from_proto(&bytes(), None, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(digest.algorithm, HashAlgorithm::Sha256);
+
+        assert!(matches!(ModuleDigest::from_proto(&bytes()[..31], None, HashAlgorithm::Sha256), Err(DigestError::Malformed(_))));
+        assert_eq!(ModuleDigest::from_proto(&bytes(), Some(99), HashAlgorithm::Sha256), Err(DigestError::UnsupportedAlgorithm("99".to_string())));
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+
+use prost::bytes::Bytes;
+use tonic::{Code, Status};
+use tonic::metadata::MetadataValue;
+
+/// Metadata entry carrying an ErrorCode alongside a failed request's gRPC status.
+pub const ERROR_CODE_HEADER: &str = "x-mini-lambda-error-code";
+
+/// Metadata entry carrying the code a job's guest exited with, alongside the status of a job
+/// that exited with a non-zero code. The status's details are the guest's stderr.
+pub const EXIT_CODE_HEADER: &str = "x-mini-lambda-exit-code";
+
+/// Why a request failed, finer-grained than its gRPC status code (several failures share
+/// FAILED_PRECONDITION, for example), so that clients can branch on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorCode {
+    QuotaExceeded,
+    /// A client used up a quota for the UTC day, which frees up only once the day ends.
+    DailyQuotaExceeded,
+    /// The orchestrator's queue holds as many jobs as it may, across every client.
+    QueueFull,
+    InvalidCallbackUrl,
+    JobNotFound,
+    JobCancelled,
+    /// The job ran past its timeout and was stopped.
+    JobTimedOut,
+    /// The worker shut down while the job was running, so it may succeed on another worker.
+    JobAborted,
+    WorkerNotFound,
+    WorkerNotDrained,
+    /// The worker a job was pinned to has no free credit.
+    WorkerBusy,
+    /// The job never started executing, so what it ran isn't known.
+    JobNotStarted,
+    CompileError,
+    ExecutionError,
+    /// The job's wasm ran out of stack.
+    StackOverflow,
+    ModuleNotCached,
+    Unauthorized,
+    VersionMismatch,
+    LimitExceeded,
+    SignatureRejected,
+    /// The worker couldn't fetch the job's module from its source, or it didn't match its hash.
+    ModuleFetchFailed,
+    InvalidNamespace,
+    /// The job belongs to a namespace other than the caller's.
+    NamespaceForbidden,
+    /// The worker only runs jobs the Orchestrator reserved a slot for, and this one had none.
+    ReservationRequired,
+    InvalidModuleAlias,
+    /// No module is published under the requested name:tag alias.
+    ModuleNotFound,
+    /// The job's module upload, or the upload by another job it waited for, failed or took
+    /// too long.
+    ModuleUploadFailed,
+    /// The worker no longer has the base a delta upload was made against.
+    DeltaBaseMissing,
+    /// A delta upload's patch didn't rebuild the module it was for.
+    DeltaMismatch,
+    /// A module digest was made with, or labelled as, an algorithm the server doesn't support.
+    UnsupportedHashAlgorithm,
+    /// A module digest was malformed, or labelled with the wrong algorithm.
+    InvalidModuleDigest,
+    /// The worker no longer has a job output it stored, as its retention passed.
+    OutputExpired,
+    Internal,
+    /// A code sent by a newer peer that this binary doesn't know.
+    Other(String),
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ErrorCode::QuotaExceeded       => "quota_exceeded",
+            ErrorCode::DailyQuotaExceeded  => "daily_quota_exceeded",
+            ErrorCode::QueueFull           => "queue_full",
+            ErrorCode::InvalidCallbackUrl  => "invalid_callback_url",
+            ErrorCode::JobNotFound         => "job_not_found",
+            ErrorCode::JobCancelled        => "job_cancelled",
+            ErrorCode::JobTimedOut         => "job_timed_out",
+            ErrorCode::JobAborted          => "job_aborted",
+            ErrorCode::WorkerNotFound      => "worker_not_found",
+            ErrorCode::WorkerNotDrained    => "worker_not_drained",
+            ErrorCode::WorkerBusy          => "worker_busy",
+            ErrorCode::JobNotStarted       => "job_not_started",
+            ErrorCode::CompileError        => "compile_error",
+            ErrorCode::ExecutionError      => "execution_error",
+            ErrorCode::StackOverflow       => "stack_overflow",
+            ErrorCode::ModuleNotCached     => "module_not_cached",
+            ErrorCode::Unauthorized        => "unauthorized",
+            ErrorCode::VersionMismatch     => "version_mismatch",
+            ErrorCode::LimitExceeded       => "limit_exceeded",
+            ErrorCode::SignatureRejected   => "signature_rejected",
+            ErrorCode::ModuleFetchFailed   => "module_fetch_failed",
+            ErrorCode::InvalidNamespace    => "invalid_namespace",
+            ErrorCode::NamespaceForbidden  => "namespace_forbidden",
+            ErrorCode::ReservationRequired => "reservation_required",
+            ErrorCode::InvalidModuleAlias  => "invalid_module_alias",
+            ErrorCode::ModuleNotFound      => "module_not_found",
+            ErrorCode::ModuleUploadFailed  => "module_upload_failed",
+            ErrorCode::DeltaBaseMissing    => "delta_base_missing",
+            ErrorCode::DeltaMismatch       => "delta_mismatch",
+            ErrorCode::UnsupportedHashAlgorithm => "unsupported_hash_algorithm",
+            ErrorCode::InvalidModuleDigest => "invalid_module_digest",
+            ErrorCode::OutputExpired       => "output_expired",
+            ErrorCode::Internal            => "internal",
+            ErrorCode::Other(code)         => code,
+        }
+    }
+
+    /// Parses a code, keeping unknown ones as Other.
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "quota_exceeded"       => ErrorCode::QuotaExceeded,
+            "daily_quota_exceeded" => ErrorCode::DailyQuotaExceeded,
+            "queue_full"           => ErrorCode::QueueFull,
+            "invalid_callback_url" => ErrorCode::InvalidCallbackUrl,
+            "job_not_found"        => ErrorCode::JobNotFound,
+            "job_cancelled"        => ErrorCode::JobCancelled,
+            "job_timed_out"        => ErrorCode::JobTimedOut,
+            "job_aborted"          => ErrorCode::JobAborted,
+            "worker_not_found"     => ErrorCode::WorkerNotFound,
+            "worker_not_drained"   => ErrorCode::WorkerNotDrained,
+            "worker_busy"          => ErrorCode::WorkerBusy,
+            "job_not_started"      => ErrorCode::JobNotStarted,
+            "compile_error"        => ErrorCode::CompileError,
+            "execution_error"      => ErrorCode::ExecutionError,
+            "stack_overflow"       => ErrorCode::StackOverflow,
+            "module_not_cached"    => ErrorCode::ModuleNotCached,
+            "unauthorized"         => ErrorCode::Unauthorized,
+            "version_mismatch"     => ErrorCode::VersionMismatch,
+            "limit_exceeded"       => ErrorCode::LimitExceeded,
+            "signature_rejected"   => ErrorCode::SignatureRejected,
+            "module_fetch_failed"  => ErrorCode::ModuleFetchFailed,
+            "invalid_namespace"    => ErrorCode::InvalidNamespace,
+            "namespace_forbidden"  => ErrorCode::NamespaceForbidden,
+            "reservation_required" => ErrorCode::ReservationRequired,
+            "invalid_module_alias" => ErrorCode::InvalidModuleAlias,
+            "module_not_found"     => ErrorCode::ModuleNotFound,
+            "module_upload_failed" => ErrorCode::ModuleUploadFailed,
+            "delta_base_missing"   => ErrorCode::DeltaBaseMissing,
+            "delta_mismatch"       => ErrorCode::DeltaMismatch,
+            "unsupported_hash_algorithm" => ErrorCode::UnsupportedHashAlgorithm,
+            "invalid_module_digest" => ErrorCode::InvalidModuleDigest,
+            "output_expired"       => ErrorCode::OutputExpired,
+            "internal"             => ErrorCode::Internal,
+            other => ErrorCode::Other(other.to_string()),
+        }
+    }
+
+    /// A status with the given gRPC code and message, carrying this error code.
+    pub fn status(&self, code: Code, message: impl Into<String>) -> Status {
+        let mut status = Status::new(code, message);
+        if let Ok(value) = MetadataValue::try_from(self.as_str()) {
+            status.metadata_mut().insert(ERROR_CODE_HEADER, value);
+        }
+        status
+    }
+
+    /// The error code carried by a status, if the peer sent one. Peers built before error
+    /// codes existed don't.
+    pub fn of(status: &Status) -> Option<Self> {
+        status.metadata().get(ERROR_CODE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(Self::parse)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The execution_error status of a job whose guest exited with a non-zero code, carrying the
+/// code and the guest's stderr for clients to read back with exit_of.
+pub fn exited_status(exit_code: i32, stderr: &[u8], message: impl Into<String>) -> Status {
+    with_exit(ErrorCode::ExecutionError, Code::InvalidArgument, message.into(), exit_code, Bytes::copy_from_slice(stderr))
+}
+
+fn with_exit(error_code: ErrorCode, code: Code, message: String, exit_code: i32, stderr: Bytes) -> Status {
+    let mut status = Status::with_details(code, message, stderr);
+    if let Ok(value) = MetadataValue::try_from(error_code.as_str()) {
+        status.metadata_mut().insert(ERROR_CODE_HEADER, value);
+    }
+    status.metadata_mut().insert(EXIT_CODE_HEADER, MetadataValue::from(exit_code));
+    status
+}
+
+/// The code a job's guest exited with and its stderr, if the status is one made by
+/// exited_status.
+pub fn exit_of(status: &Status) -> Option<(i32, &[u8])> {
+    let exit_code = status.metadata().get(EXIT_CODE_HEADER)?.to_str().ok()?.parse().ok()?;
+    Some((exit_code, status.details()))
+}
+
+impl From<&Status> for crate::RelayedError {
+    /// A status to relay, keeping its error code, and its exit code and stderr if it has them.
+    fn from(status: &Status) -> Self {
+        let exit = exit_of(status);
+        crate::RelayedError {
+            code: status.code() as i32,
+            message: status.message().to_string(),
+            error_code: ErrorCode::of(status).map(|error_code| error_code.to_string()),
+            exit_code: exit.map(|(exit_code, _)| exit_code),
+            details: exit.map(|(_, stderr)| stderr.to_vec()).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<crate::RelayedError> for Status {
+    /// The status that was relayed, with its error code, and its exit code and stderr if it
+    /// has them.
+    fn from(error: crate::RelayedError) -> Self {
+        let code = Code::from_i32(error.code);
+        match (error.error_code, error.exit_code) {
+            (Some(error_code), Some(exit_code)) => with_exit(ErrorCode::parse(&error_code), code, error.message, exit_code, error.details.into()),
+            (Some(error_code), None) => ErrorCode::parse(&error_code).status(code, error.message),
+            (None, _) => Status::new(code, error.message),
+        }
+    }
+}
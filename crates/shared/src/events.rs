@@ -0,0 +1,158 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::JobId;
+
+/// A step in a job's lifecycle, in the JSON shape used outside the gRPC API (e.g. in webhook
+/// payloads). Serialized with a "type" field naming the variant, e.g.
+/// `{"type":"dispatched","job_id":"...","worker_address":"...","at_ms":1700000000000}`.
+/// Times are milliseconds since the unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobEvent {
+    /// Entered the queue, or the delay queue if it has a scheduled start time.
+    Queued { job_id: JobId, at_ms: u64, scheduled_for_ms: Option<u64> },
+    /// Assigned to a worker, which the client then sends the job to.
+    Dispatched { job_id: JobId, worker_address: String, at_ms: u64 },
+    /// The worker started compiling or executing the job.
+    Started { job_id: JobId, at_ms: u64 },
+    Completed { job_id: JobId, at_ms: u64 },
+    Failed { job_id: JobId, at_ms: u64 },
+    Cancelled { job_id: JobId, at_ms: u64 },
+    /// The worker ran past the job's timeout and the Orchestrator's grace period without
+    /// reporting how it finished, so the Orchestrator stopped waiting for it.
+    #[serde(rename = "presumed-lost")]
+    PresumedLost { job_id: JobId, worker_address: String, at_ms: u64 },
+}
+
+impl JobEvent {
+    pub fn job_id(&self) -> JobId {
+        match self {
+            JobEvent::Queued { job_id, .. }
+            | JobEvent::Dispatched { job_id, .. }
+            | JobEvent::Started { job_id, .. }
+            | JobEvent::Completed { job_id, .. }
+            | JobEvent::Failed { job_id, .. }
+            | JobEvent::Cancelled { job_id, .. }
+            | JobEvent::PresumedLost { job_id, .. } => *job_id,
+        }
+    }
+
+    pub fn at_ms(&self) -> u64 {
+        match self {
+            JobEvent::Queued { at_ms, .. }
+            | JobEvent::Dispatched { at_ms, .. }
+            | JobEvent::Started { at_ms, .. }
+            | JobEvent::Completed { at_ms, .. }
+            | JobEvent::Failed { at_ms, .. }
+            | JobEvent::Cancelled { at_ms, .. }
+            | JobEvent::PresumedLost { at_ms, .. } => *at_ms,
+        }
+    }
+
+    /// The event's "type" tag, which is also the state the job is in after it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobEvent::Queued { .. } => "queued",
+            JobEvent::Dispatched { .. } => "dispatched",
+            JobEvent::Started { .. } => "started",
+            JobEvent::Completed { .. } => "completed",
+            JobEvent::Failed { .. } => "failed",
+            JobEvent::Cancelled { .. } => "cancelled",
+            JobEvent::PresumedLost { .. } => "presumed-lost",
+        }
+    }
+
+    /// Whether the job is finished after this event.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, JobEvent::Completed { .. } | JobEvent::Failed { .. } | JobEvent::Cancelled { .. } | JobEvent::PresumedLost { .. })
+    }
+}
+
+/// Current status of a job, built by folding its events in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub job_id: JobId,
+    /// Name of the latest event, see JobEvent::name.
+    pub state: String,
+    pub worker_address: Option<String>,
+    pub scheduled_for_ms: Option<u64>,
+    pub queued_at_ms: Option<u64>,
+    pub dispatched_at_ms: Option<u64>,
+    pub started_at_ms: Option<u64>,
+    pub finished_at_ms: Option<u64>,
+    /// Times the job was queued again after being dispatched, i.e. retried by its client.
+    pub retries: u32,
+}
+
+impl JobRecord {
+    /// Creates the record for a job from its first event.
+    pub fn new(event: &JobEvent) -> Self {
+        let mut record = Self {
+            job_id: event.job_id(),
+            state: event.name().to_string(),
+            worker_address: None,
+            scheduled_for_ms: None,
+            queued_at_ms: None,
+            dispatched_at_ms: None,
+            started_at_ms: None,
+            finished_at_ms: None,
+            retries: 0,
+        };
+        record.apply(event);
+        record
+    }
+
+    /// Folds a job's events, in the order they happened, into its record. Returns None if
+    /// there are no events.
+    pub fn fold<'a>(events: impl IntoIterator<Item = &'a JobEvent>) -> Option<Self> {
+        let mut events = events.into_iter();
+        let mut record = Self::new(events.next()?);
+        for event in events {
+            record.apply(event);
+        }
+        Some(record)
+    }
+
+    /// Applies the job's next event. Events for other jobs are ignored. A job queued again
+    /// after it was dispatched is a retry, and starts its lifecycle over.
+    pub fn apply(&mut self, event: &JobEvent) {
+        if event.job_id() != self.job_id {
+            return;
+        }
+        match event {
+            JobEvent::Queued { at_ms, scheduled_for_ms, .. } => {
+                if self.dispatched_at_ms.is_some() {
+                    self.retries += 1;
+                }
+                self.worker_address = None;
+                self.scheduled_for_ms = *scheduled_for_ms;
+                self.queued_at_ms = Some(*at_ms);
+                self.dispatched_at_ms = None;
+                self.started_at_ms = None;
+                self.finished_at_ms = None;
+            },
+            JobEvent::Dispatched { worker_address, at_ms, .. } => {
+                self.worker_address = Some(worker_address.clone());
+                self.dispatched_at_ms = Some(*at_ms);
+            },
+            JobEvent::Started { at_ms, .. } => {
+                self.started_at_ms = Some(*at_ms);
+            },
+            JobEvent::Completed { at_ms, .. } | JobEvent::Failed { at_ms, .. } | JobEvent::Cancelled { at_ms, .. } | JobEvent::PresumedLost { at_ms, .. } => {
+                self.finished_at_ms = Some(*at_ms);
+            },
+        }
+        self.state = event.name().to_string();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished_at_ms.is_some()
+    }
+}
+
+/// Milliseconds since the unix epoch, as used in events.
+pub fn epoch_ms(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
@@ -0,0 +1,115 @@
+//! Injected misbehavior for resilience testing, compiled in only with the `fault-injection`
+//! feature. A FaultSpec is installed once at startup, and the instrumented points consult it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static INSTALLED: OnceLock<FaultSpec> = OnceLock::new();
+
+/// Misbehavior injected at one point. Rates are probabilities between 0 and 1.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultRule {
+    /// Chance of failing the call.
+    pub error_rate: f64,
+    /// Chance of silently dropping a message.
+    pub drop_rate: f64,
+    /// Delay added before the call is handled.
+    pub latency: Duration,
+}
+
+/// Fault rules by point name, parsed from e.g. `submit:error_rate=0.2,latency_ms=500;updates:drop_rate=0.5`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultSpec {
+    rules: HashMap<String, FaultRule>,
+}
+
+impl FaultSpec {
+    /// Parses a spec of `point:key=value,...` rules separated by `;`. Keys are error_rate,
+    /// drop_rate, and latency_ms. Points must be one of the given names.
+    pub fn parse(spec: &str, points: &[&str]) -> Result<Self, String> {
+        let mut rules = HashMap::new();
+        for rule_spec in spec.split(';').map(str::trim).filter(|r| !r.is_empty()) {
+            let (point, settings) = rule_spec.split_once(':')
+                .ok_or_else(|| format!("fault rule '{rule_spec}' should look like point:key=value,..."))?;
+            let point = point.trim();
+            if !points.contains(&point) {
+                return Err(format!("unknown fault point '{point}', expected one of: {}", points.join(", ")));
+            }
+
+            let mut rule = FaultRule::default();
+            for setting in settings.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let (key, value) = setting.split_once('=')
+                    .ok_or_else(|| format!("fault setting '{setting}' should look like key=value"))?;
+                match key.trim() {
+                    "error_rate" => rule.error_rate = parse_rate(key, value)?,
+                    "drop_rate" => rule.drop_rate = parse_rate(key, value)?,
+                    "latency_ms" => {
+                        let ms = value.trim().parse()
+                            .map_err(|_| format!("latency_ms should be a whole number of milliseconds, got '{value}'"))?;
+                        rule.latency = Duration::from_millis(ms);
+                    },
+                    other => return Err(format!("unknown fault setting '{other}', expected error_rate, drop_rate, or latency_ms")),
+                }
+            }
+            if rules.insert(point.to_string(), rule).is_some() {
+                return Err(format!("fault point '{point}' is given more than once"));
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn rule(&self, point: &str) -> Option<&FaultRule> {
+        self.rules.get(point)
+    }
+}
+
+impl fmt::Display for FaultSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut points: Vec<_> = self.rules.keys().collect();
+        points.sort();
+        for (i, point) in points.into_iter().enumerate() {
+            let rule = &self.rules[point];
+            if i > 0 {
+                write!(f, ";")?;
+            }
+            write!(f, "{point}:error_rate={},drop_rate={},latency_ms={}", rule.error_rate, rule.drop_rate, rule.latency.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_rate(key: &str, value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("{key} should be a number between 0 and 1, got '{value}'")),
+    }
+}
+
+/// Installs the spec for the rest of the process. Only the first call has an effect.
+pub fn install(spec: FaultSpec) {
+    INSTALLED.set(spec).ok();
+}
+
+/// Applies the installed rule for a point, if any: waits out its latency, then fails with
+/// Unavailable at its error rate.
+pub async fn inject(point: &str) -> Result<(), tonic::Status> {
+    let Some(rule) = INSTALLED.get().and_then(|spec| spec.rule(point)) else {
+        return Ok(());
+    };
+    if !rule.latency.is_zero() {
+        tokio::time::sleep(rule.latency).await;
+    }
+    if rand::random::<f64>() < rule.error_rate {
+        return Err(tonic::Status::unavailable(format!("injected fault at {point}")));
+    }
+    Ok(())
+}
+
+/// Whether to drop a message at a point, according to the installed rule's drop rate.
+pub fn should_drop(point: &str) -> bool {
+    INSTALLED.get()
+        .and_then(|spec| spec.rule(point))
+        .is_some_and(|rule| rand::random::<f64>() < rule.drop_rate)
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A job's ID, generated by the client that submits it. Sent in protobuf messages as the
+/// UUID's 16 raw bytes, and in JSON (JWT claims, webhooks) as the hyphenated UUID string,
+/// exactly as a bare Uuid would be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct JobId(Uuid);
+
+impl JobId {
+    /// A new random job ID.
+    pub fn random() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    /// Parses the raw bytes of a protobuf job_id field.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, uuid::Error> {
+        Uuid::from_slice(bytes).map(Self)
+    }
+
+    /// The raw bytes to send in a protobuf job_id field.
+    pub fn to_bytes(self) -> Vec<u8> {
+        self.0.as_bytes().to_vec()
+    }
+
+    pub fn as_uuid(&self) -> &Uuid {
+        &self.0
+    }
+
+    /// The first 8 characters, enough to tell jobs apart on screen.
+    pub fn short(&self) -> String {
+        self.0.simple().to_string()[..8].to_string()
+    }
+}
+
+impl fmt::Display for JobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for JobId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::parse_str(s).map(Self)
+    }
+}
+
+impl From<Uuid> for JobId {
+    fn from(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl From<JobId> for Uuid {
+    fn from(job_id: JobId) -> Self {
+        job_id.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::JobClaims;
+    use crate::events::JobEvent;
+
+    const ID: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+    /// JobClaims as they were when job ids were bare Uuids.
+    #[derive(Serialize, Deserialize)]
+    struct UuidClaims {
+        sub: Uuid,
+        exp: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        coalesced_with: Option<Uuid>,
+    }
+
+    fn ids() -> (JobId, Uuid) {
+        (ID.parse().unwrap(), Uuid::parse_str(ID).unwrap())
+    }
+
+    #[test]
+    fn json_is_the_uuid_string() {
+        let (job_id, uuid) = ids();
+        assert_eq!(serde_json::to_vec(&job_id).unwrap(), serde_json::to_vec(&uuid).unwrap());
+        assert_eq!(serde_json::to_string(&job_id).unwrap(), format!("\"{ID}\""));
+        assert_eq!(serde_json::from_str::<JobId>(&format!("\"{ID}\"")).unwrap(), job_id);
+    }
+
+    #[test]
+    fn claims_are_byte_identical() {
+        let (job_id, uuid) = ids();
+        let leader = JobId::random();
+        let cases = [
+            (JobClaims::new(job_id), UuidClaims { sub: uuid, exp: usize::MAX, coalesced_with: None }),
+            (JobClaims::coalesced(job_id, leader), UuidClaims { sub: uuid, exp: usize::MAX, coalesced_with: Some(leader.into()) }),
+        ];
+        for (claims, uuid_claims) in cases {
+            let json = serde_json::to_vec(&claims).unwrap();
+            assert_eq!(json, serde_json::to_vec(&uuid_claims).unwrap());
+            let parsed: JobClaims = serde_json::from_slice(&json).unwrap();
+            assert_eq!((parsed.sub, parsed.coalesced_with), (claims.sub, claims.coalesced_with));
+        }
+    }
+
+    #[test]
+    fn journal_events_keep_their_format() {
+        let (job_id, _) = ids();
+        let event = JobEvent::Started { job_id, at_ms: 5 };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains(&format!("\"job_id\":\"{ID}\"")), "{json}");
+        assert_eq!(serde_json::from_str::<JobEvent>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn protobuf_bytes_are_the_raw_uuid() {
+        let (job_id, uuid) = ids();
+        assert_eq!(job_id.to_bytes(), uuid.as_bytes());
+        assert_eq!(JobId::from_bytes(uuid.as_bytes()).unwrap(), job_id);
+        assert!(JobId::from_bytes(&[0; 15]).is_err());
+    }
+
+    #[test]
+    fn ids_print_in_full_or_short() {
+        let (job_id, uuid) = ids();
+        assert_eq!(job_id.to_string(), ID);
+        assert_eq!(job_id.short(), "67e55044");
+        assert_eq!(Uuid::from(job_id), uuid);
+        assert_eq!(JobId::from(uuid), job_id);
+        assert!("not-a-uuid".parse::<JobId>().is_err());
+    }
+}
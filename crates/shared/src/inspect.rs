@@ -0,0 +1,296 @@
+//! Static checks of whether a wasm module is something Workers can run, without compiling or
+//! running it. Used by the Orchestrator's ValidateModule and by the CLI before submitting.
+//! Also reads the metadata Workers keep about the modules they cache.
+
+use wasmparser::{BinaryReaderError, ComponentName, KnownCustom, Name, Parser, Payload, Validator, WasmFeatures};
+
+use crate::{CachedModule, ValidateModuleResponse, validate_module_response};
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+/// Interface a module must export for workers to run it as a command.
+pub const RUN_INTERFACE: &str = "wasi:cli/run";
+
+/// Packages whose interfaces only work on clusters that allow jobs network access.
+const NETWORK_PACKAGES: [&str; 2] = ["wasi:sockets", "wasi:http"];
+
+/// What inspecting a module found. Errors mean Workers can't run it, warnings don't stop it
+/// running but are likely mistakes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleReport {
+    pub kind: ModuleKind,
+    /// The outermost component's imports, or a core module's as module::name.
+    pub imports: Vec<String>,
+    pub exports: Vec<String>,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ModuleKind {
+    /// Not parseable as wasm at all.
+    #[default]
+    Invalid,
+    Component,
+    /// A core module, which Workers can't run as they only run WASI preview 2 components.
+    CoreModule,
+}
+
+impl ModuleReport {
+    /// Whether Workers can run the module.
+    pub fn is_runnable(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl ModuleKind {
+    /// The kind's name, as printed by the CLI.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModuleKind::Invalid => "invalid",
+            ModuleKind::Component => "component",
+            ModuleKind::CoreModule => "core module",
+        }
+    }
+}
+
+/// Checks that the module is a valid WASI command component that only imports WASI
+/// interfaces, since that's all workers provide, and lists what it imports and exports.
+pub fn inspect_module(wasm_bytes: &[u8]) -> ModuleReport {
+    let mut report = ModuleReport::default();
+    if !wasm_bytes.starts_with(WASM_MAGIC) {
+        report.errors.push("not a wasm module (missing the \\0asm header)".to_string());
+        return report;
+    }
+    if Parser::is_core_wasm(wasm_bytes) {
+        report.kind = ModuleKind::CoreModule;
+        inspect_core_module(wasm_bytes, &mut report);
+        return report;
+    }
+
+    if let Err(e) = Validator::new_with_features(WasmFeatures::all()).validate_all(wasm_bytes) {
+        report.errors.push(format!("invalid wasm component: {e}"));
+        return report;
+    }
+    report.kind = ModuleKind::Component;
+    if let Err(e) = list_component_interfaces(wasm_bytes, &mut report) {
+        report.errors.push(format!("invalid wasm component: {e}"));
+        return report;
+    }
+
+    for name in &report.imports {
+        if !name.starts_with("wasi:") {
+            report.errors.push(format!("the component imports '{name}', which workers don't provide; only wasi:* interfaces are available"));
+        }
+    }
+    for package in NETWORK_PACKAGES {
+        if report.imports.iter().any(|name| name.starts_with(package) && name[package.len()..].starts_with('/')) {
+            report.warnings.push(format!("the component imports {package} interfaces, which only work on clusters that allow jobs network access"));
+        }
+    }
+    if !report.exports.iter().any(|name| name.split('@').next() == Some(RUN_INTERFACE)) {
+        report.errors.push(format!("the component doesn't export {RUN_INTERFACE}, so it isn't a command workers can run — is it a library component? Build it as a binary (fn main) for wasm32-wasip2"));
+    }
+    report
+}
+
+/// Lists the outermost component's imports and exports.
+fn list_component_interfaces(wasm_bytes: &[u8], report: &mut ModuleReport) -> Result<(), wasmparser::BinaryReaderError> {
+    // Only the outermost component's imports and exports matter; nested modules and
+    // components are flattened into the payload stream, so track how deep we are
+    let mut depth = 0u32;
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::ModuleSection { .. } | Payload::ComponentSection { .. } => depth += 1,
+            Payload::End(_) => depth = depth.saturating_sub(1),
+            Payload::ComponentImportSection(imports) if depth == 0 => {
+                for import in imports {
+                    report.imports.push(import?.name.0.to_string());
+                }
+            },
+            Payload::ComponentExportSection(exports) if depth == 0 => {
+                for export in exports {
+                    report.exports.push(export?.name.0.to_string());
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Lists a core module's imports and exports, and explains why it can't be run based on what
+/// it imports.
+fn inspect_core_module(wasm_bytes: &[u8], report: &mut ModuleReport) {
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload {
+            Ok(Payload::ImportSection(imports)) => {
+                for import in imports.into_imports().flatten() {
+                    report.imports.push(format!("{}::{}", import.module, import.name));
+                }
+            },
+            Ok(Payload::ExportSection(exports)) => {
+                report.exports.extend(exports.into_iter().flatten().map(|export| export.name.to_string()));
+            },
+            _ => {},
+        }
+    }
+
+    let imports_preview1 = report.imports.iter().any(|name| name.starts_with("wasi_snapshot_preview1::"));
+    report.errors.push(if imports_preview1 {
+        "this is a WASI preview 1 core module, but workers run WASI preview 2 components — did you build for wasm32-wasip1? Rebuild for wasm32-wasip2".to_string()
+    } else {
+        "this looks like a wasm core module without WASI — did you build for wasm32-unknown-unknown? Rebuild for wasm32-wasip2".to_string()
+    });
+}
+
+impl From<ModuleReport> for ValidateModuleResponse {
+    fn from(report: ModuleReport) -> Self {
+        let kind = match report.kind {
+            ModuleKind::Invalid => validate_module_response::Kind::Invalid,
+            ModuleKind::Component => validate_module_response::Kind::Component,
+            ModuleKind::CoreModule => validate_module_response::Kind::CoreModule,
+        };
+        Self {
+            kind: kind.into(),
+            imports: report.imports,
+            exports: report.exports,
+            errors: report.errors,
+            warnings: report.warnings,
+        }
+    }
+}
+
+impl From<ValidateModuleResponse> for ModuleReport {
+    fn from(response: ValidateModuleResponse) -> Self {
+        let kind = match response.kind() {
+            validate_module_response::Kind::Component => ModuleKind::Component,
+            validate_module_response::Kind::CoreModule => ModuleKind::CoreModule,
+            validate_module_response::Kind::Invalid => ModuleKind::Invalid,
+        };
+        Self {
+            kind,
+            imports: response.imports,
+            exports: response.exports,
+            errors: response.errors,
+            warnings: response.warnings,
+        }
+    }
+}
+
+/// What a module's bytes say about it, read by Workers as they cache it so that it can still be
+/// listed once the bytes are gone.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleMetadata {
+    /// The outermost component's or core module's export names.
+    pub exports: Vec<String>,
+    /// Where the module's imports come from: a component's interface packages such as
+    /// wasi:cli, or a core module's import module names. Each is listed once.
+    pub import_namespaces: Vec<String>,
+    /// Whether the module has a name section, as debug builds usually do.
+    pub has_name_section: bool,
+    /// The name the name section gives the module, if any.
+    pub name: Option<String>,
+    pub size_bytes: u64,
+    /// Why reading the module stopped early, leaving the rest of the metadata partial.
+    pub parse_error: Option<String>,
+}
+
+/// Reads a module's metadata without validating it. A malformed module gets what could be read
+/// before the error, along with the error.
+pub fn module_metadata(wasm_bytes: &[u8]) -> ModuleMetadata {
+    let mut metadata = ModuleMetadata { size_bytes: wasm_bytes.len() as u64, ..ModuleMetadata::default() };
+    if let Err(e) = read_metadata(wasm_bytes, &mut metadata) {
+        metadata.parse_error = Some(e.to_string());
+    }
+    metadata
+}
+
+fn read_metadata(wasm_bytes: &[u8], metadata: &mut ModuleMetadata) -> Result<(), BinaryReaderError> {
+    // As in list_component_interfaces, only the outermost module or component counts
+    let mut depth = 0u32;
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload? {
+            Payload::ModuleSection { .. } | Payload::ComponentSection { .. } => depth += 1,
+            Payload::End(_) => depth = depth.saturating_sub(1),
+            Payload::ComponentImportSection(imports) if depth == 0 => {
+                for import in imports {
+                    let name = import?.name.0;
+                    let namespace = name.split(['/', '@']).next().unwrap_or(name);
+                    add_namespace(metadata, namespace);
+                }
+            },
+            Payload::ImportSection(imports) if depth == 0 => {
+                for import in imports.into_imports() {
+                    add_namespace(metadata, import?.module);
+                }
+            },
+            Payload::ComponentExportSection(exports) if depth == 0 => {
+                for export in exports {
+                    metadata.exports.push(export?.name.0.to_string());
+                }
+            },
+            Payload::ExportSection(exports) if depth == 0 => {
+                for export in exports {
+                    metadata.exports.push(export?.name.to_string());
+                }
+            },
+            Payload::CustomSection(section) if depth == 0 => match section.as_known() {
+                KnownCustom::Name(names) => {
+                    metadata.has_name_section = true;
+                    for name in names {
+                        if let Name::Module { name, .. } = name? {
+                            metadata.name = Some(name.to_string());
+                        }
+                    }
+                },
+                KnownCustom::ComponentName(names) => {
+                    metadata.has_name_section = true;
+                    for name in names {
+                        if let ComponentName::Component { name, .. } = name? {
+                            metadata.name = Some(name.to_string());
+                        }
+                    }
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+fn add_namespace(metadata: &mut ModuleMetadata, namespace: &str) {
+    if !metadata.import_namespaces.iter().any(|known| known == namespace) {
+        metadata.import_namespaces.push(namespace.to_string());
+    }
+}
+
+impl ModuleMetadata {
+    /// The listing of a cached module with the blake3 wasm_hash, compiled at compiled_at_ms
+    /// since the Unix epoch, as a Worker sends it.
+    pub fn to_cached_module(&self, wasm_hash: &[u8], compiled_at_ms: u64) -> CachedModule {
+        CachedModule {
+            wasm_hash: wasm_hash.to_vec(),
+            exports: self.exports.clone(),
+            import_namespaces: self.import_namespaces.clone(),
+            has_name_section: self.has_name_section,
+            name: self.name.clone(),
+            size_bytes: Some(self.size_bytes),
+            compiled_at_ms,
+            parse_error: self.parse_error.clone(),
+        }
+    }
+
+    /// The metadata in a cached module's listing, or None if the Worker has none for it.
+    pub fn from_cached_module(module: &CachedModule) -> Option<Self> {
+        Some(Self {
+            exports: module.exports.clone(),
+            import_namespaces: module.import_namespaces.clone(),
+            has_name_section: module.has_name_section,
+            name: module.name.clone(),
+            size_bytes: module.size_bytes?,
+            parse_error: module.parse_error.clone(),
+        })
+    }
+}
@@ -14,29 +14,73 @@ pub mod executor {
     tonic::include_proto!("executor");
 }
 
+pub mod compat;
+pub mod cost;
+#[cfg(feature = "delta")]
+pub mod delta;
+pub mod digest;
+pub mod events;
+pub mod inspect;
+#[cfg(feature = "fault-injection")]
+pub mod faults;
+mod error_code;
+mod ids;
+pub mod limits;
+pub mod output;
+#[cfg(feature = "reflection")]
+pub mod reflection;
+pub mod signing;
+
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
+pub use error_code::{ERROR_CODE_HEADER, EXIT_CODE_HEADER, ErrorCode, exit_of, exited_status};
+pub use ids::JobId;
 pub use shared::*;
 pub use client_api::*;
 pub use worker_api::*;
 pub use executor::*;
 
 /// JWT claims used to authorize a client's access to a specific job on a Worker.
-/// The sub field holds the job_id that this token is bound to.
+/// The sub field holds the job_id that this token is bound to. coalesced_with is set for a job
+/// the Orchestrator coalesced with another, which shares that job's run and holds no credit.
 #[derive(Serialize, Deserialize)]
 pub struct JobClaims {
-    pub sub: Uuid,
+    pub sub: JobId,
     pub exp: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coalesced_with: Option<JobId>,
 }
 
 impl JobClaims {
     /// Create a new JobClaims for a job with a given job_id.
     /// Has an infinite expiration.
-    pub fn new(job_id: Uuid) -> Self {
+    pub fn new(job_id: JobId) -> Self {
         Self { 
             sub: job_id,
-            exp: usize::MAX
+            exp: usize::MAX,
+            coalesced_with: None,
+        }
+    }
+
+    /// Like new, for a job coalesced with the in-flight job leader.
+    pub fn coalesced(job_id: JobId, leader: JobId) -> Self {
+        Self { coalesced_with: Some(leader), ..Self::new(job_id) }
+    }
+}
+
+/// What identifies identical jobs for coalescing: their module's hash (the blake3 hash of its
+/// wasm, or the digest of an object store module) and their arguments, each prefixed
+/// with its length as in signing::signed_bytes.
+pub fn coalesce_key(wasm_hash: &[u8], args: &[String]) -> Vec<u8> {
+    let bytes = signing::signed_bytes(&[], wasm_hash, &[], args);
+    ring::digest::digest(&ring::digest::SHA256, &bytes).as_ref().to_vec()
+}
+
+impl JobRequest {
+    /// The object store module the Worker should fetch and run, if the job has one.
+    pub fn object_store_module(&self) -> Option<&ObjectStoreModule> {
+        match self.module_source.as_ref()?.source.as_ref()? {
+            module_source::Source::ObjectStore(module) => Some(module),
         }
     }
 }
@@ -0,0 +1,139 @@
+use std::fmt;
+
+use crate::{JobRequest, WorkerCapabilities};
+
+const MIB: usize = 1024 * 1024;
+
+/// Most a pipeline stage may read on its stdin, so most the Orchestrator relays from one
+/// stage's stdout to the next. It keeps the stage's output in memory meanwhile.
+pub const PIPELINE_MAX_STDIN_BYTES: usize = 4 * MIB;
+
+/// Size limits on a job request. Workers enforce their configured limits on every job, and
+/// clients can check a job against known limits before sending it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobLimits {
+    /// Maximum size of an uploaded wasm module, in bytes.
+    pub max_wasm_bytes: usize,
+    /// Maximum number of arguments.
+    pub max_args: usize,
+    /// Maximum combined size of all arguments, in bytes.
+    pub max_args_bytes: usize,
+}
+
+impl Default for JobLimits {
+    fn default() -> Self {
+        Self { max_wasm_bytes: 32 * MIB, max_args: 256, max_args_bytes: 64 * 1024 }
+    }
+}
+
+impl JobLimits {
+    /// The largest job request these limits allow, with headroom for the other fields, so the
+    /// gRPC server can be told to accept it.
+    pub fn max_message_bytes(&self) -> usize {
+        self.max_wasm_bytes + self.max_args_bytes + MIB
+    }
+
+    /// Checks a job's wasm size and arguments against the limits.
+    pub fn check(&self, wasm_bytes: usize, args: &[String]) -> Result<(), LimitError> {
+        if wasm_bytes > self.max_wasm_bytes {
+            return Err(LimitError { field: "wasm_bytes", value: wasm_bytes, limit: self.max_wasm_bytes });
+        }
+        if args.len() > self.max_args {
+            return Err(LimitError { field: "args", value: args.len(), limit: self.max_args });
+        }
+        let args_bytes = args.iter().map(String::len).sum();
+        if args_bytes > self.max_args_bytes {
+            return Err(LimitError { field: "args bytes", value: args_bytes, limit: self.max_args_bytes });
+        }
+        Ok(())
+    }
+}
+
+impl From<&WorkerCapabilities> for JobLimits {
+    fn from(capabilities: &WorkerCapabilities) -> Self {
+        Self {
+            max_wasm_bytes: capabilities.max_wasm_bytes as usize,
+            max_args: capabilities.max_args as usize,
+            max_args_bytes: capabilities.max_args_bytes as usize,
+        }
+    }
+}
+
+impl JobRequest {
+    /// Checks the request against the limits.
+    pub fn validate(&self, limits: &JobLimits) -> Result<(), LimitError> {
+        let patch_len = self.delta.as_ref().map_or(0, |delta| delta.patch.len());
+        limits.check(self.wasm_bytes.len() + patch_len, &self.args)
+    }
+}
+
+/// A job exceeds one of its JobLimits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitError {
+    pub field: &'static str,
+    pub value: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is {}, over the limit of {}", self.field, self.value, self.limit)
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+impl From<LimitError> for tonic::Status {
+    fn from(e: LimitError) -> Self {
+        crate::ErrorCode::LimitExceeded.status(tonic::Code::InvalidArgument, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeltaUpload;
+
+    const LIMITS: JobLimits = JobLimits { max_wasm_bytes: 100, max_args: 2, max_args_bytes: 8 };
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn jobs_at_the_limits_are_accepted() {
+        assert_eq!(LIMITS.check(100, &args(&["1234", "5678"])), Ok(()));
+        assert_eq!(LIMITS.check(0, &[]), Ok(()));
+    }
+
+    #[test]
+    fn each_limit_names_its_field() {
+        assert_eq!(LIMITS.check(101, &[]), Err(LimitError { field: "wasm_bytes", value: 101, limit: 100 }));
+        assert_eq!(LIMITS.check(1, &args(&["a", "b", "c"])), Err(LimitError { field: "args", value: 3, limit: 2 }));
+        assert_eq!(LIMITS.check(1, &args(&["12345", "6789"])), Err(LimitError { field: "args bytes", value: 9, limit: 8 }));
+    }
+
+    #[test]
+    fn errors_say_what_is_over_which_limit() {
+        let error = LIMITS.check(101, &[]).unwrap_err();
+        assert_eq!(error.to_string(), "wasm_bytes is 101, over the limit of 100");
+        let status = tonic::Status::from(error);
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(crate::ErrorCode::of(&status), Some(crate::ErrorCode::LimitExceeded));
+    }
+
+    #[test]
+    fn a_delta_patch_counts_towards_the_wasm_size() {
+        let mut request = JobRequest { wasm_bytes: vec![0; 60], ..Default::default() };
+        assert_eq!(request.validate(&LIMITS), Ok(()));
+        request.delta = Some(Box::new(DeltaUpload { patch: vec![0; 41], ..Default::default() }));
+        assert_eq!(request.validate(&LIMITS).unwrap_err().field, "wasm_bytes");
+    }
+
+    #[test]
+    fn limits_come_from_worker_capabilities() {
+        let capabilities = WorkerCapabilities { max_wasm_bytes: 100, max_args: 2, max_args_bytes: 8, ..Default::default() };
+        assert_eq!(JobLimits::from(&capabilities), LIMITS);
+        assert!(JobLimits::default().max_message_bytes() > JobLimits::default().max_wasm_bytes);
+    }
+}
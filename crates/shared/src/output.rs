@@ -0,0 +1,87 @@
+use crate::OutputRef;
+
+/// Most bytes of a stored job output a FetchOutput returns at once, so that each part fits in
+/// a gRPC message of the default size.
+pub const OUTPUT_CHUNK_BYTES: u64 = 2 * 1024 * 1024;
+
+/// The SHA-256 digest of a job's output, as an OutputRef carries it.
+pub fn output_sha256(output: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, output).as_ref().to_vec()
+}
+
+/// Computes the same digest as output_sha256 over an output written in parts.
+pub struct OutputHasher(ring::digest::Context);
+
+impl OutputHasher {
+    pub fn new() -> Self {
+        Self(ring::digest::Context::new(&ring::digest::SHA256))
+    }
+
+    pub fn update(&mut self, part: &[u8]) {
+        self.0.update(part);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.0.finish().as_ref().to_vec()
+    }
+}
+
+impl Default for OutputHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutputRef {
+    /// Checks an output fetched in full is the one the reference was made for.
+    pub fn verify(&self, output: &[u8]) -> Result<(), String> {
+        if output.len() as u64 != self.size {
+            return Err(format!("output {} is {} bytes, expected {}", self.id, output.len(), self.size));
+        }
+        if output_sha256(output) != self.sha256 {
+            return Err(format!("output {} doesn't match its SHA-256 digest", self.id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output_ref(output: &[u8]) -> OutputRef {
+        OutputRef { id: "out".to_string(), size: output.len() as u64, sha256: output_sha256(output) }
+    }
+
+    #[test]
+    fn hashing_in_parts_matches_hashing_at_once() {
+        let output = b"a job's output, written in parts";
+        let mut hasher = OutputHasher::new();
+        for part in output.chunks(5) {
+            hasher.update(part);
+        }
+        assert_eq!(hasher.finish(), output_sha256(output));
+    }
+
+    #[test]
+    fn verify_accepts_the_output_the_reference_was_made_for() {
+        let output = vec![7; 3000];
+        assert_eq!(output_ref(&output).verify(&output), Ok(()));
+    }
+
+    #[test]
+    fn verify_rejects_a_truncated_output() {
+        let output = vec![7; 3000];
+        let error = output_ref(&output).verify(&output[..2999]).unwrap_err();
+        assert!(error.contains("2999 bytes, expected 3000"), "{error}");
+    }
+
+    #[test]
+    fn verify_rejects_a_corrupted_output() {
+        let output = vec![7; 3000];
+        let mut corrupted = output.clone();
+        corrupted[1500] = 8;
+        let error = output_ref(&output).verify(&corrupted).unwrap_err();
+        assert!(error.contains("doesn't match its SHA-256 digest"), "{error}");
+    }
+}
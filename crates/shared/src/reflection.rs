@@ -0,0 +1,22 @@
+//! gRPC server reflection, which describes the services a server serves and the messages they
+//! take, so that tools like grpcurl and Postman can call them without the .proto files.
+
+use tonic_reflection::server::v1::{ServerReflection, ServerReflectionServer};
+
+/// The descriptors of every proto file, written by build.rs.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("mini_lambda_descriptor");
+
+/// The services the Orchestrator serves.
+pub const ORCHESTRATOR_SERVICES: &[&str] = &["client_api.ClientApi", "worker_api.WorkerApi"];
+/// The services a Worker serves.
+pub const WORKER_SERVICES: &[&str] = &["executor.Executor"];
+
+/// A reflection service listing the named services. Every message is described, whichever
+/// services list it.
+pub fn service(services: &[&str]) -> ServerReflectionServer<impl ServerReflection> {
+    services.iter()
+        .fold(tonic_reflection::server::Builder::configure(), |builder, service| builder.with_service_name(*service))
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build_v1()
+        .unwrap_or_else(|e| panic!("build.rs wrote a malformed descriptor set: {}", e))
+}
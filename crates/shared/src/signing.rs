@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use ring::signature::{ED25519, Ed25519KeyPair, UnparsedPublicKey};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{PrivatePkcs8KeyDer, SubjectPublicKeyInfoDer};
+
+use crate::ErrorCode;
+
+/// Prefixed to every signed message, so that a job signature can't be passed off as a
+/// signature over anything else.
+const CONTEXT: &[u8] = b"mini-lambda job v2\0";
+
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo, followed by the 32-byte raw public key.
+const ED25519_SPKI_PREFIX: &[u8] = &[0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+/// The bytes a job's signature covers: its id, the blake3 hash of its wasm module (or the
+/// SHA-256 digest for a module fetched from an object store), the SHA-256 digest of its stdin,
+/// and its arguments. Each argument is prefixed with its length as a big-endian u32, so that
+/// moving text between arguments changes the signed bytes.
+pub fn signed_bytes(job_id: &[u8], wasm_hash: &[u8], stdin: &[u8], args: &[String]) -> Vec<u8> {
+    let stdin_hash = stdin_digest(stdin);
+    let mut bytes = Vec::with_capacity(CONTEXT.len() + job_id.len() + wasm_hash.len() + stdin_hash.len()
+        + args.iter().map(|a| a.len() + 4).sum::<usize>());
+    bytes.extend_from_slice(CONTEXT);
+    bytes.extend_from_slice(job_id);
+    bytes.extend_from_slice(wasm_hash);
+    bytes.extend_from_slice(&stdin_hash);
+    for arg in args {
+        bytes.extend_from_slice(&(arg.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(arg.as_bytes());
+    }
+    bytes
+}
+
+/// The SHA-256 digest of a job's stdin, which its signature covers and its replays are checked
+/// against.
+pub fn stdin_digest(stdin: &[u8]) -> [u8; 32] {
+    ring::digest::digest(&ring::digest::SHA256, stdin).as_ref().try_into().expect("SHA-256 digests are 32 bytes")
+}
+
+/// An Ed25519 key that clients sign jobs with, named by the key id workers know it by.
+#[derive(Debug)]
+pub struct JobSigner {
+    key_id: String,
+    key_pair: Ed25519KeyPair,
+}
+
+impl JobSigner {
+    /// Loads a PKCS#8 PEM private key, as written by `openssl genpkey -algorithm ed25519`.
+    pub fn load(key_id: impl Into<String>, pem_path: &Path) -> Result<Self, String> {
+        let der = PrivatePkcs8KeyDer::from_pem_file(pem_path)
+            .map_err(|e| format!("failed to read signing key {}: {e}", pem_path.display()))?;
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.secret_pkcs8_der())
+            .map_err(|e| format!("signing key {} is not an Ed25519 key: {e}", pem_path.display()))?;
+        Ok(Self { key_id: key_id.into(), key_pair })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.key_pair.sign(message).as_ref().to_vec()
+    }
+}
+
+/// The public keys whose job signatures a worker accepts, by key id.
+#[derive(Debug, Default)]
+pub struct TrustedKeys {
+    keys: HashMap<String, UnparsedPublicKey<Vec<u8>>>,
+}
+
+impl TrustedKeys {
+    /// Loads every .pem public key in the directory, as written by `openssl pkey -pubout`.
+    /// Each key's id is its file name without the extension.
+    pub fn load_dir(dir: &Path) -> Result<Self, String> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| format!("failed to read trusted keys directory {}: {e}", dir.display()))?;
+        let mut keys = HashMap::new();
+        for entry in entries {
+            let path = entry.map_err(|e| format!("failed to read trusted keys directory {}: {e}", dir.display()))?.path();
+            if path.extension().is_none_or(|ext| ext != "pem") {
+                continue;
+            }
+            let Some(key_id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let spki = SubjectPublicKeyInfoDer::from_pem_file(&path)
+                .map_err(|e| format!("failed to read public key {}: {e}", path.display()))?;
+            let raw = spki.strip_prefix(ED25519_SPKI_PREFIX)
+                .filter(|raw| raw.len() == 32)
+                .ok_or_else(|| format!("public key {} is not an Ed25519 key", path.display()))?;
+            keys.insert(key_id.to_string(), UnparsedPublicKey::new(&ED25519, raw.to_vec()));
+        }
+        if keys.is_empty() {
+            return Err(format!("no .pem public keys found in {}", dir.display()));
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Checks that the signature over message was made by the trusted key with the given id.
+    pub fn verify(&self, key_id: &str, message: &[u8], signature: &[u8]) -> Result<(), SignatureError> {
+        if signature.is_empty() {
+            return Err(SignatureError::Missing);
+        }
+        let key = self.keys.get(key_id)
+            .ok_or_else(|| SignatureError::UnknownKey(key_id.to_string()))?;
+        key.verify(message, signature)
+            .map_err(|_| SignatureError::Invalid(key_id.to_string()))
+    }
+}
+
+/// Why a job's signature was not accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    Missing,
+    UnknownKey(String),
+    Invalid(String),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "this worker only runs signed jobs"),
+            SignatureError::UnknownKey(key_id) => write!(f, "job signed with untrusted key '{key_id}'"),
+            SignatureError::Invalid(key_id) => write!(f, "job signature does not match key '{key_id}'"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+impl From<SignatureError> for tonic::Status {
+    fn from(e: SignatureError) -> Self {
+        ErrorCode::SignatureRejected.status(tonic::Code::PermissionDenied, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::KeyPair;
+
+    use super::*;
+
+    fn signer(key_id: &str) -> JobSigner {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        JobSigner { key_id: key_id.to_string(), key_pair: Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap() }
+    }
+
+    fn trusting(signers: &[&JobSigner]) -> TrustedKeys {
+        let keys = signers.iter()
+            .map(|s| (s.key_id.clone(), UnparsedPublicKey::new(&ED25519, s.key_pair.public_key().as_ref().to_vec())))
+            .collect();
+        TrustedKeys { keys }
+    }
+
+    fn job(job_id: &[u8], wasm_hash: &[u8], stdin: &[u8], args: &[&str]) -> Vec<u8> {
+        signed_bytes(job_id, wasm_hash, stdin, &args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn a_signed_job_verifies_against_its_key() {
+        let alice = signer("alice");
+        let bob = signer("bob");
+        let trusted = trusting(&[&alice, &bob]);
+        let message = job(b"job", b"hash", b"input", &["a", "b"]);
+        assert_eq!(trusted.verify("alice", &message, &alice.sign(&message)), Ok(()));
+        assert_eq!(trusted.verify("bob", &message, &bob.sign(&message)), Ok(()));
+    }
+
+    #[test]
+    fn tampering_with_a_signed_job_is_detected() {
+        let alice = signer("alice");
+        let trusted = trusting(&[&alice]);
+        let signature = alice.sign(&job(b"job", b"hash", b"input", &["a", "b"]));
+        for tampered in [
+            job(b"jab", b"hash", b"input", &["a", "b"]),
+            job(b"job", b"hasp", b"input", &["a", "b"]),
+            job(b"job", b"hash", b"inpot", &["a", "b"]),
+            job(b"job", b"hash", b"input", &["a", "c"]),
+            job(b"job", b"hash", b"input", &["a"]),
+        ] {
+            assert_eq!(trusted.verify("alice", &tampered, &signature), Err(SignatureError::Invalid("alice".to_string())));
+        }
+    }
+
+    #[test]
+    fn a_signature_by_another_key_is_invalid() {
+        let alice = signer("alice");
+        let mallory = signer("alice");
+        let message = job(b"job", b"hash", b"", &[]);
+        let error = trusting(&[&alice]).verify("alice", &message, &mallory.sign(&message));
+        assert_eq!(error, Err(SignatureError::Invalid("alice".to_string())));
+    }
+
+    #[test]
+    fn an_unknown_key_id_is_rejected() {
+        let alice = signer("alice");
+        let carol = signer("carol");
+        let message = job(b"job", b"hash", b"", &[]);
+        let error = trusting(&[&alice]).verify("carol", &message, &carol.sign(&message));
+        assert_eq!(error, Err(SignatureError::UnknownKey("carol".to_string())));
+    }
+
+    #[test]
+    fn an_empty_signature_is_missing() {
+        let alice = signer("alice");
+        let message = job(b"job", b"hash", b"", &[]);
+        assert_eq!(trusting(&[&alice]).verify("alice", &message, &[]), Err(SignatureError::Missing));
+        // Checked before the key, so unsigned jobs get the same answer whatever key id they name.
+        assert_eq!(trusting(&[&alice]).verify("", &message, &[]), Err(SignatureError::Missing));
+    }
+
+    #[test]
+    fn stdin_is_signed() {
+        let args = vec!["a".to_string()];
+        let empty = signed_bytes(b"job", b"hash", b"", &args);
+        assert_ne!(empty, signed_bytes(b"job", b"hash", b"input", &args));
+        assert_eq!(empty, signed_bytes(b"job", b"hash", b"", &args));
+    }
+
+    #[test]
+    fn moving_text_between_args_changes_the_bytes() {
+        let split = signed_bytes(b"job", b"hash", b"", &["ab".to_string(), "c".to_string()]);
+        let moved = signed_bytes(b"job", b"hash", b"", &["a".to_string(), "bc".to_string()]);
+        assert_ne!(split, moved);
+    }
+
+    #[test]
+    fn signatures_cover_the_context_version() {
+        assert!(signed_bytes(b"job", b"hash", b"", &[]).starts_with(b"mini-lambda job v2\0"));
+    }
+}
@@ -0,0 +1,27 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+const WASM_BYTES: usize = 5 * 1024 * 1024;
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Hashing a 5 MiB module in one call, as the worker does to key its module cache, against
+/// feeding it to the hasher in chunks as a streamed upload would.
+fn module_hash(c: &mut Criterion) {
+    let wasm: Vec<u8> = (0..WASM_BYTES).map(|i| (i % 251) as u8).collect();
+
+    let mut group = c.benchmark_group("module_hash_5mib");
+    group.throughput(Throughput::Bytes(WASM_BYTES as u64));
+    group.bench_function("one_shot", |b| b.iter(|| blake3::hash(&wasm)));
+    group.bench_function("streaming", |b| {
+        b.iter(|| {
+            let mut hasher = blake3::Hasher::new();
+            for chunk in wasm.chunks(CHUNK_BYTES) {
+                hasher.update(chunk);
+            }
+            hasher.finalize()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, module_hash);
+criterion_main!(benches);
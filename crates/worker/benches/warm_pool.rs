@@ -0,0 +1,79 @@
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use tokio::runtime::Runtime;
+use wasmtime::component::{Component, Linker, ResourceTable};
+use wasmtime::{Config, Engine, Store};
+use wasmtime_wasi::p2::bindings::Command;
+use wasmtime_wasi::p2::pipe::{MemoryInputPipe, MemoryOutputPipe};
+use wasmtime_wasi::{WasiCtx, WasiCtxView, WasiView};
+
+/// Committed pre-built, and rebuilt by crates/client/test-wasm/build.sh.
+const FIB_WASM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../client/test-wasm/fib.wasm");
+
+struct State {
+    wasi_ctx: WasiCtx,
+    resource_table: ResourceTable,
+}
+
+impl WasiView for State {
+    fn ctx(&mut self) -> WasiCtxView<'_> {
+        WasiCtxView { ctx: &mut self.wasi_ctx, table: &mut self.resource_table }
+    }
+}
+
+/// Instantiates the module with an empty WASI context, as the worker does for its warm pool.
+async fn instantiate(engine: &Engine, linker: &Linker<State>, component: &Component) -> (Store<State>, Command) {
+    let state = State { wasi_ctx: WasiCtx::builder().build(), resource_table: ResourceTable::new() };
+    let mut store = Store::new(engine, state);
+    let command = Command::instantiate_async(&mut store, component, linker).await
+        .unwrap_or_else(|e| panic!("failed to instantiate fib.wasm: {e}"));
+    (store, command)
+}
+
+/// Gives the instance a job's WASI context and runs it, as the worker does for every job.
+async fn run(mut store: Store<State>, command: Command) -> Vec<u8> {
+    let stdout = MemoryOutputPipe::new(1024);
+    store.data_mut().wasi_ctx = WasiCtx::builder()
+        .args(&["job", "1"])
+        .stdin(MemoryInputPipe::new(Vec::new()))
+        .stdout(stdout.clone())
+        .build();
+    command.wasi_cli_run().call_run(&mut store).await
+        .unwrap_or_else(|e| panic!("fib.wasm trapped: {e}"))
+        .unwrap_or_else(|()| panic!("fib.wasm failed"));
+    stdout.contents().to_vec()
+}
+
+/// Running a trivial job (fib of 1) on an instance made for it, as a worker does without a warm pool,
+/// against running it on an instance made ahead of it, as a pooled module's job does.
+fn warm_pool(c: &mut Criterion) {
+    let wasm = std::fs::read(FIB_WASM)
+        .unwrap_or_else(|e| panic!("failed to read {FIB_WASM}: {e}"));
+    let engine = Engine::new(&Config::new())
+        .unwrap_or_else(|e| panic!("failed to initialize the wasmtime engine: {e}"));
+    let mut linker = Linker::new(&engine);
+    wasmtime_wasi::p2::add_to_linker_async(&mut linker)
+        .unwrap_or_else(|e| panic!("failed to add WASI to the linker: {e}"));
+    let component = Component::from_binary(&engine, &wasm)
+        .unwrap_or_else(|e| panic!("failed to compile fib.wasm: {e}"));
+    let runtime = Runtime::new()
+        .unwrap_or_else(|e| panic!("failed to start the tokio runtime: {e}"));
+
+    let mut group = c.benchmark_group("trivial_job");
+    group.bench_function("fresh_instance", |b| {
+        b.iter(|| runtime.block_on(async {
+            let (store, command) = instantiate(&engine, &linker, &component).await;
+            run(store, command).await
+        }))
+    });
+    group.bench_function("pooled_instance", |b| {
+        b.iter_batched(
+            || runtime.block_on(instantiate(&engine, &linker, &component)),
+            |(store, command)| runtime.block_on(run(store, command)),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, warm_pool);
+criterion_main!(benches);
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use shared::JobId;
+
+use crate::errors::ExecutorError;
+use crate::worker::Worker;
+
+/// Why an in-flight job was told to stop. Each ends the job differently, see ExecutorError and
+/// JobGuard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The client cancelled the job.
+    Cancelled,
+    /// The job ran past its deadline.
+    TimedOut,
+    /// The Worker is shutting down and stopped waiting for the job to finish.
+    Aborted,
+    /// The client's connection dropped, so nobody is waiting for the job's result.
+    ClientDisconnected,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StopReason::Cancelled => "cancelled",
+            StopReason::TimedOut => "timed out",
+            StopReason::Aborted => "aborted",
+            StopReason::ClientDisconnected => "client disconnected",
+        })
+    }
+}
+
+impl From<StopReason> for ExecutorError {
+    fn from(reason: StopReason) -> Self {
+        match reason {
+            StopReason::Cancelled => ExecutorError::JobCancelled,
+            StopReason::TimedOut => ExecutorError::JobTimedOut,
+            StopReason::Aborted => ExecutorError::JobAborted,
+            StopReason::ClientDisconnected => ExecutorError::ClientDisconnected,
+        }
+    }
+}
+
+/// Tells an in-flight job to stop, and why. Clones share the job's token, and only the first
+/// reason it is stopped for counts, so triggers that fire together agree on the outcome.
+#[derive(Debug, Clone, Default)]
+pub struct JobCancellation {
+    token: CancellationToken,
+    reason: Arc<OnceLock<StopReason>>,
+}
+
+impl JobCancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tells the job to stop. No-op if it was already told to.
+    pub fn stop(&self, reason: StopReason) {
+        if self.reason.set(reason).is_ok() {
+            self.token.cancel();
+        }
+    }
+
+    /// Why the job was told to stop, if it was.
+    pub fn reason(&self) -> Option<StopReason> {
+        self.reason.get().copied()
+    }
+
+    /// Waits until the job is told to stop, stopping it as timed out if the deadline passes
+    /// first, and returns why it stopped.
+    pub async fn stopped(&self, deadline: Option<Instant>) -> StopReason {
+        tokio::select! {
+            _ = self.token.cancelled() => {},
+            _ = async {
+                match deadline {
+                    Some(d) => tokio::time::sleep_until(d).await,
+                    None => std::future::pending().await,
+                }
+            } => self.stop(StopReason::TimedOut),
+        }
+        // The reason is always set before the token is cancelled
+        self.reason().unwrap_or(StopReason::TimedOut)
+    }
+
+    /// Stops the job for the reason once the returned guard is dropped, unless it is disarmed
+    /// first. Request handlers hold one so that the job stops if the handler is dropped, which
+    /// happens when the client's connection drops.
+    pub fn stop_on_drop(&self, reason: StopReason) -> StopOnDrop {
+        StopOnDrop { cancellation: Some(self.clone()), reason }
+    }
+}
+
+/// Stops a job when dropped, see JobCancellation::stop_on_drop.
+pub struct StopOnDrop {
+    cancellation: Option<JobCancellation>,
+    reason: StopReason,
+}
+
+impl StopOnDrop {
+    /// Lets the guard drop without stopping the job, once its result has been handed back.
+    pub fn disarm(mut self) {
+        self.cancellation = None;
+    }
+}
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        if let Some(cancellation) = &self.cancellation {
+            cancellation.stop(self.reason);
+        }
+    }
+}
+
+impl Worker {
+    /// Stops a job in flight on this Worker for the reason. Returns false if it isn't running.
+    pub(crate) fn stop_job(&self, job_id: JobId, reason: StopReason) -> bool {
+        let Some(cancellation) = self.active_jobs.get(&job_id) else {
+            return false;
+        };
+        cancellation.stop(reason);
+        self.wasm_engine.increment_epoch(); // increment the epoch immediately so control is yielded back
+        true
+    }
+
+    /// Aborts every job in flight, e.g. once a shutdown has waited long enough for them. Their
+    /// clients get an error they retry on another Worker.
+    pub(crate) fn abort_jobs(&self) {
+        let job_ids: Vec<_> = self.active_jobs.iter().map(|job| *job.key()).collect();
+        tracing::warn!(jobs = job_ids.len(), "aborting jobs still running at shutdown");
+        for job_id in job_ids {
+            self.stop_job(job_id, StopReason::Aborted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn each_trigger_stops_the_job_with_its_reason() {
+        for reason in [StopReason::Cancelled, StopReason::TimedOut, StopReason::Aborted, StopReason::ClientDisconnected] {
+            let cancellation = JobCancellation::new();
+            cancellation.stop(reason);
+            assert_eq!(cancellation.stopped(None).await, reason);
+        }
+    }
+
+    #[test]
+    fn each_reason_fails_the_job_differently() {
+        let errors = [StopReason::Cancelled, StopReason::TimedOut, StopReason::Aborted, StopReason::ClientDisconnected]
+            .map(|reason| tonic::Status::from(ExecutorError::from(reason)).code());
+        assert_eq!(errors, [tonic::Code::Cancelled, tonic::Code::DeadlineExceeded, tonic::Code::Unavailable, tonic::Code::Cancelled]);
+    }
+
+    #[tokio::test]
+    async fn the_first_reason_wins() {
+        let cancellation = JobCancellation::new();
+        let clone = cancellation.clone();
+        clone.stop(StopReason::Aborted);
+        cancellation.stop(StopReason::Cancelled);
+        assert_eq!(cancellation.reason(), Some(StopReason::Aborted));
+        assert_eq!(cancellation.stopped(Some(Instant::now())).await, StopReason::Aborted);
+    }
+
+    #[tokio::test]
+    async fn passing_the_deadline_times_the_job_out() {
+        let cancellation = JobCancellation::new();
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert_eq!(cancellation.stopped(Some(deadline)).await, StopReason::TimedOut);
+        // Later triggers don't change how it ended
+        cancellation.stop(StopReason::Cancelled);
+        assert_eq!(cancellation.reason(), Some(StopReason::TimedOut));
+    }
+
+    #[tokio::test]
+    async fn waiting_without_a_deadline_lasts_until_stopped() {
+        let cancellation = JobCancellation::new();
+        assert!(tokio::time::timeout(Duration::from_millis(50), cancellation.stopped(None)).await.is_err());
+        assert_eq!(cancellation.reason(), None);
+    }
+
+    #[test]
+    fn dropping_the_guard_stops_the_job() {
+        let cancellation = JobCancellation::new();
+        drop(cancellation.stop_on_drop(StopReason::ClientDisconnected));
+        assert_eq!(cancellation.reason(), Some(StopReason::ClientDisconnected));
+    }
+
+    #[test]
+    fn a_disarmed_guard_leaves_the_job_alone() {
+        let cancellation = JobCancellation::new();
+        cancellation.stop_on_drop(StopReason::ClientDisconnected).disarm();
+        assert_eq!(cancellation.reason(), None);
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{Notify, watch};
+use tokio::time::Instant;
+use tonic::{Response, Status};
+
+use shared::{JobId, JobRequest, JobResponse};
+
+use crate::cancellation::{JobCancellation, StopReason};
+use crate::errors::ExecutorError;
+use crate::executor::module_hash;
+use crate::job_guard::JobGuard;
+use crate::orchestrators::OrchestratorId;
+use crate::worker::Worker;
+
+/// How long a coalesced job waits for the job it was coalesced with to start here, and how long
+/// a finished run's result is kept for coalesced jobs that arrive late.
+pub(crate) const COALESCE_WAIT: Duration = Duration::from_secs(30);
+
+/// A job's run that jobs the Orchestrator coalesced with it share, keyed in
+/// Worker::coalesced_runs by the job that runs it.
+#[derive(Debug)]
+pub struct CoalescedRun {
+    runner: JobId,
+    /// What identifies the job, see shared::coalesce_key. Jobs sharing the run must match it.
+    key: Vec<u8>,
+    /// The jobs still waiting for the result, the runner included until it is cancelled or
+    /// its deadline passes.
+    waiting: Mutex<HashSet<JobId>>,
+    left: Notify,
+    result: watch::Sender<Option<Result<JobResponse, Status>>>,
+}
+
+impl CoalescedRun {
+    fn new(runner: JobId, key: Vec<u8>) -> Self {
+        let (result, _) = watch::channel(None);
+        Self { runner, key, waiting: Mutex::new(HashSet::from([runner])), left: Notify::new(), result }
+    }
+
+    fn waiting(&self) -> std::sync::MutexGuard<'_, HashSet<JobId>> {
+        self.waiting.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stops a job waiting for the result.
+    fn leave(&self, job_id: JobId) {
+        self.waiting().remove(&job_id);
+        self.left.notify_waiters();
+    }
+
+    /// Detaches the runner once it is cancelled or its deadline passes, and returns when no
+    /// other job waits for the run either, so that it is only stopped then.
+    pub(crate) async fn outlive_runner(&self) {
+        self.leave(self.runner);
+        loop {
+            // Registered before checking, so a job leaving in between still wakes it
+            let left = self.left.notified();
+            if self.waiting().is_empty() {
+                return;
+            }
+            left.await;
+        }
+    }
+}
+
+/// Keeps a job among those waiting for a run until it stops waiting, whichever way it stops.
+struct Waiting<'a> {
+    run: &'a CoalescedRun,
+    job_id: JobId,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.run.leave(self.job_id);
+    }
+}
+
+impl Worker {
+    /// Starts a run that jobs coalesced with the job can share, see Worker::join_coalesced_run.
+    pub(crate) fn start_coalesced_run(&self, job_id: JobId, key: Vec<u8>) -> Arc<CoalescedRun> {
+        let run = Arc::new(CoalescedRun::new(job_id, key));
+        self.coalesced_runs.insert(job_id, run.clone());
+        self.coalesced_run_started.notify_waiters();
+        run
+    }
+
+    /// Publishes a run's result to the jobs sharing it, once. The result is kept for a while
+    /// for jobs that are still on their way.
+    pub(crate) fn finish_coalesced_run(&self, run: &Arc<CoalescedRun>, result: &Result<Response<JobResponse>, Status>) {
+        let result = result.as_ref().map(|response| response.get_ref().clone()).map_err(Status::clone);
+        let first = run.result.send_if_modified(|published| {
+            if published.is_some() {
+                return false;
+            }
+            *published = Some(result);
+            true
+        });
+        if !first {
+            return;
+        }
+        let worker = self.clone();
+        let run = run.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(COALESCE_WAIT).await;
+            worker.coalesced_runs.remove_if(&run.runner, |_, kept| Arc::ptr_eq(kept, &run));
+        });
+    }
+
+    /// Runs a job the Orchestrator coalesced with another job by sharing that job's run, which
+    /// holds the credit. The job reports its own final state, stopping on its own cancellation
+    /// or deadline as other jobs do.
+    pub(crate) async fn execute_coalesced_job(&self, dispatcher: OrchestratorId, job_id: JobId, runner: JobId, request: &JobRequest, deadline: Option<Instant>) -> Result<Response<JobResponse>, Status> {
+        let cancellation = JobCancellation::new();
+        self.active_jobs.insert(job_id, cancellation.clone());
+        let mut job_guard = JobGuard::new(self.orchestrators.clone(), dispatcher, self.active_jobs.clone(), job_id);
+        job_guard.set_no_credit();
+        // Dropped before the job guard, so a dropped connection is what the guard reports
+        let disconnected = cancellation.stop_on_drop(StopReason::ClientDisconnected);
+
+        self.check_job(request)?;
+        let key = shared::coalesce_key(&module_hash(request), &request.args);
+        let result = tokio::select! {
+            result = self.join_coalesced_run(job_id, runner, &key) => {
+                if result.is_ok() {
+                    job_guard.set_completed();
+                }
+                result
+            },
+            reason = cancellation.stopped(deadline) => {
+                tracing::info!(job_id = %job_id, reason = %reason, "job stopped");
+                Err(ExecutorError::from(reason).into())
+            },
+        };
+        disconnected.disarm();
+        result
+    }
+
+    /// Waits for the run of the job a job was coalesced with, checks the job is identical to
+    /// it, and returns its result once it finishes. The runner's response is the job's, but
+    /// marked as coalesced and at no cost, since the runner was charged for it and uploaded its
+    /// module.
+    async fn join_coalesced_run(&self, job_id: JobId, runner: JobId, key: &[u8]) -> Result<Response<JobResponse>, Status> {
+        let run = self.await_coalesced_run(runner).await?;
+        if run.key != key {
+            return Err(ExecutorError::CoalescedJobMismatch.into());
+        }
+        run.waiting().insert(job_id);
+        let _waiting = Waiting { run: &run, job_id };
+        tracing::debug!(job_id = %job_id, runner = %runner, "job joined a coalesced run");
+        let result = run.result.subscribe().wait_for(Option::is_some).await.ok().and_then(|result| result.clone());
+        let Some(result) = result else {
+            tracing::error!("ERROR: coalesced run dropped without a result while a job was waiting on it, this should never happen");
+            std::process::exit(1);
+        };
+        result.map(|response| Response::new(JobResponse { coalesced_with: Some(runner.to_bytes()), estimated_cost: 0.0, upload_bytes_saved: 0, instantiation_saved_ms: 0, ..response }))
+    }
+
+    /// Waits up to COALESCE_WAIT for the runner's run to start, as it may arrive after the jobs
+    /// coalesced with it.
+    async fn await_coalesced_run(&self, runner: JobId) -> Result<Arc<CoalescedRun>, ExecutorError> {
+        let deadline = tokio::time::Instant::now() + COALESCE_WAIT;
+        loop {
+            // Registered before checking, so a run starting in between still wakes it
+            let started = self.coalesced_run_started.notified();
+            if let Some(run) = self.coalesced_runs.get(&runner) {
+                return Ok(run.clone());
+            }
+            if tokio::time::timeout_at(deadline, started).await.is_err() {
+                return Err(ExecutorError::CoalescedJobNotFound);
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::{Arc, Mutex};
+
+use blake3::Hash;
+use lru::LruCache;
+
+use shared::DeltaUpload;
+
+use crate::errors::ExecutorError;
+use crate::worker::Worker;
+
+/// The wasm of recently compiled modules, kept so that clients can upload new versions of them
+/// as patches against them, see shared::delta. Least recently used modules are dropped once
+/// they add up to more than the budget.
+#[derive(Debug)]
+pub struct DeltaBases {
+    budget: usize,
+    bases: Mutex<Bases>,
+}
+
+#[derive(Debug)]
+struct Bases {
+    wasm: LruCache<Hash, Arc<Vec<u8>>>,
+    total_bytes: usize,
+}
+
+impl DeltaBases {
+    /// Keeps up to budget bytes of wasm. A budget of zero turns delta uploads off.
+    pub fn new(budget: usize) -> Self {
+        Self { budget, bases: Mutex::new(Bases { wasm: LruCache::unbounded(), total_bytes: 0 }) }
+    }
+
+    fn bases(&self) -> std::sync::MutexGuard<'_, Bases> {
+        self.bases.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Keeps a compiled module's wasm as a base, unless it alone is over the budget.
+    pub(crate) fn insert(&self, wasm_hash: Hash, wasm_bytes: Vec<u8>) {
+        if wasm_bytes.len() > self.budget {
+            return;
+        }
+        let mut bases = self.bases();
+        let added = wasm_bytes.len();
+        if let Some(replaced) = bases.wasm.put(wasm_hash, Arc::new(wasm_bytes)) {
+            bases.total_bytes -= replaced.len();
+        }
+        bases.total_bytes += added;
+        while bases.total_bytes > self.budget {
+            let Some((_, dropped)) = bases.wasm.pop_lru() else {
+                break;
+            };
+            bases.total_bytes -= dropped.len();
+        }
+    }
+
+    fn get(&self, wasm_hash: &Hash) -> Option<Arc<Vec<u8>>> {
+        self.bases().wasm.get(wasm_hash).cloned()
+    }
+}
+
+impl Worker {
+    /// Rebuilds a module uploaded as a patch against a base this Worker keeps, checking that
+    /// it hashes to wasm_hash.
+    pub(crate) async fn apply_delta(&self, delta: &DeltaUpload, wasm_hash: &[u8]) -> Result<Vec<u8>, ExecutorError> {
+        let base_hash = <[u8; 32]>::try_from(delta.base_hash.as_slice())
+            .map(Hash::from_bytes)
+            .map_err(|_| ExecutorError::DeltaMismatch("base_hash must be a 32 byte blake3 hash".to_string()))?;
+        let base = self.delta_bases.get(&base_hash).ok_or(ExecutorError::DeltaBaseMissing(base_hash))?;
+
+        let patch = delta.patch.clone();
+        let max_bytes = self.limits.max_wasm_bytes;
+        let wasm_bytes = tokio::task::spawn_blocking(move || shared::delta::apply_patch(&base, &patch, max_bytes))
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!(error = %e, "ERROR: delta upload task panicked, this should never happen");
+                std::process::exit(1);
+            })
+            .map_err(|e| ExecutorError::DeltaMismatch(format!("the patch doesn't apply to base {base_hash}: {e}")))?;
+
+        let rebuilt_hash = blake3::hash(&wasm_bytes);
+        if rebuilt_hash.as_bytes().as_slice() != wasm_hash {
+            return Err(ExecutorError::DeltaMismatch(format!("the patch rebuilt a module that hashes to {rebuilt_hash}, not the expected hash")));
+        }
+        Ok(wasm_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const BUDGET: usize = 1000;
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        /// Keep a module of len bytes under one of a few hashes.
+        Insert { key: u8, len: usize },
+        Get(u8),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..6u8, 0..BUDGET * 3 / 2).prop_map(|(key, len)| Op::Insert { key, len }),
+            (0..6u8).prop_map(Op::Get),
+        ]
+    }
+
+    proptest! {
+        /// The bases never add up to more than the budget, and a module is always found as it
+        /// was last inserted, straight after inserting it if it fits.
+        #[test]
+        fn bases_stay_within_the_budget(ops in proptest::collection::vec(op(), 1..100)) {
+            let delta_bases = DeltaBases::new(BUDGET);
+            let mut last_inserted: HashMap<u8, Vec<u8>> = HashMap::new();
+            for (n, op) in ops.into_iter().enumerate() {
+                match op {
+                    Op::Insert { key, len } => {
+                        let hash = blake3::hash(&[key]);
+                        // Each insert's bytes differ, so an older version is told apart
+                        let wasm_bytes = vec![n as u8; len];
+                        delta_bases.insert(hash, wasm_bytes.clone());
+                        let kept = delta_bases.get(&hash);
+                        if len <= BUDGET {
+                            prop_assert_eq!(kept.as_deref(), Some(&wasm_bytes));
+                            last_inserted.insert(key, wasm_bytes);
+                        } else {
+                            prop_assert_eq!(kept.as_deref(), last_inserted.get(&key).filter(|_| kept.is_some()));
+                        }
+                    },
+                    Op::Get(key) => {
+                        if let Some(kept) = delta_bases.get(&blake3::hash(&[key])) {
+                            prop_assert_eq!(Some(&*kept), last_inserted.get(&key));
+                        }
+                    },
+                }
+
+                let bases = delta_bases.bases();
+                let total: usize = bases.wasm.iter().map(|(_, wasm)| wasm.len()).sum();
+                prop_assert_eq!(bases.total_bytes, total);
+                prop_assert!(total <= BUDGET, "{total} bytes kept");
+            }
+        }
+    }
+
+    #[test]
+    fn least_recently_used_bases_are_dropped_first() {
+        let delta_bases = DeltaBases::new(BUDGET);
+        let [a, b, c] = [1, 2, 3].map(|key| blake3::hash(&[key]));
+        delta_bases.insert(a, vec![0; 400]);
+        delta_bases.insert(b, vec![0; 400]);
+        delta_bases.get(&a);
+        delta_bases.insert(c, vec![0; 400]);
+        assert!(delta_bases.get(&a).is_some());
+        assert!(delta_bases.get(&b).is_none());
+        assert!(delta_bases.get(&c).is_some());
+    }
+
+    #[test]
+    fn a_budget_of_zero_keeps_nothing() {
+        let delta_bases = DeltaBases::new(0);
+        delta_bases.insert(blake3::hash(b"wasm"), b"wasm".to_vec());
+        assert!(delta_bases.get(&blake3::hash(b"wasm")).is_none());
+    }
+}
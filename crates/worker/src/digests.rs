@@ -0,0 +1,81 @@
+use blake3::Hash;
+use dashmap::DashMap;
+
+use shared::HashAlgorithm;
+use shared::digest::ModuleDigest;
+
+use crate::errors::ExecutorError;
+
+/// The blake3 hash of modules known by another digest, for finding them in the compiled module
+/// cache, which is keyed by blake3. Modules fetched by SHA-256 are always indexed. With
+/// dual_hash, every module compiled is indexed by its SHA-256 digest too, which costs hashing
+/// each module twice.
+#[derive(Debug)]
+pub struct DigestIndex {
+    dual_hash: bool,
+    hashes: DashMap<ModuleDigest, Hash>,
+}
+
+impl DigestIndex {
+    pub fn new(dual_hash: bool) -> Self {
+        Self { dual_hash, hashes: DashMap::new() }
+    }
+
+    /// The algorithms jobs may name modules the Worker has compiled by, see WorkerCapabilities.
+    pub fn algorithms(&self) -> Vec<HashAlgorithm> {
+        if self.dual_hash {
+            vec![HashAlgorithm::Blake3, HashAlgorithm::Sha256]
+        } else {
+            vec![HashAlgorithm::Blake3]
+        }
+    }
+
+    /// The blake3 hash of the module with the digest, if known.
+    pub fn resolve(&self, digest: &ModuleDigest) -> Option<Hash> {
+        match digest.algorithm {
+            HashAlgorithm::Blake3 => Some(Hash::from_bytes(digest.bytes)),
+            _ => self.hashes.get(digest).map(|hash| *hash),
+        }
+    }
+
+    /// Like resolve, for the hash a job sent in place of its wasm, which is only looked up by
+    /// algorithms the Worker advertises.
+    pub fn resolve_job_hash(&self, digest: &ModuleDigest) -> Result<Option<Hash>, ExecutorError> {
+        if !self.algorithms().contains(&digest.algorithm) {
+            return Err(ExecutorError::HashAlgorithmNotIndexed(digest.algorithm));
+        }
+        Ok(self.resolve(digest))
+    }
+
+    pub fn remember(&self, digest: ModuleDigest, wasm_hash: Hash) {
+        if digest.algorithm != HashAlgorithm::Blake3 {
+            self.hashes.insert(digest, wasm_hash);
+        }
+    }
+
+    /// Indexes a newly compiled module's wasm by its other digests, if dual hashing.
+    pub fn index(&self, wasm_bytes: &[u8], wasm_hash: Hash) {
+        if self.dual_hash {
+            self.remember(ModuleDigest::of(HashAlgorithm::Sha256, wasm_bytes), wasm_hash);
+        }
+    }
+}
+
+/// Checks that a fetched module matches its digest. A digest made with the other algorithm than
+/// it is labelled with fails as mislabelled rather than as a mismatch, so that it is fixed at
+/// its source rather than refetched.
+#[cfg(feature = "object-store")]
+pub fn verify(url: &str, digest: &ModuleDigest, wasm_bytes: &[u8]) -> Result<(), ExecutorError> {
+    let actual = ModuleDigest::of(digest.algorithm, wasm_bytes);
+    if actual == *digest {
+        return Ok(());
+    }
+    let other = match digest.algorithm {
+        HashAlgorithm::Sha256 => HashAlgorithm::Blake3,
+        HashAlgorithm::Blake3 => HashAlgorithm::Sha256,
+    };
+    if ModuleDigest::of(other, wasm_bytes).bytes == digest.bytes {
+        return Err(ExecutorError::ModuleDigestMislabelled { url: url.to_string(), labelled: digest.algorithm, actual: other });
+    }
+    Err(ExecutorError::ModuleHashMismatch { url: url.to_string(), algorithm: digest.algorithm, actual: actual.hex() })
+}
@@ -1,3 +1,9 @@
+use shared::{ErrorCode, HashAlgorithm};
+use shared::digest::DigestError;
+use tonic::Code;
+
+use crate::wasm_limits::fmt_stack_size;
+
 /// Enum for all recoverable errors that can occur in the Executor.
 #[derive(Debug, thiserror::Error)]
 pub enum ExecutorError {
@@ -7,36 +13,133 @@ pub enum ExecutorError {
     #[error("wasm is not a valid wasi command component: {0}")]
     InstantiationFailed(wasmtime::Error),
 
-    #[error("wasm execution failed: {0}")]
-    ExecutionFailed(String),
+    #[error("wasm execution failed: exited with code {code}, stderr: {}", String::from_utf8_lossy(stderr))]
+    Exited { code: i32, stderr: Vec<u8> },
+
+    #[error("guest stack overflow (limit {})", fmt_stack_size(*.0))]
+    StackOverflow(usize),
+
+    #[error("compiled module can't be loaded on this worker: {0}")]
+    ArtifactRejected(wasmtime::Error),
 
     #[error("job not found")]
     JobNotFound,
 
+    #[error("the module upload arrived after the worker gave up waiting for it and released the job")]
+    UploadTooLate,
+
+    #[error("wasm module is not cached on this worker")]
+    ModuleNotCached,
+
+    #[error("this worker can't fetch modules from an object store")]
+    ModuleSourceUnsupported,
+
+    #[error("module {0} not found in the object store")]
+    ModuleNotFound(String),
+
+    #[error("the object store denied access to module {0}")]
+    ModuleAccessDenied(String),
+
+    #[error("module {url} doesn't match its {algorithm} digest, it hashes to {actual}")]
+    ModuleHashMismatch { url: String, algorithm: HashAlgorithm, actual: String },
+
+    #[error("module {url}'s digest is labelled {labelled}, but it is the module's {actual} digest")]
+    ModuleDigestMislabelled { url: String, labelled: HashAlgorithm, actual: HashAlgorithm },
+
+    #[error(transparent)]
+    InvalidDigest(#[from] DigestError),
+
+    #[error("this worker doesn't find modules by {0} digest, it wasn't started with --dual-hash")]
+    HashAlgorithmNotIndexed(HashAlgorithm),
+
+    #[error("failed to fetch module: {0}")]
+    ModuleFetchFailed(String),
+
+    #[error("delta upload base {0} is not kept on this worker")]
+    DeltaBaseMissing(blake3::Hash),
+
+    #[error("delta upload rejected: {0}")]
+    DeltaMismatch(String),
+
     #[error("job cancelled by client")]
     JobCancelled,
 
+    #[error("job ran past its timeout")]
+    JobTimedOut,
+
+    #[error("job aborted as the worker shut down")]
+    JobAborted,
+
+    #[error("the client disconnected")]
+    ClientDisconnected,
+
     #[error("received unathenticated jwt token")]
     Unauthenticated,
 
+    #[error("no reservation for this job, the worker only runs jobs the orchestrator reserved a credit for")]
+    ReservationRequired,
+
+    #[error("the job this job was coalesced with never started on this worker")]
+    CoalescedJobNotFound,
+
+    #[error("the job doesn't match the job it was coalesced with")]
+    CoalescedJobMismatch,
+
+    #[error("the job's output is no longer kept on this worker, its retention passed")]
+    OutputExpired,
+
+    #[error("the output was stored for another job")]
+    OutputNotOwned,
+
+    #[error("failed to store or read a job's output: {0}")]
+    OutputStoreFailed(String),
+
     #[error("execution task failed: {0}")]
     ExecutionTaskFailed(String),
 
-    #[error("an unknown error occurred: {0}")]
-    Unknown(String)
+    #[error("wasm trapped: {0}")]
+    Trapped(String),
+
+    #[error("job process stopped: it {0}")]
+    ProcessLimitExceeded(String),
 }
 
 impl From<ExecutorError> for tonic::Status {
     fn from(e: ExecutorError) -> Self {
-        match e {
-            ExecutorError::CompilationFailed(_) => tonic::Status::invalid_argument(e.to_string()),
-            ExecutorError::InstantiationFailed(_) => tonic::Status::invalid_argument(e.to_string()),
-            ExecutorError::ExecutionFailed(_) => tonic::Status::invalid_argument(e.to_string()),
-            ExecutorError::JobNotFound => tonic::Status::not_found(e.to_string()),
-            ExecutorError::JobCancelled => tonic::Status::cancelled(e.to_string()),
-            ExecutorError::Unauthenticated => tonic::Status::unauthenticated(e.to_string()),
-            ExecutorError::ExecutionTaskFailed(_) => tonic::Status::internal(e.to_string()),
-            ExecutorError::Unknown(_) => tonic::Status::unknown(e.to_string()),
-        }
+        let (code, error_code) = match &e {
+            ExecutorError::CompilationFailed(_) => (Code::InvalidArgument, ErrorCode::CompileError),
+            ExecutorError::InstantiationFailed(_) => (Code::InvalidArgument, ErrorCode::CompileError),
+            ExecutorError::Exited { code, stderr } => return shared::exited_status(*code, stderr, e.to_string()),
+            ExecutorError::StackOverflow(_) => (Code::InvalidArgument, ErrorCode::StackOverflow),
+            ExecutorError::ArtifactRejected(_) => (Code::InvalidArgument, ErrorCode::CompileError),
+            ExecutorError::JobNotFound => (Code::NotFound, ErrorCode::JobNotFound),
+            ExecutorError::UploadTooLate => (Code::FailedPrecondition, ErrorCode::ModuleUploadFailed),
+            ExecutorError::ModuleNotCached => (Code::FailedPrecondition, ErrorCode::ModuleNotCached),
+            ExecutorError::ModuleSourceUnsupported => (Code::FailedPrecondition, ErrorCode::ModuleFetchFailed),
+            ExecutorError::ModuleNotFound(_) => (Code::NotFound, ErrorCode::ModuleFetchFailed),
+            ExecutorError::ModuleAccessDenied(_) => (Code::PermissionDenied, ErrorCode::ModuleFetchFailed),
+            ExecutorError::ModuleHashMismatch { .. } => (Code::InvalidArgument, ErrorCode::ModuleFetchFailed),
+            ExecutorError::ModuleDigestMislabelled { .. } => (Code::InvalidArgument, ErrorCode::InvalidModuleDigest),
+            ExecutorError::InvalidDigest(error) => (Code::InvalidArgument, error.error_code()),
+            ExecutorError::HashAlgorithmNotIndexed(_) => (Code::InvalidArgument, ErrorCode::UnsupportedHashAlgorithm),
+            ExecutorError::ModuleFetchFailed(_) => (Code::Unavailable, ErrorCode::ModuleFetchFailed),
+            ExecutorError::DeltaBaseMissing(_) => (Code::FailedPrecondition, ErrorCode::DeltaBaseMissing),
+            ExecutorError::DeltaMismatch(_) => (Code::InvalidArgument, ErrorCode::DeltaMismatch),
+            ExecutorError::JobCancelled => (Code::Cancelled, ErrorCode::JobCancelled),
+            ExecutorError::JobTimedOut => (Code::DeadlineExceeded, ErrorCode::JobTimedOut),
+            ExecutorError::JobAborted => (Code::Unavailable, ErrorCode::JobAborted),
+            ExecutorError::ClientDisconnected => (Code::Cancelled, ErrorCode::JobCancelled),
+            ExecutorError::Unauthenticated => (Code::Unauthenticated, ErrorCode::Unauthorized),
+            ExecutorError::ReservationRequired => (Code::Unavailable, ErrorCode::ReservationRequired),
+            ExecutorError::CoalescedJobNotFound => (Code::Unavailable, ErrorCode::JobNotFound),
+            ExecutorError::CoalescedJobMismatch => (Code::PermissionDenied, ErrorCode::Unauthorized),
+            ExecutorError::OutputExpired => (Code::NotFound, ErrorCode::OutputExpired),
+            ExecutorError::OutputNotOwned => (Code::PermissionDenied, ErrorCode::Unauthorized),
+            ExecutorError::OutputStoreFailed(_) => (Code::Internal, ErrorCode::Internal),
+            ExecutorError::ExecutionTaskFailed(_) => (Code::Internal, ErrorCode::Internal),
+            ExecutorError::Trapped(_) => (Code::InvalidArgument, ErrorCode::ExecutionError),
+            ExecutorError::ProcessLimitExceeded(_) => (Code::InvalidArgument, ErrorCode::ExecutionError),
+        };
+        error_code.status(code, e.to_string())
     }
 }
\ No newline at end of file
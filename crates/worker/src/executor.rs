@@ -1,29 +1,51 @@
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use blake3::Hash;
 
 use jsonwebtoken::{DecodingKey, Validation};
 use tokio::sync::OnceCell;
-use tokio_util::sync::CancellationToken;
 use tonic::metadata::MetadataMap;
-use tonic::{Request, Status, Response};
-use uuid::Uuid;
+use tonic::{Code, Request, Status, Response};
 
 use shared::executor_server::Executor;
-use shared::{CancelJobRequest, CancelJobResponse, JobClaims, JobRequest, JobResponse, JobState};
+use shared::{CancelJobRequest, ErrorCode, CancelJobResponse, FetchOutputRequest, FetchOutputResponse, HashAlgorithm, HealthRequest, HealthResponse, ImportModuleRequest, ImportModuleResponse, JobClaims, JobDetails, JobId, JobRequest, JobResponse, JobState, JobUsage, ListModulesRequest, ListModulesResponse, OutputRef, WarmModuleRequest, WarmModuleResponse, module_source};
+use shared::digest::ModuleDigest;
+use shared::inspect::module_metadata;
+use shared::signing::{signed_bytes, stdin_digest};
 
-use wasmtime::Store;
-use wasmtime::component::{Component, ResourceTable};
+use wasmtime::{Engine, ResourceLimiter, Store, StoreLimits, Trap};
+use wasmtime::component::{Component, Linker, ResourceTable};
 use wasmtime_wasi::p2::bindings::Command;
-use wasmtime_wasi::p2::pipe::MemoryOutputPipe;
+use wasmtime_wasi::p2::pipe::{MemoryInputPipe, MemoryOutputPipe};
 use wasmtime_wasi::{WasiCtx, WasiCtxView, WasiView};
 
+use crate::cancellation::{JobCancellation, StopReason};
+use crate::isolation::Isolation;
 use crate::job_guard::JobGuard;
+use crate::output_pipe::{OutputPipe, OutputSpill, PipeContents};
+use crate::orchestrators::OrchestratorId;
 use crate::worker::Worker;
 use crate::errors::ExecutorError;
+use crate::handoff::handoff_key;
+use crate::wasm_limits::WasmLimits;
+
+/// How long a job's credit is held after a cache miss while waiting for the client to upload the module.
+const UPLOAD_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// How long a job whose upload never arrived is remembered, so that an upload arriving later
+/// is rejected rather than run without a credit.
+const EXPIRED_UPLOAD_MEMORY: Duration = Duration::from_secs(600);
 
 /// Required by wasmtime
 pub struct ComponentRunStates {
     pub wasi_ctx: WasiCtx,
     pub resource_table: ResourceTable,
+    pub limits: StoreLimits,
+    /// Linear memory the store's instances have taken, which is what a pooled instance holds
+    /// on to while it waits for a job, see warm_pool.rs.
+    pub memory_bytes: usize,
 }
 
 /// Exposes the WASI context and resource table to wasmtime-wasi's host function implementations.
@@ -38,9 +60,258 @@ impl WasiView for ComponentRunStates {
 
 impl ComponentRunStates {
     /// An easy way to create a ComponentRunStates with a default ResourceTable and the
-    /// given WasiCtx
-    pub fn new(wasi_ctx: WasiCtx) -> Self {
-        Self { wasi_ctx, resource_table: ResourceTable::new() }
+    /// given WasiCtx and limits
+    pub fn new(wasi_ctx: WasiCtx, limits: StoreLimits) -> Self {
+        Self { wasi_ctx, resource_table: ResourceTable::new(), limits, memory_bytes: 0 }
+    }
+}
+
+/// Enforces the store's limits, counting the memory its instances take as they grow.
+impl ResourceLimiter for ComponentRunStates {
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> wasmtime::Result<bool> {
+        let allowed = self.limits.memory_growing(current, desired, maximum)?;
+        if allowed {
+            self.memory_bytes += desired.saturating_sub(current);
+        }
+        Ok(allowed)
+    }
+
+    fn table_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> wasmtime::Result<bool> {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
+
+/// How a job's command finished, reported the same way whichever way the job was isolated.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RunResult {
+    #[prost(bytes = "vec", tag = "1")]
+    pub stdout: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub stderr: Vec<u8>,
+    /// Set if the command exited early with a code rather than returning from run.
+    #[prost(int32, optional, tag = "3")]
+    pub exit_code: Option<i32>,
+    /// Set if run returned an error.
+    #[prost(bool, tag = "4")]
+    pub failed: bool,
+    /// Set if the runtime failed the command, e.g. because it trapped.
+    #[prost(string, optional, tag = "5")]
+    pub error: Option<String>,
+    /// Set along with error if the guest ran out of stack.
+    #[prost(bool, tag = "6")]
+    pub stack_overflow: bool,
+    /// Set instead of stdout if the output outgrew the job's OutputSpill and was written to it.
+    #[prost(message, optional, tag = "7")]
+    pub output_ref: Option<OutputRef>,
+}
+
+/// Why a job's command didn't succeed.
+pub enum RunFailure {
+    Failed,
+    StackOverflow,
+    Exited(i32),
+    Error(String),
+}
+
+impl RunResult {
+    pub fn outcome(&self) -> Result<(), RunFailure> {
+        match (&self.error, self.exit_code, self.failed) {
+            (Some(_), _, _) if self.stack_overflow => Err(RunFailure::StackOverflow),
+            (Some(e), _, _) => Err(RunFailure::Error(e.clone())),
+            (None, Some(0), _) => Ok(()),
+            (None, Some(code), _) => Err(RunFailure::Exited(code)),
+            (None, None, true) => Err(RunFailure::Failed),
+            (None, None, false) => Ok(()),
+        }
+    }
+
+    /// The code the guest exited with, or None if it trapped. A run that returned an error
+    /// without exiting counts as exiting with 1.
+    pub fn exit_status(&self) -> Option<i32> {
+        match (&self.error, self.exit_code, self.failed) {
+            (Some(_), _, _) => None,
+            (None, Some(code), _) => Some(code),
+            (None, None, true) => Some(1),
+            (None, None, false) => Some(0),
+        }
+    }
+}
+
+/// A module instantiated in a store of its own, which hasn't run anything yet.
+pub struct Instance {
+    pub store: Store<ComponentRunStates>,
+    pub command: Command,
+}
+
+/// Instantiates a compiled job and runs its command to completion, feeding it stdin and
+/// capturing its output. Returns an error only if the module isn't a valid WASI command, or
+/// creates more wasm resources than the limits allow while instantiating.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_command(engine: &Engine, linker: &Linker<ComponentRunStates>, component: &Component, args: &[String], stdin: &[u8], network_access_allowed: bool, wasm_limits: &WasmLimits, spill: Option<OutputSpill>) -> Result<RunResult, wasmtime::Error> {
+    let instance = instantiate(engine, linker, component, wasm_limits).await?;
+    Ok(run_instance(instance, args, stdin, network_access_allowed, wasm_limits.max_output_bytes, spill).await)
+}
+
+/// Instantiates a compiled module, ready to run a job. The job's WASI context is only given
+/// to it by run_instance, so an instance can be made before its job arrives, see warm_pool.rs.
+/// Nothing reads the context while instantiating, since WASI commands only call into it once
+/// they run.
+pub async fn instantiate(engine: &Engine, linker: &Linker<ComponentRunStates>, component: &Component, wasm_limits: &WasmLimits) -> Result<Instance, wasmtime::Error> {
+    let state = ComponentRunStates::new(WasiCtx::builder().build(), wasm_limits.store_limits());
+    let mut store = Store::new(engine, state);
+    store.limiter(|state| state);
+
+    store.epoch_deadline_async_yield_and_update(1);
+    store.set_epoch_deadline(1);
+
+    let command = Command::instantiate_async(&mut store, component, linker).await?;
+    Ok(Instance { store, command })
+}
+
+/// Runs an instance's command to completion with the job's args and stdin, capturing up to
+/// max_output_bytes of each of its stdout and stderr. With a spill, stdout is instead written to
+/// the spill's file once it outgrows the spill's threshold, see OutputPipe. The instance is
+/// dropped afterwards, so no state carries over to another job.
+pub async fn run_instance(instance: Instance, args: &[String], stdin: &[u8], network_access_allowed: bool, max_output_bytes: usize, spill: Option<OutputSpill>) -> RunResult {
+    let Instance { mut store, command } = instance;
+    let stdout_pipe = OutputPipe::new(max_output_bytes, spill);
+    let stderr_pipe = MemoryOutputPipe::new(max_output_bytes);
+
+    let mut wasi_ctx_builder = WasiCtx::builder();
+    wasi_ctx_builder
+        .args(args)
+        .stdin(MemoryInputPipe::new(stdin.to_vec()))
+        .stdout(stdout_pipe.clone())
+        .stderr(stderr_pipe.clone());
+
+    if network_access_allowed {
+        wasi_ctx_builder
+            .allow_ip_name_lookup(true)
+            .inherit_network();
+    }
+
+    // TODO: add env and file system
+    store.data_mut().wasi_ctx = wasi_ctx_builder.build();
+    // A pooled instance's epoch deadline passed while it waited, so it counts from now
+    store.set_epoch_deadline(1);
+
+    let run_result = command.wasi_cli_run().call_run(&mut store).await;
+
+    let mut result = RunResult {
+        stderr: stderr_pipe.contents().to_vec(),
+        ..Default::default()
+    };
+    match run_result {
+        Ok(Ok(())) => {},
+        Ok(Err(())) => result.failed = true,
+        Err(e) => match e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+            Some(exit) => result.exit_code = Some(exit.0),
+            None => {
+                result.stack_overflow = e.downcast_ref::<Trap>() == Some(&Trap::StackOverflow);
+                result.error = Some(e.to_string());
+            },
+        },
+    }
+    match stdout_pipe.finish() {
+        Ok(PipeContents::Inline(stdout)) => result.stdout = stdout,
+        Ok(PipeContents::Stored(output_ref)) => result.output_ref = Some(output_ref),
+        Err(e) => {
+            result.error.get_or_insert_with(|| format!("failed to store the job's output: {e}"));
+        },
+    }
+    result
+}
+
+impl Worker {
+    /// Compiles a module on the blocking thread pool, recording its metadata for ListModules,
+    /// indexing it by its other digests and keeping its wasm as a base for delta uploads.
+    async fn compile(&self, wasm_bytes: Vec<u8>) -> Result<Component, ExecutorError> {
+        let engine = self.wasm_engine.clone();
+        let digests = self.digests.clone();
+        let (component, wasm_hash, metadata, wasm_bytes) = tokio::task::spawn_blocking(move || {
+            let component = Component::from_binary(&engine, &wasm_bytes)
+                .map_err(ExecutorError::CompilationFailed)?;
+            let wasm_hash = blake3::hash(&wasm_bytes);
+            digests.index(&wasm_bytes, wasm_hash);
+            Ok::<_, ExecutorError>((component, wasm_hash, module_metadata(&wasm_bytes), wasm_bytes))
+        })
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!(error = %e, "ERROR: wasm compilation task panicked, this should never happen");
+            std::process::exit(1);
+        })?;
+        self.record_module(wasm_hash, Some(metadata)).await;
+        self.delta_bases.insert(wasm_hash, wasm_bytes);
+        self.refill_warm_pool(wasm_hash, component.clone());
+        Ok(component)
+    }
+
+    /// Holds the job's credit while the client uploads the module after a cache miss,
+    /// releasing it if the upload doesn't arrive in time.
+    fn await_upload(&self, dispatcher: OrchestratorId, job_id: JobId) {
+        if !self.awaiting_upload.insert(job_id) {
+            return;
+        }
+        let worker = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(UPLOAD_GRACE_PERIOD).await;
+            if worker.awaiting_upload.remove(&job_id).is_some() {
+                tracing::debug!(job_id = %job_id, "module upload never arrived, releasing job");
+                worker.expired_uploads.insert(job_id);
+                worker.release_unstarted_job(dispatcher, job_id);
+                tokio::time::sleep(EXPIRED_UPLOAD_MEMORY).await;
+                worker.expired_uploads.remove(&job_id);
+            }
+        });
+    }
+
+    /// Checks that the client speaks our protocol version, that the job is within this worker's
+    /// limits and isn't coalesced with stdin, and, if the worker only runs signed jobs, that it
+    /// was signed by a trusted key.
+    pub(crate) fn check_job(&self, request: &JobRequest) -> Result<(), Status> {
+        shared::compat::check(request.protocol_version)?;
+        request.validate(&self.limits)?;
+        // A coalesced run's output is shared by jobs that only match by module and args
+        if request.coalesce && !request.stdin.is_empty() {
+            return Err(Status::invalid_argument("a job with stdin can't be coalesced"));
+        }
+        if let Some(trusted_keys) = &self.trusted_keys {
+            let message = signed_bytes(&request.job_id, &module_hash(request), &request.stdin, &request.args);
+            trusted_keys.verify(&request.key_id, &message, &request.signature)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the credit for a job that ended before it began executing, and reports it failed
+    /// to the Orchestrator that dispatched it.
+    pub(crate) fn release_unstarted_job(&self, dispatcher: OrchestratorId, job_id: JobId) {
+        self.active_jobs.insert(job_id, JobCancellation::new());
+        drop(JobGuard::new(self.orchestrators.clone(), dispatcher, self.active_jobs.clone(), job_id));
+    }
+}
+
+/// The hash of the job's module. An uploaded module is identified by its actual hash, not the
+/// one the client claims. A fetched module is identified by its digest, which it is checked
+/// against. A module sent as a hash is identified by that hash, whatever its algorithm.
+pub(crate) fn module_hash(request: &JobRequest) -> Vec<u8> {
+    if let Some(module) = request.object_store_module() {
+        module.digest.clone()
+    } else if request.wasm_bytes.is_empty() {
+        request.wasm_hash.clone()
+    } else {
+        blake3::hash(&request.wasm_bytes).as_bytes().to_vec()
     }
 }
 
@@ -56,121 +327,376 @@ impl Executor for Worker {
     ) -> Result<Response<JobResponse>, Status> {
         // Extract request info
         let (metadata, _extensions, request) = request.into_parts();
-        let job_id = Uuid::from_slice(&request.job_id)
-            .unwrap_or_else(|e| {
-                tracing::error!(error = %e, "ERROR: worker received a malformed job id, this should never happen");
-                std::process::exit(1);
-            });
+        let job_id = JobId::from_bytes(&request.job_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed job id: {e}")))?;
 
         tracing::info!(job_id = %job_id, "received job to execute");
-        let wasm_bytes = request.wasm_bytes;
-        let mut wasi_args = vec![job_id.to_string()];
-        wasi_args.extend(request.args);
+        let deadline = request.timeout_ms.map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms));
 
-        // Check authentication
-        self.check_client_auth(&metadata, job_id)?;
+        // Check authentication, then that this worker will run the job. A job coalesced with
+        // another shares its run instead, holding no credit of its own
+        let (dispatcher, coalesced_with) = self.check_client_auth(&metadata, job_id)?;
+        if let Some(runner) = coalesced_with {
+            return self.execute_coalesced_job(dispatcher, job_id, runner, &request, deadline).await;
+        }
+        // A job resent with its module after a cache miss keeps the credit its first attempt
+        // took. Claiming it here stops the grace period from also returning it
+        let resumed_upload = self.awaiting_upload.remove(&job_id).is_some();
+        if !resumed_upload && self.expired_uploads.contains(&job_id) {
+            return Err(ExecutorError::UploadTooLate.into());
+        }
+        if !resumed_upload {
+            if self.require_reservation {
+                self.redeem_reservation(job_id).await?;
+            } else {
+                // The other Orchestrators only learn of the job's credit from the worker
+                self.orchestrators.credit_taken(dispatcher);
+            }
+        }
+        if let Err(status) = self.check_job(&request) {
+            self.release_unstarted_job(dispatcher, job_id);
+            return Err(status);
+        }
+        #[cfg(feature = "fault-injection")]
+        if let Err(status) = shared::faults::inject("submit").await {
+            self.release_unstarted_job(dispatcher, job_id);
+            return Err(status);
+        }
 
-        // RAII credit guard to send credit update back to Orchestrator when dropped
-        // and removes cancellation token
-        let cancellation_token = CancellationToken::new();
-        self.cancellation_tokens.insert(job_id, cancellation_token.clone());
+        let object_store_module = request.object_store_module().cloned();
+        let mut details = JobDetails {
+            module_hash: object_store_module.as_ref().map_or(request.wasm_hash, |module| module.digest.clone()),
+            module_url: object_store_module.as_ref().map(|module| module.url.clone()),
+            args: request.args.clone(),
+            module_hash_algorithm: object_store_module.as_ref().and_then(|module| module.hash_algorithm),
+            stdin_sha256: if request.stdin.is_empty() { Vec::new() } else { stdin_digest(&request.stdin).to_vec() },
+        };
+        let mut wasm_bytes = request.wasm_bytes;
+        let stdin = request.stdin;
+        // An output too large to return is stored as it is written for the client to fetch,
+        // see output_store.rs
+        let spill = request.offload_output.then(|| self.output_store.spill()).flatten();
+        let mut wasi_args = vec![job_id.to_string()];
+        wasi_args.extend(request.args);
 
-        let worker = self.clone();
-        let execute_task = tokio::spawn(async move {
-            let mut job_guard = JobGuard::new(
-                worker.orchestrator_tx.clone(), 
-                worker.cancellation_tokens.clone(),
-                job_id
-            );
+        // A delta upload is rebuilt from its base, then handled like any other upload. If it
+        // can't be, the client uploads the module in full instead
+        let mut upload_bytes_saved = 0;
+        if let Some(delta) = request.delta.filter(|_| object_store_module.is_none()) {
+            match self.apply_delta(&delta, &details.module_hash).await {
+                Ok(rebuilt) => {
+                    upload_bytes_saved = rebuilt.len().saturating_sub(delta.patch.len()) as u64;
+                    tracing::debug!(job_id = %job_id, patch_bytes = delta.patch.len(), upload_bytes_saved, "rebuilt module from delta upload");
+                    wasm_bytes = rebuilt;
+                },
+                Err(e) => {
+                    tracing::debug!(job_id = %job_id, error = %e, "delta upload rejected");
+                    if request.upload_on_miss {
+                        self.await_upload(dispatcher, job_id);
+                    } else {
+                        self.release_unstarted_job(dispatcher, job_id);
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
 
+        // Resolve the module's cache entry, either from the object store, from the uploaded
+        // wasm or, if only a hash was sent, from a module this worker already has compiled.
+        // A hash made with another algorithm than blake3 is reported as the module's blake3 hash.
+        // The module's blake3 hash also picks its warm pool, see warm_pool.rs
+        let (cell, wasm_hash) = if let Some(module) = object_store_module {
+            match self.fetch_module(&module, request.skip_cache).await {
+                Ok((cell, fetched)) => {
+                    wasm_bytes = fetched;
+                    let wasm_hash = module.module_digest().ok().and_then(|digest| self.digests.resolve(&digest));
+                    (cell, wasm_hash)
+                },
+                Err(e) => {
+                    tracing::warn!(job_id = %job_id, url = %module.url, error = %e, "failed to fetch module");
+                    self.release_unstarted_job(dispatcher, job_id);
+                    return Err(e.into());
+                }
+            }
+        } else if wasm_bytes.is_empty() {
+            let wasm_hash = ModuleDigest::from_proto(&details.module_hash, request.wasm_hash_algorithm, HashAlgorithm::Blake3)
+                .map_err(ExecutorError::from)
+                .and_then(|digest| self.digests.resolve_job_hash(&digest));
+            let wasm_hash = match wasm_hash {
+                Ok(wasm_hash) => wasm_hash,
+                Err(e) => {
+                    self.release_unstarted_job(dispatcher, job_id);
+                    return Err(e.into());
+                }
+            };
+            let cell = match wasm_hash {
+                Some(wasm_hash) => {
+                    details.module_hash = wasm_hash.as_bytes().to_vec();
+                    self.component_cache.lock().await
+                        .get(&wasm_hash)
+                        .filter(|cell| cell.initialized())
+                        .cloned()
+                },
+                None => None,
+            };
+            match cell {
+                Some(cell) => (cell, wasm_hash),
+                None => {
+                    tracing::debug!(job_id = %job_id, upload_on_miss = request.upload_on_miss, "module not cached");
+                    if request.upload_on_miss {
+                        self.await_upload(dispatcher, job_id);
+                    } else {
+                        self.release_unstarted_job(dispatcher, job_id);
+                    }
+                    return Err(ExecutorError::ModuleNotCached.into());
+                }
+            }
+        } else {
             let wasm_hash = blake3::hash(&wasm_bytes);
-
-            let cell = worker.component_cache.lock().await
+            details.module_hash = wasm_hash.as_bytes().to_vec();
+            let mut component_cache = self.component_cache.lock().await;
+            if request.skip_cache {
+                component_cache.pop(&wasm_hash);
+            }
+            let cell = component_cache
                 .get_or_insert(wasm_hash, || Arc::new(OnceCell::new()))
                 .clone();
+            (cell, Some(wasm_hash))
+        };
 
-            let cached = cell.initialized();
-            tracing::debug!(job_id = %job_id, cached, "compiling wasm");
-            let component = cell.get_or_try_init(|| async {
-                let engine = worker.wasm_engine.clone();
-                Worker::send_job_update_to_orchestrator(worker.clone().orchestrator_tx, job_id, JobState::Compiling);
-                tokio::task::spawn_blocking(move || {
-                    Component::from_binary(&engine, &wasm_bytes)
-                        .map_err(ExecutorError::CompilationFailed)
-                })
-                .await
-                .unwrap_or_else(|e| {
-                    tracing::error!(error = %e, "ERROR: wasm compilation task panicked, this should never happen");
-                    std::process::exit(1);
-                })
-            })
-            .await?;
-
-            Worker::send_job_update_to_orchestrator(worker.clone().orchestrator_tx, job_id, JobState::Executing);
-
-            let stdout_pipe = MemoryOutputPipe::new(10 * 1024 * 1024); // 10 MB
-            let stderr_pipe = MemoryOutputPipe::new(10 * 1024 * 1024); // 10 MB
-
-            let mut wasi_ctx_builder = WasiCtx::builder();
-            wasi_ctx_builder
-                .args(&wasi_args)
-                .stdout(stdout_pipe.clone())
-                .stderr(stderr_pipe.clone());
-
-            if worker.network_access_allowed.get().copied().unwrap_or(false) {
-                wasi_ctx_builder
-                    .allow_ip_name_lookup(true)
-                    .inherit_network();
-            }
-
-            let wasi_ctx = wasi_ctx_builder.build();
-
-            // TODO: add env and file system
-            let state = ComponentRunStates::new(wasi_ctx);
-            let mut store = Store::new(&worker.wasm_engine, state);
+        let module_bytes = wasm_bytes.len() as u64;
 
-            store.epoch_deadline_async_yield_and_update(1);
-            store.set_epoch_deadline(1);
+        // The job stops when cancelled, past its deadline, aborted at shutdown, or when this
+        // handler is dropped because the client's connection dropped, see cancellation.rs
+        let cancellation = JobCancellation::new();
+        self.active_jobs.insert(job_id, cancellation.clone());
+        let disconnected = cancellation.stop_on_drop(StopReason::ClientDisconnected);
 
-            let command = Command::instantiate_async(&mut store, component, &worker.wasm_linker).await
-                .map_err(ExecutorError::InstantiationFailed)?;
+        // Jobs the Orchestrator coalesces with this one share its run, see coalescing.rs
+        let coalesced_run = request.coalesce
+            .then(|| self.start_coalesced_run(job_id, shared::coalesce_key(&details.module_hash, &details.args)));
 
-            let run_result = tokio::select! {
-                result = command.wasi_cli_run().call_run(&mut store) => result,
-                _ = cancellation_token.cancelled() => {
-                    tracing::info!(job_id = %job_id, "job cancelled");
-                    job_guard.set_cancelled();
-                    return Err(ExecutorError::JobCancelled.into())
+        let worker = self.clone();
+        let runner = self.clone();
+        let run = coalesced_run.clone();
+        let execute_task = tokio::spawn(async move {
+            let shared_run = run.clone();
+            let result = async move {
+                // RAII credit guard to send credit update back to Orchestrator when dropped
+                // and removes the job's cancellation
+                let mut job_guard = JobGuard::new(
+                    worker.orchestrators.clone(),
+                    dispatcher,
+                    worker.active_jobs.clone(),
+                    job_id
+                );
+
+                let cached = cell.initialized();
+                tracing::debug!(job_id = %job_id, cached, "compiling wasm");
+                let compile_started = Instant::now();
+                // A cold start, even if another job is already compiling the module and this one waits on it
+                if !cached {
+                    Worker::send_job_update_to_orchestrator(worker.orchestrators.tx(dispatcher), job_id, JobState::Compiling);
                 }
-            };
-
-            let stdout = stdout_pipe.contents().to_vec();
-            let stderr = stderr_pipe.contents().to_vec();
-
-            match run_result {
-                Ok(Ok(())) => {
-                    tracing::info!(job_id = %job_id, "job completed successfully");
-                    job_guard.set_completed();
-                    Ok(Response::new(JobResponse { stdout, stderr }))
-                },
-                Ok(Err(())) => Err(ExecutorError::ExecutionFailed(format!("stderr: {}", String::from_utf8_lossy(&stderr))).into()),
-                Err(e) => {
-                    if let Some(exit) = e.downcast_ref::<wasmtime_wasi::I32Exit>() {
-                        match exit.0 {
-                            0 => {
-                                job_guard.set_completed();
-                                Ok(Response::new(JobResponse { stdout, stderr }))
-                            },
-                            code => Err(ExecutorError::ExecutionFailed(format!("exited with code {code}, stderr: {}: {}", code, String::from_utf8_lossy(&stderr))).into()),
+                let component = cell.get_or_try_init(|| worker.compile(wasm_bytes)).await?;
+                let compile_ms = compile_started.elapsed().as_millis() as u64;
+
+                worker.jobs_started.fetch_add(1, Ordering::Relaxed);
+                Worker::send_job_executing_to_orchestrator(worker.orchestrators.tx(dispatcher), job_id, details);
+                let execute_started = Instant::now();
+                let network_access_allowed = worker.network_access_allowed.get().copied().unwrap_or(false);
+
+                // A pooled module's job runs an instance made ahead of it, if one is ready
+                let warm = match (worker.isolation, wasm_hash) {
+                    (Isolation::None, Some(wasm_hash)) => worker.take_warm_instance(wasm_hash, component),
+                    _ => None,
+                };
+                let instantiation_saved_ms = warm.as_ref().map_or(0, |warm| warm.instantiate_time.as_millis() as u64);
+                tracing::debug!(job_id = %job_id, warm = warm.is_some(), "running job");
+                let run = async {
+                    match (worker.isolation, warm) {
+                        (_, Some(warm)) => Ok(run_instance(warm.instance, &wasi_args, &stdin, network_access_allowed, worker.wasm_limits.max_output_bytes, spill.clone()).await),
+                        (Isolation::None, None) => run_command(&worker.wasm_engine, &worker.wasm_linker, component, &wasi_args, &stdin, network_access_allowed, &worker.wasm_limits, spill.clone()).await
+                            .map_err(ExecutorError::InstantiationFailed),
+                        (Isolation::Process, None) => worker.run_isolated(component, wasi_args, stdin, network_access_allowed, spill.clone()).await,
+                    }
+                };
+                // Dropping the run stops the guest at its next epoch tick, or kills its helper process.
+                // A shared run carries on past its runner being stopped while other jobs wait for it
+                let run_result = tokio::select! {
+                    result = run => result?,
+                    reason = async {
+                        let reason = cancellation.stopped(deadline).await;
+                        if let Some(run) = &shared_run {
+                            run.outlive_runner().await;
                         }
-                    } else {
-                        Err(ExecutorError::Unknown(e.to_string()).into())
+                        reason
+                    } => {
+                        tracing::info!(job_id = %job_id, reason = %reason, "job stopped");
+                        if let Some(spill) = &spill {
+                            worker.output_store.discard(spill).await;
+                        }
+                        let execute_ms = execute_started.elapsed().as_millis() as u64;
+                        job_guard.set_usage(JobUsage { execute_ms, compiled: !cached, module_bytes });
+                        return Err(ExecutorError::from(reason).into())
                     }
+                };
+
+                let execute_ms = execute_started.elapsed().as_millis() as u64;
+                let usage = JobUsage { execute_ms, compiled: !cached, module_bytes };
+                let estimated_cost = worker.cost_weights.get().copied().unwrap_or_default().estimate(&usage);
+                job_guard.set_usage(usage);
+                job_guard.set_exit_code(run_result.exit_status());
+                let outcome = run_result.outcome();
+                let response = JobResponse { stdout: run_result.stdout, stderr: run_result.stderr, compile_ms, execute_ms, cache_hit: cached, estimated_cost, coalesced_with: None, upload_bytes_saved, instantiation_saved_ms, output_ref: run_result.output_ref };
+                if let Some(spill) = spill.as_ref().filter(|_| outcome.is_err()) {
+                    worker.output_store.discard(spill).await;
                 }
+
+                match outcome {
+                    Ok(()) => {
+                        tracing::info!(job_id = %job_id, "job completed successfully");
+                        if let Some(output_ref) = &response.output_ref {
+                            tracing::debug!(job_id = %job_id, output_id = %output_ref.id, bytes = output_ref.size, "stored job output");
+                            worker.output_store.insert(output_ref, job_id);
+                        }
+                        job_guard.set_completed();
+                        Ok(Response::new(response))
+                    },
+                    Err(RunFailure::StackOverflow) => Err(ExecutorError::StackOverflow(worker.wasm_limits.stack_bytes).into()),
+                    // A run that failed without exiting counts as exiting with 1, see exit_status
+                    Err(RunFailure::Failed) => Err(ExecutorError::Exited { code: 1, stderr: response.stderr }.into()),
+                    Err(RunFailure::Exited(code)) => Err(ExecutorError::Exited { code, stderr: response.stderr }.into()),
+                    Err(RunFailure::Error(e)) => Err(ExecutorError::Trapped(e).into()),
+                }
+            }.await;
+            if let Some(run) = &run {
+                runner.finish_coalesced_run(run, &result);
             }
+            result
         });
 
-        execute_task.await.unwrap_or_else(|e| Err(ExecutorError::ExecutionTaskFailed(e.to_string()).into()))
+        let result = execute_task.await.unwrap_or_else(|e| Err(ExecutorError::ExecutionTaskFailed(e.to_string()).into()));
+        disconnected.disarm();
+        if let Some(run) = &coalesced_run {
+            self.finish_coalesced_run(run, &result);
+        }
+        result
+    }
+
+    /// A function exposed by the Worker for the Orchestrator to call to compile a module into
+    /// the cache ahead of the jobs that will run it. Uses no credit, since nothing is run.
+    async fn warm_module(
+        &self,
+        request: Request<WarmModuleRequest>
+    ) -> Result<Response<WarmModuleResponse>, Status> {
+        let (metadata, _extensions, request) = request.into_parts();
+        let warm_id = JobId::from_bytes(&request.warm_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed warm id: {e}")))?;
+        self.check_client_auth(&metadata, warm_id)?;
+        shared::compat::check(request.protocol_version)?;
+
+        let object_store_module = request.module_source
+            .and_then(|module_source| module_source.source)
+            .map(|module_source::Source::ObjectStore(module)| module);
+        let (cell, wasm_bytes) = match object_store_module {
+            Some(module) => self.fetch_module(&module, false).await?,
+            None => {
+                self.limits.check(request.wasm_bytes.len(), &[])?;
+                let wasm_hash = blake3::hash(&request.wasm_bytes);
+                let cell = self.component_cache.lock().await
+                    .get_or_insert(wasm_hash, || Arc::new(OnceCell::new()))
+                    .clone();
+                (cell, request.wasm_bytes)
+            },
+        };
+
+        let cached = cell.initialized();
+        let compile_started = Instant::now();
+        cell.get_or_try_init(|| self.compile(wasm_bytes)).await?;
+        let compile_ms = if cached { 0 } else { compile_started.elapsed().as_millis() as u64 };
+        tracing::info!(cached, compile_ms, "module warmed");
+        Ok(Response::new(WarmModuleResponse { cached, compile_ms }))
+    }
+
+    /// A function exposed by the Worker for a peer Worker that is shutting down to hand over
+    /// one of its compiled modules, see Worker::hand_off_modules. Uses no credit.
+    async fn import_module(
+        &self,
+        request: Request<ImportModuleRequest>
+    ) -> Result<Response<ImportModuleResponse>, Status> {
+        let (metadata, _extensions, request) = request.into_parts();
+        let import_id = JobId::from_bytes(&request.import_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed import id: {e}")))?;
+        self.check_peer_auth(&metadata, import_id)?;
+        shared::compat::check(request.protocol_version)?;
+        let wasm_hash = <[u8; 32]>::try_from(request.wasm_hash.as_slice())
+            .map(Hash::from_bytes)
+            .map_err(|_| Status::invalid_argument("wasm_hash must be a 32 byte blake3 hash"))?;
+
+        let cell = self.component_cache.lock().await
+            .get_or_insert(wasm_hash, || Arc::new(OnceCell::new()))
+            .clone();
+        let cached = cell.initialized();
+        if !cached {
+            // SAFETY: the artifact was serialized by a peer Worker from a module it compiled, as
+            // only Workers can issue tokens with handoff_key. Artifacts from a different
+            // wasmtime version or engine configuration fail to deserialize rather than load.
+            let component = unsafe { Component::deserialize(&self.wasm_engine, &request.artifact) }
+                .map_err(ExecutorError::ArtifactRejected)?;
+            cell.get_or_init(|| async { component }).await;
+            self.record_module(wasm_hash, None).await;
+        }
+        tracing::info!(wasm_hash = %wasm_hash, cached, "module imported from a peer");
+        Ok(Response::new(ImportModuleResponse { cached }))
+    }
+
+    /// A function exposed by the Worker for the Orchestrator to list its compiled modules on a
+    /// client's behalf, for debugging which module a job ran. Uses no credit.
+    async fn list_modules(
+        &self,
+        request: Request<ListModulesRequest>
+    ) -> Result<Response<ListModulesResponse>, Status> {
+        let (metadata, _extensions, request) = request.into_parts();
+        let list_id = JobId::from_bytes(&request.list_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed list id: {e}")))?;
+        self.check_client_auth(&metadata, list_id)?;
+        shared::compat::check(request.protocol_version)?;
+        let wasm_hash = request.wasm_hash
+            .map(|hash| <[u8; 32]>::try_from(hash.as_slice()).map(Hash::from_bytes))
+            .transpose()
+            .map_err(|_| Status::invalid_argument("wasm_hash must be a 32 byte blake3 hash"))?;
+
+        let modules = self.cached_modules(wasm_hash).await;
+        if let Some(wasm_hash) = wasm_hash
+            && modules.is_empty()
+        {
+            return Err(ErrorCode::ModuleNotCached.status(Code::NotFound, format!("module {wasm_hash} is not cached on this worker")));
+        }
+        Ok(Response::new(ListModulesResponse { modules }))
+    }
+
+    /// A function exposed by the Worker for the Orchestrator to check it is reachable before
+    /// dispatching jobs to it. Answers straight away, needing no auth.
+    async fn health(
+        &self,
+        _request: Request<HealthRequest>
+    ) -> Result<Response<HealthResponse>, Status> {
+        Ok(Response::new(HealthResponse {}))
+    }
+
+    /// A function exposed by the Worker for the Client to call to read a job output the Worker
+    /// stored rather than returned, see output_store.rs. Uses no credit.
+    async fn fetch_output(
+        &self,
+        request: Request<FetchOutputRequest>
+    ) -> Result<Response<FetchOutputResponse>, Status> {
+        let (metadata, _extensions, request) = request.into_parts();
+        let job_id = JobId::from_bytes(&request.job_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed job id: {e}")))?;
+        let (_, coalesced_with) = self.check_client_auth(&metadata, job_id)?;
+        let (data, eof) = self.read_output(&request.output_id, job_id, coalesced_with, request.offset, request.max_bytes).await?;
+        Ok(Response::new(FetchOutputResponse { data, eof }))
     }
 
     /// A function exposed by the Worker for the Client to call
@@ -181,59 +707,59 @@ impl Executor for Worker {
         request: Request<CancelJobRequest>
     ) -> Result<Response<CancelJobResponse>, Status> {
         let (metadata, _extensions, request) = request.into_parts();
-        let job_id = Uuid::from_slice(&request.job_id)
-            .unwrap_or_else(|e| {
-                tracing::error!(error = %e, "ERROR: worker received a malformed job id, this should never happen");
-                std::process::exit(1);
-            });
+        let job_id = JobId::from_bytes(&request.job_id)
+            .map_err(|e| Status::invalid_argument(format!("malformed job id: {e}")))?;
 
         // Check authentication
-        self.check_client_auth(&metadata, job_id)?;
+        let (dispatcher, _) = self.check_client_auth(&metadata, job_id)?;
 
-        // Send job update to orchestrator
-        Worker::send_job_update_to_orchestrator(self.orchestrator_tx.clone(), job_id, JobState::Cancelled);
+        // Send job update to the orchestrator that dispatched the job
+        Worker::send_job_update_to_orchestrator(self.orchestrators.tx(dispatcher), job_id, JobState::Cancelled);
         
-        // Cancel the job via the cancellation token
-        match self.cancellation_tokens.get(&job_id) {
-            Some(cancellation_token) => {
-                cancellation_token.cancel();
-                self.wasm_engine.increment_epoch(); // increment the epoch immediately so control is yielded back
-                Ok(Response::new(CancelJobResponse {}))
-            },
-            None => {
-                Err(ExecutorError::JobNotFound.into())
-            }
+        if self.stop_job(job_id, StopReason::Cancelled) {
+            Ok(Response::new(CancelJobResponse {}))
+        } else {
+            Err(ExecutorError::JobNotFound.into())
         }
     }
 }
 
 impl Worker {
-    /// Verifies the JWT token in the request metadata matches the given job_id.
+    /// Verifies the JWT token in the request metadata matches the given job_id, returning the
+    /// Orchestrator that issued it and the job it was coalesced with, if any.
     /// Returns Unauthenticated if the token is missing, invalid, or bound to a different job.
-    fn check_client_auth(&self, metadata: &MetadataMap, job_id: Uuid) -> Result<(), ExecutorError> {
-        let jwt_token = metadata.get("authorization")
-            .ok_or(ExecutorError::Unauthenticated)?;
-
-        // jwt_secret not being set is an invariant violation — the worker registered with the
-        // orchestrator before accepting any jobs, so this should never happen.
-        let secret = self.jwt_secret.get()
-            .unwrap_or_else(|| {
-                tracing::error!("ERROR: worker should always have received jwt secret before running a job, this should never happen");
-                std::process::exit(1);
-            });
-
-        let token_data = jsonwebtoken::decode(
-            jwt_token, 
-            &DecodingKey::from_secret(secret), 
-            &Validation::default()
-        ).map_err(|_| ExecutorError::Unauthenticated)?;
-
-        let job_claims: JobClaims = token_data.claims;
-
-        if job_claims.sub != job_id {
-            Err(ExecutorError::Unauthenticated)
-        } else {
-            Ok(())
-        }
+    fn check_client_auth(&self, metadata: &MetadataMap, job_id: JobId) -> Result<(OrchestratorId, Option<JobId>), ExecutorError> {
+        self.orchestrators.jwt_secrets()
+            .find_map(|(id, secret)| check_jwt(metadata, job_id, secret).ok().map(|claims| (id, claims.coalesced_with)))
+            .ok_or(ExecutorError::Unauthenticated)
     }
-}
\ No newline at end of file
+
+    /// Like check_client_auth, for requests from peer Workers. Their tokens are issued with
+    /// handoff_key, which clients can't issue tokens with.
+    fn check_peer_auth(&self, metadata: &MetadataMap, request_id: JobId) -> Result<(), ExecutorError> {
+        self.orchestrators.jwt_secrets()
+            .find_map(|(_, secret)| check_jwt(metadata, request_id, &handoff_key(secret)).ok())
+            .map(|_| ())
+            .ok_or(ExecutorError::Unauthenticated)
+    }
+}
+
+/// Checks the request carries a JWT issued with secret for job_id, returning its claims.
+fn check_jwt(metadata: &MetadataMap, job_id: JobId, secret: &[u8]) -> Result<JobClaims, ExecutorError> {
+    let jwt_token = metadata.get("authorization")
+        .ok_or(ExecutorError::Unauthenticated)?;
+
+    let token_data = jsonwebtoken::decode(
+        jwt_token, 
+        &DecodingKey::from_secret(secret), 
+        &Validation::default()
+    ).map_err(|_| ExecutorError::Unauthenticated)?;
+
+    let job_claims: JobClaims = token_data.claims;
+
+    if job_claims.sub != job_id {
+        Err(ExecutorError::Unauthenticated)
+    } else {
+        Ok(job_claims)
+    }
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use blake3::Hash;
+use jsonwebtoken::{EncodingKey, Header};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tonic::Request;
+use tonic::transport::Channel;
+use wasmtime::component::Component;
+
+use shared::compat::PROTOCOL_VERSION;
+use shared::executor_client::ExecutorClient;
+use shared::{HandedOffModule, HandoffSummary, ImportModuleRequest, JobClaims, JobId, Peer, PeerListRequest, WorkerMessage, orchestrator_message, worker_message};
+
+use crate::worker::Worker;
+
+/// Time kept back from a handoff's deadline to report the summary to the Orchestrator.
+const SUMMARY_TIME: Duration = Duration::from_millis(500);
+
+/// Settings for handing a Worker's compiled modules to its peers as it shuts down, so the
+/// compilation work isn't lost with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandoffConfig {
+    /// Most bytes of compiled modules sent in total. Modules are sent most recently used
+    /// first, and those that don't fit are skipped.
+    pub max_bytes: usize,
+    /// How long the handoff may take, including reporting it to the Orchestrator. Shutdown
+    /// never waits longer than this for it.
+    pub deadline: Duration,
+}
+
+/// The key Workers issue each other's ImportModule tokens with. It is derived from the jwt
+/// secret so that only Workers have it: clients hold tokens for their own jobs, which must not
+/// let them hand a Worker native code.
+pub(crate) fn handoff_key(jwt_secret: &[u8; 32]) -> [u8; 32] {
+    blake3::derive_key("mini-lambda 2024 worker module handoff", jwt_secret)
+}
+
+impl Worker {
+    /// Sends this Worker's compiled modules to the least loaded of its peers, then reports
+    /// what was handed off to the Orchestrator. Called once the executor server has stopped,
+    /// so no jobs are running. Returns by the config's deadline whatever happens.
+    pub(crate) async fn hand_off_modules(&self, config: HandoffConfig) {
+        let started = Instant::now();
+        let deadline = started + config.deadline;
+        let modules: Vec<(Hash, Component)> = self.component_cache.lock().await.iter()
+            .filter_map(|(hash, cell)| cell.get().map(|component| (*hash, component.clone())))
+            .collect();
+        if modules.is_empty() {
+            tracing::info!("no compiled modules to hand off");
+            return;
+        }
+
+        let mut summary = HandoffSummary::default();
+        let push_deadline = started + config.deadline.saturating_sub(SUMMARY_TIME);
+        if tokio::time::timeout_at(push_deadline, self.push_modules(&modules, config.max_bytes, &mut summary)).await.is_err() {
+            tracing::warn!(deadline = ?config.deadline, "module handoff ran out of time");
+        }
+        summary.skipped = modules.len() as u32 - summary.modules.len() as u32 - summary.failed;
+        tracing::info!(
+            modules = summary.modules.len(),
+            skipped = summary.skipped,
+            failed = summary.failed,
+            bytes = summary.bytes,
+            elapsed = ?started.elapsed(),
+            "handed off compiled modules",
+        );
+
+        let report = self.request_from_orchestrator(worker_message::Message::HandoffSummary(summary));
+        if tokio::time::timeout_at(deadline, report).await.is_err() {
+            tracing::warn!("orchestrator didn't acknowledge the handoff summary in time");
+        }
+    }
+
+    /// Sends each module to a peer in turn, recording those accepted in the summary.
+    async fn push_modules(&self, modules: &[(Hash, Component)], max_bytes: usize, summary: &mut HandoffSummary) {
+        let peers = match self.request_from_orchestrator(worker_message::Message::PeerListRequest(PeerListRequest {})).await {
+            Some(orchestrator_message::Message::PeerList(peer_list)) => peer_list.peers,
+            _ => return,
+        };
+        if peers.is_empty() {
+            tracing::info!("no peers to hand compiled modules to");
+            return;
+        }
+        let token_key = EncodingKey::from_secret(&handoff_key(self.orchestrators.jwt_secret(self.orchestrators.primary())));
+        let mut clients: Vec<Option<ExecutorClient<Channel>>> = vec![None; peers.len()];
+
+        for (i, (wasm_hash, component)) in modules.iter().enumerate() {
+            let artifact = match component.serialize() {
+                Ok(artifact) => artifact,
+                Err(e) => {
+                    tracing::warn!(wasm_hash = %wasm_hash, error = %e, "failed to serialize compiled module for handoff");
+                    summary.failed += 1;
+                    continue;
+                },
+            };
+            if summary.bytes as usize + artifact.len() > max_bytes {
+                tracing::debug!(wasm_hash = %wasm_hash, bytes = artifact.len(), "compiled module doesn't fit in the handoff budget, skipping");
+                continue;
+            }
+            let artifact_bytes = artifact.len() as u64;
+
+            let peer = i % peers.len();
+            match import_on_peer(&peers[peer], &mut clients[peer], &token_key, *wasm_hash, artifact).await {
+                Ok(cached) => {
+                    tracing::debug!(wasm_hash = %wasm_hash, peer = %peers[peer].address, cached, "handed off compiled module");
+                    summary.bytes += artifact_bytes;
+                    summary.modules.push(HandedOffModule { wasm_hash: wasm_hash.as_bytes().to_vec(), peer_address: peers[peer].address.clone() });
+                },
+                Err(reason) => {
+                    tracing::warn!(wasm_hash = %wasm_hash, peer = %peers[peer].address, reason = %reason, "failed to hand off compiled module");
+                    summary.failed += 1;
+                },
+            }
+        }
+    }
+
+    /// Sends a message to the primary Orchestrator and waits for its reply, or returns None if
+    /// the message can't be sent. Only used during handoff, when nothing else awaits a reply.
+    async fn request_from_orchestrator(&self, message: worker_message::Message) -> Option<orchestrator_message::Message> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        *self.handoff_reply.lock().unwrap_or_else(|e| e.into_inner()) = Some(reply_tx);
+        self.orchestrators.tx(self.orchestrators.primary()).send(WorkerMessage { message: Some(message) }).await.ok()?;
+        reply_rx.await.ok()
+    }
+
+    /// Passes a reply from the Orchestrator to the handoff waiting on it.
+    pub(crate) fn handle_handoff_reply(&self, message: orchestrator_message::Message) {
+        let Some(reply_tx) = self.handoff_reply.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+            tracing::warn!("ignoring a handoff reply from the orchestrator, as no handoff asked for one");
+            return;
+        };
+        _ = reply_tx.send(message);
+    }
+}
+
+/// Sends one compiled module to a peer, connecting to it first if needed. Returns whether the
+/// peer already had the module.
+async fn import_on_peer(peer: &Peer, client: &mut Option<ExecutorClient<Channel>>, token_key: &EncodingKey, wasm_hash: Hash, artifact: Vec<u8>) -> Result<bool, String> {
+    let client = match client {
+        Some(client) => client,
+        None => {
+            let channel = Channel::from_shared(format!("http://{}", peer.address))
+                .map_err(|e| format!("malformed peer address: {e}"))?
+                .connect()
+                .await
+                .map_err(|e| format!("failed to connect: {e}"))?;
+            client.insert(ExecutorClient::new(channel))
+        },
+    };
+
+    let import_id = JobId::random();
+    let mut request = Request::new(ImportModuleRequest {
+        import_id: import_id.to_bytes(),
+        wasm_hash: wasm_hash.as_bytes().to_vec(),
+        artifact,
+        protocol_version: PROTOCOL_VERSION,
+    });
+    let jwt_token = jsonwebtoken::encode(&Header::default(), &JobClaims::new(import_id), token_key)
+        .map_err(|e| format!("failed to issue jwt: {e}"))?
+        .parse()
+        .map_err(|e| format!("malformed jwt: {e}"))?;
+    request.metadata_mut().insert("authorization", jwt_token);
+
+    let response = client.import_module(request).await
+        .map_err(|e| e.message().to_string())?
+        .into_inner();
+    Ok(response.cached)
+}
@@ -0,0 +1,30 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+
+use shared::Heartbeat;
+use shared::events::epoch_ms;
+
+use crate::worker::Worker;
+
+impl Worker {
+    /// Starts sending heartbeats to the Orchestrators in the background, reporting every job
+    /// the Worker started and is running, and when and how often it sends them. Each Orchestrator only hears about the jobs it
+    /// dispatched itself, so this is how it learns what the others keep the Worker busy with.
+    pub(crate) fn start_heartbeats(&self, interval: Duration) {
+        let worker = self.clone();
+        let interval_ms = interval.as_millis() as u64;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                let heartbeat = Heartbeat {
+                    jobs_started_total: worker.jobs_started.load(Ordering::Relaxed),
+                    running_jobs: worker.active_jobs.len() as u32,
+                    sent_at_ms: epoch_ms(SystemTime::now()),
+                    interval_ms,
+                };
+                worker.orchestrators.heartbeat(heartbeat).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+use std::process::{ExitStatus, Stdio};
+use std::str::FromStr;
+use std::time::Duration;
+
+use prost::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use wasmtime::component::Component;
+
+use crate::errors::ExecutorError;
+use crate::executor::{RunResult, run_command};
+use crate::output_pipe::OutputSpill;
+use crate::wasm_limits::WasmLimits;
+use crate::worker::{Worker, new_engine, new_linker};
+
+/// First argument that makes the worker binary run a single job as an isolated helper process
+/// instead of serving, see run_job_helper.
+pub const RUN_JOB_COMMAND: &str = "run-job";
+
+/// Where a Worker runs its jobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isolation {
+    /// In the Worker's own process, isolated only by wasmtime.
+    #[default]
+    None,
+    /// Each in a helper process, so that a job that crashes the runtime can't take down the
+    /// Worker. The helper is the Worker's own executable, run with RUN_JOB_COMMAND.
+    Process,
+}
+
+impl FromStr for Isolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Isolation::None),
+            "process" => Ok(Isolation::Process),
+            other => Err(format!("unknown isolation mode '{other}', expected none or process")),
+        }
+    }
+}
+
+impl fmt::Display for Isolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Isolation::None => "none",
+            Isolation::Process => "process",
+        })
+    }
+}
+
+/// OS limits on each helper process of a Worker with Isolation::Process, on top of the
+/// WasmLimits its job runs with. A job whose process breaks one fails like a trapping job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProcessLimits {
+    /// Kills the process once it has run this long, whether or not its job has a timeout.
+    pub wall_clock: Option<Duration>,
+    /// Seconds of CPU time the process may use, as RLIMIT_CPU. Unix only.
+    pub cpu_secs: Option<u64>,
+    /// Bytes of writable memory the process may map, as RLIMIT_DATA, which covers the job's
+    /// linear memories. Unix only.
+    pub memory_bytes: Option<u64>,
+}
+
+impl ProcessLimits {
+    /// Sets the limits' rlimits on the calling process.
+    #[cfg(unix)]
+    fn apply(&self) -> std::io::Result<()> {
+        let limits = [(libc::RLIMIT_CPU, self.cpu_secs), (libc::RLIMIT_DATA, self.memory_bytes)];
+        for (resource, limit) in limits {
+            let Some(limit) = limit else { continue };
+            // The soft limit on CPU time raises SIGXCPU, the hard one a second later SIGKILL
+            let hard = if resource == libc::RLIMIT_CPU { limit.saturating_add(1) } else { limit };
+            let rlimit = libc::rlimit { rlim_cur: limit as libc::rlim_t, rlim_max: hard as libc::rlim_t };
+            // SAFETY: setrlimit only reads the rlimit passed to it
+            if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Why a helper process that didn't exit cleanly stopped, if one of the limits stopped it.
+    fn exceeded(&self, status: ExitStatus) -> Option<String> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let (Some(cpu_secs), Some(libc::SIGXCPU)) = (self.cpu_secs, status.signal()) {
+                return Some(format!("used more than the worker's {cpu_secs}s limit on CPU time"));
+            }
+        }
+        _ = status;
+        None
+    }
+}
+
+/// A job handed to a helper process on its stdin.
+#[derive(Clone, PartialEq, Message)]
+struct IsolatedJob {
+    /// The compiled module, from Component::serialize.
+    #[prost(bytes = "vec", tag = "1")]
+    artifact: Vec<u8>,
+    #[prost(string, repeated, tag = "2")]
+    args: Vec<String>,
+    #[prost(bool, tag = "3")]
+    network_access_allowed: bool,
+    /// The Worker's WasmLimits, which the helper's engine and store are set up with.
+    #[prost(uint64, tag = "4")]
+    stack_bytes: u64,
+    #[prost(uint64, tag = "5")]
+    host_stack_bytes: u64,
+    #[prost(uint64, tag = "6")]
+    max_instances: u64,
+    #[prost(uint64, tag = "7")]
+    max_table_elements: u64,
+    #[prost(bytes = "vec", tag = "8")]
+    stdin: Vec<u8>,
+    #[prost(uint64, tag = "9")]
+    max_output_bytes: u64,
+    /// Where the job's stdout goes once it outgrows the spill's threshold, see OutputPipe.
+    #[prost(message, optional, tag = "10")]
+    output_spill: Option<OutputSpill>,
+}
+
+/// What a helper process writes to its stdout once its job has run.
+#[derive(Clone, PartialEq, Message)]
+struct IsolatedJobResult {
+    #[prost(message, optional, tag = "1")]
+    result: Option<RunResult>,
+    /// Set instead of result if the module couldn't be instantiated.
+    #[prost(string, optional, tag = "2")]
+    instantiation_error: Option<String>,
+}
+
+impl Worker {
+    /// Runs a compiled job in a helper process and waits for its result. Dropping the future
+    /// kills the helper, which is how running jobs are cancelled.
+    pub(crate) async fn run_isolated(&self, component: &Component, args: Vec<String>, stdin: Vec<u8>, network_access_allowed: bool, output_spill: Option<OutputSpill>) -> Result<RunResult, ExecutorError> {
+        let task_failed = |e: std::io::Error| ExecutorError::ExecutionTaskFailed(format!("job process: {e}"));
+        let job = IsolatedJob {
+            artifact: component.serialize().map_err(|e| ExecutorError::ExecutionTaskFailed(format!("failed to serialize module: {e}")))?,
+            args,
+            network_access_allowed,
+            stack_bytes: self.wasm_limits.stack_bytes as u64,
+            host_stack_bytes: self.wasm_limits.host_stack_bytes as u64,
+            max_instances: self.wasm_limits.max_instances as u64,
+            max_table_elements: self.wasm_limits.max_table_elements as u64,
+            stdin,
+            max_output_bytes: self.wasm_limits.max_output_bytes as u64,
+            output_spill,
+        };
+
+        let exe = std::env::current_exe().map_err(task_failed)?;
+        let mut command = Command::new(exe);
+        command
+            .arg(RUN_JOB_COMMAND)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true);
+        #[cfg(unix)]
+        {
+            let process_limits = self.process_limits;
+            // SAFETY: the closure only calls setrlimit, which is async-signal-safe
+            unsafe { command.pre_exec(move || process_limits.apply()) };
+        }
+        let mut child = command.spawn().map_err(task_failed)?;
+        tracing::debug!(pid = ?child.id(), "started job process");
+
+        // The helper reads all of its input before it writes anything, so this can't deadlock
+        let mut stdin = child.stdin.take().ok_or_else(|| task_failed(std::io::ErrorKind::BrokenPipe.into()))?;
+        stdin.write_all(&job.encode_to_vec()).await.map_err(task_failed)?;
+        drop(stdin);
+
+        // Dropping the child on timeout kills it
+        let output = match self.process_limits.wall_clock {
+            Some(wall_clock) => tokio::time::timeout(wall_clock, child.wait_with_output()).await
+                .map_err(|_| ExecutorError::ProcessLimitExceeded(format!("ran past the worker's {wall_clock:?} limit on job processes")))?,
+            None => child.wait_with_output().await,
+        }.map_err(task_failed)?;
+        if !output.status.success() {
+            if let Some(reason) = self.process_limits.exceeded(output.status) {
+                return Err(ExecutorError::ProcessLimitExceeded(reason));
+            }
+            return Err(ExecutorError::ExecutionTaskFailed(format!("job process exited with {}", output.status)));
+        }
+        let result = IsolatedJobResult::decode(output.stdout.as_slice())
+            .map_err(|e| ExecutorError::ExecutionTaskFailed(format!("job process sent a malformed result: {e}")))?;
+        match (result.result, result.instantiation_error) {
+            (_, Some(e)) => Err(ExecutorError::InstantiationFailed(wasmtime::Error::msg(e))),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(ExecutorError::ExecutionTaskFailed("job process sent an empty result".to_string())),
+        }
+    }
+}
+
+/// Runs the job given on stdin and writes its result to stdout, as the helper process for a
+/// Worker with process isolation. Programs embedding a Worker with Isolation::Process must call
+/// this when started with RUN_JOB_COMMAND as their first argument.
+pub async fn run_job_helper() -> ! {
+    let mut input = Vec::new();
+    if let Err(e) = tokio::io::stdin().read_to_end(&mut input).await {
+        eprintln!("failed to read the job: {e}");
+        std::process::exit(2);
+    }
+    let job = IsolatedJob::decode(input.as_slice()).unwrap_or_else(|e| {
+        eprintln!("received a malformed job: {e}");
+        std::process::exit(2);
+    });
+
+    let wasm_limits = WasmLimits {
+        stack_bytes: job.stack_bytes as usize,
+        host_stack_bytes: job.host_stack_bytes as usize,
+        max_instances: job.max_instances as usize,
+        max_table_elements: job.max_table_elements as usize,
+        max_output_bytes: job.max_output_bytes as usize,
+    };
+    let engine = new_engine(&wasm_limits);
+    let linker = new_linker(&engine);
+    // SAFETY: the artifact was serialized by the parent Worker, which is this same executable,
+    // from a module it compiled with the same engine configuration
+    let component = unsafe { Component::deserialize(&engine, &job.artifact) }.unwrap_or_else(|e| {
+        eprintln!("failed to load the compiled module: {e}");
+        std::process::exit(2);
+    });
+
+    let result = match run_command(&engine, &linker, &component, &job.args, &job.stdin, job.network_access_allowed, &wasm_limits, job.output_spill).await {
+        Ok(result) => IsolatedJobResult { result: Some(result), instantiation_error: None },
+        Err(e) => IsolatedJobResult { result: None, instantiation_error: Some(e.to_string()) },
+    };
+    let mut stdout = tokio::io::stdout();
+    if let Err(e) = stdout.write_all(&result.encode_to_vec()).await.and(stdout.flush().await) {
+        eprintln!("failed to write the job's result: {e}");
+        std::process::exit(2);
+    }
+    std::process::exit(0);
+}
@@ -1,59 +1,77 @@
 use std::sync::Arc;
 
 use dashmap::DashMap;
-use shared::{CreditUpdate, JobState, WorkerMessage, worker_message};
-use tokio::sync::mpsc::Sender;
-use tokio_util::sync::CancellationToken;
-use uuid::Uuid;
+use shared::{JobState, JobUsage};
+use shared::JobId;
 
+use crate::cancellation::{JobCancellation, StopReason};
+use crate::orchestrators::{OrchestratorId, Orchestrators};
 use crate::worker::Worker;
 
-/// RAII guard that returns a credit via an update to the Orchestrator when dropped
-/// and drops resources associated to this job
+/// RAII guard that returns a credit via an update to the Orchestrators when dropped
+/// and drops resources associated to this job. A job that was stopped reports the outcome
+/// its StopReason calls for.
 pub struct JobGuard {
-    tx: Sender<WorkerMessage>,
-    cancellation_tokens: Arc<DashMap<Uuid, CancellationToken>>,
-    job_id: Uuid,
-    state: Option<JobState>
+    orchestrators: Arc<Orchestrators>,
+    /// The Orchestrator that dispatched the job, which its final state is reported to.
+    dispatcher: OrchestratorId,
+    active_jobs: Arc<DashMap<JobId, JobCancellation>>,
+    job_id: JobId,
+    state: JobState,
+    usage: Option<JobUsage>,
+    exit_code: Option<i32>,
+    holds_credit: bool,
 }
 
 impl JobGuard {
     /// Creates a new JobGuard bound to the given Worker.
-    pub fn new(
-        tx: Sender<WorkerMessage>, 
-        cancellation_tokens: Arc<DashMap<Uuid, CancellationToken>>,
-        job_id: Uuid
+    pub(crate) fn new(
+        orchestrators: Arc<Orchestrators>,
+        dispatcher: OrchestratorId,
+        active_jobs: Arc<DashMap<JobId, JobCancellation>>,
+        job_id: JobId
     ) -> Self {
-        Self { tx, cancellation_tokens, job_id, state: Some(JobState::Failed) }
+        Self { orchestrators, dispatcher, active_jobs, job_id, state: JobState::Failed, usage: None, exit_code: None, holds_credit: true }
     }
 
     pub fn set_completed(&mut self) {
-        self.state = Some(JobState::Completed)
+        self.state = JobState::Completed
     }
-    pub fn set_cancelled(&mut self) {
-        self.state = None
+    /// For jobs that share another job's run, which holds the credit and reports the usage.
+    pub fn set_no_credit(&mut self) {
+        self.holds_credit = false
+    }
+    /// Records what the job used, reported to the Orchestrator with its final state.
+    pub fn set_usage(&mut self, usage: JobUsage) {
+        self.usage = Some(usage)
+    }
+    /// Records the code the job's guest exited with, reported along with its final state.
+    pub fn set_exit_code(&mut self, exit_code: Option<i32>) {
+        self.exit_code = exit_code
     }
 }
 
 impl Drop for JobGuard {
-    /// Sends a credit update to the Orchestrator, returning one credit.
+    /// Sends a credit update to the Orchestrators, returning one credit.
     /// Also drops Worker resources associated with this job
     fn drop(&mut self) {
-        if self.cancellation_tokens.remove(&self.job_id).is_none() {
+        let Some((_, cancellation)) = self.active_jobs.remove(&self.job_id) else {
             tracing::error!(job_id = %self.job_id, "ERROR: missing cancellation token in job guard, this should never happen");
             std::process::exit(1);
+        };
+        if self.holds_credit {
+            self.orchestrators.return_credit();
         }
-        let tx = self.tx.clone();
-        tokio::spawn(async move {
-            if tx.send(WorkerMessage {
-                message: Some(worker_message::Message::CreditUpdate(CreditUpdate { delta: 1 }))
-            }).await.is_err() {
-                tracing::error!("lost connection to the orchestrator, shutting down");
-                std::process::exit(1);
-            }
-        });
-        if let Some(job_state) = self.state {
-            Worker::send_job_update_to_orchestrator(self.tx.clone(), self.job_id, job_state);
+        let state = match (self.state, cancellation.reason()) {
+            (JobState::Completed, _) => Some(JobState::Completed),
+            // The cancel request already reported the job cancelled
+            (_, Some(StopReason::Cancelled)) => None,
+            // Nobody is left to see the job fail
+            (_, Some(StopReason::ClientDisconnected)) => Some(JobState::Cancelled),
+            (state, _) => Some(state),
+        };
+        if let Some(job_state) = state {
+            Worker::send_job_finished_to_orchestrator(self.orchestrators.tx(self.dispatcher), self.job_id, job_state, self.usage, self.exit_code);
         }
     }
 }
\ No newline at end of file
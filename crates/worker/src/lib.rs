@@ -0,0 +1,48 @@
+//! The mini-lambda Worker: runs wasm jobs assigned to it by an Orchestrator.
+//!
+//! The `worker` binary is a thin wrapper over this library. To run a Worker inside another
+//! program:
+//!
+//! ```no_run
+//! use worker::{Worker, WorkerConfig};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let handle = Worker::start(WorkerConfig::new("127.0.0.1", 4)).await?;
+//! println!("worker registered as {}", handle.address());
+//! handle.run_until(async { tokio::signal::ctrl_c().await.ok(); }).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod worker;
+mod executor;
+mod errors;
+mod orchestrator_client;
+mod orchestrators;
+mod job_guard;
+mod module_store;
+mod isolation;
+mod reservations;
+mod server;
+mod wasm_limits;
+mod handoff;
+mod relay;
+mod coalescing;
+mod module_info;
+mod delta;
+mod load;
+mod cancellation;
+mod digests;
+mod heartbeat;
+mod warm_pool;
+mod output_store;
+mod output_pipe;
+
+pub use handoff::HandoffConfig;
+pub use isolation::{Isolation, ProcessLimits, RUN_JOB_COMMAND, run_job_helper};
+pub use load::{LoadAware, LoadCurve};
+pub use relay::ConnectMode;
+pub use server::{WorkerConfig, WorkerHandle};
+pub use warm_pool::WarmPoolSpec;
+pub use wasm_limits::WasmLimits;
+pub use worker::Worker;
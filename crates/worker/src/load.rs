@@ -0,0 +1,219 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+use shared::{CreditUpdate, HostLoad};
+
+use crate::worker::Worker;
+
+/// Load-aware credits, where a Worker sharing its host advertises fewer credits than its
+/// configured max while the host is busy with work other than its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadAware {
+    pub curve: LoadCurve,
+    /// How often host load is sampled and the advertised credits adjusted.
+    pub sample_interval: Duration,
+}
+
+/// Maps host load, from 0 (idle) to 1 (every CPU busy), to the fraction of its max credits a
+/// Worker advertises, by linear interpolation between breakpoints. Loads before the first
+/// breakpoint or after the last get that breakpoint's fraction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadCurve {
+    /// (load, fraction) pairs, by increasing load.
+    breakpoints: Vec<(f32, f32)>,
+}
+
+impl Default for LoadCurve {
+    /// Full credits up to half load, scaling down to none at full load.
+    fn default() -> Self {
+        Self { breakpoints: vec![(0.5, 1.0), (1.0, 0.0)] }
+    }
+}
+
+impl LoadCurve {
+    /// The fraction of its max credits a Worker advertises at the given load.
+    pub fn fraction(&self, load: f32) -> f32 {
+        let (first, last) = (self.breakpoints[0], self.breakpoints[self.breakpoints.len() - 1]);
+        if load <= first.0 {
+            return first.1;
+        }
+        if load >= last.0 {
+            return last.1;
+        }
+        for pair in self.breakpoints.windows(2) {
+            let ((load0, fraction0), (load1, fraction1)) = (pair[0], pair[1]);
+            if load <= load1 {
+                return fraction0 + (fraction1 - fraction0) * (load - load0) / (load1 - load0);
+            }
+        }
+        last.1
+    }
+}
+
+impl FromStr for LoadCurve {
+    type Err = String;
+
+    /// Parses comma-separated load:fraction breakpoints by increasing load, such as
+    /// "0.5:1,1:0". Both are between 0 and 1.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut breakpoints: Vec<(f32, f32)> = Vec::new();
+        for breakpoint in s.split(',') {
+            let (load, fraction) = breakpoint.trim().split_once(':')
+                .ok_or_else(|| format!("'{breakpoint}' is not a load:fraction breakpoint"))?;
+            let load: f32 = load.trim().parse().map_err(|_| format!("'{load}' is not a load"))?;
+            let fraction: f32 = fraction.trim().parse().map_err(|_| format!("'{fraction}' is not a fraction"))?;
+            if !(0.0..=1.0).contains(&load) || !(0.0..=1.0).contains(&fraction) {
+                return Err(format!("breakpoint '{breakpoint}' must have a load and fraction between 0 and 1"));
+            }
+            if breakpoints.last().is_some_and(|(previous, _)| load <= *previous) {
+                return Err("breakpoints must be in order of increasing load".to_string());
+            }
+            breakpoints.push((load, fraction));
+        }
+        Ok(Self { breakpoints })
+    }
+}
+
+impl fmt::Display for LoadCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let breakpoints: Vec<String> = self.breakpoints.iter().map(|(load, fraction)| format!("{load}:{fraction}")).collect();
+        write!(f, "{}", breakpoints.join(","))
+    }
+}
+
+/// The credits a Worker with max_credits advertises when the curve gives fraction, never fewer
+/// than its running jobs hold. Rounded, so that a little noise in an idle host's load doesn't
+/// cost a credit.
+pub(crate) fn advertised_credits(max_credits: u32, running: u32, fraction: f32) -> u32 {
+    let scaled = (max_credits as f32 * fraction.clamp(0.0, 1.0)).round() as u32;
+    scaled.max(running.min(max_credits))
+}
+
+/// Samples how busy the host is with work other than this Worker's.
+struct LoadSampler {
+    system: System,
+    pid: Option<Pid>,
+}
+
+impl LoadSampler {
+    fn new() -> Self {
+        Self { system: System::new(), pid: sysinfo::get_current_pid().ok() }
+    }
+
+    /// The host's CPU utilization since the last sample, from 0 to 1, leaving out this process
+    /// and its children, which include isolated jobs. The first sample covers no time, so is 0.
+    fn sample(&mut self) -> f32 {
+        self.system.refresh_cpu_usage();
+        self.system.refresh_processes_specifics(ProcessesToUpdate::All, true, ProcessRefreshKind::nothing().with_cpu());
+        let cpus = self.system.cpus().len().max(1) as f32;
+        let own: f32 = self.system.processes().values()
+            .filter(|process| self.pid.is_some_and(|pid| process.pid() == pid || process.parent() == Some(pid)))
+            .map(|process| process.cpu_usage())
+            .sum();
+        ((self.system.global_cpu_usage() - own / cpus) / 100.0).clamp(0.0, 1.0)
+    }
+}
+
+impl Worker {
+    /// Starts sampling host load in the background, withholding credits from the Orchestrators
+    /// as the curve scales them down and returning them as it scales them back up. Every sample
+    /// is reported to the Orchestrators, along with the credits advertised.
+    pub(crate) fn start_load_sampler(&self, max_credits: u32, load_aware: LoadAware) {
+        let worker = self.clone();
+        tokio::spawn(async move {
+            let mut sampler = LoadSampler::new();
+            let mut withheld = 0u32;
+            let mut interval = tokio::time::interval(load_aware.sample_interval);
+            loop {
+                interval.tick().await;
+                let (returned, load) = tokio::task::spawn_blocking(move || {
+                    let load = sampler.sample();
+                    (sampler, load)
+                })
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::error!(error = %e, "ERROR: load sampling task panicked, this should never happen");
+                        std::process::exit(1);
+                    });
+                sampler = returned;
+
+                let running = worker.active_jobs.len() as u32;
+                let advertised = advertised_credits(max_credits, running, load_aware.curve.fraction(load));
+                let target = max_credits - advertised;
+                let credit_update = CreditUpdate {
+                    delta: withheld.saturating_sub(target),
+                    taken: target.saturating_sub(withheld),
+                    host_load: Some(HostLoad { load, advertised_credits: advertised }),
+                };
+                if withheld != target {
+                    tracing::debug!(load, advertised, "host load changed the advertised credits");
+                }
+                withheld = target;
+                worker.orchestrators.report_load(credit_update).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_fraction(curve: &LoadCurve, load: f32, expected: f32) {
+        let fraction = curve.fraction(load);
+        assert!((fraction - expected).abs() < 1e-6, "fraction at load {load} was {fraction}, expected {expected}");
+    }
+
+    #[test]
+    fn the_default_curve_scales_down_past_half_load() {
+        let curve = LoadCurve::default();
+        for (load, expected) in [(0.0, 1.0), (0.5, 1.0), (0.75, 0.5), (0.9, 0.2), (1.0, 0.0)] {
+            assert_fraction(&curve, load, expected);
+        }
+    }
+
+    #[test]
+    fn the_curve_is_interpolated_between_its_breakpoints() {
+        let curve: LoadCurve = "0.2:1,0.6:0.5,0.8:0.25".parse().unwrap();
+        for (load, expected) in [(0.0, 1.0), (0.2, 1.0), (0.4, 0.75), (0.6, 0.5), (0.7, 0.375), (0.8, 0.25), (1.0, 0.25)] {
+            assert_fraction(&curve, load, expected);
+        }
+    }
+
+    #[test]
+    fn a_single_breakpoint_is_flat() {
+        let curve: LoadCurve = "0.5:0.4".parse().unwrap();
+        for load in [0.0, 0.5, 1.0] {
+            assert_fraction(&curve, load, 0.4);
+        }
+    }
+
+    #[test]
+    fn curves_round_trip_through_their_flag() {
+        let curve: LoadCurve = " 0.25:1, 0.75:0.5 ,1:0".parse().unwrap();
+        assert_eq!(curve.to_string(), "0.25:1,0.75:0.5,1:0");
+        assert_eq!(LoadCurve::default().to_string().parse::<LoadCurve>(), Ok(LoadCurve::default()));
+    }
+
+    #[test]
+    fn bad_curves_are_refused() {
+        for curve in ["", "0.5", "a:1", "0.5:b", "1.5:1", "0.5:-1", "0.5:1,0.5:0", "0.8:1,0.2:0"] {
+            assert!(curve.parse::<LoadCurve>().is_err(), "'{curve}' was accepted");
+        }
+    }
+
+    #[test]
+    fn advertised_credits_are_rounded_and_cover_running_jobs() {
+        assert_eq!(advertised_credits(8, 0, 1.0), 8);
+        assert_eq!(advertised_credits(8, 0, 0.5), 4);
+        assert_eq!(advertised_credits(8, 0, 0.96), 8);
+        assert_eq!(advertised_credits(8, 0, 0.0), 0);
+        assert_eq!(advertised_credits(8, 5, 0.25), 5);
+        // Never more than the max, whatever is running or the curve gives
+        assert_eq!(advertised_credits(8, 10, 0.0), 8);
+        assert_eq!(advertised_credits(8, 0, 1.5), 8);
+    }
+}
@@ -1,27 +1,97 @@
-mod worker;
-mod executor;
-mod errors;
-mod orchestrator_client;
-mod job_guard;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::Parser;
-use tokio::net::TcpListener;
-use tokio_stream::wrappers::TcpListenerStream;
-use tonic::transport::Server;
 
-use shared::executor_server::ExecutorServer;
-
-use crate::worker::Worker;
+use shared::limits::JobLimits;
+use shared::signing::TrustedKeys;
+use worker::{ConnectMode, HandoffConfig, Isolation, LoadAware, LoadCurve, ProcessLimits, RUN_JOB_COMMAND, WarmPoolSpec, WasmLimits, Worker, WorkerConfig};
 
 #[derive(Parser, Debug)]
 #[command(about = "Run a Worker server")]
 struct Args {
     bind_host: String,
     worker_credits: u32,
-    #[arg(long, default_value = "http://127.0.0.1:50051")]
-    orchestrator: String,
+    #[arg(long, default_value_t = 0, help = "Port to listen on. By default a free port is picked")]
+    port: u16,
+    #[arg(long, default_value = "http://127.0.0.1:50051", help = "Orchestrator to register with. Repeat to register with several, for redundancy")]
+    orchestrator: Vec<String>,
     #[arg(long)]
     password: Option<String>,
+    #[arg(long, default_value_t = 32, help = "Largest wasm module accepted, in MiB")]
+    max_wasm_mb: usize,
+    #[arg(long, default_value_t = 256, help = "Most arguments accepted for a job")]
+    max_args: usize,
+    #[arg(long, default_value_t = 64, help = "Largest combined size of a job's arguments, in KiB")]
+    max_args_kb: usize,
+    #[arg(long, default_value_t = 512, help = "Most stack a job's wasm may use before it fails with a stack overflow, in KiB")]
+    wasm_stack_kb: usize,
+    #[arg(long, default_value_t = 1536, help = "Stack kept for host calls on top of --wasm-stack-kb, in KiB. Each job runs on a stack of both combined")]
+    host_stack_kb: usize,
+    #[arg(long, default_value_t = 1000, help = "Most wasm instances a job may create, and separately tables and memories")]
+    max_instances: usize,
+    #[arg(long, default_value_t = 1_000_000, help = "Most entries any one of a job's tables may hold")]
+    max_table_elements: usize,
+    #[arg(long, default_value_t = 10, help = "Most a job may write to its stdout, and separately to its stderr, in MiB")]
+    max_output_mb: usize,
+    #[arg(long, help = "Only run jobs signed by one of the Ed25519 public keys (.pem files) in this directory")]
+    trusted_keys: Option<PathBuf>,
+    #[arg(long, help = "Only receive jobs from this namespace, dedicating the worker to it")]
+    namespace: Option<String>,
+    #[arg(long, help = "Only run jobs the Orchestrator reserved a credit for, returning credits whose job never arrives")]
+    require_reservation: bool,
+    #[arg(long, default_value_t = Isolation::None, help = "Where jobs run: none (in the worker process) or process (each in its own helper process)")]
+    isolate: Isolation,
+    #[arg(long, help = "With --isolate process, kill a job's process once it has run this many seconds")]
+    job_process_max_secs: Option<u64>,
+    #[arg(long, help = "With --isolate process, kill a job's process once it has used this many seconds of CPU time (unix only)")]
+    job_process_cpu_secs: Option<u64>,
+    #[arg(long, help = "With --isolate process, most MiB of memory a job's process may allocate, including the job's wasm memory (unix only)")]
+    job_process_memory_mb: Option<u64>,
+    #[arg(long, help = "On Ctrl-C, once running jobs finish, hand compiled modules to the least loaded peer workers")]
+    handoff: bool,
+    #[arg(long, default_value_t = 256 * 1024 * 1024, help = "Most bytes of compiled modules sent to peers with --handoff")]
+    handoff_max_bytes: usize,
+    #[arg(long, default_value_t = 10, help = "Longest --handoff may delay shutdown, in seconds")]
+    handoff_deadline_secs: u64,
+    #[arg(long, help = "On Ctrl-C, abort jobs still running after this many seconds, so their clients retry them on another worker. By default shutdown waits for them")]
+    abort_jobs_after_secs: Option<u64>,
+    #[arg(long, default_value_t = ConnectMode::Listen, help = "How clients reach the worker: listen (they connect to it) or relay (the orchestrator relays their calls, for workers behind NAT)")]
+    connect_mode: ConnectMode,
+    #[arg(long, conflicts_with = "connect_mode", help = "Address (host:port) of a TLS proxy in front of the worker, which clients connect to over https instead. The orchestrator and peer workers still connect to the worker directly")]
+    tls_address: Option<String>,
+    #[arg(long, default_value_t = 256, help = "Most wasm kept for clients to upload new versions of modules as patches against, in MiB. 0 turns delta uploads off")]
+    delta_base_mb: usize,
+    #[arg(long, help = "Keep instances of a module ready for its jobs, so they skip instantiating it, as <hash>=<instances> with the module's hex blake3 hash. Repeat to pool several modules")]
+    warm_pool: Vec<WarmPoolSpec>,
+    #[arg(long, default_value_t = 256, help = "Most memory the instances kept by --warm-pool may hold together, in MiB")]
+    warm_pool_mb: usize,
+    #[arg(long, help = "Also index compiled modules by their SHA-256 digest, so that jobs and object store modules may name them by it. Costs hashing each module twice")]
+    dual_hash: bool,
+    #[arg(long, help = "Advertise fewer credits while the host is busy with work other than the worker's, following --load-curve")]
+    load_aware: bool,
+    #[arg(long, default_value_t = LoadCurve::default(), help = "With --load-aware, the fraction of credits advertised at each host load, as load:fraction breakpoints between 0 and 1, interpolated linearly")]
+    load_curve: LoadCurve,
+    #[arg(long, default_value_t = 5, help = "With --load-aware, how often host load is sampled, in seconds")]
+    load_sample_secs: u64,
+    #[arg(long, default_value_t = 5, help = "How often to tell the orchestrators how many jobs the worker has started and is running, in seconds")]
+    heartbeat_secs: u64,
+    #[arg(long, help = "Serve gRPC reflection describing the worker's API, for tools like grpcurl")]
+    reflection: bool,
+    #[arg(long, default_value_t = 4096, help = "Write job outputs larger than this to --output-dir for clients to fetch, rather than return them in the response, in KiB. 0 always returns them")]
+    offload_output_kb: usize,
+    #[arg(long, default_value_t = 1024, help = "Most a job whose output is offloaded may write to its stdout, in MiB. Only the first --offload-output-kb of it is held in memory")]
+    max_offloaded_output_mb: u64,
+    #[arg(long, help = "Where to write offloaded job outputs, in a directory for each worker. By default a mini-lambda-outputs directory in the system's temp directory")]
+    output_dir: Option<PathBuf>,
+    #[arg(long, default_value_t = 3600, help = "How long offloaded job outputs are kept for clients to fetch, in seconds")]
+    output_retention_secs: u64,
+    #[cfg(feature = "object-store")]
+    #[arg(long, help = "S3-compatible endpoint to fetch job modules from, e.g. http://127.0.0.1:9000. Credentials are read from AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY")]
+    object_store_endpoint: Option<String>,
+    #[cfg(feature = "fault-injection")]
+    #[arg(long, help = "Inject faults for resilience testing, e.g. 'submit:error_rate=0.2,latency_ms=500;updates:drop_rate=0.1'")]
+    fault_spec: Option<String>,
     #[arg(long, help = "Enable debug logging")]
     verbose: bool,
 }
@@ -39,30 +109,105 @@ fn init_tracing(verbose: bool) {
 /// Main entry point for the Worker server binary.
 #[tokio::main]
 pub async fn main() {
+    // Isolated jobs run in a copy of this binary
+    if std::env::args().nth(1).as_deref() == Some(RUN_JOB_COMMAND) {
+        worker::run_job_helper().await;
+    }
+
     let args = Args::parse();
     init_tracing(args.verbose);
 
-    let orchestrator_endpoint = &args.orchestrator;
-    let bind_host = &args.bind_host;
-    let worker_credits = args.worker_credits;
-    let password = args.password;
+    let limits = JobLimits {
+        max_wasm_bytes: args.max_wasm_mb * 1024 * 1024,
+        max_args: args.max_args,
+        max_args_bytes: args.max_args_kb * 1024,
+    };
+    if args.wasm_stack_kb == 0 {
+        tracing::error!("--wasm-stack-kb must be at least 1");
+        std::process::exit(1);
+    }
+    let wasm_limits = WasmLimits {
+        stack_bytes: args.wasm_stack_kb * 1024,
+        host_stack_bytes: args.host_stack_kb * 1024,
+        max_instances: args.max_instances,
+        max_table_elements: args.max_table_elements,
+        max_output_bytes: args.max_output_mb * 1024 * 1024,
+    };
 
-    let listener = TcpListener::bind(format!("{}:0", bind_host)).await
-        .unwrap_or_else(|e| panic!("Failed to bind to host {}: {}", bind_host, e));
-    let addr = listener.local_addr()
-        .unwrap_or_else(|e| panic!("Failed to fetch port Worker is bound to: {}", e));
+    #[cfg(feature = "fault-injection")]
+    if let Some(spec) = &args.fault_spec {
+        let spec = shared::faults::FaultSpec::parse(spec, &["submit", "updates"]).unwrap_or_else(|e| {
+            tracing::error!("invalid --fault-spec: {e}");
+            std::process::exit(1);
+        });
+        tracing::warn!(spec = %spec, "fault injection enabled");
+        shared::faults::install(spec);
+    }
 
-    // Register this worker with the orchestrator
-    let worker = Worker::new(addr, orchestrator_endpoint, password, worker_credits).await;
+    if !args.warm_pool.is_empty() && args.isolate == Isolation::Process {
+        tracing::error!("--warm-pool can't be used with --isolate process, whose jobs run in their own processes");
+        std::process::exit(1);
+    }
+    let process_limited = args.job_process_max_secs.is_some() || args.job_process_cpu_secs.is_some() || args.job_process_memory_mb.is_some();
+    if process_limited && args.isolate != Isolation::Process {
+        tracing::error!("--job-process-max-secs, --job-process-cpu-secs and --job-process-memory-mb need --isolate process");
+        std::process::exit(1);
+    }
 
-    // Start the executor server
-    tracing::info!("Worker listening on {}", addr);
-    let incoming = TcpListenerStream::new(listener);
-    Server::builder()
-        .add_service(ExecutorServer::new(worker))
-        .serve_with_incoming_shutdown(incoming, async {
-            tokio::signal::ctrl_c().await.ok();
+    let trusted_keys = args.trusted_keys.map(|dir| {
+        TrustedKeys::load_dir(&dir).unwrap_or_else(|e| {
+            tracing::error!("{e}");
+            std::process::exit(1);
         })
-        .await
+    });
+    if let Some(keys) = &trusted_keys {
+        tracing::info!(keys = keys.len(), "only running signed jobs");
+    }
+
+    let config = WorkerConfig {
+        bind_host: args.bind_host,
+        port: args.port,
+        credits: args.worker_credits,
+        orchestrators: args.orchestrator,
+        password: args.password,
+        limits,
+        wasm_limits,
+        trusted_keys,
+        namespace: args.namespace,
+        require_reservation: args.require_reservation,
+        isolation: args.isolate,
+        process_limits: ProcessLimits {
+            wall_clock: args.job_process_max_secs.map(Duration::from_secs),
+            cpu_secs: args.job_process_cpu_secs,
+            memory_bytes: args.job_process_memory_mb.map(|mb| mb * 1024 * 1024),
+        },
+        handoff: args.handoff.then_some(HandoffConfig {
+            max_bytes: args.handoff_max_bytes,
+            deadline: Duration::from_secs(args.handoff_deadline_secs),
+        }),
+        connect_mode: args.connect_mode,
+        tls_address: args.tls_address,
+        delta_base_bytes: args.delta_base_mb * 1024 * 1024,
+        warm_pool: args.warm_pool,
+        warm_pool_bytes: args.warm_pool_mb * 1024 * 1024,
+        dual_hash: args.dual_hash,
+        load_aware: args.load_aware.then_some(LoadAware {
+            curve: args.load_curve,
+            sample_interval: Duration::from_secs(args.load_sample_secs.max(1)),
+        }),
+        abort_jobs_after: args.abort_jobs_after_secs.map(Duration::from_secs),
+        heartbeat_interval: Duration::from_secs(args.heartbeat_secs.max(1)),
+        reflection: args.reflection,
+        offload_output_bytes: args.offload_output_kb * 1024,
+        max_offloaded_output_bytes: args.max_offloaded_output_mb * 1024 * 1024,
+        output_dir: args.output_dir.unwrap_or_else(|| std::env::temp_dir().join("mini-lambda-outputs")),
+        output_retention: Duration::from_secs(args.output_retention_secs),
+        #[cfg(feature = "object-store")]
+        object_store_endpoint: args.object_store_endpoint,
+    };
+    let bind_host = config.bind_host.clone();
+    let handle = Worker::start(config).await
+        .unwrap_or_else(|e| panic!("Failed to bind to host {}: {}", bind_host, e));
+    handle.run_until(async { tokio::signal::ctrl_c().await.ok(); }).await
         .unwrap_or_else(|e| panic!("Executor server failed: {}", e));
-}
\ No newline at end of file
+}
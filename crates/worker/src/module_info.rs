@@ -0,0 +1,49 @@
+use std::time::SystemTime;
+
+use blake3::Hash;
+
+use shared::CachedModule;
+use shared::events::epoch_ms;
+use shared::inspect::ModuleMetadata;
+
+use crate::worker::Worker;
+
+/// What the Worker knows about a module in its compiled module cache, kept apart from the cache
+/// so that it survives the module's bytes being dropped once compiled.
+#[derive(Debug, Clone)]
+pub struct CachedModuleInfo {
+    /// Read from the module's bytes as it was compiled. None for modules handed off by a peer,
+    /// which arrive without them.
+    metadata: Option<ModuleMetadata>,
+    compiled_at: SystemTime,
+}
+
+impl Worker {
+    /// Records a module just added to the compiled module cache, forgetting the modules the
+    /// cache has evicted since and tearing down their warm pools.
+    pub(crate) async fn record_module(&self, wasm_hash: Hash, metadata: Option<ModuleMetadata>) {
+        self.module_info.insert(wasm_hash, CachedModuleInfo { metadata, compiled_at: SystemTime::now() });
+        let component_cache = self.component_cache.lock().await;
+        self.module_info.retain(|hash, _| component_cache.contains(hash));
+        self.warm_pool.retain(|hash| component_cache.contains(hash));
+    }
+
+    /// Lists the compiled modules in the cache, most recently used first, or only the one with
+    /// wasm_hash if given.
+    pub(crate) async fn cached_modules(&self, wasm_hash: Option<Hash>) -> Vec<CachedModule> {
+        let hashes: Vec<Hash> = self.component_cache.lock().await.iter()
+            .filter(|(hash, cell)| cell.initialized() && wasm_hash.is_none_or(|wanted| wanted == **hash))
+            .map(|(hash, _)| *hash)
+            .collect();
+        hashes.into_iter()
+            .map(|hash| {
+                let info = self.module_info.get(&hash).map(|info| info.clone());
+                let compiled_at_ms = info.as_ref().map_or(0, |info| epoch_ms(info.compiled_at));
+                match info.and_then(|info| info.metadata) {
+                    Some(metadata) => metadata.to_cached_module(hash.as_bytes(), compiled_at_ms),
+                    None => CachedModule { wasm_hash: hash.as_bytes().to_vec(), compiled_at_ms, ..CachedModule::default() },
+                }
+            })
+            .collect()
+    }
+}
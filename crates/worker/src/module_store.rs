@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use tokio::sync::OnceCell;
+use wasmtime::component::Component;
+
+use shared::ObjectStoreModule;
+use shared::digest::ModuleDigest;
+
+use crate::errors::ExecutorError;
+use crate::worker::Worker;
+
+#[cfg(feature = "object-store")]
+pub use enabled::ModuleStore;
+
+/// Placeholder for the object store client in builds without the object-store feature, which
+/// can't be created. Such workers reject jobs whose module is in an object store.
+#[cfg(not(feature = "object-store"))]
+#[derive(Debug)]
+pub enum ModuleStore {}
+
+impl Worker {
+    /// Resolves the cache entry for a job's object store module, downloading the module unless
+    /// this worker already has it compiled, having fetched it or, for a blake3 digest or with
+    /// --dual-hash, had it uploaded. Returns the entry and the wasm to compile into it, which is
+    /// empty if the entry is already compiled.
+    pub(crate) async fn fetch_module(&self, module: &ObjectStoreModule, skip_cache: bool) -> Result<(Arc<OnceCell<Component>>, Vec<u8>), ExecutorError> {
+        let digest = module.module_digest()?;
+        if !skip_cache && let Some(wasm_hash) = self.digests.resolve(&digest) {
+            let cell = self.component_cache.lock().await
+                .get(&wasm_hash)
+                .filter(|cell| cell.initialized())
+                .cloned();
+            if let Some(cell) = cell {
+                return Ok((cell, Vec::new()));
+            }
+        }
+
+        let wasm_bytes = self.download_module(&module.url, &digest).await?;
+        let wasm_hash = blake3::hash(&wasm_bytes);
+        self.digests.remember(digest, wasm_hash);
+        let mut component_cache = self.component_cache.lock().await;
+        if skip_cache {
+            component_cache.pop(&wasm_hash);
+        }
+        let cell = component_cache
+            .get_or_insert(wasm_hash, || Arc::new(OnceCell::new()))
+            .clone();
+        Ok((cell, wasm_bytes))
+    }
+
+    #[cfg(feature = "object-store")]
+    async fn download_module(&self, url: &str, digest: &ModuleDigest) -> Result<Vec<u8>, ExecutorError> {
+        let store = self.module_store.as_ref().ok_or(ExecutorError::ModuleSourceUnsupported)?;
+        store.fetch(url, digest, self.limits.max_wasm_bytes).await
+    }
+
+    #[cfg(not(feature = "object-store"))]
+    async fn download_module(&self, _url: &str, _digest: &ModuleDigest) -> Result<Vec<u8>, ExecutorError> {
+        Err(ExecutorError::ModuleSourceUnsupported)
+    }
+}
+
+#[cfg(feature = "object-store")]
+mod enabled {
+    use std::sync::Arc;
+
+    use dashmap::DashMap;
+    use object_store::ObjectStore;
+    use object_store::aws::AmazonS3Builder;
+    use object_store::path::Path;
+
+    use shared::digest::ModuleDigest;
+
+    use crate::digests;
+    use crate::errors::ExecutorError;
+
+    /// Downloads job modules from an S3-compatible object store. Credentials and region are
+    /// read from the standard AWS_* environment variables.
+    #[derive(Debug)]
+    pub struct ModuleStore {
+        endpoint: String,
+        /// A client per bucket, created on first use.
+        buckets: DashMap<String, Arc<dyn ObjectStore>>,
+    }
+
+    impl ModuleStore {
+        pub fn new(endpoint: String) -> Self {
+            Self { endpoint, buckets: DashMap::new() }
+        }
+
+        /// Downloads the object at an s3://bucket/key URL, checking it is within max_bytes and
+        /// matches its digest.
+        pub async fn fetch(&self, url: &str, digest: &ModuleDigest, max_bytes: usize) -> Result<Vec<u8>, ExecutorError> {
+            let (bucket, key) = url.strip_prefix("s3://")
+                .and_then(|rest| rest.split_once('/'))
+                .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+                .ok_or_else(|| ExecutorError::ModuleFetchFailed(format!("{url} is not an s3://bucket/key URL")))?;
+
+            tracing::debug!(url = %url, "fetching module from object store");
+            let object = self.bucket(bucket)?.get(&Path::from(key)).await
+                .map_err(|e| fetch_error(url, e))?;
+            if object.meta.size > max_bytes as u64 {
+                return Err(ExecutorError::ModuleFetchFailed(format!("module {url} is {} bytes, over the limit of {max_bytes}", object.meta.size)));
+            }
+            let wasm_bytes = object.bytes().await.map_err(|e| fetch_error(url, e))?;
+
+            digests::verify(url, digest, &wasm_bytes)?;
+            Ok(wasm_bytes.to_vec())
+        }
+
+        fn bucket(&self, bucket: &str) -> Result<Arc<dyn ObjectStore>, ExecutorError> {
+            if let Some(store) = self.buckets.get(bucket) {
+                return Ok(store.clone());
+            }
+            let store = AmazonS3Builder::from_env()
+                .with_endpoint(&self.endpoint)
+                .with_bucket_name(bucket)
+                .with_allow_http(true)
+                .build()
+                .map_err(|e| ExecutorError::ModuleFetchFailed(format!("invalid object store configuration: {e}")))?;
+            let store: Arc<dyn ObjectStore> = Arc::new(store);
+            self.buckets.insert(bucket.to_string(), store.clone());
+            Ok(store)
+        }
+    }
+
+    fn fetch_error(url: &str, e: object_store::Error) -> ExecutorError {
+        match e {
+            object_store::Error::NotFound { .. } => ExecutorError::ModuleNotFound(url.to_string()),
+            object_store::Error::PermissionDenied { .. } | object_store::Error::Unauthenticated { .. } => {
+                ExecutorError::ModuleAccessDenied(url.to_string())
+            },
+            e => ExecutorError::ModuleFetchFailed(format!("{url}: {e}")),
+        }
+    }
+}
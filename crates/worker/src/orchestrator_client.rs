@@ -1,26 +1,29 @@
-use shared::{JobState, JobUpdate, OrchestratorMessage, WorkerRegistration, orchestrator_message, worker_api_client::WorkerApiClient, worker_message};
+use shared::compat::PROTOCOL_VERSION;
+use shared::{JobDetails, JobState, JobUpdate, JobUsage, OrchestratorMessage, WorkerCapabilities, WorkerRegistration, orchestrator_message, worker_api_client::WorkerApiClient, worker_message};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Status, Streaming, transport::Channel};
 
 use shared::{WorkerMessage};
-use uuid::Uuid;
+use shared::JobId;
+use shared::limits::JobLimits;
 
+use crate::orchestrators::OrchestratorId;
 use crate::worker::Worker;
 
 // Implement Worker function related to communication with the Orchestrator
 impl Worker {
 
-    /// Connects to the Orchestrator and returns a sender for outbound messages,
+    /// Connects to an Orchestrator and returns a sender for outbound messages,
     /// and a stream for inbound messages.
-    pub async fn connect_to_orchestrator(orchestrator_endpoint: &str, password: Option<String>) -> (Sender<WorkerMessage>, Streaming<OrchestratorMessage>) {
+    pub async fn connect_to_orchestrator(orchestrator_endpoint: &str, password: Option<String>) -> Result<(Sender<WorkerMessage>, Streaming<OrchestratorMessage>), Status> {
 
         let channel = Channel::from_shared(orchestrator_endpoint.to_string())
             .unwrap_or_else(|e| panic!("invalid orchestrator endpoint '{}': {}", orchestrator_endpoint, e))
             .connect().await
-            .unwrap_or_else(|e| panic!("failed to connect to orchestrator at '{}': {}", orchestrator_endpoint, e));
-        let mut client = WorkerApiClient::with_interceptor(channel, move |mut req: Request<()>| {
+            .map_err(|e| Status::unavailable(format!("failed to connect to orchestrator at '{orchestrator_endpoint}': {e}")))?;
+        let client = WorkerApiClient::with_interceptor(channel, move |mut req: Request<()>| {
             if let Some(pass) = &password {
                 let val = pass.parse()
                     .map_err(|e| Status::invalid_argument(format!("invalid authorization header value: {e}")))?;
@@ -28,84 +31,152 @@ impl Worker {
             }
             Ok(req)
         });
+        // Relayed calls carry whole modules, as jobs sent to the executor server do
+        let mut client = client.max_decoding_message_size(JobLimits::default().max_message_bytes());
 
         // Set up channel for streaming
         let (tx, rx) = mpsc::channel(32);
         let outbound = ReceiverStream::new(rx);
 
         // Connect and get the response stream
-        let response = client.connect_worker(Request::new(outbound)).await
-            .unwrap_or_else(|e| panic!("Orchestrator should accept worker connections during startup, received error {}", e));
+        let response = client.connect_worker(Request::new(outbound)).await?;
         let inbound = response.into_inner();
 
-        (tx, inbound)
+        Ok((tx, inbound))
     }
 
-    /// Start a bidirectional communication session with the Orchestrator. This consists of 
-    /// spawing a task to process inbound messages, and sending the initial registration message.
-    pub async fn start_orchestrator_session(&self, mut inbound: Streaming<OrchestratorMessage>, credits: u32) {
+    /// What this Worker supports, as reported to the Orchestrator when registering.
+    fn capabilities(&self) -> WorkerCapabilities {
+        WorkerCapabilities {
+            max_wasm_bytes: self.limits.max_wasm_bytes as u64,
+            max_args: self.limits.max_args as u32,
+            max_args_bytes: self.limits.max_args_bytes as u64,
+            signed_jobs_only: self.trusted_keys.is_some(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            hash_algorithms: self.digests.algorithms().into_iter().map(|algorithm| algorithm as i32).collect(),
+        }
+    }
+
+    /// Start a bidirectional communication session with one of the Orchestrators. This consists
+    /// of sending the initial registration message, and spawing a task to process inbound
+    /// messages. Each Orchestrator's session is independent: one rejecting the Worker or
+    /// closing its stream leaves the others be, see Orchestrators::disconnected.
+    pub(crate) async fn start_orchestrator_session(&self, id: OrchestratorId, mut inbound: Streaming<OrchestratorMessage>, credits: u32, namespace: Option<String>) {
 
         // Send the initial registration message
-        let address = self.addr.to_string();
-        self.orchestrator_tx.send(WorkerMessage {
-            message: Some(worker_message::Message::Registration(WorkerRegistration { address, credits }))
-        }).await.unwrap_or_else(|e| panic!("Channel to Orchestrator should be working for initial registration, got error {}", e));
-        
-        let (jwt_secret, network_access_allowed) = match inbound.message().await {
+        let registration = WorkerRegistration {
+            address: self.addr.to_string(),
+            credits,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Some(self.capabilities()),
+            namespace,
+            require_reservation: self.require_reservation,
+            relayed: self.relay_gate.is_some(),
+            tls_address: self.tls_address.clone(),
+        };
+        if let Err(e) = self.orchestrators.tx(id).send(WorkerMessage {
+            message: Some(worker_message::Message::Registration(registration))
+        }).await {
+            tracing::error!(orchestrator = %self.orchestrators.url(id), error = %e, "failed to send registration to orchestrator");
+            self.orchestrators.disconnected(id);
+            return;
+        }
+
+        let (jwt_secret, network_access_allowed, cost_weights) = match inbound.message().await {
             Ok(Some(OrchestratorMessage { message: Some(orchestrator_message::Message::RegistrationAck(ack)) })) => {
-                let jwt_secret = ack.jwt_secret.try_into()
-                    .unwrap_or_else(|e| panic!("received malformed jwt secret: {:?}", e));
-                (jwt_secret, ack.network_access_allowed)
+                let Ok(jwt_secret) = ack.jwt_secret.try_into() else {
+                    tracing::error!(orchestrator = %self.orchestrators.url(id), "orchestrator sent a malformed jwt secret");
+                    self.orchestrators.disconnected(id);
+                    return;
+                };
+                (jwt_secret, ack.network_access_allowed, ack.cost_weights.unwrap_or_default())
+            },
+            Err(status) => {
+                tracing::error!(orchestrator = %self.orchestrators.url(id), error = %status.message(), "orchestrator rejected registration");
+                self.orchestrators.disconnected(id);
+                return;
             },
-            msg => panic!("expected registration ack as first message, got: {:?}", msg)
+            msg => {
+                tracing::error!(orchestrator = %self.orchestrators.url(id), message = ?msg, "expected a registration ack as the orchestrator's first message");
+                self.orchestrators.disconnected(id);
+                return;
+            }
         };
-        self.jwt_secret.set(jwt_secret).ok();
+        self.orchestrators.registered(id, jwt_secret);
         self.network_access_allowed.set(network_access_allowed).ok();
+        self.cost_weights.set(cost_weights).ok();
 
-        tracing::info!(address = %self.addr, credits = credits, "registered with orchestrator");
+        tracing::info!(address = %self.addr, orchestrator = %self.orchestrators.url(id), credits = credits, "registered with orchestrator");
 
         // Spawn a task to handle incoming messages from the orchestrator
         let worker = self.clone();
         tokio::spawn(async move {
             loop {
                 match inbound.message().await {
-                    Ok(Some(message)) => worker.handle_orchestrator_message(message).await,
+                    Ok(Some(message)) => worker.handle_orchestrator_message(id, message).await,
                     Ok(None) => {
-                        tracing::info!("orchestrator stream closed, shutting down");
+                        tracing::info!(orchestrator = %worker.orchestrators.url(id), "orchestrator stream closed");
                         break;
                     },
                     Err(e) => {
-                        tracing::error!(error = %e, "received a stream error from the orchestrator, shutting down");
+                        tracing::error!(orchestrator = %worker.orchestrators.url(id), error = %e, "received a stream error from the orchestrator");
                         break;
                     }
                 }
             }
-            std::process::exit(1);
+            worker.orchestrators.disconnected(id);
         });
     }
 
-    /// Handles all incoming messages from the Orchestrator.
-    pub async fn handle_orchestrator_message(&self, message: OrchestratorMessage) {
+    /// Handles all incoming messages from one of the Orchestrators.
+    pub(crate) async fn handle_orchestrator_message(&self, id: OrchestratorId, message: OrchestratorMessage) {
         match message.message {
             Some(orchestrator_message::Message::RegistrationAck(_ack)) => {
-                tracing::error!("ERROR: received registration ack while already registered, this should never happen");
-                std::process::exit(1);
+                tracing::warn!(orchestrator = %self.orchestrators.url(id), "ignoring a registration ack received while already registered");
+            },
+            Some(orchestrator_message::Message::Reservation(reservation)) => {
+                self.hold_reservation(id, reservation);
+            },
+            Some(message @ (orchestrator_message::Message::PeerList(_) | orchestrator_message::Message::HandoffAck(_))) => {
+                self.handle_handoff_reply(message);
+            },
+            Some(orchestrator_message::Message::RelayedCall(call)) => {
+                self.handle_relayed_call(id, *call);
             },
             None => {
-                tracing::error!("ERROR: orchestrator sent a message with no content, this should never happen");
-                std::process::exit(1);
+                // A newer orchestrator's message that this worker doesn't know
+                tracing::warn!(orchestrator = %self.orchestrators.url(id), "ignoring an unrecognized message from the orchestrator");
             }
         }
     }
 
     /// Sends a job state update to the orchestrator over the worker's outbound stream.
     /// Note, this is fire-and-forget; it spawns a task and returns immediately.
-    pub fn send_job_update_to_orchestrator(orchestrator_tx: Sender<WorkerMessage>, job_id: Uuid, job_state: JobState) {
+    pub fn send_job_update_to_orchestrator(orchestrator_tx: Sender<WorkerMessage>, job_id: JobId, job_state: JobState) {
+        Self::send_job_update(orchestrator_tx, JobUpdate { job_id: job_id.to_bytes(), state: job_state.into(), details: None, usage: None, exit_code: None });
+    }
+
+    /// Tells the orchestrator a job is executing, along with what it runs. Fire-and-forget,
+    /// like send_job_update_to_orchestrator.
+    pub fn send_job_executing_to_orchestrator(orchestrator_tx: Sender<WorkerMessage>, job_id: JobId, details: JobDetails) {
+        Self::send_job_update(orchestrator_tx, JobUpdate { job_id: job_id.to_bytes(), state: JobState::Executing.into(), details: Some(details), usage: None, exit_code: None });
+    }
+
+    /// Tells the orchestrator a job ended, along with what it used if it began executing and
+    /// its exit code if its guest exited. Fire-and-forget, like send_job_update_to_orchestrator.
+    pub fn send_job_finished_to_orchestrator(orchestrator_tx: Sender<WorkerMessage>, job_id: JobId, job_state: JobState, usage: Option<JobUsage>, exit_code: Option<i32>) {
+        Self::send_job_update(orchestrator_tx, JobUpdate { job_id: job_id.to_bytes(), state: job_state.into(), details: None, usage, exit_code });
+    }
+
+    fn send_job_update(orchestrator_tx: Sender<WorkerMessage>, job_update: JobUpdate) {
+        #[cfg(feature = "fault-injection")]
+        if shared::faults::should_drop("updates") {
+            tracing::debug!(job_id = ?JobId::from_bytes(&job_update.job_id).ok(), "dropping job update (injected fault)");
+            return;
+        }
         tokio::spawn(async move {
             _ = orchestrator_tx.send(WorkerMessage {
-                message: Some(worker_message::Message::JobUpdate(JobUpdate { 
-                    job_id: job_id.as_bytes().to_vec(), state: job_state.into() 
-                }))
+                message: Some(worker_message::Message::JobUpdate(job_update))
             }).await;
         });
     }
@@ -0,0 +1,150 @@
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use tokio::sync::mpsc::Sender;
+
+use shared::{CreditUpdate, Heartbeat, WorkerMessage, worker_message};
+
+/// One of the Orchestrators a Worker is registered with, by its position among them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OrchestratorId(usize);
+
+/// A Worker's stream to one of its Orchestrators.
+#[derive(Debug)]
+struct OrchestratorLink {
+    url: String,
+    tx: Sender<WorkerMessage>,
+    /// The secret the Orchestrator issues job tokens with, from its registration ack.
+    jwt_secret: OnceLock<[u8; 32]>,
+    /// Whether the Worker is registered with the Orchestrator and its stream is still open.
+    open: AtomicBool,
+}
+
+/// The Orchestrators a Worker is registered with, for redundancy. Each dispatches jobs to the
+/// Worker from its own count of the Worker's credits, so the others are told of every credit a
+/// job takes, and the credits jobs return go back to all of them. A job's updates only go to
+/// the Orchestrator that dispatched it.
+#[derive(Debug)]
+pub struct Orchestrators {
+    links: Vec<OrchestratorLink>,
+    /// Orchestrators not yet known to have rejected the Worker or closed its stream. The
+    /// Worker shuts down once there are none.
+    remaining: AtomicUsize,
+}
+
+impl Orchestrators {
+    /// Creates the links for the Orchestrators the Worker connected to, by URL and outbound
+    /// sender, in the order they were configured.
+    pub(crate) fn new(connections: Vec<(String, Sender<WorkerMessage>)>) -> Self {
+        let links: Vec<OrchestratorLink> = connections.into_iter()
+            .map(|(url, tx)| OrchestratorLink { url, tx, jwt_secret: OnceLock::new(), open: AtomicBool::new(false) })
+            .collect();
+        let remaining = AtomicUsize::new(links.len());
+        Self { links, remaining }
+    }
+
+    pub(crate) fn ids(&self) -> impl Iterator<Item = OrchestratorId> {
+        (0..self.links.len()).map(OrchestratorId)
+    }
+
+    pub(crate) fn url(&self, id: OrchestratorId) -> &str {
+        &self.links[id.0].url
+    }
+
+    /// The sender for messages to the Orchestrator.
+    pub(crate) fn tx(&self, id: OrchestratorId) -> Sender<WorkerMessage> {
+        self.links[id.0].tx.clone()
+    }
+
+    /// Records that the Orchestrator acknowledged the Worker's registration.
+    pub(crate) fn registered(&self, id: OrchestratorId, jwt_secret: [u8; 32]) {
+        let link = &self.links[id.0];
+        link.jwt_secret.set(jwt_secret).ok();
+        link.open.store(true, Ordering::SeqCst);
+    }
+
+    /// Records that the Orchestrator rejected the Worker or its stream closed. The Worker
+    /// carries on with the others, and exits once none are left.
+    pub(crate) fn disconnected(&self, id: OrchestratorId) {
+        self.links[id.0].open.store(false, Ordering::SeqCst);
+        if self.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+            tracing::error!("lost connection to the orchestrator, shutting down");
+            std::process::exit(1);
+        }
+        tracing::warn!(orchestrator = %self.url(id), "lost connection to an orchestrator, carrying on with the others");
+    }
+
+    /// The first Orchestrator still connected, which handoffs go through.
+    pub(crate) fn primary(&self) -> OrchestratorId {
+        OrchestratorId(self.links.iter().position(|link| link.open.load(Ordering::SeqCst)).unwrap_or(0))
+    }
+
+    /// The secret the Orchestrator issues job tokens with.
+    pub(crate) fn jwt_secret(&self, id: OrchestratorId) -> &[u8; 32] {
+        // The worker registers with its orchestrators before accepting any jobs, and only
+        // connected orchestrators are picked, so this should never happen.
+        self.links[id.0].jwt_secret.get()
+            .unwrap_or_else(|| {
+                tracing::error!("ERROR: worker should always have received jwt secret before running a job, this should never happen");
+                std::process::exit(1);
+            })
+    }
+
+    /// The secrets of the Orchestrators the Worker registered with, any of which may have
+    /// issued a job's token.
+    pub(crate) fn jwt_secrets(&self) -> impl Iterator<Item = (OrchestratorId, &[u8; 32])> {
+        self.links.iter().enumerate()
+            .filter_map(|(index, link)| link.jwt_secret.get().map(|secret| (OrchestratorId(index), secret)))
+    }
+
+    /// Tells the Orchestrators other than the one that dispatched a job that it took one of
+    /// the Worker's credits. Fire-and-forget; no-op with a single Orchestrator.
+    pub(crate) fn credit_taken(self: &Arc<Self>, dispatcher: OrchestratorId) {
+        if self.links.len() == 1 {
+            return;
+        }
+        let orchestrators = self.clone();
+        tokio::spawn(async move {
+            for id in orchestrators.ids().filter(|id| *id != dispatcher) {
+                orchestrators.send_credit_update(id, CreditUpdate { delta: 0, taken: 1, host_load: None }).await;
+            }
+        });
+    }
+
+    /// Returns a job's credit to every Orchestrator, as each counted it taken. Fire-and-forget.
+    pub(crate) fn return_credit(self: &Arc<Self>) {
+        let orchestrators = self.clone();
+        tokio::spawn(async move {
+            for id in orchestrators.ids() {
+                orchestrators.send_credit_update(id, CreditUpdate { delta: 1, taken: 0, host_load: None }).await;
+            }
+        });
+    }
+
+    /// Sends a host load sample and the credits it withholds or returns to every Orchestrator,
+    /// as each counts them. See load.rs.
+    pub(crate) async fn report_load(&self, credit_update: CreditUpdate) {
+        for id in self.ids() {
+            self.send_credit_update(id, credit_update).await;
+        }
+    }
+
+    /// Sends a heartbeat to every Orchestrator, dropping it for those whose stream is closed.
+    pub(crate) async fn heartbeat(&self, heartbeat: Heartbeat) {
+        for id in self.ids() {
+            let message = WorkerMessage { message: Some(worker_message::Message::Heartbeat(heartbeat)) };
+            if self.links[id.0].tx.send(message).await.is_err() {
+                tracing::debug!(orchestrator = %self.url(id), "orchestrator stream closed, dropping heartbeat");
+            }
+        }
+    }
+
+    /// Sends a credit update, dropping it if the Orchestrator's stream is closed, as the
+    /// Orchestrator forgets the Worker's credits then.
+    async fn send_credit_update(&self, id: OrchestratorId, credit_update: CreditUpdate) {
+        let message = WorkerMessage { message: Some(worker_message::Message::CreditUpdate(credit_update)) };
+        if self.links[id.0].tx.send(message).await.is_err() {
+            tracing::debug!(orchestrator = %self.url(id), "orchestrator stream closed, dropping credit update");
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use tokio::io::AsyncWrite;
+use wasmtime_wasi::cli::{IsTerminal, StdoutStream};
+use wasmtime_wasi::p2::{OutputStream, Pollable, StreamError};
+
+use shared::OutputRef;
+use shared::output::OutputHasher;
+
+/// Where a job's stdout goes once it outgrows threshold, if the job's client can fetch it from
+/// the Worker's OutputStore. Sent along to the helper process of an isolated job, which writes
+/// the file itself.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OutputSpill {
+    /// The id the output is fetched by, which names its file.
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub path: String,
+    #[prost(uint64, tag = "3")]
+    pub threshold: u64,
+    /// Most bytes the job may write to the file.
+    #[prost(uint64, tag = "4")]
+    pub max_bytes: u64,
+}
+
+/// A job's stdout. It is kept in memory up to max_output_bytes, like wasmtime_wasi's
+/// MemoryOutputPipe, unless the job has an OutputSpill. Then once the output outgrows the
+/// spill's threshold, it is written on to the spill's file instead, so that an output of any
+/// size up to the spill's max_bytes is never held in memory.
+#[derive(Clone)]
+pub struct OutputPipe {
+    state: Arc<Mutex<PipeState>>,
+}
+
+struct PipeState {
+    buffer: Vec<u8>,
+    /// Most bytes the job may write in all.
+    capacity: u64,
+    written: u64,
+    spill: Option<OutputSpill>,
+    /// Set once the output is written to the spill's file.
+    file: Option<(BufWriter<File>, OutputHasher)>,
+}
+
+/// What a job wrote to an OutputPipe.
+pub enum PipeContents {
+    Inline(Vec<u8>),
+    /// The output was written to its spill's file, and is fetched by this reference.
+    Stored(OutputRef),
+}
+
+impl OutputPipe {
+    pub fn new(max_output_bytes: usize, spill: Option<OutputSpill>) -> Self {
+        let capacity = spill.as_ref().map_or(max_output_bytes as u64, |spill| spill.max_bytes);
+        let state = PipeState { buffer: Vec::new(), capacity, written: 0, spill, file: None };
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// What the job wrote, once it has finished. Fails if the rest of a stored output
+    /// couldn't be written to its file.
+    pub fn finish(&self) -> std::io::Result<PipeContents> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        match (state.file.take(), &state.spill) {
+            (Some((mut file, hasher)), Some(spill)) => {
+                file.flush()?;
+                Ok(PipeContents::Stored(OutputRef { id: spill.id.clone(), size: state.written, sha256: hasher.finish() }))
+            },
+            _ => Ok(PipeContents::Inline(std::mem::take(&mut state.buffer))),
+        }
+    }
+}
+
+impl PipeState {
+    fn remaining(&self) -> u64 {
+        self.capacity - self.written
+    }
+
+    /// Writes are made to the file straight away, through its buffer. The guest is waiting on
+    /// them either way, and jobs don't run for long enough to be worth a blocking task per write.
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if let Some(spill) = &self.spill
+            && self.file.is_none()
+            && self.buffer.len() + bytes.len() > spill.threshold as usize
+        {
+            let mut file = BufWriter::new(File::create(&spill.path)?);
+            let mut hasher = OutputHasher::new();
+            file.write_all(&self.buffer)?;
+            hasher.update(&self.buffer);
+            self.buffer = Vec::new();
+            self.file = Some((file, hasher));
+        }
+        match &mut self.file {
+            Some((file, hasher)) => {
+                file.write_all(bytes)?;
+                hasher.update(bytes);
+            },
+            None => self.buffer.extend_from_slice(bytes),
+        }
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl OutputStream for OutputPipe {
+    fn write(&mut self, bytes: Bytes) -> Result<(), StreamError> {
+        let mut state = self.state.lock().unwrap();
+        if bytes.len() as u64 > state.remaining() {
+            return Err(StreamError::trap("write beyond the job's output limit"));
+        }
+        state.write(&bytes)
+            .map_err(|e| StreamError::Trap(wasmtime::format_err!("failed to store the job's output: {e}")))
+    }
+
+    fn flush(&mut self) -> Result<(), StreamError> {
+        // Stored outputs are flushed once the job finishes, see finish
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> Result<usize, StreamError> {
+        match self.state.lock().unwrap().remaining() {
+            // No more bytes will ever be written
+            0 => Err(StreamError::Closed),
+            remaining => Ok(remaining.try_into().unwrap_or(usize::MAX)),
+        }
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl Pollable for OutputPipe {
+    async fn ready(&mut self) {}
+}
+
+impl AsyncWrite for OutputPipe {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        let len = buf.len().min(state.remaining().try_into().unwrap_or(usize::MAX));
+        Poll::Ready(state.write(&buf[..len]).map(|()| len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl IsTerminal for OutputPipe {
+    fn is_terminal(&self) -> bool {
+        false
+    }
+}
+
+impl StdoutStream for OutputPipe {
+    fn p2_stream(&self) -> Box<dyn OutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn async_stream(&self) -> Box<dyn AsyncWrite + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
@@ -0,0 +1,185 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use shared::output::OUTPUT_CHUNK_BYTES;
+use shared::{JobId, OutputRef};
+
+use crate::errors::ExecutorError;
+use crate::output_pipe::OutputSpill;
+use crate::worker::Worker;
+
+/// How often outputs whose retention passed are deleted.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Job outputs too large to return in a JobResponse, written to disk as the job runs for its
+/// client to fetch in parts, see shared::OutputRef and OutputPipe. Workers on the same host may
+/// be configured with the same directory, so each keeps its outputs in a directory of its own
+/// within it. Each output is deleted once its retention passes, whether it was fetched or not.
+#[derive(Debug)]
+pub struct OutputStore {
+    /// The configured directory, shared with any other Workers on the host.
+    root: PathBuf,
+    /// This Worker's directory in root.
+    dir: PathBuf,
+    /// Outputs larger than this are stored. Zero turns storing outputs off.
+    threshold: usize,
+    /// Most bytes a job whose output is stored may write to its stdout.
+    max_bytes: u64,
+    retention: Duration,
+    /// Each stored output by its id. Only these ids name files in dir that can be fetched.
+    outputs: DashMap<String, StoredOutput>,
+}
+
+#[derive(Debug)]
+struct StoredOutput {
+    /// The job that wrote the output, which alone may fetch it, along with the jobs coalesced
+    /// with it.
+    job_id: JobId,
+    size: u64,
+    stored_at: Instant,
+}
+
+impl OutputStore {
+    /// Creates the store of the Worker registered as addr in root. Anything left in its
+    /// directory was stored before the Worker last started, and can't be fetched any more, so
+    /// it is deleted.
+    pub fn new(root: PathBuf, addr: &str, threshold: usize, max_bytes: u64, retention: Duration) -> Self {
+        let dir = root.join(addr.replace(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-', "_"));
+        if threshold > 0 {
+            match std::fs::remove_dir_all(&dir) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => tracing::warn!(dir = %dir.display(), error = %e, "failed to delete outputs stored before the worker started"),
+                _ => {},
+            }
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                tracing::error!(dir = %dir.display(), error = %e, "failed to create the job output directory");
+            }
+        }
+        Self { root, dir, threshold, max_bytes, retention, outputs: DashMap::new() }
+    }
+
+    /// Where a job's output goes once it outgrows the threshold, if the job's client can fetch
+    /// a stored output. None if storing outputs is off.
+    pub fn spill(&self) -> Option<OutputSpill> {
+        if self.threshold == 0 {
+            return None;
+        }
+        // Random, so that only the client the reference is returned to knows it
+        let id = JobId::random().to_string();
+        let path = self.path(&id).to_string_lossy().into_owned();
+        Some(OutputSpill { id, path, threshold: self.threshold as u64, max_bytes: self.max_bytes })
+    }
+
+    /// Keeps an output the job wrote to its spill for the job's client to fetch, until the
+    /// retention passes.
+    pub fn insert(&self, output_ref: &OutputRef, job_id: JobId) {
+        self.outputs.insert(output_ref.id.clone(), StoredOutput { job_id, size: output_ref.size, stored_at: Instant::now() });
+    }
+
+    /// Deletes whatever a job that didn't succeed wrote to its spill.
+    pub async fn discard(&self, spill: &OutputSpill) {
+        match tokio::fs::remove_file(&spill.path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => tracing::warn!(output_id = %spill.id, error = %e, "failed to delete a job's output"),
+            _ => {},
+        }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    /// Deletes this Worker's outputs whose retention passed. So do files in root no Worker
+    /// keeps that are older than the retention: those of Workers that stopped, and of jobs that
+    /// stopped as they wrote them. Returns how many outputs were deleted.
+    pub async fn sweep(&self) -> usize {
+        let expired: Vec<String> = self.outputs.iter()
+            .filter(|output| output.stored_at.elapsed() >= self.retention)
+            .map(|output| output.key().clone())
+            .collect();
+        let mut deleted = 0;
+        for id in expired {
+            if self.outputs.remove(&id).is_some() {
+                delete(&self.path(&id)).await;
+                deleted += 1;
+            }
+        }
+
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(mut entries) = tokio::fs::read_dir(&dir).await else { continue };
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let Ok(metadata) = entry.metadata().await else { continue };
+                if metadata.is_dir() {
+                    // Only the root's own directories, one for each Worker, hold outputs
+                    if dir == self.root {
+                        dirs.push(entry.path());
+                    }
+                    continue;
+                }
+                let kept = dir == self.dir && self.outputs.contains_key(entry.file_name().to_string_lossy().as_ref());
+                let stale = metadata.modified().ok()
+                    .and_then(|modified| modified.elapsed().ok())
+                    .is_some_and(|age| age >= self.retention);
+                if !kept && stale {
+                    delete(&entry.path()).await;
+                    deleted += 1;
+                }
+            }
+        }
+        deleted
+    }
+}
+
+async fn delete(path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        tracing::warn!(path = %path.display(), error = %e, "failed to delete expired job output");
+    }
+}
+
+impl Worker {
+    /// Starts deleting stored outputs whose retention passed in the background.
+    pub(crate) fn start_output_sweeper(&self) {
+        if self.output_store.threshold == 0 {
+            return;
+        }
+        let store = self.output_store.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let deleted = store.sweep().await;
+                if deleted > 0 {
+                    tracing::info!(deleted, "deleted expired job outputs");
+                }
+            }
+        });
+    }
+
+    /// Reads up to max_bytes of a stored output from offset for job_id, which must be the job
+    /// that wrote it or the one it was coalesced with, returning them and whether they reach the
+    /// end of the output.
+    pub(crate) async fn read_output(&self, id: &str, job_id: JobId, coalesced_with: Option<JobId>, offset: u64, max_bytes: u64) -> Result<(Vec<u8>, bool), ExecutorError> {
+        let store = &self.output_store;
+        let (owner, size) = store.outputs.get(id)
+            .filter(|output| output.stored_at.elapsed() < store.retention)
+            .map(|output| (output.job_id, output.size))
+            .ok_or(ExecutorError::OutputExpired)?;
+        if owner != job_id && coalesced_with != Some(owner) {
+            return Err(ExecutorError::OutputNotOwned);
+        }
+        let len = max_bytes.min(OUTPUT_CHUNK_BYTES).min(size.saturating_sub(offset));
+
+        let read_failed = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::NotFound => ExecutorError::OutputExpired,
+            _ => ExecutorError::OutputStoreFailed(e.to_string()),
+        };
+        let mut file = tokio::fs::File::open(store.path(id)).await.map_err(read_failed)?;
+        file.seek(SeekFrom::Start(offset)).await.map_err(read_failed)?;
+        let mut data = vec![0; len as usize];
+        file.read_exact(&mut data).await.map_err(read_failed)?;
+        Ok((data, offset + len >= size))
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::sync::{OwnedRwLockReadGuard, RwLock};
+use tonic::{Request, Status};
+
+use shared::executor_server::Executor;
+use shared::{RelayedCall, RelayedError, RelayedReply, WorkerMessage, relayed_call, relayed_reply, worker_message};
+
+use crate::orchestrators::OrchestratorId;
+use crate::worker::Worker;
+
+/// How clients reach a Worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectMode {
+    /// Clients connect to the Worker's executor server.
+    #[default]
+    Listen,
+    /// The Worker accepts no connections, e.g. behind NAT, and clients' calls are relayed to
+    /// it by the Orchestrator over the Worker's own connection.
+    Relay,
+}
+
+impl FromStr for ConnectMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "listen" => Ok(ConnectMode::Listen),
+            "relay" => Ok(ConnectMode::Relay),
+            other => Err(format!("unknown connect mode '{other}', expected listen or relay")),
+        }
+    }
+}
+
+impl fmt::Display for ConnectMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConnectMode::Listen => "listen",
+            ConnectMode::Relay => "relay",
+        })
+    }
+}
+
+/// Lets a relayed Worker stop taking calls on shutdown and wait for those in flight, as the
+/// executor server does for a listening Worker.
+#[derive(Debug, Clone, Default)]
+pub struct RelayGate {
+    closed: Arc<RwLock<bool>>,
+}
+
+impl RelayGate {
+    /// Admits a call, held until the call is answered. None once closed.
+    async fn enter(&self) -> Option<OwnedRwLockReadGuard<bool>> {
+        let open = self.closed.clone().read_owned().await;
+        (!*open).then_some(open)
+    }
+
+    /// Refuses new calls, and waits for those admitted to be answered.
+    pub(crate) async fn close(&self) {
+        *self.closed.write().await = true;
+    }
+}
+
+impl Worker {
+    /// Answers a call the Orchestrator relayed from a client in a task of its own, as the
+    /// executor server would a direct call, and sends the reply back to the Orchestrator that
+    /// relayed it.
+    pub(crate) fn handle_relayed_call(&self, id: OrchestratorId, call: RelayedCall) {
+        let worker = self.clone();
+        tokio::spawn(async move {
+            let reply = match worker.answer_relayed_call(call.jwt_token, call.call).await {
+                Ok(reply) => reply,
+                Err(status) => relayed_reply::Reply::Error(RelayedError::from(&status)),
+            };
+            let message = WorkerMessage {
+                message: Some(worker_message::Message::RelayedReply(RelayedReply { call_id: call.call_id, reply: Some(reply) })),
+            };
+            _ = worker.orchestrators.tx(id).send(message).await;
+        });
+    }
+
+    async fn answer_relayed_call(&self, jwt_token: String, call: Option<relayed_call::Call>) -> Result<relayed_reply::Reply, Status> {
+        let Some(gate) = &self.relay_gate else {
+            return Err(Status::failed_precondition("worker accepts connections, call it directly"));
+        };
+        let Some(_admitted) = gate.enter().await else {
+            return Err(Status::unavailable("worker is shutting down"));
+        };
+        let jwt_token = jwt_token.parse()
+            .map_err(|_| Status::unauthenticated("malformed authorization token"))?;
+
+        Ok(match call {
+            Some(relayed_call::Call::ExecuteJob(job)) => {
+                relayed_reply::Reply::ExecuteJob(self.execute_job(authorized(job, jwt_token)).await?.into_inner())
+            },
+            Some(relayed_call::Call::CancelJob(cancel)) => {
+                relayed_reply::Reply::CancelJob(self.cancel_job(authorized(cancel, jwt_token)).await?.into_inner())
+            },
+            Some(relayed_call::Call::WarmModule(warm)) => {
+                relayed_reply::Reply::WarmModule(self.warm_module(authorized(warm, jwt_token)).await?.into_inner())
+            },
+            Some(relayed_call::Call::ListModules(list)) => {
+                relayed_reply::Reply::ListModules(self.list_modules(authorized(list, jwt_token)).await?.into_inner())
+            },
+            Some(relayed_call::Call::FetchOutput(fetch)) => {
+                relayed_reply::Reply::FetchOutput(self.fetch_output(authorized(fetch, jwt_token)).await?.into_inner())
+            },
+            None => return Err(Status::invalid_argument("relayed call has no content")),
+        })
+    }
+}
+
+/// A request carrying the token the client would have sent the Worker directly.
+fn authorized<T>(message: T, jwt_token: tonic::metadata::MetadataValue<tonic::metadata::Ascii>) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert("authorization", jwt_token);
+    request
+}
@@ -0,0 +1,172 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use shared::{JobId, Reservation};
+use tokio::sync::Notify;
+
+use crate::errors::ExecutorError;
+use crate::orchestrators::OrchestratorId;
+use crate::worker::Worker;
+
+/// How long an arriving job waits for its reservation. The reservation comes over the
+/// Orchestrator stream and the job over the client's connection, so either can arrive first.
+const RESERVATION_ARRIVAL_GRACE: Duration = Duration::from_secs(2);
+
+/// The reservations held for jobs that haven't arrived yet, with when each expires by the
+/// Worker's own clock.
+#[derive(Debug, Default)]
+pub struct Reservations {
+    held: DashMap<JobId, Instant>,
+    arrived: Notify,
+}
+
+impl Reservations {
+    /// Holds a reservation for the job for ttl from now, and returns when it expires.
+    fn hold(&self, job_id: JobId, ttl: Duration) -> Instant {
+        let expires_at = Instant::now() + ttl;
+        self.held.insert(job_id, expires_at);
+        self.arrived.notify_waiters();
+        expires_at
+    }
+
+    /// Drops the reservation that expires at expires_at, if the job hasn't claimed it. Returns
+    /// whether it was still held.
+    fn expire(&self, job_id: JobId, expires_at: Instant) -> bool {
+        self.held.remove_if(&job_id, |_, t| *t == expires_at).is_some()
+    }
+
+    /// Claims the job's unexpired reservation, waiting up to grace for it to arrive. Returns
+    /// whether there was one to claim.
+    async fn redeem(&self, job_id: JobId, grace: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + grace;
+        loop {
+            // Registered before checking, so a reservation arriving in between still wakes it
+            let arrived = self.arrived.notified();
+            if self.held.remove_if(&job_id, |_, expires_at| *expires_at > Instant::now()).is_some() {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, arrived).await.is_err() {
+                return false;
+            }
+        }
+    }
+}
+
+impl Worker {
+    /// Holds one of this worker's credits for a job the Orchestrator dispatched here, until the
+    /// job arrives or the reservation expires. An expired reservation returns its credit. The
+    /// other Orchestrators are told the credit is taken.
+    pub(crate) fn hold_reservation(&self, dispatcher: OrchestratorId, reservation: Reservation) {
+        let job_id = match JobId::from_bytes(&reservation.job_id) {
+            Ok(job_id) => job_id,
+            Err(e) => {
+                tracing::warn!(error = %e, "ignoring a reservation with a malformed job id");
+                return;
+            }
+        };
+        let ttl = Duration::from_millis(reservation.ttl_ms);
+        tracing::debug!(job_id = %job_id, ttl = ?ttl, "holding reservation");
+        let expires_at = self.reservations.hold(job_id, ttl);
+        self.orchestrators.credit_taken(dispatcher);
+
+        let worker = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(expires_at.into()).await;
+            if worker.reservations.expire(job_id, expires_at) {
+                tracing::debug!(job_id = %job_id, "reservation expired before the job arrived, releasing its credit");
+                worker.release_unstarted_job(dispatcher, job_id);
+            }
+        });
+    }
+
+    /// Claims the reservation for a job that has arrived, waiting briefly if the reservation
+    /// hasn't yet. Each reservation admits the job once. A job resent with its module after a
+    /// cache miss keeps the slot its first attempt claimed, and isn't redeemed again.
+    pub(crate) async fn redeem_reservation(&self, job_id: JobId) -> Result<(), ExecutorError> {
+        if self.reservations.redeem(job_id, RESERVATION_ARRIVAL_GRACE).await {
+            Ok(())
+        } else {
+            Err(ExecutorError::ReservationRequired)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(30);
+    const GRACE: Duration = Duration::from_millis(500);
+
+    #[tokio::test]
+    async fn a_job_arriving_before_its_reservation_waits_for_it() {
+        let reservations = Arc::new(Reservations::default());
+        let job_id = JobId::random();
+        let redeem = tokio::spawn({
+            let reservations = reservations.clone();
+            async move { reservations.redeem(job_id, GRACE).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        reservations.hold(job_id, TTL);
+        assert!(redeem.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_job_whose_reservation_never_comes_is_refused() {
+        let reservations = Reservations::default();
+        reservations.hold(JobId::random(), TTL);
+        assert!(!reservations.redeem(JobId::random(), Duration::from_millis(50)).await);
+    }
+
+    #[tokio::test]
+    async fn a_job_arriving_after_its_reservation_expired_is_refused() {
+        let reservations = Reservations::default();
+        let job_id = JobId::random();
+        let expires_at = reservations.hold(job_id, Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!reservations.redeem(job_id, Duration::from_millis(50)).await);
+        // Still held until expired, which then releases its credit
+        assert!(reservations.expire(job_id, expires_at));
+    }
+
+    #[tokio::test]
+    async fn a_reservation_is_redeemed_once() {
+        let reservations = Reservations::default();
+        let job_id = JobId::random();
+        let expires_at = reservations.hold(job_id, TTL);
+        assert!(reservations.redeem(job_id, GRACE).await);
+        assert!(!reservations.redeem(job_id, Duration::from_millis(50)).await);
+        // Claimed, so expiring it doesn't release the job's credit a second time
+        assert!(!reservations.expire(job_id, expires_at));
+    }
+
+    #[tokio::test]
+    async fn concurrent_redemptions_admit_one_job() {
+        let reservations = Arc::new(Reservations::default());
+        let job_id = JobId::random();
+        reservations.hold(job_id, TTL);
+        let redemptions: Vec<_> = (0..8)
+            .map(|_| {
+                let reservations = reservations.clone();
+                tokio::spawn(async move { reservations.redeem(job_id, Duration::from_millis(100)).await })
+            })
+            .collect();
+        let mut admitted = 0;
+        for redemption in redemptions {
+            admitted += redemption.await.unwrap() as usize;
+        }
+        assert_eq!(admitted, 1);
+    }
+
+    #[tokio::test]
+    async fn a_later_reservation_for_the_job_outlives_the_first_expiry() {
+        let reservations = Reservations::default();
+        let job_id = JobId::random();
+        let first = reservations.hold(job_id, Duration::from_millis(10));
+        reservations.hold(job_id, TTL);
+        assert!(!reservations.expire(job_id, first));
+        assert!(reservations.redeem(job_id, GRACE).await);
+    }
+}
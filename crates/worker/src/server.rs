@@ -0,0 +1,270 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::task::{JoinError, JoinHandle};
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::Server;
+
+use shared::JobId;
+use shared::executor_server::ExecutorServer;
+use shared::limits::JobLimits;
+use shared::signing::TrustedKeys;
+
+use crate::handoff::HandoffConfig;
+use crate::isolation::{Isolation, ProcessLimits};
+use crate::load::LoadAware;
+use crate::relay::ConnectMode;
+use crate::warm_pool::WarmPoolSpec;
+use crate::wasm_limits::WasmLimits;
+use crate::worker::Worker;
+
+/// Settings for a Worker. The defaults match the worker binary's defaults.
+#[derive(Debug)]
+pub struct WorkerConfig {
+    /// Host address clients will connect to (must be reachable).
+    pub bind_host: String,
+    /// Port to bind on the host, or 0 to bind a free one.
+    pub port: u16,
+    /// Number of jobs the Worker runs at once.
+    pub credits: u32,
+    /// URLs of the Orchestrators to register with. A Worker registered with several takes jobs
+    /// from each, and carries on while any of them is still connected.
+    pub orchestrators: Vec<String>,
+    /// Password to authenticate with the Orchestrators.
+    pub password: Option<String>,
+    pub limits: JobLimits,
+    /// Limits on each job's stack and wasm resources.
+    pub wasm_limits: WasmLimits,
+    /// If set, the Worker only runs jobs signed by one of these keys.
+    pub trusted_keys: Option<TrustedKeys>,
+    /// If set, the Worker is dedicated to this namespace and only receives its jobs.
+    pub namespace: Option<String>,
+    /// If set, the Worker only runs jobs the Orchestrator reserved one of its credits for, and
+    /// reservations whose job never arrives return their credit.
+    pub require_reservation: bool,
+    /// Where jobs run. With Isolation::Process, see run_job_helper.
+    pub isolation: Isolation,
+    /// OS limits on each job's helper process with Isolation::Process.
+    pub process_limits: ProcessLimits,
+    /// If set, the Worker hands its compiled modules to its peers when shut down gracefully.
+    pub handoff: Option<HandoffConfig>,
+    /// Whether clients connect to the Worker, or have their calls relayed to it.
+    pub connect_mode: ConnectMode,
+    /// If set, clients connect to the Worker over https at this address, where a TLS proxy
+    /// forwards to it. The Worker itself only serves plaintext, which the Orchestrator and peer
+    /// Workers still use.
+    pub tls_address: Option<String>,
+    /// Most bytes of wasm kept for clients to upload new versions of modules as patches
+    /// against, see DeltaBases. Zero turns delta uploads off.
+    pub delta_base_bytes: usize,
+    /// Modules to keep instances of ready for their jobs, see WarmPool. Pooled jobs skip
+    /// instantiating their module, but not with Isolation::Process, whose jobs run elsewhere.
+    pub warm_pool: Vec<WarmPoolSpec>,
+    /// Most linear memory the pooled instances may hold together.
+    pub warm_pool_bytes: usize,
+    /// If set, the Worker also indexes the modules it compiles by their SHA-256 digest, so
+    /// that jobs may name them by it, see DigestIndex.
+    pub dual_hash: bool,
+    /// If set, the Worker advertises fewer credits while its host is busy, see load.rs.
+    pub load_aware: Option<LoadAware>,
+    /// If set, jobs still running this long after a graceful shutdown begins are aborted, and
+    /// their clients retry them elsewhere. Otherwise shutdown waits for them to finish.
+    pub abort_jobs_after: Option<Duration>,
+    /// How often the Worker tells the Orchestrators how many jobs it has started and is
+    /// running, see heartbeat.rs.
+    pub heartbeat_interval: Duration,
+    /// If set, the Worker also serves gRPC reflection describing the Executor API, for tools
+    /// like grpcurl. Relayed Workers serve no reflection, as they bind nothing.
+    pub reflection: bool,
+    /// Outputs larger than this are written to output_dir for clients to fetch, rather than
+    /// returned in the job's response, see OutputStore. Zero always returns them inline.
+    pub offload_output_bytes: usize,
+    /// Most bytes a job whose output is offloaded may write to its stdout. Only the first
+    /// offload_output_bytes of them are held in memory.
+    pub max_offloaded_output_bytes: u64,
+    /// Where offloaded outputs are written, in a directory for each Worker, so Workers on the
+    /// same host can share it.
+    pub output_dir: PathBuf,
+    /// How long an offloaded output is kept for its client to fetch.
+    pub output_retention: Duration,
+    /// If set, jobs may have the Worker fetch their module from this S3-compatible endpoint.
+    #[cfg(feature = "object-store")]
+    pub object_store_endpoint: Option<String>,
+}
+
+impl WorkerConfig {
+    /// Creates a config for a Worker on the given host, with defaults for everything else.
+    pub fn new(bind_host: impl Into<String>, credits: u32) -> Self {
+        Self {
+            bind_host: bind_host.into(),
+            port: 0,
+            credits,
+            orchestrators: vec!["http://127.0.0.1:50051".to_string()],
+            password: None,
+            limits: JobLimits::default(),
+            wasm_limits: WasmLimits::default(),
+            trusted_keys: None,
+            namespace: None,
+            require_reservation: false,
+            isolation: Isolation::None,
+            process_limits: ProcessLimits::default(),
+            handoff: None,
+            connect_mode: ConnectMode::Listen,
+            tls_address: None,
+            delta_base_bytes: 256 * 1024 * 1024,
+            warm_pool: Vec::new(),
+            warm_pool_bytes: 256 * 1024 * 1024,
+            dual_hash: false,
+            load_aware: None,
+            abort_jobs_after: None,
+            heartbeat_interval: Duration::from_secs(5),
+            reflection: false,
+            offload_output_bytes: 4 * 1024 * 1024,
+            max_offloaded_output_bytes: 1024 * 1024 * 1024,
+            output_dir: std::env::temp_dir().join("mini-lambda-outputs"),
+            output_retention: Duration::from_secs(3600),
+            #[cfg(feature = "object-store")]
+            object_store_endpoint: None,
+        }
+    }
+}
+
+/// A running Worker's executor server, returned by Worker::start.
+pub struct WorkerHandle {
+    /// None for a relayed Worker, which binds nothing.
+    addr: Option<SocketAddr>,
+    shutdown: CancellationToken,
+    server: JoinHandle<Result<(), tonic::transport::Error>>,
+    worker: Worker,
+    handoff: Option<HandoffConfig>,
+    abort_jobs_after: Option<Duration>,
+}
+
+impl Worker {
+    /// Binds the configured host and port, registers with the Orchestrators, and starts serving
+    /// jobs in a background task. Like the worker binary, the process exits once the connections
+    /// to all the Orchestrators are lost or they reject the registration.
+    ///
+    /// With ConnectMode::Relay nothing is bound. The Worker registers under its host and a
+    /// random placeholder in place of the port, and serves the calls relayed to it.
+    pub async fn start(config: WorkerConfig) -> std::io::Result<WorkerHandle> {
+        let listener = match config.connect_mode {
+            ConnectMode::Listen => Some(TcpListener::bind(format!("{}:{}", config.bind_host, config.port)).await?),
+            ConnectMode::Relay => None,
+        };
+        let addr = listener.as_ref().map(TcpListener::local_addr).transpose()?;
+        let address = match addr {
+            Some(addr) => addr.to_string(),
+            None => format!("{}:relay-{}", config.bind_host, JobId::random().short()),
+        };
+
+        // Register this worker with the orchestrators
+        let limits = config.limits;
+        let handoff = config.handoff;
+        let abort_jobs_after = config.abort_jobs_after;
+        let reflection = config.reflection;
+        let worker = Worker::new(address, config).await;
+
+        // Start the executor server, or for a relayed worker wait to close the relay on shutdown
+        let shutdown = CancellationToken::new();
+        let server = match (listener, worker.relay_gate.clone()) {
+            (Some(listener), _) => {
+                tracing::info!("Worker listening on {}", worker.addr);
+                tokio::spawn(
+                    Server::builder()
+                        .add_service(ExecutorServer::new(worker.clone()).max_decoding_message_size(limits.max_message_bytes()))
+                        .add_optional_service(reflection.then(|| shared::reflection::service(shared::reflection::WORKER_SERVICES)))
+                        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown.clone().cancelled_owned())
+                )
+            },
+            (None, Some(gate)) => {
+                tracing::info!("Worker {} serving calls relayed by the orchestrator", worker.addr);
+                let shutdown = shutdown.clone();
+                tokio::spawn(async move {
+                    shutdown.cancelled().await;
+                    gate.close().await;
+                    Ok(())
+                })
+            },
+            (None, None) => {
+                tracing::error!("ERROR: worker neither listens nor is relayed, this should never happen");
+                std::process::exit(1);
+            },
+        };
+        Ok(WorkerHandle { addr, shutdown, server, worker, handoff, abort_jobs_after })
+    }
+}
+
+impl WorkerHandle {
+    /// The address the Worker registered with the Orchestrator as.
+    pub fn address(&self) -> &str {
+        &self.worker.addr
+    }
+
+    /// The address clients reach the Worker at, or None if their calls are relayed to it.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.addr.map(|addr| addr.port())
+    }
+
+    /// Stops accepting connections or relayed calls and waits for in-flight requests to finish,
+    /// aborting their jobs if configured to stop waiting, then hands off the Worker's compiled
+    /// modules if configured to.
+    pub async fn shutdown(self) -> Result<(), tonic::transport::Error> {
+        let WorkerHandle { shutdown, server, worker, handoff, abort_jobs_after, .. } = self;
+        shutdown.cancel();
+        let result = drain(&worker, server, abort_jobs_after).await;
+        hand_off(&worker, handoff).await;
+        result
+    }
+
+    /// Waits until the server stops, which only happens on shutdown or a server error.
+    pub async fn wait(self) -> Result<(), tonic::transport::Error> {
+        server_result(self.server.await)
+    }
+
+    /// Serves until the signal completes, then shuts down gracefully, see shutdown.
+    pub async fn run_until(self, signal: impl Future<Output = ()>) -> Result<(), tonic::transport::Error> {
+        let WorkerHandle { shutdown, mut server, worker, handoff, abort_jobs_after, .. } = self;
+        tokio::select! {
+            result = &mut server => return server_result(result),
+            _ = signal => shutdown.cancel(),
+        }
+        let result = drain(&worker, server, abort_jobs_after).await;
+        hand_off(&worker, handoff).await;
+        result
+    }
+}
+
+/// Waits for a server that is shutting down to finish its in-flight requests, aborting their
+/// jobs once abort_jobs_after has passed.
+async fn drain(
+    worker: &Worker,
+    mut server: JoinHandle<Result<(), tonic::transport::Error>>,
+    abort_jobs_after: Option<Duration>,
+) -> Result<(), tonic::transport::Error> {
+    if let Some(after) = abort_jobs_after {
+        match tokio::time::timeout(after, &mut server).await {
+            Ok(result) => return server_result(result),
+            Err(_) => worker.abort_jobs(),
+        }
+    }
+    server_result(server.await)
+}
+
+async fn hand_off(worker: &Worker, handoff: Option<HandoffConfig>) {
+    if let Some(config) = handoff {
+        worker.hand_off_modules(config).await;
+    }
+}
+
+fn server_result(result: Result<Result<(), tonic::transport::Error>, JoinError>) -> Result<(), tonic::transport::Error> {
+    result.unwrap_or_else(|e| panic!("Executor server task failed: {}", e))
+}
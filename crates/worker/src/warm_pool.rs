@@ -0,0 +1,186 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use blake3::Hash;
+use wasmtime::component::Component;
+
+use crate::executor::{Instance, instantiate};
+use crate::worker::Worker;
+
+/// A module to keep pre-instantiated, and how many of its instances to keep ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WarmPoolSpec {
+    pub wasm_hash: Hash,
+    pub instances: usize,
+}
+
+impl FromStr for WarmPoolSpec {
+    type Err = String;
+
+    /// Parses a module's hex blake3 hash and an instance count, such as "af13...=4".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hash, instances) = s.split_once('=')
+            .ok_or_else(|| format!("'{s}' is not a <hash>=<instances> pool"))?;
+        let wasm_hash = Hash::from_hex(hash.trim())
+            .map_err(|_| format!("'{hash}' is not a module's hex blake3 hash"))?;
+        let instances: usize = instances.trim().parse().map_err(|_| format!("'{instances}' is not an instance count"))?;
+        if instances == 0 {
+            return Err(format!("pool '{s}' must keep at least 1 instance"));
+        }
+        Ok(Self { wasm_hash, instances })
+    }
+}
+
+impl fmt::Display for WarmPoolSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.wasm_hash, self.instances)
+    }
+}
+
+/// An instance waiting in a pool for a job to run.
+pub struct WarmInstance {
+    pub instance: Instance,
+    /// How long it took to instantiate, which the job that takes it is spared.
+    pub instantiate_time: Duration,
+}
+
+/// Instances of frequently run modules, made ahead of the jobs that run them so that those
+/// skip instantiating. Each instance runs a single job and is then dropped, like any other,
+/// and the pool is refilled in the background. Modules are only pooled while compiled in the
+/// component cache, and the pooled instances' memory is capped.
+pub struct WarmPool {
+    /// How many instances to keep of each pooled module. Empty turns pooling off.
+    targets: HashMap<Hash, usize>,
+    /// Most linear memory the pooled instances may hold together.
+    max_bytes: usize,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    instances: HashMap<Hash, Vec<WarmInstance>>,
+    bytes: usize,
+    /// Modules a background task is instantiating more of.
+    refilling: HashSet<Hash>,
+}
+
+impl WarmPool {
+    pub fn new(specs: &[WarmPoolSpec], max_bytes: usize) -> Self {
+        let targets = specs.iter().map(|spec| (spec.wasm_hash, spec.instances)).collect();
+        Self { targets, max_bytes, state: Mutex::new(PoolState::default()) }
+    }
+
+    pub fn is_pooled(&self, wasm_hash: &Hash) -> bool {
+        self.targets.contains_key(wasm_hash)
+    }
+
+    /// Takes one of the module's instances, if one is ready.
+    pub fn take(&self, wasm_hash: &Hash) -> Option<WarmInstance> {
+        let mut state = self.lock();
+        let warm = state.instances.get_mut(wasm_hash)?.pop()?;
+        state.bytes -= warm.instance.store.data().memory_bytes;
+        Some(warm)
+    }
+
+    /// Adds an instance of the module, unless its pool is full or the instance's memory
+    /// doesn't fit under the cap. Returns whether it was added.
+    fn add(&self, wasm_hash: Hash, warm: WarmInstance) -> bool {
+        let mut state = self.lock();
+        let bytes = warm.instance.store.data().memory_bytes;
+        let pooled = state.instances.get(&wasm_hash).map_or(0, Vec::len);
+        if pooled >= self.target(&wasm_hash) || state.bytes + bytes > self.max_bytes {
+            return false;
+        }
+        state.bytes += bytes;
+        state.instances.entry(wasm_hash).or_default().push(warm);
+        true
+    }
+
+    /// Claims refilling the module's pool, unless it isn't pooled or is already being refilled.
+    fn start_refill(&self, wasm_hash: Hash) -> bool {
+        self.is_pooled(&wasm_hash) && self.lock().refilling.insert(wasm_hash)
+    }
+
+    /// Whether the module's refill should instantiate another instance, releasing the claim
+    /// if not. Checked under the same lock as take, so a job taking an instance after the last
+    /// check is never left without a refill.
+    fn keep_refilling(&self, wasm_hash: &Hash) -> bool {
+        let mut state = self.lock();
+        let pooled = state.instances.get(wasm_hash).map_or(0, Vec::len);
+        if pooled < self.target(wasm_hash) {
+            return true;
+        }
+        state.refilling.remove(wasm_hash);
+        false
+    }
+
+    fn stop_refill(&self, wasm_hash: &Hash) {
+        self.lock().refilling.remove(wasm_hash);
+    }
+
+    /// Tears down the pools of the modules keep returns false for.
+    pub fn retain(&self, keep: impl Fn(&Hash) -> bool) {
+        let mut state = self.lock();
+        let evicted: Vec<Hash> = state.instances.keys().filter(|hash| !keep(hash)).copied().collect();
+        for wasm_hash in evicted {
+            let instances = state.instances.remove(&wasm_hash).unwrap_or_default();
+            state.bytes -= instances.iter().map(|warm| warm.instance.store.data().memory_bytes).sum::<usize>();
+            tracing::debug!(wasm_hash = %wasm_hash, instances = instances.len(), "module evicted, warm pool torn down");
+        }
+    }
+
+    fn target(&self, wasm_hash: &Hash) -> usize {
+        self.targets.get(wasm_hash).copied().unwrap_or(0)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Worker {
+    /// Takes an instance of the module from its warm pool, if it is pooled and one is ready,
+    /// and refills the pool in the background either way.
+    pub(crate) fn take_warm_instance(&self, wasm_hash: Hash, component: &Component) -> Option<WarmInstance> {
+        if !self.warm_pool.is_pooled(&wasm_hash) {
+            return None;
+        }
+        let warm = self.warm_pool.take(&wasm_hash);
+        self.refill_warm_pool(wasm_hash, component.clone());
+        warm
+    }
+
+    /// Instantiates the module in a background task until its pool is full, if it is pooled.
+    /// Stops early if the module is evicted from the cache, or its instances no longer fit
+    /// under the pool's memory cap.
+    pub(crate) fn refill_warm_pool(&self, wasm_hash: Hash, component: Component) {
+        if !self.warm_pool.start_refill(wasm_hash) {
+            return;
+        }
+        let worker = self.clone();
+        tokio::spawn(async move {
+            while worker.warm_pool.keep_refilling(&wasm_hash) {
+                let started = Instant::now();
+                let instance = match instantiate(&worker.wasm_engine, &worker.wasm_linker, &component, &worker.wasm_limits).await {
+                    Ok(instance) => instance,
+                    Err(e) => {
+                        tracing::warn!(wasm_hash = %wasm_hash, error = %e, "failed to instantiate module for its warm pool");
+                        worker.warm_pool.stop_refill(&wasm_hash);
+                        return;
+                    },
+                };
+                let warm = WarmInstance { instance, instantiate_time: started.elapsed() };
+
+                // Held while adding, so a module evicted meanwhile isn't pooled after its teardown
+                let component_cache = worker.component_cache.lock().await;
+                if !component_cache.contains(&wasm_hash) || !worker.warm_pool.add(wasm_hash, warm) {
+                    worker.warm_pool.stop_refill(&wasm_hash);
+                    return;
+                }
+            }
+        });
+    }
+}
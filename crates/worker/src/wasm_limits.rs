@@ -0,0 +1,62 @@
+use wasmtime::{Config, StoreLimits, StoreLimitsBuilder};
+
+/// Limits on the wasm runtime's resources for each job, beyond the job size limits in JobLimits.
+///
+/// Jobs run on their own stack, sized to fit the guest's stack plus host_stack_bytes for the
+/// host functions it calls. Both are fixed when the Worker starts and apply to every job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// Most stack a guest may use before it traps with a stack overflow.
+    pub stack_bytes: usize,
+    /// Stack kept for host functions on top of stack_bytes.
+    pub host_stack_bytes: usize,
+    /// Most core wasm instances a job may create, and separately tables and memories. A
+    /// component creates several instances of its own.
+    pub max_instances: usize,
+    /// Most entries any one table may hold.
+    pub max_table_elements: usize,
+    /// Most bytes a job may write to its stdout, and separately to its stderr. Both are kept in
+    /// memory until the job finishes.
+    pub max_output_bytes: usize,
+}
+
+impl Default for WasmLimits {
+    /// The stack sizes are wasmtime's defaults.
+    fn default() -> Self {
+        Self {
+            stack_bytes: 512 * 1024,
+            host_stack_bytes: 1536 * 1024,
+            max_instances: 1000,
+            max_table_elements: 1_000_000,
+            max_output_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl WasmLimits {
+    /// Applies the stack limits to an engine's config.
+    pub(crate) fn configure(&self, config: &mut Config) {
+        config
+            .max_wasm_stack(self.stack_bytes)
+            .async_stack_size(self.stack_bytes + self.host_stack_bytes);
+    }
+
+    /// The limits enforced on a job's store.
+    pub(crate) fn store_limits(&self) -> StoreLimits {
+        StoreLimitsBuilder::new()
+            .instances(self.max_instances)
+            .tables(self.max_instances)
+            .memories(self.max_instances)
+            .table_elements(self.max_table_elements)
+            .build()
+    }
+}
+
+/// A size in the largest binary unit it is a whole number of, e.g. 1 MiB.
+pub(crate) fn fmt_stack_size(bytes: usize) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 && b % (1024 * 1024) == 0 => format!("{} MiB", b / (1024 * 1024)),
+        b if b >= 1024 && b % 1024 == 0 => format!("{} KiB", b / 1024),
+        b => format!("{b} bytes"),
+    }
+}
@@ -1,83 +1,203 @@
-use std::net::SocketAddr;
 use std::num::NonZeroUsize;
 use std::sync::{Arc, OnceLock};
+use std::sync::atomic::AtomicU64;
 use std::time::Duration;
 
 use blake3::Hash;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use lru::LruCache;
-use tokio::sync::{OnceCell, Mutex, mpsc};
+use tokio::sync::{OnceCell, Mutex, Notify, oneshot};
 
-use shared::{WorkerMessage};
-use tokio_util::sync::CancellationToken;
-use uuid::Uuid;
+use shared::{CostWeights, orchestrator_message};
+use shared::JobId;
+use shared::limits::JobLimits;
+use shared::signing::TrustedKeys;
 use wasmtime::component::{Component, Linker};
 use wasmtime::{Config, Engine};
 
+use crate::cancellation::JobCancellation;
+use crate::coalescing::CoalescedRun;
+use crate::delta::DeltaBases;
+use crate::digests::DigestIndex;
 use crate::executor::ComponentRunStates;
+use crate::isolation::{Isolation, ProcessLimits};
+use crate::module_info::CachedModuleInfo;
+use crate::module_store::ModuleStore;
+use crate::orchestrators::Orchestrators;
+use crate::output_store::OutputStore;
+use crate::relay::{ConnectMode, RelayGate};
+use crate::reservations::Reservations;
+use crate::server::WorkerConfig;
+use crate::wasm_limits::WasmLimits;
+use crate::warm_pool::WarmPool;
 
 /// Worker struct representing the main Worker component.
 /// It implements the Executor service, see executor.rs for details.
-/// It also communicates bidirectionally with the Orchestrator, via its orchestrator_tx channel.
+/// It also communicates bidirectionally with each of its Orchestrators, see orchestrators.rs.
 /// Worker internally uses Arc<RwLock/Mutex<_>> so can be cloned cheaply.
 #[derive(Clone)]
 pub struct Worker {
     // note: all shared state fields should use Arc<RwLock/Mutex<...>> for thread safety
 
     // Fields relating to the Executor service.
-    pub addr: SocketAddr,
+    /// The address the Worker registers as. Relayed Workers have no real port, see ConnectMode.
+    pub addr: String,
     pub wasm_engine: Engine,
     pub wasm_linker: Linker<ComponentRunStates>,
-    pub cancellation_tokens: Arc<DashMap<Uuid, CancellationToken>>,
+    /// The jobs in flight on this Worker, to stop them by, see cancellation.rs.
+    pub active_jobs: Arc<DashMap<JobId, JobCancellation>>,
+    /// Jobs that began executing since the Worker started, whichever Orchestrator dispatched
+    /// them, see heartbeat.rs.
+    pub jobs_started: Arc<AtomicU64>,
     pub component_cache: Arc<Mutex<LruCache<Hash, Arc<OnceCell<Component>>>>>,
+    /// What is known about each module in the component cache, see module_info.rs.
+    pub module_info: Arc<DashMap<Hash, CachedModuleInfo>>,
+    /// Instances of frequently run modules made ahead of their jobs, see warm_pool.rs.
+    pub warm_pool: Arc<WarmPool>,
+    /// The blake3 hash of modules known by other digests, see digests.rs.
+    pub digests: Arc<DigestIndex>,
+    /// The wasm of recently compiled modules, for delta uploads, see delta.rs.
+    pub delta_bases: Arc<DeltaBases>,
+    pub awaiting_upload: Arc<DashSet<JobId>>,
+    /// Jobs released as their module upload never arrived, whose late uploads are rejected.
+    pub expired_uploads: Arc<DashSet<JobId>>,
+    pub limits: JobLimits,
+    pub wasm_limits: WasmLimits,
+    /// Set if the worker only runs jobs signed by one of these keys.
+    pub trusted_keys: Option<Arc<TrustedKeys>>,
+    /// Set if the worker can fetch job modules from an object store.
+    pub module_store: Option<Arc<ModuleStore>>,
+    /// Job outputs too large to return inline, see output_store.rs.
+    pub output_store: Arc<OutputStore>,
+    /// Set if the worker only runs jobs it holds a reservation for.
+    pub require_reservation: bool,
+    /// Set if clients reach the worker through a TLS proxy at this address.
+    pub tls_address: Option<String>,
+    pub isolation: Isolation,
+    pub process_limits: ProcessLimits,
+    pub reservations: Arc<Reservations>,
+    /// Runs shared by jobs the Orchestrator coalesced, by the job that runs them.
+    pub coalesced_runs: Arc<DashMap<JobId, Arc<CoalescedRun>>>,
+    pub coalesced_run_started: Arc<Notify>,
+    /// Set if clients' calls are relayed to the worker by the Orchestrator.
+    pub relay_gate: Option<RelayGate>,
 
-    // Fields relating to communication with the Orchestrator
-    pub orchestrator_tx: mpsc::Sender<WorkerMessage>,
+    // Fields relating to communication with the Orchestrators
+    pub orchestrators: Arc<Orchestrators>,
+    /// Set while handing off modules on shutdown, to receive the Orchestrator's reply.
+    pub handoff_reply: Arc<std::sync::Mutex<Option<oneshot::Sender<orchestrator_message::Message>>>>,
 
     // Fields relating to both
-    pub jwt_secret: Arc<OnceLock<[u8; 32]>>,
+    /// Whether jobs may use the network, from the first Orchestrator to acknowledge the
+    /// Worker's registration. Orchestrators sharing Workers should be configured alike.
     pub network_access_allowed: Arc<OnceLock<bool>>,
+    /// What the cluster charges for jobs, from the first Orchestrator to acknowledge the
+    /// Worker's registration.
+    pub cost_weights: Arc<OnceLock<CostWeights>>,
 }
 
 impl Worker {
-    /// Create a new Worker instance.
-    pub async fn new(addr: SocketAddr, orchestrator_endpoint: &str, password: Option<String>, worker_credits: u32) -> Worker {
+    /// Create a new Worker instance serving at addr, and register it with the Orchestrators.
+    pub async fn new(addr: String, config: WorkerConfig) -> Worker {
 
         // Set up Executor fields
-        let wasm_engine = Engine::new(Config::new().epoch_interruption(true))
-            .unwrap_or_else(|e| panic!("Failed to initialize the wasmtime engine: {e}"));
-        let mut wasm_linker: Linker<ComponentRunStates> = Linker::new(&wasm_engine);
-        wasmtime_wasi::p2::add_to_linker_async(&mut wasm_linker)
-            .unwrap_or_else(|e| panic!("Failed to add WASI to the linker: {e}"));
+        let wasm_engine = new_engine(&config.wasm_limits);
+        let wasm_linker = new_linker(&wasm_engine);
 
-        // Create the background tasks that increments epoch
+        // Create the background thread that increments epoch. It is kept off the runtime so that
+        // guests busy on every runtime thread still get interrupted.
         let engine = wasm_engine.clone();
-        tokio::task::spawn(async move {
-            let mut inteval = tokio::time::interval(Duration::from_millis(10));
-            loop {
-                inteval.tick().await;
+        std::thread::Builder::new()
+            .name("epoch-ticker".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(Duration::from_millis(10));
                 engine.increment_epoch();
-            }
-        });
+            })
+            .unwrap_or_else(|e| panic!("Failed to start the epoch ticker thread: {e}"));
+
 
+        // Set up communication with the Orchestrators, skipping any that can't be reached
+        let mut connections = Vec::new();
+        let mut inbounds = Vec::new();
+        for url in &config.orchestrators {
+            match Worker::connect_to_orchestrator(url, config.password.clone()).await {
+                Ok((tx, inbound)) => {
+                    connections.push((url.clone(), tx));
+                    inbounds.push(inbound);
+                },
+                Err(e) => tracing::error!(orchestrator = %url, error = %e, "failed to connect to orchestrator"),
+            }
+        }
+        if connections.is_empty() {
+            panic!("failed to connect to any of the orchestrators {:?}", config.orchestrators);
+        }
+        let orchestrators = Arc::new(Orchestrators::new(connections));
+        #[cfg(feature = "object-store")]
+        let module_store = config.object_store_endpoint.map(ModuleStore::new);
+        #[cfg(not(feature = "object-store"))]
+        let module_store = None;
 
-        // Set up communication with Orchestrator
-        let (orchestrator_tx, inbound) = Worker::connect_to_orchestrator(orchestrator_endpoint, password).await;
+        let output_store = OutputStore::new(config.output_dir, &addr, config.offload_output_bytes, config.max_offloaded_output_bytes, config.output_retention);
 
         // Create the Worker instance
         let worker = Worker {
             addr,
             wasm_engine,
             wasm_linker,
-            cancellation_tokens: Arc::new(DashMap::new()),
-            orchestrator_tx,
+            active_jobs: Arc::new(DashMap::new()),
+            jobs_started: Arc::new(AtomicU64::new(0)),
+            orchestrators,
+            handoff_reply: Arc::new(std::sync::Mutex::new(None)),
             component_cache: Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(64).unwrap()))),
-            jwt_secret: Arc::new(OnceLock::new()),
-            network_access_allowed: Arc::new(OnceLock::new())
+            module_info: Arc::new(DashMap::new()),
+            warm_pool: Arc::new(WarmPool::new(&config.warm_pool, config.warm_pool_bytes)),
+            digests: Arc::new(DigestIndex::new(config.dual_hash)),
+            delta_bases: Arc::new(DeltaBases::new(config.delta_base_bytes)),
+            awaiting_upload: Arc::new(DashSet::new()),
+            expired_uploads: Arc::new(DashSet::new()),
+            limits: config.limits,
+            wasm_limits: config.wasm_limits,
+            trusted_keys: config.trusted_keys.map(Arc::new),
+            module_store: module_store.map(Arc::new),
+            output_store: Arc::new(output_store),
+            require_reservation: config.require_reservation,
+            tls_address: config.tls_address,
+            isolation: config.isolation,
+            process_limits: config.process_limits,
+            reservations: Arc::new(Reservations::default()),
+            coalesced_runs: Arc::new(DashMap::new()),
+            coalesced_run_started: Arc::new(Notify::new()),
+            relay_gate: (config.connect_mode == ConnectMode::Relay).then(RelayGate::default),
+            network_access_allowed: Arc::new(OnceLock::new()),
+            cost_weights: Arc::new(OnceLock::new()),
         };
 
-        // Begin the bidirectional communication session with the Orchestrator
-        worker.start_orchestrator_session(inbound, worker_credits).await;
+        // Begin the bidirectional communication session with each Orchestrator
+        for (id, inbound) in worker.orchestrators.ids().zip(inbounds) {
+            worker.start_orchestrator_session(id, inbound, config.credits, config.namespace.clone()).await;
+        }
+        if let Some(load_aware) = config.load_aware {
+            worker.start_load_sampler(config.credits, load_aware);
+        }
+        worker.start_heartbeats(config.heartbeat_interval);
+        worker.start_output_sweeper();
         worker
     }
-}
\ No newline at end of file
+}
+
+/// Creates the wasmtime engine jobs are compiled and run with. Isolated job processes create
+/// theirs the same way, which lets them load modules the Worker compiled.
+pub(crate) fn new_engine(wasm_limits: &WasmLimits) -> Engine {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    wasm_limits.configure(&mut config);
+    Engine::new(&config)
+        .unwrap_or_else(|e| panic!("Failed to initialize the wasmtime engine: {e}"))
+}
+
+pub(crate) fn new_linker(engine: &Engine) -> Linker<ComponentRunStates> {
+    let mut linker = Linker::new(engine);
+    wasmtime_wasi::p2::add_to_linker_async(&mut linker)
+        .unwrap_or_else(|e| panic!("Failed to add WASI to the linker: {e}"));
+    linker
+}
@@ -0,0 +1,15 @@
+//! Applies untrusted patches, which must fail rather than rebuild more than the most bytes
+//! allowed.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+const MAX_BYTES: usize = 1 << 20;
+
+fuzz_target!(|input: (&[u8], &[u8])| {
+    let (base, patch) = input;
+    if let Ok(wasm_bytes) = shared::delta::apply_patch(base, patch, MAX_BYTES) {
+        assert!(wasm_bytes.len() <= MAX_BYTES);
+    }
+});
@@ -0,0 +1,10 @@
+//! Inspects untrusted wasm as `cli inspect` and the Worker's module metadata do.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|wasm_bytes: &[u8]| {
+    shared::inspect::inspect_module(wasm_bytes);
+    shared::inspect::module_metadata(wasm_bytes);
+});
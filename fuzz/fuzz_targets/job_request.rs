@@ -0,0 +1,21 @@
+//! Decodes untrusted bytes as the requests Workers and the Orchestrator are sent, and checks
+//! them as they would before doing anything else with them.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+use shared::limits::JobLimits;
+use shared::{JobId, JobRequest, WorkerRequest};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = JobRequest::decode(data) {
+        let _ = JobId::from_bytes(&request.job_id);
+        let _ = shared::compat::check(request.protocol_version);
+        let _ = request.validate(&JobLimits::default());
+    }
+    if let Ok(request) = WorkerRequest::decode(data) {
+        let _ = JobId::from_bytes(&request.job_id);
+        let _ = shared::compat::check(request.protocol_version);
+    }
+});